@@ -0,0 +1,162 @@
+//! The work the agent does for each datapath report: reading the report's fields and running
+//! the control logic on them, round-robin across many flows in PROBE_BW.
+//!
+//! Besides the time per report, prints the heap allocations per report. Record a baseline with
+//! `cargo bench --bench report -- --save-baseline <name>`, and compare a later run against it
+//! with `--baseline <name>`.
+
+use ccp_bbr::{Action, BbrConfig, BbrCore, BbrMode, Measurement};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use portus::DatapathInfo;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const FLOW_COUNTS: [usize; 2] = [1_000, 10_000];
+// every flow reports about once per round trip
+const REPORT_INTERVAL: Duration = Duration::from_millis(10);
+const MIN_RTT_US: u64 = 10_000;
+const RATE: u64 = 1_250_000;
+
+struct Flow {
+    core: BbrCore,
+    program_uid: u32,
+    pulse_state: u64,
+}
+
+/// Stands in for the datapath of many flows.
+struct Flows {
+    flows: Vec<Flow>,
+    now: Instant,
+    next_uid: u32,
+    next_flow: usize,
+}
+
+impl Flows {
+    /// `n` flows that have left STARTUP and DRAIN.
+    fn new(cfg: &BbrConfig, n: usize) -> Self {
+        let mut flows = Flows {
+            flows: vec![],
+            now: Instant::now(),
+            next_uid: 0,
+            next_flow: 0,
+        };
+
+        for i in 0..n as u32 {
+            let info = DatapathInfo {
+                sock_id: i + 1,
+                init_cwnd: 14_600,
+                mss: 1_460,
+                src_ip: 0x0a00_0001,
+                src_port: 40_000,
+                dst_ip: 0x0a01_0000 + i,
+                dst_port: 5201,
+            };
+            let core = BbrCore::new(cfg, &info, flows.now);
+            let start = core.start();
+            flows.flows.push(Flow {
+                core,
+                program_uid: 0,
+                pulse_state: 0,
+            });
+            flows.apply(i as usize, &start);
+        }
+
+        while flows
+            .flows
+            .iter()
+            .any(|f| f.core.mode() != BbrMode::ProbeBw)
+        {
+            flows.report();
+        }
+        flows
+    }
+
+    fn apply(&mut self, idx: usize, actions: &[Action]) {
+        for action in actions {
+            if let Action::SetProgram { .. } = action {
+                self.next_uid += 1;
+                self.flows[idx].program_uid = self.next_uid;
+                self.flows[idx].core.program_installed(self.next_uid);
+            }
+        }
+    }
+
+    /// Delivers the next flow's next report, and returns how many actions it took.
+    fn report(&mut self) -> usize {
+        let idx = self.next_flow;
+        self.next_flow = (idx + 1) % self.flows.len();
+        self.now += REPORT_INTERVAL / self.flows.len() as u32;
+
+        let flow = &mut self.flows[idx];
+        flow.pulse_state = (flow.pulse_state + 1) % 3;
+        let pulse_state = flow.pulse_state;
+        let m =
+            Measurement::from_report_fields(
+                flow.core.mode(),
+                flow.program_uid,
+                |field| match field {
+                    "Report.minrtt" => Some(MIN_RTT_US),
+                    "Report.rateOut" | "Report.rateIn" | "Report.deliveryRate" => Some(RATE),
+                    "Report.pulseState" => Some(pulse_state),
+                    _ => Some(0),
+                },
+            )
+            .unwrap();
+        let actions = flow.core.on_measurement(self.now, m);
+        self.apply(idx, &actions);
+        actions.len()
+    }
+}
+
+fn on_report(c: &mut Criterion) {
+    let cfg = BbrConfig::default();
+    let mut group = c.benchmark_group("on_report");
+    group.throughput(Throughput::Elements(1));
+    for n in FLOW_COUNTS {
+        let mut flows = Flows::new(&cfg, n);
+
+        let reports = 10 * n as u64;
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        for _ in 0..reports {
+            flows.report();
+        }
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        println!(
+            "on_report/{}: {:.2} allocations per report",
+            n,
+            allocations as f64 / reports as f64
+        );
+
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, _| {
+            b.iter(|| black_box(flows.report()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, on_report);
+criterion_main!(benches);
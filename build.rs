@@ -0,0 +1,5 @@
+// generates the gRPC service from proto/bbr.proto; needs protoc
+fn main() {
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/bbr.proto").unwrap();
+}
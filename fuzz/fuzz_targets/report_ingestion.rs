@@ -0,0 +1,83 @@
+//! Drives a flow's control logic with arbitrary report contents: missing fields, extreme
+//! values, and reports from stale or unknown programs.
+#![no_main]
+
+use ccp_bbr::{Action, BbrConfig, BbrCore, Measurement, PROGRAM_VERSION};
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use portus::DatapathInfo;
+use std::time::{Duration, Instant};
+
+#[derive(Arbitrary, Debug)]
+struct Report {
+    advance_us: u32,
+    /// Subtracted from the current program uid; 0 is a report from the current program.
+    uid_lag: u8,
+    minrtt: Option<u64>,
+    loss: Option<u64>,
+    misordered: Option<u64>,
+    timeout: Option<u64>,
+    rate_outgoing: Option<u64>,
+    rate_incoming: Option<u64>,
+    delivery_rate: Option<u64>,
+    pulse_state: Option<u64>,
+    receiver_limited: Option<u64>,
+    inflight: Option<u64>,
+    srtt: Option<u64>,
+    rttvar: Option<u64>,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    mss: u32,
+    init_cwnd: u32,
+    reports: Vec<Report>,
+}
+
+fuzz_target!(|input: Input| {
+    let info = DatapathInfo {
+        sock_id: 1,
+        init_cwnd: input.init_cwnd,
+        mss: input.mss,
+        src_ip: 0,
+        src_port: 0,
+        dst_ip: 0,
+        dst_port: 0,
+    };
+    let cfg = BbrConfig::default();
+    let mut now = Instant::now();
+    let mut core = BbrCore::new(&cfg, &info, now);
+    let mut program_uid = 0u32;
+    let mut actions = core.start();
+    for report in input.reports {
+        for action in &actions {
+            if let Action::SetProgram { .. } = action {
+                program_uid = program_uid.wrapping_add(1);
+                core.program_installed(program_uid);
+            }
+        }
+
+        now += Duration::from_micros(u64::from(report.advance_us));
+        let uid = program_uid.wrapping_sub(u32::from(report.uid_lag));
+        let m = Measurement::from_report_fields(core.mode(), uid, |field| match field {
+            "Report.minrtt" => report.minrtt,
+            "Report.loss" => report.loss,
+            "Report.misordered" => report.misordered,
+            "Report.timeout" => report.timeout,
+            "Report.rateOut" => report.rate_outgoing,
+            "Report.rateIn" => report.rate_incoming,
+            "Report.deliveryRate" => report.delivery_rate,
+            "Report.pulseState" => report.pulse_state,
+            "Report.rwndLimited" => report.receiver_limited,
+            "Report.inflight" => report.inflight,
+            "Report.srtt" => report.srtt,
+            "Report.version" => Some(u64::from(PROGRAM_VERSION)),
+            "Report.rttVar" => report.rttvar,
+            _ => None,
+        });
+        actions = match m {
+            Ok(m) => core.on_measurement(now, m),
+            Err(_) => vec![],
+        };
+    }
+});
@@ -0,0 +1,100 @@
+/*
+ * C ABI for the ccp_bbr control logic; see src/ffi.rs.
+ *
+ * Build with `cargo rustc --release --lib --features ffi --crate-type staticlib`.
+ * Strings and arrays returned by a flow stay valid until the next call on that flow.
+ */
+#ifndef CCP_BBR_H
+#define CCP_BBR_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define CCP_BBR_SET_PROGRAM 0
+#define CCP_BBR_UPDATE 1
+
+typedef struct CcpBbrConfig CcpBbrConfig;
+typedef struct CcpBbrFlow CcpBbrFlow;
+
+typedef struct CcpBbrFlowInfo {
+    uint32_t sock_id;
+    uint32_t init_cwnd;
+    uint32_t mss;
+    uint32_t src_ip;
+    uint32_t src_port;
+    uint32_t dst_ip;
+    uint32_t dst_port;
+} CcpBbrFlowInfo;
+
+typedef struct CcpBbrReport {
+    uint32_t program_uid;
+    uint32_t minrtt_us;
+    uint32_t loss;
+    uint32_t misordered;
+    uint8_t timeout;
+    double rate_outgoing;
+    double rate_incoming;
+    double delivery_rate;
+    uint32_t pulse_state;
+    uint8_t receiver_limited;
+    uint32_t inflight_bytes;
+    uint32_t srtt_us;
+    uint32_t rttvar_us;
+    double max_rate;
+    uint32_t acked;
+    uint32_t acks;
+    uint32_t nonce;
+    uint32_t nonce_age_us;
+    uint64_t delivered_total;
+    uint32_t span_us;
+    uint32_t mss;
+} CcpBbrReport;
+
+typedef struct CcpBbrField {
+    const char *name;
+    uint64_t value; /* rates may exceed UINT32_MAX bytes per second */
+} CcpBbrField;
+
+typedef struct CcpBbrAction {
+    uint32_t kind;
+    const char *program; /* NULL for CCP_BBR_UPDATE */
+    const CcpBbrField *fields;
+    size_t num_fields;
+} CcpBbrAction;
+
+CcpBbrConfig *ccp_bbr_config_new(void);
+/* takes the bbr binary's flags, without the program name; NULL if they are invalid */
+CcpBbrConfig *ccp_bbr_config_from_args(int argc, const char *const *argv);
+void ccp_bbr_config_free(CcpBbrConfig *cfg);
+
+size_t ccp_bbr_num_programs(const CcpBbrConfig *cfg);
+const char *ccp_bbr_program(const CcpBbrConfig *cfg, size_t i, const char **name);
+
+/* now_us is a monotonic clock in microseconds, shared with ccp_bbr_flow_on_report */
+CcpBbrFlow *ccp_bbr_flow_new(const CcpBbrConfig *cfg, const CcpBbrFlowInfo *info, uint64_t now_us);
+void ccp_bbr_flow_free(CcpBbrFlow *flow);
+void ccp_bbr_flow_program_installed(CcpBbrFlow *flow, uint32_t program_uid);
+size_t ccp_bbr_flow_on_report(CcpBbrFlow *flow, uint64_t now_us, const CcpBbrReport *report);
+const CcpBbrAction *ccp_bbr_flow_actions(const CcpBbrFlow *flow, size_t *num_actions);
+/* like ccp_bbr_flow_on_report, return how many actions are now pending */
+size_t ccp_bbr_flow_pause(CcpBbrFlow *flow);
+size_t ccp_bbr_flow_resume(CcpBbrFlow *flow);
+/* at_us is on the clock of now_us; takes effect with --schedule_probes */
+size_t ccp_bbr_flow_schedule_probe(CcpBbrFlow *flow, uint64_t now_us, uint64_t at_us);
+/* e.g. after path MTU discovery shrank the MSS */
+size_t ccp_bbr_flow_set_mss(CcpBbrFlow *flow, uint32_t mss);
+/* 0 STARTUP, 1 DRAIN, 2 PROBE_BW, 3 PROBE_RTT */
+uint32_t ccp_bbr_flow_mode(const CcpBbrFlow *flow);
+uint64_t ccp_bbr_flow_estimated_bdp_bytes(const CcpBbrFlow *flow);
+/* 1 once STARTUP has found the pipe full */
+uint8_t ccp_bbr_flow_pipe_full(const CcpBbrFlow *flow);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,179 @@
+//! Hosting the agent inside another application.
+//!
+//! [`Agent::spawn`] serves the datapath from a background thread, so an application does not
+//! have to run the `bbr` binary next to it. [`Agent::stopped`] is a plain `Future`, so any
+//! async runtime can await it; it also has a blocking [`Stopped::wait`].
+//!
+//! portus has no way to stop serving IPC once it has started, so shutting an agent down
+//! releases its flows but leaves the serving thread behind. Flows that start afterwards are
+//! released on their first report.
+
+use crate::error::BbrError;
+use crate::shutdown::{Shutdown, SHUTDOWN_GRACE_MS};
+use crate::snapshot::{FlowSnapshot, Snapshots};
+use crate::BbrConfig;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// What the agent's flows were doing when it was asked to shut down.
+#[derive(Clone, Debug)]
+pub struct ShutdownReport {
+    /// Every flow's last snapshot from before the flows were released.
+    pub flows: Vec<FlowSnapshot>,
+    /// Flows that did not release themselves within the grace period.
+    pub unreleased: usize,
+}
+
+#[derive(Default)]
+struct Outcome {
+    result: Option<Result<ShutdownReport, BbrError>>,
+    wakers: Vec<Waker>,
+}
+
+/// Resolves once the agent has shut down, or has stopped serving the datapath on its own.
+#[derive(Clone, Default)]
+pub struct Stopped {
+    outcome: Arc<(Mutex<Outcome>, Condvar)>,
+}
+
+impl Stopped {
+    /// The first outcome wins.
+    fn finish(&self, result: Result<ShutdownReport, BbrError>) {
+        let (outcome, done) = &*self.outcome;
+        let mut outcome = outcome.lock().unwrap();
+        if outcome.result.is_some() {
+            return;
+        }
+
+        outcome.result = Some(result);
+        outcome.wakers.drain(..).for_each(Waker::wake);
+        done.notify_all();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.outcome.0.lock().unwrap().result.is_some()
+    }
+
+    /// Blocks until the agent has stopped.
+    pub fn wait(self) -> Result<ShutdownReport, BbrError> {
+        let (outcome, done) = &*self.outcome;
+        let outcome = done
+            .wait_while(outcome.lock().unwrap(), |outcome| outcome.result.is_none())
+            .unwrap();
+        outcome.result.clone().unwrap()
+    }
+}
+
+impl Future for Stopped {
+    type Output = Result<ShutdownReport, BbrError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut outcome = self.outcome.0.lock().unwrap();
+        match &outcome.result {
+            Some(result) => Poll::Ready(result.clone()),
+            None => {
+                if !outcome.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                    outcome.wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// An agent serving the datapath from a background thread.
+pub struct Agent {
+    shutdown: Shutdown,
+    snapshots: Snapshots,
+    stopped: Stopped,
+}
+
+impl Agent {
+    /// Starts serving the datapath over `ipc`, one of the transports `--ipc` accepts.
+    ///
+    /// The agent shuts down when [`Agent::shutdown`] or `cfg.shutdown` requests it.
+    pub fn spawn(cfg: BbrConfig, ipc: &str) -> Result<Agent, BbrError> {
+        portus::algs::ipc_valid(String::from(ipc)).map_err(BbrError::Ipc)?;
+        let agent = Agent {
+            shutdown: cfg.shutdown.clone(),
+            snapshots: cfg.snapshots.clone(),
+            stopped: Stopped::default(),
+        };
+
+        let stopped = agent.stopped.clone();
+        let ipc = String::from(ipc);
+        std::thread::Builder::new()
+            .name(String::from("bbr-agent"))
+            .spawn(move || {
+                info!(?ipc, probe_rtt_interval = ?cfg.probe_rtt_interval, "starting BBR agent");
+                let served = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                    portus::start!(ipc.as_str(), cfg)
+                }));
+                let err = match served {
+                    Ok(Ok(())) => String::from("agent stopped serving the datapath"),
+                    Ok(Err(err)) => format!("{:?}", err),
+                    Err(_) => String::from("agent thread panicked"),
+                };
+                warn!(%err, "BBR agent stopped");
+                stopped.finish(Err(BbrError::Ipc(err)));
+            })
+            .map_err(|e| BbrError::Ipc(e.to_string()))?;
+
+        let (shutdown, snapshots, stopped) = (
+            agent.shutdown.clone(),
+            agent.snapshots.clone(),
+            agent.stopped.clone(),
+        );
+        std::thread::Builder::new()
+            .name(String::from("bbr-shutdown"))
+            .spawn(move || {
+                while !shutdown.is_requested() {
+                    if stopped.is_stopped() {
+                        return;
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+
+                let flows = snapshots.flows();
+                info!(flows = shutdown.active_flows(), "shutting down BBR agent");
+                let unreleased =
+                    shutdown.wait_for_release(Duration::from_millis(SHUTDOWN_GRACE_MS));
+                if unreleased > 0 {
+                    warn!(flows = unreleased, "stopping without releasing all flows");
+                }
+                stopped.finish(Ok(ShutdownReport { flows, unreleased }));
+            })
+            .map_err(|e| BbrError::Ipc(e.to_string()))?;
+
+        Ok(agent)
+    }
+
+    /// Every flow's latest state.
+    pub fn snapshots(&self) -> &Snapshots {
+        &self.snapshots
+    }
+
+    /// Asks every flow to release itself on its next report.
+    pub fn shutdown(&self) -> Stopped {
+        self.shutdown.request();
+        self.stopped()
+    }
+
+    pub fn stopped(&self) -> Stopped {
+        self.stopped.clone()
+    }
+}
+
+/// Serves the datapath until `cfg.shutdown` is requested and the flows are released.
+pub fn run_bbr(
+    cfg: BbrConfig,
+    ipc: &str,
+) -> impl Future<Output = Result<ShutdownReport, BbrError>> {
+    let agent = Agent::spawn(cfg, ipc);
+    async move { agent?.stopped().await }
+}
@@ -0,0 +1,121 @@
+//! Rates given on the command line and shown in logs and stats.
+//!
+//! A rate takes a unit suffix, in bits per second as in `50Mbps`, `1.2Gbit` or `800kbit/s`,
+//! or in bytes per second as in `10MB/s`. A bare number keeps the unit its flag has always
+//! used. Rates display in bits per second, with the largest SI prefix that keeps the value at
+//! least 1, as in `12.5Mbps`; internally, and in the programs' registers, they are bytes per
+//! second.
+
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+const PREFIXES: &[(&str, f64)] = &[
+    ("", 1.0),
+    ("k", 1e3),
+    ("K", 1e3),
+    ("M", 1e6),
+    ("G", 1e9),
+    ("T", 1e12),
+];
+
+const BIT_UNITS: &[&str] = &["bps", "bit", "bit/s", "b/s"];
+const BYTE_UNITS: &[&str] = &["Bps", "B/s", "byte/s"];
+
+/// A rate in bytes per second.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct Rate(f64);
+
+impl Rate {
+    pub const fn from_bytes_per_sec(bytes_per_sec: f64) -> Self {
+        Rate(bytes_per_sec)
+    }
+
+    /// From megabits per second.
+    pub fn from_mbps(mbps: f64) -> Self {
+        Rate(mbps * 125_000.0)
+    }
+
+    pub fn bytes_per_sec(self) -> f64 {
+        self.0
+    }
+
+    /// In megabits per second.
+    pub fn mbps(self) -> f64 {
+        self.0 / 125_000.0
+    }
+}
+
+fn unit_bytes_per_sec(unit: &str) -> Option<f64> {
+    PREFIXES.iter().find_map(|(prefix, scale)| {
+        let base = unit.strip_prefix(prefix)?;
+        if BIT_UNITS.contains(&base) {
+            Some(scale / 8.0)
+        } else if BYTE_UNITS.contains(&base) {
+            Some(*scale)
+        } else {
+            None
+        }
+    })
+}
+
+/// Parses a positive rate with an optional unit suffix; bare numbers count in `bare_unit`.
+pub fn parse_rate(s: &str, bare_unit: Rate) -> Result<Rate, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("rate must be a number with an optional unit: {:?}", s))?;
+    let unit_bytes_per_sec = match unit.trim() {
+        "" => bare_unit.bytes_per_sec(),
+        unit => unit_bytes_per_sec(unit).ok_or_else(|| {
+            format!(
+                "rate unit must be bits per second, e.g. Mbps or Gbit, or bytes per second, e.g. MB/s: {:?}",
+                s
+            )
+        })?,
+    };
+
+    let rate = value * unit_bytes_per_sec;
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(format!("rate must be positive: {:?}", s));
+    }
+    Ok(Rate(rate))
+}
+
+/// Bare numbers are bits per second.
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_rate(s, Rate(1.0 / 8.0))
+    }
+}
+
+/// Three decimals, or the formatter's precision, without trailing zeros.
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bits = self.0 * 8.0;
+        let (prefix, scale) = [("T", 1e12), ("G", 1e9), ("M", 1e6), ("k", 1e3)]
+            .into_iter()
+            .find(|(_, scale)| bits.abs() >= *scale)
+            .unwrap_or(("", 1.0));
+        let value = format!("{:.*}", f.precision().unwrap_or(3), bits / scale);
+        let value = if value.contains('.') {
+            value.trim_end_matches('0').trim_end_matches('.')
+        } else {
+            &value
+        };
+        write!(f, "{}{}bps", value, prefix)
+    }
+}
+
+/// As displayed, so that stats read the same as the logs.
+impl Serialize for Rate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
@@ -0,0 +1,66 @@
+//! Runs YAML simulation scenarios and prints a JSON line per flow and sample; see
+//! `ccp_bbr::scenario`.
+
+use ccp_bbr::scenario::Scenario;
+use clap::{App, Arg};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process::exit;
+
+// each line names the scenario it came from, so that the output of a sweep can be concatenated
+fn run(path: &str, out: &mut impl Write) -> Result<(), String> {
+    let yaml = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let scenario = Scenario::from_yaml(&yaml).map_err(|e| format!("{}: {}", path, e))?;
+    let name = Path::new(path)
+        .file_stem()
+        .map_or_else(|| path.into(), |stem| stem.to_string_lossy());
+    let mut result = Ok(());
+    scenario.run(|sample| {
+        if result.is_err() {
+            return;
+        }
+        let mut line = serde_json::to_value(sample).unwrap();
+        line["scenario"] = serde_json::Value::from(name.as_ref());
+        result = writeln!(out, "{}", line);
+    });
+    result.map_err(|e| format!("writing samples: {}", e))
+}
+
+fn main() {
+    let matches = App::new("bbr-sim")
+        .about("Runs BBR flows through the simulated bottlenecks that YAML scenarios describe, and prints a JSON line of every flow's state at every sample.")
+        .arg(Arg::with_name("scenario")
+             .help("The scenario files to run, one after the other.")
+             .required(true)
+             .multiple(true)
+             .value_name("scenario.yaml"))
+        .arg(Arg::with_name("out")
+             .long("out")
+             .help("Writes the samples to the given file instead of stdout.")
+             .takes_value(true)
+             .value_name("samples.jsonl"))
+        .get_matches();
+
+    let out: Box<dyn Write> = match matches.value_of("out") {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(file),
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                exit(1);
+            }
+        },
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut out = BufWriter::new(out);
+    for path in matches.values_of("scenario").unwrap() {
+        if let Err(err) = run(path, &mut out) {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    }
+    if let Err(err) = out.flush() {
+        eprintln!("writing samples: {}", err);
+        exit(1);
+    }
+}
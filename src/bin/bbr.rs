@@ -1,53 +1,620 @@
-use ccp_bbr::BbrConfig;
-use clap::Arg;
+use ccp_bbr::bandwidth::Rate;
+use ccp_bbr::control::Control;
+use ccp_bbr::datapath::DatapathKind;
+use ccp_bbr::duration::parse_duration;
+use ccp_bbr::path_cache::PathCache;
+use ccp_bbr::selftest::{self, SelfTest};
+use ccp_bbr::shutdown::{Shutdown, SHUTDOWN_GRACE_MS};
+use ccp_bbr::stats::StatsSink;
+use ccp_bbr::trace::Recorder;
+use ccp_bbr::transport::{parse_transports, Transport};
+use ccp_bbr::{BbrConfig, WallClock};
+use clap::{Arg, SubCommand};
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::socket::{setsockopt, sockopt};
+use portus::ipc::{unix::Socket, Blocking};
+use portus::{CongAlg, CongAlgBuilder};
+use std::ffi::OsString;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use tracing::{info, warn};
 
-fn make_args() -> Result<(BbrConfig, String), String> {
-    let probe_rtt_interval_default = format!("{}", ccp_bbr::PROBE_RTT_INTERVAL_SECONDS);
-    let matches = clap::App::new("CCP BBR")
-        .version("0.2.1")
-        .author("Akshay Narayan <akshayn@mit.edu>")
-        .about("Implementation of BBR Congestion Control")
-        .arg(Arg::with_name("ipc")
-             .long("ipc")
-             .help("Sets the type of ipc to use: (netlink|unix)")
-             .default_value("unix")
-             .validator(portus::algs::ipc_valid))
-        .arg(Arg::with_name("probe_rtt_interval")
-             .long("probe_rtt_interval")
-             .help("Sets the BBR probe RTT interval in seconds, after which BBR drops its congestion window to potentially observe a new minimum RTT.")
-             .default_value(&probe_rtt_interval_default))
+// the transports --ipc accepts
+const IPC_TRANSPORTS: [&str; 3] = ["netlink", "unix", "char"];
+// how long selftest waits for its connection to the target
+const SELFTEST_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct SelfTestArgs {
+    target: SocketAddr,
+    duration: Duration,
+    expected_rate: Option<Rate>,
+}
+
+struct Args {
+    cfg: BbrConfig,
+    transports: Vec<Transport>,
+    replay: Option<String>,
+    record: Option<PathBuf>,
+    dump_programs: bool,
+    dump_config: bool,
+    version_json: bool,
+    daemon: bool,
+    pidfile: Option<PathBuf>,
+    state_dump: Option<PathBuf>,
+    path_cache_file: Option<PathBuf>,
+    control_socket: Option<PathBuf>,
+    stats_sink: Option<StatsSink>,
+    stats_interval: Duration,
+    grpc_listen: Option<SocketAddr>,
+    selftest: Option<SelfTestArgs>,
+}
+
+fn make_args() -> Result<Args, String> {
+    let matches = BbrConfig::args()
+        .arg(Arg::with_name("replay")
+             .long("replay")
+             .help("Instead of connecting to a datapath, replays the reports recorded in the given JSON lines trace and prints the decisions BBR makes.")
+             .takes_value(true)
+             .value_name("trace.jsonl"))
+        .arg(Arg::with_name("record")
+             .long("record")
+             .help("Records every flow's start and reports to the given JSON lines trace, which --replay replays. With several --ipc transports, each records to the path with its name appended, e.g. trace.jsonl.netlink.")
+             .takes_value(true)
+             .value_name("trace.jsonl")
+             .conflicts_with("replay"))
+        .arg(Arg::with_name("dump_programs")
+             .long("dump_programs")
+             .help("Prints the datapath programs the other flags select, then exits."))
+        .arg(Arg::with_name("dump_config")
+             .long("dump_config")
+             .help("Prints the configuration each transport would run with, defaults and rules resolved, as JSON, then exits. The control socket's config command prints the same for a running agent."))
+        .arg(Arg::with_name("version_json")
+             .long("version_json")
+             .help("Prints the agent's version, algorithm, IPC transports and the programs the other flags select, with their parameters, as JSON, then exits."))
+        .arg(Arg::with_name("daemon")
+             .long("daemon")
+             .help("Detaches from the terminal and runs in the background. Log output is discarded."))
+        .arg(Arg::with_name("pidfile")
+             .long("pidfile")
+             .help("Writes the agent's pid to the given file, and removes it on exit.")
+             .takes_value(true)
+             .value_name("path"))
+        .arg(Arg::with_name("state_dump")
+             .long("state_dump")
+             .help("On SIGUSR1, writes every flow's state as JSON lines to the given file instead of the log.")
+             .takes_value(true)
+             .value_name("path"))
+        .arg(Arg::with_name("path_cache_file")
+             .long("path_cache_file")
+             .help("Loads the learned bottleneck rates and min RTTs from the given JSON file at startup, and saves them to it on exit, so that restarting the agent does not reset every path. Estimates older than the path cache TTL are dropped.")
+             .takes_value(true)
+             .value_name("path"))
+        .arg(Arg::with_name("control_socket")
+             .long("control_socket")
+             .help("Listens for commands on a Unix socket at the given path, one per line: pause <sock_id> stops a flow's probing, and resume <sock_id> restores it; limit <sock_id> <rate> caps a flow's rate, and unlimit <sock_id> clears the cap.")
+             .takes_value(true)
+             .value_name("path"))
+        .arg(Arg::with_name("stats_sink")
+             .long("stats_sink")
+             .help("Periodically pushes every flow's bottleneck rate, RTTs, inflight, mode and losses to a time-series database: influx://<host>:<port> sends InfluxDB line protocol over UDP, graphite://<host>:<port> Graphite plaintext over TCP.")
+             .takes_value(true)
+             .value_name("url")
+             .validator(|sink| sink.parse::<StatsSink>().map(drop)))
+        .arg(Arg::with_name("stats_interval")
+             .long("stats_interval")
+             .help("Sets how often --stats_sink pushes; bare numbers are milliseconds.")
+             .default_value("1s"))
+        .arg(Arg::with_name("grpc_listen")
+             .long("grpc_listen")
+             .help("Serves the gRPC Agent service of proto/bbr.proto on the given address, for controllers that list, watch and steer flows. Needs an agent built with the grpc feature.")
+             .takes_value(true)
+             .value_name("addr")
+             .validator(|addr| addr.parse::<SocketAddr>().map(drop).map_err(|e| e.to_string())))
+        .subcommand(SubCommand::with_name("selftest")
+             .about("Serves the datapath, sends to the target over a connection the kernel hands to CCP, and prints whether the transfer achieved the expected rate without a standing queue or heavy loss. Exits with 1 if it did not. Needs a kernel datapath transport and a target that discards what it is sent.")
+             .arg(Arg::with_name("target")
+                  .help("The host and port to send to, e.g. 10.0.0.2:5201.")
+                  .required(true)
+                  .index(1))
+             .arg(Arg::with_name("duration")
+                  .long("duration")
+                  .help("Sets how long to send for; bare numbers are milliseconds.")
+                  .default_value("30s"))
+             .arg(Arg::with_name("expected_rate")
+                  .long("expected_rate")
+                  .help("Sets the rate the transfer should achieve, e.g. 100Mbps; by default, the flow's own bottleneck estimate.")
+                  .takes_value(true)
+                  .validator(|rate| rate.parse::<Rate>().map(drop))))
         .get_matches();
 
-    let probe_rtt_interval_arg = std::time::Duration::from_secs(
-        u64::from_str_radix(matches.value_of("probe_rtt_interval").unwrap(), 10)
+    // daemonizing changes to /, so resolve paths first
+    let resolve = |name| {
+        matches
+            .value_of(name)
+            .map(|p| std::env::current_dir().map(|cwd| cwd.join(p)))
+            .transpose()
             .map_err(|e| format!("{:?}", e))
-            .and_then(|probe_rtt_interval_arg| {
-                if probe_rtt_interval_arg <= 0 {
-                    Err(format!(
-                        "probe_rtt_interval must be positive: {}",
-                        probe_rtt_interval_arg
-                    ))
-                } else {
-                    Ok(probe_rtt_interval_arg)
-                }
-            })?,
-    );
+    };
+    let pidfile = resolve("pidfile")?;
+    let state_dump = resolve("state_dump")?;
+    let path_cache_file = resolve("path_cache_file")?;
+    let control_socket = resolve("control_socket")?;
+    let record = resolve("record")?;
+
+    let stats_sink = matches.value_of("stats_sink").map(str::parse).transpose()?;
+    let stats_interval = parse_duration(
+        matches.value_of("stats_interval").unwrap(),
+        Duration::from_millis(1),
+    )?;
+    if stats_interval.is_zero() {
+        return Err(String::from("stats interval must be positive"));
+    }
+    let grpc_listen = matches
+        .value_of("grpc_listen")
+        .map(|addr| addr.parse().unwrap());
+    if grpc_listen.is_some() && cfg!(not(feature = "grpc")) {
+        return Err(String::from(
+            "--grpc_listen needs an agent built with the grpc feature",
+        ));
+    }
+
+    let selftest = matches
+        .subcommand_matches("selftest")
+        .map(|selftest| {
+            let target = selftest.value_of("target").unwrap();
+            let target = target
+                .to_socket_addrs()
+                .map_err(|e| format!("resolving {}: {}", target, e))?
+                .find(SocketAddr::is_ipv4)
+                .ok_or_else(|| format!("{} has no IPv4 address", target))?;
+            let duration = parse_duration(
+                selftest.value_of("duration").unwrap(),
+                Duration::from_millis(1),
+            )?;
+            let expected_rate = selftest
+                .value_of("expected_rate")
+                .map(|rate| rate.parse().unwrap());
+            Ok::<_, String>(SelfTestArgs {
+                target,
+                duration,
+                expected_rate,
+            })
+        })
+        .transpose()?;
+
+    let cfg = BbrConfig::from_arg_matches(&matches).map_err(|e| e.to_string())?;
+    // with --datapath auto, each transport gets the programs for the datapath it usually serves
+    let auto_datapath = matches.value_of("datapath") == Some("auto");
+    let transports = parse_transports(matches.value_of("ipc").unwrap())?
+        .iter()
+        .map(|ipc| {
+            let datapath = if auto_datapath {
+                DatapathKind::for_ipc(ipc)
+            } else {
+                cfg.datapath
+            };
+            Transport::new(&cfg, ipc, datapath)
+        })
+        .collect::<Vec<_>>();
+    if selftest.is_some()
+        && transports
+            .iter()
+            .all(|t| t.cfg.datapath != DatapathKind::Kernel)
+    {
+        return Err(String::from(
+            "selftest sends over a kernel connection, so needs a kernel datapath transport, e.g. --ipc netlink",
+        ));
+    }
+
+    Ok(Args {
+        cfg,
+        transports,
+        replay: matches.value_of("replay").map(String::from),
+        record,
+        dump_programs: matches.is_present("dump_programs"),
+        dump_config: matches.is_present("dump_config"),
+        version_json: matches.is_present("version_json"),
+        daemon: matches.is_present("daemon"),
+        pidfile,
+        state_dump,
+        path_cache_file,
+        control_socket,
+        stats_sink,
+        stats_interval,
+        grpc_listen,
+        selftest,
+    })
+}
+
+fn run_replay(cfg: &BbrConfig, trace: &str) -> Result<(), String> {
+    let f = std::fs::File::open(trace).map_err(|e| format!("opening {}: {}", trace, e))?;
+    ccp_bbr::trace::replay(cfg, std::io::BufReader::new(f), |decision| {
+        println!("{}", decision)
+    })
+}
+
+// socket ids are only unique within one datapath, so each transport records a trace of its own
+fn start_recording(transports: &mut [Transport], path: &Path) -> std::io::Result<()> {
+    let several = transports.len() > 1;
+    for transport in transports {
+        let path = if several {
+            PathBuf::from(format!("{}.{}", path.display(), transport.ipc))
+        } else {
+            path.to_path_buf()
+        };
+        transport.cfg.recorder = Some(Recorder::create(&path)?);
+        info!(ipc = ?transport.ipc, ?path, "recording reports");
+    }
+    Ok(())
+}
+
+// sends to the target over a connection the kernel hands to CCP, which the transports serve,
+// and collects what the agent saw of it
+fn run_selftest(args: &SelfTestArgs, transports: &[Transport]) -> Result<SelfTest, String> {
+    let target = args.target;
+    let mut stream = TcpStream::connect_timeout(&target, SELFTEST_CONNECT_TIMEOUT)
+        .map_err(|e| format!("connecting to {}: {}", target, e))?;
+    setsockopt(
+        stream.as_raw_fd(),
+        sockopt::TcpCongestion,
+        &OsString::from("ccp"),
+    )
+    .map_err(|e| format!("could not hand the connection to ccp: {}", e))?;
+    stream
+        .set_write_timeout(Some(Duration::from_secs(1)))
+        .map_err(|e| e.to_string())?;
+
+    info!(%target, duration = ?args.duration, "running selftest");
+    let start = Instant::now();
+    let sent_bytes = selftest::send(&mut stream, args.duration)
+        .map_err(|e| format!("sending to {}: {}", target, e))?;
+    let elapsed = start.elapsed();
+
+    // the flow's snapshot goes once the connection closes, so look it up first
+    let local = stream.local_addr().map_err(|e| e.to_string())?;
+    let flows: Vec<_> = transports
+        .iter()
+        .flat_map(|t| t.cfg.snapshots.flows())
+        .collect();
+    let mut unpaced_interfaces: Vec<_> = transports
+        .iter()
+        .flat_map(|t| t.cfg.capabilities.unpaced_interfaces())
+        .collect();
+    unpaced_interfaces.sort();
+    unpaced_interfaces.dedup();
+    Ok(SelfTest {
+        target,
+        elapsed,
+        sent_bytes,
+        expected_rate: args.expected_rate,
+        flow: selftest::find_flow(&flows, local, target),
+        unpaced_interfaces,
+    })
+}
+
+fn print_programs(cfg: &BbrConfig) {
+    let mut programs: Vec<_> = cfg.programs().into_iter().collect();
+    programs.sort();
+    for (name, program) in programs {
+        println!("== {} ==", name);
+        println!("{}\n", program.trim());
+    }
+}
+
+#[cfg(feature = "systemd")]
+fn notify_systemd(state: &str) {
+    if let Err(err) = ccp_bbr::systemd::notify(state) {
+        warn!(?err, state, "could not notify systemd");
+    }
+}
+
+// portus does not say when the datapath connects, so the agent is ready once it has its
+// programs and is about to serve
+#[cfg(feature = "systemd")]
+fn start_systemd_notifications() {
+    notify_systemd("READY=1");
+    if let Some(interval) = ccp_bbr::systemd::watchdog_interval() {
+        std::thread::spawn(move || loop {
+            notify_systemd("WATCHDOG=1");
+            std::thread::sleep(interval / 2);
+        });
+    }
+}
+
+fn print_version_json(cfg: &BbrConfig) {
+    let info = serde_json::json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "algorithms": [<BbrConfig as CongAlg<Socket<Blocking>>>::name()],
+        "ipc": IPC_TRANSPORTS,
+        "programs": cfg.program_parameters(),
+        "program_version": ccp_bbr::PROGRAM_VERSION,
+    });
+    println!("{}", info);
+}
+
+// each flow's line names the transport it came from, since socket ids are only unique within
+// one datapath
+fn dump_state(transports: &[Transport], path: Option<&Path>) {
+    let mut lines = vec![];
+    for transport in transports {
+        let stats = transport.stats();
+        info!(stats = %serde_json::to_string(&stats).unwrap(), "datapath stats");
+        for flow in transport.cfg.snapshots.flows() {
+            let mut flow = serde_json::to_value(flow).unwrap();
+            flow["ipc"] = serde_json::Value::from(transport.ipc.as_str());
+            lines.push(flow.to_string());
+        }
+    }
 
-    Ok((
-        BbrConfig {
-            probe_rtt_interval: probe_rtt_interval_arg,
-        },
-        String::from(matches.value_of("ipc").unwrap()),
-    ))
+    match path {
+        Some(path) => {
+            let dump: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+            match std::fs::write(path, dump) {
+                Ok(()) => info!(flows = lines.len(), ?path, "dumped flow state"),
+                Err(err) => warn!(?err, ?path, "could not dump flow state"),
+            }
+        }
+        None => {
+            info!(flows = lines.len(), "dumping flow state");
+            for line in lines {
+                info!(flow = %line, "flow state");
+            }
+        }
+    }
+}
+
+fn load_path_cache(path_cache: &PathCache, file: &Path) {
+    match path_cache.load(file, WallClock::now()) {
+        Ok(paths) => info!(paths, ?file, "loaded path cache"),
+        Err(err) => warn!(?err, ?file, "could not load path cache"),
+    }
+}
+
+fn save_path_cache(path_cache: &PathCache, file: &Path) {
+    match path_cache.save(file, WallClock::now()) {
+        Ok(paths) => info!(paths, ?file, "saved path cache"),
+        Err(err) => warn!(?err, ?file, "could not save path cache"),
+    }
+}
+
+// dumps the flows' state on SIGUSR1; on any other signal, lets flows release themselves to
+// the datapath, saves what they learned, then exits
+fn handle_signals(
+    signals: SigSet,
+    shutdown: Shutdown,
+    transports: Vec<Transport>,
+    state_dump: Option<PathBuf>,
+    pidfile: Option<PathBuf>,
+    path_cache: Option<(PathCache, PathBuf)>,
+    control_socket: Option<PathBuf>,
+) -> ! {
+    let signal = loop {
+        match signals.wait() {
+            Ok(Signal::SIGUSR1) => dump_state(&transports, state_dump.as_deref()),
+            signal => break signal,
+        }
+    };
+    info!(?signal, flows = shutdown.active_flows(), "shutting down");
+    #[cfg(feature = "systemd")]
+    notify_systemd("STOPPING=1");
+    shutdown.request();
+
+    let remaining = shutdown.wait_for_release(Duration::from_millis(SHUTDOWN_GRACE_MS));
+    if remaining > 0 {
+        warn!(flows = remaining, "exiting without releasing all flows");
+    }
+
+    if let Some((path_cache, file)) = &path_cache {
+        save_path_cache(path_cache, file);
+    }
+
+    if let Some(pidfile) = pidfile {
+        if let Err(err) = std::fs::remove_file(&pidfile) {
+            warn!(?err, ?pidfile, "could not remove pidfile");
+        }
+    }
+
+    if let Some(control_socket) = control_socket {
+        if let Err(err) = std::fs::remove_file(&control_socket) {
+            warn!(?err, ?control_socket, "could not remove control socket");
+        }
+    }
+
+    info!("exiting");
+    std::process::exit(0)
 }
 
 fn main() {
     tracing_subscriber::fmt::init();
-    let (cfg, ipc) = make_args()
+    let Args {
+        cfg,
+        mut transports,
+        replay,
+        record,
+        dump_programs,
+        dump_config,
+        version_json,
+        daemon,
+        pidfile,
+        state_dump,
+        path_cache_file,
+        control_socket,
+        stats_sink,
+        stats_interval,
+        grpc_listen,
+        selftest,
+    } = make_args()
         .map_err(|e| warn!(err = ?e, "bad argument"))
         .unwrap();
 
-    info!(?ipc, probe_rtt_interval = ?cfg.probe_rtt_interval, "configured BBR");
-    portus::start!(ipc.as_str(), cfg).unwrap()
+    if version_json {
+        print_version_json(&cfg);
+        return;
+    }
+
+    if dump_programs {
+        print_programs(&cfg);
+        return;
+    }
+
+    if dump_config {
+        println!("{}", Control::new(transports).config());
+        return;
+    }
+
+    if let Some(trace) = replay {
+        info!(?trace, "replaying trace");
+        run_replay(&cfg, &trace)
+            .map_err(|e| warn!(err = ?e, "replay failed"))
+            .unwrap();
+        return;
+    }
+
+    if daemon {
+        nix::unistd::daemon(false, false)
+            .map_err(|e| warn!(err = ?e, "could not daemonize"))
+            .unwrap();
+    }
+
+    if let Some(pidfile) = &pidfile {
+        std::fs::write(pidfile, format!("{}\n", std::process::id()))
+            .map_err(|e| warn!(err = ?e, ?pidfile, "could not write pidfile"))
+            .unwrap();
+    }
+
+    if let Some(path) = &record {
+        start_recording(&mut transports, path)
+            .map_err(|e| warn!(err = ?e, ?path, "could not record"))
+            .unwrap();
+    }
+
+    // block the signals before any other thread starts, so only the signal thread sees them
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGTERM);
+    signals.add(Signal::SIGINT);
+    signals.add(Signal::SIGUSR1);
+    signals
+        .thread_block()
+        .map_err(|e| warn!(err = ?e, "could not block signals"))
+        .unwrap();
+    if let Some(file) = &path_cache_file {
+        load_path_cache(&cfg.path_cache, file);
+    }
+
+    if let Some(path) = &control_socket {
+        let control = Control::new(transports.clone());
+        let control = match &stats_sink {
+            Some(sink) => control.with_stats_sink(sink.clone()),
+            None => control,
+        };
+        control
+            .listen(path)
+            .map_err(|e| warn!(err = ?e, ?path, "could not listen on control socket"))
+            .unwrap();
+    }
+
+    if let Some(sink) = stats_sink {
+        sink.start(transports.clone(), stats_interval)
+            .map_err(|e| warn!(err = ?e, "could not start pushing stats"))
+            .unwrap();
+    }
+
+    // only the kernel paces flows by their socket's pacing rate, and only with fq
+    #[cfg(feature = "qdisc")]
+    {
+        let paced: Vec<_> = transports
+            .iter()
+            .filter(|t| t.cfg.pacing && t.cfg.datapath == DatapathKind::Kernel)
+            .map(|t| t.cfg.capabilities.clone())
+            .collect();
+        if !paced.is_empty() {
+            let interval = Duration::from_secs(ccp_bbr::QDISC_CHECK_INTERVAL_SECONDS);
+            ccp_bbr::qdisc::start(paced, interval)
+                .map_err(|e| warn!(err = ?e, "could not start checking qdiscs"))
+                .unwrap();
+        }
+    }
+
+    #[cfg(feature = "grpc")]
+    if let Some(addr) = grpc_listen {
+        ccp_bbr::grpc::AgentService::new(transports.clone())
+            .serve(addr)
+            .map_err(|e| warn!(err = ?e, %addr, "could not serve gRPC"))
+            .unwrap();
+    }
+    #[cfg(not(feature = "grpc"))]
+    let _ = grpc_listen;
+
+    let shutdown = cfg.shutdown.clone();
+    shutdown.release_on_panic();
+    let panicked = shutdown.clone();
+    let dumped = transports.clone();
+    let path_cache = path_cache_file.map(|file| (cfg.path_cache.clone(), file));
+    std::thread::spawn(move || {
+        handle_signals(
+            signals,
+            shutdown,
+            dumped,
+            state_dump,
+            pidfile,
+            path_cache,
+            control_socket,
+        )
+    });
+
+    info!(probe_rtt_interval = ?cfg.probe_rtt_interval, "configured BBR");
+    let tested = selftest.as_ref().map(|_| transports.clone());
+    // each transport is served from its own thread; the agent stops with the first to stop
+    let (stopped, first_stopped) = std::sync::mpsc::channel();
+    for Transport { ipc, cfg } in transports {
+        info!(?ipc, datapath = ?cfg.datapath, "serving datapath");
+        let stopped = stopped.clone();
+        std::thread::Builder::new()
+            .name(format!("bbr-{}", ipc))
+            .spawn(move || {
+                let served = portus::start!(ipc.as_str(), cfg);
+                stopped.send((ipc, served)).unwrap();
+            })
+            .unwrap();
+    }
+    #[cfg(feature = "systemd")]
+    start_systemd_notifications();
+
+    if let (Some(selftest), Some(transports)) = (selftest, tested) {
+        let passed = match run_selftest(&selftest, &transports) {
+            Ok(result) => {
+                println!("{}", result);
+                result.passed()
+            }
+            Err(err) => {
+                println!("selftest: {}\nverdict: FAIL", err);
+                false
+            }
+        };
+        // as on a signal, flows release themselves before the agent exits
+        cfg.shutdown.request();
+        cfg.shutdown
+            .wait_for_release(Duration::from_millis(SHUTDOWN_GRACE_MS));
+        std::process::exit(if passed { 0 } else { 1 })
+    }
+
+    // or once a panic, on whichever thread, asked the flows to release themselves
+    let (ipc, served) = loop {
+        match first_stopped.recv_timeout(Duration::from_millis(100)) {
+            Ok(stopped) => break stopped,
+            Err(_) if panicked.panicked() => {
+                let remaining = panicked.wait_for_release(Duration::from_millis(SHUTDOWN_GRACE_MS));
+                if remaining > 0 {
+                    warn!(flows = remaining, "exiting without releasing all flows");
+                }
+                std::process::exit(1)
+            }
+            Err(_) => {}
+        }
+    };
+    warn!(?ipc, "stopped serving the datapath");
+    served.unwrap()
 }
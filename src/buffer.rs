@@ -0,0 +1,89 @@
+//! How deep the bottleneck's buffer is, from the queues PROBE_BW's up pulses build.
+//!
+//! An up pulse sends faster than the bottleneck drains, and the excess queues there: the
+//! reports of the up pulse and of the down pulse after it see the smoothed RTT rise above
+//! `min_rtt`, by the queue's bytes over the bottleneck rate. A probe that lost packets to
+//! congestion filled the buffer, so the queue it saw is about the buffer's depth; otherwise the
+//! buffer holds at least that much. The estimate is the largest queue of the last
+//! `BUFFER_WINDOW_PROBES` probes that were not application-limited, and whether one of them
+//! overflowed. A smoothed RTT lags the queue, so the estimate errs on the shallow side.
+//!
+//! With `fit_probes_to_buffer`, once probes overflow the buffer, a flow lowers its up pulse's
+//! gain so that the pulse queues at most `BUFFER_PROBE_FRACTION` of the buffer, which spares it
+//! and the flows sharing the buffer the losses every probe would otherwise cause.
+
+use crate::params::BUFFER_WINDOW_PROBES;
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// The bottleneck buffer a flow's probes found.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct BufferDepth {
+    /// The largest queue the probes built.
+    pub bytes: u64,
+    /// `bytes` over the flow's BDP.
+    pub bdp: f64,
+    /// Whether a probe filled the buffer, so that `bytes` is its depth rather than a lower
+    /// bound on it.
+    pub overflowed: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct ProbeQueue {
+    bytes: f64,
+    overflowed: bool,
+}
+
+/// The queues of a flow's recent probes.
+#[derive(Clone, Debug, Default)]
+pub struct BufferEstimator {
+    probes: VecDeque<ProbeQueue>,
+    // the probe whose up pulse reported, until its down pulse does
+    current: Option<ProbeQueue>,
+}
+
+impl BufferEstimator {
+    /// Adds the report of an up pulse, which starts a probe, or of the down pulse that ends
+    /// it: the queue its smoothed RTT shows, in bytes, and whether it lost packets to
+    /// congestion.
+    pub fn record(&mut self, up: bool, queue_bytes: f64, overflowed: bool) {
+        let sample = ProbeQueue {
+            bytes: queue_bytes.max(0.0),
+            overflowed,
+        };
+        if up {
+            self.current = Some(sample);
+            return;
+        }
+        let Some(probe) = self.current.take() else {
+            return;
+        };
+        self.probes.push_back(ProbeQueue {
+            bytes: probe.bytes.max(sample.bytes),
+            overflowed: probe.overflowed || sample.overflowed,
+        });
+        if self.probes.len() > BUFFER_WINDOW_PROBES {
+            self.probes.pop_front();
+        }
+    }
+
+    /// Drops a probe whose up pulse reported, but that did not build a queue of its own.
+    pub fn discard(&mut self) {
+        self.current = None;
+    }
+
+    /// The deepest queue of the recent probes, or `None` before any probe ended; `bdp_bytes`
+    /// scales it into `BufferDepth::bdp`.
+    pub fn estimate(&self, bdp_bytes: f64) -> Option<BufferDepth> {
+        let bytes = self.probes.iter().map(|p| p.bytes).reduce(f64::max)?;
+        Some(BufferDepth {
+            bytes: bytes as u64,
+            bdp: if bdp_bytes > 0.0 {
+                bytes / bdp_bytes
+            } else {
+                0.0
+            },
+            overflowed: self.probes.iter().any(|p| p.overflowed),
+        })
+    }
+}
@@ -0,0 +1,76 @@
+//! What the datapath turned out to provide.
+//!
+//! portus does not let the agent ask a datapath which primitives it implements, and a program
+//! that reads an unimplemented primitive may still install and just read zero. So flows check
+//! their reports against what the programs rely on instead, and fall back to program features
+//! that do not need the missing primitive. A datapath that refuses to install `probe_bw` at
+//! all gets a cwnd-only AIMD program instead. Whatever one flow finds out applies to every flow
+//! of the same `BbrConfig`.
+//!
+//! On kernel datapaths, whether `Rate` is enforced also depends on the host: the kernel only
+//! paces a socket's packets at the rate the agent sets if its interface's qdisc is `fq`. With
+//! the `qdisc` feature, the agent checks which interfaces lack it; see [`crate::qdisc`].
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub use crate::params::{
+    PROBE_BW_INSTALL_ATTEMPTS, UNENFORCED_RATE_FACTOR, UNENFORCED_RATE_REPORTS,
+};
+
+#[derive(Clone, Default)]
+pub struct DatapathCapabilities {
+    no_flow_rates: Arc<AtomicBool>,
+    no_rate_enforcement: Arc<AtomicBool>,
+    no_probe_bw: Arc<AtomicBool>,
+    unpaced_interfaces: Arc<Mutex<Vec<String>>>,
+}
+
+impl DatapathCapabilities {
+    /// Whether `Flow.rate_outgoing` and `Flow.rate_incoming` stay at zero while data is acked.
+    pub fn lacks_flow_rates(&self) -> bool {
+        self.no_flow_rates.load(Ordering::SeqCst)
+    }
+
+    /// Whether the datapath sends faster than `Rate` allows.
+    pub fn lacks_rate_enforcement(&self) -> bool {
+        self.no_rate_enforcement.load(Ordering::SeqCst)
+    }
+
+    /// Whether the datapath rejects the `probe_bw` program, e.g. for lack of a rate primitive.
+    pub fn lacks_probe_bw(&self) -> bool {
+        self.no_probe_bw.load(Ordering::SeqCst)
+    }
+
+    /// The interfaces that were up without a pacing qdisc when last checked, so that flows
+    /// sending on them are not paced. Empty unless something checks; see [`crate::qdisc`].
+    pub fn unpaced_interfaces(&self) -> Vec<String> {
+        self.unpaced_interfaces.lock().unwrap().clone()
+    }
+
+    /// Records the interfaces a check found without a pacing qdisc, and returns whether they
+    /// changed since the last check.
+    pub fn set_unpaced_interfaces(&self, interfaces: Vec<String>) -> bool {
+        let mut unpaced = self.unpaced_interfaces.lock().unwrap();
+        if *unpaced == interfaces {
+            return false;
+        }
+        *unpaced = interfaces;
+        true
+    }
+
+    /// Returns whether this is news.
+    pub(crate) fn mark_no_flow_rates(&self) -> bool {
+        !self.no_flow_rates.swap(true, Ordering::SeqCst)
+    }
+
+    /// Returns whether this is news.
+    pub(crate) fn mark_no_rate_enforcement(&self) -> bool {
+        !self.no_rate_enforcement.swap(true, Ordering::SeqCst)
+    }
+
+    /// Returns whether this is news.
+    pub(crate) fn mark_no_probe_bw(&self) -> bool {
+        !self.no_probe_bw.swap(true, Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,164 @@
+//! Fault injection, for testing that flows survive a misbehaving datapath.
+//!
+//! [`FaultyIpc`] wraps a portus transport and drops, delays, duplicates and fails messages in
+//! either direction; `sim::Simulation::with_faults` does the same to reports and actions on
+//! the simulated link. Faults are drawn from a seeded generator, so a failing run can be
+//! reproduced.
+
+use portus::ipc::Ipc;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// The probability of each fault, per message.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Faults {
+    /// The message is lost.
+    pub drop: f64,
+    /// The message arrives after the next one.
+    pub delay: f64,
+    /// The message arrives twice.
+    pub duplicate: f64,
+    /// Sending or receiving the message fails with an error.
+    pub error: f64,
+}
+
+impl Faults {
+    /// Every fault with the same probability.
+    pub fn uniform(p: f64) -> Self {
+        Faults {
+            drop: p,
+            delay: p,
+            duplicate: p,
+            error: p,
+        }
+    }
+}
+
+/// A xorshift generator; faults need to be reproducible, not unpredictable.
+#[derive(Clone, Debug)]
+pub struct FaultRng(u64);
+
+impl FaultRng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift never leaves zero
+        FaultRng(seed.max(1))
+    }
+
+    /// Whether an event of probability `p` happens.
+    pub fn chance(&mut self, p: f64) -> bool {
+        if p <= 0.0 {
+            return false;
+        }
+
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        ((self.0 >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+struct Held<A> {
+    rng: FaultRng,
+    /// Sent messages that wait for the next send.
+    sends: VecDeque<(Vec<u8>, A)>,
+    /// Received messages to hand out before receiving another.
+    recvs: VecDeque<(Vec<u8>, A)>,
+    /// A received message that waits for the next one.
+    delayed_recv: Option<(Vec<u8>, A)>,
+}
+
+/// A transport that injects [`Faults`] into the messages of another.
+pub struct FaultyIpc<I: Ipc> {
+    inner: I,
+    faults: Faults,
+    held: Mutex<Held<I::Addr>>,
+}
+
+impl<I: Ipc> FaultyIpc<I> {
+    pub fn new(inner: I, faults: Faults, seed: u64) -> Self {
+        FaultyIpc {
+            inner,
+            faults,
+            held: Mutex::new(Held {
+                rng: FaultRng::new(seed),
+                sends: VecDeque::new(),
+                recvs: VecDeque::new(),
+                delayed_recv: None,
+            }),
+        }
+    }
+}
+
+fn injected(what: &str) -> portus::Error {
+    portus::Error(format!("injected {} fault", what))
+}
+
+impl<I: Ipc> Ipc for FaultyIpc<I>
+where
+    I::Addr: Send,
+{
+    type Addr = I::Addr;
+
+    fn name() -> String {
+        format!("faulty-{}", I::name())
+    }
+
+    fn send(&self, msg: &[u8], to: &Self::Addr) -> portus::Result<()> {
+        let mut held = self.held.lock().unwrap();
+        if held.rng.chance(self.faults.error) {
+            return Err(injected("send"));
+        }
+        if held.rng.chance(self.faults.drop) {
+            return Ok(());
+        }
+        if held.rng.chance(self.faults.delay) {
+            held.sends.push_back((msg.to_vec(), to.clone()));
+            return Ok(());
+        }
+
+        self.inner.send(msg, to)?;
+        if held.rng.chance(self.faults.duplicate) {
+            self.inner.send(msg, to)?;
+        }
+        while let Some((delayed, to)) = held.sends.pop_front() {
+            self.inner.send(&delayed, &to)?;
+        }
+        Ok(())
+    }
+
+    fn recv(&self, msg: &mut [u8]) -> portus::Result<(usize, Self::Addr)> {
+        loop {
+            if let Some((queued, from)) = self.held.lock().unwrap().recvs.pop_front() {
+                let len = queued.len().min(msg.len());
+                msg[..len].copy_from_slice(&queued[..len]);
+                return Ok((len, from));
+            }
+
+            let (len, from) = self.inner.recv(msg)?;
+            let mut held = self.held.lock().unwrap();
+            if held.rng.chance(self.faults.error) {
+                return Err(injected("recv"));
+            }
+            if held.rng.chance(self.faults.drop) {
+                continue;
+            }
+
+            let received = (msg[..len].to_vec(), from.clone());
+            if held.rng.chance(self.faults.delay) && held.delayed_recv.is_none() {
+                held.delayed_recv = Some(received);
+                continue;
+            }
+            if held.rng.chance(self.faults.duplicate) {
+                held.recvs.push_back(received);
+            }
+            if let Some(delayed) = held.delayed_recv.take() {
+                held.recvs.push_back(delayed);
+            }
+            return Ok((len, from));
+        }
+    }
+
+    fn close(&mut self) -> portus::Result<()> {
+        self.inner.close()
+    }
+}
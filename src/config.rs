@@ -0,0 +1,1143 @@
+//! The configuration all flows share, and the flags it is built from.
+
+use crate::bandwidth::Rate;
+use crate::capability::DatapathCapabilities;
+use crate::datapath::DatapathKind;
+use crate::duration::parse_duration;
+use crate::error::BbrError;
+use crate::flow_limit::FlowLimits;
+use crate::flow_match::FlowFilter;
+use crate::group::BottleneckGroups;
+use crate::initial::InitialPathRule;
+use crate::log_granularity::LogGranularity;
+use crate::loss::{LossAccounting, LossMode, LOSS_BURST_FRACTION, LOSS_RTT_INFLATION};
+use crate::max_rate::MaxRateRule;
+use crate::min_rate::MinRateRule;
+use crate::params::{
+    BW_FILTER_ROUNDS, CWND_BDP_MULTIPLIER, DOWN_PHASE_END_PULSES, DRAIN_GAIN,
+    HIGH_RTT_CYCLE_ROUNDS, MAX_BW_WINDOW_ROUNDS, MAX_CYCLE_PULSES, MIN_RTT_SPIKE_FACTOR,
+    PROBE_GAIN, PROBE_RTT_INTERVAL_SECONDS, PROBE_RTT_SYNC_WINDOW_MS, PULSE_CYCLE_ROUNDS,
+    STALE_PROBES, STARTUP_CWND_GAIN, STARTUP_GAIN, WARN_INTERVAL_SECONDS,
+};
+use crate::path_cache::PathCache;
+use crate::pause::PausedFlows;
+use crate::probe_schedule::ProbeSchedule;
+use crate::rate::RateEstimator;
+use crate::rate_hint::RateHints;
+use crate::short_flow::ShortFlowRule;
+use crate::shutdown::Shutdown;
+use crate::snapshot::Snapshots;
+use crate::tenant::{TenantConfig, TenantLookup, TenantRule, Tenants};
+use crate::trace::Recorder;
+use crate::update_failure::UpdateFailurePolicy;
+use crate::warn_limit::WarnLimiter;
+use crate::weight::{FlowWeights, WeightRule};
+use clap::Arg;
+use portus::CongAlgBuilder;
+use std::fmt::{Debug, Display};
+use std::ops::{Bound, RangeBounds};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+#[derive(Clone)]
+pub struct BbrConfig {
+    /// If not empty, flows that no filter matches are left to the datapath's own congestion
+    /// control, rather than run BBR.
+    pub flow_filters: Vec<FlowFilter>,
+    /// Zero disables `PROBE_RTT`.
+    pub probe_rtt_interval: Duration,
+    /// If set, `PROBE_RTT` is due after this many round trips in `PROBE_BW` since `min_rtt`
+    /// was last measured, counted in whole pulse cycles of `cruise_phase_end`, rather than
+    /// after `probe_rtt_interval`. With `PROBE_RTT` disabled, it is the `min_rtt` window
+    /// instead.
+    pub probe_rtt_rounds: Option<u32>,
+    /// `min_rtt` samples that differ from the estimate by more than this factor, such as from
+    /// a delayed ACK or a corrupted timestamp, are ignored unless the next sample confirms
+    /// them. `None` takes every sample.
+    pub min_rtt_spike_factor: Option<f64>,
+    /// If set, `PROBE_RTT` starts at multiples of `probe_rtt_interval` on this wall clock
+    /// instead of one interval after the flow's last `min_rtt` sample.
+    pub probe_rtt_alignment: Option<WallClock>,
+    /// A flow's first `PROBE_RTT` is due no sooner than this long after it leaves STARTUP
+    /// and DRAIN, however long ago it last measured `min_rtt`; `None` waits a whole
+    /// `probe_rtt_interval`. Flows aligned to a wall clock keep to it instead.
+    pub probe_rtt_grace: Option<Duration>,
+    /// Skips a due `PROBE_RTT` if the flow was app-limited or idle at some point since it last
+    /// measured `min_rtt`: it sent too little to keep a queue up then, so its RTT samples from
+    /// the window are as good as what `PROBE_RTT` would measure, and refresh `min_rtt` instead.
+    pub skip_quiet_probe_rtt: bool,
+    /// Per-flow weights; the first matching rule applies.
+    pub weight_rules: Vec<WeightRule>,
+    pub weights: FlowWeights,
+    /// Seeds new flows with what earlier flows to the same destination learned.
+    pub path_cache: PathCache,
+    /// The bottleneck rate and min RTT that flows without a path cache estimate start from,
+    /// instead of `DEFAULT_INITIAL_RATE` and `DEFAULT_INITIAL_RTT`. With both set, the flows'
+    /// first window is one BDP of them, if that is more than the datapath's initial window.
+    pub initial_rate: Option<Rate>,
+    pub initial_rtt: Option<Duration>,
+    /// Per-destination `initial_rate` and `initial_rtt`; the first matching rule applies.
+    pub initial_path_rules: Vec<InitialPathRule>,
+    /// Caps every flow's bottleneck rate estimate and the rates it probes with.
+    pub max_rate: Option<Rate>,
+    /// Per-flow `max_rate`; the first matching rule applies.
+    pub max_rate_rules: Vec<MaxRateRule>,
+    /// Keeps every flow's pacing rate, and the window that carries it, at or above this floor,
+    /// whatever its estimate. `max_rate` wins over it.
+    pub min_rate: Option<Rate>,
+    /// Per-flow `min_rate`; the first matching rule applies.
+    pub min_rate_rules: Vec<MinRateRule>,
+    /// If set, every flow starts as a short flow, skipping STARTUP's probing with a generous
+    /// window and the path cache's rate, until it has acked this many bytes.
+    pub short_flow_bytes: Option<u64>,
+    /// Per-flow `short_flow_bytes`; the first matching rule applies.
+    pub short_flow_rules: Vec<ShortFlowRule>,
+    /// Tags flows with a tenant; the first matching rule applies. See [`tenant`].
+    pub tenant_rules: Vec<TenantRule>,
+    /// Where the tenant of a flow no rule matches comes from.
+    pub tenant_lookup: TenantLookup,
+    /// The settings of each tenant's flows; the last one given for a tenant applies.
+    pub tenant_configs: Vec<TenantConfig>,
+    /// The current flows of each tenant, which split its aggregate rate.
+    pub tenants: Tenants,
+    pub groups: BottleneckGroups,
+    /// If set, a flow enters `PROBE_RTT` early when another flow in its bottleneck group
+    /// entered it within this window, so that the group drains the queue together.
+    pub probe_rtt_sync_window: Option<Duration>,
+    /// Lengthens `PROBE_RTT` by the number of flows in the flow's bottleneck group, up to
+    /// `PROBE_RTT_MAX_GROUP_SCALE` times, so that the group's drains overlap even when its
+    /// members enter it one after another.
+    pub scale_probe_rtt: bool,
+    /// Shares `min_rtt` measurements within each bottleneck group: a flow takes a lower
+    /// `min_rtt` another flow in its group sampled, or the one another flow's `PROBE_RTT`
+    /// measured, and restarts its own `PROBE_RTT` timer from it.
+    pub share_min_rtt: bool,
+    /// If set, a flow that starts within `INCAST_WINDOW_MS` of at least this many others in
+    /// its bottleneck group, itself included, starts with its share of the initial window and
+    /// pacing rate, and with STARTUP gains of at most `INCAST_STARTUP_GAIN`, so that a
+    /// fan-in of many flows at once does not overflow the shared queue.
+    pub incast_threshold: Option<u32>,
+    /// Pacing gain over the bandwidth estimate during STARTUP.
+    pub startup_gain: f64,
+    /// Cwnd gain over the estimated BDP during STARTUP.
+    pub startup_cwnd_gain: f64,
+    /// Pacing gain over the bandwidth estimate during DRAIN.
+    pub drain_gain: f64,
+    /// Leave DRAIN as soon as inflight falls to the estimated BDP, rather than after one
+    /// round trip.
+    pub drain_to_target: bool,
+    /// Cap cwnd at `cwnd_bdp_multiplier` times the estimated BDP; otherwise only `Rate`
+    /// controls the flow, and cwnd is only lowered in `PROBE_RTT`.
+    pub cwnd_cap: bool,
+    /// Have `probe_bw` set cwnd from its registers on every ack, rather than only when a
+    /// pulse starts, so that cwnd follows the window the flow last wrote at once.
+    pub track_cwnd: bool,
+    /// Higher values tolerate more ACK jitter and aggregation, lower ones bound the queue a
+    /// flow can build.
+    pub cwnd_bdp_multiplier: f64,
+    /// Adds the spread of recent RTTs to the min RTT in the cwnd cap, so that jitter does not
+    /// leave the window smaller than the BDP most packets see.
+    pub jitter_headroom: bool,
+    /// If set, PROBE_BW keeps the queue it builds within this much delay over the min RTT:
+    /// cwnd is capped at the bandwidth estimate times the min RTT plus the budget, even
+    /// without `cwnd_cap`, and the up pulse's gain is lowered until the queue it builds drains
+    /// within the budget. Flows give up some throughput on paths with ACK aggregation.
+    pub delay_budget: Option<Duration>,
+    /// Lowers `bottle_rate` to what PROBE_BW delivered as soon as `STEP_DOWN_PHASES` cruise
+    /// phases in a row deliver less than `STEP_DOWN_RATIO` of it into an inflated RTT,
+    /// instead of waiting for the bandwidth filter to forget the old rate.
+    pub fast_step_down: bool,
+    /// Once PROBE_BW's probes overflow the bottleneck buffer, lowers the up pulse's gain so
+    /// that it queues at most `BUFFER_PROBE_FRACTION` of the buffer; see [`buffer`].
+    pub fit_probes_to_buffer: bool,
+    /// Reinstalls `probe_bw`, restarting its pulse cycle, once `PULSE_DESYNC_RESET` of its
+    /// reports in a row come from another pulse phase than the cycle says. Such reports are
+    /// counted either way.
+    pub reset_desynced_pulses: bool,
+    /// Whether the datapath enforces `Rate`. Without pacing, PROBE_BW pulses cwnd to 0.75 and
+    /// 1.25 BDP instead of the rate, and STARTUP and DRAIN only set cwnd.
+    pub pacing: bool,
+    /// Splits PROBE_BW's up pulse into two half-RTT steps at 1.125 and 1.25 times the
+    /// bandwidth estimate, bounding the burst at the start of each pulse on short-RTT paths.
+    pub probe_bw_ramp: bool,
+    /// After this many consecutive up pulses without bandwidth growth, PROBE_BW only probes
+    /// every `stale_probe_interval` cycles, cruising at the estimate in between.
+    pub stale_probes: u32,
+    /// One probes every cycle.
+    pub stale_probe_interval: u32,
+    /// If set, PROBE_BW probes with this gain instead of `PROBE_GAIN` once the bandwidth
+    /// estimate has been stable for `STABLE_PROBE_CYCLES` cycles, and returns to `PROBE_GAIN`
+    /// as soon as the estimate moves.
+    pub stable_probe_gain: Option<f64>,
+    /// If set, PROBE_BW's pulse phases last multiples of this instead of multiples of the
+    /// reported min RTT, so that a spuriously low RTT sample cannot shorten them.
+    pub pulse_length: Option<Duration>,
+    /// Measures how long PROBE_BW's register updates take to take effect, by echoing a nonce
+    /// register in its reports, and delays its up and down pulses' ends by that latency, up to
+    /// `MAX_PULSE_SHIFT` of a pulse, so that a pulse the agent restarts still lasts a full
+    /// pulse at its new rate.
+    pub pulse_shift: bool,
+    /// How many pulse lengths into a PROBE_BW cycle its down pulse ends, from 2, since the up
+    /// pulse takes the first. Later ends drain a standing queue for longer, at the down
+    /// pulse's rate.
+    pub down_phase_end: u32,
+    /// How many pulse lengths into a PROBE_BW cycle its cruise phase ends and the next up
+    /// pulse starts, after `down_phase_end` and up to `MAX_CYCLE_PULSES`. Shorter cycles probe
+    /// more often. Flows on the high-RTT profile keep cycles of `HIGH_RTT_CYCLE_ROUNDS`.
+    pub cruise_phase_end: u32,
+    /// How many pulse-length rounds, each about a min RTT, PROBE_BW's bandwidth filter
+    /// remembers, from 1 to `MAX_BW_WINDOW_ROUNDS`. A shorter window forgets bandwidth that
+    /// is gone sooner, at the cost of underestimating paths whose delivery rate varies.
+    pub bw_window: usize,
+    /// If set, flows whose min RTT is above this when they enter PROBE_BW, such as ones over
+    /// geostationary satellite links, run a profile for long paths: pulse cycles of
+    /// `HIGH_RTT_CYCLE_ROUNDS` rounds, a bandwidth filter `HIGH_RTT_BW_WINDOW_FACTOR` times as
+    /// long, and a PROBE_RTT that keeps `HIGH_RTT_PROBE_RTT_GAIN` of the BDP in flight.
+    pub high_rtt_threshold: Option<Duration>,
+    /// Whether PROBE_BW can start its next up pulse at a time `probe_schedule` gives, rather
+    /// than at the end of its cycle; see [`probe_schedule`].
+    pub schedule_probes: bool,
+    /// If set, reports that reached the agent longer than this before they were handled are
+    /// ignored, rather than acted on with measurements the flow has since moved on from.
+    pub max_report_age: Option<Duration>,
+    /// If set, each update moves PROBE_BW's pacing rates at most this fraction of their
+    /// installed values, e.g. 0.1, and later reports move them the rest of the way, so that
+    /// pacing offloads with a coarse rate granularity see no sudden jumps.
+    pub rate_smoothing: Option<f64>,
+    /// Which losses lower the bandwidth estimate in PROBE_BW.
+    pub loss_mode: LossMode,
+    /// In `LossMode::Lossy`, the factor by which a report's min RTT has to exceed `min_rtt`
+    /// for its losses to count as congestion.
+    pub loss_rtt_inflation: f64,
+    /// A PROBE_BW report that lost at least this fraction of the window halves cwnd, and
+    /// stops probing up for `LOSS_BURST_HOLD_CYCLES` pulse cycles, whatever `loss_mode`
+    /// says. `None` never does.
+    pub loss_burst_fraction: Option<f64>,
+    /// Ignore decreases of the min RTT reported along with a loss burst, or after a
+    /// retransmission timeout, which may be RTT samples of retransmitted packets. Such a
+    /// sample is taken anyway before the flow has measured its min RTT.
+    pub lossy_rtt_filter: bool,
+    /// How the programs tell lost packets from reordered ones.
+    pub loss_accounting: LossAccounting,
+    /// Where the programs' bandwidth samples come from.
+    pub rate_estimator: RateEstimator,
+    /// How many times in a row a flow rewrites its registers after the datapath fails to
+    /// apply an action, before `update_failure` decides what happens instead.
+    pub update_retries: u32,
+    pub update_failure: UpdateFailurePolicy,
+    /// Whether flows log every report, a summary of every PROBE_BW cycle, or only their mode
+    /// changes.
+    pub log_granularity: LogGranularity,
+    /// Warnings that recur within this long of being logged are only counted; see
+    /// [`warn_limit`]. Zero logs every warning.
+    pub warn_interval: Duration,
+    /// When each warning was last logged, across flows.
+    pub warnings: WarnLimiter,
+    pub shutdown: Shutdown,
+    /// Flows that stop probing for bandwidth until they are resumed.
+    pub paused: PausedFlows,
+    /// Flows capped below their configured maximum rate until the limit is cleared.
+    pub flow_limits: FlowLimits,
+    /// Flows whose next up pulse starts at a given time, with `schedule_probes`.
+    pub probe_schedule: ProbeSchedule,
+    /// The latest state of every flow, for dumping.
+    pub snapshots: Snapshots,
+    /// Subscribers to the flows' pacing rates; see [`rate_hint`].
+    pub rate_hints: RateHints,
+    /// If set, records every flow's start and reports for replaying.
+    pub recorder: Option<Recorder>,
+    /// What the flows found the datapath to lack.
+    pub capabilities: DatapathCapabilities,
+    /// Selects the program variants for the primitives the datapath provides.
+    pub datapath: DatapathKind,
+    /// If set, the most registers a program may define on the datapath; see [`registers`].
+    pub register_limit: Option<usize>,
+    // TODO make more things configurable
+}
+
+impl Default for BbrConfig {
+    fn default() -> Self {
+        BbrConfig {
+            probe_rtt_interval: Duration::from_secs(PROBE_RTT_INTERVAL_SECONDS as u64),
+            probe_rtt_rounds: None,
+            min_rtt_spike_factor: Some(MIN_RTT_SPIKE_FACTOR),
+            probe_rtt_alignment: None,
+            probe_rtt_grace: None,
+            skip_quiet_probe_rtt: false,
+            weight_rules: vec![],
+            weights: FlowWeights::default(),
+            path_cache: PathCache::default(),
+            initial_rate: None,
+            initial_rtt: None,
+            initial_path_rules: vec![],
+            max_rate: None,
+            max_rate_rules: vec![],
+            min_rate: None,
+            min_rate_rules: vec![],
+            short_flow_bytes: None,
+            short_flow_rules: vec![],
+            tenant_rules: vec![],
+            tenant_lookup: TenantLookup::default(),
+            tenant_configs: vec![],
+            tenants: Tenants::default(),
+            flow_filters: vec![],
+            groups: BottleneckGroups::default(),
+            probe_rtt_sync_window: None,
+            scale_probe_rtt: false,
+            share_min_rtt: false,
+            incast_threshold: None,
+            startup_gain: STARTUP_GAIN,
+            startup_cwnd_gain: STARTUP_CWND_GAIN,
+            drain_gain: DRAIN_GAIN,
+            drain_to_target: true,
+            cwnd_cap: true,
+            track_cwnd: false,
+            cwnd_bdp_multiplier: CWND_BDP_MULTIPLIER,
+            jitter_headroom: false,
+            delay_budget: None,
+            fast_step_down: false,
+            fit_probes_to_buffer: false,
+            reset_desynced_pulses: false,
+            pacing: true,
+            probe_bw_ramp: false,
+            stale_probes: STALE_PROBES,
+            stale_probe_interval: 1,
+            stable_probe_gain: None,
+            pulse_length: None,
+            pulse_shift: false,
+            down_phase_end: DOWN_PHASE_END_PULSES,
+            cruise_phase_end: PULSE_CYCLE_ROUNDS,
+            bw_window: BW_FILTER_ROUNDS,
+            high_rtt_threshold: None,
+            schedule_probes: false,
+            max_report_age: None,
+            rate_smoothing: None,
+            loss_mode: LossMode::default(),
+            loss_accounting: LossAccounting::default(),
+            loss_rtt_inflation: LOSS_RTT_INFLATION,
+            loss_burst_fraction: Some(LOSS_BURST_FRACTION),
+            lossy_rtt_filter: true,
+            rate_estimator: RateEstimator::default(),
+            update_retries: 0,
+            update_failure: UpdateFailurePolicy::default(),
+            log_granularity: LogGranularity::default(),
+            warn_interval: Duration::from_secs(WARN_INTERVAL_SECONDS),
+            warnings: WarnLimiter::default(),
+            shutdown: Shutdown::default(),
+            paused: PausedFlows::default(),
+            flow_limits: FlowLimits::default(),
+            probe_schedule: ProbeSchedule::default(),
+            snapshots: Snapshots::default(),
+            rate_hints: RateHints::default(),
+            recorder: None,
+            capabilities: DatapathCapabilities::default(),
+            datapath: DatapathKind::default(),
+            register_limit: None,
+        }
+    }
+}
+
+impl<'a, 'b> CongAlgBuilder<'a, 'b> for BbrConfig {
+    fn args() -> clap::App<'a, 'b> {
+        clap::App::new("CCP BBR")
+            .version(env!("CARGO_PKG_VERSION"))
+            .author("Akshay Narayan <akshayn@mit.edu>")
+            .about("Implementation of BBR Congestion Control")
+            .arg(Arg::with_name("match")
+                 .long("match")
+                 .help("Only runs BBR on the flows this filter selects, and leaves every other flow to the datapath, which grows its window by a packet per round trip and halves it on loss. A filter is a comma-separated list of sport=<port>[-<port>], dport=<port>[-<port>], src=<ip>[/<prefix>] or dst=<ip>[/<prefix>], all of which a flow has to match, e.g. dst=10.1.0.0/16,dport=8000-8099. May be repeated; a flow is managed if any filter selects it.")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(Arg::with_name("probe_rtt_interval")
+                 .long("probe_rtt_interval")
+                 .help("Sets the BBR probe RTT interval, e.g. 10s or 500ms (bare numbers are seconds), after which BBR drops its congestion window to potentially observe a new minimum RTT. An interval in round trips, e.g. 800rtt, counts the PROBE_BW pulse cycles since the last new minimum RTT instead, 8 round trips each. 0 disables PROBE_RTT; the minimum RTT then follows the lowest RTT sampled in each 10 second window.")
+                 .default_value("10"))
+            .arg(Arg::with_name("min_rtt_spike_factor")
+                 .long("min_rtt_spike_factor")
+                 .help("Ignores a min RTT sample that is more than this factor above or below the current estimate, unless the next sample confirms it. 0 takes every sample.")
+                 .default_value("4"))
+            .arg(Arg::with_name("weight")
+                 .long("weight")
+                 .help("Weights the bandwidth share of matching flows, as <match>:<weight> where <match> is one of sport=<port>[-<port>], dport=<port>[-<port>], src=<ip>[/<prefix>], dst=<ip>[/<prefix>]. May be repeated; the first matching rule applies, and unmatched flows have weight 1.")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(Arg::with_name("path_cache_ttl")
+                 .long("path_cache_ttl")
+                 .help("Sets how long, e.g. 30s or 5min (bare numbers are seconds), a learned bottleneck rate and min RTT are used to seed new flows to the same destination prefix. 0 disables the path cache.")
+                 .default_value("300"))
+            .arg(Arg::with_name("path_cache_prefix")
+                 .long("path_cache_prefix")
+                 .help("Sets the length of the destination IPv4 prefix that flows share path estimates within.")
+                 .default_value("24"))
+            .arg(Arg::with_name("path_cache_capacity")
+                 .long("path_cache_capacity")
+                 .help("Sets how many destination prefixes the path cache keeps estimates for. Past that, a new prefix replaces one whose estimate aged out or, failing that, one that flows recorded or looked up least recently.")
+                 .default_value("65536"))
+            .arg(Arg::with_name("initial_rate")
+                 .long("initial_rate")
+                 .alias("initial_rate_mbps")
+                 .help("Sets the bottleneck rate, e.g. 50Mbps or 1.2Gbit (bare numbers are Mbit/s), that flows without a cached path estimate start from, instead of 1. With --initial_rtt, flows also start with a window of one BDP.")
+                 .takes_value(true))
+            .arg(Arg::with_name("initial_rtt")
+                 .long("initial_rtt")
+                 .help("Sets the min RTT, e.g. 20ms or 0.5s (bare numbers are milliseconds), that flows without a cached path estimate start from, instead of 1s.")
+                 .takes_value(true))
+            .arg(Arg::with_name("initial_path")
+                 .long("initial_path")
+                 .help("Sets the initial rate and min RTT for the flows a rule selects, as <flow match>:<rate>:<rtt>, e.g. dst=10.1.0.0/16:1Gbps:2ms (bare rates are Mbit/s). The first matching rule applies. May be repeated.")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(Arg::with_name("max_rate")
+                 .long("max_rate")
+                 .help("Caps every flow's bottleneck rate estimate and the rates it probes with, e.g. at 500Mbps or 1.2Gbit (bare numbers are Mbit/s), so that flows never send above a known ceiling.")
+                 .takes_value(true))
+            .arg(Arg::with_name("flow_max_rate")
+                 .long("flow_max_rate")
+                 .help("Caps the flows a rule selects instead, as <flow match>:<rate>, e.g. dst=10.2.0.0/16:200Mbps. The first matching rule applies. May be repeated.")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(Arg::with_name("min_rate")
+                 .long("min_rate")
+                 .help("Sets a floor, e.g. 4Mbps (bare numbers are Mbit/s), that no flow paces below, not after a congestion loss nor in DRAIN or PROBE_RTT, for applications that need a minimum bitrate.")
+                 .takes_value(true))
+            .arg(Arg::with_name("flow_min_rate")
+                 .long("flow_min_rate")
+                 .help("Sets the floor for the flows a rule selects instead, as <flow match>:<rate>, e.g. dport=1935:4Mbps. The first matching rule applies. May be repeated.")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(Arg::with_name("short_flow_bytes")
+                 .long("short_flow_bytes")
+                 .help("Starts every flow as a short flow until it has acked this many bytes, e.g. 100KB (bare numbers are bytes): instead of probing in STARTUP, it sends with a window of at least 32 packets, paced at the path cache's rate for its destination if there is one. For web-style traffic, most of which finishes before STARTUP would.")
+                 .takes_value(true))
+            .arg(Arg::with_name("short_flow")
+                 .long("short_flow")
+                 .help("Starts the flows a rule selects as short flows instead, as <flow match>:<size>, e.g. dport=443:100KB. The first matching rule applies. May be repeated.")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(Arg::with_name("tenant")
+                 .long("tenant")
+                 .help("Tags the flows a rule selects with a tenant, as <flow match>:<tenant>, e.g. src=172.17.0.0/24:blue. The first matching rule applies. May be repeated.")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(Arg::with_name("tenant_lookup")
+                 .long("tenant_lookup")
+                 .help("Sets where the tenant of a flow no --tenant rule matches comes from: (none|netns|cgroup). netns finds the network namespace that holds the flow's socket, and cgroup the cgroup of the process that holds it, by scanning /proc once per new flow.")
+                 .default_value("none"))
+            .arg(Arg::with_name("tenant_config")
+                 .long("tenant_config")
+                 .help("Sets what a tenant's flows take where no per-flow rule matches them, as <tenant>:<setting>=<value>,... with the settings weight, max_rate, min_rate and aggregate_rate, e.g. blue:weight=2,aggregate_rate=500Mbps. The tenant's flows split its aggregate_rate evenly. May be repeated; the last one for a tenant applies.")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1))
+            .arg(Arg::with_name("sync_probe_rtt")
+                 .long("sync_probe_rtt")
+                 .help("Synchronizes PROBE_RTT across flows to the same destination prefix: a flow enters PROBE_RTT early if another flow in its group entered it within the last 200ms, so that the shared queue drains."))
+            .arg(Arg::with_name("scale_probe_rtt")
+                 .long("scale_probe_rtt")
+                 .help("Lengthens PROBE_RTT from 200ms by the number of flows to the same destination prefix, up to 4 times, since the queue they share only drains while all of them hold back."))
+            .arg(Arg::with_name("share_min_rtt")
+                 .long("share_min_rtt")
+                 .help("Shares min RTT measurements between flows to the same destination prefix, so that a new flow starts from its siblings' min RTT and one flow's PROBE_RTT spares the others theirs."))
+            .arg(Arg::with_name("incast_threshold")
+                 .long("incast_threshold")
+                 .help("Once at least this many flows to the same destination prefix start within 100ms, e.g. a fan-in to one rack, each starts with its share of the initial window and pacing rate, and with STARTUP gains of at most 1.5.")
+                 .takes_value(true))
+            .arg(Arg::with_name("align_probe_rtt")
+                 .long("align_probe_rtt")
+                 .help("Starts PROBE_RTT at multiples of the probe RTT interval on the wall clock, so that BBR flows on this host, and on hosts with synchronized clocks, drain the queue together."))
+            .arg(Arg::with_name("probe_rtt_grace")
+                 .long("probe_rtt_grace")
+                 .help("Delays a flow's first PROBE_RTT until at least this long, e.g. 5s or 500ms (bare numbers are seconds), after it leaves STARTUP and DRAIN, so that a flow that took long to ramp up is not drained right away. By default, the whole probe RTT interval; 0 counts from the flow's last new minimum RTT, as later PROBE_RTTs do.")
+                 .takes_value(true))
+            .arg(Arg::with_name("skip_quiet_probe_rtt")
+                 .long("skip_quiet_probe_rtt")
+                 .help("Skips PROBE_RTT for flows that were app-limited or idle at some point of the min RTT window, as the BBR spec does: they did not keep a queue up then, so their own RTT samples refresh the min RTT instead of a cwnd cut. For bursty, request-driven flows."))
+            .arg(Arg::with_name("startup_gain")
+                 .long("startup_gain")
+                 .help("Sets the pacing gain over the bandwidth estimate during STARTUP, 2/ln(2) = 2.885 by default. Lower values, e.g. 2, reduce overshoot when many flows start at once.")
+                 .takes_value(true))
+            .arg(Arg::with_name("startup_cwnd_gain")
+                 .long("startup_cwnd_gain")
+                 .help("Sets the congestion window gain over the estimated BDP during STARTUP, 2/ln(2) = 2.885 by default.")
+                 .takes_value(true))
+            .arg(Arg::with_name("drain_gain")
+                 .long("drain_gain")
+                 .help("Sets the pacing gain over the bandwidth estimate during DRAIN, ln(2)/2 = 0.347 by default, which drains STARTUP's queue in about a round trip.")
+                 .takes_value(true))
+            .arg(Arg::with_name("drain_one_round")
+                 .long("drain_one_round")
+                 .help("Leaves DRAIN after one round trip, instead of as soon as inflight falls to the estimated BDP."))
+            .arg(Arg::with_name("no_cwnd_cap")
+                 .long("no_cwnd_cap")
+                 .help("Only sets the pacing rate and leaves cwnd uncapped outside of PROBE_RTT, for datapaths with accurate pacing where the cwnd cap throttles bursty applications."))
+            .arg(Arg::with_name("track_cwnd")
+                 .long("track_cwnd")
+                 .help("Has PROBE_BW set cwnd to the cwnd cap, or without pacing to the current pulse's window, on every ack, so that cwnd follows min RTT and bandwidth updates at once instead of at the next pulse."))
+            .arg(Arg::with_name("cwnd_bdp_multiplier")
+                 .long("cwnd_bdp_multiplier")
+                 .help("Sets the cwnd cap as a multiple of the estimated BDP. Paths with high ACK jitter may need 3; datacenter paths can use 1.25 to bound queueing.")
+                 .default_value("2"))
+            .arg(Arg::with_name("jitter_headroom")
+                 .long("jitter_headroom")
+                 .help("Adds the p10-p90 spread of recent RTTs to the min RTT when computing the cwnd cap, for jittery last-mile links."))
+            .arg(Arg::with_name("delay_budget")
+                 .long("delay_budget")
+                 .conflicts_with("no_cwnd_cap")
+                 .help("Keeps the RTT within the min RTT plus this budget, e.g. 5ms (bare numbers are milliseconds), by capping cwnd at the estimated bandwidth times that RTT and shrinking PROBE_BW's up and down pulses to fit it. Trades some throughput for bounded latency, for interactive traffic such as games or calls.")
+                 .takes_value(true))
+            .arg(Arg::with_name("reset_desynced_pulses")
+                 .long("reset_desynced_pulses")
+                 .help("Reinstalls PROBE_BW's program when 3 of its reports in a row come from another pulse phase than expected, e.g. because updates keep racing its transitions, so that the pulse cycle starts over from the up pulse."))
+            .arg(Arg::with_name("fit_probes_to_buffer")
+                 .long("fit_probes_to_buffer")
+                 .help("Lowers PROBE_BW's up pulse gain once its probes overflow the bottleneck buffer, as the RTT they raise before losing packets shows, so that a probe queues at most half of the buffer, for shallow-buffered paths where every probe would otherwise cause losses."))
+            .arg(Arg::with_name("fast_step_down")
+                 .long("fast_step_down")
+                 .help("Lowers the bandwidth estimate as soon as 3 PROBE_BW cruise phases in a row deliver less than 0.75x of it while the RTT is 1.25x the min RTT, for links whose capacity drops suddenly, such as wifi rate adaptation or an LTE handover."))
+            .arg(Arg::with_name("no_pacing")
+                 .long("no_pacing")
+                 .conflicts_with("no_cwnd_cap")
+                 .help("For datapaths that do not enforce the pacing rate: probes bandwidth by pulsing cwnd between 0.75 and 1.25 BDP instead."))
+            .arg(Arg::with_name("ramp_probe_bw")
+                 .long("ramp_probe_bw")
+                 .help("Ramps up PROBE_BW's 1.25x pulse in two half-RTT steps, at 1.125x and then 1.25x, to avoid a microburst at the start of each pulse on short-RTT paths."))
+            .arg(Arg::with_name("stale_probes")
+                 .long("stale_probes")
+                 .help("Sets how many consecutive PROBE_BW up pulses without bandwidth growth make probing back off to every stale_probe_interval cycles.")
+                 .default_value("3"))
+            .arg(Arg::with_name("stale_probe_interval")
+                 .long("stale_probe_interval")
+                 .help("Once probes stop finding bandwidth, PROBE_BW only probes every this many pulse cycles and cruises at the estimate in between, reducing self-induced queueing on stable paths. 1 probes every cycle.")
+                 .default_value("1"))
+            .arg(Arg::with_name("stable_probe_gain")
+                 .long("stable_probe_gain")
+                 .help("Shrinks PROBE_BW's 1.25x up pulse to this gain, e.g. 1.1, once the bandwidth estimate has moved less than 5% for 8 pulse cycles, and grows it back when the estimate moves. Smaller probes keep less of a queue on long-lived stable paths.")
+                 .takes_value(true))
+            .arg(Arg::with_name("pulse_length")
+                 .long("pulse_length")
+                 .alias("pulse_length_ms")
+                 .help("Sets fixed PROBE_BW pulse lengths, e.g. 10ms or 2.5ms (bare numbers are milliseconds): up and down pulses last one pulse length and the cruise phase the rest of the cycle, instead of multiples of the min RTT.")
+                 .takes_value(true))
+            .arg(Arg::with_name("pulse_shift")
+                 .long("pulse_shift")
+                 .help("Measures how long PROBE_BW's register updates take to reach the datapath and delays the end of its up and down pulses by that much, so that pulses keep their length on short-RTT paths where the IPC round trip is a sizeable part of a pulse."))
+            .arg(Arg::with_name("down_phase_end")
+                 .long("down_phase_end")
+                 .help("Sets how many pulse lengths, each about a min RTT, into a PROBE_BW cycle its down pulse ends, from 2: the up pulse lasts one pulse and the down pulse the rest. Longer down pulses drain standing queues for longer.")
+                 .default_value("2"))
+            .arg(Arg::with_name("cruise_phase_end")
+                 .long("cruise_phase_end")
+                 .help("Sets how many pulse lengths into a PROBE_BW cycle its cruise phase ends and the next up pulse starts, after --down_phase_end and up to 64. Shorter cycles probe for bandwidth more often. Flows on the --high_rtt_threshold profile keep their cycles of 4.")
+                 .default_value("8"))
+            .arg(Arg::with_name("bw_window")
+                 .long("bw_window")
+                 .help("Sets how many round trips PROBE_BW's windowed-max bandwidth filter remembers, from 1 to 32. Datacenter paths, whose capacity changes faster than their round trips are long, may want a much shorter memory.")
+                 .default_value("10"))
+            .arg(Arg::with_name("high_rtt_threshold")
+                 .long("high_rtt_threshold")
+                 .help("Switches flows whose min RTT is above this, e.g. 300ms (bare numbers are milliseconds), to a profile for satellite paths: PROBE_BW cycles of 4 round trips instead of 8, a bandwidth filter twice as long, and a PROBE_RTT that keeps half the BDP in flight instead of 4 packets.")
+                 .takes_value(true))
+            .arg(Arg::with_name("schedule_probes")
+                 .long("schedule_probes")
+                 .help("Lets a coordinator start a flow's next PROBE_BW up pulse at a given time, with the control socket's probe command, e.g. to probe a shared bottleneck from several hosts at once. The flow cruises until then."))
+            .arg(Arg::with_name("max_report_age")
+                 .long("max_report_age")
+                 .help("Ignores reports that waited longer than this, e.g. 200ms (bare numbers are milliseconds), between reaching the agent and being handled, so that a backed-up agent does not install rates from measurements seconds old.")
+                 .takes_value(true))
+            .arg(Arg::with_name("rate_smoothing")
+                 .long("rate_smoothing")
+                 .help("Limits each change of PROBE_BW's installed pacing rates to this many percent of their current values, e.g. 10, moving them the rest of the way on later reports, for fq or offloaded pacing that handles sudden rate jumps badly.")
+                 .takes_value(true))
+            .arg(Arg::with_name("loss_mode")
+                 .long("loss_mode")
+                 .help("Sets which losses lower the bandwidth estimate in PROBE_BW: (ignore|congestion|lossy). lossy only reacts to losses while the RTT is inflated, for wireless links with random loss.")
+                 .default_value("ignore"))
+            .arg(Arg::with_name("loss_rtt_inflation")
+                 .long("loss_rtt_inflation")
+                 .help("Sets how far above the min RTT, as a factor, a report's RTT has to be for --loss_mode lossy to treat its losses as congestion. Lower values react to smaller queues, higher ones tolerate more random loss.")
+                 .default_value("1.25"))
+            .arg(Arg::with_name("loss_burst_fraction")
+                 .long("loss_burst_fraction")
+                 .help("Sets the fraction of the window a PROBE_BW flow has to lose in one report, e.g. after a route change, for it to halve cwnd at once and stop probing up for a few pulse cycles, whatever --loss_mode says. 0 disables this.")
+                 .default_value("0.3"))
+            .arg(Arg::with_name("no_lossy_rtt_filter")
+                 .long("no_lossy_rtt_filter")
+                 .help("Takes min RTT decreases from reports with a loss burst or after a retransmission timeout. By default they are ignored, since the RTT samples of retransmitted packets can read far below the path's RTT."))
+            .arg(Arg::with_name("loss_accounting")
+                 .long("loss_accounting")
+                 .help("Sets how the kernel programs count losses that may only be reordering: (raw|net|windowed). raw counts every loss sample; net subtracts the packets acked out of order later in the same report; windowed also waits a few more acks before counting a loss, like RACK.")
+                 .default_value("windowed"))
+            .arg(Arg::with_name("rate_estimator")
+                 .long("rate_estimator")
+                 .help("Sets where bandwidth samples come from: (flow|delivered|auto). flow uses the datapath's outgoing and incoming rates; delivered has the programs divide the bytes acked between reports by the time between them; auto reports both and falls back to delivered if the datapath leaves its rates at zero.")
+                 .default_value("auto"))
+            .arg(Arg::with_name("update_retries")
+                 .long("update_retries")
+                 .help("Sets how many times in a row a flow rewrites its registers in place after the datapath fails to apply an update, before --on_update_failure takes over.")
+                 .default_value("0"))
+            .arg(Arg::with_name("on_update_failure")
+                 .long("on_update_failure")
+                 .help("Sets what a flow does once its updates keep failing: (reinstall|freeze|degrade). reinstall installs its program again with every register it has set; freeze leaves it at its current estimate's cwnd and pacing rate, never to be updated again; degrade switches it to the cwnd-only AIMD program, which needs no updates.")
+                 .default_value("reinstall"))
+            .arg(Arg::with_name("log_granularity")
+                 .long("log_granularity")
+                 .help("Sets how often flows log at the info level: (report|cycle|transition). report logs every report; cycle logs a summary of every PROBE_BW pulse cycle instead, with its average rate, min RTT and losses; transition logs only mode changes. Mode changes, estimates that jump and warnings are always logged.")
+                 .default_value("report"))
+            .arg(Arg::with_name("warn_interval")
+                 .long("warn_interval")
+                 .help("Logs a warning that keeps recurring, such as a failing update, at most once per this interval, e.g. 10s or 1m (bare numbers are seconds), with how many times it recurred since. Occurrences on all flows count towards the same warning. 0 logs every occurrence.")
+                 .default_value("10"))
+            .arg(Arg::with_name("ipc")
+                 .long("ipc")
+                 .help("Sets the type of ipc to use: (netlink|unix|char). Use netlink or char with the ccp-kernel datapath, matching the transport the module was loaded with; char has lower per-message overhead. Use unix with user-space datapaths. A comma-separated list, such as netlink,unix, serves the datapaths on each of them at once.")
+                 .default_value("unix")
+                 .validator(|ipc| crate::transport::parse_transports(&ipc).map(drop)))
+            .arg(Arg::with_name("datapath")
+                 .long("datapath")
+                 .help("Sets the kind of datapath: (kernel|quic|auto). quic selects programs that count inflight data in bytes and do not need loss samples, for user-space stacks. auto picks quic for the unix ipc and kernel otherwise.")
+                 .default_value("auto"))
+            .arg(Arg::with_name("register_limit")
+                 .long("register_limit")
+                 .help("Sets the most registers, report fields included, a program may define on the datapath. Optional features are turned off at startup until every program fits: pulse_shift, ramp_probe_bw, high_rtt_threshold, the auto rate estimator, then rounds of bw_window. Programs that still do not fit are an error.")
+                 .takes_value(true))
+    }
+
+    fn with_arg_matches(args: &clap::ArgMatches) -> portus::Result<Self> {
+        Ok(Self::from_arg_matches(args)?)
+    }
+}
+
+impl BbrConfig {
+    /// Builds the configuration from the flags of [`BbrConfig::args`].
+    pub fn from_arg_matches(args: &clap::ArgMatches) -> Result<Self, BbrError> {
+        let (probe_rtt_interval, probe_rtt_rounds) = match args
+            .value_of("probe_rtt_interval")
+            .unwrap()
+            .strip_suffix("rtt")
+        {
+            Some(rounds) => {
+                let rounds = rounds
+                    .trim()
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&rounds| rounds > 0)
+                    .ok_or_else(|| {
+                        BbrError::Config(format!(
+                            "probe_rtt_interval must be a positive number of round trips: {:?}",
+                            rounds
+                        ))
+                    })?;
+                if args.is_present("align_probe_rtt") {
+                    return Err(BbrError::Config(String::from(
+                        "align_probe_rtt needs a probe_rtt_interval in seconds",
+                    )));
+                }
+                (
+                    Duration::from_secs(PROBE_RTT_INTERVAL_SECONDS as u64),
+                    Some(rounds),
+                )
+            }
+            None => (
+                parse_duration(
+                    args.value_of("probe_rtt_interval").unwrap(),
+                    Duration::from_secs(1),
+                )
+                .map_err(BbrError::Config)?,
+                None,
+            ),
+        };
+
+        let probe_rtt_grace = args
+            .value_of("probe_rtt_grace")
+            .map(|grace| parse_duration(grace, Duration::from_secs(1)))
+            .transpose()
+            .map_err(BbrError::Config)?;
+
+        let weight_rules = args
+            .values_of("weight")
+            .map(|rules| rules.map(str::parse).collect::<Result<Vec<_>, _>>())
+            .transpose()
+            .map_err(BbrError::Config)?
+            .unwrap_or_default();
+
+        let initial_rate = args
+            .value_of("initial_rate")
+            .map(crate::initial::parse_initial_rate)
+            .transpose()
+            .map_err(BbrError::Config)?;
+        let initial_rtt = args
+            .value_of("initial_rtt")
+            .map(crate::initial::parse_rtt)
+            .transpose()
+            .map_err(BbrError::Config)?;
+        let initial_path_rules = args
+            .values_of("initial_path")
+            .map(|rules| rules.map(str::parse).collect::<Result<Vec<_>, _>>())
+            .transpose()
+            .map_err(BbrError::Config)?
+            .unwrap_or_default();
+        let max_rate = args
+            .value_of("max_rate")
+            .map(crate::max_rate::parse_max_rate)
+            .transpose()
+            .map_err(BbrError::Config)?;
+        let max_rate_rules = args
+            .values_of("flow_max_rate")
+            .map(|rules| rules.map(str::parse).collect::<Result<Vec<_>, _>>())
+            .transpose()
+            .map_err(BbrError::Config)?
+            .unwrap_or_default();
+        let min_rate = args
+            .value_of("min_rate")
+            .map(crate::min_rate::parse_min_rate)
+            .transpose()
+            .map_err(BbrError::Config)?;
+        if let (Some(min_rate), Some(max_rate)) = (min_rate, max_rate) {
+            if min_rate > max_rate {
+                return Err(BbrError::Config(format!(
+                    "--min_rate {} is above --max_rate {}",
+                    min_rate, max_rate
+                )));
+            }
+        }
+        let min_rate_rules = args
+            .values_of("flow_min_rate")
+            .map(|rules| rules.map(str::parse).collect::<Result<Vec<_>, _>>())
+            .transpose()
+            .map_err(BbrError::Config)?
+            .unwrap_or_default();
+        let short_flow_bytes = args
+            .value_of("short_flow_bytes")
+            .map(crate::short_flow::parse_bytes)
+            .transpose()
+            .map_err(BbrError::Config)?;
+        let short_flow_rules = args
+            .values_of("short_flow")
+            .map(|rules| rules.map(str::parse).collect::<Result<Vec<_>, _>>())
+            .transpose()
+            .map_err(BbrError::Config)?
+            .unwrap_or_default();
+        let tenant_rules = args
+            .values_of("tenant")
+            .map(|rules| rules.map(str::parse).collect::<Result<Vec<_>, _>>())
+            .transpose()
+            .map_err(BbrError::Config)?
+            .unwrap_or_default();
+        let tenant_lookup = args
+            .value_of("tenant_lookup")
+            .unwrap()
+            .parse()
+            .map_err(BbrError::Config)?;
+        let tenant_configs = args
+            .values_of("tenant_config")
+            .map(|configs| configs.map(str::parse).collect::<Result<Vec<_>, _>>())
+            .transpose()
+            .map_err(BbrError::Config)?
+            .unwrap_or_default();
+        let flow_filters = args
+            .values_of("match")
+            .map(|filters| filters.map(str::parse).collect::<Result<Vec<_>, _>>())
+            .transpose()
+            .map_err(BbrError::Config)?
+            .unwrap_or_default();
+
+        let path_cache_ttl = parse_duration(
+            args.value_of("path_cache_ttl").unwrap(),
+            Duration::from_secs(1),
+        )
+        .map_err(BbrError::Config)?;
+        let path_cache_prefix = parse_bounded(
+            args,
+            "path_cache_prefix",
+            ..=32,
+            crate::params::PATH_CACHE_PREFIX_LEN,
+        )?;
+        let path_cache_capacity = args
+            .value_of("path_cache_capacity")
+            .unwrap()
+            .parse::<usize>()
+            .map_err(|e| BbrError::Config(format!("{:?}", e)))?;
+
+        let incast_threshold = parse_bounded_opt(args, "incast_threshold", 2u32..)?;
+        // the gains default to the exact constants, which a default_value string would round
+        let startup_gain = parse_bounded(args, "startup_gain", above(1.0), STARTUP_GAIN)?;
+        let startup_cwnd_gain =
+            parse_bounded(args, "startup_cwnd_gain", above(1.0), STARTUP_CWND_GAIN)?;
+        let drain_gain = parse_bounded(
+            args,
+            "drain_gain",
+            (Bound::Excluded(0.0), Bound::Excluded(1.0)),
+            DRAIN_GAIN,
+        )?;
+
+        let min_rtt_spike_factor = parse_bounded_or_zero(args, "min_rtt_spike_factor", above(1.0))?;
+
+        let cwnd_bdp_multiplier =
+            parse_bounded(args, "cwnd_bdp_multiplier", 1.0.., CWND_BDP_MULTIPLIER)?;
+
+        let stale_probes = parse_bounded(args, "stale_probes", 1u32.., STALE_PROBES)?;
+        let stale_probe_interval = parse_bounded(args, "stale_probe_interval", 1.., 1)?;
+
+        let stable_probe_gain = parse_bounded_opt(
+            args,
+            "stable_probe_gain",
+            (Bound::Excluded(1.0), Bound::Included(PROBE_GAIN)),
+        )?;
+
+        let delay_budget = parse_positive_duration(args, "delay_budget")?;
+
+        let max_report_age = parse_positive_duration(args, "max_report_age")?;
+
+        let pulse_length = parse_positive_duration(args, "pulse_length")?;
+
+        let phase_end = |name: &str| {
+            args.value_of(name)
+                .unwrap()
+                .parse::<u32>()
+                .map_err(|e| BbrError::Config(format!("{}: {:?}", name, e)))
+        };
+        let down_phase_end = phase_end("down_phase_end")?;
+        let cruise_phase_end = phase_end("cruise_phase_end")?;
+        if down_phase_end < 2 {
+            return Err(BbrError::Config(format!(
+                "down_phase_end must be at least 2 pulses, after the up pulse: {}",
+                down_phase_end
+            )));
+        }
+        if cruise_phase_end <= down_phase_end || cruise_phase_end > MAX_CYCLE_PULSES {
+            return Err(BbrError::Config(format!(
+                "cruise_phase_end must be after down_phase_end and at most {} pulses: {}",
+                MAX_CYCLE_PULSES, cruise_phase_end
+            )));
+        }
+
+        let bw_window = parse_bounded(
+            args,
+            "bw_window",
+            1..=MAX_BW_WINDOW_ROUNDS,
+            BW_FILTER_ROUNDS,
+        )?;
+
+        let high_rtt_threshold = parse_positive_duration(args, "high_rtt_threshold")?;
+        if high_rtt_threshold.is_some() && down_phase_end >= HIGH_RTT_CYCLE_ROUNDS {
+            return Err(BbrError::Config(format!(
+                "down_phase_end must be before the end of high_rtt_threshold's {}-pulse cycles: {}",
+                HIGH_RTT_CYCLE_ROUNDS, down_phase_end
+            )));
+        }
+
+        let rate_smoothing = parse_bounded_opt(
+            args,
+            "rate_smoothing",
+            (Bound::Excluded(0.0), Bound::Excluded(100.0)),
+        )?
+        .map(|percent| percent / 100.0);
+
+        let loss_mode = args
+            .value_of("loss_mode")
+            .unwrap()
+            .parse()
+            .map_err(BbrError::Config)?;
+
+        let loss_accounting = args
+            .value_of("loss_accounting")
+            .unwrap()
+            .parse()
+            .map_err(BbrError::Config)?;
+
+        let update_retries = args
+            .value_of("update_retries")
+            .unwrap()
+            .parse::<u32>()
+            .map_err(|e| BbrError::Config(format!("{:?}", e)))?;
+        let update_failure = args
+            .value_of("on_update_failure")
+            .unwrap()
+            .parse()
+            .map_err(BbrError::Config)?;
+        let log_granularity = args
+            .value_of("log_granularity")
+            .unwrap()
+            .parse()
+            .map_err(BbrError::Config)?;
+        let warn_interval = parse_duration(
+            args.value_of("warn_interval").unwrap(),
+            Duration::from_secs(1),
+        )
+        .map_err(BbrError::Config)?;
+
+        let loss_rtt_inflation =
+            parse_bounded(args, "loss_rtt_inflation", 1.0.., LOSS_RTT_INFLATION)?;
+
+        let loss_burst_fraction = parse_bounded_or_zero(
+            args,
+            "loss_burst_fraction",
+            (Bound::Excluded(0.0), Bound::Included(1.0)),
+        )?;
+
+        let rate_estimator = args
+            .value_of("rate_estimator")
+            .unwrap()
+            .parse()
+            .map_err(BbrError::Config)?;
+
+        let datapath = match args.value_of("datapath").unwrap() {
+            "auto" => DatapathKind::for_ipc(args.value_of("ipc").unwrap()),
+            kind => kind.parse().map_err(BbrError::Config)?,
+        };
+
+        let register_limit = parse_bounded_opt(args, "register_limit", 1usize..)?;
+
+        let mut cfg = BbrConfig {
+            probe_rtt_interval,
+            probe_rtt_rounds,
+            min_rtt_spike_factor,
+            probe_rtt_grace,
+            skip_quiet_probe_rtt: args.is_present("skip_quiet_probe_rtt"),
+            probe_rtt_alignment: if args.is_present("align_probe_rtt") {
+                Some(WallClock::now())
+            } else {
+                None
+            },
+            weight_rules,
+            path_cache: PathCache::new(path_cache_ttl, path_cache_prefix)
+                .with_capacity(path_cache_capacity),
+            initial_rate,
+            initial_rtt,
+            initial_path_rules,
+            max_rate,
+            max_rate_rules,
+            min_rate,
+            min_rate_rules,
+            short_flow_bytes,
+            short_flow_rules,
+            tenant_rules,
+            tenant_lookup,
+            tenant_configs,
+            flow_filters,
+            probe_rtt_sync_window: if args.is_present("sync_probe_rtt") {
+                Some(Duration::from_millis(PROBE_RTT_SYNC_WINDOW_MS))
+            } else {
+                None
+            },
+            scale_probe_rtt: args.is_present("scale_probe_rtt"),
+            share_min_rtt: args.is_present("share_min_rtt"),
+            incast_threshold,
+            startup_gain,
+            startup_cwnd_gain,
+            drain_gain,
+            drain_to_target: !args.is_present("drain_one_round"),
+            cwnd_cap: !args.is_present("no_cwnd_cap"),
+            track_cwnd: args.is_present("track_cwnd"),
+            cwnd_bdp_multiplier,
+            jitter_headroom: args.is_present("jitter_headroom"),
+            delay_budget,
+            fast_step_down: args.is_present("fast_step_down"),
+            fit_probes_to_buffer: args.is_present("fit_probes_to_buffer"),
+            reset_desynced_pulses: args.is_present("reset_desynced_pulses"),
+            pacing: !args.is_present("no_pacing"),
+            probe_bw_ramp: args.is_present("ramp_probe_bw"),
+            stale_probes,
+            stale_probe_interval,
+            stable_probe_gain,
+            pulse_length,
+            pulse_shift: args.is_present("pulse_shift"),
+            down_phase_end,
+            cruise_phase_end,
+            bw_window,
+            high_rtt_threshold,
+            schedule_probes: args.is_present("schedule_probes"),
+            max_report_age,
+            rate_smoothing,
+            loss_mode,
+            loss_rtt_inflation,
+            loss_burst_fraction,
+            lossy_rtt_filter: !args.is_present("no_lossy_rtt_filter"),
+            loss_accounting,
+            rate_estimator,
+            update_retries,
+            update_failure,
+            log_granularity,
+            warn_interval,
+            datapath,
+            register_limit,
+            ..Default::default()
+        };
+        // with --datapath auto, the transports may run either kind's programs
+        let datapaths = match args.value_of("datapath") {
+            Some("auto") => vec![DatapathKind::Kernel, DatapathKind::Quic],
+            _ => vec![datapath],
+        };
+        for traded in cfg.fit_register_limit(&datapaths)? {
+            warn!(
+                register_limit,
+                %traded, "turning a feature off to fit the register limit"
+            );
+        }
+        Ok(cfg)
+    }
+}
+
+/// A number a flag takes, which also has to be finite.
+trait FlagNumber: FromStr + PartialOrd + Display + Copy + Default {
+    fn is_finite(self) -> bool {
+        true
+    }
+}
+
+impl FlagNumber for u8 {}
+impl FlagNumber for u32 {}
+impl FlagNumber for usize {}
+impl FlagNumber for f64 {
+    fn is_finite(self) -> bool {
+        f64::is_finite(self)
+    }
+}
+
+/// The range `(above, ∞)`, for flags that have to be greater than `above`.
+fn above(above: f64) -> (Bound<f64>, Bound<f64>) {
+    (Bound::Excluded(above), Bound::Unbounded)
+}
+
+/// Parses flag `name` as a finite number in `range`, or returns `default` if it is not given.
+fn parse_bounded<T>(
+    args: &clap::ArgMatches,
+    name: &str,
+    range: impl RangeBounds<T>,
+    default: T,
+) -> Result<T, BbrError>
+where
+    T: FlagNumber,
+    T::Err: Debug,
+{
+    Ok(parse_bounded_opt(args, name, range)?.unwrap_or(default))
+}
+
+/// Like [`parse_bounded`], for flags with no default.
+fn parse_bounded_opt<T>(
+    args: &clap::ArgMatches,
+    name: &str,
+    range: impl RangeBounds<T>,
+) -> Result<Option<T>, BbrError>
+where
+    T: FlagNumber,
+    T::Err: Debug,
+{
+    args.value_of(name)
+        .map(|value| bounded(name, value, &range, false).map(Option::unwrap))
+        .transpose()
+}
+
+/// Like [`parse_bounded_opt`], for flags that 0 turns off.
+fn parse_bounded_or_zero<T>(
+    args: &clap::ArgMatches,
+    name: &str,
+    range: impl RangeBounds<T>,
+) -> Result<Option<T>, BbrError>
+where
+    T: FlagNumber,
+    T::Err: Debug,
+{
+    Ok(args
+        .value_of(name)
+        .map(|value| bounded(name, value, &range, true))
+        .transpose()?
+        .flatten())
+}
+
+fn bounded<T>(
+    name: &str,
+    value: &str,
+    range: &impl RangeBounds<T>,
+    zero_is_off: bool,
+) -> Result<Option<T>, BbrError>
+where
+    T: FlagNumber,
+    T::Err: Debug,
+{
+    let value = value
+        .parse::<T>()
+        .map_err(|e| BbrError::Config(format!("{}: {:?}", name, e)))?;
+    if zero_is_off && value == T::default() {
+        Ok(None)
+    } else if value.is_finite() && range.contains(&value) {
+        Ok(Some(value))
+    } else {
+        let within = match (range.start_bound(), range.end_bound()) {
+            (Bound::Included(lo), Bound::Unbounded) => format!("at least {}", lo),
+            (Bound::Excluded(lo), Bound::Unbounded) => format!("greater than {}", lo),
+            (Bound::Unbounded, Bound::Included(hi)) => format!("at most {}", hi),
+            (Bound::Unbounded, Bound::Excluded(hi)) => format!("below {}", hi),
+            (Bound::Included(lo), Bound::Included(hi)) => format!("from {} to {}", lo, hi),
+            (Bound::Excluded(lo), Bound::Included(hi)) => {
+                format!("above {} and at most {}", lo, hi)
+            }
+            (Bound::Included(lo), Bound::Excluded(hi)) => {
+                format!("at least {} and below {}", lo, hi)
+            }
+            (Bound::Excluded(lo), Bound::Excluded(hi)) => format!("between {} and {}", lo, hi),
+            (Bound::Unbounded, Bound::Unbounded) => String::from("a finite number"),
+        };
+        Err(BbrError::Config(format!(
+            "{} must be {}{}: {}",
+            name,
+            if zero_is_off { "0 or " } else { "" },
+            within,
+            value
+        )))
+    }
+}
+
+/// Parses flag `name` as a positive duration, in milliseconds if it has no unit.
+fn parse_positive_duration(
+    args: &clap::ArgMatches,
+    name: &str,
+) -> Result<Option<Duration>, BbrError> {
+    args.value_of(name)
+        .map(|value| {
+            match parse_duration(value, Duration::from_millis(1)).map_err(BbrError::Config)? {
+                duration if duration.is_zero() => {
+                    Err(BbrError::Config(format!("{} must be positive", name)))
+                }
+                duration => Ok(duration),
+            }
+        })
+        .transpose()
+}
+
+/// Maps [`Instant`]s to the wall clock.
+#[derive(Clone, Copy, Debug)]
+pub struct WallClock {
+    pub instant: Instant,
+    /// The time since the Unix epoch at `instant`.
+    pub since_epoch: Duration,
+}
+
+impl WallClock {
+    pub fn now() -> Self {
+        WallClock {
+            instant: Instant::now(),
+            since_epoch: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The time since the Unix epoch at `t`.
+    pub fn at(&self, t: Instant) -> Duration {
+        match t.checked_duration_since(self.instant) {
+            Some(after) => self.since_epoch + after,
+            None => self
+                .since_epoch
+                .saturating_sub(self.instant.duration_since(t)),
+        }
+    }
+}
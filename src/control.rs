@@ -0,0 +1,283 @@
+//! Commands to a running agent over a Unix socket.
+//!
+//! `--control_socket <path>` has the agent listen on a Unix stream socket. A client writes one
+//! command per line and reads one reply line per command: `ok`, or `error: ` and the reason.
+//!
+//! - `pause <sock_id>` stops the flow's probing, so that it yields to other traffic.
+//! - `resume <sock_id>` restores it.
+//! - `limit <sock_id> <rate>` caps the flow's rate, e.g. `limit 7 20Mbps`; bare numbers are
+//!   Mbit/s.
+//! - `unlimit <sock_id>` clears the cap.
+//! - `weight <sock_id> <weight>` gives the flow another weight, a positive number, in place of
+//!   the one its `--weight` rule or tenant gave it; see [`crate::weight`].
+//! - `probe <sock_id> <time>` starts the flow's next PROBE_BW up pulse at a Unix time in
+//!   seconds, e.g. `probe 7 1760000000.250`, with `--schedule_probes`; see
+//!   [`crate::probe_schedule`]. A coordinator sends it to the agents of every flow that is to
+//!   probe at that time, whose clocks it expects synchronized.
+//! - `config` replies with the configuration each transport runs with, as one line of JSON
+//!   instead of `ok`; see [`crate::effective`].
+//! - `marker <name>` marks the current time with a name, which may have spaces, e.g. `marker
+//!   start load phase 2`, in the agent's log, every transport's recorded trace and the stats
+//!   sink, so that experiments can line up what they did with the agent's telemetry.
+//!
+//! Socket ids are only unique within one datapath, so a command applies to the flows with that
+//! id on every transport.
+
+use crate::bandwidth::Rate;
+use crate::max_rate::parse_max_rate;
+use crate::params::PROBE_SCHEDULE_HORIZON_SECONDS;
+use crate::stats::StatsSink;
+use crate::transport::Transport;
+use crate::weight::parse_weight;
+use crate::WallClock;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{info, warn};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Pause(u32),
+    Resume(u32),
+    Limit(u32, Rate),
+    Unlimit(u32),
+    Weight(u32, f64),
+    /// The time since the Unix epoch to start the flow's next up pulse at.
+    Probe(u32, Duration),
+    Config,
+    Marker(String),
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let name = words.next();
+        if name == Some("marker") {
+            return match s.trim().strip_prefix("marker").map(str::trim) {
+                Some(marker) if !marker.is_empty() => Ok(Command::Marker(String::from(marker))),
+                _ => Err(format!("expected a name: {:?}", s)),
+            };
+        }
+        let args: Vec<_> = words.collect();
+        let (arity, usage) = match name {
+            Some("config") => (0, "no arguments"),
+            Some("pause" | "resume" | "unlimit") => (1, "a socket id"),
+            Some("limit") => (2, "a socket id and a rate"),
+            Some("weight") => (2, "a socket id and a weight"),
+            Some("probe") => (2, "a socket id and a Unix time"),
+            Some(name) => return Err(format!("unknown command: {:?}", name)),
+            None => return Err(String::from("empty command")),
+        };
+        if args.len() > arity {
+            return Err(format!("too many arguments: {:?}", s));
+        }
+        if args.len() < arity {
+            return Err(format!("expected {}: {:?}", usage, s));
+        }
+        if name == Some("config") {
+            return Ok(Command::Config);
+        }
+
+        let sock_id = args[0]
+            .parse::<u32>()
+            .map_err(|e| format!("invalid socket id {:?}: {}", args[0], e))?;
+        match name {
+            Some("pause") => Ok(Command::Pause(sock_id)),
+            Some("resume") => Ok(Command::Resume(sock_id)),
+            Some("limit") => Ok(Command::Limit(sock_id, parse_max_rate(args[1])?)),
+            Some("weight") => Ok(Command::Weight(sock_id, parse_weight(args[1])?)),
+            Some("probe") => args[1]
+                .parse::<f64>()
+                .ok()
+                .and_then(|s| Duration::try_from_secs_f64(s).ok())
+                .map(|at| Command::Probe(sock_id, at))
+                .ok_or_else(|| format!("invalid Unix time: {:?}", args[1])),
+            _ => Ok(Command::Unlimit(sock_id)),
+        }
+    }
+}
+
+/// Runs commands against the flows of the agent's transports.
+#[derive(Clone)]
+pub struct Control {
+    transports: Vec<Transport>,
+    stats_sink: Option<StatsSink>,
+}
+
+impl Control {
+    pub fn new(transports: Vec<Transport>) -> Self {
+        Control {
+            transports,
+            stats_sink: None,
+        }
+    }
+
+    /// Also sends the markers of `marker` commands to `sink`.
+    pub fn with_stats_sink(mut self, sink: StatsSink) -> Self {
+        self.stats_sink = Some(sink);
+        self
+    }
+
+    // the transports that currently have a flow with this socket id
+    fn with_flow(&self, sock_id: u32) -> Result<Vec<&Transport>, String> {
+        let transports: Vec<_> = self
+            .transports
+            .iter()
+            .filter(|t| {
+                t.cfg
+                    .snapshots
+                    .flows()
+                    .iter()
+                    .any(|flow| flow.id.sock_id == sock_id)
+            })
+            .collect();
+        if transports.is_empty() {
+            return Err(format!("no flow with socket id {}", sock_id));
+        }
+
+        Ok(transports)
+    }
+
+    /// The configuration of every transport, as the `config` command replies with it.
+    pub fn config(&self) -> serde_json::Value {
+        serde_json::json!({
+            "transports": self
+                .transports
+                .iter()
+                .map(Transport::effective_config)
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    pub fn execute(&self, command: Command) -> Result<(), String> {
+        match command {
+            Command::Pause(sock_id) => {
+                for transport in self.with_flow(sock_id)? {
+                    transport.cfg.paused.pause(sock_id);
+                }
+            }
+            Command::Resume(sock_id) => {
+                for transport in self.with_flow(sock_id)? {
+                    transport.cfg.paused.resume(sock_id);
+                }
+            }
+            Command::Limit(sock_id, rate) => {
+                for transport in self.with_flow(sock_id)? {
+                    transport.cfg.flow_limits.set(sock_id, rate);
+                }
+            }
+            Command::Unlimit(sock_id) => {
+                for transport in self.with_flow(sock_id)? {
+                    transport.cfg.flow_limits.clear(sock_id);
+                }
+            }
+            Command::Weight(sock_id, weight) => {
+                for transport in self.with_flow(sock_id)? {
+                    transport.cfg.weights.reweigh(sock_id, weight);
+                }
+            }
+            Command::Probe(sock_id, since_epoch) => {
+                let transports = self.with_flow(sock_id)?;
+                if transports.iter().any(|t| !t.cfg.schedule_probes) {
+                    return Err(String::from("scheduling probes needs --schedule_probes"));
+                }
+                let clock = WallClock::now();
+                let ahead = since_epoch
+                    .checked_sub(clock.since_epoch)
+                    .filter(|ahead| !ahead.is_zero())
+                    .ok_or_else(|| format!("{:.3} has passed", since_epoch.as_secs_f64()))?;
+                if ahead > Duration::from_secs(PROBE_SCHEDULE_HORIZON_SECONDS) {
+                    return Err(format!(
+                        "{:.3} is more than {}s ahead",
+                        since_epoch.as_secs_f64(),
+                        PROBE_SCHEDULE_HORIZON_SECONDS
+                    ));
+                }
+                for transport in transports {
+                    transport
+                        .cfg
+                        .probe_schedule
+                        .schedule(sock_id, clock.instant + ahead);
+                }
+            }
+            // changes nothing, and is not logged
+            Command::Config => return Ok(()),
+            Command::Marker(name) => {
+                let at = WallClock::now();
+                info!(marker = %name, since_epoch = ?at.since_epoch, "marker");
+                for transport in &self.transports {
+                    if let Some(recorder) = &transport.cfg.recorder {
+                        recorder.marker(&name, at);
+                    }
+                }
+                if let Some(sink) = &self.stats_sink {
+                    sink.push_marker(&name, at.since_epoch)
+                        .map_err(|e| format!("could not send the marker to {:?}: {}", sink, e))?;
+                }
+                return Ok(());
+            }
+        }
+
+        info!(?command, "control command");
+        Ok(())
+    }
+
+    /// The reply line to a command line, without its newline.
+    pub fn reply(&self, line: &str) -> String {
+        let reply = line.parse().and_then(|command| match command {
+            Command::Config => Ok(self.config().to_string()),
+            command => self.execute(command).map(|()| String::from("ok")),
+        });
+        match reply {
+            Ok(reply) => reply,
+            Err(err) => format!("error: {}", err),
+        }
+    }
+
+    fn serve_client(&self, stream: UnixStream) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            writeln!(writer, "{}", self.reply(&line))?;
+        }
+        Ok(())
+    }
+
+    /// Listens on `path`, replacing a socket a previous agent left behind, and serves each
+    /// client from its own thread.
+    pub fn listen(self, path: &Path) -> io::Result<()> {
+        match std::fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        let listener = UnixListener::bind(path)?;
+        info!(?path, "listening for control commands");
+        std::thread::Builder::new()
+            .name(String::from("bbr-control"))
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            warn!(?err, "could not accept control connection");
+                            continue;
+                        }
+                    };
+                    let control = self.clone();
+                    std::thread::spawn(move || {
+                        if let Err(err) = control.serve_client(stream) {
+                            warn!(?err, "control connection failed");
+                        }
+                    });
+                }
+            })?;
+        Ok(())
+    }
+}
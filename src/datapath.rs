@@ -0,0 +1,44 @@
+//! The datapaths BBR's programs can run on.
+//!
+//! The ccp-kernel module provides every primitive the programs use. User-space stacks such as
+//! ccp-enabled QUIC implementations may not report `Ack.lost_pkts_sample`,
+//! `Ack.packets_misordered` or `Flow.was_timeout`, so they get program variants that leave
+//! `Report.loss`, `Report.acked`, `Report.misordered` and `Report.timeout` at zero. All
+//! variants count cwnd and inflight data in bytes: packet counts depend on the MSS and on how
+//! the datapath coalesces segments.
+
+use serde::Serialize;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatapathKind {
+    /// The ccp-kernel module, with loss, reordering and timeout samples.
+    #[default]
+    Kernel,
+    /// A user-space datapath, possibly without loss samples.
+    Quic,
+}
+
+impl DatapathKind {
+    /// The datapath that usually sits on the other end of the given IPC transport: the kernel
+    /// module speaks netlink or the char device, user-space datapaths a Unix socket.
+    pub fn for_ipc(ipc: &str) -> Self {
+        match ipc {
+            "unix" => DatapathKind::Quic,
+            _ => DatapathKind::Kernel,
+        }
+    }
+}
+
+impl FromStr for DatapathKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kernel" => Ok(DatapathKind::Kernel),
+            "quic" => Ok(DatapathKind::Quic),
+            _ => Err(format!("datapath must be one of (kernel|quic): {:?}", s)),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Durations given on the command line.
+//!
+//! A duration takes a unit suffix, as in `500ms`, `2.5s` or `1min`. A bare number keeps the
+//! unit its flag has always used, so existing command lines keep working.
+
+use std::time::Duration;
+
+const UNITS: &[(&str, f64)] = &[
+    ("ns", 1e-9),
+    ("us", 1e-6),
+    ("ms", 1e-3),
+    ("s", 1.0),
+    ("sec", 1.0),
+    ("m", 60.0),
+    ("min", 60.0),
+    ("h", 3600.0),
+];
+
+/// Parses a duration with an optional unit suffix; bare numbers count in `bare_unit`.
+pub fn parse_duration(s: &str, bare_unit: Duration) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("duration must be a number with an optional unit: {:?}", s))?;
+    let unit_secs = match unit.trim() {
+        "" => bare_unit.as_secs_f64(),
+        unit => UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .map(|(_, secs)| *secs)
+            .ok_or_else(|| format!("duration unit must be one of (ns|us|ms|s|min|h): {:?}", s))?,
+    };
+
+    Duration::try_from_secs_f64(value * unit_secs).map_err(|e| format!("{}: {:?}", e, s))
+}
+
+/// Formats a duration in the largest of s, ms, us and ns that shows it as a whole number, which
+/// `parse_duration` reads back.
+pub fn format_duration(d: Duration) -> String {
+    let nanos = d.as_nanos();
+    for (unit, per_unit) in [("s", 1_000_000_000), ("ms", 1_000_000), ("us", 1_000)] {
+        if nanos.is_multiple_of(per_unit) {
+            return format!("{}{}", nanos / per_unit, unit);
+        }
+    }
+    format!("{}ns", nanos)
+}
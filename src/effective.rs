@@ -0,0 +1,112 @@
+//! The configuration an agent runs with, as JSON.
+//!
+//! Settings come from the defaults, the flags and, for single flows, from rules and the control
+//! socket. [`BbrConfig::effective`] resolves a configuration into one object under
+//! `BbrConfig`'s field names, so that operators can check what an agent actually runs with.
+//! Durations, rates, sizes and rules read as their flags take them, e.g. `10s`, `20Mbps` or
+//! `dport=5201:2`; other values as the fields hold them, and unset options are `null`.
+
+use crate::duration::format_duration;
+use crate::BbrConfig;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+fn duration(d: Option<Duration>) -> Value {
+    json!(d.map(format_duration))
+}
+
+fn rules<R: ToString>(rules: &[R]) -> Value {
+    json!(rules.iter().map(R::to_string).collect::<Vec<_>>())
+}
+
+impl BbrConfig {
+    /// Every setting of the configuration; see the module documentation.
+    pub fn effective(&self) -> Value {
+        let probe_rtt_interval = match self.probe_rtt_rounds {
+            Some(rounds) => format!("{}rtt", rounds),
+            None => format_duration(self.probe_rtt_interval),
+        };
+        let settings = vec![
+            ("flow_filters", rules(&self.flow_filters)),
+            ("probe_rtt_interval", json!(probe_rtt_interval)),
+            ("min_rtt_spike_factor", json!(self.min_rtt_spike_factor)),
+            ("align_probe_rtt", json!(self.probe_rtt_alignment.is_some())),
+            ("probe_rtt_grace", duration(self.probe_rtt_grace)),
+            ("skip_quiet_probe_rtt", json!(self.skip_quiet_probe_rtt)),
+            ("weight_rules", rules(&self.weight_rules)),
+            (
+                "path_cache_ttl",
+                json!(format_duration(self.path_cache.ttl())),
+            ),
+            ("path_cache_prefix", json!(self.path_cache.prefix_len())),
+            ("path_cache_capacity", json!(self.path_cache.capacity())),
+            ("initial_rate", json!(self.initial_rate)),
+            ("initial_rtt", duration(self.initial_rtt)),
+            ("initial_path_rules", rules(&self.initial_path_rules)),
+            ("max_rate", json!(self.max_rate)),
+            ("max_rate_rules", rules(&self.max_rate_rules)),
+            ("min_rate", json!(self.min_rate)),
+            ("min_rate_rules", rules(&self.min_rate_rules)),
+            (
+                "short_flow_bytes",
+                json!(self.short_flow_bytes.map(|bytes| format!("{}B", bytes))),
+            ),
+            ("short_flow_rules", rules(&self.short_flow_rules)),
+            ("tenant_rules", rules(&self.tenant_rules)),
+            ("tenant_lookup", json!(self.tenant_lookup)),
+            ("tenant_configs", rules(&self.tenant_configs)),
+            (
+                "probe_rtt_sync_window",
+                duration(self.probe_rtt_sync_window),
+            ),
+            ("scale_probe_rtt", json!(self.scale_probe_rtt)),
+            ("share_min_rtt", json!(self.share_min_rtt)),
+            ("incast_threshold", json!(self.incast_threshold)),
+            ("startup_gain", json!(self.startup_gain)),
+            ("startup_cwnd_gain", json!(self.startup_cwnd_gain)),
+            ("drain_gain", json!(self.drain_gain)),
+            ("drain_to_target", json!(self.drain_to_target)),
+            ("track_cwnd", json!(self.track_cwnd)),
+            ("cwnd_cap", json!(self.cwnd_cap)),
+            ("cwnd_bdp_multiplier", json!(self.cwnd_bdp_multiplier)),
+            ("jitter_headroom", json!(self.jitter_headroom)),
+            ("delay_budget", duration(self.delay_budget)),
+            ("fast_step_down", json!(self.fast_step_down)),
+            ("fit_probes_to_buffer", json!(self.fit_probes_to_buffer)),
+            ("reset_desynced_pulses", json!(self.reset_desynced_pulses)),
+            ("pacing", json!(self.pacing)),
+            ("probe_bw_ramp", json!(self.probe_bw_ramp)),
+            ("stale_probes", json!(self.stale_probes)),
+            ("stale_probe_interval", json!(self.stale_probe_interval)),
+            ("stable_probe_gain", json!(self.stable_probe_gain)),
+            ("pulse_length", duration(self.pulse_length)),
+            ("pulse_shift", json!(self.pulse_shift)),
+            ("down_phase_end", json!(self.down_phase_end)),
+            ("cruise_phase_end", json!(self.cruise_phase_end)),
+            ("bw_window", json!(self.bw_window)),
+            ("high_rtt_threshold", duration(self.high_rtt_threshold)),
+            ("schedule_probes", json!(self.schedule_probes)),
+            ("max_report_age", duration(self.max_report_age)),
+            ("rate_smoothing", json!(self.rate_smoothing)),
+            ("loss_mode", json!(self.loss_mode)),
+            ("loss_rtt_inflation", json!(self.loss_rtt_inflation)),
+            ("loss_burst_fraction", json!(self.loss_burst_fraction)),
+            ("lossy_rtt_filter", json!(self.lossy_rtt_filter)),
+            ("loss_accounting", json!(self.loss_accounting)),
+            ("rate_estimator", json!(self.rate_estimator)),
+            ("update_retries", json!(self.update_retries)),
+            ("update_failure", json!(self.update_failure)),
+            ("log_granularity", json!(self.log_granularity)),
+            ("warn_interval", json!(format_duration(self.warn_interval))),
+            ("datapath", json!(self.datapath)),
+            ("register_limit", json!(self.register_limit)),
+            ("recording", json!(self.recorder.is_some())),
+        ];
+        Value::Object(
+            settings
+                .into_iter()
+                .map(|(name, value)| (String::from(name), value))
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,83 @@
+//! The errors of the library's fallible functions.
+//!
+//! An embedder can tell a [`BbrError`] that ends the agent, such as its IPC failing or its
+//! flags being invalid, from one that only costs a flow a report or a program install, which
+//! the flow recovers from by itself.
+
+use crate::params::PROGRAM_VERSION;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BbrError {
+    /// Serving the datapath failed, or could not start.
+    Ipc(String),
+    /// The datapath rejected a program.
+    Install {
+        program: &'static str,
+        reason: String,
+    },
+    /// A report lacked a field its program sends.
+    MissingField {
+        program_uid: u32,
+        field: &'static str,
+    },
+    /// A report came from a program of another version than `PROGRAM_VERSION`, or one
+    /// without a version, which an agent of another version installed.
+    ProgramVersion {
+        program_uid: u32,
+        found: Option<u64>,
+    },
+    /// The configuration, or the flags it was parsed from, is invalid.
+    Config(String),
+}
+
+impl BbrError {
+    /// Whether the agent cannot go on, rather than a single flow missing a report or an
+    /// install that it retries.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            BbrError::Ipc(_) | BbrError::Config(_) => true,
+            BbrError::Install { .. }
+            | BbrError::MissingField { .. }
+            | BbrError::ProgramVersion { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for BbrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BbrError::Ipc(reason) => write!(f, "ipc failed: {}", reason),
+            BbrError::Install { program, reason } => {
+                write!(f, "could not install {}: {}", program, reason)
+            }
+            BbrError::MissingField { program_uid, field } => {
+                write!(f, "report of program {} lacks {}", program_uid, field)
+            }
+            BbrError::ProgramVersion { program_uid, found } => {
+                let age = match *found {
+                    Some(found) if found > u64::from(PROGRAM_VERSION) => "a newer",
+                    _ => "an older",
+                };
+                write!(
+                    f,
+                    "report of program {} is from version {}, not {}: the datapath runs programs of {} agent",
+                    program_uid,
+                    found.map_or(String::from("none"), |v| v.to_string()),
+                    PROGRAM_VERSION,
+                    age
+                )
+            }
+            BbrError::Config(reason) => write!(f, "invalid configuration: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for BbrError {}
+
+/// For the portus traits, which only take their own error.
+impl From<BbrError> for portus::Error {
+    fn from(err: BbrError) -> Self {
+        portus::Error(err.to_string())
+    }
+}
@@ -0,0 +1,443 @@
+//! A C ABI for the BBR control logic, for datapaths that are not written in Rust.
+//!
+//! A C transport creates a flow per connection, feeds it its reports, and applies the actions
+//! each call returns, just as `Bbr` does for portus. `include/ccp_bbr.h` declares these
+//! functions; build the library with
+//! `cargo rustc --release --lib --features ffi --crate-type staticlib` (or `cdylib`).
+//!
+//! Strings and arrays returned by a flow stay valid until the next call on that flow.
+
+use crate::error::BbrError;
+use crate::{Action, BbrConfig, BbrCore, BbrMode, Measurement};
+use portus::{CongAlgBuilder, DatapathInfo};
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::time::{Duration, Instant};
+
+pub const CCP_BBR_SET_PROGRAM: u32 = 0;
+pub const CCP_BBR_UPDATE: u32 = 1;
+
+/// A `BbrConfig`, with its programs ready to hand out.
+pub struct CcpBbrConfig {
+    cfg: BbrConfig,
+    programs: Vec<(CString, CString)>,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CcpBbrFlowInfo {
+    pub sock_id: u32,
+    pub init_cwnd: u32,
+    pub mss: u32,
+    pub src_ip: u32,
+    pub src_port: u32,
+    pub dst_ip: u32,
+    pub dst_port: u32,
+}
+
+/// A report, as described by `Measurement`. Booleans are zero or non-zero.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CcpBbrReport {
+    pub program_uid: u32,
+    pub minrtt_us: u32,
+    pub loss: u32,
+    pub misordered: u32,
+    pub timeout: u8,
+    pub rate_outgoing: f64,
+    pub rate_incoming: f64,
+    pub delivery_rate: f64,
+    pub pulse_state: u32,
+    pub receiver_limited: u8,
+    pub inflight_bytes: u32,
+    pub srtt_us: u32,
+    pub rttvar_us: u32,
+    pub max_rate: f64,
+    pub acked: u32,
+    pub acks: u32,
+    pub nonce: u32,
+    pub nonce_age_us: u32,
+    pub delivered_total: u64,
+    pub span_us: u32,
+    pub mss: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CcpBbrField {
+    pub name: *const c_char,
+    pub value: u64,
+}
+
+/// `CCP_BBR_SET_PROGRAM` installs `program` with `fields` as initial values;
+/// `CCP_BBR_UPDATE` writes `fields` to the installed program, and `program` is null.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CcpBbrAction {
+    pub kind: u32,
+    pub program: *const c_char,
+    pub fields: *const CcpBbrField,
+    pub num_fields: usize,
+}
+
+pub struct CcpBbrFlow {
+    core: BbrCore,
+    epoch: Instant,
+    epoch_us: u64,
+    // program and register names, NUL-terminated
+    names: HashMap<&'static str, CString>,
+    fields: Vec<Vec<CcpBbrField>>,
+    actions: Vec<CcpBbrAction>,
+}
+
+impl CcpBbrFlow {
+    fn name(&mut self, name: &'static str) -> *const c_char {
+        self.names
+            .entry(name)
+            .or_insert_with(|| CString::new(name).unwrap())
+            .as_ptr()
+    }
+
+    fn field_list(&mut self, fields: &[(&'static str, u64)]) -> Vec<CcpBbrField> {
+        fields
+            .iter()
+            .map(|&(name, value)| CcpBbrField {
+                name: self.name(name),
+                value,
+            })
+            .collect()
+    }
+
+    fn set_actions(&mut self, actions: Vec<Action>) -> usize {
+        let mut kinds = vec![];
+        let mut fields = vec![];
+        for action in &actions {
+            let (kind, program, action_fields) = match action {
+                Action::SetProgram { program, fields } => {
+                    (CCP_BBR_SET_PROGRAM, self.name(program), fields)
+                }
+                Action::Update(fields) => (CCP_BBR_UPDATE, std::ptr::null(), fields),
+            };
+            kinds.push((kind, program));
+            fields.push(self.field_list(action_fields));
+        }
+
+        // the actions point into `fields`, which must not move until the next call
+        self.fields = fields;
+        self.actions = kinds
+            .into_iter()
+            .zip(&self.fields)
+            .map(|((kind, program), fields)| CcpBbrAction {
+                kind,
+                program,
+                fields: fields.as_ptr(),
+                num_fields: fields.len(),
+            })
+            .collect();
+        self.actions.len()
+    }
+}
+
+impl CcpBbrConfig {
+    fn new(cfg: BbrConfig) -> Box<Self> {
+        let mut programs: Vec<_> = cfg
+            .programs()
+            .into_iter()
+            .map(|(name, program)| (CString::new(name).unwrap(), CString::new(program).unwrap()))
+            .collect();
+        programs.sort();
+        Box::new(CcpBbrConfig { cfg, programs })
+    }
+}
+
+/// A configuration with the defaults of the `bbr` binary.
+#[no_mangle]
+pub extern "C" fn ccp_bbr_config_new() -> *mut CcpBbrConfig {
+    Box::into_raw(CcpBbrConfig::new(BbrConfig::default()))
+}
+
+/// A configuration from the `bbr` binary's flags, or null if they are invalid.
+///
+/// # Safety
+///
+/// `argv` must point to `argc` NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_config_from_args(
+    argc: c_int,
+    argv: *const *const c_char,
+) -> *mut CcpBbrConfig {
+    let mut args = vec![String::from("bbr")];
+    for i in 0..usize::try_from(argc).unwrap_or(0) {
+        let arg = *argv.add(i);
+        if arg.is_null() {
+            return std::ptr::null_mut();
+        }
+        args.push(CStr::from_ptr(arg).to_string_lossy().into_owned());
+    }
+
+    let cfg = BbrConfig::args()
+        .get_matches_from_safe(args)
+        .map_err(|e| BbrError::Config(e.message))
+        .and_then(|matches| BbrConfig::from_arg_matches(&matches));
+    match cfg {
+        Ok(cfg) => Box::into_raw(CcpBbrConfig::new(cfg)),
+        Err(err) => {
+            tracing::warn!(?err, "invalid BBR flags");
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+///
+/// `cfg` must come from `ccp_bbr_config_new` or `ccp_bbr_config_from_args`, or be null. Flows
+/// may outlive their configuration.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_config_free(cfg: *mut CcpBbrConfig) {
+    if !cfg.is_null() {
+        drop(Box::from_raw(cfg));
+    }
+}
+
+/// The number of datapath programs the flows install.
+///
+/// # Safety
+///
+/// `cfg` must be a live configuration.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_num_programs(cfg: *const CcpBbrConfig) -> usize {
+    let cfg = &*cfg;
+    cfg.programs.len()
+}
+
+/// The text of the `i`th program, with its name in `name`; null if there is no such program.
+///
+/// # Safety
+///
+/// `cfg` must be a live configuration, and `name` null or writable. The strings live as long
+/// as `cfg`.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_program(
+    cfg: *const CcpBbrConfig,
+    i: usize,
+    name: *mut *const c_char,
+) -> *const c_char {
+    let cfg = &*cfg;
+    match cfg.programs.get(i) {
+        Some((program_name, program)) => {
+            if !name.is_null() {
+                *name = program_name.as_ptr();
+            }
+            program.as_ptr()
+        }
+        None => std::ptr::null(),
+    }
+}
+
+/// Starts a flow at `now_us`, a monotonic clock in microseconds that later reports share, and
+/// returns it with its starting actions pending.
+///
+/// # Safety
+///
+/// `cfg` and `info` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_flow_new(
+    cfg: *const CcpBbrConfig,
+    info: *const CcpBbrFlowInfo,
+    now_us: u64,
+) -> *mut CcpBbrFlow {
+    let info = *info;
+    let info = DatapathInfo {
+        sock_id: info.sock_id,
+        init_cwnd: info.init_cwnd,
+        mss: info.mss,
+        src_ip: info.src_ip,
+        src_port: info.src_port,
+        dst_ip: info.dst_ip,
+        dst_port: info.dst_port,
+    };
+    let epoch = Instant::now();
+    let core = BbrCore::new(&(*cfg).cfg, &info, epoch);
+    let start = core.start();
+    let mut flow = Box::new(CcpBbrFlow {
+        core,
+        epoch,
+        epoch_us: now_us,
+        names: HashMap::new(),
+        fields: vec![],
+        actions: vec![],
+    });
+    flow.set_actions(start);
+    Box::into_raw(flow)
+}
+
+/// # Safety
+///
+/// `flow` must come from `ccp_bbr_flow_new`, or be null.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_flow_free(flow: *mut CcpBbrFlow) {
+    if !flow.is_null() {
+        drop(Box::from_raw(flow));
+    }
+}
+
+/// Tells the flow the uid of the program instance installed for its last `SET_PROGRAM`
+/// action. Reports from other instances are ignored.
+///
+/// # Safety
+///
+/// `flow` must be a live flow.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_flow_program_installed(flow: *mut CcpBbrFlow, program_uid: u32) {
+    (*flow).core.program_installed(program_uid);
+}
+
+/// Handles a report received at `now_us`, and returns how many actions are now pending.
+///
+/// # Safety
+///
+/// `flow` must be a live flow and `report` valid.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_flow_on_report(
+    flow: *mut CcpBbrFlow,
+    now_us: u64,
+    report: *const CcpBbrReport,
+) -> usize {
+    let flow = &mut *flow;
+    let r = *report;
+    let m = Measurement {
+        program_uid: r.program_uid,
+        minrtt_us: r.minrtt_us,
+        loss: r.loss,
+        acked: r.acked,
+        misordered: r.misordered,
+        timeout: r.timeout != 0,
+        rate_outgoing: r.rate_outgoing,
+        rate_incoming: r.rate_incoming,
+        delivery_rate: r.delivery_rate,
+        pulse_state: r.pulse_state,
+        receiver_limited: r.receiver_limited != 0,
+        inflight_bytes: r.inflight_bytes,
+        srtt_us: r.srtt_us,
+        rttvar_us: r.rttvar_us,
+        max_rate: r.max_rate,
+        acks: r.acks,
+        nonce: r.nonce,
+        nonce_age_us: r.nonce_age_us,
+        delivered_total: r.delivered_total,
+        span_us: r.span_us,
+        mss: r.mss,
+        received: None,
+    };
+    let now = flow.epoch + Duration::from_micros(now_us.saturating_sub(flow.epoch_us));
+    let actions = flow.core.on_measurement(now, m);
+    flow.set_actions(actions)
+}
+
+/// The pending actions, to apply in order; `num_actions` is set to their number.
+///
+/// # Safety
+///
+/// `flow` must be a live flow, and `num_actions` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_flow_actions(
+    flow: *const CcpBbrFlow,
+    num_actions: *mut usize,
+) -> *const CcpBbrAction {
+    let actions = &(*flow).actions;
+    if !num_actions.is_null() {
+        *num_actions = actions.len();
+    }
+    actions.as_ptr()
+}
+
+/// Stops the flow's probing, and returns how many actions are now pending.
+///
+/// # Safety
+///
+/// `flow` must be a live flow.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_flow_pause(flow: *mut CcpBbrFlow) -> usize {
+    let flow = &mut *flow;
+    let actions = flow.core.pause();
+    flow.set_actions(actions)
+}
+
+/// Restores the flow's probing, and returns how many actions are now pending.
+///
+/// # Safety
+///
+/// `flow` must be a live flow.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_flow_resume(flow: *mut CcpBbrFlow) -> usize {
+    let flow = &mut *flow;
+    let actions = flow.core.resume();
+    flow.set_actions(actions)
+}
+
+/// Starts the flow's next up pulse at `at_us`, on the clock of `now_us`, and returns how many
+/// actions are now pending.
+///
+/// # Safety
+///
+/// `flow` must be a live flow.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_flow_schedule_probe(
+    flow: *mut CcpBbrFlow,
+    now_us: u64,
+    at_us: u64,
+) -> usize {
+    let flow = &mut *flow;
+    let now = flow.epoch + Duration::from_micros(now_us.saturating_sub(flow.epoch_us));
+    let at = flow.epoch + Duration::from_micros(at_us.saturating_sub(flow.epoch_us));
+    let actions = flow.core.schedule_probe(now, at);
+    flow.set_actions(actions)
+}
+
+/// Takes up the flow's new MSS, e.g. after path MTU discovery shrank it, and returns how many
+/// actions are now pending.
+///
+/// # Safety
+///
+/// `flow` must be a live flow.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_flow_set_mss(flow: *mut CcpBbrFlow, mss: u32) -> usize {
+    let flow = &mut *flow;
+    let actions = flow.core.set_mss(mss);
+    flow.set_actions(actions)
+}
+
+/// 0 for STARTUP, 1 for DRAIN, 2 for `PROBE_BW` and 3 for `PROBE_RTT`.
+///
+/// # Safety
+///
+/// `flow` must be a live flow.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_flow_mode(flow: *const CcpBbrFlow) -> u32 {
+    match (*flow).core.mode() {
+        BbrMode::Startup => 0,
+        BbrMode::Drain => 1,
+        BbrMode::ProbeBw => 2,
+        BbrMode::ProbeRtt => 3,
+    }
+}
+
+/// The flow's share of its bandwidth estimate times its min RTT, in bytes.
+///
+/// # Safety
+///
+/// `flow` must be a live flow.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_flow_estimated_bdp_bytes(flow: *const CcpBbrFlow) -> u64 {
+    (*flow).core.estimated_bdp_bytes()
+}
+
+/// 1 once STARTUP has found the pipe full, else 0.
+///
+/// # Safety
+///
+/// `flow` must be a live flow.
+#[no_mangle]
+pub unsafe extern "C" fn ccp_bbr_flow_pipe_full(flow: *const CcpBbrFlow) -> u8 {
+    u8::from((*flow).core.pipe_full())
+}
@@ -0,0 +1,1066 @@
+//! A flow: the [`BbrCore`] that keeps its state and answers for it, and the portus side that
+//! installs the programs and writes the registers the core asks for, and passes it the fields
+//! of the datapath's reports.
+
+use crate::bandwidth::Rate;
+use crate::buffer::{BufferDepth, BufferEstimator};
+use crate::capability::DatapathCapabilities;
+use crate::error::BbrError;
+use crate::flow_id::FlowId;
+use crate::flow_limit::FlowLimits;
+use crate::group::BottleneckGroups;
+use crate::initial::{DEFAULT_INITIAL_RATE, DEFAULT_INITIAL_RTT};
+use crate::jitter::RttJitter;
+use crate::latency::LatencyHistogram;
+use crate::log_granularity::{CycleSummary, LogGranularity};
+use crate::loss::LossMode;
+use crate::mode_time::{ModeClock, ModeTime};
+use crate::params::{
+    HIGH_RTT_CYCLE_ROUNDS, INCAST_MIN_CWND_PACKETS, INCAST_STARTUP_GAIN, INCAST_WINDOW_MS,
+    MIN_RTT_WINDOW_SECONDS, PROBE_GAIN, PROBE_RTT_CWND_PACKETS, SHORT_FLOW_CWND_PACKETS,
+};
+use crate::path_cache::PathCache;
+use crate::pause::PausedFlows;
+use crate::probe_schedule::ProbeSchedule;
+use crate::rate::{RateEstimator, RateFilter};
+use crate::rate_hint::RateHints;
+use crate::shutdown::Shutdown;
+use crate::snapshot::{FlowSnapshot, Snapshots};
+use crate::state::min_rtt_expiry;
+use crate::tenant::Tenants;
+use crate::trace::Recorder;
+use crate::update_failure::UpdateFailurePolicy;
+use crate::warn_limit::WarnLimiter;
+use crate::weight::FlowWeights;
+use crate::{BbrConfig, BbrMode, PulsePhase, TransitionReason, WallClock};
+use portus::ipc::Ipc;
+use portus::lang::Scope;
+use portus::{CongAlg, Datapath, DatapathInfo, DatapathTrait, Report};
+use std::collections::{BTreeMap, HashMap};
+use std::panic::AssertUnwindSafe;
+use std::time::{Duration, Instant};
+use tracing::{info, info_span, warn, Span};
+
+/// A change to the flow's datapath state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Install the named program, substituting initial values for the given registers.
+    SetProgram {
+        program: &'static str,
+        fields: Vec<(&'static str, u64)>,
+    },
+    /// Update registers of the currently installed program.
+    Update(Vec<(&'static str, u64)>),
+}
+
+/// The BBR control logic, decoupled from the datapath.
+///
+/// The core consumes the measurements of each datapath report and returns the [`Action`]s
+/// the datapath should apply, so that it can be driven without a CCP datapath.
+pub struct BbrCore {
+    pub(crate) flow: FlowId,
+    /// Entered while the flow logs, so that every line carries its `FlowId`.
+    pub(crate) span: Span,
+    pub(crate) weights: FlowWeights,
+    pub(crate) rate_share: f64,
+    /// Bytes per second, or infinite for uncapped flows: the configured cap, or the flow's
+    /// limit if that is lower.
+    pub(crate) max_rate: f64,
+    /// Bytes per second, or zero for flows without a floor. A cap below it wins.
+    pub(crate) min_rate: f64,
+    pub(crate) configured_max_rate: f64,
+    pub(crate) configured_min_rate: f64,
+    pub(crate) flow_limits: FlowLimits,
+    /// The limit the flow follows, as of its last report.
+    pub(crate) rate_limit: Option<Rate>,
+    pub(crate) tenant: Option<String>,
+    pub(crate) tenants: Tenants,
+    /// The aggregate rate of the flow's tenant, which its flows split.
+    pub(crate) tenant_rate: Option<Rate>,
+    pub(crate) dst_ip: u32,
+    pub(crate) path_cache: PathCache,
+    pub(crate) groups: BottleneckGroups,
+    pub(crate) group: u32,
+    pub(crate) probe_rtt_sync_window: Option<Duration>,
+    pub(crate) scale_probe_rtt: bool,
+    pub(crate) share_min_rtt: bool,
+    /// When the flow last measured its `min_rtt`, or took one its group measured.
+    pub(crate) min_rtt_measured: Option<Instant>,
+    pub(crate) last_probe_rtt: Option<Instant>,
+    pub(crate) shutdown: Shutdown,
+    pub(crate) snapshots: Snapshots,
+    pub(crate) rate_hints: RateHints,
+    /// The pacing rate the flow last hinted to each subscriber of `rate_hints`.
+    pub(crate) hinted_rates: HashMap<u64, Rate>,
+    pub(crate) recorder: Option<Recorder>,
+    pub(crate) pauses: PausedFlows,
+    /// Whether the flow has stopped probing for bandwidth, as of its last report.
+    pub(crate) paused: bool,
+    /// The program installed last, and the last value written to each of its registers and
+    /// to the flow's `Cwnd` and `Rate`.
+    pub(crate) program: &'static str,
+    pub(crate) registers: BTreeMap<&'static str, u64>,
+    pub(crate) reports: u64,
+    pub(crate) stale_reports: u64,
+    pub(crate) outdated_reports: u64,
+    pub(crate) max_report_age: Option<Duration>,
+    pub(crate) late_reports: u64,
+    pub(crate) stalled_reports: u64,
+    pub(crate) handling: LatencyHistogram,
+    // whether the last report took long to handle, so that the warning is not repeated for
+    // every report
+    pub(crate) slow_handling: bool,
+    pub(crate) probe_rtt_entries: u64,
+    /// PROBE_BW pulse cycles that ran to their end.
+    pub(crate) probe_bw_cycles: u64,
+    /// The pulse phase `probe_bw`'s next report should come from.
+    pub(crate) expected_phase: PulsePhase,
+    pub(crate) pulse_desyncs: u64,
+    /// Reports in a row from another pulse phase than expected.
+    pub(crate) pulse_desyncs_in_row: u32,
+    /// Programs installed, including reinstalls.
+    pub(crate) program_installs: u64,
+    pub(crate) reinstalls: u64,
+    /// Actions the datapath failed to apply, apart from programs it refused to install.
+    pub(crate) failed_updates: u64,
+    pub(crate) rejected_installs: u64,
+    /// The fraction of packets lost in the last report interval that acked or lost any.
+    pub(crate) loss_rate: f64,
+    pub(crate) lost_packets: u64,
+    pub(crate) acked_packets: u64,
+    pub(crate) rate_estimator: RateEstimator,
+    pub(crate) capabilities: DatapathCapabilities,
+    /// Consecutive PROBE_BW reports that sent faster than pacing allows.
+    pub(crate) unpaced_reports: u32,
+    /// Whether the datapath failed to apply an action, so that its program and registers may
+    /// differ from `program` and `registers`.
+    pub(crate) reinstall: bool,
+    /// Failed actions since the datapath last applied the flow's actions and sent a report.
+    pub(crate) failures_in_row: u32,
+    pub(crate) update_retries: u32,
+    pub(crate) update_failure: UpdateFailurePolicy,
+    pub(crate) log_granularity: LogGranularity,
+    pub(crate) warn_interval: Duration,
+    pub(crate) warnings: WarnLimiter,
+    // what the current PROBE_BW cycle's reports saw, for its summary
+    pub(crate) cycle: CycleSummary,
+    /// What the last PROBE_BW cycle that was not held back delivered over what it paced at.
+    pub(crate) delivery_ratio: Option<f64>,
+    /// PROBE_BW cycles that delivered less than `DELIVERY_GAP_RATIO` of what they paced at.
+    pub(crate) delivery_gap_cycles: u64,
+    pub(crate) delivery_gaps_in_row: u32,
+    /// Whether the flow gave up on updates and was left at its estimate, ignoring reports.
+    pub(crate) frozen: bool,
+    /// Consecutive attempts to install `probe_bw` that the datapath rejected.
+    pub(crate) probe_bw_rejections: u32,
+    /// Whether the flow runs the `aimd` fallback instead of `probe_bw`.
+    pub(crate) degraded: bool,
+    pub(crate) released: bool,
+    /// Whether the flow periodically enters `PROBE_RTT`; otherwise `probe_rtt_interval` is the
+    /// window over which `min_rtt` is refreshed from natural samples.
+    pub(crate) probe_rtt: bool,
+    pub(crate) probe_rtt_interval: Duration,
+    /// If set, the `min_rtt` timer counts this many PROBE_BW round trips instead of
+    /// `probe_rtt_interval`.
+    pub(crate) probe_rtt_rounds: Option<u32>,
+    /// `probe_bw_cycles` when the `min_rtt` timer last restarted.
+    pub(crate) min_rtt_cycle: u64,
+    /// The lowest RTT sampled in `PROBE_BW` since the `min_rtt` timer last restarted.
+    pub(crate) window_min_rtt_us: u32,
+    pub(crate) bottle_rate: f64,
+    pub(crate) bottle_rate_timeout: Instant,
+    pub(crate) min_rtt_us: u32,
+    pub(crate) min_rtt_timeout: Instant,
+    pub(crate) srtt_us: u32,
+    pub(crate) rttvar_us: u32,
+    /// The peak inflight of the last PROBE_BW report.
+    pub(crate) inflight_bytes: u32,
+    /// Whether the last PROBE_BW report kept less than half the BDP in flight without being
+    /// receiver-limited, so the application ran out of data.
+    pub(crate) app_limited: bool,
+    pub(crate) skip_quiet_probe_rtt: bool,
+    /// Whether a PROBE_BW report since `min_rtt` was last measured was app-limited, or came
+    /// after the flow had been idle for longer than PROBE_RTT lasts.
+    pub(crate) quiet_in_window: bool,
+    pub(crate) last_probe_bw_report: Option<Instant>,
+    pub(crate) skipped_probe_rtts: u64,
+    /// Whether the current cycle's up pulse was app- or receiver-limited, so that finding no
+    /// bandwidth says nothing about the path.
+    pub(crate) probe_limited: bool,
+    /// `min_rtt` before the current `PROBE_RTT`, which replaces it with the probe's sample.
+    pub(crate) pre_probe_rtt_min_rtt_us: u32,
+    pub(crate) min_rtt_spike_factor: Option<f64>,
+    /// The last checked RTT sample, if it was a spike.
+    pub(crate) min_rtt_spike: Option<u32>,
+    pub(crate) probe_rtt_alignment: Option<WallClock>,
+    pub(crate) probe_rtt_grace: Duration,
+    pub(crate) curr_mode: BbrMode,
+    pub(crate) transition_reason: Option<TransitionReason>,
+    pub(crate) mode_clock: ModeClock,
+    pub(crate) startup_gain: f64,
+    pub(crate) startup_cwnd_gain: f64,
+    pub(crate) drain_gain: f64,
+    pub(crate) cwnd_cap: bool,
+    pub(crate) cwnd_bdp_multiplier: f64,
+    pub(crate) jitter_headroom: bool,
+    pub(crate) delay_budget_us: Option<u32>,
+    pub(crate) rtt_jitter: RttJitter,
+    pub(crate) pacing: bool,
+    pub(crate) probe_bw_ramp: bool,
+    /// Whether PROBE_BW is draining a standing queue this flow built.
+    pub(crate) queue_backoff: bool,
+    pub(crate) stale_probe_limit: u32,
+    pub(crate) stale_probe_interval: u32,
+    /// Whether the current pulse cycle probes above the estimate.
+    pub(crate) probing: bool,
+    /// Consecutive probing cycles that did not raise `bottle_rate`.
+    pub(crate) stale_probes: u32,
+    pub(crate) cycles_since_probe: u32,
+    pub(crate) probe_start_rate: f64,
+    pub(crate) stable_probe_gain: Option<f64>,
+    /// The up pulse's current gain.
+    pub(crate) probe_gain: f64,
+    /// Consecutive cycles that left `bottle_rate` within `STABLE_BW_TOLERANCE`.
+    pub(crate) stable_cycles: u32,
+    pub(crate) cycle_start_rate: f64,
+    pub(crate) fast_step_down: bool,
+    /// The queues recent probes built, for the bottleneck buffer estimate.
+    pub(crate) buffer: BufferEstimator,
+    pub(crate) fit_probes_to_buffer: bool,
+    pub(crate) reset_desynced_pulses: bool,
+    /// Consecutive cruise phases that delivered well below `bottle_rate` into an inflated RTT,
+    /// and the most any of them delivered.
+    pub(crate) step_down_phases: u32,
+    pub(crate) step_down_rate: f64,
+    pub(crate) pulse_length_us: Option<u32>,
+    pub(crate) pulse_shift: bool,
+    pub(crate) cruise_phase_end: u32,
+    pub(crate) high_rtt_threshold_us: Option<u32>,
+    /// Whether the flow entered PROBE_BW above `high_rtt_threshold_us`, and runs the high-RTT
+    /// profile.
+    pub(crate) high_rtt: bool,
+    pub(crate) schedule_probes: bool,
+    pub(crate) probe_schedule: ProbeSchedule,
+    /// When the current PROBE_BW cycle started, as of the report of the last one's end; unknown
+    /// until a cycle has ended since `probe_bw` was installed.
+    pub(crate) cycle_start: Option<Instant>,
+    /// The nonce of the last update tagged to measure the install latency, and when it was
+    /// sent, until a report echoes it.
+    pub(crate) pending_nonce: Option<(u32, Instant)>,
+    pub(crate) last_nonce: u32,
+    /// Smoothed, from report to the update it prompts taking effect; `None` until measured.
+    pub(crate) install_latency_us: Option<f64>,
+    pub(crate) rate_smoothing: Option<f64>,
+    /// Where the smoothed rate registers are headed, for the ones not there yet.
+    pub(crate) rate_targets: BTreeMap<&'static str, u64>,
+    pub(crate) loss_mode: LossMode,
+    pub(crate) loss_rtt_inflation: f64,
+    pub(crate) loss_burst_fraction: Option<f64>,
+    pub(crate) lossy_rtt_filter: bool,
+    /// RTT samples passed over for coming from lossy reports; see `is_lossy_rtt_sample`.
+    pub(crate) lossy_rtt_samples: u64,
+    /// After a loss burst, the window the flow holds cwnd to, for `loss_burst_cycles` more
+    /// pulse cycles.
+    pub(crate) loss_burst_cwnd: Option<u32>,
+    pub(crate) loss_burst_cycles: u32,
+    pub(crate) loss_bursts: u64,
+    /// The largest delivery rate seen in STARTUP that grew enough over the previous one.
+    pub(crate) full_bw: f64,
+    /// STARTUP rounds since the delivery rate last grew enough.
+    pub(crate) full_bw_rounds: u32,
+    /// Whether STARTUP ended because the flow filled the pipe, by reaching full bandwidth or
+    /// by losses that count as congestion.
+    pub(crate) pipe_full: bool,
+    pub(crate) rates: RateFilter,
+    pub(crate) mss: u32,
+    pub(crate) init_cwnd: u32,
+    /// The window `start` installs: a configured initial BDP, or `init_cwnd`.
+    pub(crate) start_cwnd: u32,
+    /// The bottleneck rate the first flight is paced from, if anything is known about the
+    /// path before the first RTT sample.
+    pub(crate) start_rate: Option<f64>,
+    /// For a short flow, the bytes it may still ack before it starts STARTUP.
+    pub(crate) short_flow_left: Option<u64>,
+    pub(crate) start: Instant,
+    pub(crate) program_uid: u32,
+}
+
+impl BbrCore {
+    pub fn new(cfg: &BbrConfig, info: &DatapathInfo, now: Instant) -> Self {
+        let flow = FlowId::from(info);
+        let tenant = crate::tenant::tenant_for(&cfg.tenant_rules, cfg.tenant_lookup, info);
+        let span = info_span!("flow", id = %flow, tenant = tenant.as_deref());
+        let _entered = span.enter();
+        let tenant_settings = crate::tenant::settings_for(&cfg.tenant_configs, tenant.as_deref());
+        if let Some(tenant) = &tenant {
+            cfg.tenants.join(tenant, info.sock_id);
+        }
+        if let Some(recorder) = &cfg.recorder {
+            recorder.new_flow(info, now);
+        }
+        let group = cfg.groups.join(info.dst_ip, info.sock_id, now);
+        let incast = cfg.incast_threshold.and_then(|threshold| {
+            let starting =
+                cfg.groups
+                    .joined_within(group, now, Duration::from_millis(INCAST_WINDOW_MS))
+                    as u32;
+            (starting >= threshold).then_some(starting)
+        });
+        let seed = cfg.path_cache.get(info.dst_ip, now);
+        if let Some(est) = seed {
+            info!(
+                bottle_rate = %Rate::from_bytes_per_sec(est.bottle_rate),
+                min_rtt_us = est.min_rtt_us,
+                "seeding new flow from path cache"
+            );
+        }
+
+        let (initial_rate, initial_rtt) = crate::initial::initial_path_for(
+            &cfg.initial_path_rules,
+            cfg.initial_rate,
+            cfg.initial_rtt,
+            info,
+        );
+        let initial_rate = initial_rate.map(Rate::bytes_per_sec);
+        let configured_max_rate = crate::max_rate::max_rate_for(
+            &cfg.max_rate_rules,
+            tenant_settings.max_rate.or(cfg.max_rate),
+            info,
+        )
+        .map_or(f64::INFINITY, Rate::bytes_per_sec);
+        let configured_min_rate = crate::min_rate::min_rate_for(
+            &cfg.min_rate_rules,
+            tenant_settings.min_rate.or(cfg.min_rate),
+            info,
+        )
+        .map_or(0.0, Rate::bytes_per_sec);
+        let tenant_share = tenant
+            .as_deref()
+            .zip(tenant_settings.aggregate_rate)
+            .map(|(tenant, rate)| cfg.tenants.share(tenant, rate));
+        let rate_limit = lower_limit(cfg.flow_limits.get(info.sock_id), tenant_share);
+        let max_rate = rate_limit.map_or(configured_max_rate, |limit| {
+            configured_max_rate.min(limit.bytes_per_sec())
+        });
+        let min_rate = configured_min_rate.min(max_rate);
+        // a cached estimate is only a hint, but a configured path is known to carry a BDP
+        let start_cwnd = match (seed, initial_rate, initial_rtt) {
+            (None, Some(rate), Some(rtt)) => {
+                let bdp = (rate * rtt.as_secs_f64()).min(f64::from(u32::MAX)) as u32;
+                info!(
+                    bottle_rate = %Rate::from_bytes_per_sec(rate),
+                    min_rtt_us = rtt.as_micros() as u64,
+                    cwnd = bdp.max(info.init_cwnd),
+                    "seeding new flow from configured path"
+                );
+                bdp.max(info.init_cwnd)
+            }
+            _ => info.init_cwnd,
+        };
+        let start_rate = seed
+            .map(|est| est.bottle_rate)
+            .or(initial_rate)
+            .or_else(|| initial_rtt.map(|rtt| f64::from(start_cwnd) / rtt.as_secs_f64()));
+        // flows starting together split what one flow would start with
+        let (start_cwnd, start_rate, startup_gain, startup_cwnd_gain) = match incast {
+            Some(flows) => {
+                let cwnd = (start_cwnd / flows)
+                    .max(info.mss.saturating_mul(INCAST_MIN_CWND_PACKETS))
+                    .min(start_cwnd);
+                info!(flows, cwnd, "starting in an incast");
+                (
+                    cwnd,
+                    start_rate.map(|rate| rate / f64::from(flows)),
+                    cfg.startup_gain.min(INCAST_STARTUP_GAIN),
+                    cfg.startup_cwnd_gain.min(INCAST_STARTUP_GAIN),
+                )
+            }
+            None => (
+                start_cwnd,
+                start_rate,
+                cfg.startup_gain,
+                cfg.startup_cwnd_gain,
+            ),
+        };
+        let initial_rate = initial_rate.unwrap_or(DEFAULT_INITIAL_RATE.bytes_per_sec());
+        let initial_rtt_us = initial_rtt
+            .unwrap_or(DEFAULT_INITIAL_RTT)
+            .as_micros()
+            .min(u128::from(u32::MAX)) as u32;
+
+        let probe_rtt = !cfg.probe_rtt_interval.is_zero();
+        let probe_rtt_interval = if probe_rtt {
+            cfg.probe_rtt_interval
+        } else {
+            Duration::from_secs(MIN_RTT_WINDOW_SECONDS)
+        };
+
+        cfg.weights.register(
+            info.sock_id,
+            crate::weight::weight_for(&cfg.weight_rules, tenant_settings.weight, info),
+        );
+        cfg.shutdown.register(info.sock_id);
+        let mut core = BbrCore {
+            flow,
+            span: span.clone(),
+            weights: cfg.weights.clone(),
+            rate_share: cfg.weights.share(info.sock_id),
+            max_rate,
+            min_rate,
+            configured_max_rate,
+            configured_min_rate,
+            flow_limits: cfg.flow_limits.clone(),
+            rate_limit,
+            tenant,
+            tenants: cfg.tenants.clone(),
+            tenant_rate: tenant_settings.aggregate_rate,
+            dst_ip: info.dst_ip,
+            path_cache: cfg.path_cache.clone(),
+            groups: cfg.groups.clone(),
+            group,
+            probe_rtt_sync_window: cfg.probe_rtt_sync_window,
+            scale_probe_rtt: cfg.scale_probe_rtt,
+            share_min_rtt: cfg.share_min_rtt,
+            min_rtt_measured: None,
+            last_probe_rtt: None,
+            shutdown: cfg.shutdown.clone(),
+            snapshots: cfg.snapshots.clone(),
+            rate_hints: cfg.rate_hints.clone(),
+            hinted_rates: HashMap::new(),
+            recorder: cfg.recorder.clone(),
+            pauses: cfg.paused.clone(),
+            paused: cfg.paused.is_paused(info.sock_id),
+            // what `start` installs
+            program: "init_program",
+            registers: BTreeMap::new(),
+            reports: 0,
+            stale_reports: 0,
+            outdated_reports: 0,
+            max_report_age: cfg.max_report_age,
+            late_reports: 0,
+            stalled_reports: 0,
+            handling: LatencyHistogram::default(),
+            slow_handling: false,
+            probe_rtt_entries: 0,
+            probe_bw_cycles: 0,
+            expected_phase: PulsePhase::Up,
+            pulse_desyncs: 0,
+            pulse_desyncs_in_row: 0,
+            // what `start` installs
+            program_installs: 1,
+            reinstalls: 0,
+            failed_updates: 0,
+            rejected_installs: 0,
+            loss_rate: 0.0,
+            lost_packets: 0,
+            acked_packets: 0,
+            rate_estimator: cfg.rate_estimator,
+            capabilities: cfg.capabilities.clone(),
+            unpaced_reports: 0,
+            reinstall: false,
+            failures_in_row: 0,
+            update_retries: cfg.update_retries,
+            update_failure: cfg.update_failure,
+            log_granularity: cfg.log_granularity,
+            warn_interval: cfg.warn_interval,
+            warnings: cfg.warnings.clone(),
+            cycle: CycleSummary::default(),
+            delivery_ratio: None,
+            delivery_gap_cycles: 0,
+            delivery_gaps_in_row: 0,
+            frozen: false,
+            probe_bw_rejections: 0,
+            degraded: false,
+            released: false,
+            probe_rtt,
+            probe_rtt_interval,
+            probe_rtt_rounds: cfg.probe_rtt_rounds,
+            min_rtt_cycle: 0,
+            probe_rtt_alignment: cfg.probe_rtt_alignment,
+            probe_rtt_grace: cfg.probe_rtt_grace.unwrap_or(probe_rtt_interval),
+            window_min_rtt_us: u32::MAX,
+            bottle_rate: seed
+                .map_or(initial_rate, |est| est.bottle_rate)
+                .min(configured_max_rate),
+            bottle_rate_timeout: now + probe_rtt_interval,
+            min_rtt_us: seed.map_or(initial_rtt_us, |est| est.min_rtt_us),
+            min_rtt_timeout: min_rtt_expiry(cfg.probe_rtt_alignment, probe_rtt_interval, now),
+            srtt_us: 0,
+            rttvar_us: 0,
+            inflight_bytes: 0,
+            app_limited: false,
+            skip_quiet_probe_rtt: cfg.skip_quiet_probe_rtt,
+            quiet_in_window: false,
+            last_probe_bw_report: None,
+            skipped_probe_rtts: 0,
+            probe_limited: false,
+            pre_probe_rtt_min_rtt_us: 0,
+            min_rtt_spike_factor: cfg.min_rtt_spike_factor,
+            min_rtt_spike: None,
+            curr_mode: BbrMode::Startup,
+            transition_reason: None,
+            mode_clock: ModeClock::new(now),
+            startup_gain,
+            startup_cwnd_gain,
+            drain_gain: cfg.drain_gain,
+            // without pacing, cwnd is the only limit, and a delay budget is one on inflight
+            cwnd_cap: cfg.cwnd_cap || !cfg.pacing || cfg.delay_budget.is_some(),
+            cwnd_bdp_multiplier: cfg.cwnd_bdp_multiplier,
+            jitter_headroom: cfg.jitter_headroom,
+            delay_budget_us: cfg
+                .delay_budget
+                .map(|d| d.as_micros().min(u128::from(u32::MAX)) as u32),
+            rtt_jitter: RttJitter::default(),
+            pacing: cfg.pacing,
+            probe_bw_ramp: cfg.probe_bw_ramp,
+            queue_backoff: false,
+            stale_probe_limit: cfg.stale_probes,
+            stale_probe_interval: cfg.stale_probe_interval,
+            probing: true,
+            stale_probes: 0,
+            cycles_since_probe: 0,
+            probe_start_rate: 0.0,
+            stable_probe_gain: cfg.stable_probe_gain,
+            probe_gain: PROBE_GAIN,
+            stable_cycles: 0,
+            cycle_start_rate: 0.0,
+            fast_step_down: cfg.fast_step_down,
+            buffer: BufferEstimator::default(),
+            fit_probes_to_buffer: cfg.fit_probes_to_buffer,
+            reset_desynced_pulses: cfg.reset_desynced_pulses,
+            step_down_phases: 0,
+            step_down_rate: 0.0,
+            pulse_length_us: cfg
+                .pulse_length
+                .map(|d| d.as_micros().min(u128::from(u32::MAX)) as u32),
+            pulse_shift: cfg.pulse_shift,
+            cruise_phase_end: cfg.cruise_phase_end,
+            high_rtt_threshold_us: cfg
+                .high_rtt_threshold
+                .map(|d| d.as_micros().min(u128::from(u32::MAX)) as u32),
+            high_rtt: false,
+            schedule_probes: cfg.schedule_probes,
+            probe_schedule: cfg.probe_schedule.clone(),
+            cycle_start: None,
+            pending_nonce: None,
+            last_nonce: 0,
+            install_latency_us: None,
+            rate_smoothing: cfg.rate_smoothing,
+            rate_targets: BTreeMap::new(),
+            loss_mode: cfg.loss_mode,
+            loss_rtt_inflation: cfg.loss_rtt_inflation,
+            loss_burst_fraction: cfg.loss_burst_fraction,
+            lossy_rtt_filter: cfg.lossy_rtt_filter,
+            lossy_rtt_samples: 0,
+            loss_burst_cwnd: None,
+            loss_burst_cycles: 0,
+            loss_bursts: 0,
+            full_bw: 0.0,
+            full_bw_rounds: 0,
+            pipe_full: false,
+            rates: RateFilter::default(),
+            mss: info.mss,
+            init_cwnd: info.init_cwnd,
+            start_cwnd,
+            start_rate,
+            short_flow_left: crate::short_flow::short_flow_bytes_for(
+                &cfg.short_flow_rules,
+                cfg.short_flow_bytes,
+                info,
+            ),
+            start: now,
+            program_uid: 0,
+        };
+        // a sibling's measurement is fresher than the path cache's, and STARTUP takes no actions
+        // for it
+        core.adopt_shared_min_rtt(&mut vec![]);
+        let fields = core.start_fields();
+        core.registers.extend(fields);
+        core.snapshots.update(core.snapshot());
+        core
+    }
+
+    // until the first report, init_program paces at the STARTUP gain over cwnd / RTT, from
+    // the first RTT sample on, or over the known path rate before that
+    pub(crate) fn start_fields(&self) -> Vec<(&'static str, u64)> {
+        if let Some(bytes) = self.short_flow_left {
+            return self.short_flow_fields(bytes);
+        }
+        let mut fields = vec![("Cwnd", u64::from(self.start_cwnd))];
+        if self.pacing {
+            fields.push(("pacingGain", (self.startup_gain * 1e6) as u64));
+            if let Some(rate) = self.start_rate {
+                let rate = (rate * self.rate_share * self.startup_gain)
+                    .max(self.min_rate)
+                    .min(self.max_rate);
+                fields.push(("initRate", rate as u64));
+                fields.push(("Rate", rate as u64));
+            }
+        }
+        fields
+    }
+
+    // a short flow sends its first flights with a generous window, or the known path's BDP if
+    // that is more, but no more than the whole flow, at the known path's rate without a gain
+    fn short_flow_fields(&self, bytes: u64) -> Vec<(&'static str, u64)> {
+        let rate = self.start_rate.map(|rate| {
+            (rate * self.rate_share)
+                .max(self.min_rate)
+                .min(self.max_rate)
+        });
+        let bdp = rate.map_or(0.0, |rate| rate * f64::from(self.min_rtt_us) / 1e6);
+        let cwnd = f64::from(self.mss.saturating_mul(SHORT_FLOW_CWND_PACKETS))
+            .max(bdp)
+            .min(bytes as f64)
+            .max(f64::from(self.start_cwnd)) as u32;
+        let mut fields = vec![("Cwnd", u64::from(cwnd))];
+        if let (true, Some(rate)) = (self.pacing, rate) {
+            fields.push(("Rate", rate as u64));
+        }
+        fields
+    }
+
+    /// Whether the flow started as a short flow and has not acked enough bytes to start
+    /// STARTUP yet.
+    pub fn is_short_flow(&self) -> bool {
+        self.short_flow_left.is_some()
+    }
+
+    /// The actions that start the flow.
+    pub fn start(&self) -> Vec<Action> {
+        vec![Action::SetProgram {
+            program: "init_program",
+            fields: self.start_fields(),
+        }]
+    }
+
+    /// Records the uid of the program instance installed for the last `SetProgram` action.
+    ///
+    /// Reports from any other program instance are ignored.
+    pub fn program_installed(&mut self, program_uid: u32) {
+        self.program_uid = program_uid;
+        // the new instance's registers start over
+        self.pending_nonce = None;
+        if self.program == "probe_bw" {
+            self.probe_bw_rejections = 0;
+        }
+    }
+
+    pub fn sock_id(&self) -> u32 {
+        self.flow.sock_id
+    }
+
+    pub fn flow_id(&self) -> FlowId {
+        self.flow
+    }
+
+    /// The span the flow logs in; enter it to log on the flow's behalf.
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn mode(&self) -> BbrMode {
+        self.curr_mode
+    }
+
+    /// Why the flow entered its current mode; `None` until it leaves STARTUP.
+    pub fn transition_reason(&self) -> Option<TransitionReason> {
+        self.transition_reason
+    }
+
+    /// How long the flow has spent in each mode, up to its latest report.
+    pub fn mode_time(&self) -> ModeTime {
+        self.mode_clock.times(self.curr_mode)
+    }
+
+    /// Bytes per second.
+    pub fn bottle_rate(&self) -> f64 {
+        self.bottle_rate
+    }
+
+    pub fn min_rtt_us(&self) -> u32 {
+        self.min_rtt_us
+    }
+
+    /// The rate the flow paces at between its probes: its share of the bandwidth estimate,
+    /// within its floor and cap, in bytes per second.
+    pub fn pacing_rate(&self) -> f64 {
+        self.pulse_rate(1.0)
+    }
+
+    /// The rate the flow's last PROBE_BW cycle delivered over the rate it paced at, among the
+    /// reports the application and the receiver did not hold back; `None` until such a cycle
+    /// ends. Well below 1 cycle after cycle, the bandwidth estimate is above what the path
+    /// delivers, as behind a policer or a degrading radio link.
+    pub fn delivery_ratio(&self) -> Option<f64> {
+        self.delivery_ratio
+    }
+
+    /// The BDP the flow paces for: its share of the bandwidth estimate times its min RTT, in
+    /// bytes. Until STARTUP has measured them, it follows from the initial estimates.
+    pub fn estimated_bdp_bytes(&self) -> u64 {
+        (self.paced_bottle_rate() * f64::from(self.min_rtt_us) / 1e6) as u64
+    }
+
+    /// The bottleneck buffer PROBE_BW's probes found, once one of them ended; see [`buffer`].
+    pub fn buffer_depth(&self) -> Option<BufferDepth> {
+        self.buffer
+            .estimate(self.bottle_rate * f64::from(self.min_rtt_us) / 1e6)
+    }
+
+    /// Whether STARTUP found the pipe full, so that the bandwidth estimate is the path's
+    /// rather than a lower bound on it. A flow paused in STARTUP never finds it full.
+    pub fn pipe_full(&self) -> bool {
+        self.pipe_full
+    }
+
+    /// The p10 to p90 spread of recent PROBE_BW reports' RTTs.
+    pub fn rtt_jitter_us(&self) -> u32 {
+        self.rtt_jitter.spread_us()
+    }
+
+    /// The smoothed RTT from the last report that had one, or zero before any did.
+    pub fn srtt_us(&self) -> u32 {
+        self.srtt_us
+    }
+
+    /// The RTT variance to go with `srtt_us`.
+    pub fn rttvar_us(&self) -> u32 {
+        self.rttvar_us
+    }
+
+    /// The peak bytes in flight of the last PROBE_BW report.
+    pub fn inflight_bytes(&self) -> u32 {
+        self.inflight_bytes
+    }
+
+    /// The peak inflight of the last PROBE_BW report as a fraction of PROBE_BW's cwnd; close
+    /// to zero without a cwnd cap.
+    pub fn cwnd_utilization(&self) -> f64 {
+        f64::from(self.inflight_bytes) / f64::from(self.probe_bw_cwnd())
+    }
+
+    /// Whether the last PROBE_BW report was limited by the application rather than the path
+    /// or the receiver.
+    pub fn is_app_limited(&self) -> bool {
+        self.app_limited
+    }
+
+    /// The smoothed outgoing and incoming rates the bandwidth samples are taken from.
+    pub fn rates(&self) -> &RateFilter {
+        &self.rates
+    }
+
+    /// The round trips of the flow's PROBE_BW pulse cycles.
+    pub fn cycle_rounds(&self) -> u32 {
+        if self.high_rtt {
+            HIGH_RTT_CYCLE_ROUNDS
+        } else {
+            self.cruise_phase_end
+        }
+    }
+
+    /// Whether the flow runs the profile for paths above `BbrConfig::high_rtt_threshold`.
+    pub fn high_rtt(&self) -> bool {
+        self.high_rtt
+    }
+
+    /// The flow's current state, as published to `BbrConfig::snapshots`.
+    pub fn snapshot(&self) -> FlowSnapshot {
+        FlowSnapshot {
+            id: self.flow,
+            mode: self.curr_mode,
+            transition_reason: self.transition_reason,
+            mode_time: self.mode_time(),
+            program: self.program,
+            registers: self.registers.clone(),
+            bottle_rate: Rate::from_bytes_per_sec(self.bottle_rate),
+            min_rtt_us: self.min_rtt_us,
+            srtt_us: self.srtt_us,
+            rttvar_us: self.rttvar_us,
+            rtt_jitter_us: self.rtt_jitter.spread_us(),
+            rate_outgoing: Rate::from_bytes_per_sec(self.rates.outgoing()),
+            rate_incoming: Rate::from_bytes_per_sec(self.rates.incoming()),
+            inflight_bytes: self.inflight_bytes,
+            mss: self.mss,
+            pacing_rate: Rate::from_bytes_per_sec(self.pacing_rate()),
+            estimated_bdp_bytes: self.estimated_bdp_bytes(),
+            buffer_depth: self.buffer_depth(),
+            pipe_full: self.pipe_full,
+            high_rtt: self.high_rtt,
+            app_limited: self.app_limited,
+            paused: self.paused,
+            rate_limit: self.rate_limit,
+            tenant: self.tenant.clone(),
+            degraded: self.degraded,
+            frozen: self.frozen,
+            short_flow: self.short_flow_left.is_some(),
+            reports: self.reports,
+            stale_reports: self.stale_reports,
+            outdated_reports: self.outdated_reports,
+            late_reports: self.late_reports,
+            stalled_reports: self.stalled_reports,
+            handling: self.handling,
+            install_latency_us: self.install_latency_us.unwrap_or_default() as u32,
+            probe_rtt_entries: self.probe_rtt_entries,
+            skipped_probe_rtts: self.skipped_probe_rtts,
+            loss_bursts: self.loss_bursts,
+            lossy_rtt_samples: self.lossy_rtt_samples,
+            probe_bw_cycles: self.probe_bw_cycles,
+            delivery_ratio: self.delivery_ratio,
+            delivery_gap_cycles: self.delivery_gap_cycles,
+            pulse_desyncs: self.pulse_desyncs,
+            program_installs: self.program_installs,
+            reinstalls: self.reinstalls,
+            failed_updates: self.failed_updates,
+            rejected_installs: self.rejected_installs,
+            loss_rate: self.loss_rate,
+            lost_packets: self.lost_packets,
+            acked_packets: self.acked_packets,
+        }
+    }
+
+    /// Percentiles of the time the flow took to handle its reports, as far as the host
+    /// recorded them with [`BbrCore::record_handling`].
+    pub fn handling_latency(&self) -> &LatencyHistogram {
+        &self.handling
+    }
+
+    /// Whether the flow runs the cwnd-only AIMD fallback because the datapath rejected
+    /// `probe_bw`, or kept failing its updates under `UpdateFailurePolicy::Degrade`.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// Whether the flow was left at its estimate under `UpdateFailurePolicy::Freeze`.
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn rate_limit(&self) -> Option<Rate> {
+        self.rate_limit
+    }
+}
+
+impl Drop for BbrCore {
+    fn drop(&mut self) {
+        let _entered = self.span.enter();
+        let mode_time = self.mode_time();
+        info!(
+            startup_us = mode_time.startup_us,
+            drain_us = mode_time.drain_us,
+            probe_bw_us = mode_time.probe_bw_us,
+            probe_rtt_us = mode_time.probe_rtt_us,
+            probe_rtt_fraction = mode_time.probe_rtt_fraction(),
+            probe_rtt_entries = self.probe_rtt_entries,
+            delivery_ratio = ?self.delivery_ratio,
+            delivery_gap_cycles = self.delivery_gap_cycles,
+            "flow ended"
+        );
+        self.weights.deregister(self.flow.sock_id);
+        self.groups.leave(self.group, self.flow.sock_id);
+        self.shutdown.deregister(self.flow.sock_id);
+        self.pauses.resume(self.flow.sock_id);
+        self.flow_limits.clear(self.flow.sock_id);
+        self.probe_schedule.cancel(self.flow.sock_id);
+        if let Some(tenant) = &self.tenant {
+            self.tenants.leave(tenant, self.flow.sock_id);
+        }
+        self.snapshots.remove(self.flow);
+    }
+}
+
+// the lower of two limits, either of which may be unset
+pub(crate) fn lower_limit(a: Option<Rate>, b: Option<Rate>) -> Option<Rate> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if b < a { b } else { a }),
+        (a, b) => a.or(b),
+    }
+}
+
+/// A datapath flow, run by a [`BbrCore`] unless the configuration leaves it to the datapath.
+pub struct Bbr<T: Ipc> {
+    control_channel: Datapath<T>,
+    sc: Scope,
+    /// `None` for flows the configuration leaves to the datapath.
+    core: Option<BbrCore>,
+}
+
+// portus only sends 32-bit register values, so rates past `u32::MAX` bytes per second,
+// about 34 Gbit/s, saturate on their way to its datapaths
+fn portus_fields(fields: &[(&'static str, u64)]) -> Vec<(&'static str, u32)> {
+    fields
+        .iter()
+        .map(|&(reg, val)| (reg, u32::try_from(val).unwrap_or(u32::MAX)))
+        .collect()
+}
+
+// a panic that unwinds through a flow leaves it to the datapath, as a shutdown would, rather
+// than pulsing at the rates last installed
+impl<T: Ipc> Drop for Bbr<T> {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            return;
+        }
+        // the panic may have left the flow's state, or state it shares, broken; panicking
+        // again here must not abort before the other flows are released
+        let _ = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            let Some(core) = &mut self.core else {
+                return;
+            };
+            let _entered = core.span().clone().entered();
+            warn!("agent panicked, releasing flow");
+            let actions = core.release_now(Instant::now());
+            self.apply(actions);
+        }));
+    }
+}
+
+impl<T: Ipc> Bbr<T> {
+    // as `BbrCore::limit_warning`; flows the configuration leaves to the datapath log them all
+    fn limit_warning(&self, message: &'static str, detail: &str) -> Option<u64> {
+        match &self.core {
+            Some(core) => core.limit_warning(message, detail, Instant::now()),
+            None => Some(0),
+        }
+    }
+
+    fn apply(&mut self, actions: Vec<Action>) {
+        for action in actions {
+            match action {
+                Action::SetProgram { program, fields } => {
+                    let fields = portus_fields(&fields);
+                    let fields = if fields.is_empty() {
+                        None
+                    } else {
+                        Some(&fields[..])
+                    };
+                    match self.control_channel.set_program(program, fields) {
+                        Ok(sc) => {
+                            self.sc = sc;
+                            if let Some(core) = &mut self.core {
+                                core.program_installed(self.sc.program_uid);
+                            }
+                        }
+                        Err(err) => {
+                            let err = BbrError::Install {
+                                program,
+                                reason: err.0,
+                            };
+                            if let Some(suppressed) =
+                                self.limit_warning("could not install program", &err.to_string())
+                            {
+                                warn!(%err, suppressed, "could not install program");
+                            }
+                            if let Some(core) = &mut self.core {
+                                core.program_rejected(program);
+                            }
+                            return;
+                        }
+                    }
+                }
+                Action::Update(update) => {
+                    let update = portus_fields(&update);
+                    if let Err(err) = self.control_channel.update_field(&self.sc, &update) {
+                        if let Some(suppressed) =
+                            self.limit_warning("Cwnd and rate update error", &format!("{:?}", err))
+                        {
+                            warn!(?err, suppressed, "Cwnd and rate update error");
+                        }
+                        if let Some(core) = &mut self.core {
+                            core.install_failed();
+                        }
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl BbrConfig {
+    /// Whether the flow runs BBR, rather than the datapath's own congestion control.
+    pub fn manages(&self, info: &DatapathInfo) -> bool {
+        self.flow_filters.is_empty() || self.flow_filters.iter().any(|f| f.matches(info))
+    }
+
+    /// The actions that leave a flow the configuration does not manage to the datapath: the
+    /// `aimd` program from the datapath's initial window, which grows and halves cwnd by
+    /// itself, and which nothing updates.
+    pub fn unmanaged_start(&self, info: &DatapathInfo) -> Vec<Action> {
+        vec![Action::SetProgram {
+            program: "aimd",
+            fields: vec![
+                ("Cwnd", u64::from(info.init_cwnd)),
+                ("aiBytes", u64::from(info.mss)),
+                (
+                    "minCwnd",
+                    u64::from(info.mss.saturating_mul(PROBE_RTT_CWND_PACKETS)),
+                ),
+            ],
+        }]
+    }
+}
+
+impl<T: Ipc> CongAlg<T> for BbrConfig {
+    type Flow = Bbr<T>;
+
+    fn name() -> &'static str {
+        "bbr"
+    }
+
+    fn datapath_programs(&self) -> HashMap<&'static str, String> {
+        self.programs()
+    }
+
+    fn new_flow(&self, control: Datapath<T>, info: DatapathInfo) -> Self::Flow {
+        if !self.manages(&info) {
+            info!(id = %FlowId::from(&info), "leaving flow to the datapath");
+            let mut s = Bbr {
+                control_channel: control,
+                sc: Scope::new(),
+                core: None,
+            };
+            s.apply(self.unmanaged_start(&info));
+            return s;
+        }
+
+        let core = BbrCore::new(self, &info, Instant::now());
+        let span = core.span().clone();
+        let _entered = span.enter();
+        let start = core.start();
+        let mut s = Bbr {
+            control_channel: control,
+            sc: Scope::new(),
+            core: Some(core),
+        };
+
+        s.apply(start);
+        s
+    }
+}
+
+impl<T: Ipc> portus::Flow for Bbr<T> {
+    fn on_report(&mut self, _sock_id: u32, m: Report) {
+        let received = Instant::now();
+        // the datapath runs unmanaged flows on its own, and their reports need no answer
+        let core = match &mut self.core {
+            Some(core) => core,
+            None => return,
+        };
+        let span = core.span().clone();
+        let _entered = span.enter();
+        let sc = &self.sc;
+        let actions = core.handle_report(Instant::now(), m.program_uid, Some(received), |field| {
+            m.get_field(field, sc).ok()
+        });
+        self.apply(actions);
+        if let Some(core) = &mut self.core {
+            core.record_handling(received.elapsed());
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Identifying flows in logs and snapshots.
+//!
+//! Every log line a flow emits is in a `flow` span that carries its [`FlowId`], so that the
+//! lines of concurrent flows can be told apart, and each flow's snapshot is keyed by it.
+
+use portus::DatapathInfo;
+use serde::Serialize;
+use std::fmt;
+use std::net::Ipv4Addr;
+
+/// A flow's socket id and 4-tuple.
+///
+/// Ordered by socket id first, which the datapath keeps unique among live flows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct FlowId {
+    pub sock_id: u32,
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub sport: u16,
+    pub dport: u16,
+}
+
+impl From<&DatapathInfo> for FlowId {
+    fn from(info: &DatapathInfo) -> Self {
+        FlowId {
+            sock_id: info.sock_id,
+            src: Ipv4Addr::from(info.src_ip),
+            dst: Ipv4Addr::from(info.dst_ip),
+            // the datapath widens ports to 32 bits
+            sport: info.src_port as u16,
+            dport: info.dst_port as u16,
+        }
+    }
+}
+
+/// `<sock_id> <src>:<sport>-><dst>:<dport>`, e.g. `7 10.0.0.1:40312->10.0.0.2:5201`.
+impl fmt::Display for FlowId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}:{}->{}:{}",
+            self.sock_id, self.src, self.sport, self.dst, self.dport
+        )
+    }
+}
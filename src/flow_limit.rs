@@ -0,0 +1,39 @@
+//! Rate limits set on individual flows while they run.
+//!
+//! An operator can throttle a single runaway transfer without touching the others: a limited
+//! flow paces, probes and sizes its windows as if `--max_rate` were the lower of the limit and
+//! its configured cap, until the limit is cleared. Unlike the configured cap, a limit leaves
+//! the flow's bottleneck rate estimate alone, so that the flow picks up where it was once
+//! released. Flows pick up a new limit on their next report.
+
+use crate::bandwidth::Rate;
+use crate::shard::ShardedMap;
+use std::collections::BTreeMap;
+
+/// The limits of the flows that have one, by socket id, shared by all flows of one
+/// `BbrConfig`.
+#[derive(Clone, Default)]
+pub struct FlowLimits {
+    limits: ShardedMap<u32, Rate>,
+}
+
+impl FlowLimits {
+    /// Returns the flow's previous limit.
+    pub fn set(&self, sock_id: u32, rate: Rate) -> Option<Rate> {
+        self.limits.insert(sock_id, rate)
+    }
+
+    /// Returns the flow's previous limit.
+    pub fn clear(&self, sock_id: u32) -> Option<Rate> {
+        self.limits.remove(&sock_id)
+    }
+
+    pub fn get(&self, sock_id: u32) -> Option<Rate> {
+        self.limits.get(&sock_id)
+    }
+
+    /// Every flow's limit, by socket id.
+    pub fn all(&self) -> BTreeMap<u32, Rate> {
+        self.limits.entries().into_iter().collect()
+    }
+}
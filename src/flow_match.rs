@@ -0,0 +1,159 @@
+//! Matching flows by their addresses and ports.
+
+use portus::DatapathInfo;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+/// Selects flows by one element of their 4-tuple.
+///
+/// Parsed from `sport=<port>[-<port>]`, `dport=<port>[-<port>]`, `src=<ipv4>[/<prefix
+/// length>]` or `dst=<ipv4>[/<prefix length>]`. Port ranges include both ends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlowMatch {
+    SrcPort(u16),
+    DstPort(u16),
+    SrcPorts(u16, u16),
+    DstPorts(u16, u16),
+    SrcNet { addr: u32, prefix_len: u8 },
+    DstNet { addr: u32, prefix_len: u8 },
+}
+
+impl FlowMatch {
+    pub fn matches(&self, info: &DatapathInfo) -> bool {
+        match *self {
+            FlowMatch::SrcPort(port) => info.src_port == u32::from(port),
+            FlowMatch::DstPort(port) => info.dst_port == u32::from(port),
+            FlowMatch::SrcPorts(lo, hi) => (u32::from(lo)..=u32::from(hi)).contains(&info.src_port),
+            FlowMatch::DstPorts(lo, hi) => (u32::from(lo)..=u32::from(hi)).contains(&info.dst_port),
+            FlowMatch::SrcNet { addr, prefix_len } => {
+                let mask = prefix_mask(prefix_len);
+                info.src_ip & mask == addr & mask
+            }
+            FlowMatch::DstNet { addr, prefix_len } => {
+                let mask = prefix_mask(prefix_len);
+                info.dst_ip & mask == addr & mask
+            }
+        }
+    }
+}
+
+/// Selects the flows that match every one of its elements.
+///
+/// Parsed from a comma-separated list of flow matches, e.g. `dst=10.1.0.0/16,dport=443`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlowFilter {
+    pub matches: Vec<FlowMatch>,
+}
+
+impl FlowFilter {
+    pub fn matches(&self, info: &DatapathInfo) -> bool {
+        self.matches.iter().all(|m| m.matches(info))
+    }
+}
+
+impl FromStr for FlowFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(|matches| FlowFilter { matches })
+    }
+}
+
+impl fmt::Display for FlowFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, m) in self.matches.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", m)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn prefix_mask(prefix_len: u8) -> u32 {
+    if prefix_len == 0 {
+        0
+    } else {
+        u32::MAX << (32 - u32::from(prefix_len))
+    }
+}
+
+impl FromStr for FlowMatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, val) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <key>=<value>: {:?}", s))?;
+        let parse_port = |v: &str| {
+            v.parse::<u16>()
+                .map_err(|e| format!("invalid port {:?}: {}", v, e))
+        };
+        let parse_ports = |v: &str| match v.split_once('-') {
+            Some((lo, hi)) => {
+                let (lo, hi) = (parse_port(lo)?, parse_port(hi)?);
+                if lo <= hi {
+                    Ok((lo, Some(hi)))
+                } else {
+                    Err(format!("empty port range: {:?}", v))
+                }
+            }
+            None => parse_port(v).map(|port| (port, None)),
+        };
+        match key {
+            "sport" => parse_ports(val).map(|ports| match ports {
+                (lo, Some(hi)) => FlowMatch::SrcPorts(lo, hi),
+                (port, None) => FlowMatch::SrcPort(port),
+            }),
+            "dport" => parse_ports(val).map(|ports| match ports {
+                (lo, Some(hi)) => FlowMatch::DstPorts(lo, hi),
+                (port, None) => FlowMatch::DstPort(port),
+            }),
+            "src" | "dst" => {
+                let (addr, prefix_len) = match val.split_once('/') {
+                    Some((addr, len)) => (
+                        addr,
+                        len.parse::<u8>()
+                            .ok()
+                            .filter(|l| *l <= 32)
+                            .ok_or_else(|| format!("invalid prefix length: {:?}", len))?,
+                    ),
+                    None => (val, 32),
+                };
+                let addr = addr
+                    .parse::<Ipv4Addr>()
+                    .map_err(|e| format!("invalid address {:?}: {}", addr, e))?;
+                let addr = u32::from(addr);
+                Ok(if key == "src" {
+                    FlowMatch::SrcNet { addr, prefix_len }
+                } else {
+                    FlowMatch::DstNet { addr, prefix_len }
+                })
+            }
+            _ => Err(format!("unknown flow match key {:?}", key)),
+        }
+    }
+}
+
+/// As parsed, e.g. `dport=8000-8099` or `dst=10.1.0.0/16`.
+impl fmt::Display for FlowMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            FlowMatch::SrcPort(port) => write!(f, "sport={}", port),
+            FlowMatch::DstPort(port) => write!(f, "dport={}", port),
+            FlowMatch::SrcPorts(lo, hi) => write!(f, "sport={}-{}", lo, hi),
+            FlowMatch::DstPorts(lo, hi) => write!(f, "dport={}-{}", lo, hi),
+            FlowMatch::SrcNet { addr, prefix_len } => {
+                write!(f, "src={}/{}", Ipv4Addr::from(addr), prefix_len)
+            }
+            FlowMatch::DstNet { addr, prefix_len } => {
+                write!(f, "dst={}/{}", Ipv4Addr::from(addr), prefix_len)
+            }
+        }
+    }
+}
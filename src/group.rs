@@ -0,0 +1,133 @@
+//! Grouping of local flows that likely share a bottleneck.
+//!
+//! Flows to the same destination prefix are assumed to share a bottleneck. The group records
+//! when its members last entered `PROBE_RTT`, so that the other members can follow within a
+//! short window and the bottleneck queue actually drains, and the newest `min_rtt` its
+//! members measured, so that the others can take it instead of measuring it themselves.
+//! It also knows when each member joined, so that a burst of flows starting together can be
+//! told apart from a single new flow.
+
+use crate::flow_match::prefix_mask;
+use crate::shard::ShardedMap;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub use crate::params::BOTTLENECK_GROUP_PREFIX_LEN;
+
+/// A `min_rtt` one of a group's flows measured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SharedMinRtt {
+    pub min_rtt_us: u32,
+    pub measured: Instant,
+    /// Whether it was measured by a `PROBE_RTT`, with the queue drained, rather than as a new
+    /// minimum sample. Only such a measurement may raise other flows' `min_rtt`.
+    pub probed: bool,
+}
+
+#[derive(Default)]
+struct GroupState {
+    /// When each member joined, by socket id.
+    members: HashMap<u32, Instant>,
+    last_probe_rtt: Option<Instant>,
+    min_rtt: Option<SharedMinRtt>,
+}
+
+/// Shared by all flows of one `BbrConfig`.
+#[derive(Clone)]
+pub struct BottleneckGroups {
+    prefix_len: u8,
+    groups: ShardedMap<u32, GroupState>,
+}
+
+impl Default for BottleneckGroups {
+    fn default() -> Self {
+        BottleneckGroups::new(BOTTLENECK_GROUP_PREFIX_LEN)
+    }
+}
+
+impl BottleneckGroups {
+    pub fn new(prefix_len: u8) -> Self {
+        BottleneckGroups {
+            prefix_len: prefix_len.min(32),
+            groups: Default::default(),
+        }
+    }
+
+    /// The length of the destination prefixes that group flows.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Adds the flow to the group of its destination and returns the group's key.
+    pub fn join(&self, dst_ip: u32, sock_id: u32, now: Instant) -> u32 {
+        let key = dst_ip & prefix_mask(self.prefix_len);
+        self.groups.with_shard(&key, |groups| {
+            groups.entry(key).or_default().members.insert(sock_id, now)
+        });
+        key
+    }
+
+    pub fn leave(&self, key: u32, sock_id: u32) {
+        self.groups.with_shard(&key, |groups| {
+            if let Some(group) = groups.get_mut(&key) {
+                group.members.remove(&sock_id);
+                if group.members.is_empty() {
+                    groups.remove(&key);
+                }
+            }
+        });
+    }
+
+    /// How many flows are in the group.
+    pub fn size(&self, key: u32) -> usize {
+        self.groups.with_shard(&key, |groups| {
+            groups.get(&key).map_or(0, |group| group.members.len())
+        })
+    }
+
+    /// How many flows in the group joined it within `window` of `now`.
+    pub fn joined_within(&self, key: u32, now: Instant, window: Duration) -> usize {
+        self.groups.with_shard(&key, |groups| {
+            groups.get(&key).map_or(0, |group| {
+                group
+                    .members
+                    .values()
+                    .filter(|&&joined| now.saturating_duration_since(joined) <= window)
+                    .count()
+            })
+        })
+    }
+
+    pub fn mark_probe_rtt(&self, key: u32, now: Instant) {
+        self.groups.with_shard(&key, |groups| {
+            if let Some(group) = groups.get_mut(&key) {
+                group.last_probe_rtt = Some(now);
+            }
+        });
+    }
+
+    /// When a member of the group last entered `PROBE_RTT`.
+    pub fn last_probe_rtt(&self, key: u32) -> Option<Instant> {
+        self.groups.with_shard(&key, |groups| {
+            groups.get(&key).and_then(|group| group.last_probe_rtt)
+        })
+    }
+
+    /// Offers a member's `min_rtt` measurement to the group, replacing any older one.
+    pub fn share_min_rtt(&self, key: u32, min_rtt: SharedMinRtt) {
+        self.groups.with_shard(&key, |groups| {
+            if let Some(group) = groups.get_mut(&key) {
+                if !matches!(group.min_rtt, Some(newest) if newest.measured > min_rtt.measured) {
+                    group.min_rtt = Some(min_rtt);
+                }
+            }
+        });
+    }
+
+    /// The newest `min_rtt` a member of the group measured.
+    pub fn shared_min_rtt(&self, key: u32) -> Option<SharedMinRtt> {
+        self.groups.with_shard(&key, |groups| {
+            groups.get(&key).and_then(|group| group.min_rtt)
+        })
+    }
+}
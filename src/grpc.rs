@@ -0,0 +1,345 @@
+//! A gRPC service for observing and steering the agent, for external controllers such as a
+//! central bandwidth arbiter.
+//!
+//! `--grpc_listen <addr>` serves the `Agent` service of `proto/bbr.proto` from a thread of its
+//! own: `ListFlows` and `GetFlowSnapshot` return the flows' snapshots, `StreamEvents` streams
+//! flows starting, changing mode, changing their pacing rate past a threshold and ending, and
+//! `SetParameter` and `SetFlowLimit` change a flow's parameters the way the control socket's
+//! commands do. `SetParameter` takes `paused`, `max_rate` and `weight`. Build with
+//! `--features grpc`, which needs `protoc`.
+
+use crate::bandwidth::Rate;
+use crate::control::{Command, Control};
+use crate::max_rate::parse_max_rate;
+use crate::rate_hint::rate_moved;
+use crate::snapshot::FlowSnapshot;
+use crate::transport::Transport;
+use crate::weight::parse_weight;
+use crate::{BbrMode, TransitionReason};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("ccp_bbr");
+}
+
+use proto::agent_server::{Agent, AgentServer};
+use proto::event::Kind;
+
+/// How often `StreamEvents` checks for changes, unless the client asks otherwise.
+pub const DEFAULT_EVENT_INTERVAL_MS: u32 = 100;
+
+fn proto_mode(mode: BbrMode) -> proto::Mode {
+    match mode {
+        BbrMode::Startup => proto::Mode::Startup,
+        BbrMode::Drain => proto::Mode::Drain,
+        BbrMode::ProbeBw => proto::Mode::ProbeBw,
+        BbrMode::ProbeRtt => proto::Mode::ProbeRtt,
+    }
+}
+
+fn proto_transition(reason: Option<TransitionReason>) -> proto::TransitionReason {
+    match reason {
+        None => proto::TransitionReason::None,
+        Some(TransitionReason::MinRttExpired) => proto::TransitionReason::MinRttExpired,
+        Some(TransitionReason::FullBwReached) => proto::TransitionReason::FullBwReached,
+        Some(TransitionReason::InflightDrained) => proto::TransitionReason::InflightDrained,
+        Some(TransitionReason::LossLimit) => proto::TransitionReason::LossLimit,
+        Some(TransitionReason::ProbeRttDone) => proto::TransitionReason::ProbeRttDone,
+        Some(TransitionReason::Manual) => proto::TransitionReason::Manual,
+    }
+}
+
+fn proto_snapshot(ipc: &str, flow: &FlowSnapshot) -> proto::FlowSnapshot {
+    proto::FlowSnapshot {
+        ipc: String::from(ipc),
+        id: Some(proto::FlowId {
+            sock_id: flow.id.sock_id,
+            src: flow.id.src.to_string(),
+            dst: flow.id.dst.to_string(),
+            sport: u32::from(flow.id.sport),
+            dport: u32::from(flow.id.dport),
+        }),
+        mode: proto_mode(flow.mode) as i32,
+        transition_reason: proto_transition(flow.transition_reason) as i32,
+        program: String::from(flow.program),
+        registers: flow
+            .registers
+            .iter()
+            .map(|(&reg, &val)| (String::from(reg), val))
+            .collect(),
+        bottle_rate_bps: flow.bottle_rate.bytes_per_sec() * 8.0,
+        min_rtt_us: flow.min_rtt_us,
+        srtt_us: flow.srtt_us,
+        rttvar_us: flow.rttvar_us,
+        rtt_jitter_us: flow.rtt_jitter_us,
+        rate_outgoing_bps: flow.rate_outgoing.bytes_per_sec() * 8.0,
+        rate_incoming_bps: flow.rate_incoming.bytes_per_sec() * 8.0,
+        inflight_bytes: flow.inflight_bytes,
+        mss: flow.mss,
+        pacing_rate_bps: flow.pacing_rate.bytes_per_sec() * 8.0,
+        estimated_bdp_bytes: flow.estimated_bdp_bytes,
+        buffer_bytes: flow.buffer_depth.map_or(0, |depth| depth.bytes),
+        buffer_bdp: flow.buffer_depth.map_or(0.0, |depth| depth.bdp),
+        buffer_overflowed: flow.buffer_depth.is_some_and(|depth| depth.overflowed),
+        pipe_full: flow.pipe_full,
+        high_rtt: flow.high_rtt,
+        app_limited: flow.app_limited,
+        paused: flow.paused,
+        degraded: flow.degraded,
+        frozen: flow.frozen,
+        short_flow: flow.short_flow,
+        reports: flow.reports,
+        stale_reports: flow.stale_reports,
+        outdated_reports: flow.outdated_reports,
+        late_reports: flow.late_reports,
+        stalled_reports: flow.stalled_reports,
+        handling_p50_us: flow.handling.percentile_us(0.5),
+        handling_p90_us: flow.handling.percentile_us(0.9),
+        handling_p99_us: flow.handling.percentile_us(0.99),
+        install_latency_us: flow.install_latency_us,
+        probe_rtt_entries: flow.probe_rtt_entries,
+        startup_time_us: flow.mode_time.startup_us,
+        drain_time_us: flow.mode_time.drain_us,
+        probe_bw_time_us: flow.mode_time.probe_bw_us,
+        probe_rtt_time_us: flow.mode_time.probe_rtt_us,
+        skipped_probe_rtts: flow.skipped_probe_rtts,
+        loss_bursts: flow.loss_bursts,
+        lossy_rtt_samples: flow.lossy_rtt_samples,
+        probe_bw_cycles: flow.probe_bw_cycles,
+        delivery_ratio: flow.delivery_ratio.unwrap_or_default(),
+        delivery_gap_cycles: flow.delivery_gap_cycles,
+        pulse_desyncs: flow.pulse_desyncs,
+        program_installs: flow.program_installs,
+        reinstalls: flow.reinstalls,
+        failed_updates: flow.failed_updates,
+        rejected_installs: flow.rejected_installs,
+        rate_limit_bps: flow
+            .rate_limit
+            .map_or(0.0, |limit| limit.bytes_per_sec() * 8.0),
+        tenant: flow.tenant.clone().unwrap_or_default(),
+        loss_rate: flow.loss_rate,
+        lost_packets: flow.lost_packets,
+        acked_packets: flow.acked_packets,
+    }
+}
+
+fn event(kind: Kind, ipc: &str, flow: &FlowSnapshot) -> proto::Event {
+    proto::Event {
+        kind: kind as i32,
+        flow: Some(proto_snapshot(ipc, flow)),
+    }
+}
+
+/// Serves the flows of the agent's transports.
+#[derive(Clone)]
+pub struct AgentService {
+    transports: Vec<Transport>,
+    control: Control,
+}
+
+impl AgentService {
+    pub fn new(transports: Vec<Transport>) -> Self {
+        AgentService {
+            control: Control::new(transports.clone()),
+            transports,
+        }
+    }
+
+    // the events between the flows last seen, with the pacing rate each last had an event
+    // for, and the current ones, which replace them. A zero threshold has no rate events.
+    fn changes(
+        &self,
+        seen: &mut HashMap<(String, u32), (FlowSnapshot, Rate)>,
+        rate_change_threshold: f64,
+    ) -> Vec<proto::Event> {
+        let mut events = vec![];
+        let mut current = HashMap::new();
+        for transport in &self.transports {
+            for flow in transport.cfg.snapshots.flows() {
+                let key = (transport.ipc.clone(), flow.id.sock_id);
+                let mut hinted = flow.pacing_rate;
+                match seen.remove(&key) {
+                    // a reused socket id is a new flow
+                    Some((last, last_rate)) if last.id == flow.id => {
+                        if last.mode != flow.mode {
+                            events.push(event(Kind::ModeChanged, &transport.ipc, &flow));
+                        }
+                        if rate_change_threshold > 0.0
+                            && rate_moved(last_rate, flow.pacing_rate, rate_change_threshold)
+                        {
+                            events.push(event(Kind::RateChanged, &transport.ipc, &flow));
+                        } else {
+                            hinted = last_rate;
+                        }
+                    }
+                    Some((last, _)) => {
+                        events.push(event(Kind::FlowEnded, &transport.ipc, &last));
+                        events.push(event(Kind::FlowStarted, &transport.ipc, &flow));
+                    }
+                    None => events.push(event(Kind::FlowStarted, &transport.ipc, &flow)),
+                }
+                current.insert(key, (flow, hinted));
+            }
+        }
+        for ((ipc, _), (last, _)) in seen.drain() {
+            events.push(event(Kind::FlowEnded, &ipc, &last));
+        }
+        *seen = current;
+        events
+    }
+
+    /// Serves on `addr` from a thread of its own, with a runtime of its own.
+    pub fn serve(self, addr: SocketAddr) -> std::io::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        info!(%addr, "serving gRPC");
+        std::thread::Builder::new()
+            .name(String::from("bbr-grpc"))
+            .spawn(move || {
+                let served = runtime.block_on(
+                    tonic::transport::Server::builder()
+                        .add_service(AgentServer::new(self))
+                        .serve(addr),
+                );
+                if let Err(err) = served {
+                    warn!(?err, %addr, "gRPC server stopped");
+                }
+            })?;
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Agent for AgentService {
+    async fn list_flows(
+        &self,
+        _request: Request<proto::ListFlowsRequest>,
+    ) -> Result<Response<proto::ListFlowsResponse>, Status> {
+        let flows = self
+            .transports
+            .iter()
+            .flat_map(|t| {
+                t.cfg
+                    .snapshots
+                    .flows()
+                    .into_iter()
+                    .map(move |flow| proto_snapshot(&t.ipc, &flow))
+            })
+            .collect();
+        Ok(Response::new(proto::ListFlowsResponse { flows }))
+    }
+
+    async fn get_flow_snapshot(
+        &self,
+        request: Request<proto::GetFlowSnapshotRequest>,
+    ) -> Result<Response<proto::FlowSnapshot>, Status> {
+        let request = request.into_inner();
+        self.transports
+            .iter()
+            .filter(|t| request.ipc.is_empty() || t.ipc == request.ipc)
+            .find_map(|t| {
+                t.cfg
+                    .snapshots
+                    .flows()
+                    .iter()
+                    .find(|flow| flow.id.sock_id == request.sock_id)
+                    .map(|flow| proto_snapshot(&t.ipc, flow))
+            })
+            .map(Response::new)
+            .ok_or_else(|| Status::not_found(format!("no flow with socket id {}", request.sock_id)))
+    }
+
+    type StreamEventsStream = ReceiverStream<Result<proto::Event, Status>>;
+
+    async fn stream_events(
+        &self,
+        request: Request<proto::StreamEventsRequest>,
+    ) -> Result<Response<Self::StreamEventsStream>, Status> {
+        let request = request.into_inner();
+        let interval_ms = match request.interval_ms {
+            0 => DEFAULT_EVENT_INTERVAL_MS,
+            ms => ms,
+        };
+        let rate_change_threshold = request.rate_change_threshold;
+        let (tx, rx) = mpsc::channel(64);
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut seen = HashMap::new();
+            let mut ticks = tokio::time::interval(Duration::from_millis(u64::from(interval_ms)));
+            loop {
+                ticks.tick().await;
+                for event in service.changes(&mut seen, rate_change_threshold) {
+                    // the client has gone away
+                    if tx.send(Ok(event)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn set_parameter(
+        &self,
+        request: Request<proto::SetParameterRequest>,
+    ) -> Result<Response<proto::SetParameterResponse>, Status> {
+        let request = request.into_inner();
+        let command = match (request.name.as_str(), request.value.as_str()) {
+            ("paused", "true") => Command::Pause(request.sock_id),
+            ("paused", "false") => Command::Resume(request.sock_id),
+            ("paused", value) => {
+                return Err(Status::invalid_argument(format!(
+                    "paused must be true or false: {:?}",
+                    value
+                )))
+            }
+            ("max_rate", "" | "none") => Command::Unlimit(request.sock_id),
+            ("max_rate", value) => Command::Limit(
+                request.sock_id,
+                parse_max_rate(value).map_err(Status::invalid_argument)?,
+            ),
+            ("weight", value) => Command::Weight(
+                request.sock_id,
+                parse_weight(value).map_err(Status::invalid_argument)?,
+            ),
+            (name, _) => {
+                return Err(Status::invalid_argument(format!(
+                    "unknown parameter: {:?}",
+                    name
+                )))
+            }
+        };
+        self.control.execute(command).map_err(Status::not_found)?;
+        Ok(Response::new(proto::SetParameterResponse {}))
+    }
+
+    async fn set_flow_limit(
+        &self,
+        request: Request<proto::SetFlowLimitRequest>,
+    ) -> Result<Response<proto::SetFlowLimitResponse>, Status> {
+        let request = request.into_inner();
+        let command = if request.max_rate_bps == 0.0 {
+            Command::Unlimit(request.sock_id)
+        } else if request.max_rate_bps > 0.0 && request.max_rate_bps.is_finite() {
+            Command::Limit(
+                request.sock_id,
+                Rate::from_bytes_per_sec(request.max_rate_bps / 8.0),
+            )
+        } else {
+            return Err(Status::invalid_argument(format!(
+                "max_rate_bps must be positive, or zero to clear the limit: {}",
+                request.max_rate_bps
+            )));
+        };
+        self.control.execute(command).map_err(Status::not_found)?;
+        Ok(Response::new(proto::SetFlowLimitResponse {}))
+    }
+}
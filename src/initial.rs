@@ -0,0 +1,85 @@
+//! The path model new flows start from.
+//!
+//! Without a learned estimate in the path cache, a flow assumes a 1 Mbit/s bottleneck and a
+//! 1 s min RTT, which any measurement replaces, but which makes the first seconds of a flow on
+//! a fast path far too conservative. Configured seeds replace these defaults, and since they
+//! describe the whole path, they also size the flow's first window to one BDP.
+
+use crate::bandwidth::{parse_rate, Rate};
+use crate::duration::{format_duration, parse_duration};
+use crate::flow_match::FlowMatch;
+use portus::DatapathInfo;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub use crate::params::{DEFAULT_INITIAL_RATE, DEFAULT_INITIAL_RTT};
+
+/// Parses a rate; bare numbers are Mbit/s.
+pub fn parse_initial_rate(s: &str) -> Result<Rate, String> {
+    parse_rate(s, Rate::from_mbps(1.0))
+}
+
+/// Parses a min RTT; bare numbers are milliseconds.
+pub fn parse_rtt(s: &str) -> Result<Duration, String> {
+    parse_duration(s, Duration::from_millis(1)).and_then(|rtt| {
+        if rtt.is_zero() {
+            Err(format!("rtt must be positive: {:?}", s))
+        } else {
+            Ok(rtt)
+        }
+    })
+}
+
+/// A bottleneck rate and min RTT to start the flows selected by `flow` from.
+///
+/// Parsed from and displayed as `<flow match>:<rate>:<rtt>`, e.g. `dst=10.1.0.0/16:1Gbps:2ms`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InitialPathRule {
+    pub flow: FlowMatch,
+    pub rate: Rate,
+    pub rtt: Duration,
+}
+
+impl FromStr for InitialPathRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.rsplitn(3, ':');
+        let (rtt, rate, flow) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(rtt), Some(rate), Some(flow)) => (rtt, rate, flow),
+            _ => return Err(format!("expected <flow match>:<rate>:<rtt>: {:?}", s)),
+        };
+        Ok(InitialPathRule {
+            flow: flow.parse()?,
+            rate: parse_initial_rate(rate)?,
+            rtt: parse_rtt(rtt)?,
+        })
+    }
+}
+
+impl fmt::Display for InitialPathRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.flow,
+            self.rate,
+            format_duration(self.rtt)
+        )
+    }
+}
+
+/// The configured rate and min RTT for a flow: those of the first matching rule, or the
+/// defaults given for all flows.
+pub fn initial_path_for(
+    rules: &[InitialPathRule],
+    rate: Option<Rate>,
+    rtt: Option<Duration>,
+    info: &DatapathInfo,
+) -> (Option<Rate>, Option<Duration>) {
+    match rules.iter().find(|r| r.flow.matches(info)) {
+        Some(rule) => (Some(rule.rate), Some(rule.rtt)),
+        None => (rate, rtt),
+    }
+}
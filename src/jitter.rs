@@ -0,0 +1,35 @@
+//! The variability of a flow's RTT.
+//!
+//! On jittery last-mile links, the RTT of most packets sits well above the path's min RTT,
+//! so a BDP computed from the min RTT alone undersizes the window. The spread between the
+//! 10th and 90th percentiles of recent reports' RTTs measures that jitter.
+
+use std::collections::VecDeque;
+
+pub use crate::params::{RTT_JITTER_MIN_SAMPLES, RTT_JITTER_SAMPLES};
+
+#[derive(Clone, Debug, Default)]
+pub struct RttJitter {
+    samples: VecDeque<u32>,
+}
+
+impl RttJitter {
+    pub fn record(&mut self, rtt_us: u32) {
+        if self.samples.len() == RTT_JITTER_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rtt_us);
+    }
+
+    /// The p10 to p90 spread of the recorded RTTs, in microseconds.
+    pub fn spread_us(&self) -> u32 {
+        if self.samples.len() < RTT_JITTER_MIN_SAMPLES {
+            return 0;
+        }
+
+        let mut sorted: Vec<u32> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let percentile = |p: usize| sorted[(sorted.len() - 1) * p / 100];
+        percentile(90) - percentile(10)
+    }
+}
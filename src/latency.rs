@@ -0,0 +1,69 @@
+//! How long flows take to handle their reports.
+//!
+//! A report is handled once the actions it led to have been written to the datapath. The time
+//! that takes bounds how many flows one agent can drive: reports that take about as long to
+//! handle as the datapath takes to send them queue up behind each other. Each flow keeps a
+//! histogram of its handling times, in power-of-two buckets of microseconds, which its snapshot
+//! shows as percentiles. A transport's stats merge the histograms of its current flows.
+
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use std::time::Duration;
+
+/// Bucket `i` counts handling times from 2^i up to 2^(i+1) microseconds; the last one also
+/// counts everything longer, from about 8 seconds.
+const BUCKETS: usize = 24;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; BUCKETS],
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let us = latency.as_micros().clamp(1, u128::from(u64::MAX)) as u64;
+        let bucket = (us.ilog2() as usize).min(BUCKETS - 1);
+        self.buckets[bucket] += 1;
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (mine, theirs) in self.buckets.iter_mut().zip(other.buckets) {
+            *mine += theirs;
+        }
+    }
+
+    /// Handling times recorded.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// The handling time that the fraction `q` of those recorded take at most, rounded up to
+    /// the end of its bucket, so within a factor of two. Zero before any was recorded.
+    pub fn percentile_us(&self, q: f64) -> u64 {
+        let count = self.count();
+        if count == 0 {
+            return 0;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return 1 << (bucket + 1);
+            }
+        }
+        1 << BUCKETS
+    }
+}
+
+/// As `count`, `p50_us`, `p90_us` and `p99_us`.
+impl Serialize for LatencyHistogram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("LatencyHistogram", 4)?;
+        s.serialize_field("count", &self.count())?;
+        s.serialize_field("p50_us", &self.percentile_us(0.5))?;
+        s.serialize_field("p90_us", &self.percentile_us(0.9))?;
+        s.serialize_field("p99_us", &self.percentile_us(0.99))?;
+        s.end()
+    }
+}
@@ -0,0 +1,132 @@
+//! How much a flow logs at the info level.
+//!
+//! By default every report logs a line, which at a few reports per PROBE_BW cycle and
+//! thousands of flows is more than anyone reads. A flow can instead log one summary per pulse
+//! cycle, or only its mode changes. Mode changes, estimates that jump, and warnings are logged
+//! at every granularity.
+
+use crate::bandwidth::Rate;
+use serde::Serialize;
+use std::str::FromStr;
+use tracing::info;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogGranularity {
+    /// A line for every report.
+    #[default]
+    Report,
+    /// A summary of every PROBE_BW pulse cycle, instead of its reports.
+    Cycle,
+    /// No line per report or cycle, only mode changes and what every granularity logs.
+    Transition,
+}
+
+impl FromStr for LogGranularity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "report" => Ok(LogGranularity::Report),
+            "cycle" => Ok(LogGranularity::Cycle),
+            "transition" => Ok(LogGranularity::Transition),
+            _ => Err(format!(
+                "log granularity must be one of (report|cycle|transition): {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// What the reports of one PROBE_BW pulse cycle saw.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CycleSummary {
+    pub reports: u32,
+    /// Bytes per second, summed over the reports that sampled a rate.
+    rate_sum: f64,
+    rate_samples: u32,
+    pub min_rtt_us: u32,
+    pub loss: u64,
+    pub acked: u64,
+    /// Bytes per second delivered and paced at, summed over the reports that were not held
+    /// back by the application or the receiver.
+    delivered_sum: f64,
+    paced_sum: f64,
+}
+
+impl Default for CycleSummary {
+    fn default() -> Self {
+        CycleSummary {
+            reports: 0,
+            rate_sum: 0.0,
+            rate_samples: 0,
+            min_rtt_us: u32::MAX,
+            loss: 0,
+            acked: 0,
+            delivered_sum: 0.0,
+            paced_sum: 0.0,
+        }
+    }
+}
+
+impl CycleSummary {
+    /// Adds a report; `rate` is zero if it did not sample one, and `min_rtt_us` is the
+    /// report's minimum RTT, or `NO_RTT_SAMPLE`.
+    pub fn record(&mut self, rate: f64, min_rtt_us: u32, loss: u32, acked: u32) {
+        self.reports += 1;
+        if rate > 0.0 {
+            self.rate_sum += rate;
+            self.rate_samples += 1;
+        }
+        self.min_rtt_us = self.min_rtt_us.min(min_rtt_us);
+        self.loss += u64::from(loss);
+        self.acked += u64::from(acked);
+    }
+
+    /// Adds what a report that was not held back delivered, and the pacing rate it was sent
+    /// at, both in bytes per second.
+    pub fn record_delivery(&mut self, delivered: f64, paced: f64) {
+        self.delivered_sum += delivered;
+        self.paced_sum += paced;
+    }
+
+    /// What the cycle's reports delivered over what they paced at, if any of them recorded
+    /// a delivery.
+    pub fn delivery_ratio(&self) -> Option<f64> {
+        (self.paced_sum > 0.0).then(|| self.delivered_sum / self.paced_sum)
+    }
+
+    /// The mean of the rates the cycle's reports sampled, in bytes per second.
+    pub fn avg_rate(&self) -> f64 {
+        if self.rate_samples == 0 {
+            0.0
+        } else {
+            self.rate_sum / f64::from(self.rate_samples)
+        }
+    }
+
+    /// The fraction of packets lost, among those the cycle's reports acked or lost.
+    pub fn loss_rate(&self) -> f64 {
+        let packets = self.loss + self.acked;
+        if packets == 0 {
+            0.0
+        } else {
+            self.loss as f64 / packets as f64
+        }
+    }
+
+    /// Logs the summary of cycle number `cycle`, in the flow's span.
+    pub fn log(&self, cycle: u64, bottle_rate: f64) {
+        info!(
+            cycle,
+            reports = self.reports,
+            avg_rate = %Rate::from_bytes_per_sec(self.avg_rate()),
+            min_rtt_us = self.min_rtt_us,
+            loss = self.loss,
+            loss_rate = self.loss_rate(),
+            delivery_ratio = ?self.delivery_ratio(),
+            bottle_rate = %Rate::from_bytes_per_sec(bottle_rate),
+            "PROBE_BW cycle"
+        );
+    }
+}
@@ -0,0 +1,115 @@
+//! How PROBE_BW reacts to reported losses.
+//!
+//! On wireless links, losses come from the radio as often as from a full queue, and backing
+//! off on every one of them keeps the flow far below the link rate. A full queue also inflates
+//! the RTT, so the lossy-link mode only treats a loss as congestion when the report's minimum
+//! RTT sits well above the path's, like Vegas telling a queue from the base RTT. How far above
+//! is configurable: the queue a path's buffers allow varies more than its random loss.
+//!
+//! The kernel datapath's `Ack.lost_pkts_sample` also counts packets that were only reordered,
+//! and that a later ack reports as `Ack.packets_misordered`. So that reordering does not look
+//! like congestion, the programs can hold sampled losses back until enough further acks have
+//! arrived without acking them out of order, much like RACK's reordering window.
+//!
+//! Whatever the mode, a report that lost a large part of the window at once means the path
+//! broke rather than filled, e.g. after a route change or a radio fade. Lowering the estimate
+//! a little per report would take many round trips to catch up, so such a burst halves cwnd
+//! right away and holds it there, without probing up, for a few pulse cycles.
+
+use serde::Serialize;
+use std::str::FromStr;
+
+pub use crate::params::{
+    LOSS_BACKOFF, LOSS_BURST_FRACTION, LOSS_BURST_HOLD_CYCLES, LOSS_BURST_MIN_PACKETS,
+    LOSS_RTT_INFLATION, REORDER_WINDOW_ACKS,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LossMode {
+    /// Losses do not change the model.
+    #[default]
+    Ignore,
+    /// Every report with losses lowers the bandwidth estimate.
+    Congestion,
+    /// Only reports with losses and an inflated RTT lower the bandwidth estimate.
+    Lossy,
+}
+
+impl LossMode {
+    /// Whether a report with `loss` lost packets and a minimum RTT of `minrtt_us` indicates
+    /// congestion on a path with a minimum RTT of `path_min_rtt_us`. In the lossy-link mode,
+    /// the report's RTT has to exceed the path's by more than the factor `rtt_inflation`.
+    pub fn is_congestion(
+        self,
+        loss: u32,
+        minrtt_us: u32,
+        path_min_rtt_us: u32,
+        rtt_inflation: f64,
+    ) -> bool {
+        if loss == 0 {
+            return false;
+        }
+
+        match self {
+            LossMode::Ignore => false,
+            LossMode::Congestion => true,
+            LossMode::Lossy => f64::from(minrtt_us) > f64::from(path_min_rtt_us) * rtt_inflation,
+        }
+    }
+}
+
+/// Whether a report that lost `loss` packets and acked `acked` lost a burst, that is, at least
+/// the fraction `fraction` of them, and no fewer than `LOSS_BURST_MIN_PACKETS`.
+pub fn is_loss_burst(loss: u32, acked: u32, fraction: f64) -> bool {
+    loss >= LOSS_BURST_MIN_PACKETS
+        && f64::from(loss) >= fraction * (f64::from(loss) + f64::from(acked))
+}
+
+impl FromStr for LossMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(LossMode::Ignore),
+            "congestion" => Ok(LossMode::Congestion),
+            "lossy" => Ok(LossMode::Lossy),
+            _ => Err(format!(
+                "loss mode must be one of (ignore|congestion|lossy): {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// How the kernel programs turn loss samples into `Report.loss`. Datapaths that do not sample
+/// losses report none either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LossAccounting {
+    /// Every sampled loss, reordered packets included.
+    Raw,
+    /// Sampled losses less the packets acked out of order later in the same report.
+    Net,
+    /// Sampled losses less the packets acked out of order since, once `REORDER_WINDOW_ACKS`
+    /// more acks, or a retransmission timeout, confirm them. Losses still unconfirmed at a
+    /// report count in a later one.
+    #[default]
+    Windowed,
+}
+
+impl FromStr for LossAccounting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(LossAccounting::Raw),
+            "net" => Ok(LossAccounting::Net),
+            "windowed" => Ok(LossAccounting::Windowed),
+            _ => Err(format!(
+                "loss accounting must be one of (raw|net|windowed): {:?}",
+                s
+            )),
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! Ceilings on the rate flows send at, for metered or contractually capped links.
+//!
+//! A capped flow's bottleneck rate estimate never exceeds the cap, and neither do the rates
+//! it probes with: it stays at the cap instead of pulsing above it. On a policed path, this
+//! also keeps the flow from learning the rate of the bursts the policer lets through.
+
+use crate::bandwidth::{parse_rate, Rate};
+use crate::flow_match::FlowMatch;
+use portus::DatapathInfo;
+use std::fmt;
+use std::str::FromStr;
+
+/// Parses a cap; bare numbers are Mbit/s.
+pub fn parse_max_rate(s: &str) -> Result<Rate, String> {
+    parse_rate(s, Rate::from_mbps(1.0))
+}
+
+/// Caps the flows selected by `flow` at `rate`.
+///
+/// Parsed from and displayed as `<flow match>:<rate>`, e.g. `dst=10.2.0.0/16:200Mbps`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MaxRateRule {
+    pub flow: FlowMatch,
+    pub rate: Rate,
+}
+
+impl FromStr for MaxRateRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (flow, rate) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected <flow match>:<rate>: {:?}", s))?;
+        Ok(MaxRateRule {
+            flow: flow.parse()?,
+            rate: parse_max_rate(rate)?,
+        })
+    }
+}
+
+impl fmt::Display for MaxRateRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.flow, self.rate)
+    }
+}
+
+/// The cap of the first matching rule, or the one given for all flows.
+pub fn max_rate_for(
+    rules: &[MaxRateRule],
+    rate: Option<Rate>,
+    info: &DatapathInfo,
+) -> Option<Rate> {
+    rules
+        .iter()
+        .find(|r| r.flow.matches(info))
+        .map_or(rate, |r| Some(r.rate))
+}
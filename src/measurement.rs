@@ -0,0 +1,190 @@
+//! The fields of the datapath's reports, as the control logic reads them.
+
+use crate::error::BbrError;
+use crate::params::PROGRAM_VERSION;
+use crate::{BbrMode, PulsePhase};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// One datapath report as a transport other than portus delivers it, for
+/// [`BbrCore::handle_report_values`](crate::BbrCore::handle_report_values): the uid of the
+/// program instance that sent it, and its fields by the names the programs give them, such as
+/// `Report.minrtt`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReportValues {
+    pub program_uid: u32,
+    pub fields: HashMap<String, u64>,
+    /// When the report reached the agent, if it may have waited before being handled; see
+    /// `BbrConfig::max_report_age`.
+    pub received: Option<Instant>,
+}
+
+/// `Measurement::minrtt_us` of a report that saw no RTT sample, whose `Report.minrtt` is
+/// still at its `+infinity` initial value.
+pub const NO_RTT_SAMPLE: u32 = u32::MAX;
+
+/// The fields of one datapath report.
+///
+/// `probe_rtt` only reports `minrtt_us`; the other fields are left at zero for its reports.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Measurement {
+    /// The uid of the program instance that sent the report.
+    pub program_uid: u32,
+    /// `NO_RTT_SAMPLE` if the report saw no RTT sample.
+    pub minrtt_us: u32,
+    /// Packets lost since the last report.
+    pub loss: u32,
+    /// Packets acked since the last report. Zero from datapaths that do not sample losses.
+    pub acked: u32,
+    /// Packets acked out of order since the last report. Unless `LossAccounting::Raw` takes
+    /// them out, reordered packets also show up in `loss`.
+    pub misordered: u32,
+    /// Whether a retransmission timeout fired since the last report, so the tail of a flight
+    /// was lost.
+    pub timeout: bool,
+    /// The highest sending rate since the last report, in bytes per second.
+    pub rate_outgoing: f64,
+    /// The highest receiving rate since the last report, in bytes per second.
+    pub rate_incoming: f64,
+    /// The bytes acked since the last report over the time since, in bytes per second. Only
+    /// programs that report it next to `rate_outgoing` and `rate_incoming` set it.
+    pub delivery_rate: f64,
+    /// The `probe_bw` pulse phase the report ends; see [`Measurement::pulse_phase`].
+    pub pulse_state: u32,
+    /// Whether the flow had data to send but kept less than half a paced BDP in flight, so
+    /// that `rate` measures the peer's receive window rather than the path.
+    pub receiver_limited: bool,
+    /// The most bytes in flight since the last report. Only `probe_bw` reports it.
+    pub inflight_bytes: u32,
+    /// The program's smoothed RTT, averaged like TCP's sRTT over every RTT sample since the
+    /// program was installed.
+    pub srtt_us: u32,
+    /// The program's smoothed mean deviation of the RTT samples from `srtt_us`.
+    pub rttvar_us: u32,
+    /// The highest delivery rate of the last `bw_window` rounds, in bytes per second,
+    /// as `probe_bw`'s bandwidth filter measured them. Zero from the other programs.
+    pub max_rate: f64,
+    /// Ack events since the last report. Only `probe_bw` counts them; zero means uncounted.
+    pub acks: u32,
+    /// The last `updateNonce` the program saw, and the microseconds from its first ack with it
+    /// to the report. Only `probe_bw` reports them, with `pulse_shift`.
+    pub nonce: u32,
+    pub nonce_age_us: u32,
+    /// The bytes acked since the program was installed, and the microseconds since its last
+    /// report, or since it was installed, that the report covers. Every program but
+    /// `probe_rtt` reports them, whatever the rate estimator; see
+    /// [`Measurement::recomputed_rate`].
+    pub delivered_total: u64,
+    pub span_us: u32,
+    /// The most bytes per packet an ack acked since the last report, which is the MSS if any
+    /// of them acked a full-sized packet. Only programs on kernel datapaths report it; zero
+    /// means unreported.
+    pub mss: u32,
+    /// When the report reached the agent, if it may have waited before being handled; see
+    /// `BbrConfig::max_report_age`.
+    pub received: Option<Instant>,
+}
+
+impl Measurement {
+    /// The gain phase `pulse_state` says the report's samples come from. `probe_bw` sends a
+    /// report at the end of each phase, with the state of the phase that ended.
+    pub fn pulse_phase(&self) -> PulsePhase {
+        match self.pulse_state {
+            0 | 3 => PulsePhase::Up,
+            1 => PulsePhase::Down,
+            2 => PulsePhase::Cruise,
+            _ => PulsePhase::Steady,
+        }
+    }
+
+    /// The fraction of the packets the report accounts for that were lost, or `None` if it
+    /// accounts for none.
+    pub fn loss_rate(&self) -> Option<f64> {
+        let packets = u64::from(self.loss) + u64::from(self.acked);
+        (packets > 0).then(|| f64::from(self.loss) / packets as f64)
+    }
+
+    /// The rate the report delivered at, recomputed from `delivered_total` rather than taken
+    /// from the datapath's rate estimates, given the report before it, if it came from the same
+    /// program. `None` if the report covers no time.
+    pub fn recomputed_rate(&self, previous: Option<&Measurement>) -> Option<f64> {
+        let before = previous
+            .filter(|p| p.program_uid == self.program_uid)
+            .map_or(0, |p| p.delivered_total);
+        (self.span_us > 0).then(|| {
+            self.delivered_total.saturating_sub(before) as f64 * 1e6 / f64::from(self.span_us)
+        })
+    }
+
+    /// Whether the report's rates are all at most `floor` bytes per second, as in the reports
+    /// of a flow that sent or had acked next to nothing since the one before.
+    pub fn is_stalled(&self, floor: f64) -> bool {
+        self.rate_outgoing
+            .max(self.rate_incoming)
+            .max(self.delivery_rate)
+            <= floor
+    }
+
+    /// Whether the report saw an RTT sample, so that `minrtt_us` is one.
+    pub fn has_rtt_sample(&self) -> bool {
+        self.minrtt_us != NO_RTT_SAMPLE
+    }
+
+    /// Reads the fields reported by the program installed in `mode`, or the first one that is
+    /// missing.
+    pub fn from_report_fields(
+        mode: BbrMode,
+        program_uid: u32,
+        get_field: impl Fn(&str) -> Option<u64>,
+    ) -> Result<Self, BbrError> {
+        let field = |field: &'static str| {
+            get_field(field).ok_or(BbrError::MissingField { program_uid, field })
+        };
+        // the other fields of a program of another version may mean something else
+        let version = get_field("Report.version");
+        if version != Some(u64::from(PROGRAM_VERSION)) {
+            return Err(BbrError::ProgramVersion {
+                program_uid,
+                found: version,
+            });
+        }
+        // +infinity, or any other value past u32, must not wrap around to a small RTT
+        let minrtt_us = field("Report.minrtt")?.min(u64::from(NO_RTT_SAMPLE)) as u32;
+        if mode == BbrMode::ProbeRtt {
+            return Ok(Measurement {
+                program_uid,
+                minrtt_us,
+                ..Default::default()
+            });
+        }
+
+        Ok(Measurement {
+            program_uid,
+            minrtt_us,
+            loss: field("Report.loss")? as u32,
+            acked: get_field("Report.acked").unwrap_or_default() as u32,
+            misordered: field("Report.misordered")? as u32,
+            timeout: field("Report.timeout")? != 0,
+            rate_outgoing: field("Report.rateOut")? as f64,
+            rate_incoming: field("Report.rateIn")? as f64,
+            delivery_rate: get_field("Report.deliveryRate").unwrap_or_default() as f64,
+            pulse_state: field("Report.pulseState")? as u32,
+            receiver_limited: field("Report.rwndLimited")? != 0,
+            inflight_bytes: if mode == BbrMode::ProbeBw {
+                field("Report.inflight")? as u32
+            } else {
+                0
+            },
+            srtt_us: field("Report.srtt")? as u32,
+            rttvar_us: field("Report.rttVar")? as u32,
+            max_rate: get_field("Report.maxRate").unwrap_or_default() as f64,
+            acks: get_field("Report.acks").unwrap_or_default() as u32,
+            nonce: get_field("Report.nonce").unwrap_or_default() as u32,
+            nonce_age_us: get_field("Report.nonceAgeUs").unwrap_or_default() as u32,
+            delivered_total: get_field("Report.deliveredTotal").unwrap_or_default(),
+            span_us: get_field("Report.spanUs").unwrap_or_default() as u32,
+            mss: get_field("Report.mss").unwrap_or_default() as u32,
+            received: None,
+        })
+    }
+}
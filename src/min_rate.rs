@@ -0,0 +1,59 @@
+//! Floors on the rate flows pace at, for applications that need a minimum bitrate, such as a
+//! live video stream.
+//!
+//! A flow with a floor never paces below it, and never keeps less than one BDP of it in
+//! flight: not in the down pulses, not in DRAIN, not after a congestion loss lowers its
+//! estimate, and not in `PROBE_RTT`. The estimate itself is left alone, so the flow still
+//! knows when the path cannot carry its floor. A cap below the floor wins.
+
+use crate::bandwidth::{parse_rate, Rate};
+use crate::flow_match::FlowMatch;
+use portus::DatapathInfo;
+use std::fmt;
+use std::str::FromStr;
+
+/// Parses a floor; bare numbers are Mbit/s.
+pub fn parse_min_rate(s: &str) -> Result<Rate, String> {
+    parse_rate(s, Rate::from_mbps(1.0))
+}
+
+/// Keeps the flows selected by `flow` at or above `rate`.
+///
+/// Parsed from and displayed as `<flow match>:<rate>`, e.g. `dport=1935:4Mbps`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MinRateRule {
+    pub flow: FlowMatch,
+    pub rate: Rate,
+}
+
+impl FromStr for MinRateRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (flow, rate) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected <flow match>:<rate>: {:?}", s))?;
+        Ok(MinRateRule {
+            flow: flow.parse()?,
+            rate: parse_min_rate(rate)?,
+        })
+    }
+}
+
+impl fmt::Display for MinRateRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.flow, self.rate)
+    }
+}
+
+/// The floor of the first matching rule, or the one given for all flows.
+pub fn min_rate_for(
+    rules: &[MinRateRule],
+    rate: Option<Rate>,
+    info: &DatapathInfo,
+) -> Option<Rate> {
+    rules
+        .iter()
+        .find(|r| r.flow.matches(info))
+        .map_or(rate, |r| Some(r.rate))
+}
@@ -0,0 +1,85 @@
+//! How long a flow has spent in each mode.
+//!
+//! BBR keeps `PROBE_RTT` to 200ms out of every 10s, so it should cost a flow about 2% of its
+//! time; the time a flow spends in each mode shows what it costs on a live path, where
+//! reports that come late, groups that align their `PROBE_RTT`s and flows that go idle all
+//! move it. A flow counts the time up to its latest report, so the current mode's share grows
+//! in steps of the report interval.
+
+use crate::BbrMode;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// The time a flow has spent in each mode, up to its latest report.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ModeTime {
+    pub startup_us: u64,
+    pub drain_us: u64,
+    pub probe_bw_us: u64,
+    pub probe_rtt_us: u64,
+}
+
+impl ModeTime {
+    pub fn total_us(&self) -> u64 {
+        self.startup_us + self.drain_us + self.probe_bw_us + self.probe_rtt_us
+    }
+
+    /// The fraction of the flow's time it spent in `PROBE_RTT`; zero before any time passed.
+    pub fn probe_rtt_fraction(&self) -> f64 {
+        match self.total_us() {
+            0 => 0.0,
+            total => self.probe_rtt_us as f64 / total as f64,
+        }
+    }
+}
+
+/// Accumulates a flow's time per mode on a monotonic clock, as its mode changes.
+#[derive(Clone, Copy, Debug)]
+pub struct ModeClock {
+    spent: [Duration; 4],
+    entered: Instant,
+    now: Instant,
+}
+
+fn index(mode: BbrMode) -> usize {
+    match mode {
+        BbrMode::Startup => 0,
+        BbrMode::Drain => 1,
+        BbrMode::ProbeBw => 2,
+        BbrMode::ProbeRtt => 3,
+    }
+}
+
+impl ModeClock {
+    pub fn new(now: Instant) -> Self {
+        ModeClock {
+            spent: [Duration::ZERO; 4],
+            entered: now,
+            now,
+        }
+    }
+
+    /// Moves the clock to `now`; it never goes back.
+    pub fn advance(&mut self, now: Instant) {
+        self.now = self.now.max(now);
+    }
+
+    /// Charges the time since the last switch to `from`, the mode the flow is leaving.
+    pub fn switch(&mut self, from: BbrMode) {
+        self.spent[index(from)] += self.now - self.entered;
+        self.entered = self.now;
+    }
+
+    /// The time spent in each mode, with the time since the last switch in `current`.
+    pub fn times(&self, current: BbrMode) -> ModeTime {
+        let mut spent = self.spent;
+        spent[index(current)] += self.now - self.entered;
+        let us = |d: Duration| d.as_micros().min(u128::from(u64::MAX)) as u64;
+        ModeTime {
+            startup_us: us(spent[0]),
+            drain_us: us(spent[1]),
+            probe_bw_us: us(spent[2]),
+            probe_rtt_us: us(spent[3]),
+        }
+    }
+}
@@ -0,0 +1,405 @@
+//! The rates and windows a flow's programs run on, derived from its model of the path.
+//!
+//! Every pacing rate and window a flow installs follows from the same few inputs: its
+//! bottleneck rate estimate and its share of it, its min RTT and MSS, the gains of its PROBE_BW
+//! cycle, and the floor, cap and delay budget it is configured with. [`Model`] collects them,
+//! and [`ModelDerived`] holds what `probe_bw` is installed and updated with, so that installing
+//! the program and replacing its registers cannot drift apart.
+
+use crate::bandwidth::Rate;
+use crate::loss::is_loss_burst;
+use crate::params::{
+    BUFFER_PROBE_FRACTION, LOSS_BURST_FRACTION, PROBE_DOWN_GAIN, PROBE_RTT_CWND_PACKETS,
+    QUEUE_BACKOFF_GAIN, STALL_RATE_FRACTION, STEP_DOWN_PHASES, STEP_DOWN_RATIO,
+    STEP_DOWN_RTT_INFLATION,
+};
+use crate::rate::RateEstimator;
+use crate::{BbrCore, BbrMode, Measurement, PulsePhase};
+use tracing::{info, warn};
+
+/// The cwnd in rate-only mode, large enough that only pacing limits the flow.
+pub const UNCAPPED_CWND: u32 = u32::MAX;
+/// The `Rate` that turns pacing off.
+pub const UNPACED_RATE: u64 = u64::MAX;
+
+/// The gains of a PROBE_BW cycle's down pulse, its cruise phase, its up pulse and, with
+/// `ramp_probe_bw`, the first half of its up pulse.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProbeBwGains {
+    pub down: f64,
+    pub cruise: f64,
+    pub up: f64,
+    pub ramp: f64,
+}
+
+impl ProbeBwGains {
+    /// The same gain throughout the cycle.
+    pub const fn flat(gain: f64) -> Self {
+        ProbeBwGains {
+            down: gain,
+            cruise: gain,
+            up: gain,
+            ramp: gain,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Model {
+    /// Bytes per second.
+    pub bottle_rate: f64,
+    /// The flow's weighted share of `bottle_rate`, from 0 to 1.
+    pub share: f64,
+    pub min_rtt_us: u32,
+    pub mss: u32,
+    /// Bytes per second. No rate derived from the model is below `min_rate` or above
+    /// `max_rate`, which wins.
+    pub min_rate: f64,
+    pub max_rate: f64,
+    /// The cwnd cap is the BDP over this multiple of `min_rtt_us` plus `headroom_us`, but
+    /// over no more than `min_rtt_us` plus `delay_budget_us`.
+    pub cwnd_bdp_multiplier: f64,
+    pub headroom_us: u32,
+    pub delay_budget_us: Option<u32>,
+    /// If set, after a loss burst, no window derived from the model is larger.
+    pub max_cwnd: Option<u32>,
+    pub gains: ProbeBwGains,
+}
+
+/// What `probe_bw` runs on, in the units of its registers: bytes per second and bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModelDerived {
+    pub down_rate: u64,
+    pub cruise_rate: u64,
+    pub up_rate: u64,
+    pub ramp_rate: u64,
+    /// The windows of each phase, which datapaths without pacing pulse instead of the rate.
+    pub down_cwnd: u32,
+    pub cruise_cwnd: u32,
+    pub up_cwnd: u32,
+    pub ramp_cwnd: u32,
+    pub cwnd_cap: u32,
+}
+
+impl Model {
+    /// The flow's share of the bottleneck rate times `gain`, within its floor and cap.
+    pub fn rate(&self, gain: f64) -> f64 {
+        (self.bottle_rate * self.share * gain)
+            .max(self.min_rate)
+            .min(self.max_rate)
+    }
+
+    /// What PROBE_RTT keeps in flight: a few packets, or the BDP of the floor if that is more.
+    pub fn probe_rtt_cwnd(&self) -> u32 {
+        let floor_bdp = (self.min_rate * f64::from(self.min_rtt_us) / 1e6) as u32;
+        self.mss
+            .saturating_mul(PROBE_RTT_CWND_PACKETS)
+            .max(floor_bdp)
+    }
+
+    /// The BDP at `rate(gain)`, but at least what PROBE_RTT keeps in flight and at most
+    /// `max_cwnd`.
+    pub fn bdp_cwnd(&self, gain: f64) -> u32 {
+        let bdp = self.rate(gain) * f64::from(self.min_rtt_us) / 1e6;
+        self.limit_cwnd((bdp as u32).max(self.probe_rtt_cwnd()))
+    }
+
+    /// `cwnd`, or `max_cwnd` if that is less.
+    pub fn limit_cwnd(&self, cwnd: u32) -> u32 {
+        self.max_cwnd.map_or(cwnd, |max| cwnd.min(max))
+    }
+
+    /// The cwnd loss recovery cuts `window` to: half of it, but no less than what is still in
+    /// flight, `inflight_bytes` less `lost_bytes`, plus one packet, so that every ack still
+    /// releases a packet, nor less than what PROBE_RTT keeps in flight. Cutting below what is
+    /// in flight would stall the flow until its window drained, which short flows never get
+    /// over.
+    pub fn recovery_cwnd(&self, window: u32, inflight_bytes: u32, lost_bytes: u32) -> u32 {
+        let in_flight = inflight_bytes
+            .saturating_sub(lost_bytes)
+            .saturating_add(self.mss);
+        (window / 2).max(in_flight).max(self.probe_rtt_cwnd())
+    }
+
+    /// The BDP over the cap's RTT, but at least what PROBE_RTT keeps in flight and at most
+    /// `max_cwnd`.
+    pub fn cwnd_cap(&self) -> u32 {
+        let min_rtt_us = f64::from(self.min_rtt_us);
+        let mut rtt_us = self.cwnd_bdp_multiplier * min_rtt_us + f64::from(self.headroom_us);
+        if let Some(budget_us) = self.delay_budget_us {
+            rtt_us = rtt_us.min(min_rtt_us + f64::from(budget_us));
+        }
+        let cap = (self.rate(1.0) * rtt_us / 1e6) as u32;
+        self.limit_cwnd(cap.max(self.probe_rtt_cwnd()))
+    }
+
+    pub fn derive(&self) -> ModelDerived {
+        let gains = self.gains;
+        ModelDerived {
+            down_rate: self.rate(gains.down) as u64,
+            cruise_rate: self.rate(gains.cruise) as u64,
+            up_rate: self.rate(gains.up) as u64,
+            ramp_rate: self.rate(gains.ramp) as u64,
+            down_cwnd: self.bdp_cwnd(gains.down),
+            cruise_cwnd: self.bdp_cwnd(gains.cruise),
+            up_cwnd: self.bdp_cwnd(gains.up),
+            ramp_cwnd: self.bdp_cwnd(gains.ramp),
+            cwnd_cap: self.cwnd_cap(),
+        }
+    }
+}
+
+impl BbrCore {
+    // what every rate and window the flow installs is derived from
+    pub(crate) fn model(&self) -> Model {
+        Model {
+            bottle_rate: self.bottle_rate,
+            share: self.rate_share,
+            min_rtt_us: self.min_rtt_us,
+            mss: self.mss,
+            min_rate: self.min_rate,
+            max_rate: self.max_rate,
+            cwnd_bdp_multiplier: self.cwnd_bdp_multiplier,
+            headroom_us: if self.jitter_headroom {
+                self.rtt_jitter.spread_us()
+            } else {
+                0
+            },
+            delay_budget_us: self.delay_budget_us,
+            max_cwnd: self.loss_burst_cwnd,
+            gains: self.probe_bw_gains(),
+        }
+    }
+
+    // the bottle rate scaled down by this flow's weighted share
+    pub(crate) fn paced_bottle_rate(&self) -> f64 {
+        self.bottle_rate * self.rate_share
+    }
+
+    // the paced rate times the gain, but never below the flow's floor nor above its cap
+    pub(crate) fn pulse_rate(&self, gain: f64) -> f64 {
+        self.model().rate(gain)
+    }
+
+    // the configured multiple of the estimated BDP, but no more than queues the delay budget
+    pub(crate) fn capped_cwnd(&self) -> u32 {
+        self.model().cwnd_cap()
+    }
+
+    // the cwnd cap, or uncapped in rate-only mode unless a loss burst holds cwnd down
+    pub(crate) fn probe_bw_cwnd(&self) -> u32 {
+        if self.cwnd_cap {
+            self.capped_cwnd()
+        } else {
+            self.model().limit_cwnd(UNCAPPED_CWND)
+        }
+    }
+
+    // PROBE_RTT's cwnd in bytes, like every cwnd and inflight target the programs use, or
+    // the BDP of the flow's floor if that is more
+    pub(crate) fn probe_rtt_cwnd(&self) -> u32 {
+        self.model().probe_rtt_cwnd()
+    }
+
+    // the paced BDP times the gain, but at least what PROBE_RTT keeps in flight
+    pub(crate) fn bdp_cwnd(&self, gain: f64) -> u32 {
+        self.model().bdp_cwnd(gain)
+    }
+
+    // the cwnd pulse for probe bw programs on datapaths without pacing
+    pub(crate) fn probe_bw_cwnd_pulse(&self, derived: &ModelDerived) -> Vec<(&'static str, u64)> {
+        let mut fields = vec![
+            ("bdpCwnd", u64::from(derived.cruise_cwnd)),
+            ("threeFourthsCwnd", u64::from(derived.down_cwnd)),
+            ("fiveFourthsCwnd", u64::from(derived.up_cwnd)),
+        ];
+        if self.probe_bw_ramp {
+            fields.push(("nineEighthsCwnd", u64::from(derived.ramp_cwnd)));
+        }
+        fields
+    }
+
+    // backing off a standing queue cruises below the estimate and skips the up pulses, and
+    // cycles that don't probe, or that follow a loss burst, stay at the estimate
+    pub(crate) fn probe_bw_gains(&self) -> ProbeBwGains {
+        if self.queue_backoff {
+            ProbeBwGains {
+                down: PROBE_DOWN_GAIN,
+                ..ProbeBwGains::flat(QUEUE_BACKOFF_GAIN)
+            }
+        } else if !self.probing || self.paused || self.loss_burst_cycles > 0 {
+            ProbeBwGains::flat(1.0)
+        } else {
+            let up = self.budgeted_probe_gain();
+            ProbeBwGains {
+                down: 2.0 - up,
+                cruise: 1.0,
+                up,
+                ramp: (1.0 + up) / 2.0,
+            }
+        }
+    }
+
+    // an up pulse at gain g queues g - 1 pulse lengths of delay, which the delay budget bounds,
+    // and with `fit_probes_to_buffer`, a fraction of the buffer probes overflowed
+    pub(crate) fn budgeted_probe_gain(&self) -> f64 {
+        let pulse_us = f64::from(self.pulse_length_us.unwrap_or(self.min_rtt_us).max(1));
+        let mut gain = self.probe_gain;
+        if let Some(budget_us) = self.delay_budget_us {
+            gain = gain.min(1.0 + f64::from(budget_us) / pulse_us);
+        }
+        if let Some(buffer_us) = self.overflowed_buffer_us() {
+            gain = gain.min(1.0 + BUFFER_PROBE_FRACTION * buffer_us / pulse_us);
+        }
+        gain
+    }
+
+    // the time the bottleneck takes to drain a buffer that probes overflowed, if probes are
+    // to fit it
+    fn overflowed_buffer_us(&self) -> Option<f64> {
+        if !self.fit_probes_to_buffer || self.bottle_rate <= 0.0 {
+            return None;
+        }
+        let depth = self.buffer_depth().filter(|depth| depth.overflowed)?;
+        Some(depth.bytes as f64 / self.bottle_rate * 1e6)
+    }
+
+    // the gain of the pulse a cycle starts with: the up pulse, or its first half with the ramp
+    pub(crate) fn first_pulse_gain(&self) -> f64 {
+        let gains = self.probe_bw_gains();
+        if self.probe_bw_ramp {
+            gains.ramp
+        } else {
+            gains.up
+        }
+    }
+
+    // whether an RTT sample is too far from `reference` to be taken, unless the previous
+    // checked sample was just as far off in the same direction. STARTUP takes every sample,
+    // since its min_rtt starts out as a guess.
+    pub(crate) fn is_min_rtt_spike(&mut self, sample: u32, reference: u32) -> bool {
+        let factor = match self.min_rtt_spike_factor {
+            Some(factor) => factor,
+            None => return false,
+        };
+
+        let above = f64::from(sample) > f64::from(reference) * factor;
+        let below = f64::from(sample) * factor < f64::from(reference);
+        if !above && !below {
+            self.min_rtt_spike = None;
+            return false;
+        }
+
+        match self.min_rtt_spike.take() {
+            Some(prev) if (prev > reference) == above => false,
+            _ => {
+                info!(
+                    sample_us = sample,
+                    min_rtt_us = reference,
+                    "ignoring min_rtt spike"
+                );
+                self.min_rtt_spike = Some(sample);
+                true
+            }
+        }
+    }
+
+    // whether a report's RTT sample falls below `reference` during a loss burst or after a
+    // retransmission timeout, and so may time a retransmission: an ack for the original
+    // segment looks like a fast ack for its copy. Such samples are counted and not taken. A
+    // flow that has not measured its min_rtt yet takes them anyway, rather than keep a guess.
+    pub(crate) fn is_lossy_rtt_sample(&mut self, m: &Measurement, reference: u32) -> bool {
+        if !self.lossy_rtt_filter
+            || self.min_rtt_measured.is_none()
+            || !m.has_rtt_sample()
+            || m.minrtt_us >= reference
+        {
+            return false;
+        }
+        let fraction = self.loss_burst_fraction.unwrap_or(LOSS_BURST_FRACTION);
+        if !m.timeout && !is_loss_burst(m.loss, m.acked, fraction) {
+            return false;
+        }
+        info!(
+            sample_us = m.minrtt_us,
+            min_rtt_us = reference,
+            loss = m.loss,
+            timeout = m.timeout,
+            "ignoring min_rtt sample from a lossy report"
+        );
+        self.lossy_rtt_samples += 1;
+        true
+    }
+
+    // the bandwidth sample from one report, up to the flow's cap, so that the estimate never
+    // exceeds it
+    pub(crate) fn sample_rate(&mut self, m: &Measurement) -> f64 {
+        // a stalled report would drag the smoothed rates toward zero
+        if self.is_stalled(m) {
+            return 0.0;
+        }
+        self.sample_delivery_rate(m).min(self.max_rate)
+    }
+
+    // whether the report's rates are next to zero, so that it measured the flow's silence rather
+    // than the path; probe_rtt reports no rates at all
+    pub(crate) fn is_stalled(&self, m: &Measurement) -> bool {
+        self.curr_mode != BbrMode::ProbeRtt && m.is_stalled(self.bottle_rate * STALL_RATE_FRACTION)
+    }
+
+    // from the programs' own estimate if the datapath turns out to leave its rates at zero
+    fn sample_delivery_rate(&mut self, m: &Measurement) -> f64 {
+        if self.rate_estimator != RateEstimator::Auto {
+            return self.rates.sample(m.rate_outgoing, m.rate_incoming);
+        }
+
+        if m.delivery_rate > 0.0
+            && m.rate_outgoing == 0.0
+            && m.rate_incoming == 0.0
+            && self.capabilities.mark_no_flow_rates()
+        {
+            warn!(
+                delivery_rate = %Rate::from_bytes_per_sec(m.delivery_rate),
+                "datapath does not report Flow rates, estimating bandwidth from acked bytes"
+            );
+        }
+
+        if self.capabilities.lacks_flow_rates() {
+            self.rates.sample(m.delivery_rate, m.delivery_rate)
+        } else {
+            self.rates.sample(m.rate_outgoing, m.rate_incoming)
+        }
+    }
+
+    // counts the cruise phases that deliver well below bottle_rate while the RTT is inflated,
+    // and returns the most they delivered once there have been enough of them in a row. A
+    // phase that was limited by the application or the receiver says nothing about the path.
+    pub(crate) fn track_step_down(&mut self, m: &Measurement, rate: f64) -> Option<f64> {
+        if !self.fast_step_down || m.pulse_phase() != PulsePhase::Cruise {
+            return None;
+        }
+        // a lighter flow only paces at its share of bottle_rate, so it is the path's rate
+        // that its sample stands for
+        let rate = rate / self.rate_share;
+        let inflated = m.has_rtt_sample()
+            && f64::from(m.minrtt_us) > f64::from(self.min_rtt_us) * STEP_DOWN_RTT_INFLATION;
+        if rate <= 0.0
+            || rate >= self.bottle_rate * STEP_DOWN_RATIO
+            || !inflated
+            || self.app_limited
+            || m.receiver_limited
+        {
+            self.step_down_phases = 0;
+            self.step_down_rate = 0.0;
+            return None;
+        }
+
+        self.step_down_phases += 1;
+        self.step_down_rate = self.step_down_rate.max(rate);
+        if self.step_down_phases < STEP_DOWN_PHASES {
+            return None;
+        }
+        self.step_down_phases = 0;
+        Some(std::mem::take(&mut self.step_down_rate))
+    }
+}
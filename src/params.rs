@@ -0,0 +1,209 @@
+//! The algorithm's tuning constants and the defaults of its configuration.
+//!
+//! Every gain, threshold and default that the programs, the control plane or `BbrConfig`'s
+//! flags rely on is defined here once, so that tools and tests downstream can refer to the
+//! values the agent actually runs with. The modules that use a constant also re-export it
+//! under their own path.
+
+use crate::bandwidth::Rate;
+use std::time::Duration;
+
+// PROBE_RTT and the min RTT estimate
+
+pub const PROBE_RTT_INTERVAL_SECONDS: i64 = 10;
+pub const PROBE_RTT_SYNC_WINDOW_MS: u64 = 200;
+/// How long PROBE_RTT holds inflight down once it has drained to its target, at least a round
+/// trip.
+pub const PROBE_RTT_DURATION_US: u32 = 200_000;
+/// With `scale_probe_rtt`, PROBE_RTT lasts at most this many times `PROBE_RTT_DURATION_US`,
+/// however large the flow's bottleneck group.
+pub const PROBE_RTT_MAX_GROUP_SCALE: u32 = 4;
+/// A `min_rtt` sample more than this factor away from the estimate is only taken if the next
+/// sample is just as far off.
+pub const MIN_RTT_SPIKE_FACTOR: f64 = 4.0;
+/// PROBE_RTT's cwnd, in MSS-sized packets.
+pub const PROBE_RTT_CWND_PACKETS: u32 = 4;
+/// On paths above `BbrConfig::high_rtt_threshold`, PROBE_RTT keeps this fraction of the BDP in
+/// flight instead, if that is more: draining to a few packets costs whole seconds of
+/// throughput there, and half the BDP still drains any queue the flow keeps.
+pub const HIGH_RTT_PROBE_RTT_GAIN: f64 = 0.5;
+/// The `min_rtt` filter window used when `PROBE_RTT` is disabled.
+pub const MIN_RTT_WINDOW_SECONDS: u64 = 10;
+
+// STARTUP and DRAIN
+
+/// 2/ln(2), the smallest gain that doubles the sending rate every round.
+pub const STARTUP_GAIN: f64 = 2.0 / std::f64::consts::LN_2;
+pub const STARTUP_CWND_GAIN: f64 = STARTUP_GAIN;
+/// STARTUP ends when the delivery rate grows less than this factor for
+/// `STARTUP_FULL_BW_ROUNDS` rounds in a row.
+pub const STARTUP_GROWTH_TARGET: f64 = 1.25;
+pub const STARTUP_FULL_BW_ROUNDS: u32 = 3;
+/// Flows to the same bottleneck group that start within this long of each other start
+/// together, for `incast_threshold`.
+pub const INCAST_WINDOW_MS: u64 = 100;
+/// The STARTUP pacing and cwnd gains of flows that start in an incast.
+pub const INCAST_STARTUP_GAIN: f64 = 1.5;
+/// The smallest share of the initial window a flow that starts in an incast gets, in
+/// MSS-sized packets.
+pub const INCAST_MIN_CWND_PACKETS: u32 = 2;
+/// Drains the queue STARTUP built in about one round.
+pub const DRAIN_GAIN: f64 = 1.0 / STARTUP_GAIN;
+/// What flows pace at before their first RTT sample, 1 Mbit/s, unless `initial_rate` or a
+/// cached estimate says otherwise.
+pub const DEFAULT_INITIAL_RATE: Rate = Rate::from_bytes_per_sec(125_000.0);
+pub const DEFAULT_INITIAL_RTT: Duration = Duration::from_secs(1);
+/// The smallest window a short flow starts with, in MSS-sized packets.
+pub const SHORT_FLOW_CWND_PACKETS: u32 = 32;
+/// The smallest MSS a flow takes from its reports, the one every IPv4 path carries; smaller
+/// samples come from acks of small segments.
+pub const MIN_MSS_BYTES: u32 = 536;
+
+// PROBE_BW
+
+/// `probe_bw`'s bandwidth filter keeps the delivery rates of this many pulse-length rounds,
+/// unless `bw_window` says otherwise.
+pub const BW_FILTER_ROUNDS: usize = 10;
+/// The longest `bw_window`, since the program keeps each round in a register of its own.
+pub const MAX_BW_WINDOW_ROUNDS: usize = 32;
+/// With delayed or stretched ACKs, a pulse or filter round that saw fewer ack events than
+/// this measures their spacing rather than the path, so its rate is not trusted.
+pub const MIN_RATE_SAMPLE_ACKS: u32 = 4;
+/// A report whose rates all stay below this fraction of `bottle_rate` comes from a flow that
+/// sent or had acked next to nothing, e.g. while application-limited or stalled, and is no
+/// bandwidth sample.
+pub const STALL_RATE_FRACTION: f64 = 0.01;
+/// PROBE_BW caps cwnd at this multiple of the estimated BDP.
+pub const CWND_BDP_MULTIPLIER: f64 = 2.0;
+/// A cruise phase whose min RTT stays above this factor of `min_rtt` means the flow keeps a
+/// standing queue.
+pub const QUEUE_RTT_THRESHOLD: f64 = 1.25;
+/// While backing off a standing queue, PROBE_BW cruises at this gain and skips up pulses.
+pub const QUEUE_BACKOFF_GAIN: f64 = 0.9;
+/// Consecutive up pulses without bandwidth growth after which PROBE_BW probes less often.
+pub const STALE_PROBES: u32 = 3;
+/// PROBE_BW's up pulse gain; the down pulse drains what it added.
+pub const PROBE_GAIN: f64 = 1.25;
+/// PROBE_BW's down pulse gain with the usual up pulse, and while backing off a standing
+/// queue.
+pub const PROBE_DOWN_GAIN: f64 = 2.0 - PROBE_GAIN;
+/// Consecutive pulse cycles that move the bandwidth estimate by at most `STABLE_BW_TOLERANCE`
+/// after which PROBE_BW probes with `BbrConfig::stable_probe_gain`.
+pub const STABLE_PROBE_CYCLES: u32 = 8;
+/// The probes whose queues the bottleneck buffer estimate takes the largest of.
+pub const BUFFER_WINDOW_PROBES: usize = 10;
+/// With `BbrConfig::fit_probes_to_buffer`, the most of a buffer that probes overflowed an up
+/// pulse may queue.
+pub const BUFFER_PROBE_FRACTION: f64 = 0.5;
+/// The round trips of a PROBE_BW pulse cycle: one up pulse, one down pulse and six cruising.
+/// `BbrConfig::cruise_phase_end` moves the cycle's end.
+pub const PULSE_CYCLE_ROUNDS: u32 = 8;
+/// The pulses into a PROBE_BW cycle its down pulse ends at: one pulse up, then one down.
+pub const DOWN_PHASE_END_PULSES: u32 = 2;
+/// The longest PROBE_BW cycle `BbrConfig::cruise_phase_end` can set, in pulses.
+pub const MAX_CYCLE_PULSES: u32 = 64;
+/// The round trips of a pulse cycle on paths above `BbrConfig::high_rtt_threshold`: one up
+/// pulse, one down pulse and two cruising, so that a cycle still probes every few seconds.
+pub const HIGH_RTT_CYCLE_ROUNDS: u32 = 4;
+/// Above the threshold, the bandwidth filter remembers this many times `bw_window` rounds, up
+/// to `MAX_BW_WINDOW_ROUNDS`, since the shorter cycles spend fewer rounds at the estimate.
+pub const HIGH_RTT_BW_WINDOW_FACTOR: usize = 2;
+/// How far ahead a coordinator can schedule a flow's next up pulse, which holds the flow in
+/// its cruise phase until then.
+pub const PROBE_SCHEDULE_HORIZON_SECONDS: u64 = 60;
+pub const STABLE_BW_TOLERANCE: f64 = 0.05;
+/// With `fast_step_down`, cruise phases that deliver less than this fraction of `bottle_rate`
+/// while the RTT is inflated by `STEP_DOWN_RTT_INFLATION` suggest the bottleneck has shrunk.
+pub const STEP_DOWN_RATIO: f64 = 0.75;
+pub const STEP_DOWN_RTT_INFLATION: f64 = 1.25;
+/// Consecutive such cruise phases after which `bottle_rate` drops to the most they delivered.
+pub const STEP_DOWN_PHASES: u32 = 3;
+/// Consecutive PROBE_BW reports from an unexpected pulse phase after which the flow
+/// reinstalls `probe_bw`, restarting its pulse cycle.
+pub const PULSE_DESYNC_RESET: u32 = 3;
+
+// Losses and bandwidth samples
+
+/// The factor the bandwidth estimate is cut by after a congestion loss.
+pub const LOSS_BACKOFF: f64 = 0.85;
+/// Acks after a sampled loss that have to arrive before the windowed accounting counts it.
+pub const REORDER_WINDOW_ACKS: u32 = 3;
+/// By default, how far a report's minimum RTT has to exceed the path's for its losses to count
+/// as congestion in the lossy-link mode.
+pub const LOSS_RTT_INFLATION: f64 = 1.25;
+/// By default, the fraction of its window a PROBE_BW flow has to lose in one report for the
+/// losses to count as a burst, such as from a route change or a radio fade, that halves cwnd.
+pub const LOSS_BURST_FRACTION: f64 = 0.3;
+/// Fewer lost packets than this are too small a sample to be a burst.
+pub const LOSS_BURST_MIN_PACKETS: u32 = 8;
+/// Pulse cycles after a loss burst that hold cwnd at half and do not probe up.
+pub const LOSS_BURST_HOLD_CYCLES: u32 = 3;
+/// A PROBE_BW cycle that delivered less than this fraction of the flow's pacing rate, though
+/// neither the application nor the receiver held it back, counts as a delivery gap.
+pub const DELIVERY_GAP_RATIO: f64 = 0.8;
+/// Delivery gaps in a row after which the flow warns that its model overestimates the path,
+/// as behind a policer or a degrading radio link.
+pub const DELIVERY_GAP_WARN_CYCLES: u32 = 4;
+/// The weight of a new report in the moving averages.
+pub const RATE_EWMA_GAIN: f64 = 0.5;
+/// How many reports' RTTs the spread is computed over.
+pub const RTT_JITTER_SAMPLES: usize = 50;
+/// Fewer samples than this give no spread.
+pub const RTT_JITTER_MIN_SAMPLES: usize = 10;
+
+// What the datapath can do
+
+/// PROBE_BW sending this factor faster than its fastest pulse means the datapath does not
+/// enforce `Rate`.
+pub const UNENFORCED_RATE_FACTOR: f64 = 1.5;
+/// Consecutive PROBE_BW reports that have to send too fast before a flow stops relying on
+/// pacing alone.
+pub const UNENFORCED_RATE_REPORTS: u32 = 3;
+/// Consecutive attempts to install `probe_bw` that the datapath has to reject before flows
+/// fall back to the AIMD program.
+pub const PROBE_BW_INSTALL_ATTEMPTS: u32 = 3;
+/// The weight of a new sample in the smoothed install latency, as of a new RTT sample in
+/// TCP's sRTT.
+pub const INSTALL_LATENCY_GAIN: f64 = 0.125;
+/// `pulse_shift` delays the ends of the pulses by at most this fraction of a pulse.
+pub const MAX_PULSE_SHIFT: f64 = 0.5;
+/// `pulse_shift` only rewrites the shift once it moved by more than a pulse over this.
+pub const PULSE_SHIFT_RESOLUTION: u32 = 16;
+
+// The agent itself
+
+/// The version of the datapath programs' text, which every program reports in
+/// `Report.version`. Bump it whenever a program's reports or registers change meaning, so that
+/// flows tell reports from programs an agent of another version installed apart.
+pub const PROGRAM_VERSION: u32 = 1;
+
+/// Handling a report in more than this fraction of the time between reports is logged as a
+/// warning, since reports that take longer still queue up.
+pub const SLOW_HANDLING_FRACTION: f64 = 0.5;
+
+// State shared between flows
+
+pub const PATH_CACHE_TTL_SECONDS: u64 = 300;
+pub const PATH_CACHE_PREFIX_LEN: u8 = 24;
+/// The most destination prefixes the path cache keeps estimates for.
+pub const PATH_CACHE_CAPACITY: usize = 65_536;
+pub const BOTTLENECK_GROUP_PREFIX_LEN: u8 = 24;
+pub const DEFAULT_WEIGHT: f64 = 1.0;
+/// How long the agent waits for flows to release themselves before exiting anyway.
+pub const SHUTDOWN_GRACE_MS: u64 = 1000;
+/// A warning that recurs within this long of being logged is only counted.
+pub const WARN_INTERVAL_SECONDS: u64 = 10;
+/// How often the `qdisc` feature checks the host's interfaces for a pacing qdisc.
+pub const QDISC_CHECK_INTERVAL_SECONDS: u64 = 60;
+
+// The selftest subcommand
+
+/// How long `selftest` sends for, unless told otherwise.
+pub const SELFTEST_DURATION_SECONDS: u64 = 30;
+/// `selftest` fails a transfer that achieved less than this fraction of the rate it expected.
+pub const SELFTEST_RATE_FRACTION: f64 = 0.8;
+/// `selftest` fails a transfer whose smoothed RTT exceeded its min RTT by more than this many
+/// min RTTs, a standing queue BBR should have drained.
+pub const SELFTEST_MAX_QUEUEING_MIN_RTTS: f64 = 1.0;
+/// `selftest` fails a transfer that lost more than this fraction of the packets it sent.
+pub const SELFTEST_MAX_LOSS_FRACTION: f64 = 0.02;
@@ -0,0 +1,268 @@
+//! Learned path characteristics per destination prefix.
+//!
+//! Flows record their `bottle_rate` and `min_rtt` estimates here as they learn them, and new
+//! flows to the same prefix start from the most recent fresh estimate instead of re-learning
+//! the path from the conservative defaults.
+//!
+//! The cache can be saved to a JSON file and loaded again, so that restarting the agent does
+//! not reset every path. Each estimate keeps the wall-clock time it was recorded at, and ages
+//! out after the same TTL as in memory.
+//!
+//! An agent talking to ever more destinations would otherwise keep a path for each of them, so
+//! the cache holds at most `capacity` paths. A path that would take it past that replaces one
+//! that has aged out, or else the least recently used one among those sharing its
+//! [`ShardedMap`] shard.
+
+use crate::flow_match::prefix_mask;
+use crate::shard::{ShardedMap, SHARDS};
+use crate::WallClock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+pub use crate::params::{PATH_CACHE_CAPACITY, PATH_CACHE_PREFIX_LEN, PATH_CACHE_TTL_SECONDS};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathEstimate {
+    /// Bytes per second.
+    pub bottle_rate: f64,
+    pub min_rtt_us: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct CachedPath {
+    est: PathEstimate,
+    recorded: Instant,
+    /// When a flow last recorded or looked the path up.
+    used: Instant,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedPath {
+    prefix: Ipv4Addr,
+    bottle_rate: f64,
+    min_rtt_us: u32,
+    /// Milliseconds since the Unix epoch.
+    recorded_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedCache {
+    prefix_len: u8,
+    paths: Vec<SavedPath>,
+}
+
+/// Shared by all flows of one `BbrConfig`.
+#[derive(Clone)]
+pub struct PathCache {
+    ttl: Duration,
+    prefix_len: u8,
+    capacity: usize,
+    entries: ShardedMap<u32, CachedPath>,
+}
+
+impl Default for PathCache {
+    fn default() -> Self {
+        PathCache::new(
+            Duration::from_secs(PATH_CACHE_TTL_SECONDS),
+            PATH_CACHE_PREFIX_LEN,
+        )
+    }
+}
+
+impl PathCache {
+    /// Estimates older than `ttl` are not used; a zero `ttl` disables the cache.
+    pub fn new(ttl: Duration, prefix_len: u8) -> Self {
+        PathCache {
+            ttl,
+            prefix_len: prefix_len.min(32),
+            capacity: PATH_CACHE_CAPACITY,
+            entries: Default::default(),
+        }
+    }
+
+    /// Holds at most `capacity` paths, at least one per shard, instead of
+    /// `PATH_CACHE_CAPACITY`.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// How many paths the cache holds, including any that aged out but were not replaced or
+    /// looked up since.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    fn enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    fn key(&self, dst_ip: u32) -> u32 {
+        dst_ip & prefix_mask(self.prefix_len)
+    }
+
+    pub fn get(&self, dst_ip: u32, now: Instant) -> Option<PathEstimate> {
+        if !self.enabled() {
+            return None;
+        }
+
+        let key = self.key(dst_ip);
+        self.entries
+            .with_shard(&key, |entries| match entries.get_mut(&key) {
+                Some(path) if now.saturating_duration_since(path.recorded) <= self.ttl => {
+                    path.used = now;
+                    Some(path.est)
+                }
+                Some(_) => {
+                    entries.remove(&key);
+                    None
+                }
+                None => None,
+            })
+    }
+
+    // makes room for one more path in a shard that is full: drops the paths that aged out,
+    // or if none did, the least recently used one
+    fn make_room(&self, entries: &mut HashMap<u32, CachedPath>, now: Instant) {
+        let per_shard = self.capacity.div_ceil(SHARDS).max(1);
+        if entries.len() < per_shard {
+            return;
+        }
+        entries.retain(|_, path| now.saturating_duration_since(path.recorded) <= self.ttl);
+        if entries.len() < per_shard {
+            return;
+        }
+        if let Some(&lru) = entries
+            .iter()
+            .min_by_key(|(_, path)| path.used)
+            .map(|(key, _)| key)
+        {
+            entries.remove(&lru);
+        }
+    }
+
+    pub fn record(&self, dst_ip: u32, est: PathEstimate, now: Instant) {
+        if !self.enabled() {
+            return;
+        }
+
+        let key = self.key(dst_ip);
+        self.entries.with_shard(&key, |entries| {
+            if !entries.contains_key(&key) {
+                self.make_room(entries, now);
+            }
+            entries.insert(
+                key,
+                CachedPath {
+                    est,
+                    recorded: now,
+                    used: now,
+                },
+            );
+        });
+    }
+
+    /// Writes the fresh estimates to `path`, replacing it, and returns how many there were.
+    pub fn save(&self, path: &Path, clock: WallClock) -> io::Result<usize> {
+        let paths: Vec<_> = self
+            .entries
+            .entries()
+            .into_iter()
+            .filter(|(_, path)| clock.instant.saturating_duration_since(path.recorded) <= self.ttl)
+            .map(|(key, path)| SavedPath {
+                prefix: Ipv4Addr::from(key),
+                bottle_rate: path.est.bottle_rate,
+                min_rtt_us: path.est.min_rtt_us,
+                recorded_ms: clock.at(path.recorded).as_millis() as u64,
+            })
+            .collect();
+        let saved = SavedCache {
+            prefix_len: self.prefix_len,
+            paths,
+        };
+
+        // a crash while writing must not leave a truncated cache behind
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&saved)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(saved.paths.len())
+    }
+
+    /// Adds the estimates saved in `path` that are still fresh, and returns how many there
+    /// were. A missing file holds no estimates; one saved with a different prefix length is an
+    /// error, since its prefixes group destinations differently.
+    pub fn load(&self, path: &Path, clock: WallClock) -> io::Result<usize> {
+        if !self.enabled() {
+            return Ok(0);
+        }
+
+        let saved: SavedCache = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err),
+        };
+        if saved.prefix_len != self.prefix_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "path cache was saved with prefix length {}, not {}",
+                    saved.prefix_len, self.prefix_len
+                ),
+            ));
+        }
+
+        let now_ms = clock.since_epoch.as_millis() as u64;
+        let mut loaded = 0;
+        for path in saved.paths {
+            // estimates from the future were recorded under a clock that has since been set back
+            let age = Duration::from_millis(now_ms.saturating_sub(path.recorded_ms));
+            if age > self.ttl {
+                continue;
+            }
+            // shortly after a reboot, the monotonic clock may not reach back that far
+            let recorded = clock.instant.checked_sub(age).unwrap_or(clock.instant);
+            let est = PathEstimate {
+                bottle_rate: path.bottle_rate,
+                min_rtt_us: path.min_rtt_us,
+            };
+            let key = self.key(u32::from(path.prefix));
+            // estimates learned since the agent started are newer
+            self.entries.with_shard(&key, |entries| {
+                if !entries.contains_key(&key) {
+                    self.make_room(entries, clock.instant);
+                    entries.insert(
+                        key,
+                        CachedPath {
+                            est,
+                            recorded,
+                            used: recorded,
+                        },
+                    );
+                    loaded += 1;
+                }
+            });
+        }
+
+        Ok(loaded)
+    }
+}
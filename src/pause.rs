@@ -0,0 +1,42 @@
+//! Pausing flows' probing on demand.
+//!
+//! A paused flow stops looking for more bandwidth: it leaves STARTUP, and PROBE_BW paces at
+//! its current bottleneck rate estimate without up pulses, so that bulk transfers yield to
+//! interactive traffic without closing their connections. Resuming restores the flow's
+//! probing. Flows pick up a pause or resume on their next report.
+
+use crate::shard::ShardedMap;
+
+/// The socket ids of the paused flows, shared by all flows of one `BbrConfig`.
+#[derive(Clone, Default)]
+pub struct PausedFlows {
+    paused: ShardedMap<u32, ()>,
+}
+
+impl PausedFlows {
+    /// Returns whether the flow was running.
+    pub fn pause(&self, sock_id: u32) -> bool {
+        self.paused.insert(sock_id, ()).is_none()
+    }
+
+    /// Returns whether the flow was paused.
+    pub fn resume(&self, sock_id: u32) -> bool {
+        self.paused.remove(&sock_id).is_some()
+    }
+
+    pub fn is_paused(&self, sock_id: u32) -> bool {
+        self.paused.contains_key(&sock_id)
+    }
+
+    /// The socket ids of the paused flows, in order.
+    pub fn sock_ids(&self) -> Vec<u32> {
+        let mut sock_ids: Vec<_> = self
+            .paused
+            .entries()
+            .into_iter()
+            .map(|(id, ())| id)
+            .collect();
+        sock_ids.sort_unstable();
+        sock_ids
+    }
+}
@@ -0,0 +1,43 @@
+//! Up pulses started at a time an external coordinator picks.
+//!
+//! For probing experiments across hosts, e.g. measuring a shared bottleneck from several of
+//! them at once, a coordinator can tell each flow when to start its next PROBE_BW up pulse.
+//! The flow's cruise phase then lasts until that time, however long or short its cycle would
+//! otherwise run, and the cycles after it run as usual. A flow takes a scheduled probe once it
+//! knows when its current cycle started, which is from its first cycle end in PROBE_BW on; a
+//! time that passes before then is dropped.
+
+use crate::shard::ShardedMap;
+use std::time::Instant;
+
+/// When each flow with a scheduled probe starts its next up pulse, by socket id, shared by
+/// all flows of one `BbrConfig`.
+#[derive(Clone, Default)]
+pub struct ProbeSchedule {
+    at: ShardedMap<u32, Instant>,
+}
+
+impl ProbeSchedule {
+    /// Returns the flow's previously scheduled probe, which this one replaces.
+    pub fn schedule(&self, sock_id: u32, at: Instant) -> Option<Instant> {
+        self.at.insert(sock_id, at)
+    }
+
+    /// Returns the flow's scheduled probe, which it no longer takes.
+    pub fn cancel(&self, sock_id: u32) -> Option<Instant> {
+        self.at.remove(&sock_id)
+    }
+
+    pub fn get(&self, sock_id: u32) -> Option<Instant> {
+        self.at.get(&sock_id)
+    }
+
+    /// How many flows have a probe scheduled that they have not taken yet.
+    pub fn len(&self) -> usize {
+        self.at.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.at.is_empty()
+    }
+}
@@ -0,0 +1,659 @@
+//! The datapath programs flows install, generated from the configuration: which registers
+//! they have, and the CCP language text of each.
+
+use crate::datapath::DatapathKind;
+use crate::loss::{LossAccounting, REORDER_WINDOW_ACKS};
+use crate::params::{
+    HIGH_RTT_BW_WINDOW_FACTOR, MAX_BW_WINDOW_ROUNDS, MIN_RATE_SAMPLE_ACKS, PROGRAM_VERSION,
+};
+use crate::rate::RateEstimator;
+use crate::BbrConfig;
+use std::collections::{BTreeMap, HashMap};
+
+impl BbrConfig {
+    /// The registers of each program that flows set, by program name. Besides these, flows
+    /// set `Cwnd` and `Rate`.
+    pub fn program_parameters(&self) -> BTreeMap<&'static str, Vec<&'static str>> {
+        let mut probe_bw = if self.pacing {
+            vec![
+                "cwndCap",
+                "bottleRate",
+                "threeFourthsRate",
+                "fiveFourthsRate",
+            ]
+        } else {
+            vec!["bdpCwnd", "threeFourthsCwnd", "fiveFourthsCwnd"]
+        };
+        if self.probe_bw_ramp {
+            probe_bw.push(if self.pacing {
+                "nineEighthsRate"
+            } else {
+                "nineEighthsCwnd"
+            });
+        }
+        if self.pulse_length.is_some() {
+            probe_bw.push("pulseUs");
+        }
+        if self.pulse_shift {
+            probe_bw.extend(["pulseShiftUs", "updateNonce"]);
+        }
+        if self.high_rtt_threshold.is_some() {
+            probe_bw.extend(["cycleRounds", "bwLongFilter"]);
+        }
+        if self.schedule_probes {
+            probe_bw.push("probeAtUs");
+        }
+        probe_bw.push("bw0");
+
+        BTreeMap::from([
+            ("init_program", vec!["pacingGain", "initRate"]),
+            ("drain", vec!["bdpTarget"]),
+            ("probe_rtt", vec!["targetInflight", "probeRttUs"]),
+            ("probe_bw", probe_bw),
+            ("aimd", vec!["aiBytes", "minCwnd"]),
+        ])
+    }
+
+    // the rounds probe_bw's bandwidth filter keeps registers for: with the high-RTT profile,
+    // enough for the longer filter
+    pub(crate) fn bw_ring_rounds(&self) -> usize {
+        match self.high_rtt_threshold {
+            Some(_) => (self.bw_window * HIGH_RTT_BW_WINDOW_FACTOR).min(MAX_BW_WINDOW_ROUNDS),
+            None => self.bw_window,
+        }
+    }
+
+    /// The datapath programs for this configuration, by name, before any flow substitutes
+    /// initial register values.
+    pub fn programs(&self) -> HashMap<&'static str, String> {
+        // user-space datapaths may not sample losses, reordering or retransmission timeouts.
+        // a later ack that acks packets out of order takes them back out of the losses; the
+        // windowed accounting holds losses as suspect until enough acks confirm them. the
+        // kernel's packet counts also give the MSS, as the most bytes per packet an ack acked
+        let sample_loss = "(:= Report.acked (+ Report.acked Ack.packets_acked))
+                    (:= Report.misordered (+ Report.misordered Ack.packets_misordered))
+                    (:= Report.timeout (max Report.timeout Flow.was_timeout))
+                    (:= Report.mss (max Report.mss (/ Ack.bytes_acked (max Ack.packets_acked 1))))";
+        let mss_field = match self.datapath {
+            DatapathKind::Kernel => "(volatile mss 0)",
+            DatapathKind::Quic => "",
+        };
+        let (loss_def, accumulate_loss, confirm_loss) = match (self.datapath, self.loss_accounting) {
+            (DatapathKind::Quic, _) => (String::new(), String::new(), String::new()),
+            (DatapathKind::Kernel, LossAccounting::Raw) => (
+                String::new(),
+                format!(
+                    "(:= Report.loss (+ Report.loss Ack.lost_pkts_sample))
+                    {sample_loss}"
+                ),
+                String::new(),
+            ),
+            (DatapathKind::Kernel, LossAccounting::Net) => (
+                String::new(),
+                format!(
+                    "(:= Report.loss (+ Report.loss Ack.lost_pkts_sample))
+                    (:= Report.loss (- (max Report.loss Ack.packets_misordered) Ack.packets_misordered))
+                    {sample_loss}"
+                ),
+                String::new(),
+            ),
+            (DatapathKind::Kernel, LossAccounting::Windowed) => (
+                String::from(
+                    "(suspectLoss 0)
+                    (suspectAcks 0)",
+                ),
+                format!(
+                    "(:= suspectLoss (+ suspectLoss Ack.lost_pkts_sample))
+                    (:= suspectLoss (- (max suspectLoss Ack.packets_misordered) Ack.packets_misordered))
+                    {sample_loss}"
+                ),
+                format!(
+                    "
+                (when (== suspectLoss 0)
+                    (:= suspectAcks 0)
+                    (fallthrough)
+                )
+                (when (> suspectLoss 0)
+                    (:= suspectAcks (+ suspectAcks 1))
+                    (fallthrough)
+                )
+                (when (|| (> suspectAcks {REORDER_WINDOW_ACKS}) (> Flow.was_timeout 0))
+                    (:= Report.loss (+ Report.loss suspectLoss))
+                    (:= suspectLoss 0)
+                    (:= suspectAcks 0)
+                    (fallthrough)
+                )"
+                ),
+            ),
+        };
+
+        // take the datapath's rates, divide the bytes acked since the last report by the time
+        // since, or both. drain can report on its first ack, so the interval is at least 1us
+        let (rate_fields, accumulate_estimate, report_estimate) = match self.rate_estimator {
+            RateEstimator::Flow => (
+                "",
+                "(:= Report.rateOut (max Report.rateOut Flow.rate_outgoing))
+                    (:= Report.rateIn (max Report.rateIn Flow.rate_incoming))",
+                "",
+            ),
+            RateEstimator::Delivered => (
+                "(volatile delivered 0)",
+                "(:= Report.delivered (+ Report.delivered Ack.bytes_acked))",
+                "(:= Report.rateOut (/ (* Report.delivered 1000000) (max Report.spanUs 1)))
+                    (:= Report.rateIn Report.rateOut)",
+            ),
+            RateEstimator::Auto => (
+                "(volatile delivered 0)
+                        (volatile deliveryRate 0)",
+                "(:= Report.rateOut (max Report.rateOut Flow.rate_outgoing))
+                    (:= Report.rateIn (max Report.rateIn Flow.rate_incoming))
+                    (:= Report.delivered (+ Report.delivered Ack.bytes_acked))",
+                "(:= Report.deliveryRate (/ (* Report.delivered 1000000) (max Report.spanUs 1)))",
+            ),
+        };
+        // whatever the estimator, every report but PROBE_RTT's carries the bytes acked since the
+        // program was installed and the time it covers, to recompute the rate from
+        let delivered_field = format!(
+            "(deliveredTotal 0)
+                        (volatile spanUs 0)
+                        {rate_fields}"
+        );
+        let delivery_def = "(deliveryStart 0)";
+        let accumulate_rate = format!(
+            "(:= Report.deliveredTotal (+ Report.deliveredTotal Ack.bytes_acked))
+                    {accumulate_estimate}"
+        );
+        let report_rate = format!(
+            "(:= Report.spanUs (- Micros deliveryStart))
+                    {report_estimate}
+                    (:= deliveryStart Micros)"
+        );
+        let restart_rate = "(:= deliveryStart 0)";
+
+        // TCP-style RTT smoothing over the program's lifetime, seeded with the first sample;
+        // the variance is updated from the old average first
+        let seed_rtt = "(when (&& (== Report.srtt 0) (> Flow.rtt_sample_us 0))
+                    (:= Report.srtt Flow.rtt_sample_us)
+                    (:= Report.rttVar (/ Flow.rtt_sample_us 2))
+                    (fallthrough)
+                )";
+        let smooth_rtt = "(:= Report.rttVar (/ (+ (* Report.rttVar 3)
+                        (- (max Report.srtt Flow.rtt_sample_us) (min Report.srtt Flow.rtt_sample_us))) 4))
+                    (:= Report.srtt (/ (+ (* Report.srtt 7) Flow.rtt_sample_us) 8))";
+
+        // data was waiting, but less than half the paced BDP was in flight: the receive
+        // window held the flow back
+        let receiver_limited = "
+                (when (&& (> Flow.bytes_pending 0)
+                          (< (* Flow.bytes_in_flight 2000000) (* Rate Flow.rtt_sample_us)))
+                    (:= Report.rwndLimited 1)
+                    (fallthrough)
+                )";
+
+        // without pacing, the pulse moves cwnd around the BDP instead of the rate
+        let (probe_bw_def, pulse_down, pulse_cruise, pulse_cap, pulse_up, ramp_def, ramp_up) =
+            if self.pacing {
+                (
+                    "(cwndCap 0)
+                    (bottleRate 0)
+                    (threeFourthsRate 0)
+                    (fiveFourthsRate 0)",
+                    "(:= Rate threeFourthsRate)",
+                    "(:= Rate bottleRate)",
+                    "(:= Cwnd cwndCap)",
+                    "(:= Rate fiveFourthsRate)",
+                    "(nineEighthsRate 0)",
+                    "(:= Rate nineEighthsRate)",
+                )
+            } else {
+                (
+                    "(bdpCwnd 0)
+                    (threeFourthsCwnd 0)
+                    (fiveFourthsCwnd 0)",
+                    "(:= Cwnd threeFourthsCwnd)",
+                    "(:= Cwnd bdpCwnd)",
+                    "",
+                    "(:= Cwnd fiveFourthsCwnd)",
+                    "(nineEighthsCwnd 0)",
+                    "(:= Cwnd nineEighthsCwnd)",
+                )
+            };
+
+        // pulse phases last multiples of the min RTT, or of a fixed length; the up pulse ends
+        // one pulse into the cycle, the down pulse `down_phase_end` pulses in, and the cruise
+        // phase with the cycle
+        let (pulse_def, pulse) = match self.pulse_length {
+            Some(_) => ("(pulseUs 0)", "pulseUs"),
+            None => ("", "Report.minrtt"),
+        };
+        let down_phase_end = self.down_phase_end;
+
+        // the program reports the last nonce the agent wrote and how long ago its first ack
+        // saw it, which the agent tells the install latency from, and the ends of the up and
+        // down pulses wait for the shift it derives from it. the age is zero once the cycle's
+        // end restarts Micros, by when the nonce has been reported
+        let (nonce_field, nonce_def, echo_nonce, age_nonce, up_end, down_end, ramp_end) =
+            if self.pulse_shift {
+                (
+                    "(nonce 0)
+                        (nonceAgeUs 0)",
+                    "(updateNonce 0)
+                    (nonceSeenUs 0)
+                    (pulseShiftUs 0)",
+                    "
+                (when (> updateNonce Report.nonce)
+                    (:= Report.nonce updateNonce)
+                    (:= nonceSeenUs Micros)
+                    (fallthrough)
+                )",
+                    "(:= Report.nonceAgeUs (- (max Micros nonceSeenUs) nonceSeenUs))",
+                    format!("(+ {pulse} pulseShiftUs)"),
+                    format!("(+ (* {pulse} {down_phase_end}) pulseShiftUs)"),
+                    format!("(+ (/ {pulse} 2) pulseShiftUs)"),
+                )
+            } else {
+                (
+                    "",
+                    "",
+                    "",
+                    "",
+                    String::from(pulse),
+                    format!("(* {pulse} {down_phase_end})"),
+                    format!("(/ {pulse} 2)"),
+                )
+            };
+
+        // with the ramp, the up pulse spends half a pulse in pulse state 3 first
+        let (first_pulse, ramp_def, pulse_start, pulse_ramp) = if self.probe_bw_ramp {
+            (
+                3,
+                ramp_def,
+                format!(
+                    "(:= pulseState 3)
+                    {pulse_cap}
+                    {ramp_up}"
+                ),
+                format!(
+                    "
+                (when (&& (> Micros {ramp_end}) (== pulseState 3))
+                    (:= pulseState 0)
+                    {pulse_up}
+                )"
+                ),
+            )
+        } else {
+            (
+                0,
+                "",
+                format!(
+                    "(:= pulseState 0)
+                    {pulse_cap}
+                    {pulse_up}"
+                ),
+                String::new(),
+            )
+        };
+
+        // with `track_cwnd`, every ack sets cwnd from the current pulse's register, so that an
+        // update takes effect before the pulse ends
+        let track_cwnd = match (self.track_cwnd, self.pacing) {
+            (false, _) => String::new(),
+            (true, true) => String::from(
+                "
+                (when true
+                    (:= Cwnd cwndCap)
+                    (fallthrough)
+                )",
+            ),
+            (true, false) => {
+                let mut windows = vec![
+                    (0, "fiveFourthsCwnd"),
+                    (1, "threeFourthsCwnd"),
+                    (2, "bdpCwnd"),
+                ];
+                if self.probe_bw_ramp {
+                    windows.push((3, "nineEighthsCwnd"));
+                }
+                windows
+                    .into_iter()
+                    .map(|(state, cwnd)| {
+                        format!(
+                            "
+                (when (== pulseState {state})
+                    (:= Cwnd {cwnd})
+                    (fallthrough)
+                )"
+                        )
+                    })
+                    .collect()
+            }
+        };
+
+        // the bandwidth filter: each pulse-length round's delivery rate goes into a ring of the
+        // last bw_window rounds, and every report carries their maximum, so the rounds
+        // between reports count as well. the end of a cycle also ends its last round. a round
+        // lasts until it has seen MIN_RATE_SAMPLE_ACKS acks, and the end of a cycle drops a
+        // round that has not. programs with the high-RTT profile keep a longer ring, whose older
+        // rounds only count with bwLongFilter set, and take the cycle's rounds from cycleRounds
+        let ring_len = self.bw_ring_rounds();
+        let (cycle_def, cycle_rounds) = match self.high_rtt_threshold {
+            Some(_) => (
+                "(cycleRounds 0)
+                    (bwLongFilter 0)",
+                String::from("cycleRounds"),
+            ),
+            None => ("", self.cruise_phase_end.to_string()),
+        };
+        // a scheduled probe ends the cruise phase at probeAtUs instead, and is cleared as the
+        // next cycle starts
+        let cycle_over = format!("(> Micros (* {pulse} {cycle_rounds}))");
+        let (schedule_def, cycle_over, clear_schedule) = if self.schedule_probes {
+            (
+                "(probeAtUs 0)",
+                format!(
+                    "(|| (&& (== probeAtUs 0) {cycle_over}) (&& (> probeAtUs 0) (> Micros probeAtUs)))"
+                ),
+                "(:= probeAtUs 0)",
+            )
+        } else {
+            ("", cycle_over, "")
+        };
+        let bw_ring_def = (0..ring_len)
+            .map(|i| format!("(bw{i} 0)"))
+            .collect::<Vec<_>>()
+            .join("\n                    ");
+        let bw_ring_shift = (1..ring_len)
+            .rev()
+            .map(|i| format!("(:= bw{i} bw{})", i - 1))
+            .collect::<Vec<_>>()
+            .join("\n                    ");
+        let mut bw_ring_max =
+            (1..self.bw_window).fold(String::from("bw0"), |max, i| format!("(max {max} bw{i})"));
+        if ring_len > self.bw_window {
+            let long_max = (self.bw_window + 1..ring_len)
+                .fold(format!("bw{}", self.bw_window), |max, i| {
+                    format!("(max {max} bw{i})")
+                });
+            bw_ring_max = format!("(max {bw_ring_max} (* {long_max} bwLongFilter))");
+        }
+        let round_min_acks = MIN_RATE_SAMPLE_ACKS - 1;
+        let bw_round = format!(
+            "
+                (when (&& (> roundAcks {round_min_acks})
+                          (|| (> (- Micros roundStart) {pulse})
+                              (&& {cycle_over} (== pulseState 2))))
+                    {bw_ring_shift}
+                    (:= bw0 (/ (* roundDelivered 1000000) (max (- Micros roundStart) 1)))
+                    (:= roundDelivered 0)
+                    (:= roundAcks 0)
+                    (:= roundStart Micros)
+                    (:= Report.maxRate {bw_ring_max})
+                    (fallthrough)
+                )"
+        );
+
+        let drain_done = if self.drain_to_target {
+            "(|| (< Flow.bytes_in_flight bdpTarget) (== Flow.bytes_in_flight bdpTarget))"
+        } else {
+            "(> Micros Report.minrtt)"
+        };
+
+        vec![
+            // until its first report, init_program paces at pacingGain, the STARTUP gain in
+            // millionths, times cwnd over the latest RTT sample; the flow sets Rate after that
+            (
+                "init_program",
+                format!(
+                    "
+                (def
+                    (Report 
+                        (volatile loss 0)
+                        (volatile acked 0)
+                        (volatile misordered 0)
+                        (volatile timeout 0)
+                        (minrtt +infinity)
+                        (volatile rateOut 0)
+                        (volatile rateIn 0)
+                        (pulseState 0)
+                        (volatile rwndLimited 0)
+                        (srtt 0)
+                        (rttVar 0)
+                        {delivered_field}
+                        {mss_field}
+                        (version {PROGRAM_VERSION})
+                    )
+                    {delivery_def}
+                    {loss_def}
+                    (pacingGain 0)
+                    (initRate 0)
+                )
+                {seed_rtt}
+                (when true
+                    {accumulate_loss}
+                    (:= Report.minrtt (min Report.minrtt Flow.rtt_sample_us))
+                    {smooth_rtt}
+                    {accumulate_rate}
+                    (:= Report.pulseState 5)
+                    (fallthrough)
+                ){confirm_loss}
+                (when (&& (> pacingGain 0) (> Flow.rtt_sample_us 0))
+                    (:= initRate (max initRate (/ (* Cwnd pacingGain) Flow.rtt_sample_us)))
+                    (:= Rate initRate)
+                    (fallthrough)
+                ){receiver_limited}
+                (when (> Micros Report.minrtt)
+                    {report_rate}
+                    (:= Micros 0)
+                    {restart_rate}
+                    (:= pacingGain 0)
+                    (report)
+                )
+            ",
+                ),
+            ),
+            (
+                "probe_rtt",
+                format!(
+                    "
+		(def 
+		    (Report (volatile minrtt +infinity) (version {PROGRAM_VERSION}))
+		    (volatile target_inflight_reached 0)
+		    (targetInflight 0)
+		    (probeRttUs 0)
+		)
+		(when true
+		    (:= Report.minrtt (min Report.minrtt Flow.rtt_sample_us))
+		    (fallthrough)
+		)
+		(when (&& (== target_inflight_reached 0)
+			  (|| (< Flow.bytes_in_flight targetInflight) (== Flow.bytes_in_flight targetInflight)))
+		    (:= target_inflight_reached 1)
+		    (:= Micros 0)
+		)
+		(when (&& (== target_inflight_reached 1) 
+		          (&& (> Micros Flow.rtt_sample_us) (> Micros probeRttUs))
+                      )
+                    (:= Micros 0)
+		    (report)
+		)
+            ",
+                ),
+            ),
+            (
+                "drain",
+                format!(
+                    "
+                (def
+                    (Report
+                        (volatile loss 0)
+                        (volatile acked 0)
+                        (volatile misordered 0)
+                        (volatile timeout 0)
+                        (minrtt +infinity)
+                        (volatile rateOut 0)
+                        (volatile rateIn 0)
+                        (pulseState 0)
+                        (volatile rwndLimited 0)
+                        (srtt 0)
+                        (rttVar 0)
+                        {delivered_field}
+                        {mss_field}
+                        (version {PROGRAM_VERSION})
+                    )
+                    (bdpTarget 0)
+                    {delivery_def}
+                    {loss_def}
+                )
+                {seed_rtt}
+                (when true
+                    {accumulate_loss}
+                    (:= Report.minrtt (min Report.minrtt Flow.rtt_sample_us))
+                    {smooth_rtt}
+                    {accumulate_rate}
+                    (fallthrough)
+                ){confirm_loss}{receiver_limited}
+                (when {drain_done}
+                    {report_rate}
+                    (report)
+                )
+            ",
+                ),
+            ),
+            // loss halves cwnd at most once per report, so once per round; on datapaths that do
+            // not sample losses, cwnd only grows
+            (
+                "aimd",
+                format!(
+                    "
+                (def
+                    (Report
+                        (volatile loss 0)
+                        (volatile acked 0)
+                        (volatile misordered 0)
+                        (volatile timeout 0)
+                        (minrtt +infinity)
+                        (volatile rateOut 0)
+                        (volatile rateIn 0)
+                        (pulseState 0)
+                        (volatile rwndLimited 0)
+                        (srtt 0)
+                        (rttVar 0)
+                        (volatile inflight 0)
+                        {delivered_field}
+                        {mss_field}
+                        (version {PROGRAM_VERSION})
+                    )
+                    (aiBytes 0)
+                    (minCwnd 0)
+                    (volatile cut 0)
+                    {delivery_def}
+                    {loss_def}
+                )
+                {seed_rtt}
+                (when true
+                    {accumulate_loss}
+                    (:= Report.minrtt (min Report.minrtt Flow.rtt_sample_us))
+                    {smooth_rtt}
+                    (:= Report.inflight (max Report.inflight Flow.bytes_in_flight))
+                    {accumulate_rate}
+                    (:= Cwnd (+ Cwnd (/ (* Ack.bytes_acked aiBytes) (max Cwnd 1))))
+                    (fallthrough)
+                ){confirm_loss}
+                (when (&& (> Report.loss 0) (== cut 0))
+                    (:= Cwnd (max (/ Cwnd 2) minCwnd))
+                    (:= cut 1)
+                    (fallthrough)
+                )
+                (when (> Micros Report.minrtt)
+                    {report_rate}
+                    (:= Micros 0)
+                    {restart_rate}
+                    (report)
+                )
+            ",
+                ),
+            ),
+            (
+                "probe_bw",
+                format!(
+                    "
+                (def
+                    (Report 
+                        (volatile loss 0)
+                        (volatile acked 0)
+                        (volatile misordered 0)
+                        (volatile timeout 0)
+                        (volatile minrtt +infinity)
+                        (volatile rateOut 0)
+                        (volatile rateIn 0)
+                        (pulseState 0)
+                        (volatile rwndLimited 0)
+                        (srtt 0)
+                        (rttVar 0)
+                        (volatile inflight 0)
+                        (maxRate 0)
+                        (volatile acks 0)
+                        {delivered_field}
+                        {mss_field}
+                        {nonce_field}
+                        (version {PROGRAM_VERSION})
+                    )
+                    (pulseState {first_pulse})
+                    {probe_bw_def}
+                    {ramp_def}
+                    {pulse_def}
+                    {nonce_def}
+                    {cycle_def}
+                    {schedule_def}
+                    {delivery_def}
+                    (roundStart 0)
+                    (roundDelivered 0)
+                    (roundAcks 0)
+                    {loss_def}
+                    {bw_ring_def}
+                )
+                {seed_rtt}{echo_nonce}
+                (when true
+                    {accumulate_loss}
+                    (:= Report.minrtt (min Report.minrtt Flow.rtt_sample_us))
+                    {smooth_rtt}
+                    {age_nonce}
+                    (:= Report.pulseState pulseState)
+                    (:= Report.inflight (max Report.inflight Flow.bytes_in_flight))
+                    (:= roundDelivered (+ roundDelivered Ack.bytes_acked))
+                    (:= roundAcks (+ roundAcks 1))
+                    (:= Report.acks (+ Report.acks 1))
+                    {accumulate_rate}
+                    (fallthrough)
+                ){confirm_loss}{receiver_limited}{track_cwnd}{bw_round}
+                (when (&& (> Micros {up_end}) (== pulseState 0))
+                    {pulse_down}
+                    (:= pulseState 1)
+                    {report_rate}
+                    (report)
+                )
+                (when (&& (> Micros {down_end}) (== pulseState 1))
+                    {pulse_cruise}
+                    (:= pulseState 2)
+                    {report_rate}
+                    (report)
+                )
+                (when (&& {cycle_over} (== pulseState 2))
+                    {pulse_start}
+                    {report_rate}
+                    (:= Micros 0)
+                    (:= roundStart 0)
+                    (:= roundDelivered 0)
+                    (:= roundAcks 0)
+                    {clear_schedule}
+                    {restart_rate}
+                    (report)
+                ){pulse_ramp}
+	    ",
+                ),
+            ),
+        ]
+        .into_iter()
+        .collect()
+    }
+}
@@ -0,0 +1,360 @@
+//! Python bindings, for driving the control logic and the simulation from scripts.
+//!
+//! Build an importable module with
+//! `cargo rustc --release --lib --features python,pyo3/extension-module --crate-type cdylib`,
+//! then copy
+//! `target/release/libccp_bbr.so` to `ccp_bbr.so` on the Python path.
+//!
+//! Actions are `(program, fields)` tuples, where `program` is `None` for an update of the
+//! installed program's registers. Modes are named as in the logs, e.g. `"PROBE_BW"`.
+
+use crate::sim::{self, Link, SimFlow};
+use crate::{Action, BbrConfig, BbrCore, BbrMode, Measurement};
+use portus::{CongAlgBuilder, DatapathInfo};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+type PyAction = (Option<&'static str>, HashMap<&'static str, u64>);
+
+fn py_actions(actions: Vec<Action>) -> Vec<PyAction> {
+    actions
+        .into_iter()
+        .map(|action| match action {
+            Action::SetProgram { program, fields } => (Some(program), fields.into_iter().collect()),
+            Action::Update(fields) => (None, fields.into_iter().collect()),
+        })
+        .collect()
+}
+
+fn mode_name(mode: BbrMode) -> &'static str {
+    match mode {
+        BbrMode::Startup => "STARTUP",
+        BbrMode::Drain => "DRAIN",
+        BbrMode::ProbeBw => "PROBE_BW",
+        BbrMode::ProbeRtt => "PROBE_RTT",
+    }
+}
+
+fn seconds(s: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(s).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// A `BbrConfig`, from the `bbr` binary's flags: `Config(["--startup_gain", "2.5"])`.
+#[pyclass(name = "Config")]
+struct Config {
+    cfg: BbrConfig,
+}
+
+#[pymethods]
+impl Config {
+    #[new]
+    #[pyo3(signature = (flags = Vec::new()))]
+    fn new(flags: Vec<String>) -> PyResult<Self> {
+        let matches = BbrConfig::args()
+            .get_matches_from_safe(std::iter::once(String::from("bbr")).chain(flags))
+            .map_err(|e| PyValueError::new_err(e.message))?;
+        let cfg = BbrConfig::from_arg_matches(&matches)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(Config { cfg })
+    }
+
+    /// The datapath programs, by name.
+    fn programs(&self) -> HashMap<&'static str, String> {
+        self.cfg.programs()
+    }
+}
+
+/// A `BbrCore`, fed reports by hand. Times are microseconds on any monotonic clock.
+#[pyclass(name = "Flow", unsendable)]
+struct Flow {
+    core: BbrCore,
+    epoch: Instant,
+    epoch_us: u64,
+}
+
+#[pymethods]
+impl Flow {
+    #[new]
+    #[pyo3(signature = (config, now_us = 0, sock_id = 1, init_cwnd = 14_600, mss = 1_460, dst_ip = 0))]
+    fn new(
+        config: &Config,
+        now_us: u64,
+        sock_id: u32,
+        init_cwnd: u32,
+        mss: u32,
+        dst_ip: u32,
+    ) -> Self {
+        let info = DatapathInfo {
+            sock_id,
+            init_cwnd,
+            mss,
+            src_ip: 0,
+            src_port: 0,
+            dst_ip,
+            dst_port: 0,
+        };
+        let epoch = Instant::now();
+        Flow {
+            core: BbrCore::new(&config.cfg, &info, epoch),
+            epoch,
+            epoch_us: now_us,
+        }
+    }
+
+    /// The actions that start the flow.
+    fn start(&self) -> Vec<PyAction> {
+        py_actions(self.core.start())
+    }
+
+    /// Reports from any other program instance than the last one installed are ignored.
+    fn program_installed(&mut self, program_uid: u32) {
+        self.core.program_installed(program_uid);
+    }
+
+    /// Handles one report, with the fields of `Measurement`, and returns the actions to apply.
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (
+        now_us,
+        program_uid,
+        minrtt_us,
+        rate_outgoing = 0.0,
+        rate_incoming = 0.0,
+        delivery_rate = 0.0,
+        loss = 0,
+        misordered = 0,
+        timeout = false,
+        pulse_state = 0,
+        receiver_limited = false,
+        inflight_bytes = 0,
+        srtt_us = 0,
+        rttvar_us = 0,
+        max_rate = 0.0,
+        acked = 0,
+        acks = 0,
+        nonce = 0,
+        nonce_age_us = 0,
+        delivered_total = 0,
+        span_us = 0,
+        mss = 0
+    ))]
+    fn on_report(
+        &mut self,
+        now_us: u64,
+        program_uid: u32,
+        minrtt_us: u32,
+        rate_outgoing: f64,
+        rate_incoming: f64,
+        delivery_rate: f64,
+        loss: u32,
+        misordered: u32,
+        timeout: bool,
+        pulse_state: u32,
+        receiver_limited: bool,
+        inflight_bytes: u32,
+        srtt_us: u32,
+        rttvar_us: u32,
+        max_rate: f64,
+        acked: u32,
+        acks: u32,
+        nonce: u32,
+        nonce_age_us: u32,
+        delivered_total: u64,
+        span_us: u32,
+        mss: u32,
+    ) -> Vec<PyAction> {
+        let m = Measurement {
+            program_uid,
+            minrtt_us,
+            loss,
+            acked,
+            misordered,
+            timeout,
+            rate_outgoing,
+            rate_incoming,
+            delivery_rate,
+            pulse_state,
+            receiver_limited,
+            inflight_bytes,
+            srtt_us,
+            rttvar_us,
+            max_rate,
+            acks,
+            nonce,
+            nonce_age_us,
+            delivered_total,
+            span_us,
+            mss,
+            received: None,
+        };
+        let now = self.epoch + Duration::from_micros(now_us.saturating_sub(self.epoch_us));
+        py_actions(self.core.on_measurement(now, m))
+    }
+
+    /// Stops the flow's probing, and returns the actions to apply.
+    fn pause(&mut self) -> Vec<PyAction> {
+        py_actions(self.core.pause())
+    }
+
+    fn resume(&mut self) -> Vec<PyAction> {
+        py_actions(self.core.resume())
+    }
+
+    /// Starts the flow's next up pulse at `at_us`, on the clock of `now_us`, and returns the
+    /// actions to apply.
+    fn schedule_probe(&mut self, now_us: u64, at_us: u64) -> Vec<PyAction> {
+        let now = self.epoch + Duration::from_micros(now_us.saturating_sub(self.epoch_us));
+        let at = self.epoch + Duration::from_micros(at_us.saturating_sub(self.epoch_us));
+        py_actions(self.core.schedule_probe(now, at))
+    }
+
+    /// Takes up the flow's new MSS, e.g. after path MTU discovery shrank it, and returns the
+    /// actions to apply.
+    fn set_mss(&mut self, mss: u32) -> Vec<PyAction> {
+        py_actions(self.core.set_mss(mss))
+    }
+
+    #[getter]
+    fn mode(&self) -> &'static str {
+        mode_name(self.core.mode())
+    }
+
+    /// Bytes per second.
+    #[getter]
+    fn bottle_rate(&self) -> f64 {
+        self.core.bottle_rate()
+    }
+
+    #[getter]
+    fn min_rtt_us(&self) -> u32 {
+        self.core.min_rtt_us()
+    }
+
+    #[getter]
+    fn estimated_bdp_bytes(&self) -> u64 {
+        self.core.estimated_bdp_bytes()
+    }
+
+    #[getter]
+    fn pipe_full(&self) -> bool {
+        self.core.pipe_full()
+    }
+
+    #[getter]
+    fn srtt_us(&self) -> u32 {
+        self.core.srtt_us()
+    }
+}
+
+fn flow_dict<'py>(py: Python<'py>, flow: &SimFlow) -> PyResult<&'py PyDict> {
+    let core = flow.core();
+    let d = PyDict::new(py);
+    d.set_item("mode", mode_name(core.mode()))?;
+    d.set_item(
+        "transition_reason",
+        core.transition_reason()
+            .map(|reason| format!("{:?}", reason)),
+    )?;
+    d.set_item("bottle_rate", core.bottle_rate())?;
+    d.set_item("min_rtt_us", core.min_rtt_us())?;
+    d.set_item("estimated_bdp_bytes", core.estimated_bdp_bytes())?;
+    d.set_item("pipe_full", core.pipe_full())?;
+    d.set_item("srtt_us", core.srtt_us())?;
+    d.set_item("send_rate", flow.send_rate())?;
+    d.set_item("delivered_bytes", flow.delivered_bytes())?;
+    d.set_item("lost_bytes", flow.lost_bytes())?;
+    Ok(d)
+}
+
+/// BBR flows sharing a simulated bottleneck; see `sim::Simulation`. Rates are in bytes per
+/// second and times in seconds unless their names say otherwise.
+#[pyclass(name = "Simulation", unsendable)]
+struct Simulation {
+    sim: sim::Simulation,
+}
+
+#[pymethods]
+impl Simulation {
+    #[new]
+    #[pyo3(signature = (rate, buffer, base_rtt_ms, tick_us = sim::DEFAULT_TICK_US))]
+    fn new(rate: f64, buffer: f64, base_rtt_ms: f64, tick_us: u64) -> PyResult<Self> {
+        let link = Link {
+            rate,
+            buffer,
+            base_rtt: seconds(base_rtt_ms / 1e3)?,
+        };
+        Ok(Simulation {
+            sim: sim::Simulation::new(link).with_tick(Duration::from_micros(tick_us)),
+        })
+    }
+
+    /// Starts a flow and returns its index.
+    fn add_flow(&mut self, config: &Config) -> usize {
+        self.sim.add_flow(&config.cfg)
+    }
+
+    fn run_for(&mut self, seconds: f64) -> PyResult<()> {
+        self.sim.run_for(self::seconds(seconds)?);
+        Ok(())
+    }
+
+    /// Runs for `seconds` and returns a sample of the link and every flow each `every_ms`,
+    /// for plotting.
+    fn trace<'py>(
+        &mut self,
+        py: Python<'py>,
+        seconds: f64,
+        every_ms: f64,
+    ) -> PyResult<Vec<&'py PyDict>> {
+        let every = self::seconds(every_ms / 1e3)?;
+        if every.is_zero() {
+            return Err(PyValueError::new_err("every_ms must be positive"));
+        }
+
+        let end = self.sim.elapsed() + self::seconds(seconds)?;
+        let mut samples = vec![];
+        while self.sim.elapsed() < end {
+            self.sim.run_for(every);
+            let sample = PyDict::new(py);
+            sample.set_item("t", self.sim.elapsed().as_secs_f64())?;
+            sample.set_item("queue_bytes", self.sim.queue_bytes())?;
+            sample.set_item("rtt_us", self.sim.rtt().as_micros() as u64)?;
+            sample.set_item("flows", self.flows(py)?)?;
+            samples.push(sample);
+        }
+        Ok(samples)
+    }
+
+    fn flows<'py>(&self, py: Python<'py>) -> PyResult<Vec<&'py PyDict>> {
+        self.sim
+            .flows()
+            .iter()
+            .map(|flow| flow_dict(py, flow))
+            .collect()
+    }
+
+    #[getter]
+    fn elapsed(&self) -> f64 {
+        self.sim.elapsed().as_secs_f64()
+    }
+
+    #[getter]
+    fn queue_bytes(&self) -> f64 {
+        self.sim.queue_bytes()
+    }
+
+    #[getter]
+    fn rtt_us(&self) -> u64 {
+        self.sim.rtt().as_micros() as u64
+    }
+}
+
+#[pymodule]
+fn ccp_bbr(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Config>()?;
+    m.add_class::<Flow>()?;
+    m.add_class::<Simulation>()?;
+    Ok(())
+}
@@ -0,0 +1,118 @@
+//! Checking that the host paces what kernel datapaths send.
+//!
+//! The CCP kernel module hands the `Rate` a flow installs to the kernel as the socket's pacing
+//! rate, which only the `fq` qdisc enforces: on an interface with another qdisc, such as
+//! `pfifo_fast` or `fq_codel`, which schedule packets but do not pace them, flows send their
+//! windows in bursts whatever rate they install. With `--features qdisc`, the agent lists the
+//! host's qdiscs with `tc` when it starts and every `QDISC_CHECK_INTERVAL_SECONDS` after, and
+//! warns about the interfaces that are up and queue packets but have no `fq`, which the
+//! transports' capabilities then report. Interfaces without a queue, such as loopback, are
+//! left out.
+
+use crate::capability::DatapathCapabilities;
+use std::collections::BTreeMap;
+use std::io;
+use std::process::Command;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// qdiscs that do not queue egress packets, and so have nothing to pace
+const UNQUEUED: &[&str] = &["noqueue", "ingress", "clsact"];
+
+/// The kinds of the qdiscs `tc qdisc show` lists, by device.
+pub fn parse_qdiscs(output: &str) -> BTreeMap<String, Vec<String>> {
+    let mut qdiscs: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for line in output.lines() {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("qdisc") {
+            continue;
+        }
+        let kind = match words.next() {
+            Some(kind) => kind,
+            None => continue,
+        };
+        if let Some(dev) = words.skip_while(|&word| word != "dev").nth(1) {
+            qdiscs
+                .entry(String::from(dev))
+                .or_default()
+                .push(String::from(kind));
+        }
+    }
+    qdiscs
+}
+
+/// The devices that queue packets, but with no `fq` among their qdiscs to pace them.
+pub fn unpaced(qdiscs: &BTreeMap<String, Vec<String>>) -> Vec<String> {
+    qdiscs
+        .iter()
+        .filter(|(_, kinds)| {
+            kinds.iter().all(|kind| kind != "fq")
+                && kinds.iter().any(|kind| !UNQUEUED.contains(&kind.as_str()))
+        })
+        .map(|(dev, _)| dev.clone())
+        .collect()
+}
+
+// whether the device is up, as far as sysfs knows
+fn is_up(dev: &str) -> bool {
+    std::fs::read_to_string(format!("/sys/class/net/{}/operstate", dev))
+        .map_or(true, |state| state.trim() != "down")
+}
+
+/// The interfaces that are up and queue packets without pacing them, as `tc` lists them.
+pub fn unpaced_interfaces() -> io::Result<Vec<String>> {
+    let output = Command::new("tc").args(["qdisc", "show"]).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    let mut unpaced = unpaced(&parse_qdiscs(&String::from_utf8_lossy(&output.stdout)));
+    unpaced.retain(|dev| is_up(dev));
+    Ok(unpaced)
+}
+
+// records what a check found in every transport's capabilities, and logs what changed
+fn record(capabilities: &[DatapathCapabilities], unpaced: Vec<String>) {
+    let mut changed = false;
+    for capabilities in capabilities {
+        changed |= capabilities.set_unpaced_interfaces(unpaced.clone());
+    }
+    if !changed {
+        return;
+    }
+    if unpaced.is_empty() {
+        info!("every interface paces with fq");
+    } else {
+        warn!(
+            interfaces = ?unpaced,
+            "interfaces have no fq qdisc, so kernel flows are not paced at the rates they install"
+        );
+    }
+}
+
+/// Checks now, and then every `interval` from a thread of its own, recording the interfaces
+/// that do not pace in `capabilities`, those of the transports whose flows need pacing. A
+/// failed check is logged, unless the one before failed too, and retried as usual.
+pub fn start(capabilities: Vec<DatapathCapabilities>, interval: Duration) -> io::Result<()> {
+    let mut failed = false;
+    let mut check = move || match unpaced_interfaces() {
+        Ok(unpaced) => {
+            failed = false;
+            record(&capabilities, unpaced);
+        }
+        Err(err) => {
+            if !std::mem::replace(&mut failed, true) {
+                warn!(?err, "could not list qdiscs");
+            }
+        }
+    };
+    check();
+    std::thread::Builder::new()
+        .name(String::from("bbr-qdisc"))
+        .spawn(move || loop {
+            std::thread::sleep(interval);
+            check();
+        })?;
+    Ok(())
+}
@@ -0,0 +1,80 @@
+//! Delivery rate samples from the sending and receiving rates the programs report.
+//!
+//! On asymmetric paths, or with thinned or stretched ACKs, the incoming rate the datapath
+//! measures is bursty, so each report's maximum of the per-ACK `min(outgoing, incoming)` is
+//! noisy. The programs instead report the maximum of each rate separately, and each is
+//! smoothed here before taking the smaller one as the flow's delivery rate.
+//!
+//! Alternatively, the programs can estimate the delivery rate themselves, as the bytes
+//! acknowledged between two reports over the time between them, without relying on the
+//! datapath's rate estimates. They then report that rate as both rates. By default they report
+//! it next to the datapath's rates, which are only used while the datapath provides them.
+
+use serde::Serialize;
+use std::str::FromStr;
+
+pub use crate::params::RATE_EWMA_GAIN;
+
+/// Where the programs' rate samples come from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateEstimator {
+    /// The datapath's `Flow.rate_outgoing` and `Flow.rate_incoming`.
+    Flow,
+    /// `Ack.bytes_acked` summed over each report interval.
+    Delivered,
+    /// Both; the datapath's rates unless it turns out to leave them at zero.
+    #[default]
+    Auto,
+}
+
+impl FromStr for RateEstimator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flow" => Ok(RateEstimator::Flow),
+            "delivered" => Ok(RateEstimator::Delivered),
+            "auto" => Ok(RateEstimator::Auto),
+            _ => Err(format!(
+                "rate estimator must be one of (flow|delivered|auto): {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// Moving averages of the outgoing and incoming rates, in bytes per second.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RateFilter {
+    outgoing: Option<f64>,
+    incoming: Option<f64>,
+}
+
+fn ewma(avg: &mut Option<f64>, sample: f64) -> f64 {
+    let next = match *avg {
+        Some(prev) => prev + RATE_EWMA_GAIN * (sample - prev),
+        // don't average in the rates from before the first acks
+        None if sample > 0.0 => sample,
+        None => return 0.0,
+    };
+    *avg = Some(next);
+    next
+}
+
+impl RateFilter {
+    /// Folds in one report's rates and returns the delivery rate sample.
+    pub fn sample(&mut self, outgoing: f64, incoming: f64) -> f64 {
+        let outgoing = ewma(&mut self.outgoing, outgoing);
+        let incoming = ewma(&mut self.incoming, incoming);
+        outgoing.min(incoming)
+    }
+
+    pub fn outgoing(&self) -> f64 {
+        self.outgoing.unwrap_or_default()
+    }
+
+    pub fn incoming(&self) -> f64 {
+        self.incoming.unwrap_or_default()
+    }
+}
@@ -0,0 +1,88 @@
+//! Hints of each flow's pacing rate, for the applications sending on it.
+//!
+//! An application that adapts what it sends to the path, such as a video player picking the
+//! bitrate of its next segment, can go by the rate its flow paces at rather than measure the
+//! path itself. A subscriber to [`RateHints`] gets a [`RateHint`] for each flow once it first
+//! reports, and then whenever the flow's pacing rate moves by more than the subscriber's
+//! threshold from the rate of the last hint it got about the flow. Hints carry the flow's
+//! estimated BDP too, for applications that size what they keep in flight.
+
+use crate::bandwidth::Rate;
+use crate::flow_id::FlowId;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, RwLock};
+
+/// A flow's pacing rate and BDP, as of its latest report.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct RateHint {
+    pub id: FlowId,
+    /// See [`crate::BbrCore::pacing_rate`].
+    pub pacing_rate: Rate,
+    /// See [`crate::BbrCore::estimated_bdp_bytes`].
+    pub estimated_bdp_bytes: u64,
+}
+
+struct Subscriber {
+    id: u64,
+    threshold: f64,
+    tx: Sender<RateHint>,
+}
+
+/// The subscribers to the flows of one `BbrConfig`.
+#[derive(Clone, Default)]
+pub struct RateHints {
+    subscribers: Arc<RwLock<Vec<Subscriber>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl RateHints {
+    /// Subscribes to hints about every flow, sent when a flow's pacing rate moves by more than
+    /// `threshold`, a fraction of the rate last hinted; a zero threshold hints every change.
+    /// Hints stop once the receiver is dropped.
+    pub fn subscribe(&self, threshold: f64) -> Receiver<RateHint> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.write().unwrap().push(Subscriber {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            threshold: threshold.max(0.0),
+            tx,
+        });
+        rx
+    }
+
+    /// Sends `hint` to the subscribers it is news to; `hinted` holds the rate the flow last
+    /// hinted to each of them, and forgets those that have since unsubscribed.
+    pub(crate) fn publish(&self, hint: RateHint, hinted: &mut HashMap<u64, Rate>) {
+        let mut gone = vec![];
+        let subscribers = self.subscribers.read().unwrap();
+        if hinted.len() > subscribers.len() {
+            hinted.retain(|id, _| subscribers.iter().any(|subscriber| subscriber.id == *id));
+        }
+        for subscriber in subscribers.iter() {
+            if let Some(&last) = hinted.get(&subscriber.id) {
+                if !rate_moved(last, hint.pacing_rate, subscriber.threshold) {
+                    continue;
+                }
+            }
+            hinted.insert(subscriber.id, hint.pacing_rate);
+            if subscriber.tx.send(hint).is_err() {
+                gone.push(subscriber.id);
+            }
+        }
+        drop(subscribers);
+        if !gone.is_empty() {
+            self.subscribers
+                .write()
+                .unwrap()
+                .retain(|subscriber| !gone.contains(&subscriber.id));
+        }
+    }
+}
+
+/// Whether a pacing rate moved from `last` to `now` by more than `threshold`, a fraction of
+/// `last`.
+pub fn rate_moved(last: Rate, now: Rate, threshold: f64) -> bool {
+    (now.bytes_per_sec() - last.bytes_per_sec()).abs() > threshold * last.bytes_per_sec()
+}
@@ -0,0 +1,137 @@
+//! How many registers the programs define, and fitting them into a datapath's budget.
+//!
+//! Every `def` entry of a program, report fields included, takes one of the datapath's
+//! registers, and some datapaths have few. `probe_bw` defines the most, and each optional
+//! feature adds to it. With `register_limit`, [`BbrConfig::fit_register_limit`] turns
+//! features off at startup, in this order, until every program fits:
+//!
+//! - `pulse_shift`, whose nonces and shift take five registers;
+//! - `ramp_probe_bw`, which takes the ramp's rate or window;
+//! - `high_rtt_threshold`, whose profile takes two registers and a longer bandwidth filter;
+//! - the `auto` rate estimator, which becomes `delivered` and reports one rate less;
+//! - then `bw_window`, one round of the bandwidth filter at a time.
+//!
+//! Only the features a program over the limit defines are turned off. A configuration whose
+//! programs still do not fit is refused.
+
+use crate::datapath::DatapathKind;
+use crate::error::BbrError;
+use crate::rate::RateEstimator;
+use crate::BbrConfig;
+use std::collections::BTreeMap;
+
+/// The registers `program` defines: the entries of its `def`, report fields included.
+pub fn count_registers(program: &str) -> usize {
+    let Some(def) = program.find("(def") else {
+        return 0;
+    };
+    // each entry is a list without lists inside
+    let mut depth = 0;
+    let mut count = 0;
+    let mut innermost = false;
+    for c in program[def..].chars() {
+        match c {
+            '(' => {
+                depth += 1;
+                innermost = true;
+            }
+            ')' => {
+                if innermost {
+                    count += 1;
+                }
+                innermost = false;
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    count
+}
+
+impl BbrConfig {
+    /// The registers each program defines.
+    pub fn register_usage(&self) -> BTreeMap<&'static str, usize> {
+        self.programs()
+            .into_iter()
+            .map(|(name, program)| (name, count_registers(&program)))
+            .collect()
+    }
+
+    /// Turns features off until every program fits `register_limit` on each of `datapaths`,
+    /// as the module documentation describes, and returns the settings it changed, as flags.
+    pub fn fit_register_limit(
+        &mut self,
+        datapaths: &[DatapathKind],
+    ) -> Result<Vec<String>, BbrError> {
+        let Some(limit) = self.register_limit else {
+            return Ok(vec![]);
+        };
+        let mut traded: Vec<String> = vec![];
+        loop {
+            let (program, used) = self.most_registers(datapaths);
+            if used <= limit {
+                return Ok(traded);
+            }
+            let Some(trade) = self.trade_registers(program) else {
+                return Err(BbrError::Config(format!(
+                    "{} needs {} registers, more than the register limit of {}",
+                    program, used, limit
+                )));
+            };
+            // the filter shrinks a round at a time, but only its last size is worth telling
+            if trade.starts_with("--bw_window")
+                && traded
+                    .last()
+                    .is_some_and(|last| last.starts_with("--bw_window"))
+            {
+                traded.pop();
+            }
+            traded.push(trade);
+        }
+    }
+
+    // the program with the most registers on any of `datapaths`
+    fn most_registers(&self, datapaths: &[DatapathKind]) -> (&'static str, usize) {
+        datapaths
+            .iter()
+            .flat_map(|&datapath| {
+                BbrConfig {
+                    datapath,
+                    ..self.clone()
+                }
+                .register_usage()
+            })
+            .max_by_key(|&(_, used)| used)
+            .unwrap_or(("", 0))
+    }
+
+    // turns off the first feature in the budget's order that `program` defines registers for
+    fn trade_registers(&mut self, program: &str) -> Option<String> {
+        if program == "probe_bw" {
+            if self.pulse_shift {
+                self.pulse_shift = false;
+                return Some(String::from("--pulse_shift off"));
+            }
+            if self.probe_bw_ramp {
+                self.probe_bw_ramp = false;
+                return Some(String::from("--ramp_probe_bw off"));
+            }
+            if self.high_rtt_threshold.is_some() {
+                self.high_rtt_threshold = None;
+                return Some(String::from("--high_rtt_threshold off"));
+            }
+        }
+        if program != "probe_rtt" && self.rate_estimator == RateEstimator::Auto {
+            self.rate_estimator = RateEstimator::Delivered;
+            return Some(String::from("--rate_estimator delivered"));
+        }
+        if program == "probe_bw" && self.bw_window > 1 {
+            self.bw_window -= 1;
+            return Some(format!("--bw_window {}", self.bw_window));
+        }
+        None
+    }
+}
@@ -0,0 +1,332 @@
+//! Simulation scenarios described in YAML, for sweeping experiments without writing Rust.
+//!
+//! A scenario gives a bottleneck link and how it changes over time, the flows that start and
+//! stop on it, and the `bbr` flags they run with:
+//!
+//! ```yaml
+//! description: a second flow joins, then the link halves
+//! duration_s: 30
+//! args: ["--loss_mode", "congestion"]
+//! link: { rate_mbit: 100, rtt_ms: 20, buffer_bdp: 1.0 }
+//! link_changes:
+//!   - { at_s: 20, rate_mbit: 50 }
+//! flows:
+//!   - { name: bulk }
+//!   - { name: late, start_s: 5, stop_s: 25, args: ["--startup_gain", "2"] }
+//! ```
+//!
+//! Flows without `args` of their own share one configuration, as flows of one agent do, so
+//! that they also share its path cache and weighted shares. Running a scenario yields a
+//! [`ScenarioSample`] of every running flow each `sample_interval_ms`: its snapshot, as the
+//! agent's state dump shows it, with the link's queue and what the flow delivered.
+
+use crate::sim::{Link, Simulation, DEFAULT_TICK_US};
+use crate::snapshot::FlowSnapshot;
+use crate::BbrConfig;
+use portus::CongAlgBuilder;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+
+const DEFAULT_SAMPLE_INTERVAL_MS: f64 = 1000.0;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LinkSpec {
+    rate_mbit: f64,
+    rtt_ms: f64,
+    /// The buffer in multiples of the link's BDP, or in bytes; one BDP if neither is given.
+    buffer_bdp: Option<f64>,
+    buffer_bytes: Option<f64>,
+}
+
+/// Sets whichever of the link's rate, RTT and buffer it gives, at `at_s`. A buffer in BDPs
+/// is of the link as changed.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LinkChange {
+    at_s: f64,
+    rate_mbit: Option<f64>,
+    rtt_ms: Option<f64>,
+    buffer_bdp: Option<f64>,
+    buffer_bytes: Option<f64>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FlowSpec {
+    /// `flow<index>` if unset.
+    name: Option<String>,
+    #[serde(default)]
+    start_s: f64,
+    /// Runs to the end of the scenario if unset.
+    stop_s: Option<f64>,
+    /// Flags on top of the scenario's.
+    args: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioSpec {
+    #[serde(default)]
+    description: String,
+    duration_s: f64,
+    sample_interval_ms: Option<f64>,
+    tick_us: Option<u64>,
+    #[serde(default)]
+    args: Vec<String>,
+    link: LinkSpec,
+    #[serde(default)]
+    link_changes: Vec<LinkChange>,
+    flows: Vec<FlowSpec>,
+}
+
+/// What happens at a point in a scenario; at the same time, the link changes first, then
+/// flows stop, then they start.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Event {
+    Link(usize),
+    Stop(usize),
+    Start(usize),
+}
+
+/// One running flow at one point of a scenario.
+#[derive(Clone, Debug, Serialize)]
+pub struct ScenarioSample {
+    pub t_s: f64,
+    pub flow: String,
+    /// The link's, in bytes.
+    pub queue_bytes: f64,
+    pub rtt_us: u64,
+    /// Bytes per second offered to the link on the last tick.
+    pub send_rate: f64,
+    /// Since the flow started.
+    pub delivered_bytes: f64,
+    pub lost_bytes: f64,
+    #[serde(flatten)]
+    pub snapshot: FlowSnapshot,
+}
+
+pub struct Scenario {
+    pub description: String,
+    spec: ScenarioSpec,
+    names: Vec<String>,
+}
+
+fn mbit(rate_mbit: f64) -> f64 {
+    rate_mbit * 1e6 / 8.0
+}
+
+fn seconds(s: f64, what: &str) -> Result<Duration, String> {
+    Duration::try_from_secs_f64(s).map_err(|e| format!("{} {}: {}", what, s, e))
+}
+
+fn parse_config(args: &[String]) -> Result<BbrConfig, String> {
+    let matches = BbrConfig::args()
+        .get_matches_from_safe(std::iter::once("bbr").chain(args.iter().map(String::as_str)))
+        .map_err(|e| e.message)?;
+    BbrConfig::from_arg_matches(&matches).map_err(|e| e.to_string())
+}
+
+fn buffer(bdp: Option<f64>, bytes: Option<f64>, link: &Link) -> Result<Option<f64>, String> {
+    match (bdp, bytes) {
+        (Some(_), Some(_)) => Err(String::from(
+            "a link takes buffer_bdp or buffer_bytes, not both",
+        )),
+        (Some(bdp), None) if bdp >= 0.0 => Ok(Some(bdp * link.rate * link.base_rtt.as_secs_f64())),
+        (None, Some(bytes)) if bytes >= 0.0 => Ok(Some(bytes)),
+        (None, None) => Ok(None),
+        _ => Err(String::from("a link's buffer cannot be negative")),
+    }
+}
+
+fn positive(value: f64, what: &str) -> Result<f64, String> {
+    if value > 0.0 && value.is_finite() {
+        Ok(value)
+    } else {
+        Err(format!("{} must be positive: {}", what, value))
+    }
+}
+
+impl Scenario {
+    pub fn from_yaml(yaml: &str) -> Result<Scenario, String> {
+        let spec: ScenarioSpec = serde_yaml::from_str(yaml).map_err(|e| e.to_string())?;
+        positive(spec.duration_s, "duration_s")?;
+        positive(
+            spec.sample_interval_ms
+                .unwrap_or(DEFAULT_SAMPLE_INTERVAL_MS),
+            "sample_interval_ms",
+        )?;
+        if spec.tick_us == Some(0) {
+            return Err(String::from("tick_us must be positive"));
+        }
+        positive(spec.link.rate_mbit, "link rate_mbit")?;
+        positive(spec.link.rtt_ms, "link rtt_ms")?;
+        for change in &spec.link_changes {
+            seconds(change.at_s, "link change at_s")?;
+            if let Some(rate) = change.rate_mbit {
+                positive(rate, "link change rate_mbit")?;
+            }
+            if let Some(rtt) = change.rtt_ms {
+                positive(rtt, "link change rtt_ms")?;
+            }
+        }
+
+        let mut names = vec![];
+        let mut seen = HashSet::new();
+        for (i, flow) in spec.flows.iter().enumerate() {
+            let name = flow.name.clone().unwrap_or_else(|| format!("flow{}", i));
+            if !seen.insert(name.clone()) {
+                return Err(format!("flow {:?} is named twice", name));
+            }
+            seconds(flow.start_s, "flow start_s")?;
+            if let Some(stop) = flow.stop_s {
+                if stop <= flow.start_s {
+                    return Err(format!("flow {:?} stops before it starts", name));
+                }
+            }
+            names.push(name);
+        }
+
+        let scenario = Scenario {
+            description: spec.description.clone(),
+            spec,
+            names,
+        };
+        scenario.configs()?;
+        // the buffers are only known with the link they belong to
+        let mut link = scenario.link()?;
+        for (_, event) in scenario.events() {
+            if let Event::Link(change) = event {
+                scenario.change_link(&mut link, change)?;
+            }
+        }
+        Ok(scenario)
+    }
+
+    // the configuration of each flow, with state of its own, such as the path cache, so that
+    // every run starts afresh
+    fn configs(&self) -> Result<Vec<BbrConfig>, String> {
+        let shared = parse_config(&self.spec.args)?;
+        self.spec
+            .flows
+            .iter()
+            .zip(&self.names)
+            .map(|(flow, name)| match &flow.args {
+                Some(args) => {
+                    let args: Vec<_> = self.spec.args.iter().chain(args).cloned().collect();
+                    parse_config(&args).map_err(|e| format!("flow {:?}: {}", name, e))
+                }
+                None => Ok(shared.clone()),
+            })
+            .collect()
+    }
+
+    fn link(&self) -> Result<Link, String> {
+        let spec = &self.spec.link;
+        let mut link = Link {
+            rate: mbit(spec.rate_mbit),
+            buffer: 0.0,
+            base_rtt: seconds(spec.rtt_ms / 1e3, "link rtt_ms")?,
+        };
+        link.buffer = buffer(spec.buffer_bdp, spec.buffer_bytes, &link)?
+            .unwrap_or(link.rate * link.base_rtt.as_secs_f64());
+        Ok(link)
+    }
+
+    fn change_link(&self, link: &mut Link, change: usize) -> Result<(), String> {
+        let change = &self.spec.link_changes[change];
+        if let Some(rate) = change.rate_mbit {
+            link.rate = mbit(rate);
+        }
+        if let Some(rtt) = change.rtt_ms {
+            link.base_rtt = seconds(rtt / 1e3, "link change rtt_ms")?;
+        }
+        if let Some(buffer) = buffer(change.buffer_bdp, change.buffer_bytes, link)? {
+            link.buffer = buffer;
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<(Duration, Event)> {
+        let at = |s: f64| Duration::from_secs_f64(s);
+        let mut events: Vec<_> = self
+            .spec
+            .link_changes
+            .iter()
+            .enumerate()
+            .map(|(i, change)| (at(change.at_s), Event::Link(i)))
+            .collect();
+        for (i, flow) in self.spec.flows.iter().enumerate() {
+            events.push((at(flow.start_s), Event::Start(i)));
+            if let Some(stop) = flow.stop_s {
+                events.push((at(stop), Event::Stop(i)));
+            }
+        }
+        events.sort();
+        events
+    }
+
+    /// Runs the scenario from the start, and hands every sample to `on_sample` as it is
+    /// taken. The same scenario always yields the same samples.
+    pub fn run(&self, mut on_sample: impl FnMut(&ScenarioSample)) {
+        // checked when the scenario was parsed
+        let cfgs = self.configs().unwrap();
+        let mut sim = Simulation::new(self.link().unwrap()).with_tick(Duration::from_micros(
+            self.spec.tick_us.unwrap_or(DEFAULT_TICK_US),
+        ));
+        let interval = Duration::from_secs_f64(
+            self.spec
+                .sample_interval_ms
+                .unwrap_or(DEFAULT_SAMPLE_INTERVAL_MS)
+                / 1e3,
+        );
+        let end = Duration::from_secs_f64(self.spec.duration_s);
+        let events = self.events();
+        let mut next_event = 0;
+        // the scenario's flow behind each of the simulation's
+        let mut running: Vec<usize> = vec![];
+        let mut next_sample = interval;
+        loop {
+            while let Some(&(at, event)) = events.get(next_event) {
+                if at > sim.elapsed() {
+                    break;
+                }
+                next_event += 1;
+                match event {
+                    Event::Link(change) => self.change_link(sim.link_mut(), change).unwrap(),
+                    Event::Stop(flow) => {
+                        if let Some(idx) = running.iter().position(|&f| f == flow) {
+                            running.remove(idx);
+                            sim.remove_flow(idx);
+                        }
+                    }
+                    Event::Start(flow) => {
+                        sim.add_flow(&cfgs[flow]);
+                        running.push(flow);
+                    }
+                }
+            }
+
+            if sim.elapsed() >= next_sample {
+                next_sample += interval;
+                for (&flow, sim_flow) in running.iter().zip(sim.flows()) {
+                    on_sample(&ScenarioSample {
+                        t_s: sim.elapsed().as_secs_f64(),
+                        flow: self.names[flow].clone(),
+                        queue_bytes: sim.queue_bytes(),
+                        rtt_us: sim.rtt().as_micros() as u64,
+                        send_rate: sim_flow.send_rate(),
+                        delivered_bytes: sim_flow.delivered_bytes(),
+                        lost_bytes: sim_flow.lost_bytes(),
+                        snapshot: sim_flow.core().snapshot(),
+                    });
+                }
+            }
+            if sim.elapsed() >= end {
+                return;
+            }
+            sim.step();
+        }
+    }
+}
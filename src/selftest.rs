@@ -0,0 +1,228 @@
+//! A one-command check that the agent, the datapath and the host's qdiscs work together.
+//!
+//! `bbr selftest <host>:<port>` serves the datapath as usual, sends to the target over a TCP
+//! connection the kernel hands to CCP for `SELFTEST_DURATION_SECONDS`, and then judges the
+//! transfer by what it achieved and by the snapshot of the flow the agent ran for it: a flow
+//! the agent never saw means the datapath is not reaching it, and a transfer well below the
+//! expected rate, with a standing queue, or with heavy loss means BBR is not controlling it
+//! the way it should. The target only has to read and discard what it is sent, as `nc -l` or
+//! an `iperf -s` server does.
+
+use crate::bandwidth::Rate;
+use crate::params::{
+    DELIVERY_GAP_RATIO, SELFTEST_MAX_LOSS_FRACTION, SELFTEST_MAX_QUEUEING_MIN_RTTS,
+    SELFTEST_RATE_FRACTION,
+};
+use crate::snapshot::FlowSnapshot;
+use crate::BbrMode;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+// what each write sends
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// What a self-test transfer achieved, and what the agent saw of it.
+#[derive(Clone, Debug)]
+pub struct SelfTest {
+    pub target: SocketAddr,
+    pub elapsed: Duration,
+    /// The bytes the transfer handed to the socket.
+    pub sent_bytes: u64,
+    /// The rate to compare the achieved one with; without it, the flow's bottleneck estimate.
+    pub expected_rate: Option<Rate>,
+    /// The flow's snapshot as the transfer ended, if the agent ran the flow.
+    pub flow: Option<FlowSnapshot>,
+    /// The interfaces that do not pace kernel flows, as the `qdisc` feature last found them.
+    pub unpaced_interfaces: Vec<String>,
+}
+
+impl SelfTest {
+    pub fn achieved_rate(&self) -> Rate {
+        Rate::from_bytes_per_sec(self.sent_bytes as f64 / self.elapsed.as_secs_f64().max(1e-6))
+    }
+
+    /// The expected rate, or else the flow's bottleneck estimate.
+    pub fn expected(&self) -> Option<Rate> {
+        self.expected_rate
+            .or_else(|| self.flow.as_ref().map(|flow| flow.bottle_rate))
+    }
+
+    /// How far the flow's smoothed RTT was above its min RTT as the transfer ended.
+    pub fn queueing_delay_us(&self) -> Option<u32> {
+        let flow = self.flow.as_ref()?;
+        Some(flow.srtt_us.saturating_sub(flow.min_rtt_us))
+    }
+
+    /// The flow's lost packets over the packets the transfer sent, on datapaths that sample
+    /// losses.
+    pub fn loss_fraction(&self) -> Option<f64> {
+        let flow = self.flow.as_ref()?;
+        let sent_packets = self.sent_bytes as f64 / f64::from(flow.mss.max(1));
+        Some(flow.lost_packets as f64 / sent_packets.max(1.0))
+    }
+
+    /// What is wrong with the transfer, if anything.
+    pub fn problems(&self) -> Vec<String> {
+        let flow = match &self.flow {
+            Some(flow) => flow,
+            None => {
+                return vec![String::from(
+                    "the agent never ran the flow: check that the ccp kernel module is loaded, \
+                     that --ipc names the transport it was loaded with, and that the kernel \
+                     allows the ccp congestion control",
+                )]
+            }
+        };
+
+        let mut problems = vec![];
+        if flow.degraded {
+            problems.push(String::from(
+                "the datapath rejected probe_bw, so the flow ran the AIMD fallback",
+            ));
+        }
+        if flow.mode != BbrMode::ProbeBw {
+            problems.push(format!(
+                "the flow ended in {:?} rather than PROBE_BW",
+                flow.mode
+            ));
+        }
+        if let Some(expected) = self.expected() {
+            let achieved = self.achieved_rate();
+            if achieved.bytes_per_sec() < SELFTEST_RATE_FRACTION * expected.bytes_per_sec() {
+                problems.push(format!(
+                    "achieved {:.1} is less than {}% of the {:.1} expected",
+                    achieved,
+                    SELFTEST_RATE_FRACTION * 100.0,
+                    expected
+                ));
+            }
+        }
+        let queueing_delay_us = self.queueing_delay_us().unwrap_or(0);
+        if f64::from(queueing_delay_us)
+            > SELFTEST_MAX_QUEUEING_MIN_RTTS * f64::from(flow.min_rtt_us)
+        {
+            problems.push(format!(
+                "a standing queue of {}us over the min RTT of {}us",
+                queueing_delay_us, flow.min_rtt_us
+            ));
+        }
+        let loss = self.loss_fraction().unwrap_or(0.0);
+        if loss > SELFTEST_MAX_LOSS_FRACTION {
+            problems.push(format!("lost {:.2}% of the packets sent", loss * 100.0));
+        }
+        if !self.unpaced_interfaces.is_empty() {
+            problems.push(format!(
+                "interfaces without an fq qdisc do not pace kernel flows: {}",
+                self.unpaced_interfaces.join(", ")
+            ));
+        }
+        problems
+    }
+
+    pub fn passed(&self) -> bool {
+        self.problems().is_empty()
+    }
+}
+
+/// The measurements, then `verdict: PASS`, or `verdict: FAIL` and what went wrong.
+impl fmt::Display for SelfTest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "selftest: sent {} bytes to {} in {:.1}s",
+            self.sent_bytes,
+            self.target,
+            self.elapsed.as_secs_f64()
+        )?;
+        match self.expected() {
+            Some(expected) => writeln!(
+                f,
+                "  rate: achieved {:.1}, expected {:.1}",
+                self.achieved_rate(),
+                expected
+            )?,
+            None => writeln!(f, "  rate: achieved {:.1}", self.achieved_rate())?,
+        }
+        if let Some(flow) = &self.flow {
+            writeln!(f, "  flow: {} in {:?}", flow.id, flow.mode)?;
+            writeln!(
+                f,
+                "  rtt: min {}us, smoothed {}us, queueing delay {}us",
+                flow.min_rtt_us,
+                flow.srtt_us,
+                self.queueing_delay_us().unwrap_or(0)
+            )?;
+            writeln!(
+                f,
+                "  loss: {} packets, {:.2}% of those sent",
+                flow.lost_packets,
+                self.loss_fraction().unwrap_or(0.0) * 100.0
+            )?;
+            if let Some(ratio) = flow.delivery_ratio {
+                writeln!(
+                    f,
+                    "  delivery: {:.0}% of the pacing rate in the last cycle, {} cycles below {:.0}%",
+                    ratio * 100.0,
+                    flow.delivery_gap_cycles,
+                    DELIVERY_GAP_RATIO * 100.0
+                )?;
+            }
+        }
+        let problems = self.problems();
+        if problems.is_empty() {
+            return write!(f, "verdict: PASS");
+        }
+        write!(f, "verdict: FAIL")?;
+        for problem in problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// The snapshot of the flow between `local` and `peer`, among `flows`.
+pub fn find_flow(
+    flows: &[FlowSnapshot],
+    local: SocketAddr,
+    peer: SocketAddr,
+) -> Option<FlowSnapshot> {
+    let (local, peer) = match (local, peer) {
+        (SocketAddr::V4(local), SocketAddr::V4(peer)) => (local, peer),
+        // the datapath only reports IPv4 flows
+        _ => return None,
+    };
+    flows
+        .iter()
+        .find(|flow| {
+            flow.id.src == *local.ip()
+                && flow.id.sport == local.port()
+                && flow.id.dst == *peer.ip()
+                && flow.id.dport == peer.port()
+        })
+        .cloned()
+}
+
+/// Writes to `out` until `duration` has passed, and returns the bytes written. Writes that
+/// time out are retried until then.
+pub fn send<W: Write>(out: &mut W, duration: Duration) -> io::Result<u64> {
+    let chunk = vec![0u8; CHUNK_BYTES];
+    let deadline = Instant::now() + duration;
+    let mut sent = 0;
+    while Instant::now() < deadline {
+        match out.write(&chunk) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => sent += n as u64,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(sent)
+}
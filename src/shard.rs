@@ -0,0 +1,115 @@
+//! Maps shared by the flows of one `BbrConfig`, split into shards.
+//!
+//! portus may call into flows from several threads at once, and every report touches some
+//! cross-flow state: the flow's snapshot, its rate limit, whether it is paused. Behind a
+//! single mutex, thousands of flows would take turns on every report. A [`ShardedMap`] hashes
+//! each key to one of [`SHARDS`] independently locked maps instead, so that flows only wait
+//! for the others in the same shard, and only for as long as a single lookup or insert.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// How many independently locked maps a [`ShardedMap`] spreads its keys over.
+pub const SHARDS: usize = 16;
+
+struct Shards<K, V> {
+    hasher: RandomState,
+    maps: Vec<Mutex<HashMap<K, V>>>,
+}
+
+/// A map whose clones share their entries.
+pub struct ShardedMap<K, V> {
+    shards: Arc<Shards<K, V>>,
+}
+
+impl<K, V> Clone for ShardedMap<K, V> {
+    fn clone(&self) -> Self {
+        ShardedMap {
+            shards: self.shards.clone(),
+        }
+    }
+}
+
+impl<K, V> Default for ShardedMap<K, V> {
+    fn default() -> Self {
+        ShardedMap {
+            shards: Arc::new(Shards {
+                hasher: RandomState::new(),
+                maps: (0..SHARDS).map(|_| Mutex::default()).collect(),
+            }),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> ShardedMap<K, V> {
+    fn shard(&self, key: &K) -> MutexGuard<'_, HashMap<K, V>> {
+        let shard = self.shards.hasher.hash_one(key) as usize % SHARDS;
+        self.shards.maps[shard].lock().unwrap()
+    }
+
+    fn shards(&self) -> impl Iterator<Item = MutexGuard<'_, HashMap<K, V>>> {
+        self.shards.maps.iter().map(|map| map.lock().unwrap())
+    }
+
+    /// Runs `f` on the shard that holds `key`, with only that shard locked, for changes that
+    /// must see the entry and change it at once.
+    pub fn with_shard<R>(&self, key: &K, f: impl FnOnce(&mut HashMap<K, V>) -> R) -> R {
+        f(&mut self.shard(key))
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.shard(&key).insert(key, value)
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.shard(key).remove(key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.shard(key).contains_key(key)
+    }
+
+    pub fn get(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.shard(key).get(key).cloned()
+    }
+
+    /// The number of entries. Shards are counted one after the other, so entries added or
+    /// removed meanwhile may or may not be.
+    pub fn len(&self) -> usize {
+        self.shards().map(|map| map.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards().all(|map| map.is_empty())
+    }
+
+    /// Every entry, in no particular order, read a shard at a time like [`ShardedMap::len`].
+    pub fn entries(&self) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.shards()
+            .flat_map(|map| {
+                map.iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Every value, in no particular order, read a shard at a time like [`ShardedMap::len`].
+    pub fn values(&self) -> Vec<V>
+    where
+        V: Clone,
+    {
+        self.shards()
+            .flat_map(|map| map.values().cloned().collect::<Vec<_>>())
+            .collect()
+    }
+}
@@ -0,0 +1,93 @@
+//! A start for flows that are expected to finish before BBR learns the path.
+//!
+//! Most web-style transfers send a few hundred kilobytes at most, and are done within the
+//! handful of round trips STARTUP spends finding the bottleneck. A flow hinted to be short
+//! skips STARTUP's probing instead: it starts with a generous window, paces at the path
+//! cache's rate for its destination if there is one, and only keeps its min RTT up to date.
+//! Should it outlive its hint and ack more bytes than the threshold, it starts STARTUP then.
+
+use crate::flow_match::FlowMatch;
+use portus::DatapathInfo;
+use std::fmt;
+use std::str::FromStr;
+
+const UNITS: &[(&str, f64)] = &[
+    ("", 1.0),
+    ("B", 1.0),
+    ("KB", 1e3),
+    ("kB", 1e3),
+    ("MB", 1e6),
+    ("GB", 1e9),
+    ("KiB", 1024.0),
+    ("MiB", 1024.0 * 1024.0),
+    ("GiB", 1024.0 * 1024.0 * 1024.0),
+];
+
+/// Parses a size, e.g. `100KB` or `1MiB`; bare numbers are bytes.
+pub fn parse_bytes(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value = value
+        .parse::<f64>()
+        .map_err(|_| format!("invalid size: {:?}", s))?;
+    let scale = UNITS
+        .iter()
+        .find(|(name, _)| *name == unit.trim())
+        .map(|&(_, scale)| scale)
+        .ok_or_else(|| {
+            format!(
+                "unknown size unit in {:?}, expected B, KB, MB, GB or KiB, MiB, GiB",
+                s
+            )
+        })?;
+    let bytes = value * scale;
+    if bytes >= 1.0 && bytes < u64::MAX as f64 {
+        Ok(bytes as u64)
+    } else {
+        Err(format!("size must be at least a byte: {:?}", s))
+    }
+}
+
+/// Starts the flows selected by `flow` as short flows, until they have acked `bytes`.
+///
+/// Parsed from and displayed as `<flow match>:<size>`, e.g. `dport=443:100KB`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShortFlowRule {
+    pub flow: FlowMatch,
+    pub bytes: u64,
+}
+
+impl FromStr for ShortFlowRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (flow, bytes) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected <flow match>:<size>: {:?}", s))?;
+        Ok(ShortFlowRule {
+            flow: flow.parse()?,
+            bytes: parse_bytes(bytes)?,
+        })
+    }
+}
+
+impl fmt::Display for ShortFlowRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}B", self.flow, self.bytes)
+    }
+}
+
+/// The threshold of the first matching rule, or the one given for all flows.
+pub fn short_flow_bytes_for(
+    rules: &[ShortFlowRule],
+    bytes: Option<u64>,
+    info: &DatapathInfo,
+) -> Option<u64> {
+    rules
+        .iter()
+        .find(|r| r.flow.matches(info))
+        .map_or(bytes, |r| Some(r.bytes))
+}
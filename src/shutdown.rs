@@ -0,0 +1,113 @@
+//! Handing flows back to the datapath when the agent exits.
+//!
+//! Once shutdown is requested, each flow releases itself on its next report: it logs its
+//! final estimates and replaces its BBR program with one that leaves the flow window-limited
+//! and unpaced, instead of stuck at whatever cwnd and rate were last installed.
+//!
+//! A panic ends the agent without asking, so [`Shutdown::release_on_panic`] installs a panic
+//! hook that requests the shutdown. Flows the panic unwinds through release themselves as they
+//! are dropped, since they will not see another report; the others release themselves on
+//! their next one.
+
+use crate::shard::ShardedMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::error;
+
+pub use crate::params::SHUTDOWN_GRACE_MS;
+
+type ActiveFlows = ShardedMap<u32, ()>;
+
+/// Shared by all flows of one `BbrConfig`.
+#[derive(Clone)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    panicked: Arc<AtomicBool>,
+    active: ActiveFlows,
+    /// The active flows of every scope of this shutdown, this one's included.
+    scopes: Arc<Mutex<Vec<ActiveFlows>>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        let active = ActiveFlows::default();
+        Shutdown {
+            requested: Arc::default(),
+            panicked: Arc::default(),
+            scopes: Arc::new(Mutex::new(vec![active.clone()])),
+            active,
+        }
+    }
+}
+
+impl Shutdown {
+    /// A shutdown requested together with this one, for flows whose socket ids may collide
+    /// with this one's, such as those of another datapath. Every scope counts the active
+    /// flows of all of them.
+    pub fn scope(&self) -> Shutdown {
+        let active = ActiveFlows::default();
+        self.scopes.lock().unwrap().push(active.clone());
+        Shutdown {
+            requested: self.requested.clone(),
+            panicked: self.panicked.clone(),
+            active,
+            scopes: self.scopes.clone(),
+        }
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Makes a panic on any thread request the shutdown, before the previous panic hook
+    /// reports it, so that the flows are handed back to the datapath.
+    pub fn release_on_panic(&self) {
+        let shutdown = self.clone();
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |panic| {
+            // the panic may hold the locks of the active flows, so they are not counted here
+            shutdown.panicked.store(true, Ordering::SeqCst);
+            shutdown.request();
+            error!(%panic, "panicked, releasing flows");
+            previous(panic);
+        }));
+    }
+
+    /// Whether a panic requested the shutdown; see [`Shutdown::release_on_panic`].
+    pub fn panicked(&self) -> bool {
+        self.panicked.load(Ordering::SeqCst)
+    }
+
+    /// The number of flows that have not yet been released.
+    pub fn active_flows(&self) -> usize {
+        self.scopes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|active| active.len())
+            .sum()
+    }
+
+    /// Waits up to `grace` for the flows to release themselves; returns how many did not.
+    pub fn wait_for_release(&self, grace: Duration) -> usize {
+        let deadline = Instant::now() + grace;
+        while self.active_flows() > 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        self.active_flows()
+    }
+
+    pub(crate) fn register(&self, sock_id: u32) {
+        self.active.insert(sock_id, ());
+    }
+
+    pub(crate) fn deregister(&self, sock_id: u32) {
+        self.active.remove(&sock_id);
+    }
+}
@@ -0,0 +1,832 @@
+//! Deterministic simulation of BBR flows sharing a bottleneck link, on virtual time.
+//!
+//! The link is a fluid FIFO queue: on every tick each flow offers its paced (or
+//! cwnd-limited) rate, the link serves up to its capacity, the rest queues up to the buffer
+//! size and the excess is dropped. Each flow runs a [`BbrCore`] against an emulation of the
+//! datapath programs' fold functions, so the whole control loop runs without a datapath.
+
+use crate::chaos::{FaultRng, Faults};
+use crate::datapath::DatapathKind;
+use crate::loss::{LossAccounting, REORDER_WINDOW_ACKS};
+use crate::rate::RateEstimator;
+use crate::{Action, BbrConfig, BbrCore, Measurement, MIN_RATE_SAMPLE_ACKS, PROBE_RTT_DURATION_US};
+use portus::DatapathInfo;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_TICK_US: u64 = 100;
+pub const SIM_MSS: u32 = 1460;
+const SIM_DST_IP: u32 = 0x0a00_0001;
+
+/// A bottleneck link.
+#[derive(Clone, Copy, Debug)]
+pub struct Link {
+    /// Bytes per second.
+    pub rate: f64,
+    /// Bytes.
+    pub buffer: f64,
+    pub base_rtt: Duration,
+}
+
+/// Time that only advances when told to.
+#[derive(Clone, Copy, Debug)]
+pub struct VirtualClock {
+    origin: Instant,
+    elapsed: Duration,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        VirtualClock {
+            origin: Instant::now(),
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+impl VirtualClock {
+    pub fn now(&self) -> Instant {
+        self.origin + self.elapsed
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn advance(&mut self, by: Duration) {
+        self.elapsed += by;
+    }
+}
+
+// the per-ack primitives the programs read
+struct AckSample {
+    rtt: Duration,
+    rate_outgoing: f64,
+    rate_incoming: f64,
+    bytes_acked: f64,
+    lost_pkts: f64,
+    bytes_in_flight: f64,
+}
+
+enum Program {
+    Init,
+    Drain,
+    ProbeBw { pulse_state: u32 },
+    ProbeRtt { target_inflight_reached: bool },
+}
+
+// the datapath side of one flow: its registers and the installed program's fold state
+struct DatapathModel {
+    program: Program,
+    program_uid: u32,
+    /// Bytes.
+    cwnd: f64,
+    /// Bytes per second; unpaced until the first `Rate` write.
+    rate: Option<f64>,
+    /// init_program's pacing ramp, in millionths; zero once it has reported.
+    pacing_gain: u32,
+    init_rate: f64,
+    cwnd_cap: u32,
+    bottle_rate: u64,
+    three_fourths_rate: u64,
+    five_fourths_rate: u64,
+    nine_eighths_rate: u64,
+    bdp_cwnd: u32,
+    three_fourths_cwnd: u32,
+    five_fourths_cwnd: u32,
+    nine_eighths_cwnd: u32,
+    /// Zero when the pulses follow the min RTT.
+    pulse_us: u32,
+    /// How much later the up and down pulses end, with `pulse_shift`.
+    pulse_shift_us: u32,
+    /// The last nonce the agent wrote, the last one the program saw, and the `Micros` it
+    /// first saw it at.
+    update_nonce: u32,
+    report_nonce: u32,
+    nonce_seen_us: u64,
+    bdp_target: u32,
+    target_inflight: u32,
+    probe_rtt_us: u32,
+    drain_to_target: bool,
+    /// Without pacing, `Rate` writes are ignored and the probe_bw pulse moves cwnd.
+    pacing: bool,
+    /// The up pulse starts with half an RTT in pulse state 3.
+    probe_bw_ramp: bool,
+    /// probe_bw sets cwnd from the current pulse's register on every ack.
+    track_cwnd: bool,
+    rate_estimator: RateEstimator,
+    loss_accounting: LossAccounting,
+    /// Losses the windowed accounting has not confirmed yet, and the acks since they were seen.
+    suspect_loss: f64,
+    suspect_acks: u32,
+    micros_origin: Duration,
+    /// The `Micros` of the last report, for the delivered-bytes estimator.
+    delivery_start_us: u64,
+    report_minrtt_us: u64,
+    report_rate_out: f64,
+    report_rate_in: f64,
+    report_loss: f64,
+    report_acked: f64,
+    report_delivered: f64,
+    /// Not volatile: the bytes acked since the program was installed.
+    report_delivered_total: f64,
+    report_inflight: f64,
+    report_acks: u32,
+    /// Not volatile: smoothed over the program's lifetime.
+    report_srtt_us: u64,
+    report_rttvar_us: u64,
+    /// probe_bw's bandwidth filter: the `Micros` the current round started at, the bytes
+    /// and acks in it, and the delivery rates of the last rounds, newest first.
+    round_start_us: u64,
+    round_delivered: f64,
+    round_acks: u32,
+    bw_ring: Vec<f64>,
+    /// The rounds of `bw_ring` the maximum is taken over, unless `bw_long_filter` takes them
+    /// all.
+    bw_window: usize,
+    bw_long_filter: bool,
+    cycle_rounds: u64,
+    /// The cycle's rounds without the high-RTT profile's `cycleRounds`.
+    cruise_phase_end: u64,
+    down_phase_end: u64,
+    /// If set, the `Micros` the cruise phase ends at, instead of after `cycle_rounds`.
+    probe_at_us: u64,
+    report_max_rate: f64,
+    /// The flow's segment size, which kernel programs report.
+    mss: u32,
+    reports_mss: bool,
+}
+
+impl DatapathModel {
+    fn new(cfg: &BbrConfig) -> Self {
+        DatapathModel {
+            program: Program::Init,
+            program_uid: 0,
+            cwnd: 0.0,
+            rate: None,
+            pacing_gain: 0,
+            init_rate: 0.0,
+            cwnd_cap: 0,
+            bottle_rate: 0,
+            three_fourths_rate: 0,
+            five_fourths_rate: 0,
+            nine_eighths_rate: 0,
+            bdp_cwnd: 0,
+            three_fourths_cwnd: 0,
+            five_fourths_cwnd: 0,
+            nine_eighths_cwnd: 0,
+            pulse_us: 0,
+            pulse_shift_us: 0,
+            update_nonce: 0,
+            report_nonce: 0,
+            nonce_seen_us: 0,
+            bdp_target: 0,
+            target_inflight: 0,
+            probe_rtt_us: PROBE_RTT_DURATION_US,
+            drain_to_target: cfg.drain_to_target,
+            pacing: cfg.pacing,
+            probe_bw_ramp: cfg.probe_bw_ramp,
+            track_cwnd: cfg.track_cwnd,
+            rate_estimator: cfg.rate_estimator,
+            loss_accounting: cfg.loss_accounting,
+            suspect_loss: 0.0,
+            suspect_acks: 0,
+            micros_origin: Duration::ZERO,
+            delivery_start_us: 0,
+            report_minrtt_us: u64::MAX,
+            report_rate_out: 0.0,
+            report_rate_in: 0.0,
+            report_loss: 0.0,
+            report_acked: 0.0,
+            report_delivered: 0.0,
+            report_delivered_total: 0.0,
+            report_inflight: 0.0,
+            report_acks: 0,
+            report_srtt_us: 0,
+            report_rttvar_us: 0,
+            round_start_us: 0,
+            round_delivered: 0.0,
+            round_acks: 0,
+            bw_ring: vec![0.0; cfg.bw_ring_rounds()],
+            bw_window: cfg.bw_window,
+            bw_long_filter: false,
+            cycle_rounds: u64::from(cfg.cruise_phase_end),
+            cruise_phase_end: u64::from(cfg.cruise_phase_end),
+            down_phase_end: u64::from(cfg.down_phase_end),
+            probe_at_us: 0,
+            report_max_rate: 0.0,
+            mss: SIM_MSS,
+            reports_mss: cfg.datapath == DatapathKind::Kernel,
+        }
+    }
+
+    // the datapath's registers are 64 bits wide; the ones that are not rates fit in 32
+    fn set_register(&mut self, reg: &str, val: u64) {
+        let narrow = u32::try_from(val).unwrap_or(u32::MAX);
+        match reg {
+            "Cwnd" => self.cwnd = val as f64,
+            "Rate" if self.pacing => self.rate = Some(val as f64),
+            "pacingGain" => self.pacing_gain = narrow,
+            "initRate" => self.init_rate = val as f64,
+            "cwndCap" => self.cwnd_cap = narrow,
+            "bottleRate" => self.bottle_rate = val,
+            "threeFourthsRate" => self.three_fourths_rate = val,
+            "fiveFourthsRate" => self.five_fourths_rate = val,
+            "nineEighthsRate" => self.nine_eighths_rate = val,
+            "bdpCwnd" => self.bdp_cwnd = narrow,
+            "threeFourthsCwnd" => self.three_fourths_cwnd = narrow,
+            "fiveFourthsCwnd" => self.five_fourths_cwnd = narrow,
+            "nineEighthsCwnd" => self.nine_eighths_cwnd = narrow,
+            "pulseUs" => self.pulse_us = narrow,
+            "pulseShiftUs" => self.pulse_shift_us = narrow,
+            "updateNonce" => self.update_nonce = narrow,
+            "bdpTarget" => self.bdp_target = narrow,
+            "targetInflight" => self.target_inflight = narrow,
+            "probeRttUs" => self.probe_rtt_us = narrow,
+            "bw0" => self.bw_ring[0] = val as f64,
+            "cycleRounds" => self.cycle_rounds = val,
+            "bwLongFilter" => self.bw_long_filter = val != 0,
+            "probeAtUs" => self.probe_at_us = val,
+            _ => {}
+        }
+    }
+
+    fn apply(&mut self, core: &mut BbrCore, actions: Vec<Action>, now: Duration) {
+        for action in actions {
+            match action {
+                Action::SetProgram { program, fields } => {
+                    self.program = match program {
+                        "drain" => Program::Drain,
+                        "probe_bw" => Program::ProbeBw {
+                            pulse_state: if self.probe_bw_ramp { 3 } else { 0 },
+                        },
+                        "probe_rtt" => Program::ProbeRtt {
+                            target_inflight_reached: false,
+                        },
+                        _ => Program::Init,
+                    };
+                    self.program_uid += 1;
+                    self.pacing_gain = 0;
+                    self.init_rate = 0.0;
+                    self.pulse_shift_us = 0;
+                    self.update_nonce = 0;
+                    self.report_nonce = 0;
+                    self.nonce_seen_us = 0;
+                    self.micros_origin = now;
+                    self.delivery_start_us = 0;
+                    self.report_minrtt_us = u64::MAX;
+                    self.report_rate_out = 0.0;
+                    self.report_rate_in = 0.0;
+                    self.report_loss = 0.0;
+                    self.suspect_loss = 0.0;
+                    self.suspect_acks = 0;
+                    self.report_acked = 0.0;
+                    self.report_delivered = 0.0;
+                    self.report_delivered_total = 0.0;
+                    self.report_inflight = 0.0;
+                    self.report_acks = 0;
+                    self.report_srtt_us = 0;
+                    self.report_rttvar_us = 0;
+                    self.round_start_us = 0;
+                    self.round_delivered = 0.0;
+                    self.round_acks = 0;
+                    self.bw_ring.fill(0.0);
+                    self.bw_long_filter = false;
+                    self.cycle_rounds = self.cruise_phase_end;
+                    self.probe_at_us = 0;
+                    self.report_max_rate = 0.0;
+                    for (reg, val) in fields {
+                        self.set_register(reg, val);
+                    }
+                    core.program_installed(self.program_uid);
+                }
+                Action::Update(fields) => {
+                    for (reg, val) in fields {
+                        self.set_register(reg, val);
+                    }
+                }
+            }
+        }
+    }
+
+    // the up pulse moves the rate, or cwnd without pacing
+    fn pulse_up(&mut self, rate: u64, cwnd: u32) {
+        if self.pacing {
+            self.rate = Some(rate as f64);
+        } else {
+            self.cwnd = f64::from(cwnd);
+        }
+    }
+
+    /// Bytes per second.
+    fn send_rate(&self, rtt: Duration) -> f64 {
+        let cwnd_limited = self.cwnd / rtt.as_secs_f64();
+        self.rate
+            .map_or(cwnd_limited, |rate| rate.min(cwnd_limited))
+    }
+
+    // nothing is ever misordered on the fluid link, so only the windowed accounting differs
+    fn accumulate_loss(&mut self, lost_pkts: f64) {
+        if self.loss_accounting != LossAccounting::Windowed {
+            self.report_loss += lost_pkts;
+            return;
+        }
+
+        self.suspect_loss += lost_pkts;
+        if self.suspect_loss > 0.0 {
+            self.suspect_acks += 1;
+        } else {
+            self.suspect_acks = 0;
+        }
+        if self.suspect_acks > REORDER_WINDOW_ACKS {
+            self.report_loss += self.suspect_loss;
+            self.suspect_loss = 0.0;
+            self.suspect_acks = 0;
+        }
+    }
+
+    fn smooth_rtt(&mut self, rtt_us: u64) {
+        if self.report_srtt_us == 0 {
+            self.report_srtt_us = rtt_us;
+            self.report_rttvar_us = rtt_us / 2;
+        }
+        self.report_rttvar_us =
+            (self.report_rttvar_us * 3 + self.report_srtt_us.abs_diff(rtt_us)) / 4;
+        self.report_srtt_us = (self.report_srtt_us * 7 + rtt_us) / 8;
+    }
+
+    fn accumulate_rates(&mut self, ack: &AckSample) {
+        self.report_delivered_total += ack.bytes_acked;
+        match self.rate_estimator {
+            RateEstimator::Flow => {
+                self.report_rate_out = self.report_rate_out.max(ack.rate_outgoing);
+                self.report_rate_in = self.report_rate_in.max(ack.rate_incoming);
+            }
+            RateEstimator::Delivered => self.report_delivered += ack.bytes_acked,
+            RateEstimator::Auto => {
+                self.report_rate_out = self.report_rate_out.max(ack.rate_outgoing);
+                self.report_rate_in = self.report_rate_in.max(ack.rate_incoming);
+                self.report_delivered += ack.bytes_acked;
+            }
+        }
+    }
+
+    fn report(&mut self, pulse_state: u32, keep_minrtt: bool, micros: u64) -> Measurement {
+        let span_us = micros.saturating_sub(self.delivery_start_us);
+        let delivery_rate = self.report_delivered * 1e6 / span_us.max(1) as f64;
+        // probe_rtt reports neither
+        let probe_rtt = matches!(self.program, Program::ProbeRtt { .. });
+        self.delivery_start_us = micros;
+        if self.rate_estimator == RateEstimator::Delivered {
+            self.report_rate_out = delivery_rate;
+            self.report_rate_in = delivery_rate;
+        }
+        let m = Measurement {
+            program_uid: self.program_uid,
+            minrtt_us: self.report_minrtt_us.min(u64::from(u32::MAX)) as u32,
+            loss: self.report_loss as u32,
+            acked: self.report_acked as u32,
+            // the fluid link neither reorders nor times out
+            misordered: 0,
+            timeout: false,
+            rate_outgoing: self.report_rate_out.floor(),
+            rate_incoming: self.report_rate_in.floor(),
+            delivery_rate: if self.rate_estimator == RateEstimator::Auto {
+                delivery_rate.floor()
+            } else {
+                0.0
+            },
+            pulse_state,
+            // the simulated receivers never limit the flows
+            receiver_limited: false,
+            inflight_bytes: self.report_inflight as u32,
+            srtt_us: self.report_srtt_us as u32,
+            rttvar_us: self.report_rttvar_us as u32,
+            max_rate: self.report_max_rate.floor(),
+            acks: self.report_acks,
+            nonce: self.report_nonce,
+            nonce_age_us: micros.saturating_sub(self.nonce_seen_us) as u32,
+            delivered_total: self.report_delivered_total as u64,
+            span_us: if probe_rtt { 0 } else { span_us as u32 },
+            mss: if probe_rtt || !self.reports_mss {
+                0
+            } else {
+                self.mss
+            },
+            received: None,
+        };
+        if !keep_minrtt {
+            self.report_minrtt_us = u64::MAX;
+        }
+        self.report_rate_out = 0.0;
+        self.report_rate_in = 0.0;
+        self.report_loss = 0.0;
+        self.report_acked = 0.0;
+        self.report_delivered = 0.0;
+        self.report_inflight = 0.0;
+        self.report_acks = 0;
+        m
+    }
+
+    // ends a round of probe_bw's bandwidth filter
+    fn end_round(&mut self, micros: u64) {
+        let round_us = micros.saturating_sub(self.round_start_us).max(1);
+        self.bw_ring.rotate_right(1);
+        self.bw_ring[0] = (self.round_delivered * 1e6 / round_us as f64).floor();
+        self.round_delivered = 0.0;
+        self.round_acks = 0;
+        self.round_start_us = micros;
+        let rounds = if self.bw_long_filter {
+            self.bw_ring.len()
+        } else {
+            self.bw_window
+        };
+        self.report_max_rate = self.bw_ring[..rounds].iter().copied().fold(0.0, f64::max);
+    }
+
+    // whether the cruise phase is over: at the scheduled probe, if there is one
+    fn cycle_over(&self, pulse: u64, micros: u64) -> bool {
+        match self.probe_at_us {
+            0 => micros > pulse.saturating_mul(self.cycle_rounds),
+            at_us => micros > at_us,
+        }
+    }
+
+    // runs the installed program's fold function for one ack
+    fn on_ack(&mut self, now: Duration, ack: &AckSample) -> Option<Measurement> {
+        let rtt_us = ack.rtt.as_micros() as u64;
+        let micros = (now - self.micros_origin).as_micros() as u64;
+        self.report_minrtt_us = self.report_minrtt_us.min(rtt_us);
+        match self.program {
+            Program::Init => {
+                self.accumulate_loss(ack.lost_pkts);
+                self.report_acked += ack.bytes_acked / f64::from(self.mss);
+                self.smooth_rtt(rtt_us);
+                self.accumulate_rates(ack);
+                if self.pacing_gain > 0 && rtt_us > 0 {
+                    let rate = self.cwnd * f64::from(self.pacing_gain) / rtt_us as f64;
+                    self.init_rate = self.init_rate.max(rate.floor());
+                    if self.pacing {
+                        self.rate = Some(self.init_rate);
+                    }
+                }
+                if micros > self.report_minrtt_us {
+                    self.pacing_gain = 0;
+                    self.micros_origin = now;
+                    // init_program's minrtt is not volatile
+                    let m = self.report(5, true, micros);
+                    self.delivery_start_us = 0;
+                    return Some(m);
+                }
+            }
+            Program::Drain => {
+                self.accumulate_loss(ack.lost_pkts);
+                self.report_acked += ack.bytes_acked / f64::from(self.mss);
+                self.smooth_rtt(rtt_us);
+                self.accumulate_rates(ack);
+                let drained = if self.drain_to_target {
+                    ack.bytes_in_flight <= f64::from(self.bdp_target)
+                } else {
+                    micros > self.report_minrtt_us
+                };
+                if drained {
+                    return Some(self.report(0, true, micros));
+                }
+            }
+            Program::ProbeBw { pulse_state } => {
+                self.accumulate_loss(ack.lost_pkts);
+                self.report_acked += ack.bytes_acked / f64::from(self.mss);
+                self.smooth_rtt(rtt_us);
+                self.accumulate_rates(ack);
+                self.report_inflight = self.report_inflight.max(ack.bytes_in_flight);
+                self.round_delivered += ack.bytes_acked;
+                self.round_acks += 1;
+                self.report_acks += 1;
+                if self.update_nonce > self.report_nonce {
+                    self.report_nonce = self.update_nonce;
+                    self.nonce_seen_us = micros;
+                }
+                if self.track_cwnd {
+                    self.cwnd = f64::from(match (self.pacing, pulse_state) {
+                        (true, _) => self.cwnd_cap,
+                        (false, 0) => self.five_fourths_cwnd,
+                        (false, 1) => self.three_fourths_cwnd,
+                        (false, 2) => self.bdp_cwnd,
+                        (false, _) => self.nine_eighths_cwnd,
+                    });
+                }
+                // the pulses follow the min RTT unless their length is fixed
+                let pulse = match self.pulse_us {
+                    0 => self.report_minrtt_us,
+                    us => u64::from(us),
+                };
+                let shift = u64::from(self.pulse_shift_us);
+                if self.round_acks >= MIN_RATE_SAMPLE_ACKS
+                    && (micros.saturating_sub(self.round_start_us) > pulse
+                        || (pulse_state == 2 && self.cycle_over(pulse, micros)))
+                {
+                    self.end_round(micros);
+                }
+                if pulse_state == 0 && micros > pulse.saturating_add(shift) {
+                    if self.pacing {
+                        self.rate = Some(self.three_fourths_rate as f64);
+                    } else {
+                        self.cwnd = f64::from(self.three_fourths_cwnd);
+                    }
+                    self.program = Program::ProbeBw { pulse_state: 1 };
+                    return Some(self.report(pulse_state, false, micros));
+                } else if pulse_state == 1
+                    && micros
+                        > pulse
+                            .saturating_mul(self.down_phase_end)
+                            .saturating_add(shift)
+                {
+                    if self.pacing {
+                        self.rate = Some(self.bottle_rate as f64);
+                    } else {
+                        self.cwnd = f64::from(self.bdp_cwnd);
+                    }
+                    self.program = Program::ProbeBw { pulse_state: 2 };
+                    return Some(self.report(pulse_state, false, micros));
+                } else if pulse_state == 2 && self.cycle_over(pulse, micros) {
+                    if self.pacing {
+                        self.cwnd = f64::from(self.cwnd_cap);
+                    }
+                    if self.probe_bw_ramp {
+                        self.program = Program::ProbeBw { pulse_state: 3 };
+                        self.pulse_up(self.nine_eighths_rate, self.nine_eighths_cwnd);
+                    } else {
+                        self.program = Program::ProbeBw { pulse_state: 0 };
+                        self.pulse_up(self.five_fourths_rate, self.five_fourths_cwnd);
+                    }
+                    self.micros_origin = now;
+                    self.round_start_us = 0;
+                    self.round_delivered = 0.0;
+                    self.round_acks = 0;
+                    self.probe_at_us = 0;
+                    let m = self.report(pulse_state, false, micros);
+                    self.delivery_start_us = 0;
+                    return Some(m);
+                } else if pulse_state == 3 && micros > (pulse / 2).saturating_add(shift) {
+                    self.program = Program::ProbeBw { pulse_state: 0 };
+                    self.pulse_up(self.five_fourths_rate, self.five_fourths_cwnd);
+                }
+            }
+            Program::ProbeRtt {
+                target_inflight_reached,
+            } => {
+                if !target_inflight_reached
+                    && ack.bytes_in_flight <= f64::from(self.target_inflight)
+                {
+                    self.program = Program::ProbeRtt {
+                        target_inflight_reached: true,
+                    };
+                    self.micros_origin = now;
+                } else if target_inflight_reached
+                    && micros > rtt_us
+                    && micros > u64::from(self.probe_rtt_us)
+                {
+                    self.micros_origin = now;
+                    return Some(self.report(0, false, micros));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// One simulated flow.
+pub struct SimFlow {
+    core: BbrCore,
+    datapath: DatapathModel,
+    send_rate: f64,
+    delivered: f64,
+    lost: f64,
+    /// A report held back by a delay fault.
+    delayed: Option<Measurement>,
+}
+
+impl SimFlow {
+    pub fn core(&self) -> &BbrCore {
+        &self.core
+    }
+
+    /// Bytes per second offered to the link on the last tick.
+    pub fn send_rate(&self) -> f64 {
+        self.send_rate
+    }
+
+    /// Bytes delivered since the flow started.
+    pub fn delivered_bytes(&self) -> f64 {
+        self.delivered
+    }
+
+    /// Bytes dropped at the bottleneck since the flow started.
+    pub fn lost_bytes(&self) -> f64 {
+        self.lost
+    }
+
+    // hands a report from the datapath to the flow, through the faults
+    fn deliver(
+        &mut self,
+        m: Measurement,
+        at: Instant,
+        now: Duration,
+        faults: &Faults,
+        rng: &mut FaultRng,
+    ) {
+        let mut reports = vec![];
+        if !rng.chance(faults.drop) {
+            if rng.chance(faults.delay) && self.delayed.is_none() {
+                // it reached the agent now, and waits until the next report
+                self.delayed = Some(Measurement {
+                    received: Some(at),
+                    ..m
+                });
+            } else {
+                reports.push(m);
+                if rng.chance(faults.duplicate) {
+                    reports.push(m);
+                }
+                reports.extend(self.delayed.take());
+            }
+        }
+
+        for m in reports {
+            let actions = match self.core.take_reinstall() {
+                Some(actions) => actions,
+                None => self.core.on_measurement(at, m),
+            };
+            for action in actions {
+                if rng.chance(faults.error) {
+                    self.core.install_failed();
+                    break;
+                }
+                self.datapath.apply(&mut self.core, vec![action], now);
+            }
+        }
+    }
+}
+
+pub struct Simulation {
+    link: Link,
+    clock: VirtualClock,
+    tick: Duration,
+    /// Bytes.
+    queue: f64,
+    flows: Vec<SimFlow>,
+    /// Socket ids are not reused after a flow is removed.
+    next_sock_id: u32,
+    faults: Faults,
+    rng: FaultRng,
+}
+
+impl Simulation {
+    pub fn new(link: Link) -> Self {
+        Simulation {
+            link,
+            clock: VirtualClock::default(),
+            tick: Duration::from_micros(DEFAULT_TICK_US),
+            queue: 0.0,
+            flows: vec![],
+            next_sock_id: 1,
+            faults: Faults::default(),
+            rng: FaultRng::new(1),
+        }
+    }
+
+    /// Injects faults into the reports the flows receive and the actions they take: reports
+    /// are dropped, delayed and duplicated, and actions fail to apply.
+    pub fn with_faults(mut self, faults: Faults, seed: u64) -> Self {
+        self.faults = faults;
+        self.rng = FaultRng::new(seed);
+        self
+    }
+
+    pub fn set_faults(&mut self, faults: Faults) {
+        self.faults = faults;
+    }
+
+    /// Sets the simulation step; every flow sees one ack per tick.
+    pub fn with_tick(mut self, tick: Duration) -> Self {
+        self.tick = tick;
+        self
+    }
+
+    /// Starts a flow through the bottleneck and returns its index.
+    pub fn add_flow(&mut self, cfg: &BbrConfig) -> usize {
+        let idx = self.flows.len();
+        let info = DatapathInfo {
+            sock_id: self.next_sock_id,
+            init_cwnd: 10 * SIM_MSS,
+            mss: SIM_MSS,
+            src_ip: 0,
+            src_port: 0,
+            dst_ip: SIM_DST_IP,
+            dst_port: 0,
+        };
+        let mut core = BbrCore::new(cfg, &info, self.clock.now());
+        let mut datapath = DatapathModel::new(cfg);
+        let start = core.start();
+        datapath.apply(&mut core, start, self.clock.elapsed());
+        self.flows.push(SimFlow {
+            core,
+            datapath,
+            send_rate: 0.0,
+            delivered: 0.0,
+            lost: 0.0,
+            delayed: None,
+        });
+        self.next_sock_id += 1;
+        idx
+    }
+
+    /// Ends the flow at `idx`, as if its connection closed; the flows after it move up one
+    /// index.
+    pub fn remove_flow(&mut self, idx: usize) -> SimFlow {
+        self.flows.remove(idx)
+    }
+
+    pub fn flows(&self) -> &[SimFlow] {
+        &self.flows
+    }
+
+    /// Changes the segment size of the flow at `idx`, as path MTU discovery does; the flow
+    /// learns it from its next report that samples it.
+    pub fn set_mss(&mut self, idx: usize, mss: u32) {
+        self.flows[idx].datapath.mss = mss;
+    }
+
+    pub fn link(&self) -> &Link {
+        &self.link
+    }
+
+    /// Allows changing the link mid-simulation.
+    pub fn link_mut(&mut self) -> &mut Link {
+        &mut self.link
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.clock.elapsed()
+    }
+
+    pub fn queue_bytes(&self) -> f64 {
+        self.queue
+    }
+
+    pub fn rtt(&self) -> Duration {
+        self.link.base_rtt + Duration::from_secs_f64(self.queue / self.link.rate)
+    }
+
+    pub fn run_for(&mut self, duration: Duration) {
+        let end = self.clock.elapsed() + duration;
+        while self.clock.elapsed() < end {
+            self.step();
+        }
+    }
+
+    pub fn step(&mut self) {
+        let dt = self.tick.as_secs_f64();
+        let rtt = self.rtt();
+        for flow in &mut self.flows {
+            flow.send_rate = flow.datapath.send_rate(rtt);
+        }
+
+        let offered: f64 = self.flows.iter().map(|f| f.send_rate).sum();
+        let backlog = self.queue + offered * dt;
+        let served = backlog.min(self.link.rate * dt);
+        self.queue = backlog - served;
+        let dropped = (self.queue - self.link.buffer).max(0.0);
+        self.queue -= dropped;
+
+        self.clock.advance(self.tick);
+        let now = self.clock.elapsed();
+        let rtt = self.rtt();
+        for flow in &mut self.flows {
+            let share = if offered > 0.0 {
+                flow.send_rate / offered
+            } else {
+                0.0
+            };
+            let delivered = served * share;
+            let lost = dropped * share;
+            flow.delivered += delivered;
+            flow.lost += lost;
+
+            // on the wire for one base RTT, plus this flow's part of the queue
+            let bytes_in_flight =
+                delivered / dt * self.link.base_rtt.as_secs_f64() + self.queue * share;
+            let ack = AckSample {
+                rtt,
+                rate_outgoing: flow.send_rate,
+                rate_incoming: delivered / dt,
+                bytes_acked: delivered,
+                lost_pkts: lost / f64::from(flow.datapath.mss),
+                bytes_in_flight,
+            };
+            if let Some(m) = flow.datapath.on_ack(now, &ack) {
+                flow.deliver(m, self.clock.now(), now, &self.faults, &mut self.rng);
+            }
+        }
+    }
+}
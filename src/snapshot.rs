@@ -0,0 +1,140 @@
+//! Snapshots of every flow's state, for dumping what the agent is doing while it runs.
+//!
+//! Each flow replaces its snapshot after every report, so a dump shows flows that have gone
+//! quiet as they were at their last report.
+
+use crate::bandwidth::Rate;
+use crate::buffer::BufferDepth;
+use crate::flow_id::FlowId;
+use crate::latency::LatencyHistogram;
+use crate::mode_time::ModeTime;
+use crate::shard::ShardedMap;
+use crate::{BbrMode, TransitionReason};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FlowSnapshot {
+    /// Flattened, so that a dump lists `sock_id`, `src`, `dst`, `sport` and `dport`.
+    #[serde(flatten)]
+    pub id: FlowId,
+    pub mode: BbrMode,
+    /// Why the flow entered `mode`; `None` in STARTUP.
+    pub transition_reason: Option<TransitionReason>,
+    /// How long the flow has spent in each mode, up to its latest report.
+    pub mode_time: ModeTime,
+    /// The program installed last.
+    pub program: &'static str,
+    /// The last value written to `Cwnd`, `Rate` and each register of `program`.
+    pub registers: BTreeMap<&'static str, u64>,
+    pub bottle_rate: Rate,
+    pub min_rtt_us: u32,
+    pub srtt_us: u32,
+    pub rttvar_us: u32,
+    pub rtt_jitter_us: u32,
+    /// The smoothed rates the bandwidth samples are taken from.
+    pub rate_outgoing: Rate,
+    pub rate_incoming: Rate,
+    pub inflight_bytes: u32,
+    /// The flow's current MSS, as its datapath last reported it.
+    pub mss: u32,
+    /// See [`crate::BbrCore::pacing_rate`].
+    pub pacing_rate: Rate,
+    /// See [`crate::BbrCore::estimated_bdp_bytes`].
+    pub estimated_bdp_bytes: u64,
+    /// See [`crate::BbrCore::buffer_depth`].
+    pub buffer_depth: Option<BufferDepth>,
+    /// Whether STARTUP found the pipe full; see [`crate::BbrCore::pipe_full`].
+    pub pipe_full: bool,
+    /// Whether the flow runs the profile for paths above `--high_rtt_threshold`.
+    pub high_rtt: bool,
+    pub app_limited: bool,
+    /// Whether the flow has stopped probing for bandwidth until it is resumed.
+    pub paused: bool,
+    /// The limit an operator set on the flow's rate, or its share of its tenant's aggregate
+    /// rate, whichever is lower, if any.
+    pub rate_limit: Option<Rate>,
+    /// The tenant the flow belongs to; see [`crate::tenant`].
+    pub tenant: Option<String>,
+    /// Whether the flow runs the cwnd-only AIMD fallback because the datapath rejected
+    /// `probe_bw`, or kept failing its updates under `--on_update_failure degrade`.
+    pub degraded: bool,
+    /// Whether the flow kept failing its updates and was left at its estimate under
+    /// `--on_update_failure freeze`.
+    pub frozen: bool,
+    /// Whether the flow started as a short flow and has not yet acked enough to start
+    /// STARTUP.
+    pub short_flow: bool,
+    /// Reports from the current program.
+    pub reports: u64,
+    /// Reports from programs that had already been replaced, and were ignored.
+    pub stale_reports: u64,
+    /// Reports from programs of another version, after which the flow reinstalled its
+    /// program.
+    pub outdated_reports: u64,
+    /// Reports that waited longer than `--max_report_age` to be handled, and were ignored.
+    pub late_reports: u64,
+    /// Reports whose rates were next to zero, e.g. while the flow was application-limited or
+    /// stalled, and were not taken as bandwidth samples.
+    pub stalled_reports: u64,
+    /// How long the flow took to handle its reports, up to its previous one.
+    pub handling: LatencyHistogram,
+    /// With `--pulse_shift`, the smoothed time from a report to the update it prompts taking
+    /// effect in the datapath; zero until measured.
+    pub install_latency_us: u32,
+    /// Times the flow entered PROBE_RTT.
+    pub probe_rtt_entries: u64,
+    /// Times PROBE_RTT was due but skipped under `--skip_quiet_probe_rtt`.
+    pub skipped_probe_rtts: u64,
+    /// PROBE_BW reports that lost so much of the window at once that the flow halved cwnd.
+    pub loss_bursts: u64,
+    /// Min RTT decreases ignored for coming from reports with a loss burst or a
+    /// retransmission timeout.
+    pub lossy_rtt_samples: u64,
+    /// PROBE_BW pulse cycles that ran to their end.
+    pub probe_bw_cycles: u64,
+    /// See [`crate::BbrCore::delivery_ratio`].
+    pub delivery_ratio: Option<f64>,
+    /// PROBE_BW cycles that delivered less than `DELIVERY_GAP_RATIO` of the rate they paced
+    /// at.
+    pub delivery_gap_cycles: u64,
+    /// PROBE_BW reports from another pulse phase than the one the flow expected.
+    pub pulse_desyncs: u64,
+    /// Programs installed, including the first and every reinstall.
+    pub program_installs: u64,
+    /// Programs reinstalled after the datapath failed to apply an action.
+    pub reinstalls: u64,
+    /// Register updates and other actions the datapath failed to apply.
+    pub failed_updates: u64,
+    /// Programs the datapath refused to install.
+    pub rejected_installs: u64,
+    /// The fraction of packets lost in the last report interval that acked or lost any.
+    pub loss_rate: f64,
+    /// Packets lost and acked over the flow's lifetime, as its reports counted them. Zero on
+    /// datapaths that do not sample losses.
+    pub lost_packets: u64,
+    pub acked_packets: u64,
+}
+
+/// Shared by all flows of one `BbrConfig`.
+#[derive(Clone, Default)]
+pub struct Snapshots {
+    flows: ShardedMap<FlowId, FlowSnapshot>,
+}
+
+impl Snapshots {
+    /// The latest snapshot of every flow, in `FlowId` order.
+    pub fn flows(&self) -> Vec<FlowSnapshot> {
+        let mut flows = self.flows.values();
+        flows.sort_by_key(|flow| flow.id);
+        flows
+    }
+
+    pub(crate) fn update(&self, snapshot: FlowSnapshot) {
+        self.flows.insert(snapshot.id, snapshot);
+    }
+
+    pub(crate) fn remove(&self, id: FlowId) {
+        self.flows.remove(&id);
+    }
+}
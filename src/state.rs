@@ -0,0 +1,1983 @@
+//! The mode machine: how each report moves a flow between STARTUP, DRAIN, `PROBE_BW` and
+//! `PROBE_RTT`, and the programs it installs as it does, and the controls that pause, limit
+//! or reschedule a running flow.
+
+use crate::bandwidth::Rate;
+use crate::capability::{
+    PROBE_BW_INSTALL_ATTEMPTS, UNENFORCED_RATE_FACTOR, UNENFORCED_RATE_REPORTS,
+};
+use crate::error::BbrError;
+use crate::flow::lower_limit;
+use crate::group::SharedMinRtt;
+use crate::log_granularity::{CycleSummary, LogGranularity};
+use crate::loss::{is_loss_burst, LossMode, LOSS_BACKOFF, LOSS_BURST_HOLD_CYCLES};
+use crate::params::{
+    DELIVERY_GAP_RATIO, DELIVERY_GAP_WARN_CYCLES, HIGH_RTT_PROBE_RTT_GAIN, INSTALL_LATENCY_GAIN,
+    MAX_PULSE_SHIFT, MIN_MSS_BYTES, MIN_RATE_SAMPLE_ACKS, PROBE_GAIN, PROBE_RTT_DURATION_US,
+    PROBE_RTT_MAX_GROUP_SCALE, PULSE_DESYNC_RESET, PULSE_SHIFT_RESOLUTION, QUEUE_BACKOFF_GAIN,
+    QUEUE_RTT_THRESHOLD, SLOW_HANDLING_FRACTION, STABLE_BW_TOLERANCE, STABLE_PROBE_CYCLES,
+    STARTUP_FULL_BW_ROUNDS, STARTUP_GROWTH_TARGET, STEP_DOWN_PHASES,
+};
+use crate::path_cache::PathEstimate;
+use crate::rate_hint::RateHint;
+use crate::update_failure::UpdateFailurePolicy;
+use crate::{Action, BbrCore, Measurement, ReportValues, WallClock, UNCAPPED_CWND, UNPACED_RATE};
+use serde::Serialize;
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+/// The `probe_bw` registers that `rate_smoothing` limits the changes of.
+pub const SMOOTHED_RATE_REGISTERS: [&str; 4] = [
+    "bottleRate",
+    "threeFourthsRate",
+    "fiveFourthsRate",
+    "nineEighthsRate",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum BbrMode {
+    Startup,
+    Drain,
+    ProbeBw,
+    ProbeRtt,
+}
+
+/// Why a flow entered its current mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum TransitionReason {
+    /// PROBE_BW went without a new min RTT for the PROBE_RTT interval, or the flow's
+    /// bottleneck group entered PROBE_RTT.
+    MinRttExpired,
+    /// STARTUP stopped finding more bandwidth.
+    FullBwReached,
+    /// DRAIN emptied the queue STARTUP built.
+    InflightDrained,
+    /// STARTUP lost packets that `--loss_mode` counts as congestion.
+    LossLimit,
+    /// PROBE_RTT held inflight down for its duration.
+    ProbeRttDone,
+    /// An operator paused the flow in STARTUP.
+    Manual,
+}
+
+/// The `probe_bw` gain phase whose samples a report carries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum PulsePhase {
+    /// The up pulse, which probes above the estimate, including its ramp.
+    Up,
+    /// The down pulse, which drains what the up pulse queued.
+    Down,
+    /// The six rounds at the estimate.
+    Cruise,
+    /// A report from a program that does not pulse.
+    Steady,
+}
+
+impl PulsePhase {
+    // `probe_bw` reports as each phase ends, so the phase of the report after this one's
+    fn following(self) -> PulsePhase {
+        match self {
+            PulsePhase::Up => PulsePhase::Down,
+            PulsePhase::Down => PulsePhase::Cruise,
+            PulsePhase::Cruise => PulsePhase::Up,
+            PulsePhase::Steady => PulsePhase::Steady,
+        }
+    }
+}
+
+impl BbrCore {
+    // overrides the pulse the program just started
+    pub(crate) fn set_pulse(&self, gain: f64, actions: &mut Vec<Action>) {
+        let pulse = if self.pacing {
+            ("Rate", self.pulse_rate(gain) as u64)
+        } else {
+            ("Cwnd", u64::from(self.bdp_cwnd(gain)))
+        };
+        actions.push(Action::Update(vec![pulse]));
+    }
+
+    // keeps the probe bw program's cwnds in line with a new min_rtt, and with a delay budget,
+    // its pulses too
+    fn update_min_rtt_cwnd(&self, actions: &mut Vec<Action>) {
+        if !self.pacing || self.delay_budget_us.is_some() {
+            self.replace_probe_bw_rate(actions);
+        } else if self.cwnd_cap {
+            actions.push(Action::Update(vec![(
+                "cwndCap",
+                u64::from(self.probe_bw_cwnd()),
+            )]));
+        }
+    }
+
+    // replaces the variables in the probe bw program if the bottle rate or min_rtt changes
+    pub(crate) fn replace_probe_bw_rate(&self, actions: &mut Vec<Action>) {
+        let derived = self.model().derive();
+        if !self.pacing {
+            if self.logs_reports() {
+                info!(
+                    bdp = self.bdp_cwnd(1.0),
+                    bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+                    share = self.rate_share,
+                    "PROBE_BW: updating cwnd"
+                );
+            }
+            actions.push(Action::Update(self.probe_bw_cwnd_pulse(&derived)));
+            return;
+        }
+
+        let cwnd_cap = self.probe_bw_cwnd();
+        let mut update = vec![
+            ("bottleRate", derived.cruise_rate),
+            ("threeFourthsRate", derived.down_rate),
+            ("fiveFourthsRate", derived.up_rate),
+        ];
+        if self.probe_bw_ramp {
+            update.push(("nineEighthsRate", derived.ramp_rate));
+        }
+        // in rate-only mode, the cap only changes around a loss burst
+        if self.cwnd_cap || self.registers.get("cwndCap") != Some(&u64::from(cwnd_cap)) {
+            update.push(("cwndCap", u64::from(cwnd_cap)));
+        }
+        actions.push(Action::Update(update));
+        if self.logs_reports() {
+            info!(
+                cwnd = cwnd_cap,
+                down_rate = %Rate::from_bytes_per_sec(derived.down_rate as f64),
+                bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+                up_rate = %Rate::from_bytes_per_sec(derived.up_rate as f64),
+                share = self.rate_share,
+                "PROBE_BW: updating rate"
+            );
+        }
+    }
+
+    fn install_probe_bw(&mut self, actions: &mut Vec<Action>) {
+        if self.capabilities.lacks_probe_bw() {
+            self.install_fallback(actions);
+            return;
+        }
+
+        // first, install the rate and cwnd for state 0 for state 0
+        self.select_rtt_profile();
+        self.cycle_start = None;
+        let min_rtt = self.min_rtt_us;
+        self.expected_phase = PulsePhase::Up;
+        self.pulse_desyncs_in_row = 0;
+        self.cycle = CycleSummary::default();
+        self.queue_backoff = false;
+        self.probing = true;
+        self.probe_limited = false;
+        self.probe_start_rate = self.bottle_rate;
+        let derived = self.model().derive();
+        if !self.pacing {
+            info!(
+                bdp = self.bdp_cwnd(1.0),
+                bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+                min_rtt_us = min_rtt,
+                share = self.rate_share,
+                "switching to cwnd-pulsed PROBE_BW"
+            );
+            let mut fields = self.probe_bw_cwnd_pulse(&derived);
+            fields.push(("bw0", self.bottle_rate as u64));
+            if let Some(pulse_us) = self.pulse_length_us {
+                fields.push(("pulseUs", u64::from(pulse_us)));
+            }
+            if self.pulse_shift {
+                fields.push(("pulseShiftUs", self.pulse_shift_us()));
+            }
+            fields.extend(self.rtt_profile_fields());
+            // the program starts in the first half of the up pulse
+            let first_pulse_cwnd = if self.probe_bw_ramp {
+                derived.ramp_cwnd
+            } else {
+                derived.up_cwnd
+            };
+            actions.push(Action::Update(vec![("Cwnd", u64::from(first_pulse_cwnd))]));
+            actions.push(Action::SetProgram {
+                program: "probe_bw",
+                fields,
+            });
+            return;
+        }
+
+        let cwnd_cap = self.probe_bw_cwnd();
+
+        info!(
+            cwnd = cwnd_cap,
+            down_rate = %Rate::from_bytes_per_sec(derived.down_rate as f64),
+            bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+            up_rate = %Rate::from_bytes_per_sec(derived.up_rate as f64),
+            min_rtt_us = min_rtt,
+            share = self.rate_share,
+            "switching to PROBE_BW"
+        );
+
+        let mut fields = vec![
+            ("cwndCap", u64::from(cwnd_cap)),
+            ("bottleRate", derived.cruise_rate),
+            ("threeFourthsRate", derived.down_rate),
+            ("fiveFourthsRate", derived.up_rate),
+            // the bandwidth filter starts from the current estimate
+            ("bw0", self.bottle_rate as u64),
+        ];
+        // the program starts in the first half of the up pulse
+        let first_pulse_rate = if self.probe_bw_ramp {
+            fields.push(("nineEighthsRate", derived.ramp_rate));
+            derived.ramp_rate
+        } else {
+            derived.up_rate
+        };
+        if let Some(pulse_us) = self.pulse_length_us {
+            fields.push(("pulseUs", u64::from(pulse_us)));
+        }
+        if self.pulse_shift {
+            fields.push(("pulseShiftUs", self.pulse_shift_us()));
+        }
+        fields.extend(self.rtt_profile_fields());
+        actions.push(Action::Update(vec![
+            ("Cwnd", u64::from(cwnd_cap)),
+            ("Rate", first_pulse_rate),
+        ]));
+        actions.push(Action::SetProgram {
+            program: "probe_bw",
+            fields,
+        });
+    }
+
+    // for datapaths that reject probe_bw: the datapath itself grows cwnd by an MSS per round
+    // and halves it on loss, starting from the estimated BDP. the flow stays in PROBE_BW and
+    // only keeps its estimates up to date from then on
+    pub(crate) fn install_fallback(&mut self, actions: &mut Vec<Action>) {
+        self.mode_clock.switch(self.curr_mode);
+        self.curr_mode = BbrMode::ProbeBw;
+        self.degraded = true;
+        let cwnd = self.bdp_cwnd(1.0).max(self.init_cwnd);
+        warn!(
+            cwnd,
+            bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+            min_rtt_us = self.min_rtt_us,
+            "switching to cwnd-only AIMD"
+        );
+
+        let mut fields = vec![
+            ("Cwnd", u64::from(cwnd)),
+            ("aiBytes", u64::from(self.mss)),
+            ("minCwnd", u64::from(self.probe_rtt_cwnd())),
+        ];
+        // a datapath that takes Rate at all accepted it in STARTUP, and must not keep pacing
+        if self.pacing {
+            fields.push(("Rate", UNPACED_RATE));
+        }
+        actions.push(Action::SetProgram {
+            program: "aimd",
+            fields,
+        });
+    }
+
+    fn on_fallback_report(&mut self, now: Instant, m: Measurement) {
+        if m.minrtt_us < self.min_rtt_us && !self.is_lossy_rtt_sample(&m, self.min_rtt_us) {
+            self.min_rtt_us = m.minrtt_us;
+            self.measured_min_rtt(now, false);
+            self.record_path(now);
+        }
+        let rate = self.sample_rate(&m);
+        self.inflight_bytes = m.inflight_bytes;
+        if self.logs_reports() {
+            info!(
+                rate = %Rate::from_bytes_per_sec(rate),
+                min_rtt_us = self.min_rtt_us,
+                srtt_us = m.srtt_us,
+                loss = m.loss,
+                inflight_bytes = m.inflight_bytes,
+                "AIMD"
+            );
+        }
+    }
+
+    fn switch_mode(&mut self, mode: BbrMode, reason: TransitionReason) {
+        info!(from = ?self.curr_mode, to = ?mode, ?reason, "mode change");
+        self.mode_clock.switch(self.curr_mode);
+        self.curr_mode = mode;
+        self.transition_reason = Some(reason);
+    }
+
+    // whether the flow logs a line for every report, rather than per cycle or transition
+    fn logs_reports(&self) -> bool {
+        self.log_granularity == LogGranularity::Report
+    }
+
+    fn enter_probe_rtt(&mut self, now: Instant, actions: &mut Vec<Action>) {
+        self.switch_mode(BbrMode::ProbeRtt, TransitionReason::MinRttExpired);
+        self.probe_rtt_entries += 1;
+        info!(
+            min_rtt_us = self.min_rtt_us,
+            srtt_us = self.srtt_us,
+            rttvar_us = self.rttvar_us,
+            bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+            "switching to PROBE_RTT"
+        );
+
+        self.groups.mark_probe_rtt(self.group, now);
+        self.last_probe_rtt = Some(now);
+        // PROBE_RTT is no idle time of the flow's own
+        self.last_probe_bw_report = None;
+        // high-RTT paths drain to part of the BDP of the min RTT that is about to be forgotten
+        let high_rtt_target = self
+            .high_rtt
+            .then(|| self.bdp_cwnd(HIGH_RTT_PROBE_RTT_GAIN));
+        self.pre_probe_rtt_min_rtt_us = self.min_rtt_us;
+        self.min_rtt_us = 0x3fff_ffff;
+        let target = high_rtt_target.unwrap_or_else(|| self.probe_rtt_cwnd());
+        actions.push(Action::SetProgram {
+            program: "probe_rtt",
+            fields: vec![
+                ("targetInflight", u64::from(target)),
+                ("probeRttUs", u64::from(self.probe_rtt_duration_us())),
+            ],
+        });
+        actions.push(Action::Update(vec![("Cwnd", u64::from(target))]));
+    }
+
+    // how long PROBE_RTT holds inflight down: with the group's other flows still sending, the
+    // queue only drains where their PROBE_RTTs overlap, so each lasts longer the more there are
+    fn probe_rtt_duration_us(&self) -> u32 {
+        if !self.scale_probe_rtt {
+            return PROBE_RTT_DURATION_US;
+        }
+        let members = self.groups.size(self.group) as u32;
+        PROBE_RTT_DURATION_US * members.clamp(1, PROBE_RTT_MAX_GROUP_SCALE)
+    }
+
+    // restarts the min_rtt timer from a measurement of the flow's own, and offers it to the
+    // other flows in the bottleneck group
+    fn measured_min_rtt(&mut self, now: Instant, probed: bool) {
+        self.restart_min_rtt_timer(now);
+        self.min_rtt_measured = Some(now);
+        if self.share_min_rtt {
+            self.groups.share_min_rtt(
+                self.group,
+                SharedMinRtt {
+                    min_rtt_us: self.min_rtt_us,
+                    measured: now,
+                    probed,
+                },
+            );
+        }
+    }
+
+    // takes a min_rtt another flow in the bottleneck group measured since this flow last did,
+    // as if the flow had measured it itself. Other flows' PROBE_RTTs mostly measure the
+    // queue this one keeps up, so a flow in PROBE_RTT keeps to its own measurement.
+    pub(crate) fn adopt_shared_min_rtt(&mut self, actions: &mut Vec<Action>) {
+        if !self.share_min_rtt || self.curr_mode == BbrMode::ProbeRtt {
+            return;
+        }
+        let shared = match self.groups.shared_min_rtt(self.group) {
+            Some(shared)
+                if self
+                    .min_rtt_measured
+                    .is_none_or(|mine| shared.measured > mine) =>
+            {
+                shared
+            }
+            _ => return,
+        };
+        self.min_rtt_measured = Some(shared.measured);
+        if !shared.probed && shared.min_rtt_us >= self.min_rtt_us {
+            return;
+        }
+
+        self.restart_min_rtt_timer(shared.measured);
+        if shared.min_rtt_us == self.min_rtt_us {
+            return;
+        }
+        info!(
+            old_min_rtt_us = self.min_rtt_us,
+            min_rtt_us = shared.min_rtt_us,
+            probed = shared.probed,
+            "taking min_rtt from bottleneck group"
+        );
+        self.min_rtt_us = shared.min_rtt_us;
+        if self.curr_mode == BbrMode::ProbeBw && !self.degraded {
+            self.update_min_rtt_cwnd(actions);
+        }
+    }
+
+    // whether another flow in the bottleneck group recently entered PROBE_RTT without us
+    fn group_probe_rtt_pending(&self, now: Instant) -> bool {
+        let window = match self.probe_rtt_sync_window {
+            Some(window) if self.probe_rtt => window,
+            _ => return false,
+        };
+
+        match self.groups.last_probe_rtt(self.group) {
+            Some(group_entry) => {
+                now.saturating_duration_since(group_entry) <= window
+                    && !matches!(self.last_probe_rtt, Some(mine) if mine >= group_entry)
+            }
+            None => false,
+        }
+    }
+
+    // hands the flow back to the datapath: window-limited at the current cwnd cap, unpaced
+    fn release(&mut self, now: Instant, actions: &mut Vec<Action>) {
+        let cwnd = if self.curr_mode == BbrMode::Startup {
+            self.init_cwnd
+        } else {
+            self.capped_cwnd()
+        };
+        info!(
+            mode = ?self.curr_mode,
+            elapsed_s = (now - self.start).as_secs_f32(),
+            bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+            min_rtt_us = self.min_rtt_us,
+            cwnd,
+            "releasing flow"
+        );
+
+        self.released = true;
+        self.shutdown.deregister(self.flow.sock_id);
+        // init_program only reports, so nothing overwrites these after the agent is gone
+        actions.push(Action::SetProgram {
+            program: "init_program",
+            fields: vec![("Cwnd", u64::from(cwnd)), ("Rate", UNPACED_RATE)],
+        });
+    }
+
+    // gives up on updates: init_program only reports, so the flow stays at its estimate
+    pub(crate) fn freeze(&mut self, actions: &mut Vec<Action>) {
+        let cwnd = self.capped_cwnd();
+        warn!(
+            failures = self.failures_in_row,
+            cwnd,
+            bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+            "updates keep failing, freezing flow"
+        );
+
+        self.frozen = true;
+        let mut fields = vec![("Cwnd", u64::from(cwnd))];
+        if self.pacing {
+            fields.push(("Rate", self.pulse_rate(1.0) as u64));
+        }
+        actions.push(Action::SetProgram {
+            program: "init_program",
+            fields,
+        });
+    }
+
+    /// Whether the flow has been handed back to the datapath for shutdown.
+    pub fn is_released(&self) -> bool {
+        self.released
+    }
+
+    /// Hands the flow back to the datapath now rather than on its next report, as when the
+    /// agent is about to die: the actions install a program that leaves it window-limited and
+    /// unpaced. None if the flow was already released.
+    pub fn release_now(&mut self, now: Instant) -> Vec<Action> {
+        let mut actions = vec![];
+        if !self.released {
+            self.release(now, &mut actions);
+            self.record_actions(&actions);
+        }
+        actions
+    }
+
+    /// Handles one report, read field by field, and returns the actions to apply, in order.
+    ///
+    /// This is what a flow does with each report portus delivers, for transports of other
+    /// kinds: it reinstalls the program when [`BbrCore::take_reinstall`] says to, counts
+    /// reports from other program instances as stale, and skips reports it cannot read,
+    /// warning about them, before it hands the others to [`BbrCore::on_measurement`]. Callers
+    /// apply the actions, report each program they install with
+    /// [`BbrCore::program_installed`], and may time their handling for
+    /// [`BbrCore::record_handling`].
+    pub fn handle_report_values(&mut self, now: Instant, report: ReportValues) -> Vec<Action> {
+        let received = report.received;
+        self.handle_report(now, report.program_uid, received, |field| {
+            report.fields.get(field).copied()
+        })
+    }
+
+    // as `handle_report_values`, reading the fields from wherever the transport keeps them
+    pub(crate) fn handle_report(
+        &mut self,
+        now: Instant,
+        program_uid: u32,
+        received: Option<Instant>,
+        get_field: impl Fn(&str) -> Option<u64>,
+    ) -> Vec<Action> {
+        if let Some(actions) = self.take_reinstall() {
+            return actions;
+        }
+        // fields can only be read in the scope of the program that sent the report
+        if self.program_uid != program_uid {
+            self.count_stale_report();
+            return vec![];
+        }
+
+        let span = self.span.clone();
+        let _entered = span.enter();
+        match Measurement::from_report_fields(self.curr_mode, program_uid, get_field) {
+            Ok(measurement) => self.on_measurement(
+                now,
+                Measurement {
+                    received,
+                    ..measurement
+                },
+            ),
+            Err(err @ BbrError::ProgramVersion { .. }) => {
+                if let Some(suppressed) =
+                    self.limit_warning("reinstalling program", &err.to_string(), now)
+                {
+                    warn!(%err, suppressed, "reinstalling program");
+                }
+                self.program_outdated();
+                vec![]
+            }
+            Err(err) => {
+                if let Some(suppressed) =
+                    self.limit_warning("report is missing fields", &err.to_string(), now)
+                {
+                    warn!(%err, suppressed, "report is missing fields");
+                }
+                vec![]
+            }
+        }
+    }
+
+    /// Handles one report and returns the actions to apply, in order.
+    pub fn on_measurement(&mut self, now: Instant, m: Measurement) -> Vec<Action> {
+        let span = self.span.clone();
+        let _entered = span.enter();
+        let mut actions = vec![];
+        self.mode_clock.advance(now);
+        // if report is not for the current program, please return
+        if self.released || self.frozen {
+            return actions;
+        }
+        if self.program_uid != m.program_uid {
+            self.count_stale_report();
+            return actions;
+        }
+        if let Some(age) = self.report_age(now, &m) {
+            if let Some(suppressed) = self.limit_warning("ignoring a late report", "", now) {
+                warn!(
+                    age_ms = age.as_millis() as u64,
+                    suppressed, "ignoring a late report"
+                );
+            }
+            self.late_reports += 1;
+            self.snapshots.update(self.snapshot());
+            return actions;
+        }
+
+        self.reports += 1;
+        self.failures_in_row = 0;
+        if let Some(recorder) = &self.recorder {
+            recorder.report(self.flow.sock_id, &m, now);
+        }
+        // init_program's pacing ramp ends with its first report, so a reinstall must not
+        // restart it
+        if self.program == "init_program" {
+            self.registers
+                .retain(|reg, _| matches!(*reg, "Cwnd" | "Rate"));
+        }
+        if self.shutdown.is_requested() {
+            self.release(now, &mut actions);
+            self.record_actions(&actions);
+            return actions;
+        }
+        self.sync_pause(&mut actions);
+        self.sync_rate_limit(&mut actions);
+        self.adopt_shared_min_rtt(&mut actions);
+
+        self.lost_packets += u64::from(m.loss);
+        self.acked_packets += u64::from(m.acked);
+        if self.is_stalled(&m) {
+            self.stalled_reports += 1;
+        }
+        // probe_rtt reports no packets at all
+        if let Some(loss_rate) = m.loss_rate() {
+            self.loss_rate = loss_rate;
+        }
+
+        // probe_rtt does not smooth its RTT samples
+        if m.srtt_us > 0 {
+            self.srtt_us = m.srtt_us;
+            self.rttvar_us = m.rttvar_us;
+        }
+        self.measure_install_latency(now, &m, &mut actions);
+        self.sample_mss(&m, &mut actions);
+
+        match self.curr_mode {
+            _ if self.degraded => self.on_fallback_report(now, m),
+            _ if self.short_flow_left.is_some() => self.on_short_flow_report(now, m, &mut actions),
+            BbrMode::Startup => self.on_startup_report(now, m, &mut actions),
+            BbrMode::Drain => self.on_drain_report(now, m, &mut actions),
+            BbrMode::ProbeRtt => self.on_probe_rtt_report(now, m, &mut actions),
+            BbrMode::ProbeBw => self.on_probe_bw_report(now, m, &mut actions),
+        }
+        self.sync_probe_schedule(now, &mut actions);
+
+        self.smooth_rates(&mut actions);
+        self.elide_unchanged(&mut actions);
+        self.tag_update(now, &mut actions);
+        self.record_actions(&actions);
+
+        actions
+    }
+
+    // how far `pulse_shift` delays the ends of the up and down pulses: the install latency,
+    // but at most `MAX_PULSE_SHIFT` of a pulse
+    fn pulse_shift_us(&self) -> u64 {
+        let pulse_us = f64::from(self.pulse_length_us.unwrap_or(self.min_rtt_us));
+        self.install_latency_us
+            .map_or(0.0, |latency_us| latency_us.min(MAX_PULSE_SHIFT * pulse_us)) as u64
+    }
+
+    // with `pulse_shift`, writes a new nonce along with the last update to `probe_bw`, unless
+    // an earlier one has not been echoed yet
+    fn tag_update(&mut self, now: Instant, actions: &mut [Action]) {
+        if !self.pulse_shift || self.program != "probe_bw" || self.pending_nonce.is_some() {
+            return;
+        }
+        if actions
+            .iter()
+            .any(|action| matches!(action, Action::SetProgram { .. }))
+        {
+            return;
+        }
+        if let Some(Action::Update(fields)) = actions.last_mut() {
+            self.last_nonce += 1;
+            fields.push(("updateNonce", u64::from(self.last_nonce)));
+            self.pending_nonce = Some((self.last_nonce, now));
+        }
+    }
+
+    // the report echoes the pending nonce: it took effect with the first ack the program
+    // handled `nonce_age_us` before the report. What is left of the time since it was sent
+    // is the round trip from a report to the update it prompts taking effect, the delay an
+    // update that restarts a pulse starts it late by
+    fn measure_install_latency(
+        &mut self,
+        now: Instant,
+        m: &Measurement,
+        actions: &mut Vec<Action>,
+    ) {
+        let sent = match self.pending_nonce {
+            Some((nonce, sent)) if nonce == m.nonce => sent,
+            _ => return,
+        };
+        self.pending_nonce = None;
+        let elapsed_us = m
+            .received
+            .unwrap_or(now)
+            .saturating_duration_since(sent)
+            .as_micros() as f64;
+        let latency_us = (elapsed_us - f64::from(m.nonce_age_us)).max(0.0);
+        let smoothed = match self.install_latency_us {
+            Some(avg) => avg + INSTALL_LATENCY_GAIN * (latency_us - avg),
+            None => latency_us,
+        };
+        self.install_latency_us = Some(smoothed);
+
+        let shift_us = self.pulse_shift_us();
+        let installed = self
+            .registers
+            .get("pulseShiftUs")
+            .copied()
+            .unwrap_or_default();
+        let pulse_us = self.pulse_length_us.unwrap_or(self.min_rtt_us);
+        if shift_us.abs_diff(installed) > u64::from(pulse_us / PULSE_SHIFT_RESOLUTION) {
+            info!(
+                install_latency_us = smoothed as u64,
+                shift_us, "PROBE_BW: shifting pulse ends by the install latency"
+            );
+            actions.push(Action::Update(vec![("pulseShiftUs", shift_us)]));
+        }
+    }
+
+    // a short flow only follows the min RTT, and counts down the bytes it acks, from the
+    // report's rate where the datapath does not count acked packets
+    fn on_short_flow_report(&mut self, now: Instant, m: Measurement, actions: &mut Vec<Action>) {
+        if m.minrtt_us < self.min_rtt_us && !self.is_lossy_rtt_sample(&m, self.min_rtt_us) {
+            self.min_rtt_us = m.minrtt_us;
+            self.measured_min_rtt(now, false);
+        }
+
+        let acked_bytes = if m.acked > 0 {
+            u64::from(m.acked) * u64::from(self.mss)
+        } else {
+            (m.rate_incoming * f64::from(self.min_rtt_us) / 1e6) as u64
+        };
+        let left = self
+            .short_flow_left
+            .unwrap_or_default()
+            .saturating_sub(acked_bytes);
+        if self.logs_reports() {
+            info!(
+                acked_bytes,
+                left,
+                min_rtt_us = self.min_rtt_us,
+                "short flow"
+            );
+        }
+        if left > 0 {
+            self.short_flow_left = Some(left);
+            return;
+        }
+
+        // STARTUP takes over from the window the flow already has
+        self.short_flow_left = None;
+        let cwnd = self.registers.get("Cwnd").copied().unwrap_or_default();
+        let mut fields = self.start_fields();
+        for (reg, val) in &mut fields {
+            if *reg == "Cwnd" {
+                *val = (*val).max(cwnd);
+            }
+        }
+        info!(cwnd, "short flow outgrew its hint, starting STARTUP");
+        actions.push(Action::SetProgram {
+            program: "init_program",
+            fields,
+        });
+    }
+
+    fn on_startup_report(&mut self, now: Instant, m: Measurement, actions: &mut Vec<Action>) {
+        if m.minrtt_us < self.min_rtt_us && !self.is_lossy_rtt_sample(&m, self.min_rtt_us) {
+            self.min_rtt_us = m.minrtt_us;
+            self.measured_min_rtt(now, false);
+        }
+
+        self.rate_share = self.weights.share(self.flow.sock_id);
+        let loss_limited = self.loss_mode.is_congestion(
+            m.loss,
+            m.minrtt_us,
+            self.min_rtt_us,
+            self.loss_rtt_inflation,
+        );
+        let rate = self.sample_rate(&m);
+        if self.is_stalled(&m) {
+            // nor does a round that delivered next to nothing
+            if self.logs_reports() {
+                info!("STARTUP: stalled round, not sampling its rate");
+            }
+        } else if m.receiver_limited {
+            // the receive window says nothing about the path, and neither does its plateau
+            if self.logs_reports() {
+                info!(
+                    rate = %Rate::from_bytes_per_sec(rate),
+                    "STARTUP: receiver-limited round"
+                );
+            }
+        } else {
+            if self.bottle_rate < rate {
+                self.bottle_rate = rate;
+                self.bottle_rate_timeout = now + self.probe_rtt_interval;
+            }
+
+            if rate >= self.full_bw * STARTUP_GROWTH_TARGET {
+                self.full_bw = rate;
+                self.full_bw_rounds = 0;
+            } else {
+                self.full_bw_rounds += 1;
+            }
+        }
+        self.record_path(now);
+
+        if self.logs_reports() {
+            info!(
+                elapsed_s = (now - self.start).as_secs_f32(),
+                rate = %Rate::from_bytes_per_sec(rate),
+                bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+                min_rtt_us = self.min_rtt_us,
+                full_bw_rounds = self.full_bw_rounds,
+                "STARTUP"
+            );
+        }
+
+        let exit = if self.paused {
+            Some(TransitionReason::Manual)
+        } else if self.full_bw_rounds >= STARTUP_FULL_BW_ROUNDS {
+            Some(TransitionReason::FullBwReached)
+        } else if loss_limited {
+            Some(TransitionReason::LossLimit)
+        } else {
+            None
+        };
+        if let Some(reason) = exit {
+            self.pipe_full = reason != TransitionReason::Manual;
+            self.enter_drain(reason, actions);
+            return;
+        }
+
+        let bottle_rate = self.paced_bottle_rate();
+        let cwnd = if self.cwnd_cap {
+            (bottle_rate * self.startup_cwnd_gain * f64::from(self.min_rtt_us) / 1e6) as u32
+        } else {
+            UNCAPPED_CWND
+        };
+        // never below the initial window, or the flow's share of it in an incast
+        let mut update = vec![(
+            "Cwnd",
+            u64::from(cwnd.max(self.start_cwnd.min(self.init_cwnd))),
+        )];
+        if self.pacing {
+            update.push(("Rate", self.pulse_rate(self.startup_gain) as u64));
+        }
+        actions.push(Action::Update(update));
+    }
+
+    fn enter_drain(&mut self, reason: TransitionReason, actions: &mut Vec<Action>) {
+        self.switch_mode(BbrMode::Drain, reason);
+        let bottle_rate = self.paced_bottle_rate();
+        let bdp = (bottle_rate * f64::from(self.min_rtt_us) / 1e6) as u32;
+        info!(
+            bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+            min_rtt_us = self.min_rtt_us,
+            bdp,
+            "switching to DRAIN"
+        );
+
+        actions.push(Action::SetProgram {
+            program: "drain",
+            fields: vec![("bdpTarget", u64::from(bdp))],
+        });
+        // without pacing, holding cwnd at the BDP drains the queue instead
+        let update = if self.pacing {
+            ("Rate", self.pulse_rate(self.drain_gain) as u64)
+        } else {
+            ("Cwnd", u64::from(self.bdp_cwnd(1.0)))
+        };
+        actions.push(Action::Update(vec![update]));
+    }
+
+    // drain reports once the queue is drained (or after a round), so move on to PROBE_BW
+    fn on_drain_report(&mut self, now: Instant, m: Measurement, actions: &mut Vec<Action>) {
+        if m.minrtt_us < self.min_rtt_us && !self.is_lossy_rtt_sample(&m, self.min_rtt_us) {
+            self.min_rtt_us = m.minrtt_us;
+            self.measured_min_rtt(now, false);
+            self.record_path(now);
+        }
+
+        self.switch_mode(BbrMode::ProbeBw, TransitionReason::InflightDrained);
+        // the flow only now has a bandwidth estimate worth keeping, so its first PROBE_RTT
+        // waits a grace period in which it has it
+        if self.probe_rtt_alignment.is_none() {
+            self.min_rtt_timeout = self.min_rtt_timeout.max(now + self.probe_rtt_grace);
+        }
+        self.install_probe_bw(actions);
+    }
+
+    fn on_probe_rtt_report(&mut self, now: Instant, m: Measurement, actions: &mut Vec<Action>) {
+        self.min_rtt_us = if !m.has_rtt_sample() {
+            info!("PROBE_RTT saw no RTT sample, keeping min_rtt");
+            self.pre_probe_rtt_min_rtt_us
+        } else if self.is_lossy_rtt_sample(&m, self.pre_probe_rtt_min_rtt_us)
+            || self.is_min_rtt_spike(m.minrtt_us, self.pre_probe_rtt_min_rtt_us)
+        {
+            self.pre_probe_rtt_min_rtt_us
+        } else {
+            m.minrtt_us
+        };
+        self.measured_min_rtt(now, true);
+        self.record_path(now);
+
+        self.install_probe_bw(actions);
+        self.switch_mode(BbrMode::ProbeBw, TransitionReason::ProbeRttDone);
+
+        info!(min_rtt_us = self.min_rtt_us, "PROBE_RTT");
+    }
+
+    // a cruise phase that never brought the RTT back near min_rtt means this flow keeps a
+    // standing queue: cut the up pulse that just started and cruise below the estimate until
+    // a report shows the queue gone
+    fn check_standing_queue(&mut self, m: Measurement, actions: &mut Vec<Action>) {
+        // neither a receiver-limited report nor one without an RTT sample shows the queue
+        if m.receiver_limited || !m.has_rtt_sample() {
+            return;
+        }
+
+        let queued = f64::from(m.minrtt_us) > f64::from(self.min_rtt_us) * QUEUE_RTT_THRESHOLD;
+        if !self.queue_backoff && queued && m.pulse_phase() == PulsePhase::Cruise {
+            info!(
+                minrtt_us = m.minrtt_us,
+                min_rtt_us = self.min_rtt_us,
+                "PROBE_BW: standing queue, backing off"
+            );
+            self.queue_backoff = true;
+            self.set_pulse(QUEUE_BACKOFF_GAIN, actions);
+            self.replace_probe_bw_rate(actions);
+        } else if self.queue_backoff && !queued {
+            info!(minrtt_us = m.minrtt_us, "PROBE_BW: queue drained");
+            self.queue_backoff = false;
+            self.replace_probe_bw_rate(actions);
+        }
+    }
+
+    // a report that lost a large part of the window halves cwnd at once, to half of what was
+    // in flight but no less than what still is, and holds it there, without probing up, for a
+    // few pulse cycles. A report covers about a round trip, so its packets stand in for the
+    // window
+    fn check_loss_burst(&mut self, m: &Measurement, actions: &mut Vec<Action>) {
+        let fraction = match self.loss_burst_fraction {
+            Some(fraction) => fraction,
+            None => return,
+        };
+        if !is_loss_burst(m.loss, m.acked, fraction) {
+            return;
+        }
+
+        // datapaths that do not report what is in flight lost about a BDP
+        let window = match m.inflight_bytes {
+            0 => self.bdp_cwnd(1.0),
+            inflight => inflight,
+        };
+        let lost_bytes = m.loss.saturating_mul(self.mss);
+        let cwnd = self
+            .model()
+            .recovery_cwnd(window, m.inflight_bytes, lost_bytes);
+        self.loss_bursts += 1;
+        self.loss_burst_cycles = LOSS_BURST_HOLD_CYCLES;
+        self.loss_burst_cwnd = Some(cwnd);
+        warn!(
+            loss = m.loss,
+            acked = m.acked,
+            window,
+            cwnd,
+            cycles = LOSS_BURST_HOLD_CYCLES,
+            "loss burst, halving cwnd"
+        );
+        actions.push(Action::Update(vec![("Cwnd", u64::from(cwnd))]));
+        self.replace_probe_bw_rate(actions);
+    }
+
+    // counts down the pulse cycles a loss burst holds cwnd for, and lifts the hold after the
+    // last one
+    fn count_loss_burst_cycle(&mut self, actions: &mut Vec<Action>) {
+        if self.loss_burst_cycles == 0 {
+            return;
+        }
+        self.loss_burst_cycles -= 1;
+        if self.loss_burst_cycles > 0 {
+            return;
+        }
+
+        info!("loss burst over, probing again");
+        self.loss_burst_cwnd = None;
+        if !self.queue_backoff {
+            self.set_pulse(self.first_pulse_gain(), actions);
+        }
+        self.replace_probe_bw_rate(actions);
+    }
+
+    // takes the delivery ratio of the cycle that just ended, and warns once its cycles have
+    // delivered well below the pacing rate for long enough. A cycle held back throughout
+    // says nothing about the path, and leaves both as they were
+    fn end_delivery_cycle(&mut self) {
+        let Some(ratio) = self.cycle.delivery_ratio() else {
+            return;
+        };
+        self.delivery_ratio = Some(ratio);
+        if ratio >= DELIVERY_GAP_RATIO {
+            if self.delivery_gaps_in_row >= DELIVERY_GAP_WARN_CYCLES {
+                info!(ratio, "delivering at the pacing rate again");
+            }
+            self.delivery_gaps_in_row = 0;
+            return;
+        }
+        self.delivery_gap_cycles += 1;
+        self.delivery_gaps_in_row += 1;
+        if self.delivery_gaps_in_row == DELIVERY_GAP_WARN_CYCLES {
+            warn!(
+                ratio,
+                cycles = self.delivery_gaps_in_row,
+                pacing_rate = %Rate::from_bytes_per_sec(self.pacing_rate()),
+                "delivering well below the pacing rate, the model may overestimate the path"
+            );
+        }
+    }
+
+    // at the end of each pulse cycle, decides whether the cycle that just started probes:
+    // after enough probes found no more bandwidth, only every `stale_probe_interval`th does
+    fn next_pulse_cycle(&mut self, actions: &mut Vec<Action>) {
+        // a paused flow's cycles probe nothing, so they say nothing about stale probes either
+        if self.paused {
+            return;
+        }
+
+        // cycles held down after a loss burst did not probe
+        if self.probing && !self.probe_limited && self.loss_burst_cycles == 0 {
+            if self.bottle_rate > self.probe_start_rate {
+                self.stale_probes = 0;
+            } else {
+                self.stale_probes = self.stale_probes.saturating_add(1);
+            }
+        }
+
+        self.probe_limited = false;
+
+        let probe = self.stale_probes < self.stale_probe_limit
+            || self.cycles_since_probe + 1 >= self.stale_probe_interval;
+        if probe {
+            self.cycles_since_probe = 0;
+            self.probe_start_rate = self.bottle_rate;
+        } else {
+            self.cycles_since_probe += 1;
+        }
+
+        let gain_changed = self.adapt_probe_gain();
+        let cadence_changed = probe != self.probing;
+        if cadence_changed {
+            info!(
+                stale_probes = self.stale_probes,
+                probing = probe,
+                "PROBE_BW: changing probe cadence"
+            );
+            self.probing = probe;
+        }
+        if cadence_changed || gain_changed {
+            self.set_pulse(self.first_pulse_gain(), actions);
+            self.replace_probe_bw_rate(actions);
+        }
+    }
+
+    // a report from another phase than the cycle says means an update raced a transition, or
+    // the datapath lost track of the cycle. the flow goes on from the reported phase, and
+    // returns whether it should restart the cycle
+    fn pulse_desynced(&mut self, now: Instant, phase: PulsePhase) -> bool {
+        let expected = std::mem::replace(&mut self.expected_phase, phase.following());
+        if phase == expected {
+            self.pulse_desyncs_in_row = 0;
+            return false;
+        }
+
+        self.pulse_desyncs += 1;
+        self.pulse_desyncs_in_row += 1;
+        if let Some(suppressed) =
+            self.limit_warning("report from an unexpected pulse phase", "", now)
+        {
+            warn!(
+                ?expected,
+                reported = ?phase,
+                in_row = self.pulse_desyncs_in_row,
+                suppressed,
+                "report from an unexpected pulse phase"
+            );
+        }
+        self.reset_desynced_pulses && self.pulse_desyncs_in_row >= PULSE_DESYNC_RESET
+    }
+
+    // once the estimate stops moving, the up pulse only has to notice when it moves again, and
+    // a smaller one keeps less of a queue. returns whether the gain changed
+    fn adapt_probe_gain(&mut self) -> bool {
+        let stable_gain = match self.stable_probe_gain {
+            Some(gain) => gain,
+            None => return false,
+        };
+
+        let change = (self.bottle_rate - self.cycle_start_rate).abs();
+        if change > self.cycle_start_rate * STABLE_BW_TOLERANCE {
+            self.stable_cycles = 0;
+        } else {
+            self.stable_cycles = self.stable_cycles.saturating_add(1);
+        }
+        self.cycle_start_rate = self.bottle_rate;
+
+        let gain = if self.stable_cycles >= STABLE_PROBE_CYCLES {
+            stable_gain
+        } else {
+            PROBE_GAIN
+        };
+        if gain == self.probe_gain {
+            return false;
+        }
+
+        info!(
+            probe_gain = gain,
+            stable_cycles = self.stable_cycles,
+            bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+            "PROBE_BW: changing probe gain"
+        );
+        self.probe_gain = gain;
+        true
+    }
+
+    // without PROBE_RTT, min_rtt follows the lowest RTT sampled in the last window, so that it
+    // can also rise when the path changes
+    fn refresh_min_rtt(&mut self, now: Instant, actions: &mut Vec<Action>) {
+        self.restart_min_rtt_timer(now);
+        let window_min = std::mem::replace(&mut self.window_min_rtt_us, u32::MAX);
+        if window_min == u32::MAX
+            || window_min == self.min_rtt_us
+            || self.is_min_rtt_spike(window_min, self.min_rtt_us)
+        {
+            return;
+        }
+
+        info!(
+            old_min_rtt_us = self.min_rtt_us,
+            min_rtt_us = window_min,
+            srtt_us = self.srtt_us,
+            rttvar_us = self.rttvar_us,
+            "refreshing min_rtt"
+        );
+        self.min_rtt_us = window_min;
+        self.record_path(now);
+        self.update_min_rtt_cwnd(actions);
+    }
+
+    // in rate-only mode, only pacing limits the flow; if the datapath does not pace, cap cwnd
+    // after all
+    fn check_rate_enforcement(&mut self, m: &Measurement, actions: &mut Vec<Action>) {
+        if self.cwnd_cap {
+            return;
+        }
+
+        let fastest_pulse = self.pulse_rate(PROBE_GAIN);
+        if m.rate_outgoing > fastest_pulse * UNENFORCED_RATE_FACTOR {
+            self.unpaced_reports += 1;
+        } else {
+            self.unpaced_reports = 0;
+        }
+        if self.unpaced_reports >= UNENFORCED_RATE_REPORTS
+            && self.capabilities.mark_no_rate_enforcement()
+        {
+            warn!(
+                rate_out = %Rate::from_bytes_per_sec(m.rate_outgoing),
+                pacing_rate = %Rate::from_bytes_per_sec(fastest_pulse),
+                "datapath does not enforce Rate, capping cwnd"
+            );
+        }
+
+        if self.capabilities.lacks_rate_enforcement() {
+            self.cwnd_cap = true;
+            let cwnd_cap = self.probe_bw_cwnd();
+            actions.push(Action::Update(vec![
+                ("cwndCap", u64::from(cwnd_cap)),
+                ("Cwnd", u64::from(cwnd_cap)),
+            ]));
+        }
+    }
+
+    // adds the queue an up pulse, or the down pulse after it, raised the smoothed RTT by to the
+    // buffer estimate, and fits the probes to a buffer they overflowed
+    fn sample_buffer(&mut self, m: &Measurement, phase: PulsePhase, actions: &mut Vec<Action>) {
+        if !matches!(phase, PulsePhase::Up | PulsePhase::Down) {
+            return;
+        }
+        // a probe that did not send above the estimate queued nothing of its own
+        if self.probe_limited || m.srtt_us == 0 || self.probe_bw_gains().up <= 1.0 {
+            self.buffer.discard();
+            return;
+        }
+        let gain = self.budgeted_probe_gain();
+        let queue_us = m.srtt_us.saturating_sub(self.min_rtt_us);
+        // whatever losses do to the estimate, only the lossy-link mode tells them from a full
+        // queue
+        let overflowed = match self.loss_mode {
+            LossMode::Lossy => self.loss_mode.is_congestion(
+                m.loss,
+                m.minrtt_us,
+                self.min_rtt_us,
+                self.loss_rtt_inflation,
+            ),
+            _ => m.loss > 0,
+        };
+        self.buffer.record(
+            phase == PulsePhase::Up,
+            f64::from(queue_us) * self.bottle_rate / 1e6,
+            overflowed,
+        );
+        if self.budgeted_probe_gain() != gain {
+            info!(
+                buffer = ?self.buffer_depth(),
+                gain = self.budgeted_probe_gain(),
+                "fitting probes to the buffer"
+            );
+            self.replace_probe_bw_rate(actions);
+        }
+    }
+
+    fn on_probe_bw_report(&mut self, now: Instant, m: Measurement, actions: &mut Vec<Action>) {
+        if self.pulse_desynced(now, m.pulse_phase()) {
+            warn!(
+                desyncs = self.pulse_desyncs_in_row,
+                "pulse state keeps disagreeing with the datapath, reinstalling probe_bw"
+            );
+            self.install_probe_bw(actions);
+            return;
+        }
+        let minrtt = m.minrtt_us;
+        // a pulse that saw only a few acks neither raises the estimate nor enters the
+        // smoothed rates
+        let sparse_acks = m.acks > 0 && m.acks < MIN_RATE_SAMPLE_ACKS;
+        // and neither does one that delivered next to nothing, nor the filter it reports
+        let stalled = self.is_stalled(&m);
+        let sampled = if stalled {
+            if self.logs_reports() {
+                info!(
+                    rate_out = %Rate::from_bytes_per_sec(m.rate_outgoing),
+                    rate_in = %Rate::from_bytes_per_sec(m.rate_incoming),
+                    "stalled report, not sampling its rate"
+                );
+            }
+            0.0
+        } else if sparse_acks {
+            if self.logs_reports() {
+                info!(acks = m.acks, "too few acks to sample the pulse's rate");
+            }
+            0.0
+        } else {
+            self.sample_rate(&m)
+        };
+        // the program's bandwidth filter also saw the rounds it did not report
+        let filtered = m.max_rate > 0.0 && !stalled;
+        let rate = if filtered {
+            m.max_rate.min(self.max_rate)
+        } else {
+            sampled
+        };
+        self.check_rate_enforcement(&m, actions);
+        let jitter_us = self.rtt_jitter.spread_us();
+        if m.has_rtt_sample() {
+            self.rtt_jitter.record(minrtt);
+        }
+        let jitter_changed = self.rtt_jitter.spread_us() != jitter_us;
+        // like the programs' receiver-limited check, against half the paced BDP
+        self.inflight_bytes = m.inflight_bytes;
+        self.app_limited = !m.receiver_limited
+            && f64::from(m.inflight_bytes) * 2e6
+                < self.paced_bottle_rate() * f64::from(self.min_rtt_us);
+        // reports stopped for longer than PROBE_RTT lasts on top of a whole pulse cycle: the
+        // flow sent next to nothing for a while, and the queue it kept up drained
+        let pulse_us = u64::from(self.pulse_length_us.unwrap_or(self.min_rtt_us));
+        let idle_after = Duration::from_micros(
+            pulse_us * u64::from(self.cycle_rounds()) + u64::from(self.probe_rtt_duration_us()),
+        );
+        // unless a scheduled probe held the flow in its cruise phase
+        let scheduled = self
+            .registers
+            .get("probeAtUs")
+            .is_some_and(|&at_us| at_us > 0);
+        let idled = !scheduled
+            && self
+                .last_probe_bw_report
+                .is_some_and(|last| now.saturating_duration_since(last) > idle_after);
+        self.last_probe_bw_report = Some(now);
+        self.quiet_in_window |= self.app_limited || idled;
+        let phase = m.pulse_phase();
+        if phase == PulsePhase::Up {
+            self.probe_limited = self.app_limited || m.receiver_limited;
+        }
+        let step_down = self.track_step_down(&m, sampled);
+        self.cycle.record(sampled, minrtt, m.loss, m.acked);
+        // the rate acked over the report, against what the flow paced at, unless the
+        // application or the receiver kept it from sending that fast
+        if sampled > 0.0 && !self.app_limited && !m.receiver_limited {
+            let delivered = if m.delivery_rate > 0.0 {
+                m.delivery_rate
+            } else {
+                m.rate_incoming
+            };
+            self.cycle.record_delivery(delivered, self.pacing_rate());
+        }
+        self.sample_buffer(&m, phase, actions);
+        let elapsed = now - self.start;
+        if self.logs_reports() {
+            info!(
+            elapsed_s = elapsed.as_secs_f32(),
+            rate = %Rate::from_bytes_per_sec(rate),
+            rate_out = %Rate::from_bytes_per_sec(m.rate_outgoing),
+            rate_in = %Rate::from_bytes_per_sec(m.rate_incoming),
+            bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+            rtt_jitter_us = self.rtt_jitter.spread_us(),
+            srtt_us = m.srtt_us,
+            rttvar_us = m.rttvar_us,
+            loss = m.loss,
+            loss_rate = self.loss_rate,
+            misordered = m.misordered,
+            timeout = m.timeout,
+            inflight_bytes = m.inflight_bytes,
+            cwnd_utilization = self.cwnd_utilization(),
+            app_limited = self.app_limited,
+            ?phase,
+            "probe_bw"
+            );
+        }
+
+        // reset probe rtt counter and update cwnd cap. A report without an RTT sample, or with
+        // a lossy one, is passed over like a spike.
+        let spike = !m.has_rtt_sample()
+            || self.is_lossy_rtt_sample(&m, self.min_rtt_us)
+            || self.is_min_rtt_spike(minrtt, self.min_rtt_us);
+        if minrtt < self.min_rtt_us && !spike {
+            // datapath automatically uses minrtt for when condition (non volatile),
+            // this isn't reset, so no need to install again
+            self.min_rtt_us = minrtt;
+            self.measured_min_rtt(now, false);
+            info!(
+                min_rtt_us = self.min_rtt_us,
+                bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+                "new min_rtt"
+            );
+            self.record_path(now);
+
+            self.update_min_rtt_cwnd(actions);
+        } else if jitter_changed && self.jitter_headroom && self.pacing && self.cwnd_cap {
+            actions.push(Action::Update(vec![(
+                "cwndCap",
+                u64::from(self.probe_bw_cwnd()),
+            )]));
+        }
+
+        if !spike {
+            self.window_min_rtt_us = self.window_min_rtt_us.min(minrtt);
+        }
+        if self.min_rtt_expired(now) {
+            let quiet = self.skip_quiet_probe_rtt && self.quiet_in_window;
+            if self.probe_rtt && !quiet {
+                self.enter_probe_rtt(now, actions);
+                return;
+            }
+            if self.probe_rtt {
+                self.skipped_probe_rtts += 1;
+                info!(
+                    "skipping PROBE_RTT, the flow was app-limited or idle since its last min_rtt"
+                );
+            }
+
+            self.refresh_min_rtt(now, actions);
+        }
+
+        if self.group_probe_rtt_pending(now) {
+            info!("joining bottleneck group PROBE_RTT");
+            self.enter_probe_rtt(now, actions);
+            return;
+        }
+
+        self.check_standing_queue(m, actions);
+        if phase == PulsePhase::Cruise {
+            self.probe_bw_cycles += 1;
+            self.cycle_start = Some(now);
+            // the program clears a scheduled probe as it starts it
+            if scheduled {
+                info!("scheduled up pulse started");
+                self.registers.insert("probeAtUs", 0);
+            }
+            if self.log_granularity == LogGranularity::Cycle {
+                self.cycle.log(self.probe_bw_cycles, self.bottle_rate);
+            }
+            self.end_delivery_cycle();
+            self.cycle = CycleSummary::default();
+            if !self.queue_backoff {
+                self.next_pulse_cycle(actions);
+            }
+            self.count_loss_burst_cycle(actions);
+        }
+        self.check_loss_burst(&m, actions);
+
+        // flows joining or leaving change this flow's weighted share
+        let share = self.weights.share(self.flow.sock_id);
+        let share_changed = (share - self.rate_share).abs() > f64::EPSILON;
+        self.rate_share = share;
+
+        if m.receiver_limited {
+            if self.logs_reports() {
+                info!(
+                    rate = %Rate::from_bytes_per_sec(rate),
+                    "receiver-limited report, keeping bottle_rate"
+                );
+            }
+            if share_changed {
+                self.replace_probe_bw_rate(actions);
+            }
+        } else if self.loss_mode.is_congestion(
+            m.loss,
+            minrtt,
+            self.min_rtt_us,
+            self.loss_rtt_inflation,
+        ) {
+            self.bottle_rate *= LOSS_BACKOFF;
+            info!(
+                loss = m.loss,
+                loss_rate = self.loss_rate,
+                misordered = m.misordered,
+                timeout = m.timeout,
+                min_rtt_us = minrtt,
+                bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+                "congestion loss, lowering bottle_rate"
+            );
+            self.replace_probe_bw_rate(actions);
+        } else if let Some(step_down_rate) = step_down {
+            info!(
+                old_bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+                bottle_rate = %Rate::from_bytes_per_sec(step_down_rate),
+                phases = STEP_DOWN_PHASES,
+                "bandwidth stepped down, lowering bottle_rate"
+            );
+            self.bottle_rate = step_down_rate;
+            self.record_path(now);
+            self.replace_probe_bw_rate(actions);
+        } else if self.bottle_rate < rate && phase != PulsePhase::Up {
+            // only probing finds more bandwidth; higher samples from the other phases are
+            // noise, such as a burst of acks that were held back
+            if self.logs_reports() {
+                info!(
+                    rate = %Rate::from_bytes_per_sec(rate),
+                    ?phase,
+                    "keeping bottle_rate, not an up pulse"
+                );
+            }
+            if share_changed {
+                self.replace_probe_bw_rate(actions);
+            }
+        } else if self.bottle_rate < rate {
+            self.bottle_rate = rate;
+            self.bottle_rate_timeout = now + self.probe_rtt_interval;
+            self.record_path(now);
+            // restart the pulse state
+            // here, we must reinstall the program for substitution with the correct values
+            self.replace_probe_bw_rate(actions);
+        } else if filtered && rate < self.bottle_rate && !self.app_limited && share >= 1.0 {
+            // the rounds that measured the old estimate left the filter's window. lighter
+            // flows deliver only their share, and an application-limited flow less than that
+            self.bottle_rate = rate;
+            self.record_path(now);
+            info!(
+                bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+                "bandwidth filter expired, lowering bottle_rate"
+            );
+            self.replace_probe_bw_rate(actions);
+        } else if share_changed {
+            self.replace_probe_bw_rate(actions);
+        }
+    }
+
+    // restarts the min_rtt timer as of a measurement at `from`
+    fn restart_min_rtt_timer(&mut self, from: Instant) {
+        self.min_rtt_timeout =
+            min_rtt_expiry(self.probe_rtt_alignment, self.probe_rtt_interval, from);
+        self.min_rtt_cycle = self.probe_bw_cycles;
+        self.quiet_in_window = false;
+    }
+
+    fn min_rtt_expired(&self, now: Instant) -> bool {
+        match self.probe_rtt_rounds {
+            Some(rounds) => {
+                (self.probe_bw_cycles - self.min_rtt_cycle) * u64::from(self.cycle_rounds())
+                    >= u64::from(rounds)
+            }
+            None => now > self.min_rtt_timeout,
+        }
+    }
+
+    fn record_path(&self, now: Instant) {
+        self.path_cache.record(
+            self.dst_ip,
+            PathEstimate {
+                bottle_rate: self.bottle_rate,
+                min_rtt_us: self.min_rtt_us,
+            },
+            now,
+        );
+    }
+
+    // picks the profile for the min RTT the flow enters PROBE_BW with
+    fn select_rtt_profile(&mut self) {
+        let high_rtt = self
+            .high_rtt_threshold_us
+            .is_some_and(|threshold| self.min_rtt_us > threshold);
+        if high_rtt != self.high_rtt {
+            info!(
+                min_rtt_us = self.min_rtt_us,
+                high_rtt, "switching PROBE_BW profile"
+            );
+        }
+        self.high_rtt = high_rtt;
+    }
+
+    // the registers of the profile's cycle length and bandwidth filter, for programs with
+    // both profiles
+    fn rtt_profile_fields(&self) -> Vec<(&'static str, u64)> {
+        if self.high_rtt_threshold_us.is_none() {
+            return vec![];
+        }
+        vec![
+            ("cycleRounds", u64::from(self.cycle_rounds())),
+            ("bwLongFilter", u64::from(self.high_rtt)),
+        ]
+    }
+
+    // how long the report waited to be handled, if longer than `max_report_age`
+    fn report_age(&self, now: Instant, m: &Measurement) -> Option<Duration> {
+        let age = now.saturating_duration_since(m.received?);
+        (age > self.max_report_age?).then_some(age)
+    }
+
+    fn count_stale_report(&mut self) {
+        self.stale_reports += 1;
+        self.snapshots.update(self.snapshot());
+    }
+
+    // whether to log the warning `message` about `detail` now, and how many times it recurred
+    // since it last was; see `warn_limit`
+    pub(crate) fn limit_warning(
+        &self,
+        message: &'static str,
+        detail: &str,
+        now: Instant,
+    ) -> Option<u64> {
+        self.warnings
+            .check(message, detail, self.warn_interval, now)
+    }
+
+    // holds each PROBE_BW rate register within `rate_smoothing` of its installed value,
+    // remembering where it was headed, and moves the registers still on their way another
+    // step. A program is installed with its rates as they are.
+    fn smooth_rates(&mut self, actions: &mut Vec<Action>) {
+        let step = match self.rate_smoothing {
+            Some(step) => step,
+            None => return,
+        };
+        if self.curr_mode != BbrMode::ProbeBw
+            || actions
+                .iter()
+                .any(|action| matches!(action, Action::SetProgram { .. }))
+        {
+            self.rate_targets.clear();
+            return;
+        }
+
+        let mut installed = self.registers.clone();
+        let mut updated = vec![];
+        for action in actions.iter_mut() {
+            if let Action::Update(fields) = action {
+                for (reg, val) in fields.iter_mut() {
+                    if !SMOOTHED_RATE_REGISTERS.contains(reg) {
+                        continue;
+                    }
+                    self.rate_targets.insert(reg, *val);
+                    if let Some(&from) = installed.get(reg) {
+                        *val = rate_step(from, *val, step);
+                    }
+                    installed.insert(reg, *val);
+                    updated.push(*reg);
+                }
+            }
+        }
+
+        let mut catch_up = vec![];
+        for (&reg, &target) in &self.rate_targets {
+            match installed.get(reg) {
+                Some(&from) if from != target && !updated.contains(&reg) => {
+                    let val = rate_step(from, target, step);
+                    catch_up.push((reg, val));
+                    installed.insert(reg, val);
+                }
+                _ => {}
+            }
+        }
+        self.rate_targets
+            .retain(|reg, target| installed.get(reg) != Some(target));
+        if !catch_up.is_empty() {
+            info!(registers = ?catch_up, "PROBE_BW: smoothing rate change");
+            actions.push(Action::Update(catch_up));
+        }
+    }
+
+    // drops the register writes that would leave a register as it was last written, such as
+    // a rate that rounds to the same bytes per second, and the updates left with none. The
+    // programs change Cwnd and Rate themselves, so those are always written.
+    fn elide_unchanged(&self, actions: &mut Vec<Action>) {
+        let mut written = self.registers.clone();
+        actions.retain_mut(|action| match action {
+            Action::SetProgram { fields, .. } => {
+                written.retain(|reg, _| matches!(*reg, "Cwnd" | "Rate"));
+                written.extend(fields.iter().copied());
+                true
+            }
+            Action::Update(fields) => {
+                fields.retain(|&(reg, val)| {
+                    matches!(reg, "Cwnd" | "Rate") || written.insert(reg, val) != Some(val)
+                });
+                !fields.is_empty()
+            }
+        });
+    }
+
+    // tracks the registers the actions write, and publishes the new state
+    fn record_actions(&mut self, actions: &[Action]) {
+        for action in actions {
+            let fields = match action {
+                Action::SetProgram { program, fields } => {
+                    // Cwnd and Rate belong to the flow, everything else to the program
+                    self.program = program;
+                    self.program_installs += 1;
+                    self.registers
+                        .retain(|reg, _| matches!(*reg, "Cwnd" | "Rate"));
+                    fields
+                }
+                Action::Update(fields) => fields,
+            };
+            self.registers.extend(fields.iter().copied());
+        }
+        self.snapshots.update(self.snapshot());
+        let hint = RateHint {
+            id: self.flow,
+            pacing_rate: Rate::from_bytes_per_sec(self.pacing_rate()),
+            estimated_bdp_bytes: self.estimated_bdp_bytes(),
+        };
+        self.rate_hints.publish(hint, &mut self.hinted_rates);
+    }
+
+    /// Records that the datapath failed to apply one of the actions last returned; the ones
+    /// after it should not be applied either.
+    ///
+    /// When [`BbrCore::take_reinstall`] is next called, the flow then rewrites its registers,
+    /// up to `BbrConfig::update_retries` times in a row, and after that handles the failure
+    /// as `BbrConfig::update_failure` says.
+    pub fn install_failed(&mut self) {
+        self.failed_updates += 1;
+        self.failures_in_row += 1;
+        self.reinstall = true;
+        self.pending_nonce = None;
+    }
+
+    /// Records that a report came from a program of another version, as
+    /// [`BbrError::ProgramVersion`] says, which the flow cannot make sense of. When
+    /// [`BbrCore::take_reinstall`] is next called, the flow then reinstalls its program, so
+    /// that the datapath runs this agent's version of it.
+    pub fn program_outdated(&mut self) {
+        self.outdated_reports += 1;
+        self.reinstall = true;
+        self.snapshots.update(self.snapshot());
+    }
+
+    /// Records how long the flow took to handle a report, from its arrival until its actions
+    /// were applied, and warns when that nears the time between reports. Snapshots show it from
+    /// the next report on.
+    pub fn record_handling(&mut self, latency: Duration) {
+        self.handling.record(latency);
+        let interval_us = match self.curr_mode {
+            BbrMode::ProbeBw => self.pulse_length_us.unwrap_or(self.min_rtt_us),
+            _ => self.min_rtt_us,
+        };
+        let slow = latency.as_secs_f64() * 1e6 >= SLOW_HANDLING_FRACTION * f64::from(interval_us);
+        if slow && !self.slow_handling {
+            let _entered = self.span.enter();
+            warn!(
+                latency_us = latency.as_micros() as u64,
+                interval_us, "handling a report takes most of the time between reports"
+            );
+        }
+        self.slow_handling = slow;
+    }
+
+    /// Records that the datapath refused to install `program`, which was the action that
+    /// failed in [`BbrCore::install_failed`]'s sense; call this instead of `install_failed`.
+    ///
+    /// A datapath that keeps refusing `probe_bw`, e.g. because it has no rate primitive, cannot
+    /// run BBR: the flow then reinstalls the cwnd-only `aimd` program instead, and so do the
+    /// other flows of its `BbrConfig` when they would install `probe_bw`.
+    pub fn program_rejected(&mut self, program: &'static str) {
+        let span = self.span.clone();
+        let _entered = span.enter();
+        self.rejected_installs += 1;
+        if program == "probe_bw" {
+            self.probe_bw_rejections += 1;
+            if self.probe_bw_rejections >= PROBE_BW_INSTALL_ATTEMPTS
+                && self.capabilities.mark_no_probe_bw()
+            {
+                error!(
+                    attempts = self.probe_bw_rejections,
+                    "datapath rejects probe_bw, falling back to cwnd-only AIMD; flows will not run BBR"
+                );
+            }
+        }
+        self.reinstall = true;
+    }
+
+    /// The actions that reinstall the flow's program after [`BbrCore::install_failed`], if
+    /// any. Call this before handling each report, and apply the actions instead of handling
+    /// the report; until the program is reinstalled, reports may come from a different program
+    /// than the flow's mode expects.
+    pub fn take_reinstall(&mut self) -> Option<Vec<Action>> {
+        if !std::mem::take(&mut self.reinstall) {
+            return None;
+        }
+
+        let span = self.span.clone();
+        let _entered = span.enter();
+        if self.failures_in_row > 0 && self.failures_in_row <= self.update_retries {
+            info!(
+                attempt = self.failures_in_row,
+                retries = self.update_retries,
+                "rewriting registers after a failed update"
+            );
+            return Some(vec![Action::Update(
+                self.registers
+                    .iter()
+                    .map(|(&reg, &val)| (reg, val))
+                    .collect(),
+            )]);
+        }
+        if self.failures_in_row > 0 && !self.degraded {
+            let mut actions = vec![];
+            match self.update_failure {
+                UpdateFailurePolicy::Reinstall => {}
+                UpdateFailurePolicy::Freeze => {
+                    self.freeze(&mut actions);
+                    self.record_actions(&actions);
+                    return Some(actions);
+                }
+                UpdateFailurePolicy::Degrade => {
+                    self.install_fallback(&mut actions);
+                    self.record_actions(&actions);
+                    return Some(actions);
+                }
+            }
+        }
+        self.reinstalls += 1;
+
+        if self.program == "probe_bw" && self.capabilities.lacks_probe_bw() {
+            let mut actions = vec![];
+            self.install_fallback(&mut actions);
+            self.record_actions(&actions);
+            return Some(actions);
+        }
+
+        // the reinstall restarts the cycle a scheduled probe was timed from, so the flow takes
+        // it again once it knows when its new cycle started
+        if self.program == "probe_bw" {
+            let cycle_start = self.cycle_start.take();
+            if let (Some(start), Some(at_us)) = (cycle_start, self.registers.remove("probeAtUs")) {
+                if at_us > 0 && self.probe_schedule.get(self.flow.sock_id).is_none() {
+                    self.probe_schedule
+                        .schedule(self.flow.sock_id, start + Duration::from_micros(at_us));
+                }
+            }
+        }
+        info!(program = self.program, "reinstalling program");
+        self.program_installs += 1;
+        self.snapshots.update(self.snapshot());
+        Some(vec![Action::SetProgram {
+            program: self.program,
+            fields: self
+                .registers
+                .iter()
+                .map(|(&reg, &val)| (reg, val))
+                .collect(),
+        }])
+    }
+
+    /// Stops the flow's probing, as `BbrConfig::paused` does on the next report, and returns
+    /// the actions that pace it at its current estimate.
+    pub fn pause(&mut self) -> Vec<Action> {
+        self.pauses.pause(self.flow.sock_id);
+        self.follow_controls()
+    }
+
+    /// Undoes [`BbrCore::pause`], and returns the actions that restore probing.
+    pub fn resume(&mut self) -> Vec<Action> {
+        self.pauses.resume(self.flow.sock_id);
+        self.follow_controls()
+    }
+
+    fn follow_controls(&mut self) -> Vec<Action> {
+        let span = self.span.clone();
+        let _entered = span.enter();
+        let mut actions = vec![];
+        if !self.released && !self.frozen {
+            self.sync_pause(&mut actions);
+            self.sync_rate_limit(&mut actions);
+            self.smooth_rates(&mut actions);
+            self.elide_unchanged(&mut actions);
+            self.record_actions(&actions);
+        }
+        actions
+    }
+
+    // takes up a pause or resume. STARTUP ends on its next report instead, and DRAIN and
+    // PROBE_RTT install PROBE_BW with the gains that apply by then
+    fn sync_pause(&mut self, actions: &mut Vec<Action>) {
+        let paused = self.pauses.is_paused(self.flow.sock_id);
+        if paused == self.paused {
+            return;
+        }
+
+        info!(
+            paused,
+            mode = ?self.curr_mode,
+            bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+            "changing probing"
+        );
+        self.paused = paused;
+        if !paused {
+            self.probe_start_rate = self.bottle_rate;
+        }
+        if self.curr_mode == BbrMode::ProbeBw && !self.degraded {
+            self.set_pulse(self.first_pulse_gain(), actions);
+            self.replace_probe_bw_rate(actions);
+        }
+    }
+
+    // takes up a new rate limit, or a cleared one. STARTUP and DRAIN pace at the new limit from
+    // their next update on, and PROBE_RTT's exit does in PROBE_BW
+    fn sync_rate_limit(&mut self, actions: &mut Vec<Action>) {
+        let tenant_share = self
+            .tenant
+            .as_deref()
+            .zip(self.tenant_rate)
+            .map(|(tenant, rate)| self.tenants.share(tenant, rate));
+        let limit = lower_limit(self.flow_limits.get(self.flow.sock_id), tenant_share);
+        if limit == self.rate_limit {
+            return;
+        }
+
+        info!(
+            limit = ?limit.map(|limit| limit.to_string()),
+            mode = ?self.curr_mode,
+            bottle_rate = %Rate::from_bytes_per_sec(self.bottle_rate),
+            "changing rate limit"
+        );
+        self.rate_limit = limit;
+        self.max_rate = limit.map_or(self.configured_max_rate, |limit| {
+            self.configured_max_rate.min(limit.bytes_per_sec())
+        });
+        self.min_rate = self.configured_min_rate.min(self.max_rate);
+        if self.curr_mode == BbrMode::ProbeBw && !self.degraded {
+            self.set_pulse(self.first_pulse_gain(), actions);
+            self.replace_probe_bw_rate(actions);
+        }
+    }
+
+    /// Limits the flow to `limit`, or clears its limit, as `BbrConfig::flow_limits` does on
+    /// the next report, and returns the actions that enforce it.
+    pub fn set_rate_limit(&mut self, limit: Option<Rate>) -> Vec<Action> {
+        match limit {
+            Some(limit) => self.flow_limits.set(self.flow.sock_id, limit),
+            None => self.flow_limits.clear(self.flow.sock_id),
+        };
+        self.follow_controls()
+    }
+
+    /// Starts the flow's next up pulse at `at`, as `BbrConfig::probe_schedule` does once the
+    /// flow can take it, and returns the actions that schedule it.
+    pub fn schedule_probe(&mut self, now: Instant, at: Instant) -> Vec<Action> {
+        self.probe_schedule.schedule(self.flow.sock_id, at);
+        let span = self.span.clone();
+        let _entered = span.enter();
+        let mut actions = vec![];
+        if !self.released && !self.frozen {
+            self.sync_probe_schedule(now, &mut actions);
+            self.record_actions(&actions);
+        }
+        actions
+    }
+
+    /// Takes up the flow's new MSS, e.g. after path MTU discovery shrank it, and returns the
+    /// actions that update the registers derived from it.
+    pub fn set_mss(&mut self, mss: u32) -> Vec<Action> {
+        let span = self.span.clone();
+        let _entered = span.enter();
+        let mut actions = vec![];
+        if !self.released && !self.frozen && mss > 0 {
+            self.update_mss(mss, &mut actions);
+            self.record_actions(&actions);
+        }
+        actions
+    }
+
+    // the report's MSS sample, unless the flow was app-limited, when its acks may all have
+    // been of small segments, or it is smaller than any path's
+    fn sample_mss(&mut self, m: &Measurement, actions: &mut Vec<Action>) {
+        if m.mss >= MIN_MSS_BYTES && !self.app_limited {
+            self.update_mss(m.mss, actions);
+        }
+    }
+
+    // PROBE_RTT's cwnd, the fallback's additive increase and every window floored at
+    // PROBE_RTT's are in multiples of the MSS, so the running program's registers follow it.
+    // a PROBE_RTT already running keeps its target
+    fn update_mss(&mut self, mss: u32, actions: &mut Vec<Action>) {
+        if mss == self.mss {
+            return;
+        }
+        info!(from = self.mss, to = mss, "MSS changed");
+        self.mss = mss;
+        match self.program {
+            "aimd" => actions.push(Action::Update(vec![
+                ("aiBytes", u64::from(self.mss)),
+                ("minCwnd", u64::from(self.probe_rtt_cwnd())),
+            ])),
+            "probe_bw" => self.replace_probe_bw_rate(actions),
+            _ => {}
+        }
+    }
+
+    // takes up a probe scheduled for the flow: the cruise phase of its current cycle ends, and
+    // the next up pulse starts, that long after the cycle started. A flow that does not know
+    // when its cycle started yet leaves the probe scheduled
+    fn sync_probe_schedule(&mut self, now: Instant, actions: &mut Vec<Action>) {
+        if !self.schedule_probes || self.program != "probe_bw" {
+            return;
+        }
+        let Some(cycle_start) = self.cycle_start else {
+            return;
+        };
+        let Some(at) = self.probe_schedule.cancel(self.flow.sock_id) else {
+            return;
+        };
+        if at <= now {
+            warn!(
+                late_ms = now.duration_since(at).as_millis() as u64,
+                "scheduled probe time passed, dropping it"
+            );
+            return;
+        }
+
+        let at_us = at.saturating_duration_since(cycle_start).as_micros().max(1);
+        info!(
+            in_ms = at.duration_since(now).as_millis() as u64,
+            "scheduling the next up pulse"
+        );
+        actions.push(Action::Update(vec![(
+            "probeAtUs",
+            at_us.min(u128::from(u64::MAX)) as u64,
+        )]));
+    }
+}
+
+// `from` moved toward `to` by at most `step` of itself, or all the way where such a step
+// rounds to nothing
+fn rate_step(from: u64, to: u64, step: f64) -> u64 {
+    let from_rate = from as f64;
+    let stepped = (to as f64).clamp(from_rate * (1.0 - step), from_rate * (1.0 + step)) as u64;
+    if stepped == from {
+        to
+    } else {
+        stepped
+    }
+}
+
+// When the min_rtt estimate sampled at `now` expires. With an alignment, that is the first
+// multiple of the interval on the wall clock at least half an interval away, so that every
+// flow aligned to the same clock probes together.
+pub(crate) fn min_rtt_expiry(
+    alignment: Option<WallClock>,
+    interval: Duration,
+    now: Instant,
+) -> Instant {
+    let clock = match alignment {
+        Some(clock) if !interval.is_zero() => clock,
+        _ => return now + interval,
+    };
+
+    let earliest = now + interval / 2;
+    let wall = clock.at(earliest).as_nanos();
+    let interval = interval.as_nanos();
+    let next = wall.div_ceil(interval) * interval;
+    earliest + Duration::from_nanos((next - wall) as u64)
+}
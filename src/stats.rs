@@ -0,0 +1,311 @@
+//! Pushing every flow's measurements to a time-series database.
+//!
+//! `--stats_sink influx://<host>:<port>` sends InfluxDB line protocol over UDP, which influxd's
+//! UDP listener and Telegraf's `socket_listener` accept; `graphite://<host>:<port>` sends
+//! Graphite's plaintext protocol over TCP. Each interval, every flow of every transport sends
+//! its bottleneck rate, pacing rate, estimated BDP, RTTs, inflight, mode, why it entered that
+//! mode, how long it has spent in each mode, and losses, how often it has entered
+//! PROBE_RTT, finished a PROBE_BW cycle, and finished one well below its pacing rate, what its
+//! last cycle delivered over its pacing rate,
+//! had its program reinstalled, failed an update, ignored a stale report and saw a stalled one,
+//! and the median and 99th percentile of the time it took to handle its reports, tagged with
+//! its transport and [`FlowId`](crate::flow_id::FlowId).
+//! It also sends the value last written to each register of its program and to its `Cwnd` and
+//! `Rate`, so that what the datapath was told can be graphed next to the estimates it came from.
+//!
+//! The control socket's `marker` command sends a named marker at once, as the `bbr_marker`
+//! measurement or under `bbr.markers`, so that experiments can line up the agent's stats with
+//! what they did when.
+
+use crate::snapshot::FlowSnapshot;
+use crate::transport::Transport;
+use crate::{BbrMode, TransitionReason, WallClock};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{info, warn};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StatsSink {
+    /// InfluxDB line protocol, one datagram per flow, to `host:port`.
+    Influx(String),
+    /// Graphite plaintext, over a connection to `host:port` per push.
+    Graphite(String),
+}
+
+impl FromStr for StatsSink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, addr) = s.split_once("://").ok_or_else(|| {
+            format!(
+                "expected influx://<host>:<port> or graphite://<host>:<port>: {:?}",
+                s
+            )
+        })?;
+        let (host, port) = addr
+            .rsplit_once(':')
+            .ok_or_else(|| format!("stats sink needs a port: {:?}", s))?;
+        if host.is_empty() {
+            return Err(format!("stats sink needs a host: {:?}", s));
+        }
+        port.parse::<u16>()
+            .map_err(|e| format!("invalid port {:?}: {}", port, e))?;
+
+        match scheme {
+            "influx" => Ok(StatsSink::Influx(String::from(addr))),
+            "graphite" => Ok(StatsSink::Graphite(String::from(addr))),
+            _ => Err(format!(
+                "stats sink must be influx:// or graphite://: {:?}",
+                s
+            )),
+        }
+    }
+}
+
+fn bottle_rate_bps(flow: &FlowSnapshot) -> f64 {
+    flow.bottle_rate.bytes_per_sec() * 8.0
+}
+
+// as in the C ABI, since Graphite only takes numbers
+fn mode_code(mode: BbrMode) -> u32 {
+    match mode {
+        BbrMode::Startup => 0,
+        BbrMode::Drain => 1,
+        BbrMode::ProbeBw => 2,
+        BbrMode::ProbeRtt => 3,
+    }
+}
+
+fn transition_code(reason: TransitionReason) -> u32 {
+    match reason {
+        TransitionReason::MinRttExpired => 0,
+        TransitionReason::FullBwReached => 1,
+        TransitionReason::InflightDrained => 2,
+        TransitionReason::LossLimit => 3,
+        TransitionReason::ProbeRttDone => 4,
+        TransitionReason::Manual => 5,
+    }
+}
+
+/// A `bbr` line per flow, tagged with `ipc`, the flow's id and its tenant, if any, at
+/// `since_epoch`. Each line goes in a datagram of its own, which keeps them under any MTU.
+pub fn influx_lines(ipc: &str, flows: &[FlowSnapshot], since_epoch: Duration) -> Vec<String> {
+    flows
+        .iter()
+        .map(|flow| {
+            let id = flow.id;
+            let registers: String = flow
+                .registers
+                .iter()
+                .map(|(reg, val)| format!(",reg_{}={}i", reg, val))
+                .collect();
+            let transition = flow
+                .transition_reason
+                .map_or(String::new(), |reason| {
+                    format!(",transition_reason=\"{:?}\"", reason)
+                });
+            let buffer = flow.buffer_depth.map_or(String::new(), |depth| {
+                format!(
+                    ",buffer_bytes={}i,buffer_overflowed={}",
+                    depth.bytes, depth.overflowed
+                )
+            });
+            let delivery = flow
+                .delivery_ratio
+                .map_or(String::new(), |ratio| format!(",delivery_ratio={}", ratio));
+            let tenant = flow.tenant.as_ref().map_or(String::new(), |tenant| {
+                format!(",tenant={}", tenant.replace([',', ' ', '='], "_"))
+            });
+            format!(
+                "bbr,ipc={},sock_id={},src={},dst={},sport={},dport={}{} mode=\"{:?}\"{},bottle_rate_bps={},pacing_rate_bps={},estimated_bdp_bytes={}i,min_rtt_us={}i,srtt_us={}i,inflight_bytes={}i,loss_rate={},lost_packets={}i,probe_rtt_entries={}i,startup_time_us={}i,drain_time_us={}i,probe_bw_time_us={}i,probe_rtt_time_us={}i,probe_bw_cycles={}i,delivery_gap_cycles={}i,reinstalls={}i,failed_updates={}i,stale_reports={}i,stalled_reports={}i,handling_p50_us={}i,handling_p99_us={}i{}{}{} {}",
+                ipc.replace([',', ' ', '='], "_"),
+                id.sock_id,
+                id.src,
+                id.dst,
+                id.sport,
+                id.dport,
+                tenant,
+                flow.mode,
+                transition,
+                bottle_rate_bps(flow),
+                flow.pacing_rate.bytes_per_sec() * 8.0,
+                flow.estimated_bdp_bytes,
+                flow.min_rtt_us,
+                flow.srtt_us,
+                flow.inflight_bytes,
+                flow.loss_rate,
+                flow.lost_packets,
+                flow.probe_rtt_entries,
+                flow.mode_time.startup_us,
+                flow.mode_time.drain_us,
+                flow.mode_time.probe_bw_us,
+                flow.mode_time.probe_rtt_us,
+                flow.probe_bw_cycles,
+                flow.delivery_gap_cycles,
+                flow.reinstalls,
+                flow.failed_updates,
+                flow.stale_reports,
+                flow.stalled_reports,
+                flow.handling.percentile_us(0.5),
+                flow.handling.percentile_us(0.99),
+                buffer,
+                delivery,
+                registers,
+                since_epoch.as_nanos(),
+            )
+        })
+        .collect()
+}
+
+/// An InfluxDB line marking `name` at `since_epoch`, as the `name` field of the `bbr_marker`
+/// measurement.
+pub fn influx_marker(name: &str, since_epoch: Duration) -> String {
+    format!(
+        "bbr_marker name=\"{}\" {}",
+        name.replace('\\', "\\\\").replace('"', "\\\""),
+        since_epoch.as_nanos()
+    )
+}
+
+/// A `bbr.markers.<name> 1 <seconds>` line marking `name` at `since_epoch`, with whatever
+/// Graphite cannot have in a metric name replaced by `_`.
+pub fn graphite_marker(name: &str, since_epoch: Duration) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("bbr.markers.{} 1 {}\n", name, since_epoch.as_secs())
+}
+
+/// `bbr.<ipc>.<sock_id>.<metric> <value> <seconds>` lines for every flow. `mode` counts
+/// STARTUP, DRAIN, `PROBE_BW` and `PROBE_RTT` from 0, `transition_reason` counts the
+/// [`TransitionReason`]s from 0 in their declared order once the flow has left STARTUP,
+/// `buffer_bytes` follows once a probe has ended, `delivery_ratio` once a cycle has, and
+/// registers are under `registers.<name>`.
+pub fn graphite_lines(ipc: &str, flows: &[FlowSnapshot], since_epoch: Duration) -> String {
+    let at = since_epoch.as_secs();
+    let ipc = ipc.replace(['.', ' '], "_");
+    let mut lines = String::new();
+    for flow in flows {
+        let metrics = [
+            ("mode", f64::from(mode_code(flow.mode))),
+            ("bottle_rate_bps", bottle_rate_bps(flow)),
+            ("pacing_rate_bps", flow.pacing_rate.bytes_per_sec() * 8.0),
+            ("estimated_bdp_bytes", flow.estimated_bdp_bytes as f64),
+            ("min_rtt_us", f64::from(flow.min_rtt_us)),
+            ("srtt_us", f64::from(flow.srtt_us)),
+            ("inflight_bytes", f64::from(flow.inflight_bytes)),
+            ("loss_rate", flow.loss_rate),
+            ("lost_packets", flow.lost_packets as f64),
+            ("probe_rtt_entries", flow.probe_rtt_entries as f64),
+            ("startup_time_us", flow.mode_time.startup_us as f64),
+            ("drain_time_us", flow.mode_time.drain_us as f64),
+            ("probe_bw_time_us", flow.mode_time.probe_bw_us as f64),
+            ("probe_rtt_time_us", flow.mode_time.probe_rtt_us as f64),
+            ("probe_bw_cycles", flow.probe_bw_cycles as f64),
+            ("delivery_gap_cycles", flow.delivery_gap_cycles as f64),
+            ("reinstalls", flow.reinstalls as f64),
+            ("failed_updates", flow.failed_updates as f64),
+            ("stale_reports", flow.stale_reports as f64),
+            ("stalled_reports", flow.stalled_reports as f64),
+            ("handling_p50_us", flow.handling.percentile_us(0.5) as f64),
+            ("handling_p99_us", flow.handling.percentile_us(0.99) as f64),
+        ];
+        let transition = flow
+            .transition_reason
+            .map(|reason| ("transition_reason", f64::from(transition_code(reason))));
+        let buffer = flow
+            .buffer_depth
+            .map(|depth| ("buffer_bytes", depth.bytes as f64));
+        let delivery = flow.delivery_ratio.map(|ratio| ("delivery_ratio", ratio));
+        for (metric, value) in metrics
+            .into_iter()
+            .chain(transition)
+            .chain(buffer)
+            .chain(delivery)
+        {
+            writeln!(
+                lines,
+                "bbr.{}.{}.{} {} {}",
+                ipc, flow.id.sock_id, metric, value, at
+            )
+            .unwrap();
+        }
+        for (reg, val) in &flow.registers {
+            writeln!(
+                lines,
+                "bbr.{}.{}.registers.{} {} {}",
+                ipc, flow.id.sock_id, reg, val, at
+            )
+            .unwrap();
+        }
+    }
+    lines
+}
+
+impl StatsSink {
+    /// Sends the current measurements of every transport's flows.
+    pub fn push(&self, transports: &[Transport], since_epoch: Duration) -> io::Result<()> {
+        match self {
+            StatsSink::Influx(addr) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                for transport in transports {
+                    let flows = transport.cfg.snapshots.flows();
+                    for line in influx_lines(&transport.ipc, &flows, since_epoch) {
+                        socket.send_to(line.as_bytes(), addr.as_str())?;
+                    }
+                }
+            }
+            StatsSink::Graphite(addr) => {
+                let lines: String = transports
+                    .iter()
+                    .map(|t| graphite_lines(&t.ipc, &t.cfg.snapshots.flows(), since_epoch))
+                    .collect();
+                if !lines.is_empty() {
+                    TcpStream::connect(addr.as_str())?.write_all(lines.as_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a marker on its own, rather than with the next push.
+    pub fn push_marker(&self, name: &str, since_epoch: Duration) -> io::Result<()> {
+        match self {
+            StatsSink::Influx(addr) => {
+                let line = influx_marker(name, since_epoch);
+                UdpSocket::bind("0.0.0.0:0")?.send_to(line.as_bytes(), addr.as_str())?;
+            }
+            StatsSink::Graphite(addr) => {
+                let line = graphite_marker(name, since_epoch);
+                TcpStream::connect(addr.as_str())?.write_all(line.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pushes every `interval` from a thread of its own. A failed push is logged, and the next
+    /// one tried as usual, so that the sink may restart under a running agent.
+    pub fn start(self, transports: Vec<Transport>, interval: Duration) -> io::Result<()> {
+        info!(sink = ?self, ?interval, "pushing flow stats");
+        std::thread::Builder::new()
+            .name(String::from("bbr-stats"))
+            .spawn(move || loop {
+                std::thread::sleep(interval);
+                let now = WallClock::now().since_epoch;
+                if let Err(err) = self.push(&transports, now) {
+                    warn!(?err, sink = ?self, "could not push flow stats");
+                }
+            })?;
+        Ok(())
+    }
+}
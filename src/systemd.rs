@@ -0,0 +1,52 @@
+//! systemd service notifications.
+//!
+//! When the agent runs as a `Type=notify` unit, it tells systemd when it is ready to serve
+//! flows, keeps the unit's watchdog fed, and says when it starts shutting down. Outside of
+//! systemd, `NOTIFY_SOCKET` is unset and every notification is a no-op.
+
+use std::io;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+fn notify_addr() -> io::Result<Option<SocketAddr>> {
+    let path = match std::env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+
+    let path = path.to_string_lossy();
+    // systemd may pass a socket in the abstract namespace
+    match path.strip_prefix('@') {
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name.as_bytes()).map(Some)
+        }
+        None => SocketAddr::from_pathname(path.as_ref()).map(Some),
+    }
+}
+
+/// Sends a notification such as `READY=1`; does nothing outside of systemd.
+pub fn notify(state: &str) -> io::Result<()> {
+    let addr = match notify_addr()? {
+        Some(addr) => addr,
+        None => return Ok(()),
+    };
+
+    let sock = UnixDatagram::unbound()?;
+    sock.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// How often systemd expects `WATCHDOG=1`, if the unit has a watchdog for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    let for_us = std::env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_none_or(|pid| pid == std::process::id());
+    if usec == 0 || !for_us {
+        return None;
+    }
+
+    Some(Duration::from_micros(usec))
+}
@@ -0,0 +1,358 @@
+//! Flows tagged with the tenant they belong to, on hosts shared between containers.
+//!
+//! A flow's tenant is the one the first matching [`TenantRule`] names, e.g. for the address
+//! block a container runtime hands one tenant's containers. A flow that no rule matches can be
+//! looked up by its 4-tuple instead, since neither portus datapath reports where a socket
+//! lives: with [`TenantLookup::Netns`] its tenant is the network namespace whose TCP table
+//! holds the socket, and with [`TenantLookup::Cgroup`] the cgroup of the process that holds
+//! it. A lookup scans `/proc` once per new flow, and only finds IPv4 sockets.
+//!
+//! A tenant's flows log and report their tenant, and take the settings of its
+//! [`TenantConfig`] where no per-flow rule matches them: a weight, a cap and a floor on each
+//! flow's rate, and an aggregate rate that caps the tenant's flows together, so that one
+//! tenant's flows cannot starve another's. The tenant's flows split the aggregate evenly, and
+//! a flow that sends less than its share leaves the rest unused.
+
+use crate::bandwidth::Rate;
+use crate::flow_match::FlowMatch;
+use crate::max_rate::parse_max_rate;
+use crate::min_rate::parse_min_rate;
+use crate::shard::ShardedMap;
+use portus::DatapathInfo;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Tags the flows selected by `flow` with `tenant`.
+///
+/// Parsed from and displayed as `<flow match>:<tenant>`, e.g. `src=172.17.0.0/24:blue`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TenantRule {
+    pub flow: FlowMatch,
+    pub tenant: String,
+}
+
+impl FromStr for TenantRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (flow, tenant) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected <flow match>:<tenant>: {:?}", s))?;
+        if tenant.is_empty() {
+            return Err(format!("empty tenant: {:?}", s));
+        }
+        Ok(TenantRule {
+            flow: flow.parse()?,
+            tenant: String::from(tenant),
+        })
+    }
+}
+
+impl fmt::Display for TenantRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.flow, self.tenant)
+    }
+}
+
+/// Where the tenant of a flow that no rule matches comes from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TenantLookup {
+    /// Such flows have no tenant.
+    #[default]
+    None,
+    /// The network namespace the socket lives in, as `netns:<inode>`, the inode `lsns` shows.
+    Netns,
+    /// The cgroup of the process that holds the socket, as its path, e.g.
+    /// `/system.slice/docker-<id>.scope`.
+    Cgroup,
+}
+
+impl FromStr for TenantLookup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(TenantLookup::None),
+            "netns" => Ok(TenantLookup::Netns),
+            "cgroup" => Ok(TenantLookup::Cgroup),
+            _ => Err(format!(
+                "tenant lookup must be one of (none|netns|cgroup): {:?}",
+                s
+            )),
+        }
+    }
+}
+
+impl TenantLookup {
+    /// Looks the flow's socket up in `/proc`; `None` if it is not found.
+    pub fn lookup(self, info: &DatapathInfo) -> Option<String> {
+        self.lookup_in(Path::new("/proc"), info)
+    }
+
+    /// Looks the flow's socket up in a `/proc` mounted at `proc`.
+    pub fn lookup_in(self, proc: &Path, info: &DatapathInfo) -> Option<String> {
+        if self == TenantLookup::None {
+            return None;
+        }
+        let (netns, inode) = find_socket(proc, info)?;
+        match self {
+            TenantLookup::None => None,
+            TenantLookup::Netns => Some(format!("netns:{}", netns)),
+            TenantLookup::Cgroup => socket_cgroup(proc, inode),
+        }
+    }
+}
+
+/// What a tenant's flows take instead of the configuration's own settings.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TenantSettings {
+    pub weight: Option<f64>,
+    pub max_rate: Option<Rate>,
+    pub min_rate: Option<Rate>,
+    /// Caps the rates of all of the tenant's flows together.
+    pub aggregate_rate: Option<Rate>,
+}
+
+/// The settings of `tenant`'s flows.
+///
+/// Parsed from and displayed as `<tenant>:<setting>=<value>[,<setting>=<value>...]`, where
+/// the settings are `weight`, `max_rate`, `min_rate` and `aggregate_rate`, e.g.
+/// `blue:weight=2,aggregate_rate=500Mbps`. Rates without a unit are Mbit/s.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TenantConfig {
+    pub tenant: String,
+    pub settings: TenantSettings,
+}
+
+impl FromStr for TenantConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // cgroup paths and namespaces may hold colons, but settings do not
+        let (tenant, list) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected <tenant>:<setting>=<value>,...: {:?}", s))?;
+        if tenant.is_empty() {
+            return Err(format!("empty tenant: {:?}", s));
+        }
+        let mut settings = TenantSettings::default();
+        for setting in list.split(',') {
+            let (key, val) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("expected <setting>=<value>: {:?}", setting))?;
+            match key {
+                "weight" => {
+                    settings.weight = Some(
+                        val.parse::<f64>()
+                            .ok()
+                            .filter(|w| w.is_finite() && *w > 0.0)
+                            .ok_or_else(|| {
+                                format!("weight must be a positive number: {:?}", val)
+                            })?,
+                    )
+                }
+                "max_rate" => settings.max_rate = Some(positive(parse_max_rate(val)?, key)?),
+                "min_rate" => settings.min_rate = Some(parse_min_rate(val)?),
+                "aggregate_rate" => {
+                    settings.aggregate_rate = Some(positive(parse_max_rate(val)?, key)?)
+                }
+                _ => return Err(format!("unknown tenant setting {:?}", key)),
+            }
+        }
+        Ok(TenantConfig {
+            tenant: String::from(tenant),
+            settings,
+        })
+    }
+}
+
+fn positive(rate: Rate, what: &str) -> Result<Rate, String> {
+    if rate.bytes_per_sec() > 0.0 {
+        Ok(rate)
+    } else {
+        Err(format!("{} must be positive: {}", what, rate))
+    }
+}
+
+impl fmt::Display for TenantConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = &self.settings;
+        let settings: Vec<String> = [
+            s.weight.map(|w| format!("weight={}", w)),
+            s.max_rate.map(|r| format!("max_rate={}", r)),
+            s.min_rate.map(|r| format!("min_rate={}", r)),
+            s.aggregate_rate.map(|r| format!("aggregate_rate={}", r)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        write!(f, "{}:{}", self.tenant, settings.join(","))
+    }
+}
+
+/// The tenant of the first matching rule, or the one `lookup` finds.
+pub fn tenant_for(
+    rules: &[TenantRule],
+    lookup: TenantLookup,
+    info: &DatapathInfo,
+) -> Option<String> {
+    rules
+        .iter()
+        .find(|r| r.flow.matches(info))
+        .map(|r| r.tenant.clone())
+        .or_else(|| lookup.lookup(info))
+}
+
+/// The settings of the tenant's last `TenantConfig`, since later flags override earlier ones.
+pub fn settings_for(configs: &[TenantConfig], tenant: Option<&str>) -> TenantSettings {
+    tenant
+        .and_then(|tenant| configs.iter().rev().find(|c| c.tenant == tenant))
+        .map(|c| c.settings)
+        .unwrap_or_default()
+}
+
+/// The current flows of each tenant, by socket id, shared by all flows of one `BbrConfig`.
+#[derive(Clone, Default)]
+pub struct Tenants {
+    flows: ShardedMap<String, BTreeSet<u32>>,
+}
+
+impl Tenants {
+    pub fn join(&self, tenant: &str, sock_id: u32) {
+        let tenant = String::from(tenant);
+        self.flows.with_shard(&tenant, |flows| {
+            flows.entry(tenant.clone()).or_default().insert(sock_id);
+        });
+    }
+
+    pub fn leave(&self, tenant: &str, sock_id: u32) {
+        let tenant = String::from(tenant);
+        self.flows.with_shard(&tenant, |flows| {
+            if let Some(members) = flows.get_mut(&tenant) {
+                members.remove(&sock_id);
+                if members.is_empty() {
+                    flows.remove(&tenant);
+                }
+            }
+        });
+    }
+
+    /// How many flows the tenant has.
+    pub fn len(&self, tenant: &str) -> usize {
+        self.flows
+            .get(&String::from(tenant))
+            .map_or(0, |members| members.len())
+    }
+
+    /// Each of the tenant's flows' even share of `aggregate_rate`.
+    pub fn share(&self, tenant: &str, aggregate_rate: Rate) -> Rate {
+        Rate::from_bytes_per_sec(aggregate_rate.bytes_per_sec() / self.len(tenant).max(1) as f64)
+    }
+
+    /// How many flows each tenant has.
+    pub fn all(&self) -> BTreeMap<String, usize> {
+        self.flows
+            .entries()
+            .into_iter()
+            .map(|(tenant, members)| (tenant, members.len()))
+            .collect()
+    }
+}
+
+// the network namespace and inode of the flow's socket, from the TCP table of each namespace a
+// process is in
+fn find_socket(proc: &Path, info: &DatapathInfo) -> Option<(u64, u64)> {
+    let mut seen = HashSet::new();
+    for pid in pids(proc) {
+        let Some(netns) = link_inode(&pid.join("ns/net"), "net:[") else {
+            continue;
+        };
+        if !seen.insert(netns) {
+            continue;
+        }
+        let Ok(table) = fs::read_to_string(pid.join("net/tcp")) else {
+            continue;
+        };
+        if let Some(inode) = socket_inode(&table, info) {
+            return Some((netns, inode));
+        }
+    }
+    None
+}
+
+// the cgroup of the first process with the socket open
+fn socket_cgroup(proc: &Path, inode: u64) -> Option<String> {
+    pids(proc).find_map(|pid| {
+        let mut fds = fs::read_dir(pid.join("fd")).ok()?;
+        fds.any(|fd| fd.is_ok_and(|fd| link_inode(&fd.path(), "socket:[") == Some(inode)))
+            .then(|| fs::read_to_string(pid.join("cgroup")).ok())
+            .flatten()
+            .and_then(|cgroups| cgroup_path(&cgroups))
+    })
+}
+
+fn pids(proc: &Path) -> impl Iterator<Item = PathBuf> {
+    fs::read_dir(proc)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit()))
+        })
+        .map(|entry| entry.path())
+}
+
+// the inode of a `<kind>:[<inode>]` link, such as `/proc/<pid>/ns/net`
+fn link_inode(link: &Path, prefix: &str) -> Option<u64> {
+    let target = fs::read_link(link).ok()?;
+    target
+        .to_str()?
+        .strip_prefix(prefix)?
+        .strip_suffix(']')?
+        .parse()
+        .ok()
+}
+
+/// The inode of the socket with the flow's 4-tuple in a `/proc/net/tcp` table, its source
+/// being the local end.
+pub fn socket_inode(table: &str, info: &DatapathInfo) -> Option<u64> {
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let local = table_addr(fields.get(1)?)?;
+        let remote = table_addr(fields.get(2)?)?;
+        let inode = fields.get(9)?.parse::<u64>().ok()?;
+        // sockets in TIME_WAIT have no inode
+        (local == (info.src_ip, info.src_port)
+            && remote == (info.dst_ip, info.dst_port)
+            && inode != 0)
+            .then_some(inode)
+    })
+}
+
+// an `<address>:<port>` of the table, the address in hex as the kernel holds it in memory, in
+// network byte order
+fn table_addr(s: &str) -> Option<(u32, u32)> {
+    let (addr, port) = s.split_once(':')?;
+    let addr = u32::from_str_radix(addr, 16).ok()?;
+    let port = u32::from_str_radix(port, 16).ok()?;
+    Some((u32::from_be_bytes(addr.to_ne_bytes()), port))
+}
+
+/// The path of the unified hierarchy's cgroup in a `/proc/<pid>/cgroup`, or of the first
+/// hierarchy's without one.
+pub fn cgroup_path(cgroups: &str) -> Option<String> {
+    let path = |line: &str| line.splitn(3, ':').nth(2).map(String::from);
+    cgroups
+        .lines()
+        .find(|line| line.starts_with("0::"))
+        .or_else(|| cgroups.lines().next())
+        .and_then(path)
+}
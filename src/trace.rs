@@ -0,0 +1,384 @@
+//! Traces of datapath reports, and replaying them through [`BbrCore`].
+//!
+//! A trace is a JSON lines file of [`TraceEvent`]s. Replaying feeds each recorded report to
+//! the control logic at its recorded time on a virtual clock and yields the [`Decision`]s the
+//! algorithm makes, so a misbehaving flow can be debugged offline.
+//!
+//! A [`Recorder`] writes such a trace from a running agent: every flow's start, and every
+//! report a flow acts on, as the datapath sent it, and the markers experiments send with the
+//! control socket's `marker` command, which replaying skips.
+
+use crate::{Action, BbrConfig, BbrCore, BbrMode, Measurement, WallClock};
+use portus::DatapathInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Used for flows whose reports appear in a trace without a `new_flow` event.
+pub const DEFAULT_TRACE_MSS: u32 = 1460;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TraceEvent {
+    NewFlow {
+        /// Time since the start of the trace.
+        elapsed_us: u64,
+        sock_id: u32,
+        init_cwnd: u32,
+        mss: u32,
+        #[serde(default)]
+        src_ip: u32,
+        #[serde(default)]
+        src_port: u32,
+        #[serde(default)]
+        dst_ip: u32,
+        #[serde(default)]
+        dst_port: u32,
+    },
+    /// A report from the flow's currently installed program.
+    Report {
+        elapsed_us: u64,
+        sock_id: u32,
+        /// The datapath's uid of the program that sent the report. Replaying assigns uids of
+        /// its own, since a recorded report was always from the flow's current program.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        program_uid: Option<u32>,
+        minrtt_us: u32,
+        #[serde(default)]
+        loss: u32,
+        #[serde(default)]
+        acked: u32,
+        #[serde(default)]
+        misordered: u32,
+        #[serde(default)]
+        timeout: bool,
+        /// Only in traces recorded before the programs reported both rates; stands in for
+        /// both of them.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rate: Option<f64>,
+        #[serde(default)]
+        rate_outgoing: f64,
+        #[serde(default)]
+        rate_incoming: f64,
+        #[serde(default)]
+        delivery_rate: f64,
+        #[serde(default)]
+        pulse_state: u32,
+        #[serde(default)]
+        receiver_limited: bool,
+        #[serde(default)]
+        inflight_bytes: u32,
+        #[serde(default)]
+        srtt_us: u32,
+        #[serde(default)]
+        rttvar_us: u32,
+        #[serde(default)]
+        max_rate: f64,
+        #[serde(default)]
+        acks: u32,
+        #[serde(default)]
+        nonce: u32,
+        #[serde(default)]
+        nonce_age_us: u32,
+        #[serde(default)]
+        delivered_total: u64,
+        #[serde(default)]
+        span_us: u32,
+        #[serde(default)]
+        mss: u32,
+    },
+    /// A named point in an experiment, such as the start of a load phase.
+    Marker {
+        elapsed_us: u64,
+        /// The time since the Unix epoch, for aligning the trace with what the experiment saw.
+        since_epoch_us: u64,
+        name: String,
+    },
+}
+
+/// An action taken in response to a replayed event.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decision {
+    pub elapsed_us: u64,
+    pub sock_id: u32,
+    /// The flow's mode after the action.
+    pub mode: BbrMode,
+    pub action: Action,
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>12.6}s sock={} mode={:?} ",
+            self.elapsed_us as f64 / 1e6,
+            self.sock_id,
+            self.mode
+        )?;
+        match &self.action {
+            Action::SetProgram { program, fields } => {
+                write!(f, "set_program {}", program)?;
+                for (reg, val) in fields {
+                    write!(f, " {}={}", reg, val)?;
+                }
+            }
+            Action::Update(fields) => {
+                write!(f, "update")?;
+                for (reg, val) in fields {
+                    write!(f, " {}={}", reg, val)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// stands in for the datapath, which assigns each installed program a new uid
+struct ReplayFlow {
+    core: BbrCore,
+    program_uid: u32,
+}
+
+impl ReplayFlow {
+    fn new(cfg: &BbrConfig, info: DatapathInfo, now: Instant) -> Self {
+        ReplayFlow {
+            core: BbrCore::new(cfg, &info, now),
+            program_uid: 0,
+        }
+    }
+
+    fn apply(
+        &mut self,
+        elapsed_us: u64,
+        actions: Vec<Action>,
+        on_decision: &mut impl FnMut(&Decision),
+    ) {
+        for action in actions {
+            if let Action::SetProgram { .. } = action {
+                self.program_uid += 1;
+                self.core.program_installed(self.program_uid);
+            }
+
+            on_decision(&Decision {
+                elapsed_us,
+                sock_id: self.core.sock_id(),
+                mode: self.core.mode(),
+                action,
+            });
+        }
+    }
+}
+
+/// Replays the trace against flows created from `cfg`, calling `on_decision` for every action
+/// the flows take.
+pub fn replay<R: BufRead>(
+    cfg: &BbrConfig,
+    trace: R,
+    mut on_decision: impl FnMut(&Decision),
+) -> Result<(), String> {
+    let start = Instant::now();
+    let mut flows: HashMap<u32, ReplayFlow> = HashMap::new();
+    for (lineno, line) in trace.lines().enumerate() {
+        let line = line.map_err(|e| format!("reading trace: {}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let event: TraceEvent =
+            serde_json::from_str(&line).map_err(|e| format!("trace line {}: {}", lineno + 1, e))?;
+        match event {
+            TraceEvent::NewFlow {
+                elapsed_us,
+                sock_id,
+                init_cwnd,
+                mss,
+                src_ip,
+                src_port,
+                dst_ip,
+                dst_port,
+            } => {
+                let info = DatapathInfo {
+                    sock_id,
+                    init_cwnd,
+                    mss,
+                    src_ip,
+                    src_port,
+                    dst_ip,
+                    dst_port,
+                };
+                let now = start + Duration::from_micros(elapsed_us);
+                // a restarted flow replaces the old one
+                flows.remove(&sock_id);
+                let mut flow = ReplayFlow::new(cfg, info, now);
+                let actions = flow.core.start();
+                flow.apply(elapsed_us, actions, &mut on_decision);
+                flows.insert(sock_id, flow);
+            }
+            TraceEvent::Report {
+                elapsed_us,
+                sock_id,
+                program_uid: _,
+                minrtt_us,
+                loss,
+                acked,
+                misordered,
+                timeout,
+                rate,
+                rate_outgoing,
+                rate_incoming,
+                delivery_rate,
+                pulse_state,
+                receiver_limited,
+                inflight_bytes,
+                srtt_us,
+                rttvar_us,
+                max_rate,
+                acks,
+                nonce,
+                nonce_age_us,
+                delivered_total,
+                span_us,
+                mss,
+            } => {
+                let now = start + Duration::from_micros(elapsed_us);
+                let flow = flows.entry(sock_id).or_insert_with(|| {
+                    let info = DatapathInfo {
+                        sock_id,
+                        init_cwnd: 10 * DEFAULT_TRACE_MSS,
+                        mss: DEFAULT_TRACE_MSS,
+                        src_ip: 0,
+                        src_port: 0,
+                        dst_ip: 0,
+                        dst_port: 0,
+                    };
+                    let mut flow = ReplayFlow::new(cfg, info, now);
+                    flow.program_uid = 1;
+                    flow.core.program_installed(flow.program_uid);
+                    flow
+                });
+                let m = Measurement {
+                    program_uid: flow.program_uid,
+                    minrtt_us,
+                    loss,
+                    acked,
+                    misordered,
+                    timeout,
+                    rate_outgoing: rate.unwrap_or(rate_outgoing),
+                    rate_incoming: rate.unwrap_or(rate_incoming),
+                    delivery_rate,
+                    pulse_state,
+                    receiver_limited,
+                    inflight_bytes,
+                    srtt_us,
+                    rttvar_us,
+                    max_rate,
+                    acks,
+                    nonce,
+                    nonce_age_us,
+                    delivered_total,
+                    span_us,
+                    mss,
+                    received: None,
+                };
+                let actions = flow.core.on_measurement(now, m);
+                flow.apply(elapsed_us, actions, &mut on_decision);
+            }
+            TraceEvent::Marker { .. } => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Appends the flows' starts and reports to a trace, for [`replay`].
+///
+/// Shared by all flows of one `BbrConfig`, whose socket ids must be unique. Every event is
+/// written as soon as it happens, so that the trace of an agent that crashes is complete.
+#[derive(Clone)]
+pub struct Recorder {
+    start: Instant,
+    // dropped after a failed write, which ends the recording
+    out: Arc<Mutex<Option<LineWriter<File>>>>,
+}
+
+impl Recorder {
+    /// Records to `path`, replacing it. The trace's clock starts now.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Recorder {
+            start: Instant::now(),
+            out: Arc::new(Mutex::new(Some(LineWriter::new(File::create(path)?)))),
+        })
+    }
+
+    fn elapsed_us(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.start).as_micros() as u64
+    }
+
+    fn write(&self, event: &TraceEvent) {
+        let mut out = self.out.lock().unwrap();
+        if let Some(writer) = out.as_mut() {
+            let line = serde_json::to_string(event).unwrap();
+            if let Err(err) = writeln!(writer, "{}", line) {
+                warn!(?err, "could not record trace, stopping");
+                *out = None;
+            }
+        }
+    }
+
+    pub fn new_flow(&self, info: &DatapathInfo, now: Instant) {
+        self.write(&TraceEvent::NewFlow {
+            elapsed_us: self.elapsed_us(now),
+            sock_id: info.sock_id,
+            init_cwnd: info.init_cwnd,
+            mss: info.mss,
+            src_ip: info.src_ip,
+            src_port: info.src_port,
+            dst_ip: info.dst_ip,
+            dst_port: info.dst_port,
+        });
+    }
+
+    pub fn marker(&self, name: &str, at: WallClock) {
+        self.write(&TraceEvent::Marker {
+            elapsed_us: self.elapsed_us(at.instant),
+            since_epoch_us: at.since_epoch.as_micros() as u64,
+            name: String::from(name),
+        });
+    }
+
+    pub fn report(&self, sock_id: u32, m: &Measurement, now: Instant) {
+        self.write(&TraceEvent::Report {
+            elapsed_us: self.elapsed_us(now),
+            sock_id,
+            program_uid: Some(m.program_uid),
+            minrtt_us: m.minrtt_us,
+            loss: m.loss,
+            acked: m.acked,
+            misordered: m.misordered,
+            timeout: m.timeout,
+            rate: None,
+            rate_outgoing: m.rate_outgoing,
+            rate_incoming: m.rate_incoming,
+            delivery_rate: m.delivery_rate,
+            pulse_state: m.pulse_state,
+            receiver_limited: m.receiver_limited,
+            inflight_bytes: m.inflight_bytes,
+            srtt_us: m.srtt_us,
+            rttvar_us: m.rttvar_us,
+            max_rate: m.max_rate,
+            acks: m.acks,
+            nonce: m.nonce,
+            nonce_age_us: m.nonce_age_us,
+            delivered_total: m.delivered_total,
+            span_us: m.span_us,
+            mss: m.mss,
+        });
+    }
+}
@@ -0,0 +1,119 @@
+//! Serving several datapaths from one agent.
+//!
+//! `--ipc` takes a comma-separated list of transports, e.g. `netlink,unix` for the kernel
+//! module and a user-space stack on the same host. Each transport serves its flows with its
+//! own copy of the configuration: the program variants for its kind of datapath, what its
+//! flows find that datapath to lack, and its own weights, bottleneck groups, paused, limited
+//! and scheduled flows, tenants and snapshots, since socket ids are only unique within one
+//! datapath. The path cache is shared, and a shutdown request releases the flows of every
+//! transport.
+
+use crate::capability::DatapathCapabilities;
+use crate::datapath::DatapathKind;
+use crate::flow_limit::FlowLimits;
+use crate::group::BottleneckGroups;
+use crate::latency::LatencyHistogram;
+use crate::pause::PausedFlows;
+use crate::probe_schedule::ProbeSchedule;
+use crate::snapshot::Snapshots;
+use crate::tenant::Tenants;
+use crate::weight::FlowWeights;
+use crate::BbrConfig;
+use serde::Serialize;
+
+/// Parses a comma-separated list of the transports `--ipc` accepts, each at most once.
+pub fn parse_transports(s: &str) -> Result<Vec<String>, String> {
+    let mut transports: Vec<String> = vec![];
+    for ipc in s.split(',').map(str::trim) {
+        portus::algs::ipc_valid(String::from(ipc))?;
+        if transports.iter().any(|t| t == ipc) {
+            return Err(format!("ipc {:?} is listed twice", ipc));
+        }
+        transports.push(String::from(ipc));
+    }
+
+    Ok(transports)
+}
+
+/// One transport and the configuration its flows start from.
+#[derive(Clone)]
+pub struct Transport {
+    pub ipc: String,
+    pub cfg: BbrConfig,
+}
+
+impl Transport {
+    pub fn new(cfg: &BbrConfig, ipc: &str, datapath: DatapathKind) -> Self {
+        Transport {
+            ipc: String::from(ipc),
+            cfg: BbrConfig {
+                datapath,
+                capabilities: DatapathCapabilities::default(),
+                weights: FlowWeights::default(),
+                groups: BottleneckGroups::new(cfg.groups.prefix_len()),
+                snapshots: Snapshots::default(),
+                paused: PausedFlows::default(),
+                flow_limits: FlowLimits::default(),
+                probe_schedule: ProbeSchedule::default(),
+                tenants: Tenants::default(),
+                shutdown: cfg.shutdown.scope(),
+                ..cfg.clone()
+            },
+        }
+    }
+
+    /// The transport's configuration, see [`BbrConfig::effective`], the flows the control
+    /// socket paused or limited, by socket id, and how many flows each tenant has.
+    pub fn effective_config(&self) -> serde_json::Value {
+        serde_json::json!({
+            "ipc": self.ipc,
+            "config": self.cfg.effective(),
+            "paused": self.cfg.paused.sock_ids(),
+            "rate_limits": self.cfg.flow_limits.all(),
+            "tenants": self.cfg.tenants.all(),
+        })
+    }
+
+    pub fn stats(&self) -> TransportStats {
+        let flows = self.cfg.snapshots.flows();
+        TransportStats {
+            ipc: self.ipc.clone(),
+            datapath: self.cfg.datapath,
+            flows: flows.len(),
+            reports: flows.iter().map(|flow| flow.reports).sum(),
+            stale_reports: flows.iter().map(|flow| flow.stale_reports).sum(),
+            lacks_flow_rates: self.cfg.capabilities.lacks_flow_rates(),
+            lacks_rate_enforcement: self.cfg.capabilities.lacks_rate_enforcement(),
+            lacks_probe_bw: self.cfg.capabilities.lacks_probe_bw(),
+            unpaced_interfaces: self.cfg.capabilities.unpaced_interfaces(),
+            degraded_flows: flows.iter().filter(|flow| flow.degraded).count(),
+            handling: flows
+                .iter()
+                .fold(LatencyHistogram::default(), |mut handling, flow| {
+                    handling.merge(&flow.handling);
+                    handling
+                }),
+        }
+    }
+}
+
+/// What a transport's current flows have seen.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TransportStats {
+    pub ipc: String,
+    pub datapath: DatapathKind,
+    pub flows: usize,
+    /// Reports from the flows' current programs.
+    pub reports: u64,
+    /// Reports from programs that had already been replaced.
+    pub stale_reports: u64,
+    pub lacks_flow_rates: bool,
+    pub lacks_rate_enforcement: bool,
+    pub lacks_probe_bw: bool,
+    /// Interfaces that were up without a pacing qdisc when last checked.
+    pub unpaced_interfaces: Vec<String>,
+    /// Flows that run the cwnd-only AIMD fallback instead of BBR.
+    pub degraded_flows: usize,
+    /// How long the flows took to handle their reports, over all of them.
+    pub handling: LatencyHistogram,
+}
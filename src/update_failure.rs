@@ -0,0 +1,40 @@
+//! What a flow does when the datapath fails to apply its register updates.
+//!
+//! A failed update leaves the datapath running on registers the flow has since replaced: a
+//! `probe_bw` program keeps pulsing around a bandwidth estimate that may be long gone. By
+//! default the flow reinstalls its program with every register it has set. It can first retry
+//! by rewriting the registers in place a few times, and when failures persist, stop relying on
+//! updates altogether: either freeze at its current estimate, or fall back to the cwnd-only
+//! AIMD program, which needs no updates and shows as degraded in the stats.
+
+use serde::Serialize;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateFailurePolicy {
+    /// Reinstall the program with every register the flow has set.
+    #[default]
+    Reinstall,
+    /// Hand the flow to `init_program` at its current estimate's cwnd and pacing rate, which
+    /// nothing updates again.
+    Freeze,
+    /// Switch to the cwnd-only AIMD program, as for datapaths that reject `probe_bw`.
+    Degrade,
+}
+
+impl FromStr for UpdateFailurePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reinstall" => Ok(UpdateFailurePolicy::Reinstall),
+            "freeze" => Ok(UpdateFailurePolicy::Freeze),
+            "degrade" => Ok(UpdateFailurePolicy::Degrade),
+            _ => Err(format!(
+                "update failure policy must be one of (reinstall|freeze|degrade): {:?}",
+                s
+            )),
+        }
+    }
+}
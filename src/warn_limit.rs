@@ -0,0 +1,94 @@
+//! Warnings that keep recurring, logged once per interval.
+//!
+//! Some failures last: a datapath whose IPC socket went away fails every update of every flow,
+//! and a backed-up agent finds every report late. Logging each occurrence drowns whatever else
+//! goes wrong meanwhile. The flows of one `BbrConfig` share a [`WarnLimiter`] instead, which
+//! lets the first occurrence of a warning through and then at most one every `warn_interval`,
+//! with how many it held back since as `suppressed`. Two occurrences are the same warning if
+//! they have the same message and error; the line that is logged carries the `FlowId` of the
+//! flow that logged it, and the other flows only show up in the count.
+//!
+//! Errors carry details that vary, so a long-running agent sees ever new warnings. A warning
+//! that was logged at least `warn_interval` ago with nothing held back since is as good as
+//! unseen, and the limiter forgets those whenever it meets a new warning in the same shard.
+
+use crate::shard::ShardedMap;
+use std::collections::hash_map::Entry;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug)]
+struct Seen {
+    logged: Instant,
+    suppressed: u64,
+}
+
+/// When each warning was last logged, shared by all flows of one `BbrConfig`.
+#[derive(Clone, Default)]
+pub struct WarnLimiter {
+    seen: ShardedMap<(&'static str, String), Seen>,
+    suppressed: Arc<AtomicU64>,
+}
+
+impl WarnLimiter {
+    /// Whether an occurrence of the warning `message` about `detail` at `now` is to be logged:
+    /// if so, how many occurrences were held back since it last was, and `None` if it was
+    /// logged less than `interval` ago. A zero interval logs every occurrence.
+    pub fn check(
+        &self,
+        message: &'static str,
+        detail: &str,
+        interval: Duration,
+        now: Instant,
+    ) -> Option<u64> {
+        if interval.is_zero() {
+            return Some(0);
+        }
+        let key = (message, String::from(detail));
+        let held = self.seen.with_shard(&key, |seen| {
+            if !seen.contains_key(&key) {
+                seen.retain(|_, seen| {
+                    seen.suppressed > 0 || now.saturating_duration_since(seen.logged) < interval
+                });
+            }
+            match seen.entry(key.clone()) {
+                Entry::Vacant(entry) => {
+                    entry.insert(Seen {
+                        logged: now,
+                        suppressed: 0,
+                    });
+                    Some(0)
+                }
+                Entry::Occupied(mut entry) => {
+                    let seen = entry.get_mut();
+                    if now.saturating_duration_since(seen.logged) < interval {
+                        seen.suppressed += 1;
+                        None
+                    } else {
+                        seen.logged = now;
+                        Some(std::mem::take(&mut seen.suppressed))
+                    }
+                }
+            }
+        });
+        if held.is_none() {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+        }
+        held
+    }
+
+    /// How many warnings the limiter remembers.
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// How many occurrences were held back, of every warning, since the limiter was made.
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed.load(Ordering::Relaxed)
+    }
+}
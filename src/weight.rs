@@ -0,0 +1,146 @@
+//! Weighted bandwidth shares among the local BBR flows.
+//!
+//! Each flow takes the weight of the first matching [`WeightRule`], or 1 if none match, until
+//! the control socket's `weight` command sets another.
+//! A flow then paces at `weight / max_weight` of its bottleneck rate estimate, where
+//! `max_weight` is the largest weight among the currently active flows: the heaviest flows
+//! probe as usual and pick up the bandwidth that lighter flows leave unused.
+
+use crate::flow_match::FlowMatch;
+use crate::shard::ShardedMap;
+use portus::DatapathInfo;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub use crate::params::DEFAULT_WEIGHT;
+
+/// Assigns `weight` to the flows selected by `flow`.
+///
+/// Parsed from and displayed as `<flow match>:<weight>`, e.g. `dport=5201:2.5`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WeightRule {
+    pub flow: FlowMatch,
+    pub weight: f64,
+}
+
+impl FromStr for WeightRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (flow, weight) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected <flow match>:<weight>: {:?}", s))?;
+        let weight = parse_weight(weight)?;
+        Ok(WeightRule {
+            flow: flow.parse()?,
+            weight,
+        })
+    }
+}
+
+impl fmt::Display for WeightRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.flow, self.weight)
+    }
+}
+
+/// Parses a weight, which has to be a positive number.
+pub fn parse_weight(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|w| w.is_finite() && *w > 0.0)
+        .ok_or_else(|| format!("weight must be a positive number: {:?}", s))
+}
+
+/// The weight of the first matching rule, or the one given, or `DEFAULT_WEIGHT`.
+pub fn weight_for(rules: &[WeightRule], weight: Option<f64>, info: &DatapathInfo) -> f64 {
+    rules
+        .iter()
+        .find(|r| r.flow.matches(info))
+        .map(|r| r.weight)
+        .or(weight)
+        .unwrap_or(DEFAULT_WEIGHT)
+}
+
+/// The weights of the currently active flows, shared by all flows of one `BbrConfig`.
+///
+/// Flows look up their share on every report, so the largest weight is kept up to date as
+/// flows come and go rather than searched for.
+#[derive(Clone, Default)]
+pub struct FlowWeights {
+    active: ShardedMap<u32, f64>,
+    /// How many active flows have each weight, by its bits, which sort like the weights since
+    /// they are positive.
+    counts: Arc<Mutex<BTreeMap<u64, usize>>>,
+    /// The bits of the largest weight among the active flows, or of zero without any.
+    max_weight: Arc<AtomicU64>,
+}
+
+impl FlowWeights {
+    // `f` adjusts the counts; the largest weight follows under the same lock
+    fn count(&self, f: impl FnOnce(&mut BTreeMap<u64, usize>)) {
+        let mut counts = self.counts.lock().unwrap();
+        f(&mut counts);
+        let max = counts.keys().next_back().copied().unwrap_or(0);
+        self.max_weight.store(max, Ordering::SeqCst);
+    }
+
+    pub fn register(&self, sock_id: u32, weight: f64) {
+        let previous = self.active.insert(sock_id, weight);
+        self.count(|counts| {
+            if let Some(previous) = previous {
+                uncount(counts, previous);
+            }
+            *counts.entry(weight.to_bits()).or_default() += 1;
+        });
+    }
+
+    /// Gives an active flow another weight, which it paces by from its next report on, and
+    /// returns whether it was active.
+    pub fn reweigh(&self, sock_id: u32, weight: f64) -> bool {
+        let previous = self.active.with_shard(&sock_id, |active| {
+            active
+                .get_mut(&sock_id)
+                .map(|active| std::mem::replace(active, weight))
+        });
+        let Some(previous) = previous else {
+            return false;
+        };
+        self.count(|counts| {
+            uncount(counts, previous);
+            *counts.entry(weight.to_bits()).or_default() += 1;
+        });
+        true
+    }
+
+    /// The weight of an active flow.
+    pub fn weight(&self, sock_id: u32) -> Option<f64> {
+        self.active.get(&sock_id)
+    }
+
+    pub fn deregister(&self, sock_id: u32) {
+        if let Some(weight) = self.active.remove(&sock_id) {
+            self.count(|counts| uncount(counts, weight));
+        }
+    }
+
+    /// The fraction of its bottleneck rate estimate at which the flow should pace.
+    pub fn share(&self, sock_id: u32) -> f64 {
+        let weight = self.active.get(&sock_id).unwrap_or(DEFAULT_WEIGHT);
+        let max_weight = f64::from_bits(self.max_weight.load(Ordering::SeqCst)).max(weight);
+        weight / max_weight
+    }
+}
+
+fn uncount(counts: &mut BTreeMap<u64, usize>, weight: f64) {
+    if let Entry::Occupied(mut count) = counts.entry(weight.to_bits()) {
+        *count.get_mut() -= 1;
+        if *count.get() == 0 {
+            count.remove();
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use ccp_bbr::agent::{run_bbr, Agent};
+use ccp_bbr::error::BbrError;
+use ccp_bbr::BbrConfig;
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+#[test]
+fn unknown_ipc_is_rejected() {
+    let err = Agent::spawn(BbrConfig::default(), "carrier_pigeon")
+        .err()
+        .unwrap();
+    assert!(matches!(err, BbrError::Ipc(_)));
+    assert!(err.is_fatal());
+
+    // the future reports the error on its first poll, without a runtime
+    let mut run = pin!(run_bbr(BbrConfig::default(), "carrier_pigeon"));
+    let mut cx = Context::from_waker(Waker::noop());
+    assert!(matches!(run.as_mut().poll(&mut cx), Poll::Ready(Err(_))));
+}
+
+#[test]
+fn panics_request_the_shutdown() {
+    let cfg = BbrConfig::default();
+    cfg.shutdown.release_on_panic();
+    assert!(!cfg.shutdown.panicked());
+    assert!(std::thread::spawn(|| panic!("flow state broken"))
+        .join()
+        .is_err());
+    assert!(cfg.shutdown.panicked());
+    assert!(cfg.shutdown.is_requested());
+}
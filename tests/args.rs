@@ -0,0 +1,515 @@
+mod common;
+
+use ccp_bbr::bandwidth::Rate;
+use ccp_bbr::error::BbrError;
+use ccp_bbr::log_granularity::LogGranularity;
+use ccp_bbr::loss::{LossAccounting, LossMode};
+use ccp_bbr::params;
+use ccp_bbr::rate::RateEstimator;
+use ccp_bbr::tenant::TenantLookup;
+use ccp_bbr::update_failure::UpdateFailurePolicy;
+use ccp_bbr::{Action, BbrConfig};
+use portus::{CongAlgBuilder, DatapathInfo};
+use std::time::Duration;
+
+fn parse(argv: &[&str]) -> Result<BbrConfig, BbrError> {
+    let matches = BbrConfig::args()
+        .get_matches_from_safe(std::iter::once("bbr").chain(argv.iter().copied()))
+        .expect("argument parsing");
+    BbrConfig::from_arg_matches(&matches)
+}
+
+#[test]
+fn defaults_match_library_defaults() {
+    let cfg = parse(&[]).unwrap();
+    let default = BbrConfig::default();
+    assert_eq!(cfg.probe_rtt_interval, default.probe_rtt_interval);
+    assert_eq!(
+        cfg.probe_rtt_interval,
+        Duration::from_secs(ccp_bbr::PROBE_RTT_INTERVAL_SECONDS as u64)
+    );
+    assert!(cfg.weight_rules.is_empty());
+    assert_eq!(cfg.probe_rtt_sync_window, None);
+    assert!(!cfg.scale_probe_rtt);
+    assert!(!cfg.share_min_rtt);
+    assert!(!cfg.skip_quiet_probe_rtt);
+    assert_eq!(cfg.incast_threshold, None);
+    assert_eq!(cfg.startup_gain, params::STARTUP_GAIN);
+    assert_eq!(cfg.startup_cwnd_gain, params::STARTUP_CWND_GAIN);
+    assert_eq!(cfg.drain_gain, params::DRAIN_GAIN);
+    assert!(cfg.cwnd_cap);
+    assert!(cfg.pacing);
+    assert!(!cfg.fast_step_down);
+    assert!(!cfg.reset_desynced_pulses);
+    assert_eq!(cfg.loss_mode, LossMode::Ignore);
+    assert_eq!(cfg.loss_rtt_inflation, default.loss_rtt_inflation);
+    assert_eq!(cfg.loss_accounting, LossAccounting::Windowed);
+    assert_eq!(cfg.loss_burst_fraction, default.loss_burst_fraction);
+    assert!(!cfg.track_cwnd);
+    assert!(cfg.lossy_rtt_filter);
+    assert_eq!(cfg.cwnd_bdp_multiplier, default.cwnd_bdp_multiplier);
+    assert_eq!(cfg.pulse_length, None);
+    assert!(!cfg.pulse_shift);
+    assert_eq!(cfg.down_phase_end, params::DOWN_PHASE_END_PULSES);
+    assert_eq!(cfg.cruise_phase_end, params::PULSE_CYCLE_ROUNDS);
+    assert_eq!(cfg.bw_window, default.bw_window);
+    assert_eq!(cfg.high_rtt_threshold, None);
+    assert!(!cfg.schedule_probes);
+    assert_eq!(cfg.register_limit, None);
+    assert_eq!(cfg.max_report_age, None);
+    assert_eq!(cfg.rate_smoothing, None);
+    assert_eq!(cfg.min_rtt_spike_factor, default.min_rtt_spike_factor);
+    assert_eq!(cfg.stale_probes, default.stale_probes);
+    assert_eq!(cfg.stale_probe_interval, 1);
+    assert_eq!(cfg.stable_probe_gain, None);
+    assert_eq!(cfg.initial_rate, None);
+    assert_eq!(cfg.initial_rtt, None);
+    assert!(cfg.initial_path_rules.is_empty());
+    assert_eq!(cfg.delay_budget, None);
+    assert_eq!(cfg.rate_estimator, RateEstimator::Auto);
+    assert_eq!(cfg.update_retries, 0);
+    assert_eq!(cfg.update_failure, UpdateFailurePolicy::Reinstall);
+    assert_eq!(cfg.log_granularity, LogGranularity::Report);
+    assert_eq!(cfg.warn_interval, Duration::from_secs(10));
+    assert!(cfg.tenant_rules.is_empty());
+    assert_eq!(cfg.tenant_lookup, TenantLookup::None);
+    assert!(cfg.tenant_configs.is_empty());
+    assert!(!cfg.fit_probes_to_buffer);
+}
+
+#[test]
+fn flags_configure_bbr() {
+    let cfg = parse(&[
+        "--probe_rtt_interval",
+        "5",
+        "--weight",
+        "dport=5201:2",
+        "--weight",
+        "dst=10.0.0.0/8:0.5",
+        "--sync_probe_rtt",
+        "--scale_probe_rtt",
+        "--share_min_rtt",
+        "--skip_quiet_probe_rtt",
+        "--incast_threshold",
+        "32",
+        "--no_cwnd_cap",
+        "--loss_mode",
+        "lossy",
+        "--loss_rtt_inflation",
+        "1.1",
+        "--loss_accounting",
+        "net",
+        "--loss_burst_fraction",
+        "0.5",
+        "--no_lossy_rtt_filter",
+        "--track_cwnd",
+        "--cwnd_bdp_multiplier",
+        "1.25",
+        "--pulse_length",
+        "10",
+        "--down_phase_end",
+        "3",
+        "--cruise_phase_end",
+        "12",
+        "--bw_window",
+        "3",
+        "--high_rtt_threshold",
+        "300ms",
+        "--schedule_probes",
+        "--register_limit",
+        "64",
+        "--max_report_age",
+        "200",
+        "--rate_smoothing",
+        "10",
+        "--jitter_headroom",
+        "--fast_step_down",
+        "--reset_desynced_pulses",
+        "--pulse_shift",
+        "--stale_probes",
+        "2",
+        "--stale_probe_interval",
+        "4",
+        "--stable_probe_gain",
+        "1.1",
+        "--rate_estimator",
+        "delivered",
+        "--update_retries",
+        "2",
+        "--on_update_failure",
+        "freeze",
+        "--log_granularity",
+        "cycle",
+        "--warn_interval",
+        "1m",
+        "--tenant",
+        "src=172.17.0.0/24:blue",
+        "--tenant_lookup",
+        "cgroup",
+        "--tenant_config",
+        "blue:weight=2,aggregate_rate=500Mbps",
+        "--fit_probes_to_buffer",
+        "--initial_rate",
+        "100",
+        "--initial_rtt",
+        "20",
+        "--initial_path",
+        "dst=10.1.0.0/16:1000:2ms",
+    ])
+    .unwrap();
+    assert_eq!(cfg.probe_rtt_interval, Duration::from_secs(5));
+    assert_eq!(cfg.weight_rules.len(), 2);
+    assert_eq!(
+        cfg.probe_rtt_sync_window,
+        Some(Duration::from_millis(ccp_bbr::PROBE_RTT_SYNC_WINDOW_MS))
+    );
+    assert!(cfg.scale_probe_rtt);
+    assert!(cfg.share_min_rtt);
+    assert!(cfg.skip_quiet_probe_rtt);
+    assert_eq!(cfg.incast_threshold, Some(32));
+    assert!(!cfg.cwnd_cap);
+    assert_eq!(cfg.loss_mode, LossMode::Lossy);
+    assert_eq!(cfg.loss_rtt_inflation, 1.1);
+    assert_eq!(cfg.loss_accounting, LossAccounting::Net);
+    assert_eq!(cfg.loss_burst_fraction, Some(0.5));
+    assert!(!cfg.lossy_rtt_filter);
+    assert!(cfg.track_cwnd);
+    assert_eq!(cfg.cwnd_bdp_multiplier, 1.25);
+    assert_eq!(cfg.pulse_length, Some(Duration::from_millis(10)));
+    assert_eq!(cfg.down_phase_end, 3);
+    assert_eq!(cfg.cruise_phase_end, 12);
+    assert_eq!(cfg.bw_window, 3);
+    assert_eq!(cfg.high_rtt_threshold, Some(Duration::from_millis(300)));
+    assert!(cfg.schedule_probes);
+    assert_eq!(cfg.register_limit, Some(64));
+    assert_eq!(cfg.max_report_age, Some(Duration::from_millis(200)));
+    assert_eq!(cfg.rate_smoothing, Some(0.1));
+    assert!(cfg.jitter_headroom);
+    assert!(cfg.fast_step_down);
+    assert!(cfg.reset_desynced_pulses);
+    assert!(cfg.pulse_shift);
+    assert_eq!(cfg.stale_probes, 2);
+    assert_eq!(cfg.stale_probe_interval, 4);
+    assert_eq!(cfg.stable_probe_gain, Some(1.1));
+    assert_eq!(cfg.rate_estimator, RateEstimator::Delivered);
+    assert_eq!(cfg.update_retries, 2);
+    assert_eq!(cfg.update_failure, UpdateFailurePolicy::Freeze);
+    assert_eq!(cfg.log_granularity, LogGranularity::Cycle);
+    assert_eq!(cfg.warn_interval, Duration::from_secs(60));
+    assert_eq!(cfg.tenant_rules[0].tenant, "blue");
+    assert_eq!(cfg.tenant_lookup, TenantLookup::Cgroup);
+    assert_eq!(cfg.tenant_configs[0].settings.weight, Some(2.0));
+    assert_eq!(
+        cfg.tenant_configs[0].settings.aggregate_rate,
+        Some(Rate::from_mbps(500.0))
+    );
+    assert!(cfg.fit_probes_to_buffer);
+    assert_eq!(cfg.initial_rate, Some(Rate::from_mbps(100.0)));
+    assert_eq!(cfg.initial_rtt, Some(Duration::from_millis(20)));
+    assert_eq!(cfg.initial_path_rules.len(), 1);
+    assert_eq!(cfg.initial_path_rules[0].rate, Rate::from_mbps(1000.0));
+    assert_eq!(cfg.initial_path_rules[0].rtt, Duration::from_millis(2));
+}
+
+#[test]
+fn durations_take_units() {
+    let cfg = parse(&[
+        "--probe_rtt_interval",
+        "500ms",
+        "--path_cache_ttl",
+        "1min",
+        "--pulse_length",
+        "2.5ms",
+    ])
+    .unwrap();
+    assert_eq!(cfg.probe_rtt_interval, Duration::from_millis(500));
+    assert_eq!(cfg.pulse_length, Some(Duration::from_micros(2_500)));
+
+    let cfg = parse(&["--probe_rtt_interval", "2.5s", "--pulse_length", "10"]).unwrap();
+    assert_eq!(cfg.probe_rtt_interval, Duration::from_millis(2_500));
+    assert_eq!(cfg.pulse_length, Some(Duration::from_millis(10)));
+    // the flag's old spelling
+    let cfg = parse(&["--pulse_length_ms", "10"]).unwrap();
+    assert_eq!(cfg.pulse_length, Some(Duration::from_millis(10)));
+
+    let cfg = parse(&["--delay_budget", "5ms"]).unwrap();
+    assert_eq!(cfg.delay_budget, Some(Duration::from_millis(5)));
+    let cfg = parse(&["--delay_budget", "2.5"]).unwrap();
+    assert_eq!(cfg.delay_budget, Some(Duration::from_micros(2_500)));
+
+    assert_eq!(parse(&[]).unwrap().probe_rtt_grace, None);
+    let cfg = parse(&["--probe_rtt_grace", "5s"]).unwrap();
+    assert_eq!(cfg.probe_rtt_grace, Some(Duration::from_secs(5)));
+    let cfg = parse(&["--probe_rtt_grace", "0"]).unwrap();
+    assert_eq!(cfg.probe_rtt_grace, Some(Duration::ZERO));
+    assert!(parse(&["--probe_rtt_grace", "soon"]).is_err());
+
+    assert!(parse(&["--probe_rtt_interval", "10 fortnights"]).is_err());
+    assert!(parse(&["--probe_rtt_interval", "1.5.2s"]).is_err());
+
+    let cfg = parse(&["--probe_rtt_interval", "800rtt"]).unwrap();
+    assert_eq!(cfg.probe_rtt_rounds, Some(800));
+    assert_eq!(parse(&[]).unwrap().probe_rtt_rounds, None);
+    assert!(parse(&["--probe_rtt_interval", "0rtt"]).is_err());
+    assert!(parse(&["--probe_rtt_interval", "800rtt", "--align_probe_rtt"]).is_err());
+}
+
+#[test]
+fn rates_take_units() {
+    let cfg = parse(&[
+        "--initial_rate",
+        "1.2Gbit",
+        "--max_rate",
+        "500Mbps",
+        "--initial_path",
+        "dst=10.1.0.0/16:10MB/s:2ms",
+    ])
+    .unwrap();
+    assert_eq!(cfg.initial_rate, Some(Rate::from_mbps(1200.0)));
+    assert_eq!(cfg.max_rate, Some(Rate::from_mbps(500.0)));
+    assert_eq!(
+        cfg.initial_path_rules[0].rate,
+        Rate::from_bytes_per_sec(10_000_000.0)
+    );
+
+    let cfg = parse(&["--max_rate", "20", "--flow_max_rate", "dport=5201:1.5Gbit"]).unwrap();
+    assert_eq!(cfg.max_rate, Some(Rate::from_mbps(20.0)));
+    assert_eq!(cfg.max_rate_rules.len(), 1);
+    assert_eq!(cfg.max_rate_rules[0].rate, Rate::from_mbps(1500.0));
+    assert_eq!(parse(&[]).unwrap().max_rate, None);
+
+    let cfg = parse(&["--min_rate", "4Mbps", "--flow_min_rate", "dport=1935:6"]).unwrap();
+    assert_eq!(cfg.min_rate, Some(Rate::from_mbps(4.0)));
+    assert_eq!(cfg.min_rate_rules[0].rate, Rate::from_mbps(6.0));
+
+    // the flag's old spelling
+    let cfg = parse(&["--initial_rate_mbps", "100"]).unwrap();
+    assert_eq!(cfg.initial_rate, Some(Rate::from_mbps(100.0)));
+}
+
+#[test]
+fn filters_select_the_flows_to_manage() {
+    let flow = |dst_ip: u32, dst_port: u32| DatapathInfo {
+        dst_ip,
+        dst_port,
+        ..common::info(1)
+    };
+    let everything = parse(&[]).unwrap();
+    assert!(everything.flow_filters.is_empty());
+    assert!(everything.manages(&flow(0x0a02_0002, 22)));
+
+    let cfg = parse(&[
+        "--match",
+        "dst=10.1.0.0/16,dport=8000-8099",
+        "--match",
+        "src=10.0.0.0/24,sport=40000",
+    ])
+    .unwrap();
+    assert_eq!(cfg.flow_filters.len(), 2);
+    assert_eq!(cfg.flow_filters[0].matches.len(), 2);
+    assert!(cfg.manages(&flow(0x0a01_0002, 8099)));
+    // the second filter selects every flow from this host's port 40000
+    assert!(cfg.manages(&flow(0x0a02_0002, 22)));
+    let cfg = parse(&["--match", "dst=10.1.0.0/16,dport=8000-8099"]).unwrap();
+    assert!(cfg.manages(&flow(0x0a01_0002, 8000)));
+    assert!(!cfg.manages(&flow(0x0a01_0002, 8100)));
+    assert!(!cfg.manages(&flow(0x0a02_0002, 8000)));
+    match &cfg.unmanaged_start(&flow(0x0a02_0002, 8000))[..] {
+        [Action::SetProgram {
+            program: "aimd",
+            fields,
+        }] => assert!(fields.contains(&("Cwnd", 14_600))),
+        actions => panic!("{:?}", actions),
+    }
+
+    assert!(parse(&["--match", "dport=9000-8000"]).is_err());
+    assert!(parse(&["--match", "dport=80,"]).is_err());
+    assert!(parse(&["--match", "proto=tcp"]).is_err());
+}
+
+#[test]
+fn sizes_take_units() {
+    let cfg = parse(&[
+        "--short_flow_bytes",
+        "100KB",
+        "--short_flow",
+        "dport=443:1MiB",
+        "--short_flow",
+        "dport=80:50000",
+    ])
+    .unwrap();
+    assert_eq!(cfg.short_flow_bytes, Some(100_000));
+    assert_eq!(cfg.short_flow_rules.len(), 2);
+    assert_eq!(cfg.short_flow_rules[0].bytes, 1 << 20);
+    assert_eq!(cfg.short_flow_rules[1].bytes, 50_000);
+    assert_eq!(parse(&[]).unwrap().short_flow_bytes, None);
+
+    assert!(parse(&["--short_flow_bytes", "0"]).is_err());
+    assert!(parse(&["--short_flow_bytes", "10 furlongs"]).is_err());
+    assert!(parse(&["--short_flow", "dport=443"]).is_err());
+}
+
+#[test]
+fn zero_probe_rtt_interval_disables_probe_rtt() {
+    let cfg = parse(&["--probe_rtt_interval", "0"]).unwrap();
+    assert_eq!(cfg.probe_rtt_interval, Duration::ZERO);
+}
+
+#[test]
+fn zero_spike_factor_takes_every_min_rtt_sample() {
+    let cfg = parse(&["--min_rtt_spike_factor", "0"]).unwrap();
+    assert_eq!(cfg.min_rtt_spike_factor, None);
+    let cfg = parse(&["--loss_burst_fraction", "0"]).unwrap();
+    assert_eq!(cfg.loss_burst_fraction, None);
+}
+
+#[test]
+fn invalid_values_are_rejected() {
+    assert!(parse(&["--probe_rtt_interval", "ten"]).is_err());
+    assert!(parse(&["--path_cache_prefix", "33"]).is_err());
+    assert!(parse(&["--path_cache_capacity", "many"]).is_err());
+    assert!(parse(&["--weight", "dport=5201"]).is_err());
+    assert!(parse(&["--startup_gain", "1"]).is_err());
+    assert!(parse(&["--startup_gain", "inf"]).is_err());
+    assert!(parse(&["--startup_cwnd_gain", "inf"]).is_err());
+    assert!(parse(&["--loss_mode", "random"]).is_err());
+    assert!(parse(&["--loss_rtt_inflation", "0.9"]).is_err());
+    assert!(parse(&["--loss_accounting", "rack"]).is_err());
+    assert!(parse(&["--loss_burst_fraction", "1.5"]).is_err());
+    assert!(parse(&["--cwnd_bdp_multiplier", "0.5"]).is_err());
+    assert!(parse(&["--cwnd_bdp_multiplier", "inf"]).is_err());
+    assert!(parse(&["--pulse_length", "0"]).is_err());
+    assert!(parse(&["--rate_smoothing", "0"]).is_err());
+    assert!(parse(&["--down_phase_end", "1"]).is_err());
+    assert!(parse(&["--down_phase_end", "8"]).is_err());
+    assert!(parse(&["--cruise_phase_end", "65"]).is_err());
+    assert!(parse(&["--cruise_phase_end", "long"]).is_err());
+    assert!(parse(&["--down_phase_end", "4", "--high_rtt_threshold", "300ms"]).is_err());
+    assert!(parse(&["--bw_window", "0"]).is_err());
+    assert!(parse(&["--bw_window", "33"]).is_err());
+    assert!(parse(&["--max_report_age", "0"]).is_err());
+    assert!(parse(&["--max_report_age", "soon"]).is_err());
+    assert!(parse(&["--delay_budget", "0"]).is_err());
+    assert!(parse(&["--incast_threshold", "1"]).is_err());
+    assert!(parse(&["--rate_smoothing", "100"]).is_err());
+    assert!(parse(&["--min_rtt_spike_factor", "0.5"]).is_err());
+    assert!(parse(&["--stale_probe_interval", "0"]).is_err());
+    assert!(parse(&["--register_limit", "0"]).is_err());
+    assert!(parse(&["--register_limit", "few"]).is_err());
+    assert!(parse(&["--drain_gain", "NaN"]).is_err());
+    assert!(parse(&["--stable_probe_gain", "inf"]).is_err());
+    assert!(parse(&["--loss_rtt_inflation", "inf"]).is_err());
+    assert!(parse(&["--min_rtt_spike_factor", "inf"]).is_err());
+    assert!(matches!(
+        parse(&["--startup_gain", "1"]),
+        Err(BbrError::Config(_))
+    ));
+    assert_eq!(
+        parse(&["--loss_burst_fraction", "1.5"])
+            .err()
+            .map(|e| e.to_string()),
+        Some(String::from(
+            "invalid configuration: loss_burst_fraction must be 0 or above 0 and at most 1: 1.5"
+        ))
+    );
+    assert!(parse(&["--rate_estimator", "bbr"]).is_err());
+    assert!(parse(&["--update_retries", "many"]).is_err());
+    assert!(parse(&["--on_update_failure", "ignore"]).is_err());
+    assert!(parse(&["--log_granularity", "pulse"]).is_err());
+    assert!(parse(&["--warn_interval", "often"]).is_err());
+    assert!(parse(&["--tenant", "src=172.17.0.0/24"]).is_err());
+    assert!(parse(&["--tenant", "src=172.17.0.0/24:"]).is_err());
+    assert!(parse(&["--tenant_lookup", "pid"]).is_err());
+    assert!(parse(&["--tenant_config", "blue:share=2"]).is_err());
+    assert!(parse(&["--tenant_config", "blue:aggregate_rate=0"]).is_err());
+    assert!(parse(&["--stable_probe_gain", "1"]).is_err());
+    assert!(parse(&["--stable_probe_gain", "1.5"]).is_err());
+    assert!(parse(&["--initial_rate", "0"]).is_err());
+    assert!(parse(&["--max_rate", "0Mbps"]).is_err());
+    assert!(parse(&["--max_rate", "5furlongs"]).is_err());
+    assert!(parse(&["--flow_max_rate", "200Mbps"]).is_err());
+    assert!(parse(&["--min_rate", "20Mbps", "--max_rate", "10Mbps"]).is_err());
+    assert!(parse(&["--flow_min_rate", "dport=1935"]).is_err());
+    assert!(parse(&["--initial_rtt", "0ms"]).is_err());
+    assert!(parse(&["--initial_path", "dst=10.1.0.0/16:1000"]).is_err());
+    assert!(parse(&["--initial_path", "dst=10.1.0.0/16:fast:2ms"]).is_err());
+    assert!(BbrConfig::args()
+        .get_matches_from_safe(["bbr", "--no_pacing", "--no_cwnd_cap"])
+        .is_err());
+    assert!(BbrConfig::args()
+        .get_matches_from_safe(["bbr", "--delay_budget", "5ms", "--no_cwnd_cap"])
+        .is_err());
+}
+
+#[test]
+fn register_limit_trades_features_at_startup() {
+    let cfg = parse(&["--pulse_shift", "--ramp_probe_bw", "--register_limit", "40"]).unwrap();
+    assert!(!cfg.pulse_shift);
+    assert!(!cfg.probe_bw_ramp);
+    assert!(cfg.register_usage().values().all(|&used| used <= 40));
+
+    // an auto datapath has to fit on both
+    assert_eq!(
+        parse(&["--register_limit", "39"]).unwrap().rate_estimator,
+        RateEstimator::Delivered
+    );
+    assert_eq!(
+        parse(&["--datapath", "quic", "--register_limit", "39"])
+            .unwrap()
+            .rate_estimator,
+        RateEstimator::Auto
+    );
+    assert!(parse(&["--register_limit", "10"]).is_err());
+}
+
+#[test]
+fn effective_config_reads_back_as_flags() {
+    let cfg = parse(&[
+        "--probe_rtt_interval",
+        "800rtt",
+        "--weight",
+        "dport=5201:2.5",
+        "--initial_path",
+        "dst=10.1.0.0/16:1Gbps:2ms",
+        "--flow_max_rate",
+        "sport=8000-8099:4Mbps",
+        "--short_flow",
+        "dport=443:100KB",
+        "--match",
+        "dst=10.1.0.0/16,dport=443",
+        "--pulse_length",
+        "2.5",
+        "--loss_mode",
+        "lossy",
+    ])
+    .unwrap();
+    let effective = cfg.effective();
+    assert_eq!(effective["probe_rtt_interval"], "800rtt");
+    assert_eq!(effective["pulse_length"], "2500us");
+    assert_eq!(effective["path_cache_ttl"], "300s");
+    assert_eq!(effective["path_cache_capacity"], 65_536);
+    assert_eq!(effective["loss_mode"], "lossy");
+    assert_eq!(effective["short_flow_bytes"], serde_json::Value::Null);
+    // --datapath auto picks the datapath --ipc usually serves
+    assert_eq!(effective["datapath"], "quic");
+    assert_eq!(
+        parse(&["--ipc", "netlink"]).unwrap().effective()["datapath"],
+        "kernel"
+    );
+
+    let flag = |name: &str| effective[name][0].as_str().unwrap().to_owned();
+    assert_eq!(flag("weight_rules"), "dport=5201:2.5");
+    assert_eq!(flag("initial_path_rules"), "dst=10.1.0.0/16:1Gbps:2ms");
+    assert_eq!(flag("max_rate_rules"), "sport=8000-8099:4Mbps");
+    assert_eq!(flag("short_flow_rules"), "dport=443:100000B");
+    assert_eq!(flag("flow_filters"), "dst=10.1.0.0/16,dport=443");
+    let reparsed = parse(&[
+        "--short_flow",
+        &flag("short_flow_rules"),
+        "--pulse_length",
+        effective["pulse_length"].as_str().unwrap(),
+    ])
+    .unwrap();
+    assert_eq!(reparsed.short_flow_rules, cfg.short_flow_rules);
+    assert_eq!(reparsed.pulse_length, cfg.pulse_length);
+}
@@ -0,0 +1,57 @@
+use ccp_bbr::bandwidth::{parse_rate, Rate};
+
+#[test]
+fn rates_parse_bit_and_byte_units() {
+    assert_eq!("50Mbps".parse(), Ok(Rate::from_mbps(50.0)));
+    assert_eq!("1.2Gbit".parse(), Ok(Rate::from_mbps(1200.0)));
+    assert_eq!("800kbit/s".parse(), Ok(Rate::from_mbps(0.8)));
+    assert_eq!("800Kbps".parse(), Ok(Rate::from_mbps(0.8)));
+    assert_eq!("10MB/s".parse(), Ok(Rate::from_bytes_per_sec(10_000_000.0)));
+    assert_eq!(
+        " 2 GBps ".parse(),
+        Ok(Rate::from_bytes_per_sec(2_000_000_000.0))
+    );
+    // bare numbers are bits per second, unless the flag says otherwise
+    assert_eq!("8000".parse(), Ok(Rate::from_bytes_per_sec(1_000.0)));
+    assert_eq!(
+        parse_rate("100", Rate::from_mbps(1.0)),
+        Ok(Rate::from_mbps(100.0))
+    );
+}
+
+#[test]
+fn invalid_rates_are_rejected() {
+    for s in [
+        "",
+        "fast",
+        "0Mbps",
+        "1.5.2Mbps",
+        "10mbps",
+        "10 furlongs",
+        "-5Mbps",
+    ] {
+        assert!(s.parse::<Rate>().is_err(), "{:?}", s);
+    }
+}
+
+#[test]
+fn rates_display_in_bits_per_second() {
+    assert_eq!(Rate::from_mbps(12.5).to_string(), "12.5Mbps");
+    assert_eq!(Rate::from_mbps(1200.0).to_string(), "1.2Gbps");
+    assert_eq!(Rate::from_mbps(0.8).to_string(), "800kbps");
+    assert_eq!(Rate::from_bytes_per_sec(12.0).to_string(), "96bps");
+    assert_eq!(Rate::from_mbps(1.0 / 3.0).to_string(), "333.333kbps");
+    assert_eq!(format!("{:.1}", Rate::from_mbps(1.0 / 3.0)), "333.3kbps");
+    assert_eq!(Rate::default().to_string(), "0bps");
+
+    let displayed = Rate::from_mbps(123.456);
+    assert_eq!(displayed.to_string().parse(), Ok(displayed));
+}
+
+#[test]
+fn rates_serialize_as_displayed() {
+    assert_eq!(
+        serde_json::to_string(&Rate::from_mbps(50.0)).unwrap(),
+        "\"50Mbps\""
+    );
+}
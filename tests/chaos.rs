@@ -0,0 +1,166 @@
+mod common;
+
+use ccp_bbr::chaos::{Faults, FaultyIpc};
+use ccp_bbr::sim::{Link, Simulation};
+use ccp_bbr::{Action, BbrConfig, BbrCore, BbrMode, Measurement};
+use portus::ipc::Ipc;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A transport that receives what it sent.
+#[derive(Clone, Default)]
+struct Loopback {
+    queue: Arc<Mutex<VecDeque<Vec<u8>>>>,
+}
+
+impl Ipc for Loopback {
+    type Addr = ();
+
+    fn name() -> String {
+        String::from("loopback")
+    }
+
+    fn send(&self, msg: &[u8], _to: &()) -> portus::Result<()> {
+        self.queue.lock().unwrap().push_back(msg.to_vec());
+        Ok(())
+    }
+
+    fn recv(&self, msg: &mut [u8]) -> portus::Result<(usize, ())> {
+        let queued = self.queue.lock().unwrap().pop_front();
+        let queued = queued.ok_or_else(|| portus::Error(String::from("empty")))?;
+        msg[..queued.len()].copy_from_slice(&queued);
+        Ok((queued.len(), ()))
+    }
+
+    fn close(&mut self) -> portus::Result<()> {
+        Ok(())
+    }
+}
+
+fn faulty(faults: Faults) -> (FaultyIpc<Loopback>, Loopback) {
+    let loopback = Loopback::default();
+    (FaultyIpc::new(loopback.clone(), faults, 7), loopback)
+}
+
+fn recv(ipc: &impl Ipc<Addr = ()>) -> portus::Result<Vec<u8>> {
+    let mut buf = [0u8; 16];
+    let (len, ()) = ipc.recv(&mut buf)?;
+    Ok(buf[..len].to_vec())
+}
+
+#[test]
+fn faulty_ipc_injects_each_fault() {
+    let (ipc, _) = faulty(Faults::default());
+    ipc.send(b"a", &()).unwrap();
+    assert_eq!(recv(&ipc).unwrap(), b"a");
+
+    let (ipc, _) = faulty(Faults {
+        error: 1.0,
+        ..Default::default()
+    });
+    assert!(ipc.send(b"a", &()).is_err());
+
+    let (ipc, loopback) = faulty(Faults {
+        drop: 1.0,
+        ..Default::default()
+    });
+    ipc.send(b"a", &()).unwrap();
+    assert!(loopback.queue.lock().unwrap().is_empty());
+
+    let (ipc, loopback) = faulty(Faults {
+        duplicate: 1.0,
+        ..Default::default()
+    });
+    loopback.send(b"a", &()).unwrap();
+    assert_eq!(recv(&ipc).unwrap(), b"a");
+    assert_eq!(recv(&ipc).unwrap(), b"a");
+    assert!(recv(&ipc).is_err());
+
+    let (ipc, loopback) = faulty(Faults {
+        delay: 1.0,
+        ..Default::default()
+    });
+    loopback.send(b"a", &()).unwrap();
+    loopback.send(b"b", &()).unwrap();
+    assert_eq!(recv(&ipc).unwrap(), b"b");
+    assert_eq!(recv(&ipc).unwrap(), b"a");
+}
+
+#[test]
+fn failed_install_reinstalls_the_program_on_the_next_report() {
+    let cfg = BbrConfig::default();
+    let info = common::info(1);
+    let now = Instant::now();
+    let mut core = BbrCore::new(&cfg, &info, now);
+    core.program_installed(1);
+    assert_eq!(core.take_reinstall(), None);
+
+    let m = Measurement {
+        program_uid: 1,
+        minrtt_us: 10_000,
+        rate_outgoing: 1_000_000.0,
+        rate_incoming: 1_000_000.0,
+        ..Default::default()
+    };
+    // STARTUP raises Cwnd and Rate
+    let fields = match core
+        .on_measurement(now + Duration::from_millis(10), m)
+        .as_slice()
+    {
+        [Action::Update(fields)] => fields.clone(),
+        actions => panic!("unexpected actions {:?}", actions),
+    };
+    core.install_failed();
+
+    let reinstall = core.take_reinstall().unwrap();
+    assert_eq!(
+        reinstall,
+        vec![Action::SetProgram {
+            program: "init_program",
+            fields,
+        }]
+    );
+    assert_eq!(core.take_reinstall(), None);
+}
+
+// 100 Mbit/s, 20 ms, one BDP of buffer
+fn link() -> Link {
+    Link {
+        rate: 12_500_000.0,
+        buffer: 250_000.0,
+        base_rtt: Duration::from_millis(20),
+    }
+}
+
+#[test]
+fn flows_survive_faults_and_reconverge() {
+    for seed in 1..=4 {
+        let mut sim = Simulation::new(link()).with_faults(Faults::uniform(0.05), seed);
+        sim.add_flow(&BbrConfig::default());
+        sim.add_flow(&BbrConfig::default());
+        sim.run_for(Duration::from_secs(20));
+
+        sim.set_faults(Faults::default());
+        sim.run_for(Duration::from_secs(10));
+        let before: f64 = sim.flows().iter().map(|f| f.delivered_bytes()).sum();
+        sim.run_for(Duration::from_secs(5));
+        let after: f64 = sim.flows().iter().map(|f| f.delivered_bytes()).sum();
+
+        let rate = (after - before) / 5.0;
+        assert!(
+            rate > 0.8 * link().rate,
+            "seed {}: throughput {}",
+            seed,
+            rate
+        );
+        for flow in sim.flows() {
+            assert!(
+                matches!(flow.core().mode(), BbrMode::ProbeBw | BbrMode::ProbeRtt),
+                "seed {}: {:?}",
+                seed,
+                flow.core().mode()
+            );
+        }
+    }
+}
@@ -0,0 +1,21 @@
+//! Fixtures shared by the integration tests.
+
+// each test crate uses only some of them
+#![allow(dead_code)]
+
+use portus::DatapathInfo;
+
+pub const MSS: u32 = 1460;
+
+/// A flow from 10.0.0.1:40000 to 10.0.0.2:5201 with an initial window of ten packets.
+pub fn info(sock_id: u32) -> DatapathInfo {
+    DatapathInfo {
+        sock_id,
+        init_cwnd: 10 * MSS,
+        mss: MSS,
+        src_ip: 0x0a00_0001,
+        src_port: 40000,
+        dst_ip: 0x0a00_0002,
+        dst_port: 5201,
+    }
+}
@@ -0,0 +1,246 @@
+mod common;
+
+use ccp_bbr::bandwidth::Rate;
+use ccp_bbr::control::{Command, Control};
+use ccp_bbr::datapath::DatapathKind;
+use ccp_bbr::stats::StatsSink;
+use ccp_bbr::trace::{Recorder, TraceEvent};
+use ccp_bbr::transport::Transport;
+use ccp_bbr::{BbrConfig, BbrCore};
+use common::info;
+use std::io::{BufRead, BufReader, Write};
+use std::net::UdpSocket;
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[test]
+fn commands_parse() {
+    assert_eq!("pause 3".parse(), Ok(Command::Pause(3)));
+    assert_eq!("  resume   7 ".parse(), Ok(Command::Resume(7)));
+    assert!("pause".parse::<Command>().is_err());
+    assert!("pause three".parse::<Command>().is_err());
+    assert!("pause 3 4".parse::<Command>().is_err());
+    assert!("stop 3".parse::<Command>().is_err());
+    assert!("".parse::<Command>().is_err());
+
+    assert_eq!(
+        "limit 3 20Mbps".parse(),
+        Ok(Command::Limit(3, Rate::from_mbps(20.0)))
+    );
+    assert_eq!(
+        "limit 3 20".parse(),
+        Ok(Command::Limit(3, Rate::from_mbps(20.0)))
+    );
+    assert_eq!("unlimit 3".parse(), Ok(Command::Unlimit(3)));
+    assert!("limit 3".parse::<Command>().is_err());
+    assert!("limit 3 fast".parse::<Command>().is_err());
+    assert!("limit 3 20Mbps 4".parse::<Command>().is_err());
+    assert!("unlimit".parse::<Command>().is_err());
+    assert_eq!("weight 3 2.5".parse(), Ok(Command::Weight(3, 2.5)));
+    assert!("weight 3".parse::<Command>().is_err());
+    assert!("weight 3 0".parse::<Command>().is_err());
+    assert!("weight 3 heavy".parse::<Command>().is_err());
+    assert_eq!(" config ".parse(), Ok(Command::Config));
+    assert!("config 3".parse::<Command>().is_err());
+
+    assert_eq!(
+        "probe 3 1760000000.25".parse(),
+        Ok(Command::Probe(3, Duration::from_millis(1_760_000_000_250)))
+    );
+    assert!("probe 3".parse::<Command>().is_err());
+    assert!("probe 3 soon".parse::<Command>().is_err());
+    assert!("probe 3 -1".parse::<Command>().is_err());
+
+    assert_eq!(
+        "marker  start load phase 2 ".parse(),
+        Ok(Command::Marker(String::from("start load phase 2")))
+    );
+    assert!("marker".parse::<Command>().is_err());
+    assert!("marker  ".parse::<Command>().is_err());
+}
+
+#[test]
+fn limit_applies_until_cleared() {
+    let cfg = BbrConfig::default();
+    let unix = Transport::new(&cfg, "unix", DatapathKind::Quic);
+    let _flow = BbrCore::new(&unix.cfg, &info(1), Instant::now());
+    let control = Control::new(vec![unix.clone()]);
+
+    assert_eq!(control.reply("limit 1 5Mbps"), "ok");
+    assert_eq!(unix.cfg.flow_limits.get(1), Some(Rate::from_mbps(5.0)));
+    assert_eq!(control.reply("unlimit 1"), "ok");
+    assert_eq!(unix.cfg.flow_limits.get(1), None);
+    assert_eq!(
+        control.reply("limit 2 5Mbps"),
+        "error: no flow with socket id 2"
+    );
+}
+
+#[test]
+fn weight_changes_the_flows_share() {
+    let cfg = BbrConfig::default();
+    let unix = Transport::new(&cfg, "unix", DatapathKind::Quic);
+    let _flows = [1, 2].map(|sock_id| BbrCore::new(&unix.cfg, &info(sock_id), Instant::now()));
+    let control = Control::new(vec![unix.clone()]);
+    assert_eq!(unix.cfg.weights.share(1), 1.0);
+
+    assert_eq!(control.reply("weight 2 4"), "ok");
+    assert_eq!(unix.cfg.weights.weight(2), Some(4.0));
+    assert_eq!(unix.cfg.weights.share(1), 0.25);
+    assert_eq!(unix.cfg.weights.share(2), 1.0);
+    assert_eq!(control.reply("weight 2 1"), "ok");
+    assert_eq!(unix.cfg.weights.share(1), 1.0);
+
+    assert_eq!(
+        control.reply("weight 3 2"),
+        "error: no flow with socket id 3"
+    );
+    assert!(control
+        .reply("weight 1 -2")
+        .starts_with("error: weight must be a positive number"));
+}
+
+#[test]
+fn pause_applies_to_the_transports_with_the_flow() {
+    let cfg = BbrConfig::default();
+    let unix = Transport::new(&cfg, "unix", DatapathKind::Quic);
+    let netlink = Transport::new(&cfg, "netlink", DatapathKind::Kernel);
+    let _flow = BbrCore::new(&unix.cfg, &info(1), Instant::now());
+    let control = Control::new(vec![unix.clone(), netlink.clone()]);
+
+    assert_eq!(control.reply("pause 1"), "ok");
+    assert!(unix.cfg.paused.is_paused(1));
+    assert!(!netlink.cfg.paused.is_paused(1));
+    assert_eq!(control.reply("resume 1"), "ok");
+    assert!(!unix.cfg.paused.is_paused(1));
+
+    assert_eq!(control.reply("pause 2"), "error: no flow with socket id 2");
+    assert!(control.reply("pause").starts_with("error: "));
+}
+
+#[test]
+fn probe_schedules_the_flows_next_up_pulse() {
+    let cfg = BbrConfig {
+        schedule_probes: true,
+        ..Default::default()
+    };
+    let unix = Transport::new(&cfg, "unix", DatapathKind::Quic);
+    let netlink = Transport::new(&cfg, "netlink", DatapathKind::Kernel);
+    let _flow = BbrCore::new(&unix.cfg, &info(1), Instant::now());
+    let control = Control::new(vec![unix.clone(), netlink.clone()]);
+    let unix_time = |from_now: f64| {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        format!("{:.3}", now.as_secs_f64() + from_now)
+    };
+
+    let before = Instant::now();
+    assert_eq!(control.reply(&format!("probe 1 {}", unix_time(2.0))), "ok");
+    let at = unix.cfg.probe_schedule.get(1).unwrap();
+    assert!(at > before + Duration::from_secs(1) && at < before + Duration::from_secs(3));
+    assert!(netlink.cfg.probe_schedule.is_empty());
+
+    assert!(control
+        .reply(&format!("probe 1 {}", unix_time(-2.0)))
+        .ends_with("has passed"));
+    assert!(control
+        .reply(&format!("probe 1 {}", unix_time(120.0)))
+        .ends_with("is more than 60s ahead"));
+    assert_eq!(
+        control.reply(&format!("probe 2 {}", unix_time(2.0))),
+        "error: no flow with socket id 2"
+    );
+
+    // flows whose programs cannot end their cruise phase early refuse it
+    let unscheduled = Transport::new(&BbrConfig::default(), "unix", DatapathKind::Quic);
+    let _flow = BbrCore::new(&unscheduled.cfg, &info(1), Instant::now());
+    assert_eq!(
+        Control::new(vec![unscheduled]).reply(&format!("probe 1 {}", unix_time(2.0))),
+        "error: scheduling probes needs --schedule_probes"
+    );
+}
+
+#[test]
+fn config_replies_with_each_transports_configuration() {
+    let cfg = BbrConfig {
+        max_rate: Some(Rate::from_mbps(100.0)),
+        ..Default::default()
+    };
+    let unix = Transport::new(&cfg, "unix", DatapathKind::Quic);
+    let netlink = Transport::new(&cfg, "netlink", DatapathKind::Kernel);
+    let _flow = BbrCore::new(&unix.cfg, &info(1), Instant::now());
+    let control = Control::new(vec![unix.clone(), netlink.clone()]);
+    assert_eq!(control.reply("pause 1"), "ok");
+    assert_eq!(control.reply("limit 1 5Mbps"), "ok");
+
+    let reply: serde_json::Value = serde_json::from_str(&control.reply("config")).unwrap();
+    let unix = &reply["transports"][0];
+    assert_eq!(unix["ipc"], "unix");
+    assert_eq!(unix["config"]["datapath"], "quic");
+    assert_eq!(unix["config"]["max_rate"], "100Mbps");
+    assert_eq!(unix["config"]["probe_rtt_interval"], "10s");
+    assert_eq!(unix["paused"], serde_json::json!([1]));
+    assert_eq!(unix["rate_limits"], serde_json::json!({ "1": "5Mbps" }));
+    let netlink = &reply["transports"][1];
+    assert_eq!(netlink["config"]["datapath"], "kernel");
+    assert_eq!(netlink["paused"], serde_json::json!([]));
+}
+
+#[test]
+fn control_socket_replies_to_each_line() {
+    let path = std::env::temp_dir().join(format!("ccp_bbr_control_{}.sock", std::process::id()));
+    let cfg = BbrConfig::default();
+    let transport = Transport::new(&cfg, "unix", DatapathKind::Quic);
+    let _flow = BbrCore::new(&transport.cfg, &info(1), Instant::now());
+    Control::new(vec![transport.clone()]).listen(&path).unwrap();
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    stream.write_all(b"pause 1\n\nresume 9\n").unwrap();
+    let mut replies = BufReader::new(stream.try_clone().unwrap()).lines();
+    assert_eq!(replies.next().unwrap().unwrap(), "ok");
+    assert_eq!(
+        replies.next().unwrap().unwrap(),
+        "error: no flow with socket id 9"
+    );
+    assert!(transport.cfg.paused.is_paused(1));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn markers_reach_the_trace_and_the_stats_sink() {
+    let path = std::env::temp_dir().join(format!("ccp_bbr_marker_{}.jsonl", std::process::id()));
+    let cfg = BbrConfig {
+        recorder: Some(Recorder::create(&path).unwrap()),
+        ..Default::default()
+    };
+    let unix = Transport::new(&cfg, "unix", DatapathKind::Quic);
+    let influx = UdpSocket::bind("127.0.0.1:0").unwrap();
+    influx
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let sink = StatsSink::Influx(influx.local_addr().unwrap().to_string());
+    let control = Control::new(vec![unix]).with_stats_sink(sink);
+
+    let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    assert_eq!(control.reply("marker start \"phase\" 2"), "ok");
+    let mut buf = [0; 1500];
+    let len = influx.recv(&mut buf).unwrap();
+    let line = std::str::from_utf8(&buf[..len]).unwrap();
+    let (field, at) = line.rsplit_once(' ').unwrap();
+    assert_eq!(field, r#"bbr_marker name="start \"phase\" 2""#);
+    assert!(at.parse::<u128>().unwrap() >= before.as_nanos());
+
+    drop((control, cfg));
+    let trace = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    match serde_json::from_str(trace.trim()).unwrap() {
+        TraceEvent::Marker {
+            since_epoch_us,
+            name,
+            ..
+        } => {
+            assert_eq!(name, r#"start "phase" 2"#);
+            assert!(u128::from(since_epoch_us) >= before.as_micros());
+        }
+        event => panic!("{:?}", event),
+    }
+}
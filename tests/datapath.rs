@@ -0,0 +1,798 @@
+mod common;
+
+use ccp_bbr::datapath::DatapathKind;
+use ccp_bbr::error::BbrError;
+use ccp_bbr::loss::{LossAccounting, REORDER_WINDOW_ACKS};
+use ccp_bbr::rate::RateEstimator;
+use ccp_bbr::registers::count_registers;
+use ccp_bbr::{
+    Action, BbrConfig, BbrCore, BbrMode, Measurement, ReportValues, MAX_BW_WINDOW_ROUNDS,
+    MIN_RATE_SAMPLE_ACKS, NO_RTT_SAMPLE, PROBE_RTT_CWND_PACKETS, PROBE_RTT_DURATION_US,
+    PROGRAM_VERSION, STARTUP_FULL_BW_ROUNDS,
+};
+use portus::ipc::{unix::Socket, Blocking};
+use portus::{CongAlg, DatapathInfo};
+use std::time::Instant;
+
+fn programs(datapath: DatapathKind) -> Vec<String> {
+    let cfg = BbrConfig {
+        datapath,
+        ..Default::default()
+    };
+    <BbrConfig as CongAlg<Socket<Blocking>>>::datapath_programs(&cfg)
+        .into_values()
+        .collect()
+}
+
+#[test]
+fn kernel_programs_sample_losses() {
+    let programs = programs(DatapathKind::Kernel);
+    assert!(programs.iter().any(|p| p.contains("Ack.lost_pkts_sample")));
+    // next to the packets acked, for the loss rate
+    for p in programs.iter().filter(|p| p.contains("Report.loss")) {
+        assert!(p.contains("Ack.packets_acked"));
+    }
+}
+
+#[test]
+fn kernel_programs_sample_the_mss() {
+    let cfg = BbrConfig {
+        datapath: DatapathKind::Kernel,
+        ..Default::default()
+    };
+    for (name, program) in cfg.programs() {
+        if name == "probe_rtt" {
+            continue;
+        }
+        assert!(program.contains("(volatile mss 0)"), "{}", name);
+        assert!(
+            program.contains(
+                "(:= Report.mss (max Report.mss (/ Ack.bytes_acked (max Ack.packets_acked 1))))"
+            ),
+            "{}",
+            name
+        );
+    }
+    for program in programs(DatapathKind::Quic) {
+        assert!(!program.contains("Report.mss"));
+    }
+
+    let fields = |field: &str| match field {
+        "Report.version" => Some(u64::from(PROGRAM_VERSION)),
+        "Report.mss" => Some(1200),
+        _ => Some(0),
+    };
+    let m = Measurement::from_report_fields(BbrMode::ProbeBw, 3, fields).unwrap();
+    assert_eq!(m.mss, 1200);
+}
+
+#[test]
+fn quic_programs_do_not_need_loss_samples() {
+    let programs = programs(DatapathKind::Quic);
+    assert_eq!(programs.len(), 5);
+    for p in &programs {
+        assert!(!p.contains("Ack.lost_pkts_sample"));
+        assert!(!p.contains("Ack.packets_acked"));
+    }
+}
+
+#[test]
+fn programs_count_inflight_in_bytes() {
+    for datapath in [DatapathKind::Kernel, DatapathKind::Quic] {
+        let programs = programs(datapath);
+        for p in &programs {
+            assert!(!p.contains("Flow.packets_in_flight"));
+        }
+        assert!(programs.iter().any(|p| p.contains("Flow.bytes_in_flight")));
+    }
+}
+
+#[test]
+fn reports_without_an_rtt_sample_are_told_apart() {
+    let fields = |minrtt: u64| {
+        move |field: &str| match field {
+            "Report.minrtt" => Some(minrtt),
+            "Report.version" => Some(u64::from(PROGRAM_VERSION)),
+            _ => Some(0),
+        }
+    };
+    for minrtt in [u64::MAX, u64::from(u32::MAX) + 5] {
+        let m = Measurement::from_report_fields(BbrMode::ProbeBw, 1, fields(minrtt)).unwrap();
+        assert_eq!(m.minrtt_us, NO_RTT_SAMPLE);
+        assert!(!m.has_rtt_sample());
+    }
+    let m = Measurement::from_report_fields(BbrMode::ProbeRtt, 1, fields(10_000)).unwrap();
+    assert_eq!(m.minrtt_us, 10_000);
+    assert!(m.has_rtt_sample());
+}
+
+#[test]
+fn probe_rtt_targets_inflight_in_bytes() {
+    // a jumbo-frame MSS
+    let mss = 9000;
+    let info = DatapathInfo {
+        init_cwnd: 10 * mss,
+        mss,
+        ..common::info(1)
+    };
+    let report = |uid| Measurement {
+        program_uid: uid,
+        minrtt_us: 10_000,
+        rate_outgoing: 12_500_000.0,
+        rate_incoming: 12_500_000.0,
+        ..Default::default()
+    };
+
+    for datapath in [DatapathKind::Kernel, DatapathKind::Quic] {
+        let cfg = BbrConfig {
+            datapath,
+            ..Default::default()
+        };
+        let mut now = Instant::now();
+        let mut core = BbrCore::new(&cfg, &info, now);
+        core.program_installed(1);
+        for _ in 0..=STARTUP_FULL_BW_ROUNDS {
+            core.on_measurement(now, report(1));
+        }
+        core.program_installed(2);
+        core.on_measurement(now, report(2));
+        core.program_installed(3);
+
+        now += cfg.probe_rtt_interval * 2;
+        let actions = core.on_measurement(now, report(3));
+        assert_eq!(
+            actions,
+            vec![
+                Action::SetProgram {
+                    program: "probe_rtt",
+                    fields: vec![
+                        ("targetInflight", u64::from(PROBE_RTT_CWND_PACKETS * mss)),
+                        ("probeRttUs", u64::from(PROBE_RTT_DURATION_US)),
+                    ],
+                },
+                Action::Update(vec![("Cwnd", u64::from(PROBE_RTT_CWND_PACKETS * mss))]),
+            ]
+        );
+    }
+}
+
+#[test]
+fn ipc_selects_datapath() {
+    assert_eq!(DatapathKind::for_ipc("unix"), DatapathKind::Quic);
+    assert_eq!(DatapathKind::for_ipc("netlink"), DatapathKind::Kernel);
+    assert_eq!(DatapathKind::for_ipc("char"), DatapathKind::Kernel);
+    assert!("tcp".parse::<DatapathKind>().is_err());
+}
+
+#[test]
+fn unpaced_probe_bw_does_not_set_rate() {
+    let cfg = BbrConfig {
+        pacing: false,
+        ..Default::default()
+    };
+    let programs = <BbrConfig as CongAlg<Socket<Blocking>>>::datapath_programs(&cfg);
+    let probe_bw = &programs["probe_bw"];
+    assert!(!probe_bw.contains("(:= Rate"));
+    assert!(probe_bw.contains("(:= Cwnd fiveFourthsCwnd)"));
+}
+
+#[test]
+fn ramped_probe_bw_steps_up_in_two_halves() {
+    let cfg = BbrConfig {
+        probe_bw_ramp: true,
+        ..Default::default()
+    };
+    let programs = <BbrConfig as CongAlg<Socket<Blocking>>>::datapath_programs(&cfg);
+    let probe_bw = &programs["probe_bw"];
+    assert!(probe_bw.contains("(pulseState 3)"));
+    assert!(probe_bw.contains("(:= Rate nineEighthsRate)"));
+    assert!(probe_bw.contains("(> Micros (/ Report.minrtt 2))"));
+}
+
+#[test]
+fn tracked_cwnd_follows_the_current_pulse_on_every_ack() {
+    let cfg = BbrConfig {
+        track_cwnd: true,
+        ..Default::default()
+    };
+    let probe_bw = &cfg.programs()["probe_bw"];
+    assert!(probe_bw.contains(
+        "(when true
+                    (:= Cwnd cwndCap)
+                    (fallthrough)
+                )"
+    ));
+
+    let cfg = BbrConfig {
+        track_cwnd: true,
+        pacing: false,
+        probe_bw_ramp: true,
+        ..Default::default()
+    };
+    let probe_bw = &cfg.programs()["probe_bw"];
+    for (state, cwnd) in [
+        (0, "fiveFourthsCwnd"),
+        (1, "threeFourthsCwnd"),
+        (2, "bdpCwnd"),
+        (3, "nineEighthsCwnd"),
+    ] {
+        assert!(probe_bw.contains(&format!(
+            "(when (== pulseState {state})
+                    (:= Cwnd {cwnd})
+                    (fallthrough)
+                )"
+        )));
+    }
+
+    assert!(!BbrConfig::default().programs()["probe_bw"].contains("(when (== pulseState"));
+}
+
+#[test]
+fn fixed_pulses_do_not_depend_on_min_rtt() {
+    let cfg = BbrConfig {
+        pulse_length: Some(std::time::Duration::from_millis(10)),
+        ..Default::default()
+    };
+    let programs = <BbrConfig as CongAlg<Socket<Blocking>>>::datapath_programs(&cfg);
+    let probe_bw = &programs["probe_bw"];
+    assert!(probe_bw.contains("(pulseUs 0)"));
+    assert!(probe_bw.contains("(> Micros (* pulseUs 8))"));
+    assert!(!probe_bw.contains("(> Micros Report.minrtt)"));
+}
+
+#[test]
+fn shifted_pulses_echo_update_nonces() {
+    let cfg = BbrConfig {
+        pulse_shift: true,
+        probe_bw_ramp: true,
+        ..Default::default()
+    };
+    let programs = <BbrConfig as CongAlg<Socket<Blocking>>>::datapath_programs(&cfg);
+    let probe_bw = &programs["probe_bw"];
+    assert!(probe_bw.contains("(:= Report.nonce updateNonce)"));
+    assert!(probe_bw.contains("(:= Report.nonceAgeUs (- (max Micros nonceSeenUs) nonceSeenUs))"));
+    assert!(probe_bw.contains("(> Micros (+ (/ Report.minrtt 2) pulseShiftUs))"));
+    assert!(probe_bw.contains("(> Micros (+ Report.minrtt pulseShiftUs))"));
+    assert!(probe_bw.contains("(> Micros (+ (* Report.minrtt 2) pulseShiftUs))"));
+    // the cycle keeps its length
+    assert!(probe_bw.contains("(> Micros (* Report.minrtt 8))"));
+
+    let programs = BbrConfig::default().programs();
+    assert!(!programs["probe_bw"].contains("updateNonce"));
+}
+
+#[test]
+fn delivered_estimator_counts_acked_bytes() {
+    let cfg = BbrConfig {
+        rate_estimator: RateEstimator::Delivered,
+        ..Default::default()
+    };
+    let programs = <BbrConfig as CongAlg<Socket<Blocking>>>::datapath_programs(&cfg);
+    for name in ["init_program", "drain", "probe_bw"] {
+        let program = &programs[name];
+        assert!(program.contains("Ack.bytes_acked"), "{}", name);
+        assert!(!program.contains("Flow.rate_outgoing"), "{}", name);
+        assert!(!program.contains("Flow.rate_incoming"), "{}", name);
+    }
+}
+
+#[test]
+fn reports_carry_what_to_recompute_the_rate_from() {
+    for rate_estimator in [
+        RateEstimator::Flow,
+        RateEstimator::Delivered,
+        RateEstimator::Auto,
+    ] {
+        let cfg = BbrConfig {
+            rate_estimator,
+            ..Default::default()
+        };
+        for (name, program) in cfg.programs() {
+            if name == "probe_rtt" {
+                continue;
+            }
+            assert!(
+                program.contains(
+                    "(:= Report.deliveredTotal (+ Report.deliveredTotal Ack.bytes_acked))"
+                ),
+                "{} {:?}",
+                name,
+                rate_estimator
+            );
+            assert!(
+                program.contains("(:= Report.spanUs (- Micros deliveryStart))"),
+                "{} {:?}",
+                name,
+                rate_estimator
+            );
+        }
+    }
+
+    let report = |program_uid, delivered_total: u64, span_us: u64| {
+        Measurement::from_report_fields(BbrMode::ProbeBw, program_uid, |field| match field {
+            "Report.version" => Some(u64::from(PROGRAM_VERSION)),
+            "Report.deliveredTotal" => Some(delivered_total),
+            "Report.spanUs" => Some(span_us),
+            _ => Some(0),
+        })
+        .unwrap()
+    };
+    let first = report(1, 25_000, 20_000);
+    assert_eq!(first.recomputed_rate(None), Some(1_250_000.0));
+    let second = report(1, 40_000, 10_000);
+    assert_eq!(second.recomputed_rate(Some(&first)), Some(1_500_000.0));
+    // a new program counts from its install
+    let reinstalled = report(2, 10_000, 10_000);
+    assert_eq!(
+        reinstalled.recomputed_rate(Some(&second)),
+        Some(1_000_000.0)
+    );
+    assert_eq!(report(2, 0, 0).recomputed_rate(Some(&reinstalled)), None);
+}
+
+#[test]
+fn reports_carry_smoothed_rtt() {
+    let cfg = BbrConfig::default();
+    let programs = <BbrConfig as CongAlg<Socket<Blocking>>>::datapath_programs(&cfg);
+    for name in ["init_program", "drain", "probe_bw"] {
+        let program = &programs[name];
+        assert!(program.contains("(srtt 0)"), "{}", name);
+        assert!(program.contains("(:= Report.rttVar"), "{}", name);
+    }
+}
+
+#[test]
+fn probe_bw_reports_peak_inflight() {
+    let cfg = BbrConfig::default();
+    let programs = <BbrConfig as CongAlg<Socket<Blocking>>>::datapath_programs(&cfg);
+    assert!(programs["probe_bw"]
+        .contains("(:= Report.inflight (max Report.inflight Flow.bytes_in_flight))"));
+}
+
+#[test]
+fn kernel_programs_report_reordering_and_timeouts() {
+    for p in programs(DatapathKind::Kernel)
+        .iter()
+        .filter(|p| p.contains("(:= Report.loss"))
+    {
+        assert!(p.contains("Ack.packets_misordered"));
+        assert!(p.contains("Flow.was_timeout"));
+    }
+    for p in &programs(DatapathKind::Quic) {
+        assert!(!p.contains("Ack.packets_misordered"));
+        assert!(!p.contains("Flow.was_timeout"));
+    }
+}
+
+#[test]
+fn loss_accounting_decides_when_losses_reach_the_report() {
+    let programs = |loss_accounting| {
+        BbrConfig {
+            loss_accounting,
+            ..Default::default()
+        }
+        .programs()
+    };
+
+    let raw = programs(LossAccounting::Raw);
+    assert!(raw["probe_bw"].contains("(:= Report.loss (+ Report.loss Ack.lost_pkts_sample))"));
+    assert!(!raw["probe_bw"].contains("(- (max Report.loss Ack.packets_misordered)"));
+
+    let net = programs(LossAccounting::Net);
+    assert!(net["probe_bw"].contains(
+        "(:= Report.loss (- (max Report.loss Ack.packets_misordered) Ack.packets_misordered))"
+    ));
+
+    // losses wait in a register until enough acks, or a timeout, confirm them
+    let windowed = programs(LossAccounting::Windowed);
+    for name in ["init_program", "drain", "aimd", "probe_bw"] {
+        let p = &windowed[name];
+        assert!(
+            !p.contains("(+ Report.loss Ack.lost_pkts_sample)"),
+            "{}",
+            name
+        );
+        assert!(p.contains("(:= suspectLoss (+ suspectLoss Ack.lost_pkts_sample))"));
+        assert!(p.contains(&format!("(> suspectAcks {})", REORDER_WINDOW_ACKS)));
+        assert!(p.contains("(:= Report.loss (+ Report.loss suspectLoss))"));
+    }
+    // before aimd decides whether to cut
+    let aimd = &windowed["aimd"];
+    assert!(
+        aimd.find("(:= Report.loss (+ Report.loss suspectLoss))")
+            .unwrap()
+            < aimd
+                .find("(when (&& (> Report.loss 0) (== cut 0))")
+                .unwrap()
+    );
+}
+
+#[test]
+fn printed_programs_are_the_installed_ones() {
+    let cfg = BbrConfig {
+        probe_bw_ramp: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        cfg.programs(),
+        <BbrConfig as CongAlg<Socket<Blocking>>>::datapath_programs(&cfg)
+    );
+}
+
+#[test]
+fn auto_estimator_reports_both_rates() {
+    let cfg = BbrConfig::default();
+    for program in cfg
+        .programs()
+        .values()
+        .filter(|p| p.contains("Report.rateOut"))
+    {
+        assert!(program.contains("Flow.rate_incoming"));
+        assert!(program.contains("(:= Report.deliveryRate"));
+    }
+}
+
+#[test]
+fn init_program_paces_until_its_first_report() {
+    let cfg = BbrConfig::default();
+    let init = &cfg.programs()["init_program"];
+    assert!(init.contains("(/ (* Cwnd pacingGain) Flow.rtt_sample_us)"));
+    assert!(init.contains("(:= Rate initRate)"));
+    // the report block turns the ramp off
+    let report_block = init.find("(when (> Micros Report.minrtt)").unwrap();
+    assert!(init[report_block..].contains("(:= pacingGain 0)"));
+}
+
+#[test]
+fn aimd_fallback_needs_no_rate() {
+    for datapath in [DatapathKind::Kernel, DatapathKind::Quic] {
+        let cfg = BbrConfig {
+            datapath,
+            ..Default::default()
+        };
+        let aimd = &cfg.programs()["aimd"];
+        let mut words = aimd.split(|c: char| !c.is_ascii_alphanumeric());
+        assert!(!words.any(|word| word == "Rate"), "{}", aimd);
+        assert!(aimd.contains("(:= Cwnd"));
+    }
+}
+
+#[test]
+fn program_parameters_are_defined_by_the_programs() {
+    for cfg in [
+        BbrConfig::default(),
+        BbrConfig {
+            pacing: false,
+            probe_bw_ramp: true,
+            pulse_length: Some(std::time::Duration::from_millis(10)),
+            pulse_shift: true,
+            high_rtt_threshold: Some(std::time::Duration::from_millis(300)),
+            schedule_probes: true,
+            ..Default::default()
+        },
+    ] {
+        let programs = cfg.programs();
+        let parameters = cfg.program_parameters();
+        assert_eq!(parameters.len(), programs.len());
+        for (name, registers) in parameters {
+            for reg in registers {
+                assert!(programs[name].contains(&format!("({} 0)", reg)), "{}", reg);
+            }
+        }
+    }
+}
+
+#[test]
+fn probe_bw_filters_bandwidth_over_rounds() {
+    let cfg = BbrConfig::default();
+    let programs = <BbrConfig as CongAlg<Socket<Blocking>>>::datapath_programs(&cfg);
+    let probe_bw = &programs["probe_bw"];
+    assert!(probe_bw.contains("(:= roundDelivered (+ roundDelivered Ack.bytes_acked))"));
+    assert!(probe_bw.contains("(:= bw9 bw8)"));
+    assert!(!probe_bw.contains("bw10"));
+    assert!(probe_bw.contains("(:= Report.maxRate (max (max"));
+
+    let cfg = BbrConfig {
+        bw_window: 3,
+        ..Default::default()
+    };
+    let programs = <BbrConfig as CongAlg<Socket<Blocking>>>::datapath_programs(&cfg);
+    let probe_bw = &programs["probe_bw"];
+    assert!(probe_bw.contains("(:= bw2 bw1)"));
+    assert!(!probe_bw.contains("bw3"));
+    assert!(probe_bw.contains("(:= Report.maxRate (max (max bw0 bw1) bw2))"));
+}
+
+#[test]
+fn high_rtt_programs_take_the_cycle_and_filter_from_registers() {
+    let cfg = BbrConfig {
+        bw_window: 3,
+        high_rtt_threshold: Some(std::time::Duration::from_millis(300)),
+        ..Default::default()
+    };
+    let programs = <BbrConfig as CongAlg<Socket<Blocking>>>::datapath_programs(&cfg);
+    let probe_bw = &programs["probe_bw"];
+    assert!(probe_bw.contains("(cycleRounds 0)"));
+    assert!(probe_bw.contains("(> Micros (* Report.minrtt cycleRounds))"));
+    assert!(!probe_bw.contains("(* Report.minrtt 8)"));
+    // the ring is twice as long, and its older half only counts with the long filter
+    assert!(probe_bw.contains("(:= bw5 bw4)"));
+    assert!(!probe_bw.contains("bw6"));
+    assert!(probe_bw.contains(
+        "(:= Report.maxRate (max (max (max bw0 bw1) bw2) (* (max (max bw3 bw4) bw5) bwLongFilter)))"
+    ));
+
+    // but never longer than the longest window
+    let cfg = BbrConfig {
+        bw_window: MAX_BW_WINDOW_ROUNDS,
+        ..cfg
+    };
+    let programs = cfg.programs();
+    assert!(!programs["probe_bw"].contains("bwLongFilter)"));
+    assert!(!programs["probe_bw"].contains(&format!("bw{}", MAX_BW_WINDOW_ROUNDS)));
+}
+
+#[test]
+fn phase_ends_are_substituted_into_probe_bw() {
+    let cfg = BbrConfig {
+        down_phase_end: 3,
+        cruise_phase_end: 12,
+        ..Default::default()
+    };
+    let probe_bw = &cfg.programs()["probe_bw"];
+    assert!(probe_bw.contains("(when (&& (> Micros Report.minrtt) (== pulseState 0))"));
+    assert!(probe_bw.contains("(when (&& (> Micros (* Report.minrtt 3)) (== pulseState 1))"));
+    assert!(probe_bw.contains("(when (&& (> Micros (* Report.minrtt 12)) (== pulseState 2))"));
+
+    let shifted = BbrConfig {
+        pulse_shift: true,
+        pulse_length: Some(std::time::Duration::from_millis(5)),
+        ..cfg
+    };
+    let probe_bw = &shifted.programs()["probe_bw"];
+    assert!(probe_bw.contains("(> Micros (+ (* pulseUs 3) pulseShiftUs))"));
+    assert!(probe_bw.contains("(> Micros (* pulseUs 12))"));
+
+    let default = &BbrConfig::default().programs()["probe_bw"];
+    assert!(default.contains("(> Micros (* Report.minrtt 2))"));
+    assert!(default.contains("(> Micros (* Report.minrtt 8))"));
+}
+
+#[test]
+fn scheduled_probes_end_the_cruise_phase_at_probe_at_us() {
+    let cfg = BbrConfig {
+        schedule_probes: true,
+        ..Default::default()
+    };
+    let probe_bw = &cfg.programs()["probe_bw"];
+    let cycle_over = "(|| (&& (== probeAtUs 0) (> Micros (* Report.minrtt 8))) (&& (> probeAtUs 0) (> Micros probeAtUs)))";
+    assert!(probe_bw.contains("(probeAtUs 0)"));
+    assert!(probe_bw.contains(&format!("(when (&& {cycle_over} (== pulseState 2))")));
+    assert!(probe_bw.contains(&format!("(&& {cycle_over} (== pulseState 2))))")));
+    assert!(probe_bw.contains("(:= probeAtUs 0)"));
+
+    let unscheduled = &BbrConfig::default().programs()["probe_bw"];
+    assert!(!unscheduled.contains("probeAtUs"));
+}
+
+#[test]
+fn register_usage_counts_the_defined_registers() {
+    assert_eq!(
+        count_registers("(def (Report (volatile acked 0) (minrtt +infinity)) (state 0)) (when true (:= state 1))"),
+        3
+    );
+    assert_eq!(count_registers("(when true (report))"), 0);
+
+    let usage = BbrConfig::default().register_usage();
+    assert_eq!(usage["probe_rtt"], 5);
+    assert_eq!(usage["probe_bw"], 41);
+    assert_eq!(usage.values().max(), Some(&usage["probe_bw"]));
+}
+
+#[test]
+fn register_limit_turns_features_off_until_the_programs_fit() {
+    let full = BbrConfig {
+        datapath: DatapathKind::Kernel,
+        pulse_shift: true,
+        probe_bw_ramp: true,
+        high_rtt_threshold: Some(std::time::Duration::from_millis(300)),
+        ..Default::default()
+    };
+    assert_eq!(full.register_usage()["probe_bw"], 59);
+
+    let mut cfg = full.clone();
+    assert_eq!(
+        cfg.fit_register_limit(&[DatapathKind::Kernel]).unwrap(),
+        Vec::<String>::new()
+    );
+    assert!(cfg.pulse_shift);
+
+    let mut cfg = BbrConfig {
+        register_limit: Some(41),
+        ..full.clone()
+    };
+    assert_eq!(
+        cfg.fit_register_limit(&[DatapathKind::Kernel]).unwrap(),
+        [
+            "--pulse_shift off",
+            "--ramp_probe_bw off",
+            "--high_rtt_threshold off"
+        ]
+    );
+    assert_eq!(cfg.rate_estimator, RateEstimator::Auto);
+    assert_eq!(cfg.register_usage()["probe_bw"], 41);
+
+    // past the features, the estimator and the bandwidth filter shrink
+    let mut cfg = BbrConfig {
+        register_limit: Some(35),
+        ..full.clone()
+    };
+    let traded = cfg.fit_register_limit(&[DatapathKind::Kernel]).unwrap();
+    assert_eq!(
+        &traded[3..],
+        ["--rate_estimator delivered", "--bw_window 5"]
+    );
+    assert_eq!(cfg.bw_window, 5);
+    assert_eq!(cfg.register_usage()["probe_bw"], 35);
+
+    // a program the trades cannot shrink enough is refused
+    let mut cfg = BbrConfig {
+        register_limit: Some(20),
+        ..full
+    };
+    match cfg.fit_register_limit(&[DatapathKind::Kernel]) {
+        Err(BbrError::Config(msg)) => assert_eq!(
+            msg,
+            "probe_bw needs 31 registers, more than the register limit of 20"
+        ),
+        other => panic!("expected a config error, got {:?}", other),
+    }
+}
+
+#[test]
+fn probe_bw_rounds_wait_for_enough_acks() {
+    let cfg = BbrConfig::default();
+    let programs = <BbrConfig as CongAlg<Socket<Blocking>>>::datapath_programs(&cfg);
+    let probe_bw = &programs["probe_bw"];
+    assert!(probe_bw.contains("(:= Report.acks (+ Report.acks 1))"));
+    assert!(probe_bw.contains(&format!("(&& (> roundAcks {})", MIN_RATE_SAMPLE_ACKS - 1)));
+}
+
+#[test]
+fn reports_missing_a_field_name_it() {
+    let fields = |field: &str| match field {
+        "Report.srtt" => None,
+        "Report.version" => Some(u64::from(PROGRAM_VERSION)),
+        _ => Some(0),
+    };
+    let err = Measurement::from_report_fields(BbrMode::ProbeBw, 3, fields).unwrap_err();
+    assert_eq!(
+        err,
+        BbrError::MissingField {
+            program_uid: 3,
+            field: "Report.srtt"
+        }
+    );
+    assert!(!err.is_fatal());
+    // PROBE_RTT's program only reports the RTT
+    assert!(Measurement::from_report_fields(BbrMode::ProbeRtt, 3, fields).is_ok());
+}
+
+#[test]
+fn every_program_reports_its_version() {
+    for datapath in [DatapathKind::Kernel, DatapathKind::Quic] {
+        for p in programs(datapath) {
+            assert!(
+                p.contains(&format!("(version {})", PROGRAM_VERSION)),
+                "{}",
+                p
+            );
+        }
+    }
+}
+
+#[test]
+fn reports_from_other_program_versions_are_refused() {
+    let fields = |version: Option<u64>| {
+        move |field: &str| match field {
+            "Report.version" => version,
+            _ => Some(0),
+        }
+    };
+    for mode in [BbrMode::ProbeBw, BbrMode::ProbeRtt] {
+        for version in [None, Some(0), Some(u64::from(PROGRAM_VERSION) + 1)] {
+            let err = Measurement::from_report_fields(mode, 3, fields(version)).unwrap_err();
+            assert_eq!(
+                err,
+                BbrError::ProgramVersion {
+                    program_uid: 3,
+                    found: version
+                }
+            );
+            assert!(!err.is_fatal());
+        }
+    }
+    let err = Measurement::from_report_fields(BbrMode::ProbeBw, 3, fields(None)).unwrap_err();
+    assert!(err.to_string().contains("an older agent"), "{}", err);
+    let newer = Some(u64::from(PROGRAM_VERSION) + 1);
+    let err = Measurement::from_report_fields(BbrMode::ProbeBw, 3, fields(newer)).unwrap_err();
+    assert!(err.to_string().contains("a newer agent"), "{}", err);
+}
+
+#[test]
+fn outdated_programs_are_reinstalled() {
+    let info = common::info(1);
+    let cfg = BbrConfig::default();
+    let mut core = BbrCore::new(&cfg, &info, Instant::now());
+    core.start();
+    core.program_installed(1);
+    assert!(core.take_reinstall().is_none());
+
+    core.program_outdated();
+    match &core.take_reinstall().unwrap()[..] {
+        [Action::SetProgram {
+            program: "init_program",
+            ..
+        }] => {}
+        actions => panic!("{:?}", actions),
+    }
+    let snapshot = core.snapshot();
+    assert_eq!(snapshot.outdated_reports, 1);
+    // not a failed update
+    assert_eq!(snapshot.failed_updates, 0);
+    assert_eq!(snapshot.reinstalls, 1);
+}
+
+#[test]
+fn reports_from_other_transports_are_handled() {
+    let info = common::info(1);
+    let cfg = BbrConfig::default();
+    let now = Instant::now();
+    let mut core = BbrCore::new(&cfg, &info, now);
+    core.start();
+    core.program_installed(1);
+    let report = |program_uid, version| ReportValues {
+        program_uid,
+        fields: [
+            ("Report.version", version),
+            ("Report.minrtt", 10_000),
+            ("Report.loss", 0),
+            ("Report.misordered", 0),
+            ("Report.timeout", 0),
+            ("Report.rateOut", 1_250_000),
+            ("Report.rateIn", 1_250_000),
+            ("Report.pulseState", 0),
+            ("Report.rwndLimited", 0),
+            ("Report.srtt", 10_000),
+            ("Report.rttVar", 0),
+        ]
+        .into_iter()
+        .map(|(field, value)| (String::from(field), value))
+        .collect(),
+        received: None,
+    };
+
+    core.handle_report_values(now, report(1, u64::from(PROGRAM_VERSION)));
+    assert_eq!(core.min_rtt_us(), 10_000);
+    assert_eq!(core.snapshot().reports, 1);
+
+    assert!(core
+        .handle_report_values(now, report(2, u64::from(PROGRAM_VERSION)))
+        .is_empty());
+    assert_eq!(core.snapshot().stale_reports, 1);
+
+    // a report from another version of the program has the flow reinstall it first
+    assert!(core
+        .handle_report_values(now, report(1, u64::from(PROGRAM_VERSION) + 1))
+        .is_empty());
+    match &core.handle_report_values(now, report(1, u64::from(PROGRAM_VERSION)))[..] {
+        [Action::SetProgram {
+            program: "init_program",
+            ..
+        }] => {}
+        actions => panic!("{:?}", actions),
+    }
+    let snapshot = core.snapshot();
+    assert_eq!(snapshot.outdated_reports, 1);
+    assert_eq!(snapshot.reports, 1);
+}
@@ -0,0 +1,367 @@
+//! End-to-end tests of the agent against the ccp-kernel datapath and an emulated bottleneck.
+//!
+//! Each test connects a sender and a receiver network namespace with a veth pair, shapes the
+//! sender's egress with netem, and runs an iperf3 transfer using the `ccp` TCP congestion
+//! control while the `bbr` binary serves the flows over netlink.
+//!
+//! They need root, iproute2, iperf3, and a loaded ccp-kernel module, so they are ignored by
+//! default:
+//!
+//! ```text
+//! sudo -E cargo test --test emulated -- --ignored --test-threads=1
+//! ```
+//!
+//! The link can be changed with `BBR_EMU_RATE_MBIT`, `BBR_EMU_DELAY_MS`, and
+//! `BBR_EMU_DURATION_S`, and `BBR_EMU_IPC=char` runs the agent over the character device
+//! instead of netlink.
+
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+const SENDER_NS: &str = "bbr-emu-snd";
+const RECEIVER_NS: &str = "bbr-emu-rcv";
+const RECEIVER_ADDR: &str = "10.77.0.2";
+
+fn env_or(var: &str, default: u64) -> u64 {
+    std::env::var(var)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn run(args: &[&str]) -> String {
+    let out = Command::new(args[0])
+        .args(&args[1..])
+        .output()
+        .unwrap_or_else(|e| panic!("running {:?}: {}", args, e));
+    assert!(
+        out.status.success(),
+        "{:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8_lossy(&out.stdout).into_owned()
+}
+
+struct Link {
+    rate_mbit: u64,
+    delay_ms: u64,
+}
+
+/// The namespaces and the shaped veth pair between them; torn down on drop.
+struct Topology;
+
+impl Topology {
+    fn new(link: &Link) -> Self {
+        // clean up after an earlier aborted run
+        let _ = Command::new("ip")
+            .args(["netns", "del", SENDER_NS])
+            .status();
+        let _ = Command::new("ip")
+            .args(["netns", "del", RECEIVER_NS])
+            .status();
+
+        run(&["ip", "netns", "add", SENDER_NS]);
+        run(&["ip", "netns", "add", RECEIVER_NS]);
+        let topo = Topology;
+        run(&[
+            "ip",
+            "link",
+            "add",
+            "veth-snd",
+            "netns",
+            SENDER_NS,
+            "type",
+            "veth",
+            "peer",
+            "name",
+            "veth-rcv",
+            "netns",
+            RECEIVER_NS,
+        ]);
+        for (ns, dev, addr) in [
+            (SENDER_NS, "veth-snd", "10.77.0.1/24"),
+            (RECEIVER_NS, "veth-rcv", "10.77.0.2/24"),
+        ] {
+            run(&["ip", "-n", ns, "addr", "add", addr, "dev", dev]);
+            run(&["ip", "-n", ns, "link", "set", dev, "up"]);
+            run(&["ip", "-n", ns, "link", "set", "lo", "up"]);
+        }
+
+        // one bandwidth-delay product of buffering at the bottleneck
+        let bdp_pkts = (link.rate_mbit * 1_000_000 / 8 * link.delay_ms / 1000 / 1500).max(10);
+        run(&[
+            "ip",
+            "netns",
+            "exec",
+            SENDER_NS,
+            "tc",
+            "qdisc",
+            "add",
+            "dev",
+            "veth-snd",
+            "root",
+            "netem",
+            "rate",
+            &format!("{}mbit", link.rate_mbit),
+            "delay",
+            &format!("{}ms", link.delay_ms),
+            "limit",
+            &bdp_pkts.to_string(),
+        ]);
+        topo
+    }
+}
+
+impl Drop for Topology {
+    fn drop(&mut self) {
+        let _ = Command::new("ip")
+            .args(["netns", "del", SENDER_NS])
+            .status();
+        let _ = Command::new("ip")
+            .args(["netns", "del", RECEIVER_NS])
+            .status();
+    }
+}
+
+/// A background process, killed on drop.
+struct Background(Child);
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn spawn(args: &[&str]) -> Background {
+    Background(
+        Command::new(args[0])
+            .args(&args[1..])
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap_or_else(|e| panic!("spawning {:?}: {}", args, e)),
+    )
+}
+
+struct Transfer {
+    /// Bytes per second.
+    throughput: f64,
+    mean_rtt: Duration,
+}
+
+fn iperf(duration_s: u64) -> Transfer {
+    let out = run(&[
+        "ip",
+        "netns",
+        "exec",
+        SENDER_NS,
+        "iperf3",
+        "-c",
+        RECEIVER_ADDR,
+        "-C",
+        "ccp",
+        "-t",
+        &duration_s.to_string(),
+        "-J",
+    ]);
+    let result: serde_json::Value = serde_json::from_str(&out).expect("iperf3 JSON output");
+    let bits_per_second = result["end"]["sum_received"]["bits_per_second"]
+        .as_f64()
+        .expect("iperf3 receiver throughput");
+    let mean_rtt_us = result["end"]["streams"][0]["sender"]["mean_rtt"]
+        .as_u64()
+        .expect("iperf3 sender mean_rtt");
+    Transfer {
+        throughput: bits_per_second / 8.0,
+        mean_rtt: Duration::from_micros(mean_rtt_us),
+    }
+}
+
+#[test]
+#[ignore]
+fn single_flow_fills_emulated_link() {
+    let link = Link {
+        rate_mbit: env_or("BBR_EMU_RATE_MBIT", 48),
+        delay_ms: env_or("BBR_EMU_DELAY_MS", 20),
+    };
+    let duration_s = env_or("BBR_EMU_DURATION_S", 30);
+
+    let _topo = Topology::new(&link);
+    let ipc = std::env::var("BBR_EMU_IPC").unwrap_or_else(|_| String::from("netlink"));
+    let _agent = spawn(&[env!("CARGO_BIN_EXE_bbr"), "--ipc", &ipc]);
+    let _server = spawn(&[
+        "ip",
+        "netns",
+        "exec",
+        RECEIVER_NS,
+        "iperf3",
+        "-s",
+        "-B",
+        RECEIVER_ADDR,
+    ]);
+    // let the agent register with the datapath and the server bind
+    std::thread::sleep(Duration::from_secs(1));
+
+    let transfer = iperf(duration_s);
+    let link_rate = link.rate_mbit as f64 * 1e6 / 8.0;
+    assert!(
+        transfer.throughput > 0.85 * link_rate,
+        "throughput {:.2} Mbit/s on a {} Mbit/s link",
+        transfer.throughput * 8.0 / 1e6,
+        link.rate_mbit
+    );
+
+    // the cwnd cap of two BDPs, with one BDP in flight, allows at most one BDP of queue
+    let base_rtt = Duration::from_millis(link.delay_ms);
+    let queueing = transfer.mean_rtt.saturating_sub(base_rtt);
+    assert!(
+        queueing <= base_rtt,
+        "mean queueing delay {:?} over a {:?} base RTT",
+        queueing,
+        base_rtt
+    );
+}
+
+// a rate as `ss` prints it, e.g. `47.6Mbps`, in bytes per second
+fn ss_rate(rate: &str) -> Option<f64> {
+    let (value, scale) = match rate.strip_suffix("bps")? {
+        r if r.ends_with('G') => (&r[..r.len() - 1], 1e9),
+        r if r.ends_with('M') => (&r[..r.len() - 1], 1e6),
+        r if r.ends_with('K') => (&r[..r.len() - 1], 1e3),
+        r => (r, 1.0),
+    };
+    Some(value.parse::<f64>().ok()? * scale / 8.0)
+}
+
+/// The state of the sender's busiest connection, from `ss -tin`.
+#[derive(Default)]
+struct SsSample {
+    bytes_acked: u64,
+    delivery_rate: f64,
+    bbr_bw: f64,
+    bbr_min_rtt_ms: f64,
+    cwnd_bytes: u64,
+    pacing_rate: f64,
+}
+
+fn ss_sample() -> Option<SsSample> {
+    let out = run(&[
+        "ip",
+        "netns",
+        "exec",
+        SENDER_NS,
+        "ss",
+        "-tin",
+        "dst",
+        RECEIVER_ADDR,
+    ]);
+    let mut busiest: Option<SsSample> = None;
+    // each connection's details are on the line after its addresses
+    for line in out.lines().filter(|l| l.contains("bytes_acked")) {
+        let mut sample = SsSample::default();
+        let mut mss = 0;
+        let mut cwnd = 0;
+        let mut tokens = line.split_whitespace();
+        while let Some(token) = tokens.next() {
+            match token.split_once(':') {
+                Some(("bytes_acked", v)) => sample.bytes_acked = v.parse().unwrap_or(0),
+                Some(("mss", v)) => mss = v.parse().unwrap_or(0),
+                Some(("cwnd", v)) => cwnd = v.parse().unwrap_or(0),
+                Some(("bbr", v)) => {
+                    let fields = v.trim_matches(|c| c == '(' || c == ')');
+                    for field in fields.split(',') {
+                        match field.split_once(':') {
+                            Some(("bw", bw)) => sample.bbr_bw = ss_rate(bw).unwrap_or(0.0),
+                            Some(("mrtt", rtt)) => {
+                                sample.bbr_min_rtt_ms = rtt.parse().unwrap_or(0.0)
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => match token {
+                    "pacing_rate" => {
+                        sample.pacing_rate = tokens.next().and_then(ss_rate).unwrap_or(0.0)
+                    }
+                    "delivery_rate" => {
+                        sample.delivery_rate = tokens.next().and_then(ss_rate).unwrap_or(0.0)
+                    }
+                    _ => {}
+                },
+            }
+        }
+        sample.cwnd_bytes = mss * cwnd;
+        if busiest
+            .as_ref()
+            .is_none_or(|b| sample.bytes_acked > b.bytes_acked)
+        {
+            busiest = Some(sample);
+        }
+    }
+    busiest
+}
+
+/// Records the kernel's own BBR on the emulated link, for comparing the bands in
+/// `tests/golden` with by hand.
+///
+/// Writes one CSV line per 100 ms to `BBR_GOLDEN_CAPTURE` (by default `kernel_bbr.csv`), in
+/// bytes per second, bytes, and milliseconds. It needs the `tcp_bbr` module rather than
+/// ccp-kernel, and does not run the agent.
+#[test]
+#[ignore]
+fn capture_kernel_bbr() {
+    let link = Link {
+        rate_mbit: env_or("BBR_EMU_RATE_MBIT", 50),
+        delay_ms: env_or("BBR_EMU_DELAY_MS", 40),
+    };
+    let duration_s = env_or("BBR_EMU_DURATION_S", 25);
+    let path =
+        std::env::var("BBR_GOLDEN_CAPTURE").unwrap_or_else(|_| String::from("kernel_bbr.csv"));
+
+    let _topo = Topology::new(&link);
+    let _server = spawn(&[
+        "ip",
+        "netns",
+        "exec",
+        RECEIVER_NS,
+        "iperf3",
+        "-s",
+        "-B",
+        RECEIVER_ADDR,
+    ]);
+    std::thread::sleep(Duration::from_secs(1));
+    let client = spawn(&[
+        "ip",
+        "netns",
+        "exec",
+        SENDER_NS,
+        "iperf3",
+        "-c",
+        RECEIVER_ADDR,
+        "-C",
+        "bbr",
+        "-t",
+        &duration_s.to_string(),
+    ]);
+
+    let start = std::time::Instant::now();
+    let mut csv = String::from("t_s,delivery_rate,bbr_bw,bbr_min_rtt_ms,cwnd_bytes,pacing_rate\n");
+    while start.elapsed() < Duration::from_secs(duration_s) {
+        if let Some(s) = ss_sample() {
+            csv += &format!(
+                "{:.1},{:.0},{:.0},{:.2},{},{:.0}\n",
+                start.elapsed().as_secs_f64(),
+                s.delivery_rate,
+                s.bbr_bw,
+                s.bbr_min_rtt_ms,
+                s.cwnd_bytes,
+                s.pacing_rate
+            );
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    drop(client);
+
+    std::fs::write(&path, csv).unwrap_or_else(|e| panic!("writing {}: {}", path, e));
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use ccp_bbr::ffi::*;
+use ccp_bbr::{BbrConfig, DRAIN_GAIN, STARTUP_FULL_BW_ROUNDS};
+use common::MSS;
+use std::ffi::{CStr, CString};
+
+fn info() -> CcpBbrFlowInfo {
+    let info = common::info(1);
+    CcpBbrFlowInfo {
+        sock_id: info.sock_id,
+        init_cwnd: info.init_cwnd,
+        mss: info.mss,
+        src_ip: info.src_ip,
+        src_port: info.src_port,
+        dst_ip: info.dst_ip,
+        dst_port: info.dst_port,
+    }
+}
+
+/// A pending action, as its program name (if it installs one) and fields.
+type PendingAction = (Option<String>, Vec<(String, u64)>);
+
+unsafe fn actions(flow: *const CcpBbrFlow) -> Vec<PendingAction> {
+    let mut n = 0;
+    let actions = ccp_bbr_flow_actions(flow, &mut n);
+    std::slice::from_raw_parts(actions, n)
+        .iter()
+        .map(|action| {
+            let program = (action.kind == CCP_BBR_SET_PROGRAM)
+                .then(|| CStr::from_ptr(action.program).to_str().unwrap().to_owned());
+            let fields = std::slice::from_raw_parts(action.fields, action.num_fields)
+                .iter()
+                .map(|f| (CStr::from_ptr(f.name).to_str().unwrap().to_owned(), f.value))
+                .collect();
+            (program, fields)
+        })
+        .collect()
+}
+
+#[test]
+fn c_flow_follows_the_core_through_startup() {
+    unsafe {
+        let cfg = ccp_bbr_config_new();
+        let flow = ccp_bbr_flow_new(cfg, &info(), 1_000);
+        // flows do not need their configuration afterwards
+        ccp_bbr_config_free(cfg);
+
+        assert_eq!(
+            actions(flow),
+            vec![(
+                Some(String::from("init_program")),
+                vec![
+                    (String::from("Cwnd"), u64::from(10 * MSS)),
+                    (
+                        String::from("pacingGain"),
+                        (ccp_bbr::STARTUP_GAIN * 1e6) as u64
+                    )
+                ]
+            )]
+        );
+        ccp_bbr_flow_program_installed(flow, 1);
+
+        let report = CcpBbrReport {
+            program_uid: 1,
+            minrtt_us: 10_000,
+            rate_outgoing: 1_250_000.0,
+            rate_incoming: 1_250_000.0,
+            ..Default::default()
+        };
+        let mut now_us = 1_000;
+        for _ in 0..STARTUP_FULL_BW_ROUNDS {
+            now_us += 10_000;
+            ccp_bbr_flow_on_report(flow, now_us, &report);
+        }
+        assert_eq!(ccp_bbr_flow_pipe_full(flow), 0);
+        now_us += 10_000;
+        assert_eq!(ccp_bbr_flow_on_report(flow, now_us, &report), 2);
+        assert_eq!(ccp_bbr_flow_mode(flow), 1);
+        assert_eq!(ccp_bbr_flow_pipe_full(flow), 1);
+        assert_eq!(ccp_bbr_flow_estimated_bdp_bytes(flow), 12_500);
+        assert_eq!(
+            actions(flow),
+            vec![
+                (
+                    Some(String::from("drain")),
+                    vec![(String::from("bdpTarget"), 12_500)]
+                ),
+                (
+                    None,
+                    vec![(String::from("Rate"), (1_250_000.0 * DRAIN_GAIN) as u64)]
+                ),
+            ]
+        );
+
+        // a report from the replaced program changes nothing
+        ccp_bbr_flow_program_installed(flow, 2);
+        assert_eq!(ccp_bbr_flow_on_report(flow, now_us + 10_000, &report), 0);
+        ccp_bbr_flow_free(flow);
+    }
+}
+
+#[test]
+fn c_configurations_take_the_binary_flags() {
+    unsafe {
+        let flags: Vec<CString> = ["--rate_estimator", "delivered"]
+            .iter()
+            .map(|f| CString::new(*f).unwrap())
+            .collect();
+        let argv: Vec<_> = flags.iter().map(|f| f.as_ptr()).collect();
+        let cfg = ccp_bbr_config_from_args(argv.len() as _, argv.as_ptr());
+        assert!(!cfg.is_null());
+
+        // the binary's --datapath auto and --ipc unix select the quic programs
+        let mut expected = BbrConfig {
+            rate_estimator: ccp_bbr::rate::RateEstimator::Delivered,
+            datapath: ccp_bbr::datapath::DatapathKind::Quic,
+            ..Default::default()
+        }
+        .programs();
+        assert_eq!(ccp_bbr_num_programs(cfg), expected.len());
+        for i in 0..ccp_bbr_num_programs(cfg) {
+            let mut name = std::ptr::null();
+            let program = ccp_bbr_program(cfg, i, &mut name);
+            let name = CStr::from_ptr(name).to_str().unwrap();
+            let program = CStr::from_ptr(program).to_str().unwrap();
+            assert_eq!(expected.remove(name).as_deref(), Some(program));
+        }
+        assert!(ccp_bbr_program(cfg, expected.len() + 10, std::ptr::null_mut()).is_null());
+        ccp_bbr_config_free(cfg);
+
+        let bad = CString::new("--loss_mode=random").unwrap();
+        assert!(ccp_bbr_config_from_args(1, [bad.as_ptr()].as_ptr()).is_null());
+    }
+}
@@ -0,0 +1,136 @@
+//! Invariants of the control plane over arbitrary report sequences.
+
+mod common;
+
+use ccp_bbr::bandwidth::Rate;
+use ccp_bbr::{
+    Action, BbrConfig, BbrCore, BbrMode, Measurement, NO_RTT_SAMPLE, PROBE_RTT_CWND_PACKETS,
+};
+use common::{info, MSS};
+use proptest::collection::vec;
+use proptest::prelude::*;
+use std::time::{Duration, Instant};
+
+/// One report, and how long after the one before it arrives.
+#[derive(Clone, Debug)]
+struct Step {
+    after: Duration,
+    m: Measurement,
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    (
+        1u64..50,
+        prop_oneof![9 => 2_000u32..100_000, 1 => Just(NO_RTT_SAMPLE)],
+        prop_oneof![1 => Just(0.0), 9 => 0.0..20e6f64],
+        prop_oneof![1 => Just(0.0), 1 => 0.0..20e6f64],
+        0u32..8,
+        0u32..200,
+        0u32..4,
+        0u32..500_000,
+        any::<bool>(),
+        0u32..64,
+    )
+        .prop_map(
+            |(
+                after_ms,
+                minrtt_us,
+                rate,
+                max_rate,
+                loss,
+                acked,
+                pulse_state,
+                inflight_bytes,
+                receiver_limited,
+                acks,
+            )| Step {
+                after: Duration::from_millis(after_ms),
+                m: Measurement {
+                    minrtt_us,
+                    loss,
+                    acked,
+                    rate_outgoing: rate,
+                    rate_incoming: rate,
+                    delivery_rate: rate,
+                    pulse_state,
+                    receiver_limited,
+                    inflight_bytes,
+                    srtt_us: if minrtt_us == NO_RTT_SAMPLE {
+                        0
+                    } else {
+                        minrtt_us
+                    },
+                    max_rate,
+                    acks,
+                    ..Default::default()
+                },
+            },
+        )
+}
+
+fn fields(action: &Action) -> &[(&'static str, u64)] {
+    match action {
+        Action::SetProgram { fields, .. } | Action::Update(fields) => fields,
+    }
+}
+
+// the modes a flow may switch to from each mode
+fn allowed(from: BbrMode, to: BbrMode) -> bool {
+    from == to
+        || matches!(
+            (from, to),
+            (BbrMode::Startup, BbrMode::Drain)
+                | (BbrMode::Drain, BbrMode::ProbeBw)
+                | (BbrMode::ProbeBw, BbrMode::ProbeRtt)
+                | (BbrMode::ProbeRtt, BbrMode::ProbeBw)
+        )
+}
+
+proptest! {
+    #[test]
+    fn reports_keep_the_control_plane_invariants(steps in vec(step(), 1..400)) {
+        let max_rate = Rate::from_mbps(40.0);
+        let cfg = BbrConfig {
+            probe_rtt_interval: Duration::from_secs(1),
+            max_rate: Some(max_rate),
+            ..Default::default()
+        };
+        let min_cwnd = u64::from(MSS * PROBE_RTT_CWND_PACKETS);
+        let mut now = Instant::now();
+        let mut core = BbrCore::new(&cfg, &info(1), now);
+        let mut uid = 0;
+        let mut actions = core.start();
+        for step in steps {
+            for action in &actions {
+                if let Action::SetProgram { .. } = action {
+                    uid += 1;
+                    core.program_installed(uid);
+                }
+                for &(reg, val) in fields(action) {
+                    if matches!(reg, "Cwnd" | "cwndCap") {
+                        prop_assert!(val >= min_cwnd, "{} of {} below {}", reg, val, min_cwnd);
+                    }
+                }
+                // every register named for a rate
+                for &(reg, val) in fields(action).iter().filter(|(reg, _)| reg.ends_with("Rate")) {
+                    prop_assert!(val as f64 <= max_rate.bytes_per_sec(), "{} of {}", reg, val);
+                }
+            }
+
+            let (mode, min_rtt_us) = (core.mode(), core.min_rtt_us());
+            now += step.after;
+            actions = core.on_measurement(now, Measurement { program_uid: uid, ..step.m });
+            prop_assert!(allowed(mode, core.mode()), "{:?} to {:?}", mode, core.mode());
+            // min_rtt only rises as PROBE_RTT forgets it
+            prop_assert!(
+                core.min_rtt_us() <= min_rtt_us
+                    || mode == BbrMode::ProbeRtt
+                    || core.mode() == BbrMode::ProbeRtt,
+                "min_rtt rose from {} to {} in {:?}",
+                min_rtt_us,
+                core.min_rtt_us(),
+                mode
+            );
+        }
+    }
+}
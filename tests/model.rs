@@ -0,0 +1,127 @@
+use ccp_bbr::model::{Model, ModelDerived, ProbeBwGains};
+
+// 10 Mbit/s over 20ms, probing with the usual gains
+fn model() -> Model {
+    Model {
+        bottle_rate: 1_250_000.0,
+        share: 1.0,
+        min_rtt_us: 20_000,
+        mss: 1_460,
+        min_rate: 0.0,
+        max_rate: f64::INFINITY,
+        cwnd_bdp_multiplier: 2.0,
+        headroom_us: 0,
+        delay_budget_us: None,
+        max_cwnd: None,
+        gains: ProbeBwGains {
+            down: 0.75,
+            cruise: 1.0,
+            up: 1.25,
+            ramp: 1.125,
+        },
+    }
+}
+
+#[test]
+fn derives_the_pulses_from_the_bdp() {
+    assert_eq!(
+        model().derive(),
+        ModelDerived {
+            down_rate: 937_500,
+            cruise_rate: 1_250_000,
+            up_rate: 1_562_500,
+            ramp_rate: 1_406_250,
+            down_cwnd: 18_750,
+            cruise_cwnd: 25_000,
+            up_cwnd: 31_250,
+            ramp_cwnd: 28_125,
+            cwnd_cap: 50_000,
+        }
+    );
+    assert_eq!(model().probe_rtt_cwnd(), 4 * 1_460);
+}
+
+#[test]
+fn shares_and_caps_bound_every_rate() {
+    let derived = Model {
+        share: 0.5,
+        max_rate: 500_000.0,
+        ..model()
+    }
+    .derive();
+    assert_eq!(derived.down_rate, 468_750);
+    assert_eq!(derived.cruise_rate, 500_000);
+    assert_eq!(derived.up_rate, 500_000);
+    assert_eq!(derived.up_cwnd, 10_000);
+    assert_eq!(derived.cwnd_cap, 20_000);
+
+    // a floor lifts the down pulse, and what PROBE_RTT keeps in flight, to its BDP
+    let floored = Model {
+        share: 0.5,
+        min_rate: 1_000_000.0,
+        ..model()
+    };
+    assert_eq!(floored.derive().down_rate, 1_000_000);
+    assert_eq!(floored.probe_rtt_cwnd(), 20_000);
+    // but not above the cap
+    let capped = Model {
+        max_rate: 500_000.0,
+        ..floored
+    };
+    assert_eq!(capped.derive().down_rate, 500_000);
+}
+
+#[test]
+fn cwnd_cap_takes_headroom_within_the_delay_budget() {
+    let headroom = Model {
+        headroom_us: 3_000,
+        ..model()
+    };
+    assert_eq!(headroom.cwnd_cap(), 53_750);
+    let budget = Model {
+        delay_budget_us: Some(5_000),
+        ..headroom
+    };
+    assert_eq!(budget.cwnd_cap(), 31_250);
+}
+
+#[test]
+fn tiny_bdps_keep_what_probe_rtt_keeps_in_flight() {
+    let derived = Model {
+        bottle_rate: 10_000.0,
+        gains: ProbeBwGains::flat(1.0),
+        ..model()
+    }
+    .derive();
+    assert_eq!(derived.cruise_rate, 10_000);
+    assert_eq!(derived.down_cwnd, 4 * 1_460);
+    assert_eq!(derived.up_cwnd, 4 * 1_460);
+    // nor does the cap, which would otherwise hold less than a packet
+    assert_eq!(derived.cwnd_cap, 4 * 1_460);
+}
+
+#[test]
+fn recovery_keeps_what_is_still_in_flight() {
+    let model = model();
+    // most of the window was lost, so halving it leaves room for what is left
+    assert_eq!(model.recovery_cwnd(40_000, 40_000, 30_000), 20_000);
+    // but with a third lost, half the window would stall the flow
+    assert_eq!(model.recovery_cwnd(30_000, 30_000, 10_000), 21_460);
+    // small windows keep what PROBE_RTT keeps, and unknown inflight only halves
+    assert_eq!(model.recovery_cwnd(6_000, 6_000, 5_000), 4 * 1_460);
+    assert_eq!(model.recovery_cwnd(40_000, 0, 30_000), 20_000);
+}
+
+#[test]
+fn a_loss_burst_limits_every_window() {
+    let derived = Model {
+        max_cwnd: Some(20_000),
+        ..model()
+    }
+    .derive();
+    assert_eq!(derived.down_cwnd, 18_750);
+    assert_eq!(derived.up_cwnd, 20_000);
+    assert_eq!(derived.cwnd_cap, 20_000);
+    // the rates are not
+    assert_eq!(derived.up_rate, 1_562_500);
+}
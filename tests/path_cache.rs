@@ -0,0 +1,107 @@
+use ccp_bbr::path_cache::{PathCache, PathEstimate};
+use ccp_bbr::WallClock;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const DST: u32 = 0x0a00_0102;
+
+fn cache_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("ccp_bbr_{}_{}.json", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn estimate() -> PathEstimate {
+    PathEstimate {
+        bottle_rate: 6_250_000.0,
+        min_rtt_us: 20_000,
+    }
+}
+
+#[test]
+fn saved_estimates_survive_a_restart() {
+    let path = cache_file("restart");
+    let clock = WallClock::now();
+    let cache = PathCache::default();
+    cache.record(DST, estimate(), clock.instant);
+    assert_eq!(cache.save(&path, clock).unwrap(), 1);
+
+    // the next agent starts a minute later, on the same prefix
+    let restarted = PathCache::default();
+    let later = WallClock {
+        instant: clock.instant + Duration::from_secs(60),
+        since_epoch: clock.since_epoch + Duration::from_secs(60),
+    };
+    assert_eq!(restarted.load(&path, later).unwrap(), 1);
+    assert_eq!(restarted.get(DST + 1, later.instant), Some(estimate()));
+    // and the estimate keeps its age
+    let expired = later.instant + Duration::from_secs(241);
+    assert_eq!(restarted.get(DST, expired), None);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn stale_and_missing_caches_load_nothing() {
+    let path = cache_file("stale");
+    let clock = WallClock::now();
+    assert_eq!(PathCache::default().load(&path, clock).unwrap(), 0);
+
+    let cache = PathCache::new(Duration::from_secs(30), 24);
+    cache.record(DST, estimate(), clock.instant);
+    cache.save(&path, clock).unwrap();
+    let later = WallClock {
+        instant: Instant::now(),
+        since_epoch: clock.since_epoch + Duration::from_secs(31),
+    };
+    let restarted = PathCache::new(Duration::from_secs(30), 24);
+    assert_eq!(restarted.load(&path, later).unwrap(), 0);
+    assert_eq!(restarted.get(DST, later.instant), None);
+
+    // a cache keyed by other prefixes is rejected
+    assert!(PathCache::new(Duration::from_secs(30), 16)
+        .load(&path, clock)
+        .is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn estimates_learned_since_startup_win() {
+    let path = cache_file("newer");
+    let clock = WallClock::now();
+    let cache = PathCache::default();
+    cache.record(DST, estimate(), clock.instant);
+    cache.save(&path, clock).unwrap();
+
+    let restarted = PathCache::default();
+    let learned = PathEstimate {
+        bottle_rate: 1_250_000.0,
+        min_rtt_us: 40_000,
+    };
+    restarted.record(DST, learned, clock.instant);
+    assert_eq!(restarted.load(&path, clock).unwrap(), 0);
+    assert_eq!(restarted.get(DST, clock.instant), Some(learned));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn a_full_cache_replaces_its_least_recently_used_paths() {
+    let cache = PathCache::new(Duration::from_secs(300), 24).with_capacity(32);
+    let mut now = Instant::now();
+    cache.record(DST, estimate(), now);
+    for prefix in 1..1_000u32 {
+        now += Duration::from_millis(1);
+        assert_eq!(cache.get(DST, now), Some(estimate()));
+        now += Duration::from_millis(1);
+        cache.record(0x0b00_0000 | prefix << 8, estimate(), now);
+        assert!(cache.len() <= 32, "{}", cache.len());
+    }
+    // the path flows keep looking up stays, as does the one recorded last
+    assert_eq!(cache.get(DST, now), Some(estimate()));
+    assert_eq!(cache.get(0x0b00_0000 | 999 << 8, now), Some(estimate()));
+
+    // and new paths take the place of those that aged out
+    now += Duration::from_secs(301);
+    cache.record(0x0c00_0002, estimate(), now);
+    assert!(cache.len() <= 32);
+    assert_eq!(cache.get(0x0c00_0002, now), Some(estimate()));
+}
@@ -0,0 +1,501 @@
+use ccp_bbr::datapath::DatapathKind;
+use ccp_bbr::loss::{LossAccounting, REORDER_WINDOW_ACKS};
+use ccp_bbr::rate::RateEstimator;
+use ccp_bbr::{BbrConfig, PROBE_RTT_DURATION_US};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+// configurations that between them generate every optional part of the programs
+fn configs() -> Vec<(&'static str, BbrConfig)> {
+    vec![
+        ("default", BbrConfig::default()),
+        (
+            "quic",
+            BbrConfig {
+                datapath: DatapathKind::Quic,
+                ..Default::default()
+            },
+        ),
+        (
+            "unpaced",
+            BbrConfig {
+                pacing: false,
+                probe_bw_ramp: true,
+                track_cwnd: true,
+                ..Default::default()
+            },
+        ),
+        (
+            "everything",
+            BbrConfig {
+                probe_bw_ramp: true,
+                pulse_shift: true,
+                pulse_length: Some(Duration::from_millis(10)),
+                high_rtt_threshold: Some(Duration::from_millis(150)),
+                schedule_probes: true,
+                drain_to_target: false,
+                track_cwnd: true,
+                rate_estimator: RateEstimator::Auto,
+                loss_accounting: LossAccounting::Net,
+                ..Default::default()
+            },
+        ),
+        (
+            "raw",
+            BbrConfig {
+                rate_estimator: RateEstimator::Delivered,
+                loss_accounting: LossAccounting::Raw,
+                ..Default::default()
+            },
+        ),
+    ]
+}
+
+#[test]
+fn every_program_compiles() {
+    for (config, cfg) in configs() {
+        for (name, program) in cfg.programs() {
+            if let Err(err) = portus::lang::compile(program.as_bytes(), &[]) {
+                panic!("{} program of the {} config: {:?}", name, config, err);
+            }
+        }
+    }
+}
+
+#[test]
+fn fully_featured_probe_bw_fits_every_register_limit_it_accepts() {
+    let (_, everything) = configs().remove(3);
+    let everything = BbrConfig {
+        bw_window: 10,
+        ..everything
+    };
+    let datapaths = [DatapathKind::Kernel, DatapathKind::Quic];
+    let most = |cfg: &BbrConfig| {
+        datapaths
+            .iter()
+            .map(|&datapath| {
+                BbrConfig {
+                    datapath,
+                    ..cfg.clone()
+                }
+                .register_usage()["probe_bw"]
+            })
+            .max()
+            .unwrap()
+    };
+    let used = most(&everything);
+
+    let mut accepted = 0;
+    for limit in (1..=used).rev() {
+        let mut cfg = BbrConfig {
+            register_limit: Some(limit),
+            ..everything.clone()
+        };
+        let Ok(traded) = cfg.fit_register_limit(&datapaths) else {
+            break;
+        };
+        accepted += 1;
+        if limit == used {
+            assert!(traded.is_empty(), "{:?}", traded);
+        } else {
+            assert_eq!(traded[0], "--pulse_shift off");
+        }
+        for datapath in datapaths {
+            let cfg = BbrConfig {
+                datapath,
+                ..cfg.clone()
+            };
+            for (name, used) in cfg.register_usage() {
+                assert!(used <= limit, "{} needs {} of {}", name, used, limit);
+            }
+            for (name, program) in cfg.programs() {
+                if let Err(err) = portus::lang::compile(program.as_bytes(), &[]) {
+                    panic!("{} program at limit {}: {:?}", name, limit, err);
+                }
+            }
+        }
+    }
+    // every feature and all but one round of the filter are traded before a limit is refused
+    let smallest = most(&BbrConfig {
+        pulse_shift: false,
+        probe_bw_ramp: false,
+        high_rtt_threshold: None,
+        rate_estimator: RateEstimator::Delivered,
+        bw_window: 1,
+        ..everything
+    });
+    assert_eq!(accepted, used - smallest + 1);
+}
+
+// portus only compiles programs; the datapath runs them. `Fold` runs a program's text the way
+// the datapath does, on one ack at a time: the `when` clauses in order, each true one's body,
+// and on to the next clause only after `(fallthrough)`. A report resets the volatile
+// registers once the ack is handled. Reading a register the program does not define panics.
+
+#[derive(Clone, Debug)]
+enum Sexp {
+    Atom(String),
+    List(Vec<Sexp>),
+}
+
+fn parse(text: &str) -> Vec<Sexp> {
+    let spaced = text.replace('(', " ( ").replace(')', " ) ");
+    let mut stack = vec![vec![]];
+    for token in spaced.split_whitespace() {
+        match token {
+            "(" => stack.push(vec![]),
+            ")" => {
+                let list = stack.pop().unwrap();
+                stack
+                    .last_mut()
+                    .expect("unbalanced )")
+                    .push(Sexp::List(list));
+            }
+            atom => stack
+                .last_mut()
+                .unwrap()
+                .push(Sexp::Atom(String::from(atom))),
+        }
+    }
+    assert_eq!(stack.len(), 1, "unbalanced (");
+    stack.pop().unwrap()
+}
+
+fn atom(sexp: &Sexp) -> &str {
+    match sexp {
+        Sexp::Atom(atom) => atom,
+        Sexp::List(_) => panic!("expected an atom, got {:?}", sexp),
+    }
+}
+
+struct Clause {
+    condition: Sexp,
+    body: Vec<Sexp>,
+}
+
+struct Fold {
+    clauses: Vec<Clause>,
+    registers: HashMap<String, u64>,
+    initial: HashMap<String, u64>,
+    volatile: HashSet<String>,
+    micros_origin: u64,
+    now_us: u64,
+    primitives: HashMap<&'static str, u64>,
+}
+
+// each ack's primitives, as the datapath sets them
+type Ack = [(&'static str, u64)];
+
+impl Fold {
+    // installs `program` at `now_us`, with `fields` substituted for the initial values
+    fn install(program: &str, fields: &[(&str, u64)], now_us: u64) -> Self {
+        let mut fold = Fold {
+            clauses: vec![],
+            registers: HashMap::from([(String::from("Cwnd"), 0), (String::from("Rate"), 0)]),
+            initial: HashMap::new(),
+            volatile: HashSet::new(),
+            micros_origin: now_us,
+            now_us,
+            primitives: HashMap::new(),
+        };
+        for sexp in parse(program) {
+            let Sexp::List(list) = sexp else {
+                panic!("top-level atom");
+            };
+            match atom(&list[0]) {
+                "def" => list[1..].iter().for_each(|def| fold.define(def, "")),
+                "when" => {
+                    let body = list[2..].to_vec();
+                    fold.clauses.push(Clause {
+                        condition: list[1].clone(),
+                        body,
+                    });
+                }
+                other => panic!("unexpected {}", other),
+            }
+        }
+        for &(name, value) in fields {
+            assert!(fold.registers.contains_key(name), "no register {}", name);
+            fold.registers.insert(String::from(name), value);
+        }
+        fold
+    }
+
+    fn define(&mut self, def: &Sexp, prefix: &str) {
+        let Sexp::List(list) = def else {
+            panic!("bad def {:?}", def);
+        };
+        if atom(&list[0]) == "Report" {
+            list[1..]
+                .iter()
+                .for_each(|field| self.define(field, "Report."));
+            return;
+        }
+        let (volatile, entry) = match atom(&list[0]) {
+            "volatile" => (true, &list[1..]),
+            _ => (false, &list[..]),
+        };
+        let name = format!("{}{}", prefix, atom(&entry[0]));
+        let value = self.eval(&entry[1]);
+        if volatile {
+            self.volatile.insert(name.clone());
+        }
+        self.initial.insert(name.clone(), value);
+        self.registers.insert(name, value);
+    }
+
+    fn update(&mut self, name: &str, value: u64) {
+        assert!(self.registers.contains_key(name), "no register {}", name);
+        self.registers.insert(String::from(name), value);
+    }
+
+    fn get(&self, name: &str) -> u64 {
+        self.registers[name]
+    }
+
+    fn eval(&self, sexp: &Sexp) -> u64 {
+        let list = match sexp {
+            Sexp::Atom(atom) => {
+                return match atom.as_str() {
+                    "+infinity" => u64::MAX,
+                    "true" => 1,
+                    "false" => 0,
+                    "Micros" => self.now_us - self.micros_origin,
+                    name if name.starts_with("Ack.") || name.starts_with("Flow.") => {
+                        self.primitives.get(name).copied().unwrap_or(0)
+                    }
+                    name => match name.parse() {
+                        Ok(value) => value,
+                        Err(_) => *self
+                            .registers
+                            .get(name)
+                            .unwrap_or_else(|| panic!("undefined {}", name)),
+                    },
+                }
+            }
+            Sexp::List(list) => list,
+        };
+        let (a, b) = (self.eval(&list[1]), self.eval(&list[2]));
+        match atom(&list[0]) {
+            "+" => a.saturating_add(b),
+            "-" => a.saturating_sub(b),
+            "*" => a.saturating_mul(b),
+            "/" => a.checked_div(b).expect("division by zero"),
+            "max" => a.max(b),
+            "min" => a.min(b),
+            "<" => u64::from(a < b),
+            ">" => u64::from(a > b),
+            "==" => u64::from(a == b),
+            "&&" => u64::from(a != 0 && b != 0),
+            "||" => u64::from(a != 0 || b != 0),
+            op => panic!("unknown operator {}", op),
+        }
+    }
+
+    // runs the program for an ack at `now_us`, and returns the report it sent, if any
+    fn on_ack(&mut self, now_us: u64, ack: &Ack) -> Option<BTreeMap<String, u64>> {
+        self.now_us = now_us;
+        self.primitives = ack.iter().copied().collect();
+        let mut report = false;
+        for i in 0..self.clauses.len() {
+            if self.eval(&self.clauses[i].condition) == 0 {
+                continue;
+            }
+            let mut fallthrough = false;
+            for statement in self.clauses[i].body.clone() {
+                let Sexp::List(list) = statement else {
+                    panic!("bare atom in a when body");
+                };
+                match atom(&list[0]) {
+                    ":=" => {
+                        let value = self.eval(&list[2]);
+                        match atom(&list[1]) {
+                            "Micros" => self.micros_origin = now_us - value,
+                            name => self.update(name, value),
+                        }
+                    }
+                    "report" => report = true,
+                    "fallthrough" => fallthrough = true,
+                    other => panic!("unknown statement {}", other),
+                }
+            }
+            if !fallthrough {
+                break;
+            }
+        }
+        if !report {
+            return None;
+        }
+        let fields = self
+            .registers
+            .iter()
+            .filter_map(|(name, &value)| Some((name.strip_prefix("Report.")?.to_string(), value)))
+            .collect();
+        for name in &self.volatile {
+            self.registers.insert(name.clone(), self.initial[name]);
+        }
+        Some(fields)
+    }
+}
+
+fn program(cfg: &BbrConfig, name: &str) -> String {
+    cfg.programs().remove(name).unwrap()
+}
+
+#[test]
+fn every_program_runs_on_acks() {
+    for (config, cfg) in configs() {
+        for (name, program) in cfg.programs() {
+            let mut fold = Fold::install(&program, &[], 0);
+            // set the registers the agent writes, so that nothing divides by zero
+            for (program_name, registers) in cfg.program_parameters() {
+                if program_name == name {
+                    registers.iter().for_each(|reg| fold.update(reg, 1_000));
+                }
+            }
+            let mut reports = 0;
+            for i in 1..=2_000 {
+                let ack = [
+                    ("Ack.bytes_acked", 1_460),
+                    ("Ack.packets_acked", 1),
+                    ("Flow.rtt_sample_us", 10_000),
+                    // drained now and then, for drain and probe_rtt
+                    (
+                        "Flow.bytes_in_flight",
+                        if i % 100 == 0 { 0 } else { 14_600 },
+                    ),
+                    ("Flow.rate_outgoing", 1_000_000),
+                    ("Flow.rate_incoming", 1_000_000),
+                ];
+                reports += usize::from(fold.on_ack(i * 1_000, &ack).is_some());
+            }
+            assert!(reports > 0, "{} program of the {} config", name, config);
+        }
+    }
+}
+
+#[test]
+fn probe_rtt_waits_for_the_target_then_its_duration_and_an_rtt() {
+    let cfg = BbrConfig::default();
+    let fields = [
+        ("targetInflight", 5_840),
+        ("probeRttUs", u64::from(PROBE_RTT_DURATION_US)),
+    ];
+    let ack = |inflight, rtt_us| {
+        [
+            ("Flow.bytes_in_flight", inflight),
+            ("Flow.rtt_sample_us", rtt_us),
+        ]
+    };
+
+    let mut fold = Fold::install(&program(&cfg, "probe_rtt"), &fields, 0);
+    // above the target, no time counts
+    for ms in 1..=500 {
+        assert!(fold.on_ack(ms * 1_000, &ack(20_000, 10_000)).is_none());
+    }
+    // from when inflight fell to the target, 200ms
+    assert!(fold.on_ack(501_000, &ack(5_840, 10_000)).is_none());
+    for ms in 502..=701 {
+        assert!(
+            fold.on_ack(ms * 1_000, &ack(20_000, 10_000)).is_none(),
+            "{}ms",
+            ms
+        );
+    }
+    let report = fold.on_ack(702_000, &ack(5_000, 9_000)).unwrap();
+    assert_eq!(report["minrtt"], 9_000);
+
+    // and at least an RTT, if that is longer
+    let mut fold = Fold::install(&program(&cfg, "probe_rtt"), &fields, 0);
+    assert!(fold.on_ack(1_000, &ack(5_000, 300_000)).is_none());
+    assert!(fold.on_ack(250_000, &ack(5_000, 300_000)).is_none());
+    assert!(fold.on_ack(301_000, &ack(5_000, 300_000)).is_none());
+    assert!(fold.on_ack(302_000, &ack(5_000, 300_000)).is_some());
+}
+
+#[test]
+fn drain_reports_once_inflight_reaches_the_target() {
+    let cfg = BbrConfig::default();
+    let mut fold = Fold::install(&program(&cfg, "drain"), &[("bdpTarget", 12_500)], 0);
+    let ack = |inflight| {
+        [
+            ("Flow.bytes_in_flight", inflight),
+            ("Flow.rtt_sample_us", 10_000),
+            ("Ack.bytes_acked", 1_460),
+        ]
+    };
+    assert!(fold.on_ack(1_000, &ack(40_000)).is_none());
+    assert!(fold.on_ack(30_000, &ack(12_501)).is_none());
+    let report = fold.on_ack(31_000, &ack(12_500)).unwrap();
+    assert_eq!(report["deliveredTotal"], 3 * 1_460);
+    assert_eq!(report["spanUs"], 31_000);
+}
+
+#[test]
+fn probe_bw_pulses_the_rate_over_the_cycle() {
+    let cfg = BbrConfig::default();
+    let fields = [
+        ("cwndCap", 25_000),
+        ("bottleRate", 1_000_000),
+        ("threeFourthsRate", 750_000),
+        ("fiveFourthsRate", 1_250_000),
+    ];
+    let mut fold = Fold::install(&program(&cfg, "probe_bw"), &fields, 0);
+    let ack = [("Flow.rtt_sample_us", 10_000), ("Ack.bytes_acked", 1_460)];
+
+    // the up pulse lasts a min RTT, the down pulse until two, and the cruise phase until eight
+    let mut reports = vec![];
+    for ms in 1..=100 {
+        if let Some(report) = fold.on_ack(ms * 1_000, &ack) {
+            reports.push((ms, report["pulseState"], fold.get("Rate")));
+        }
+    }
+    assert_eq!(
+        reports[..4],
+        [
+            (11, 0, 750_000),
+            (21, 1, 1_000_000),
+            (81, 2, 1_250_000),
+            (92, 0, 750_000)
+        ]
+    );
+    assert_eq!(fold.get("Cwnd"), 25_000);
+}
+
+#[test]
+fn tracked_cwnd_takes_updates_on_the_next_ack() {
+    let ack = [("Flow.rtt_sample_us", 10_000), ("Ack.bytes_acked", 1_460)];
+    for track_cwnd in [false, true] {
+        let cfg = BbrConfig {
+            track_cwnd,
+            ..Default::default()
+        };
+        let mut fold = Fold::install(&program(&cfg, "probe_bw"), &[("cwndCap", 25_000)], 0);
+        fold.update("Cwnd", 25_000);
+        fold.update("cwndCap", 20_000);
+        fold.on_ack(1_000, &ack);
+        let expected = if track_cwnd { 20_000 } else { 25_000 };
+        assert_eq!(fold.get("Cwnd"), expected);
+    }
+}
+
+#[test]
+fn windowed_losses_wait_for_later_acks() {
+    let cfg = BbrConfig::default();
+    let mut fold = Fold::install(&program(&cfg, "init_program"), &[], 0);
+    let ack = |lost| {
+        [
+            ("Ack.lost_pkts_sample", lost),
+            ("Ack.packets_acked", 1),
+            ("Flow.rtt_sample_us", 100_000),
+        ]
+    };
+    fold.on_ack(1_000, &ack(2));
+    for i in 1..REORDER_WINDOW_ACKS {
+        fold.on_ack(1_000 + u64::from(i) * 1_000, &ack(0));
+        assert_eq!(fold.get("Report.loss"), 0);
+    }
+    // the third ack after the loss confirms it
+    fold.on_ack(10_000, &ack(0));
+    assert_eq!(fold.get("Report.loss"), 2);
+}
@@ -0,0 +1,38 @@
+use ccp_bbr::datapath::DatapathKind;
+use ccp_bbr::qdisc::{parse_qdiscs, unpaced};
+use ccp_bbr::transport::Transport;
+use ccp_bbr::BbrConfig;
+
+const TC: &str = "qdisc noqueue 0: dev lo root refcnt 2 
+qdisc mq 0: dev eth0 root 
+qdisc fq 0: dev eth0 parent :2 limit 10000p flow_limit 100p buckets 1024 
+qdisc fq 0: dev eth0 parent :1 limit 10000p flow_limit 100p buckets 1024 
+qdisc fq_codel 0: dev eth1 root refcnt 2 limit 10240p flows 1024 quantum 1514 
+qdisc pfifo_fast 0: dev eth2 root refcnt 2 bands 3 priomap 1 2 2 2 1 2 0 0 1 1 1 1 1 1 1 1
+qdisc ingress ffff: dev eth2 parent ffff:fff1 ---------------- 
+qdisc noqueue 0: dev docker0 root refcnt 2 
+";
+
+#[test]
+fn interfaces_without_fq_are_unpaced() {
+    let qdiscs = parse_qdiscs(TC);
+    assert_eq!(qdiscs["eth0"], ["mq", "fq", "fq"]);
+    assert_eq!(qdiscs["eth2"], ["pfifo_fast", "ingress"]);
+    // fq_codel schedules, but does not pace; interfaces without a queue have nothing to pace
+    assert_eq!(unpaced(&qdiscs), ["eth1", "eth2"]);
+    assert!(unpaced(&parse_qdiscs("")).is_empty());
+}
+
+#[test]
+fn unpaced_interfaces_are_reported() {
+    let kernel = Transport::new(&BbrConfig::default(), "netlink", DatapathKind::Kernel);
+    let capabilities = &kernel.cfg.capabilities;
+    assert!(kernel.stats().unpaced_interfaces.is_empty());
+    assert!(capabilities.set_unpaced_interfaces(vec![String::from("eth1")]));
+    // only changes are news
+    assert!(!capabilities.set_unpaced_interfaces(vec![String::from("eth1")]));
+    assert_eq!(kernel.stats().unpaced_interfaces, ["eth1"]);
+
+    assert!(capabilities.set_unpaced_interfaces(vec![]));
+    assert!(kernel.stats().unpaced_interfaces.is_empty());
+}
@@ -0,0 +1,165 @@
+mod common;
+
+use ccp_bbr::bandwidth::Rate;
+use ccp_bbr::path_cache::PathCache;
+use ccp_bbr::shard::ShardedMap;
+use ccp_bbr::weight::WeightRule;
+use ccp_bbr::{Action, BbrConfig, BbrCore, Measurement, STARTUP_FULL_BW_ROUNDS};
+use portus::DatapathInfo;
+use std::time::{Duration, Instant};
+
+const FLOWS: u32 = 10_000;
+const THREADS: u32 = 8;
+const REPORTS: u32 = 5;
+
+fn info(sock_id: u32) -> DatapathInfo {
+    DatapathInfo {
+        // a few hundred bottleneck groups
+        dst_ip: 0x0a01_0000 | (sock_id % 300) << 8 | 2,
+        ..common::info(sock_id)
+    }
+}
+
+fn installed(core: &mut BbrCore, uid: &mut u32, actions: &[Action]) {
+    for action in actions {
+        if let Action::SetProgram { .. } = action {
+            *uid += 1;
+            core.program_installed(*uid);
+        }
+    }
+}
+
+#[test]
+fn clones_share_entries() {
+    let map = ShardedMap::default();
+    let clone = map.clone();
+    for key in 0..1000 {
+        assert_eq!(map.insert(key, key * 2), None);
+    }
+    assert_eq!(clone.len(), 1000);
+    assert_eq!(clone.get(&21), Some(42));
+    assert_eq!(clone.remove(&21), Some(42));
+    assert!(!map.contains_key(&21));
+    let mut values = map.values();
+    values.sort_unstable();
+    assert_eq!(values.len(), 999);
+    assert_eq!(values[..2], [0, 2]);
+}
+
+#[test]
+fn ten_thousand_flows_report_from_several_threads() {
+    let cfg = BbrConfig {
+        weight_rules: vec!["dport=5201:2".parse::<WeightRule>().unwrap()],
+        ..Default::default()
+    };
+    let start = Instant::now();
+    let threads: Vec<_> = (0..THREADS)
+        .map(|thread| {
+            let cfg = cfg.clone();
+            std::thread::spawn(move || {
+                let mut flows: Vec<_> = (thread..FLOWS)
+                    .step_by(THREADS as usize)
+                    .map(|sock_id| {
+                        let mut core = BbrCore::new(&cfg, &info(sock_id), start);
+                        let mut uid = 0;
+                        let actions = core.start();
+                        installed(&mut core, &mut uid, &actions);
+                        (core, uid)
+                    })
+                    .collect();
+                for report in 1..=REPORTS {
+                    let now = start + Duration::from_millis(u64::from(report) * 10);
+                    for (core, uid) in &mut flows {
+                        let m = Measurement {
+                            program_uid: *uid,
+                            minrtt_us: 10_000,
+                            rate_outgoing: 1_250_000.0,
+                            rate_incoming: 1_250_000.0,
+                            ..Default::default()
+                        };
+                        let actions = core.on_measurement(now, m);
+                        installed(core, uid, &actions);
+                    }
+                }
+                flows
+            })
+        })
+        .collect();
+    let flows: Vec<_> = threads
+        .into_iter()
+        .flat_map(|thread| thread.join().unwrap())
+        .collect();
+
+    let snapshots = cfg.snapshots.flows();
+    assert_eq!(snapshots.len(), FLOWS as usize);
+    assert!(snapshots.windows(2).all(|pair| pair[0].id < pair[1].id));
+    assert!(snapshots
+        .iter()
+        .all(|flow| flow.reports == u64::from(REPORTS)));
+    assert_eq!(cfg.shutdown.active_flows(), FLOWS as usize);
+    // every flow has the same weight, so each gets the whole of its estimate
+    assert_eq!(cfg.weights.share(FLOWS / 2), 1.0);
+
+    cfg.paused.pause(7);
+    cfg.flow_limits.set(9, Rate::from_mbps(1.0));
+    assert!(cfg.paused.is_paused(7) && !cfg.paused.is_paused(8));
+    assert_eq!(cfg.flow_limits.get(9), Some(Rate::from_mbps(1.0)));
+
+    drop(flows);
+    assert!(cfg.snapshots.flows().is_empty());
+    assert_eq!(cfg.shutdown.active_flows(), 0);
+}
+
+#[test]
+fn shared_state_stays_flat_as_flows_come_and_go_for_hours() {
+    const PATHS: usize = 4_096;
+    let cfg = BbrConfig {
+        path_cache: PathCache::new(Duration::from_secs(300), 24).with_capacity(PATHS),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    // every quarter of an hour for three hours, ten thousand flows to destinations not seen
+    // before come and go
+    for round in 0..12u32 {
+        let now = start + Duration::from_secs(u64::from(round) * 900);
+        let hints = cfg.rate_hints.subscribe(0.1);
+        let flows: Vec<_> = (0..FLOWS)
+            .map(|flow| {
+                let sock_id = round * FLOWS + flow;
+                let info = DatapathInfo {
+                    dst_ip: 0x0a00_0002 + (sock_id << 8),
+                    ..info(sock_id)
+                };
+                let mut core = BbrCore::new(&cfg, &info, now);
+                let mut uid = 0;
+                let actions = core.start();
+                installed(&mut core, &mut uid, &actions);
+                for report in 1..=STARTUP_FULL_BW_ROUNDS + 2 {
+                    let m = Measurement {
+                        program_uid: uid,
+                        minrtt_us: 10_000,
+                        rate_outgoing: 1_250_000.0,
+                        rate_incoming: 1_250_000.0,
+                        ..Default::default()
+                    };
+                    let at = now + Duration::from_millis(u64::from(report) * 10);
+                    let actions = core.on_measurement(at, m);
+                    installed(&mut core, &mut uid, &actions);
+                }
+                core.schedule_probe(now, now + Duration::from_secs(1));
+                core
+            })
+            .collect();
+        assert_eq!(cfg.snapshots.flows().len(), FLOWS as usize);
+        assert_eq!(cfg.probe_schedule.len(), FLOWS as usize);
+        drop(hints);
+        drop(flows);
+
+        assert!(cfg.snapshots.flows().is_empty());
+        assert_eq!(cfg.shutdown.active_flows(), 0);
+        assert!(cfg.probe_schedule.is_empty());
+        let paths = cfg.path_cache.len();
+        assert!(paths > 0 && paths <= PATHS, "{}", paths);
+        assert!(cfg.warnings.len() < 100, "{}", cfg.warnings.len());
+    }
+}
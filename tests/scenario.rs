@@ -0,0 +1,92 @@
+use ccp_bbr::scenario::{Scenario, ScenarioSample};
+use ccp_bbr::BbrMode;
+
+fn run(scenario: &Scenario) -> Vec<ScenarioSample> {
+    let mut samples = vec![];
+    scenario.run(|sample| samples.push(sample.clone()));
+    samples
+}
+
+fn mean_rate(samples: &[ScenarioSample], flow: &str, from_s: f64, to_s: f64) -> f64 {
+    let window: Vec<_> = samples
+        .iter()
+        .filter(|s| s.flow == flow && from_s <= s.t_s && s.t_s <= to_s)
+        .collect();
+    let (first, last) = (window[0], window[window.len() - 1]);
+    (last.delivered_bytes - first.delivered_bytes) / (last.t_s - first.t_s)
+}
+
+#[test]
+fn flows_come_and_go_as_the_link_changes() {
+    let scenario =
+        Scenario::from_yaml(include_str!("../scenarios/late_flow_and_rate_drop.yaml")).unwrap();
+    let samples = run(&scenario);
+
+    // a sample every 500 ms, of each flow running then
+    let late: Vec<_> = samples.iter().filter(|s| s.flow == "late").collect();
+    assert_eq!(samples.len() - late.len(), 60);
+    // the changes at a time come before its sample
+    assert_eq!(late.len(), 20);
+    assert!(late.iter().all(|s| s.t_s >= 5.0 && s.t_s < 15.0));
+    assert!(samples[0].t_s >= 0.5 && samples[0].t_s < 0.51);
+    assert_ne!(samples[0].snapshot.id, late[0].snapshot.id);
+
+    // alone, the flow fills each link
+    let rate = mean_rate(&samples, "bulk", 16.0, 20.0);
+    assert!(rate > 0.9 * 12_500_000.0, "throughput {}", rate);
+    let rate = mean_rate(&samples, "bulk", 25.0, 30.0);
+    assert!(
+        rate > 0.9 * 6_250_000.0 && rate < 6_300_000.0,
+        "throughput {}",
+        rate
+    );
+    let last = samples.last().unwrap();
+    assert!(matches!(
+        last.snapshot.mode,
+        BbrMode::ProbeBw | BbrMode::ProbeRtt
+    ));
+
+    // and a second run takes the same samples
+    let again = run(&scenario);
+    assert_eq!(again.len(), samples.len());
+    assert!(again
+        .iter()
+        .zip(&samples)
+        .all(|(a, b)| a.delivered_bytes == b.delivered_bytes));
+}
+
+#[test]
+fn samples_serialize_as_flat_json_lines() {
+    let scenario = Scenario::from_yaml(
+        "duration_s: 1
+link: { rate_mbit: 10, rtt_ms: 10, buffer_bytes: 20000 }
+flows: [{}]",
+    )
+    .unwrap();
+    let samples = run(&scenario);
+    assert_eq!(samples.len(), 1);
+    let line = serde_json::to_value(&samples[0]).unwrap();
+    assert_eq!(line["flow"], "flow0");
+    assert!(line["t_s"].as_f64().unwrap() >= 1.0);
+    assert!(line["bottle_rate"].is_string());
+    assert!(line["mode"].is_string());
+}
+
+#[test]
+fn invalid_scenarios_are_rejected() {
+    let link = "link: { rate_mbit: 10, rtt_ms: 10 }";
+    let parse = |rest: &str| Scenario::from_yaml(&format!("{}\n{}", link, rest));
+    assert!(parse("duration_s: 1\nflows: []").is_ok());
+    assert!(parse("duration_s: 0\nflows: []").is_err());
+    assert!(parse("duration_s: 1").is_err());
+    assert!(parse("duration_s: 1\nflows: []\nlinks: []").is_err());
+    assert!(parse("duration_s: 1\nflows: [{ name: a }, { name: a }]").is_err());
+    assert!(parse("duration_s: 1\nflows: [{ start_s: 2, stop_s: 1 }]").is_err());
+    assert!(parse("duration_s: 1\nflows: [{ args: [\"--loss_mode\", \"random\"] }]").is_err());
+    assert!(parse("duration_s: 1\nargs: [\"--no_such_flag\"]\nflows: []").is_err());
+    assert!(parse(
+        "duration_s: 1\nflows: []\nlink_changes: [{ at_s: 1, buffer_bdp: 1, buffer_bytes: 1 }]"
+    )
+    .is_err());
+    assert!(parse("duration_s: 1\nflows: []\nlink_changes: [{ at_s: -1 }]").is_err());
+}
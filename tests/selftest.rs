@@ -0,0 +1,99 @@
+mod common;
+
+use ccp_bbr::bandwidth::Rate;
+use ccp_bbr::selftest::{find_flow, send, SelfTest};
+use ccp_bbr::snapshot::FlowSnapshot;
+use ccp_bbr::{BbrConfig, BbrCore, BbrMode};
+use portus::DatapathInfo;
+use std::time::{Duration, Instant};
+
+fn flow() -> FlowSnapshot {
+    let info = DatapathInfo {
+        mss: 1_000,
+        ..common::info(3)
+    };
+    let mut flow = BbrCore::new(&BbrConfig::default(), &info, Instant::now()).snapshot();
+    flow.mode = BbrMode::ProbeBw;
+    flow.bottle_rate = Rate::from_mbps(100.0);
+    flow.min_rtt_us = 10_000;
+    flow.srtt_us = 12_000;
+    flow
+}
+
+// 30s at 96 Mbps, in 1000-byte packets
+fn healthy() -> SelfTest {
+    SelfTest {
+        target: "10.0.0.2:5201".parse().unwrap(),
+        elapsed: Duration::from_secs(30),
+        sent_bytes: 360_000_000,
+        expected_rate: None,
+        flow: Some(flow()),
+        unpaced_interfaces: vec![],
+    }
+}
+
+#[test]
+fn selftest_finds_the_flow_by_its_addresses() {
+    let flows = [flow()];
+    let local = "10.0.0.1:40000".parse().unwrap();
+    let found = find_flow(&flows, local, "10.0.0.2:5201".parse().unwrap());
+    assert_eq!(found.map(|flow| flow.id.sock_id), Some(3));
+    assert!(find_flow(&flows, local, "10.0.0.2:5202".parse().unwrap()).is_none());
+    assert!(find_flow(&flows, "[::1]:40000".parse().unwrap(), local).is_none());
+}
+
+#[test]
+fn healthy_transfers_pass() {
+    let result = healthy();
+    assert_eq!(result.achieved_rate(), Rate::from_mbps(96.0));
+    assert_eq!(result.expected(), Some(Rate::from_mbps(100.0)));
+    assert_eq!(result.queueing_delay_us(), Some(2_000));
+    assert!(result.passed(), "{}", result);
+    assert!(result.to_string().ends_with("verdict: PASS"));
+}
+
+#[test]
+fn selftest_names_what_went_wrong() {
+    let unseen = SelfTest {
+        flow: None,
+        ..healthy()
+    };
+    assert!(!unseen.passed());
+    assert!(unseen.problems()[0].starts_with("the agent never ran the flow"));
+
+    let slow = SelfTest {
+        expected_rate: Some(Rate::from_mbps(200.0)),
+        ..healthy()
+    };
+    assert_eq!(
+        slow.problems(),
+        ["achieved 96Mbps is less than 80% of the 200Mbps expected"]
+    );
+
+    let mut queued = healthy();
+    let flow = queued.flow.as_mut().unwrap();
+    flow.srtt_us = 25_000;
+    flow.lost_packets = 36_000;
+    flow.mode = BbrMode::ProbeRtt;
+    queued.unpaced_interfaces = vec![String::from("eth0")];
+    assert_eq!(
+        queued.problems(),
+        [
+            "the flow ended in ProbeRtt rather than PROBE_BW",
+            "a standing queue of 15000us over the min RTT of 10000us",
+            "lost 10.00% of the packets sent",
+            "interfaces without an fq qdisc do not pace kernel flows: eth0",
+        ]
+    );
+    assert!(queued
+        .to_string()
+        .contains("verdict: FAIL\n  - the flow ended in ProbeRtt"));
+}
+
+#[test]
+fn send_writes_until_the_duration_is_up() {
+    let start = Instant::now();
+    let sent = send(&mut std::io::sink(), Duration::from_millis(20)).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    assert!(sent > 0);
+}
@@ -0,0 +1,503 @@
+use ccp_bbr::rate::RateEstimator;
+use ccp_bbr::sim::{Link, Simulation, DEFAULT_TICK_US};
+use ccp_bbr::trace::{Recorder, TraceEvent};
+use ccp_bbr::{BbrConfig, BbrMode, Measurement};
+use std::time::Duration;
+
+// 100 Mbit/s, 20 ms, one BDP of buffer
+fn link() -> Link {
+    Link {
+        rate: 12_500_000.0,
+        buffer: 250_000.0,
+        base_rtt: Duration::from_millis(20),
+    }
+}
+
+fn throughput(sim: &mut Simulation, flow: usize, over: Duration) -> f64 {
+    let before = sim.flows()[flow].delivered_bytes();
+    sim.run_for(over);
+    (sim.flows()[flow].delivered_bytes() - before) / over.as_secs_f64()
+}
+
+#[test]
+fn single_flow_fills_the_link() {
+    let mut sim = Simulation::new(link());
+    sim.add_flow(&BbrConfig::default());
+    sim.run_for(Duration::from_secs(5));
+
+    let rate = throughput(&mut sim, 0, Duration::from_secs(10));
+    assert!(rate > 0.9 * link().rate, "throughput {}", rate);
+    assert!(
+        (sim.flows()[0].core().bottle_rate() - link().rate).abs() < 0.01 * link().rate,
+        "bottle_rate {}",
+        sim.flows()[0].core().bottle_rate()
+    );
+}
+
+#[test]
+fn pulse_shift_measures_the_simulated_install_latency() {
+    let mut sim = Simulation::new(link());
+    sim.add_flow(&BbrConfig {
+        pulse_shift: true,
+        ..Default::default()
+    });
+    sim.run_for(Duration::from_secs(5));
+
+    // updates apply at once, and the program sees them with the next tick's acks
+    let snapshot = sim.flows()[0].core().snapshot();
+    assert_eq!(u64::from(snapshot.install_latency_us), DEFAULT_TICK_US);
+    let rate = throughput(&mut sim, 0, Duration::from_secs(10));
+    assert!(rate > 0.9 * link().rate, "throughput {}", rate);
+}
+
+#[test]
+fn high_rtt_profile_cycles_faster_on_a_satellite_path() {
+    // 20 Mbit/s over a geostationary hop
+    let geo = Link {
+        rate: 2_500_000.0,
+        buffer: 1_500_000.0,
+        base_rtt: Duration::from_millis(600),
+    };
+    let run = |high_rtt_threshold| {
+        let mut sim = Simulation::new(geo);
+        sim.add_flow(&BbrConfig {
+            high_rtt_threshold,
+            ..Default::default()
+        });
+        sim.run_for(Duration::from_secs(10));
+        let start_cycles = sim.flows()[0].core().snapshot().probe_bw_cycles;
+        let rate = throughput(&mut sim, 0, Duration::from_secs(20));
+        let snapshot = sim.flows()[0].core().snapshot();
+        (
+            snapshot.high_rtt,
+            snapshot.probe_bw_cycles - start_cycles,
+            rate,
+        )
+    };
+
+    let (high_rtt, cycles, rate) = run(Some(Duration::from_millis(300)));
+    assert!(high_rtt);
+    assert!(rate > 0.9 * geo.rate, "throughput {}", rate);
+    let (high_rtt, default_cycles, _) = run(None);
+    assert!(!high_rtt);
+    assert!(
+        cycles > default_cycles,
+        "{} cycles, {} by default",
+        cycles,
+        default_cycles
+    );
+}
+
+#[test]
+fn shorter_cruise_phases_probe_more_often() {
+    let run = |down_phase_end, cruise_phase_end| {
+        let mut sim = Simulation::new(link());
+        sim.add_flow(&BbrConfig {
+            down_phase_end,
+            cruise_phase_end,
+            ..Default::default()
+        });
+        sim.run_for(Duration::from_secs(5));
+        let start_cycles = sim.flows()[0].core().snapshot().probe_bw_cycles;
+        let rate = throughput(&mut sim, 0, Duration::from_secs(10));
+        (
+            sim.flows()[0].core().snapshot().probe_bw_cycles - start_cycles,
+            rate,
+        )
+    };
+
+    let (default_cycles, _) = run(2, 8);
+    let (cycles, rate) = run(3, 5);
+    assert!(rate > 0.9 * link().rate, "throughput {}", rate);
+    assert!(
+        cycles > default_cycles * 3 / 2,
+        "{} cycles, {} by default",
+        cycles,
+        default_cycles
+    );
+}
+
+#[test]
+fn flows_follow_a_shrinking_mss() {
+    let mut sim = Simulation::new(link());
+    sim.add_flow(&BbrConfig::default());
+    sim.run_for(Duration::from_secs(2));
+    assert_eq!(sim.flows()[0].core().snapshot().mss, 1460);
+
+    sim.set_mss(0, 1200);
+    let rate = throughput(&mut sim, 0, Duration::from_secs(10));
+    assert_eq!(sim.flows()[0].core().snapshot().mss, 1200);
+    assert!(rate > 0.9 * link().rate, "throughput {}", rate);
+}
+
+#[test]
+fn probe_rtt_drains_the_queue() {
+    let mut sim = Simulation::new(link());
+    sim.add_flow(&BbrConfig::default());
+    while sim.flows()[0].core().mode() != BbrMode::ProbeRtt {
+        sim.step();
+        assert!(sim.elapsed() < Duration::from_secs(15), "no PROBE_RTT");
+    }
+
+    sim.run_for(Duration::from_millis(100));
+    assert_eq!(sim.flows()[0].core().mode(), BbrMode::ProbeRtt);
+    assert!(sim.queue_bytes() < 1.0, "queue {}", sim.queue_bytes());
+
+    while sim.flows()[0].core().mode() == BbrMode::ProbeRtt {
+        sim.step();
+    }
+    assert_eq!(sim.flows()[0].core().min_rtt_us(), 20_000);
+}
+
+#[test]
+fn simulation_is_deterministic() {
+    let run = || {
+        let mut sim = Simulation::new(link());
+        let cfg = BbrConfig::default();
+        sim.add_flow(&cfg);
+        sim.add_flow(&cfg);
+        sim.run_for(Duration::from_secs(3));
+        sim.flows()
+            .iter()
+            .map(|f| f.delivered_bytes())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(run(), run());
+}
+
+#[test]
+fn drain_releases_the_startup_queue() {
+    let mut sim = Simulation::new(link());
+    sim.add_flow(&BbrConfig::default());
+    let mut peak_queue: f64 = 0.0;
+    while sim.flows()[0].core().mode() != BbrMode::ProbeBw {
+        sim.step();
+        peak_queue = peak_queue.max(sim.queue_bytes());
+        assert!(sim.elapsed() < Duration::from_secs(5), "still starting up");
+    }
+
+    assert!(
+        peak_queue > 0.5 * link().buffer,
+        "peak queue {}",
+        peak_queue
+    );
+    assert!(sim.queue_bytes() < 1.0, "queue {}", sim.queue_bytes());
+}
+
+#[test]
+fn unpaced_datapath_probes_with_cwnd() {
+    let mut sim = Simulation::new(link());
+    sim.add_flow(&BbrConfig {
+        pacing: false,
+        ..Default::default()
+    });
+    sim.run_for(Duration::from_secs(5));
+
+    let rate = throughput(&mut sim, 0, Duration::from_secs(4));
+    assert!(rate > 0.9 * link().rate, "throughput {}", rate);
+    assert!(
+        sim.queue_bytes() < 0.5 * link().buffer,
+        "queue {}",
+        sim.queue_bytes()
+    );
+}
+
+#[test]
+fn ramped_pulses_build_a_smaller_queue() {
+    let peak_queue = |cfg: &BbrConfig| {
+        let mut sim = Simulation::new(link());
+        sim.add_flow(cfg);
+        sim.run_for(Duration::from_secs(2));
+        let mut peak: f64 = 0.0;
+        while sim.elapsed() < Duration::from_secs(6) {
+            sim.step();
+            peak = peak.max(sim.queue_bytes());
+        }
+        peak
+    };
+
+    let abrupt = peak_queue(&BbrConfig::default());
+    let ramped = peak_queue(&BbrConfig {
+        probe_bw_ramp: true,
+        ..Default::default()
+    });
+    assert!(ramped < abrupt, "ramped {} abrupt {}", ramped, abrupt);
+}
+
+#[test]
+fn fixed_length_pulses_fill_the_link() {
+    let mut sim = Simulation::new(link());
+    sim.add_flow(&BbrConfig {
+        pulse_length: Some(Duration::from_millis(20)),
+        ..Default::default()
+    });
+    sim.run_for(Duration::from_secs(5));
+
+    let rate = throughput(&mut sim, 0, Duration::from_secs(4));
+    assert!(rate > 0.9 * link().rate, "throughput {}", rate);
+}
+
+#[test]
+fn delivered_bytes_estimator_fills_the_link() {
+    let mut sim = Simulation::new(link());
+    sim.add_flow(&BbrConfig {
+        rate_estimator: RateEstimator::Delivered,
+        ..Default::default()
+    });
+    sim.run_for(Duration::from_secs(5));
+
+    let rate = throughput(&mut sim, 0, Duration::from_secs(4));
+    assert!(rate > 0.9 * link().rate, "throughput {}", rate);
+    let estimate = sim.flows()[0].core().bottle_rate();
+    assert!(
+        estimate < 1.1 * link().rate,
+        "estimate {} link {}",
+        estimate,
+        link().rate
+    );
+}
+
+#[test]
+fn recorded_reports_recompute_the_link_rate() {
+    let path = std::env::temp_dir().join(format!("ccp_bbr_sim_{}.jsonl", std::process::id()));
+    let cfg = BbrConfig {
+        rate_estimator: RateEstimator::Flow,
+        recorder: Some(Recorder::create(&path).unwrap()),
+        ..Default::default()
+    };
+    let mut sim = Simulation::new(link());
+    sim.add_flow(&cfg);
+    sim.run_for(Duration::from_secs(5));
+    drop((sim, cfg));
+
+    let trace = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut previous: Option<Measurement> = None;
+    let mut cruise = vec![];
+    for line in trace.lines() {
+        let TraceEvent::Report {
+            program_uid,
+            pulse_state,
+            delivered_total,
+            span_us,
+            ..
+        } = serde_json::from_str(line).unwrap()
+        else {
+            continue;
+        };
+        let m = Measurement {
+            program_uid: program_uid.unwrap(),
+            pulse_state,
+            delivered_total,
+            span_us,
+            ..Default::default()
+        };
+        // the cruise phases of PROBE_BW, whose reports follow one of the same program
+        if let Some(rate) = m.recomputed_rate(previous.as_ref()) {
+            if pulse_state == 2 && previous.is_some_and(|p| p.program_uid == m.program_uid) {
+                cruise.push(rate);
+            }
+        }
+        previous = Some(m);
+    }
+    assert!(cruise.len() > 10, "{} cruise reports", cruise.len());
+    let avg = cruise.iter().sum::<f64>() / cruise.len() as f64;
+    assert!(
+        (avg - link().rate).abs() < 0.05 * link().rate,
+        "recomputed {} link {}",
+        avg,
+        link().rate
+    );
+}
+
+#[test]
+fn smoothed_rtt_sees_the_queue_the_min_rtt_hides() {
+    let mut sim = Simulation::new(link());
+    sim.add_flow(&BbrConfig::default());
+    sim.run_for(Duration::from_secs(5));
+
+    let core = sim.flows()[0].core();
+    assert_eq!(core.min_rtt_us(), 20_000);
+    assert!(
+        core.srtt_us() > core.min_rtt_us(),
+        "srtt {}",
+        core.srtt_us()
+    );
+    assert!(
+        core.srtt_us() < 2 * core.min_rtt_us(),
+        "srtt {}",
+        core.srtt_us()
+    );
+}
+
+#[test]
+fn bulk_flow_is_not_app_limited() {
+    let mut sim = Simulation::new(link());
+    sim.add_flow(&BbrConfig::default());
+    sim.run_for(Duration::from_secs(5));
+
+    let core = sim.flows()[0].core();
+    assert!(!core.is_app_limited());
+    // the cwnd cap is two BDPs
+    assert!(
+        core.cwnd_utilization() > 0.4 && core.cwnd_utilization() < 1.0,
+        "cwnd utilization {}",
+        core.cwnd_utilization()
+    );
+}
+
+#[test]
+fn stable_probe_gain_keeps_a_smaller_queue() {
+    // the mean queue between PROBE_RTTs, once the estimate has settled
+    let mean_queue = |cfg: &BbrConfig| {
+        let mut sim = Simulation::new(link());
+        sim.add_flow(cfg);
+        sim.run_for(Duration::from_secs(4));
+        let (mut total, mut steps) = (0.0, 0.0);
+        while sim.elapsed() < Duration::from_secs(9) {
+            sim.step();
+            total += sim.queue_bytes();
+            steps += 1.0;
+        }
+        let rate = throughput(&mut sim, 0, Duration::from_millis(500));
+        (total / steps, rate)
+    };
+
+    let (full, _) = mean_queue(&BbrConfig::default());
+    let (stable, rate) = mean_queue(&BbrConfig {
+        stable_probe_gain: Some(1.1),
+        ..Default::default()
+    });
+    assert!(stable < full, "stable {} full {}", stable, full);
+    assert!(rate > 0.9 * link().rate, "throughput {}", rate);
+}
+
+// how long two flows get to settle on their shares, and how evenly they must split the link
+// after that
+const CONVERGENCE: Duration = Duration::from_secs(5);
+const FAIRNESS: f64 = 0.9;
+
+// Jain's fairness index: 1 when every flow gets the same, 1/n when one flow gets everything
+fn jain(rates: &[f64]) -> f64 {
+    let sum: f64 = rates.iter().sum();
+    let squares: f64 = rates.iter().map(|r| r * r).sum();
+    sum * sum / (rates.len() as f64 * squares)
+}
+
+fn throughputs(sim: &mut Simulation, over: Duration) -> Vec<f64> {
+    let before: Vec<_> = sim.flows().iter().map(|f| f.delivered_bytes()).collect();
+    sim.run_for(over);
+    sim.flows()
+        .iter()
+        .zip(before)
+        .map(|(f, before)| (f.delivered_bytes() - before) / over.as_secs_f64())
+        .collect()
+}
+
+#[test]
+fn two_flows_share_the_bottleneck_fairly() {
+    let mut sim = Simulation::new(link());
+    sim.add_flow(&BbrConfig::default());
+    // started together, the flows would stay in lockstep and split the link trivially evenly
+    sim.run_for(Duration::from_millis(500));
+    sim.add_flow(&BbrConfig::default());
+    sim.run_for(CONVERGENCE);
+
+    let rates = throughputs(&mut sim, Duration::from_secs(10));
+    assert!(jain(&rates) > FAIRNESS, "throughputs {:?}", rates);
+    assert!(
+        rates.iter().sum::<f64>() > 0.9 * link().rate,
+        "throughputs {:?}",
+        rates
+    );
+}
+
+#[test]
+fn late_joiner_converges() {
+    let mut sim = Simulation::new(link());
+    sim.add_flow(&BbrConfig::default());
+    sim.run_for(Duration::from_secs(10));
+    assert!(sim.flows()[0].core().bottle_rate() > 0.9 * link().rate);
+
+    sim.add_flow(&BbrConfig::default());
+    sim.run_for(CONVERGENCE);
+    let rates = throughputs(&mut sim, Duration::from_secs(10));
+    assert!(jain(&rates) > FAIRNESS, "throughputs {:?}", rates);
+}
+
+#[test]
+fn fast_step_down_leaves_a_lighter_flow_its_share() {
+    let cfg = BbrConfig {
+        fast_step_down: true,
+        ..Default::default()
+    };
+    let mut sim = Simulation::new(link());
+    sim.add_flow(&cfg);
+    sim.run_for(Duration::from_millis(500));
+    let light = sim.add_flow(&cfg);
+    assert!(cfg
+        .weights
+        .reweigh(sim.flows()[light].core().sock_id(), 0.5));
+    sim.run_for(CONVERGENCE);
+
+    // the lighter flow cruises at half the heavier flow's rate, which is no sign that the
+    // bottleneck shrank
+    let rates = throughputs(&mut sim, Duration::from_secs(10));
+    assert!(
+        sim.flows()[light].core().bottle_rate() > 0.5 * link().rate,
+        "bottle_rate {}",
+        sim.flows()[light].core().bottle_rate()
+    );
+    assert!(rates[light] > 0.25 * link().rate, "throughputs {:?}", rates);
+    assert!(
+        rates.iter().sum::<f64>() > 0.9 * link().rate,
+        "throughputs {:?}",
+        rates
+    );
+}
+
+#[test]
+fn probes_find_the_bottleneck_buffer() {
+    // a full BDP of buffer holds the queue of a 1.25x probe, which is a quarter of a BDP
+    let mut sim = Simulation::new(link());
+    sim.add_flow(&BbrConfig::default());
+    sim.run_for(Duration::from_secs(10));
+    let depth = sim.flows()[0].core().buffer_depth().unwrap();
+    assert!(!depth.overflowed);
+    assert!(depth.bdp > 0.15 && depth.bdp < 0.35, "{:?}", depth);
+
+    // a tenth of one overflows, and the queue it held is the buffer
+    let shallow = Link {
+        buffer: 25_000.0,
+        ..link()
+    };
+    let run = |fit_probes_to_buffer| {
+        let mut sim = Simulation::new(shallow);
+        sim.add_flow(&BbrConfig {
+            fit_probes_to_buffer,
+            ..Default::default()
+        });
+        sim.run_for(Duration::from_secs(5));
+        let lost = sim.flows()[0].lost_bytes();
+        let rate = throughput(&mut sim, 0, Duration::from_secs(10));
+        let depth = sim.flows()[0].core().buffer_depth().unwrap();
+        (depth, rate, sim.flows()[0].lost_bytes() - lost)
+    };
+    let (depth, _, lost) = run(false);
+    assert!(depth.overflowed);
+    assert!(
+        (depth.bytes as f64 - shallow.buffer).abs() < 0.1 * shallow.buffer,
+        "{:?}",
+        depth
+    );
+
+    // probes that fit it lose a fraction of the packets, at no cost in throughput
+    let (_, fitted_rate, fitted_lost) = run(true);
+    assert!(fitted_lost < lost / 5.0, "{} vs {}", fitted_lost, lost);
+    assert!(
+        fitted_rate > 0.95 * link().rate,
+        "throughput {}",
+        fitted_rate
+    );
+}
@@ -0,0 +1,2881 @@
+mod common;
+
+use ccp_bbr::bandwidth::Rate;
+use ccp_bbr::capability::{PROBE_BW_INSTALL_ATTEMPTS, UNENFORCED_RATE_REPORTS};
+use ccp_bbr::initial::InitialPathRule;
+use ccp_bbr::jitter::RTT_JITTER_MIN_SAMPLES;
+use ccp_bbr::loss::{LossMode, LOSS_BURST_HOLD_CYCLES};
+use ccp_bbr::path_cache::{PathCache, PathEstimate};
+use ccp_bbr::update_failure::UpdateFailurePolicy;
+use ccp_bbr::{
+    Action, BbrConfig, BbrCore, BbrMode, Measurement, PulsePhase, TransitionReason, WallClock,
+    DELIVERY_GAP_RATIO, DELIVERY_GAP_WARN_CYCLES, DRAIN_GAIN, HIGH_RTT_CYCLE_ROUNDS,
+    INCAST_MIN_CWND_PACKETS, INCAST_STARTUP_GAIN, MIN_RATE_SAMPLE_ACKS, NO_RTT_SAMPLE, PROBE_GAIN,
+    PROBE_RTT_DURATION_US, PROBE_RTT_MAX_GROUP_SCALE, PULSE_CYCLE_ROUNDS, STABLE_PROBE_CYCLES,
+    STARTUP_FULL_BW_ROUNDS, STARTUP_GAIN, STEP_DOWN_PHASES, STEP_DOWN_RATIO, UNCAPPED_CWND,
+    UNPACED_RATE,
+};
+use common::{info, MSS};
+use portus::DatapathInfo;
+use std::time::{Duration, Instant};
+
+/// Stands in for the datapath: assigns a new program uid for every installed program.
+struct Harness {
+    core: BbrCore,
+    uid: u32,
+    now: Instant,
+}
+
+impl Harness {
+    fn new(cfg: &BbrConfig) -> Self {
+        Harness::for_flow(cfg, &info(1), Instant::now())
+    }
+
+    fn for_flow(cfg: &BbrConfig, info: &DatapathInfo, now: Instant) -> Self {
+        let core = BbrCore::new(cfg, info, now);
+        let mut h = Harness { core, uid: 0, now };
+        let start = h.core.start();
+        h.apply(start);
+        h
+    }
+
+    fn apply(&mut self, actions: Vec<Action>) -> Vec<Action> {
+        for action in &actions {
+            if let Action::SetProgram { .. } = action {
+                self.uid += 1;
+                self.core.program_installed(self.uid);
+            }
+        }
+
+        actions
+    }
+
+    fn report(&mut self, after: Duration, minrtt_us: u32, rate: f64) -> Vec<Action> {
+        self.now += after;
+        let m = Measurement {
+            program_uid: self.uid,
+            minrtt_us,
+            rate_outgoing: rate,
+            rate_incoming: rate,
+            ..Default::default()
+        };
+        let actions = self.core.on_measurement(self.now, m);
+        self.apply(actions)
+    }
+
+    /// A flow that has left STARTUP at 1.25 MB/s, drained, and installed `probe_bw`.
+    fn started(cfg: &BbrConfig) -> Self {
+        Harness::started_flow(cfg, &info(1), Instant::now())
+    }
+
+    fn started_flow(cfg: &BbrConfig, info: &DatapathInfo, now: Instant) -> Self {
+        let mut h = Harness::for_flow(cfg, info, now);
+        for _ in 0..=STARTUP_FULL_BW_ROUNDS {
+            h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+        }
+        h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+        assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+        h
+    }
+}
+
+fn start_field(core: &BbrCore, name: &str) -> Option<u64> {
+    match core.start().as_slice() {
+        [Action::SetProgram {
+            program: "init_program",
+            fields,
+        }] => fields
+            .iter()
+            .find(|(reg, _)| *reg == name)
+            .map(|&(_, val)| val),
+        actions => panic!("unexpected actions {:?}", actions),
+    }
+}
+
+#[test]
+fn flows_starting_in_an_incast_share_the_first_flight() {
+    let cfg = BbrConfig {
+        initial_rtt: Some(Duration::from_millis(20)),
+        incast_threshold: Some(4),
+        ..Default::default()
+    };
+    let now = Instant::now();
+    let flow = |sock_id, at| {
+        let info = DatapathInfo { sock_id, ..info(1) };
+        BbrCore::new(&cfg, &info, at)
+    };
+    let rate = f64::from(10 * MSS) / 0.02;
+
+    let flows: Vec<_> = (1..=3).map(|sock_id| flow(sock_id, now)).collect();
+    assert_eq!(start_field(&flows[2], "Cwnd"), Some(u64::from(10 * MSS)));
+    assert_eq!(
+        start_field(&flows[2], "Rate"),
+        Some((rate * STARTUP_GAIN) as u64)
+    );
+
+    // the fourth flow within the window splits the window and rate four ways
+    let fourth = flow(4, now + Duration::from_millis(50));
+    assert_eq!(start_field(&fourth, "Cwnd"), Some(u64::from(10 * MSS / 4)));
+    assert_eq!(
+        start_field(&fourth, "pacingGain"),
+        Some((INCAST_STARTUP_GAIN * 1e6) as u64)
+    );
+    assert_eq!(
+        start_field(&fourth, "Rate"),
+        Some((rate / 4.0 * INCAST_STARTUP_GAIN) as u64)
+    );
+    let crowd: Vec<_> = (5..=40)
+        .map(|sock_id| flow(sock_id, now + Duration::from_millis(60)))
+        .collect();
+    assert_eq!(
+        start_field(&crowd[35], "Cwnd"),
+        Some(u64::from(INCAST_MIN_CWND_PACKETS * MSS))
+    );
+
+    // once the burst is over, flows start as usual
+    let later = flow(41, now + Duration::from_millis(500));
+    assert_eq!(start_field(&later, "Cwnd"), Some(u64::from(10 * MSS)));
+}
+
+#[test]
+fn start_installs_init_program() {
+    let cfg = BbrConfig::default();
+    let core = BbrCore::new(&cfg, &info(1), Instant::now());
+    // nothing is known about the path, so pacing starts with the first RTT sample
+    assert_eq!(
+        core.start(),
+        vec![Action::SetProgram {
+            program: "init_program",
+            fields: vec![
+                ("Cwnd", u64::from(10 * MSS)),
+                ("pacingGain", (STARTUP_GAIN * 1e6) as u64)
+            ],
+        }]
+    );
+    assert_eq!(core.mode(), BbrMode::Startup);
+
+    let cfg = BbrConfig {
+        pacing: false,
+        ..Default::default()
+    };
+    let core = BbrCore::new(&cfg, &info(1), Instant::now());
+    assert_eq!(
+        core.start(),
+        vec![Action::SetProgram {
+            program: "init_program",
+            fields: vec![("Cwnd", u64::from(10 * MSS))],
+        }]
+    );
+}
+
+#[test]
+fn known_paths_pace_the_first_flight() {
+    let cfg = BbrConfig {
+        initial_rate: Some(Rate::from_mbps(100.0)),
+        initial_rtt: Some(Duration::from_millis(20)),
+        ..Default::default()
+    };
+    let core = BbrCore::new(&cfg, &info(1), Instant::now());
+    let rate = (12_500_000.0 * STARTUP_GAIN) as u64;
+    assert_eq!(start_field(&core, "Rate"), Some(rate));
+    // the program's ramp only raises the rate from there
+    assert_eq!(start_field(&core, "initRate"), Some(rate));
+
+    // a configured RTT alone paces the first window over it
+    let cfg = BbrConfig {
+        initial_rtt: Some(Duration::from_millis(20)),
+        ..Default::default()
+    };
+    let core = BbrCore::new(&cfg, &info(1), Instant::now());
+    assert_eq!(
+        start_field(&core, "Rate"),
+        Some((f64::from(10 * MSS) / 0.02 * STARTUP_GAIN) as u64)
+    );
+
+    // and so does a cached estimate
+    let cfg = BbrConfig::default();
+    let now = Instant::now();
+    cfg.path_cache.record(
+        info(1).dst_ip,
+        PathEstimate {
+            bottle_rate: 6_250_000.0,
+            min_rtt_us: 30_000,
+        },
+        now,
+    );
+    let core = BbrCore::new(&cfg, &info(1), now);
+    assert_eq!(
+        start_field(&core, "Rate"),
+        Some((6_250_000.0 * STARTUP_GAIN) as u64)
+    );
+}
+
+#[test]
+fn max_rate_caps_the_pacing_rate() {
+    let cfg = BbrConfig {
+        initial_rate: Some(Rate::from_mbps(100.0)),
+        initial_rtt: Some(Duration::from_millis(20)),
+        max_rate: Some(Rate::from_mbps(50.0)),
+        ..Default::default()
+    };
+    let core = BbrCore::new(&cfg, &info(1), Instant::now());
+    // the start rate is capped, not just the estimate it comes from
+    assert_eq!(start_field(&core, "Rate"), Some(6_250_000));
+    assert_eq!(core.bottle_rate(), 6_250_000.0);
+}
+
+#[test]
+fn max_rate_caps_probing_and_the_estimate() {
+    let cfg = BbrConfig {
+        max_rate: Some(Rate::from_mbps(10.0)),
+        ..Default::default()
+    };
+    let mut h = Harness::new(&cfg);
+    for _ in 0..=STARTUP_FULL_BW_ROUNDS {
+        h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    }
+    let actions = h.report(Duration::from_millis(10), 10_000, 1_000_000.0);
+
+    // the up pulse stays at the 1.25 MB/s cap
+    assert_eq!(
+        actions,
+        vec![
+            Action::Update(vec![("Cwnd", 25_000), ("Rate", 1_250_000)]),
+            Action::SetProgram {
+                program: "probe_bw",
+                fields: vec![
+                    ("cwndCap", 25_000),
+                    ("bottleRate", 1_250_000),
+                    ("threeFourthsRate", 937_500),
+                    ("fiveFourthsRate", 1_250_000),
+                    ("bw0", 1_250_000),
+                ],
+            },
+        ]
+    );
+
+    // a policer's bursts do not raise the estimate
+    let actions = h.report(Duration::from_millis(10), 10_000, 5_000_000.0);
+    assert!(actions.is_empty());
+    assert_eq!(h.core.bottle_rate(), 1_250_000.0);
+}
+
+#[test]
+fn flow_limits_cap_a_running_flow_until_cleared() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    let field = |actions: &[Action], name: &str| {
+        actions.iter().find_map(|action| match action {
+            Action::Update(fields) => fields
+                .iter()
+                .find(|(reg, _)| *reg == name)
+                .map(|&(_, val)| val),
+            _ => None,
+        })
+    };
+
+    cfg.flow_limits.set(1, Rate::from_mbps(4.0));
+    let actions = h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    assert_eq!(field(&actions, "Rate"), Some(500_000));
+    assert_eq!(field(&actions, "bottleRate"), Some(500_000));
+    assert_eq!(field(&actions, "fiveFourthsRate"), Some(500_000));
+    assert_eq!(field(&actions, "cwndCap"), Some(10_000));
+    // the estimate survives the limit
+    assert_eq!(h.core.bottle_rate(), 1_250_000.0);
+    assert_eq!(h.core.snapshot().rate_limit, Some(Rate::from_mbps(4.0)));
+
+    let actions = h.core.set_rate_limit(None);
+    assert_eq!(field(&actions, "bottleRate"), Some(1_250_000));
+    assert_eq!(field(&actions, "cwndCap"), Some(25_000));
+    assert_eq!(cfg.flow_limits.get(1), None);
+    assert_eq!(h.core.rate_limit(), None);
+}
+
+#[test]
+fn tenants_split_their_aggregate_rate() {
+    let cfg = BbrConfig {
+        tenant_rules: vec!["src=10.0.0.0/8:blue".parse().unwrap()],
+        tenant_configs: vec!["blue:aggregate_rate=8Mbps,max_rate=6Mbps".parse().unwrap()],
+        ..Default::default()
+    };
+    let base = Instant::now();
+    let sibling = |sock_id| DatapathInfo { sock_id, ..info(1) };
+    let mut a = Harness::started_flow(&cfg, &info(1), base);
+    assert_eq!(a.core.snapshot().tenant.as_deref(), Some("blue"));
+    // alone, the flow is held to the tenant's cap on each of its flows
+    assert_eq!(a.core.rate_limit(), Some(Rate::from_mbps(8.0)));
+    a.report(Duration::from_millis(10), 10_000, 5_000_000.0);
+    assert_eq!(a.core.bottle_rate(), Rate::from_mbps(6.0).bytes_per_sec());
+
+    let b = Harness::started_flow(&cfg, &sibling(2), base);
+    assert_eq!(cfg.tenants.len("blue"), 2);
+    assert_eq!(b.core.rate_limit(), Some(Rate::from_mbps(4.0)));
+    a.report(Duration::from_millis(10), 10_000, 5_000_000.0);
+    assert_eq!(a.core.rate_limit(), Some(Rate::from_mbps(4.0)));
+
+    // an operator's limit still applies below the share
+    cfg.flow_limits.set(1, Rate::from_mbps(2.0));
+    a.report(Duration::from_millis(10), 10_000, 5_000_000.0);
+    assert_eq!(a.core.rate_limit(), Some(Rate::from_mbps(2.0)));
+    cfg.flow_limits.clear(1);
+
+    drop(b);
+    assert_eq!(cfg.tenants.len("blue"), 1);
+    a.report(Duration::from_millis(10), 10_000, 5_000_000.0);
+    assert_eq!(a.core.rate_limit(), Some(Rate::from_mbps(8.0)));
+
+    // flows of no tenant are left alone
+    let other = DatapathInfo {
+        sock_id: 3,
+        src_ip: 0xc0a8_0001,
+        ..info(1)
+    };
+    let c = Harness::started_flow(&cfg, &other, base);
+    assert_eq!(c.core.snapshot().tenant, None);
+    assert_eq!(c.core.rate_limit(), None);
+}
+
+#[test]
+fn max_rate_rules_override_the_default_cap() {
+    let cfg = BbrConfig {
+        initial_rate: Some(Rate::from_mbps(100.0)),
+        max_rate: Some(Rate::from_mbps(10.0)),
+        max_rate_rules: vec![
+            "dst=192.168.0.0/16:1Gbps".parse().unwrap(),
+            "dst=10.0.0.0/8:5Mbps".parse().unwrap(),
+        ],
+        ..Default::default()
+    };
+    let core = BbrCore::new(&cfg, &info(1), Instant::now());
+    assert_eq!(core.bottle_rate(), 625_000.0);
+}
+
+#[test]
+fn configured_initial_path_seeds_the_first_window() {
+    let cfg = BbrConfig {
+        initial_rate: Some(Rate::from_mbps(100.0)),
+        initial_rtt: Some(Duration::from_millis(20)),
+        ..Default::default()
+    };
+    let core = BbrCore::new(&cfg, &info(1), Instant::now());
+    // one BDP: 12.5 MB/s * 20 ms
+    assert_eq!(start_field(&core, "Cwnd"), Some(250_000));
+    assert_eq!(core.bottle_rate(), 12_500_000.0);
+    assert_eq!(core.min_rtt_us(), 20_000);
+
+    // a rate alone does not say how much is in flight
+    let cfg = BbrConfig {
+        initial_rate: Some(Rate::from_mbps(100.0)),
+        ..Default::default()
+    };
+    let core = BbrCore::new(&cfg, &info(1), Instant::now());
+    assert_eq!(core.bottle_rate(), 12_500_000.0);
+    assert_eq!(core.min_rtt_us(), 1_000_000);
+    assert_eq!(start_field(&core, "Cwnd"), Some(u64::from(10 * MSS)));
+}
+
+#[test]
+fn initial_path_rules_override_the_default_seed() {
+    let cfg = BbrConfig {
+        initial_rate: Some(Rate::from_mbps(100.0)),
+        initial_rtt: Some(Duration::from_millis(20)),
+        initial_path_rules: vec![
+            "dst=192.168.0.0/16:1:100ms".parse().unwrap(),
+            "dst=10.0.0.0/8:1Gbps:2ms"
+                .parse::<InitialPathRule>()
+                .unwrap(),
+        ],
+        ..Default::default()
+    };
+    let core = BbrCore::new(&cfg, &info(1), Instant::now());
+    assert_eq!(core.bottle_rate(), 125_000_000.0);
+    assert_eq!(core.min_rtt_us(), 2_000);
+    assert_eq!(start_field(&core, "Cwnd"), Some(250_000));
+}
+
+#[test]
+fn path_cache_estimates_take_precedence_over_the_initial_path() {
+    let cfg = BbrConfig {
+        initial_rate: Some(Rate::from_mbps(100.0)),
+        initial_rtt: Some(Duration::from_millis(20)),
+        ..Default::default()
+    };
+    let now = Instant::now();
+    cfg.path_cache.record(
+        info(1).dst_ip,
+        PathEstimate {
+            bottle_rate: 6_250_000.0,
+            min_rtt_us: 30_000,
+        },
+        now,
+    );
+    let core = BbrCore::new(&cfg, &info(1), now);
+    assert_eq!(core.bottle_rate(), 6_250_000.0);
+    assert_eq!(core.min_rtt_us(), 30_000);
+    assert_eq!(start_field(&core, "Cwnd"), Some(u64::from(10 * MSS)));
+}
+
+#[test]
+fn startup_paces_at_gain_while_bandwidth_grows() {
+    let cfg = BbrConfig {
+        startup_gain: 2.0,
+        startup_cwnd_gain: 2.0,
+        ..Default::default()
+    };
+    let mut h = Harness::new(&cfg);
+
+    // cwnd = 2 * 1.25 MB/s * 10 ms
+    let actions = h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    assert_eq!(
+        actions,
+        vec![Action::Update(vec![("Cwnd", 25_000), ("Rate", 2_500_000)])]
+    );
+    // the rate samples are smoothed: (1.25 MB/s + 2.5 MB/s) / 2
+    let actions = h.report(Duration::from_millis(10), 10_000, 2_500_000.0);
+    assert_eq!(
+        actions,
+        vec![Action::Update(vec![("Cwnd", 37_500), ("Rate", 3_750_000)])]
+    );
+    assert_eq!(h.core.mode(), BbrMode::Startup);
+}
+
+#[test]
+fn startup_cwnd_is_at_least_init_cwnd() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::new(&cfg);
+    let actions = h.report(Duration::from_millis(10), 1_000, 125_000.0);
+    assert_eq!(
+        actions,
+        vec![Action::Update(vec![
+            ("Cwnd", u64::from(10 * MSS)),
+            ("Rate", (125_000.0 * ccp_bbr::STARTUP_GAIN) as u64)
+        ])]
+    );
+}
+
+#[test]
+fn startup_plateau_enters_drain() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::new(&cfg);
+    for _ in 0..STARTUP_FULL_BW_ROUNDS {
+        h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+        assert_eq!(h.core.mode(), BbrMode::Startup);
+    }
+    assert_eq!(h.core.transition_reason(), None);
+    let actions = h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+
+    // the BDP is 1.25 MB/s * 10 ms
+    assert_eq!(
+        actions,
+        vec![
+            Action::SetProgram {
+                program: "drain",
+                fields: vec![("bdpTarget", 12_500)],
+            },
+            Action::Update(vec![("Rate", (1_250_000.0 * DRAIN_GAIN) as u64)]),
+        ]
+    );
+    assert_eq!(h.core.mode(), BbrMode::Drain);
+    assert_eq!(
+        h.core.transition_reason(),
+        Some(TransitionReason::FullBwReached)
+    );
+}
+
+#[test]
+fn drained_report_installs_probe_bw() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::new(&cfg);
+    for _ in 0..=STARTUP_FULL_BW_ROUNDS {
+        h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    }
+    assert_eq!(h.core.mode(), BbrMode::Drain);
+    let actions = h.report(Duration::from_millis(10), 10_000, 1_000_000.0);
+
+    // cwndCap = 2 * 1.25 MB/s * 10 ms
+    assert_eq!(
+        actions,
+        vec![
+            Action::Update(vec![("Cwnd", 25_000), ("Rate", 1_562_500)]),
+            Action::SetProgram {
+                program: "probe_bw",
+                fields: vec![
+                    ("cwndCap", 25_000),
+                    ("bottleRate", 1_250_000),
+                    ("threeFourthsRate", 937_500),
+                    ("fiveFourthsRate", 1_562_500),
+                    ("bw0", 1_250_000),
+                ],
+            },
+        ]
+    );
+    assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+    assert_eq!(
+        h.core.transition_reason(),
+        Some(TransitionReason::InflightDrained)
+    );
+    assert_eq!(h.core.min_rtt_us(), 10_000);
+}
+
+#[test]
+fn rates_past_32_bits_are_written_whole() {
+    // 100 Gbit/s, far past the 4.29 GB/s that fits in 32 bits
+    let bottle_rate = 12_500_000_000.0;
+    let mut h = Harness::new(&BbrConfig::default());
+    for _ in 0..=STARTUP_FULL_BW_ROUNDS {
+        h.report(Duration::from_millis(10), 10_000, bottle_rate);
+    }
+    let actions = h.report(Duration::from_millis(10), 10_000, bottle_rate);
+
+    let fields = match &actions[..] {
+        [_, Action::SetProgram { fields, .. }] => fields,
+        _ => panic!("no probe_bw install: {:?}", actions),
+    };
+    let field = |name| fields.iter().find(|(reg, _)| *reg == name).unwrap().1;
+    assert_eq!(field("bottleRate"), 12_500_000_000);
+    assert_eq!(field("fiveFourthsRate"), 15_625_000_000);
+    assert_eq!(field("bw0"), 12_500_000_000);
+}
+
+#[test]
+fn bottle_rate_latches_maximum() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+
+    // smoothed with the 1.25 MB/s samples from STARTUP
+    let actions = h.report(Duration::from_millis(10), 10_000, 2_500_000.0);
+    assert_eq!(
+        actions,
+        vec![Action::Update(vec![
+            ("bottleRate", 1_875_000),
+            ("threeFourthsRate", 1_406_250),
+            ("fiveFourthsRate", 2_343_750),
+            ("cwndCap", 37_500),
+        ])]
+    );
+
+    let actions = h.report(Duration::from_millis(10), 10_000, 1_000_000.0);
+    assert!(actions.is_empty());
+    assert_eq!(h.core.bottle_rate(), 1_875_000.0);
+}
+
+#[test]
+fn bandwidth_samples_take_the_lower_smoothed_rate() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+
+    // an ACK burst: the incoming rate spikes, but the flow sent no faster
+    h.now += Duration::from_millis(10);
+    let m = Measurement {
+        program_uid: h.uid,
+        minrtt_us: 10_000,
+        rate_outgoing: 1_250_000.0,
+        rate_incoming: 5_000_000.0,
+        ..Default::default()
+    };
+    assert!(h.core.on_measurement(h.now, m).is_empty());
+    assert_eq!(h.core.bottle_rate(), 1_250_000.0);
+    assert_eq!(h.core.rates().outgoing(), 1_250_000.0);
+    assert_eq!(h.core.rates().incoming(), 3_125_000.0);
+}
+
+#[test]
+fn lower_min_rtt_updates_cwnd_cap() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+
+    let actions = h.report(Duration::from_millis(10), 5_000, 1_000_000.0);
+    assert_eq!(actions, vec![Action::Update(vec![("cwndCap", 12_500)])]);
+    assert_eq!(h.core.min_rtt_us(), 5_000);
+
+    let actions = h.report(Duration::from_millis(10), 8_000, 1_000_000.0);
+    assert!(actions.is_empty());
+    assert_eq!(h.core.min_rtt_us(), 5_000);
+}
+
+#[test]
+fn cwnd_cap_follows_bdp_multiplier() {
+    let cfg = BbrConfig {
+        cwnd_bdp_multiplier: 1.25,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+
+    // cwndCap = 1.25 * 1.25 MB/s * 5 ms
+    let actions = h.report(Duration::from_millis(10), 5_000, 1_000_000.0);
+    assert_eq!(actions, vec![Action::Update(vec![("cwndCap", 7_812)])]);
+}
+
+#[test]
+fn jitter_headroom_widens_cwnd_cap() {
+    for jitter_headroom in [false, true] {
+        let cfg = BbrConfig {
+            jitter_headroom,
+            ..Default::default()
+        };
+        let mut h = Harness::started(&cfg);
+        for i in 0..RTT_JITTER_MIN_SAMPLES - 1 {
+            let rtt = if i % 2 == 0 { 10_000 } else { 14_000 };
+            assert!(h
+                .report(Duration::from_millis(10), rtt, 1_000_000.0)
+                .is_empty());
+            assert_eq!(h.core.rtt_jitter_us(), 0);
+        }
+
+        let actions = h.report(Duration::from_millis(10), 14_000, 1_000_000.0);
+        assert_eq!(h.core.rtt_jitter_us(), 4_000);
+        if jitter_headroom {
+            // cwndCap = 1.25 MB/s * (2 * 10 ms + 4 ms)
+            assert_eq!(actions, vec![Action::Update(vec![("cwndCap", 30_000)])]);
+        } else {
+            assert!(actions.is_empty());
+        }
+    }
+}
+
+#[test]
+fn delay_budget_bounds_cwnd_cap_and_probes() {
+    let cfg = BbrConfig {
+        delay_budget: Some(Duration::from_millis(2)),
+        // the budget caps cwnd all the same
+        cwnd_cap: false,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    // a 1.2x up pulse queues 2 ms on a 10 ms path
+    let up = 1.0 + 2_000.0 / 10_000.0;
+    let registers = h.core.snapshot().registers;
+    assert_eq!(registers["cwndCap"], 15_000);
+    assert_eq!(registers["fiveFourthsRate"], (1_250_000.0 * up) as u64);
+    assert_eq!(
+        registers["threeFourthsRate"],
+        (1_250_000.0 * (2.0 - up)) as u64
+    );
+
+    // on a 5 ms path, the usual 1.25x pulse fits the budget
+    let actions = h.report(Duration::from_millis(10), 5_000, 1_000_000.0);
+    assert_eq!(
+        actions,
+        vec![Action::Update(vec![
+            ("threeFourthsRate", 937_500),
+            ("fiveFourthsRate", 1_562_500),
+            ("cwndCap", 8_750),
+        ])]
+    );
+}
+
+#[test]
+fn short_flows_skip_startup_until_they_outgrow_their_hint() {
+    let cfg = BbrConfig {
+        short_flow_bytes: Some(100_000),
+        ..Default::default()
+    };
+    let now = Instant::now();
+    cfg.path_cache.record(
+        info(1).dst_ip,
+        PathEstimate {
+            bottle_rate: 6_250_000.0,
+            min_rtt_us: 30_000,
+        },
+        now,
+    );
+    let mut h = Harness::for_flow(&cfg, &info(1), now);
+    assert!(h.core.is_short_flow());
+    assert!(h.core.snapshot().short_flow);
+    // the cached BDP is more than the whole flow, and there is no STARTUP ramp
+    assert_eq!(start_field(&h.core, "Cwnd"), Some(100_000));
+    assert_eq!(start_field(&h.core, "Rate"), Some(6_250_000));
+    assert_eq!(start_field(&h.core, "pacingGain"), None);
+
+    let report = |h: &mut Harness| {
+        h.now += Duration::from_millis(30);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 28_000,
+            rate_outgoing: 6_250_000.0,
+            rate_incoming: 6_250_000.0,
+            acked: 50,
+            ..Default::default()
+        };
+        let actions = h.core.on_measurement(h.now, m);
+        h.apply(actions)
+    };
+    // 73 KB acked
+    assert!(report(&mut h).is_empty());
+    assert!(h.core.is_short_flow());
+    assert_eq!(h.core.min_rtt_us(), 28_000);
+
+    // the flow outgrew its hint, and starts STARTUP from the window it has
+    match &report(&mut h)[..] {
+        [Action::SetProgram {
+            program: "init_program",
+            fields,
+        }] => {
+            assert!(fields.contains(&("Cwnd", 100_000)));
+            assert!(fields.iter().any(|&(reg, _)| reg == "pacingGain"));
+        }
+        actions => panic!("{:?}", actions),
+    }
+    assert!(!h.core.is_short_flow());
+    assert_eq!(h.core.mode(), BbrMode::Startup);
+    assert!(matches!(report(&mut h)[..], [Action::Update(_)]));
+}
+
+#[test]
+fn rate_only_mode_leaves_cwnd_uncapped() {
+    let cfg = BbrConfig {
+        cwnd_cap: false,
+        ..Default::default()
+    };
+    let mut h = Harness::new(&cfg);
+    for _ in 0..=STARTUP_FULL_BW_ROUNDS {
+        h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    }
+    let actions = h.report(Duration::from_millis(10), 10_000, 1_000_000.0);
+    assert_eq!(
+        actions,
+        vec![
+            Action::Update(vec![
+                ("Cwnd", u64::from(UNCAPPED_CWND)),
+                ("Rate", 1_562_500)
+            ]),
+            Action::SetProgram {
+                program: "probe_bw",
+                fields: vec![
+                    ("cwndCap", u64::from(UNCAPPED_CWND)),
+                    ("bottleRate", 1_250_000),
+                    ("threeFourthsRate", 937_500),
+                    ("fiveFourthsRate", 1_562_500),
+                    ("bw0", 1_250_000),
+                ],
+            },
+        ]
+    );
+
+    let actions = h.report(Duration::from_millis(10), 5_000, 2_500_000.0);
+    assert_eq!(
+        actions,
+        vec![Action::Update(vec![
+            ("bottleRate", 1_875_000),
+            ("threeFourthsRate", 1_406_250),
+            ("fiveFourthsRate", 2_343_750),
+        ])]
+    );
+}
+
+#[test]
+fn rate_only_mode_caps_cwnd_if_the_datapath_does_not_pace() {
+    let cfg = BbrConfig {
+        cwnd_cap: false,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    let overshoot = |h: &mut Harness| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: 5_000_000.0,
+            rate_incoming: 1_250_000.0,
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m)
+    };
+
+    for _ in 1..UNENFORCED_RATE_REPORTS {
+        assert!(overshoot(&mut h).is_empty());
+    }
+    assert!(!cfg.capabilities.lacks_rate_enforcement());
+    assert_eq!(
+        overshoot(&mut h),
+        vec![Action::Update(vec![("cwndCap", 25_000), ("Cwnd", 25_000)])]
+    );
+    assert!(cfg.capabilities.lacks_rate_enforcement());
+}
+
+#[test]
+fn auto_estimator_falls_back_to_acked_bytes() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::new(&cfg);
+    let report = |h: &mut Harness, flow_rate, delivery_rate| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: flow_rate,
+            rate_incoming: flow_rate,
+            delivery_rate,
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m)
+    };
+
+    // the datapath's rates are used while it provides them
+    report(&mut h, 1_250_000.0, 2_500_000.0);
+    assert_eq!(h.core.bottle_rate(), 1_250_000.0);
+    assert!(!cfg.capabilities.lacks_flow_rates());
+
+    report(&mut h, 0.0, 2_500_000.0);
+    assert!(cfg.capabilities.lacks_flow_rates());
+    assert_eq!(h.core.bottle_rate(), 1_875_000.0);
+}
+
+#[test]
+fn ramped_probe_bw_starts_at_nine_eighths() {
+    let cfg = BbrConfig {
+        probe_bw_ramp: true,
+        ..Default::default()
+    };
+    let mut h = Harness::new(&cfg);
+    for _ in 0..=STARTUP_FULL_BW_ROUNDS {
+        h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    }
+    let actions = h.report(Duration::from_millis(10), 10_000, 1_000_000.0);
+    assert_eq!(
+        actions,
+        vec![
+            Action::Update(vec![("Cwnd", 25_000), ("Rate", 1_406_250)]),
+            Action::SetProgram {
+                program: "probe_bw",
+                fields: vec![
+                    ("cwndCap", 25_000),
+                    ("bottleRate", 1_250_000),
+                    ("threeFourthsRate", 937_500),
+                    ("fiveFourthsRate", 1_562_500),
+                    ("bw0", 1_250_000),
+                    ("nineEighthsRate", 1_406_250),
+                ],
+            },
+        ]
+    );
+}
+
+#[test]
+fn fixed_pulse_length_is_a_probe_bw_parameter() {
+    let cfg = BbrConfig {
+        pulse_length: Some(Duration::from_millis(10)),
+        ..Default::default()
+    };
+    let mut h = Harness::new(&cfg);
+    for _ in 0..=STARTUP_FULL_BW_ROUNDS {
+        h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    }
+    let actions = h.report(Duration::from_millis(10), 10_000, 1_000_000.0);
+    assert_eq!(
+        actions[1],
+        Action::SetProgram {
+            program: "probe_bw",
+            fields: vec![
+                ("cwndCap", 25_000),
+                ("bottleRate", 1_250_000),
+                ("threeFourthsRate", 937_500),
+                ("fiveFourthsRate", 1_562_500),
+                ("bw0", 1_250_000),
+                ("pulseUs", 10_000),
+            ],
+        }
+    );
+}
+
+#[test]
+fn unpaced_mode_pulses_cwnd() {
+    let cfg = BbrConfig {
+        pacing: false,
+        ..Default::default()
+    };
+    let mut h = Harness::new(&cfg);
+    for _ in 0..STARTUP_FULL_BW_ROUNDS {
+        let actions = h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+        assert!(
+            matches!(&actions[..], [Action::Update(fields)] if fields.len() == 1 && fields[0].0 == "Cwnd"),
+            "{:?}",
+            actions
+        );
+    }
+    let actions = h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    assert_eq!(h.core.mode(), BbrMode::Drain);
+    assert_eq!(actions[1], Action::Update(vec![("Cwnd", 12_500)]));
+
+    // BDP = 1.25 MB/s * 10 ms
+    let actions = h.report(Duration::from_millis(10), 10_000, 1_000_000.0);
+    assert_eq!(
+        actions,
+        vec![
+            Action::Update(vec![("Cwnd", 15_625)]),
+            Action::SetProgram {
+                program: "probe_bw",
+                fields: vec![
+                    ("bdpCwnd", 12_500),
+                    ("threeFourthsCwnd", 9_375),
+                    ("fiveFourthsCwnd", 15_625),
+                    ("bw0", 1_250_000),
+                ],
+            },
+        ]
+    );
+
+    let actions = h.report(Duration::from_millis(10), 8_000, 1_000_000.0);
+    assert_eq!(
+        actions,
+        vec![Action::Update(vec![
+            ("bdpCwnd", 10_000),
+            ("threeFourthsCwnd", 7_500),
+            ("fiveFourthsCwnd", 12_500),
+        ])]
+    );
+}
+
+#[test]
+fn min_rtt_expiry_enters_probe_rtt() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+
+    let actions = h.report(cfg.probe_rtt_interval * 2, 12_000, 1_000_000.0);
+    assert_eq!(
+        actions,
+        vec![
+            Action::SetProgram {
+                program: "probe_rtt",
+                fields: vec![
+                    ("targetInflight", u64::from(4 * MSS)),
+                    ("probeRttUs", u64::from(PROBE_RTT_DURATION_US)),
+                ],
+            },
+            Action::Update(vec![("Cwnd", u64::from(4 * MSS))]),
+        ]
+    );
+    assert_eq!(h.core.mode(), BbrMode::ProbeRtt);
+    assert_eq!(
+        h.core.transition_reason(),
+        Some(TransitionReason::MinRttExpired)
+    );
+}
+
+#[test]
+fn first_probe_rtt_waits_a_grace_period_after_drain() {
+    // the min RTT is measured on the first report, and the flow leaves DRAIN a few rounds later
+    let ramp = |cfg: &BbrConfig| {
+        let mut h = Harness::new(cfg);
+        h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+        while h.core.mode() != BbrMode::ProbeBw {
+            h.report(Duration::from_millis(10), 11_000, 1_250_000.0);
+        }
+        h
+    };
+
+    let cfg = BbrConfig::default();
+    let mut h = ramp(&cfg);
+    let actions = h.report(
+        cfg.probe_rtt_interval - Duration::from_millis(1),
+        11_000,
+        1_000_000.0,
+    );
+    assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+    assert!(actions.is_empty());
+    h.report(Duration::from_millis(2), 11_000, 1_000_000.0);
+    assert_eq!(h.core.mode(), BbrMode::ProbeRtt);
+
+    // without one, the timer runs from the min RTT sample
+    let cfg = BbrConfig {
+        probe_rtt_grace: Some(Duration::ZERO),
+        ..BbrConfig::default()
+    };
+    let mut h = ramp(&cfg);
+    h.report(
+        cfg.probe_rtt_interval - Duration::from_millis(1),
+        11_000,
+        1_000_000.0,
+    );
+    assert_eq!(h.core.mode(), BbrMode::ProbeRtt);
+}
+
+#[test]
+fn reported_mss_changes_resize_probe_rtt() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    let report = |h: &mut Harness, mss| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: 1_250_000.0,
+            rate_incoming: 1_250_000.0,
+            // a BDP in flight, so that the flow is not app-limited
+            inflight_bytes: 12_500,
+            mss,
+            ..Default::default()
+        };
+        let actions = h.core.on_measurement(h.now, m);
+        h.apply(actions)
+    };
+    report(&mut h, MSS);
+    assert_eq!(h.core.snapshot().mss, MSS);
+    // path MTU discovery shrank the segments
+    report(&mut h, 1200);
+    assert_eq!(h.core.snapshot().mss, 1200);
+    // unreported, or smaller than any path's, as when only small segments were acked
+    report(&mut h, 0);
+    report(&mut h, 200);
+    assert_eq!(h.core.snapshot().mss, 1200);
+
+    let actions = h.report(cfg.probe_rtt_interval * 2, 12_000, 1_250_000.0);
+    assert_eq!(h.core.mode(), BbrMode::ProbeRtt);
+    assert!(actions.contains(&Action::Update(vec![("Cwnd", 4 * 1200)])));
+}
+
+#[test]
+fn embedders_can_set_the_mss() {
+    let cfg = BbrConfig {
+        pacing: false,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    assert_eq!(h.core.set_mss(MSS), vec![]);
+    // the pulse's windows are floored at PROBE_RTT's, which follows the MSS
+    match h.core.set_mss(9000).as_slice() {
+        [Action::Update(fields)] => assert!(fields.iter().all(|&(_, cwnd)| cwnd >= 4 * 9000)),
+        actions => panic!("unexpected actions {:?}", actions),
+    }
+    assert_eq!(h.core.snapshot().mss, 9000);
+}
+
+#[test]
+fn flows_count_the_time_they_spend_in_each_mode() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    let startup = Duration::from_millis(10) * (STARTUP_FULL_BW_ROUNDS + 1);
+    let time = h.core.mode_time();
+    assert_eq!(time.startup_us, startup.as_micros() as u64);
+    assert_eq!(time.drain_us, 10_000);
+    assert_eq!(time.probe_bw_us, 0);
+    assert_eq!(time.probe_rtt_fraction(), 0.0);
+
+    h.report(cfg.probe_rtt_interval * 2, 12_000, 1_000_000.0);
+    assert_eq!(h.core.mode(), BbrMode::ProbeRtt);
+    h.report(Duration::from_millis(200), 12_000, 0.0);
+    assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+    // the mode the flow is in counts up to its latest report
+    h.report(Duration::from_millis(300), 12_000, 1_000_000.0);
+
+    let time = h.core.snapshot().mode_time;
+    assert_eq!(time, h.core.mode_time());
+    assert_eq!(
+        time.probe_bw_us,
+        (cfg.probe_rtt_interval * 2 + Duration::from_millis(300)).as_micros() as u64
+    );
+    assert_eq!(time.probe_rtt_us, 200_000);
+    assert_eq!(
+        time.total_us(),
+        (startup + cfg.probe_rtt_interval * 2 + Duration::from_millis(510)).as_micros() as u64
+    );
+    assert!((time.probe_rtt_fraction() - 200e3 / time.total_us() as f64).abs() < 1e-9);
+}
+
+#[test]
+fn probe_rtt_exit_resets_min_rtt_and_returns_to_probe_bw() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    h.report(cfg.probe_rtt_interval * 2, 12_000, 1_000_000.0);
+    assert_eq!(h.core.mode(), BbrMode::ProbeRtt);
+
+    let actions = h.report(Duration::from_millis(250), 12_000, 0.0);
+    assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+    assert_eq!(
+        h.core.transition_reason(),
+        Some(TransitionReason::ProbeRttDone)
+    );
+    assert_eq!(h.core.min_rtt_us(), 12_000);
+    assert_eq!(
+        actions,
+        vec![
+            Action::Update(vec![("Cwnd", 30_000), ("Rate", 1_562_500)]),
+            Action::SetProgram {
+                program: "probe_bw",
+                fields: vec![
+                    ("cwndCap", 30_000),
+                    ("bottleRate", 1_250_000),
+                    ("threeFourthsRate", 937_500),
+                    ("fiveFourthsRate", 1_562_500),
+                    ("bw0", 1_250_000),
+                ],
+            },
+        ]
+    );
+
+    // the min_rtt timer restarted at PROBE_RTT exit
+    let actions = h.report(
+        cfg.probe_rtt_interval - Duration::from_millis(500),
+        12_000,
+        1_000_000.0,
+    );
+    assert!(actions.is_empty());
+}
+
+#[test]
+fn min_rtt_spikes_are_ignored_unless_repeated() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+
+    // a corrupted sample far below the estimate
+    assert!(h
+        .report(Duration::from_millis(10), 1_000, 1_000_000.0)
+        .is_empty());
+    assert_eq!(h.core.min_rtt_us(), 10_000);
+    assert!(h
+        .report(Duration::from_millis(10), 10_000, 1_000_000.0)
+        .is_empty());
+    assert!(h
+        .report(Duration::from_millis(10), 2_000, 1_000_000.0)
+        .is_empty());
+    assert_eq!(h.core.min_rtt_us(), 10_000);
+
+    // ... unless the path really changed; the cap of 5 kB is raised to what PROBE_RTT keeps
+    let actions = h.report(Duration::from_millis(10), 2_000, 1_000_000.0);
+    assert_eq!(actions, vec![Action::Update(vec![("cwndCap", 4 * 1_460)])]);
+    assert_eq!(h.core.min_rtt_us(), 2_000);
+}
+
+#[test]
+fn min_rtt_decreases_from_lossy_reports_are_ignored() {
+    let lossy_report = |h: &mut Harness, loss, timeout| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 8_000,
+            rate_outgoing: 1_250_000.0,
+            rate_incoming: 1_250_000.0,
+            loss,
+            acked: 10,
+            timeout,
+            ..Default::default()
+        };
+        let actions = h.core.on_measurement(h.now, m);
+        h.apply(actions);
+    };
+
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    // a loss burst, and then a retransmission timeout
+    lossy_report(&mut h, 8, false);
+    assert_eq!(h.core.min_rtt_us(), 10_000);
+    lossy_report(&mut h, 0, true);
+    assert_eq!(h.core.min_rtt_us(), 10_000);
+    assert_eq!(h.core.snapshot().lossy_rtt_samples, 2);
+
+    // a loss or two is not a burst
+    lossy_report(&mut h, 2, false);
+    assert_eq!(h.core.min_rtt_us(), 8_000);
+
+    let cfg = BbrConfig {
+        lossy_rtt_filter: false,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    lossy_report(&mut h, 8, false);
+    assert_eq!(h.core.min_rtt_us(), 8_000);
+    assert_eq!(h.core.snapshot().lossy_rtt_samples, 0);
+}
+
+#[test]
+fn reports_without_an_rtt_sample_keep_min_rtt() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+
+    let actions = h.report(Duration::from_millis(10), NO_RTT_SAMPLE, 1_250_000.0);
+    assert!(actions.is_empty());
+    assert_eq!(h.core.min_rtt_us(), 10_000);
+    assert_eq!(h.core.snapshot().rtt_jitter_us, 0);
+
+    h.report(cfg.probe_rtt_interval * 2, 12_000, 1_250_000.0);
+    assert_eq!(h.core.mode(), BbrMode::ProbeRtt);
+    h.report(Duration::from_millis(250), NO_RTT_SAMPLE, 0.0);
+    assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+    assert_eq!(h.core.min_rtt_us(), 10_000);
+}
+
+#[test]
+fn probe_rtt_ignores_a_delayed_ack() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    h.report(cfg.probe_rtt_interval * 2, 12_000, 1_000_000.0);
+    assert_eq!(h.core.mode(), BbrMode::ProbeRtt);
+
+    h.report(Duration::from_millis(250), 200_000, 0.0);
+    assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+    assert_eq!(h.core.min_rtt_us(), 10_000);
+
+    let cfg = BbrConfig {
+        min_rtt_spike_factor: None,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    h.report(cfg.probe_rtt_interval * 2, 12_000, 1_000_000.0);
+    h.report(Duration::from_millis(250), 200_000, 0.0);
+    assert_eq!(h.core.min_rtt_us(), 200_000);
+}
+
+#[test]
+fn standing_queue_skips_up_pulses_until_drained() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    let cruise_report = |h: &mut Harness, minrtt_us| {
+        h.now += Duration::from_millis(60);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us,
+            rate_outgoing: 1_000_000.0,
+            rate_incoming: 1_000_000.0,
+            pulse_state: 2,
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m)
+    };
+
+    // 13 ms through the whole cruise phase of a 10 ms path
+    let actions = cruise_report(&mut h, 13_000);
+    assert_eq!(
+        actions,
+        vec![
+            Action::Update(vec![("Rate", 1_125_000)]),
+            Action::Update(vec![
+                ("bottleRate", 1_125_000),
+                ("fiveFourthsRate", 1_125_000)
+            ]),
+        ]
+    );
+    assert!(cruise_report(&mut h, 13_000).is_empty());
+
+    let actions = cruise_report(&mut h, 11_000);
+    assert_eq!(
+        actions,
+        vec![Action::Update(vec![
+            ("bottleRate", 1_250_000),
+            ("fiveFourthsRate", 1_562_500),
+        ])]
+    );
+}
+
+#[test]
+fn desynced_pulse_states_are_counted_and_can_restart_the_cycle() {
+    for reset_desynced_pulses in [false, true] {
+        let cfg = BbrConfig {
+            reset_desynced_pulses,
+            ..Default::default()
+        };
+        let mut h = Harness::started(&cfg);
+        let phase_report = |h: &mut Harness, pulse_state: u32| {
+            h.now += Duration::from_millis(10);
+            let m = Measurement {
+                program_uid: h.uid,
+                minrtt_us: 10_000,
+                rate_outgoing: 1_250_000.0,
+                rate_incoming: 1_250_000.0,
+                pulse_state,
+                ..Default::default()
+            };
+            let actions = h.core.on_measurement(h.now, m);
+            h.apply(actions)
+        };
+        let restarts = |actions: &[Action]| {
+            actions.iter().any(|a| {
+                matches!(
+                    a,
+                    Action::SetProgram {
+                        program: "probe_bw",
+                        ..
+                    }
+                )
+            })
+        };
+
+        // up, down, cruise and up again
+        for state in [0, 1, 2, 0] {
+            assert!(!restarts(&phase_report(&mut h, state)));
+        }
+        assert_eq!(h.core.snapshot().pulse_desyncs, 0);
+
+        // the flow follows the reported phase, so only reports that keep disagreeing add up
+        assert!(!restarts(&phase_report(&mut h, 2)));
+        assert!(!restarts(&phase_report(&mut h, 2)));
+        assert_eq!(restarts(&phase_report(&mut h, 1)), reset_desynced_pulses);
+        assert_eq!(h.core.snapshot().pulse_desyncs, 3);
+        assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+
+        if reset_desynced_pulses {
+            // the new program starts with the up pulse
+            assert!(!restarts(&phase_report(&mut h, 0)));
+            assert_eq!(h.core.snapshot().pulse_desyncs, 3);
+        }
+    }
+}
+
+#[test]
+fn stale_probes_probe_every_nth_cycle() {
+    let cfg = BbrConfig {
+        stale_probes: 1,
+        stale_probe_interval: 3,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    let cruise_report = |h: &mut Harness| {
+        h.now += Duration::from_millis(60);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: 1_000_000.0,
+            rate_incoming: 1_000_000.0,
+            pulse_state: 2,
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m)
+    };
+    // only the pulse rates change, since the estimate stays put
+    let cruising = vec![
+        Action::Update(vec![("Rate", 1_250_000)]),
+        Action::Update(vec![
+            ("threeFourthsRate", 1_250_000),
+            ("fiveFourthsRate", 1_250_000),
+        ]),
+    ];
+    let probing = vec![
+        Action::Update(vec![("Rate", 1_562_500)]),
+        Action::Update(vec![
+            ("threeFourthsRate", 937_500),
+            ("fiveFourthsRate", 1_562_500),
+        ]),
+    ];
+
+    // the first probe found nothing, so the next two cycles stay at the estimate
+    assert_eq!(cruise_report(&mut h), cruising);
+    assert!(cruise_report(&mut h).is_empty());
+    assert_eq!(cruise_report(&mut h), probing);
+    assert_eq!(cruise_report(&mut h), cruising);
+}
+
+#[test]
+fn app_limited_probes_are_not_stale() {
+    let cfg = BbrConfig {
+        stale_probes: 1,
+        stale_probe_interval: 3,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    let report = |h: &mut Harness, pulse_state, inflight_bytes| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: 1_000_000.0,
+            rate_incoming: 1_000_000.0,
+            pulse_state,
+            inflight_bytes,
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m)
+    };
+
+    // the application only kept 5 KB of the 12.5 KB BDP in flight
+    assert!(report(&mut h, 0, 5_000).is_empty());
+    assert!(h.core.is_app_limited());
+    assert_eq!(h.core.inflight_bytes(), 5_000);
+    assert_eq!(h.core.cwnd_utilization(), 0.2);
+    assert!(report(&mut h, 2, 5_000).is_empty());
+
+    // a probe that filled the pipe and found nothing is stale
+    assert!(report(&mut h, 0, 15_000).is_empty());
+    assert!(!h.core.is_app_limited());
+    assert_eq!(
+        report(&mut h, 2, 12_500)[0],
+        Action::Update(vec![("Rate", 1_250_000)])
+    );
+}
+
+#[test]
+fn reports_from_stale_programs_are_ignored() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+
+    let stale = Measurement {
+        program_uid: h.uid - 1,
+        minrtt_us: 1_000,
+        rate_outgoing: 100_000_000.0,
+        rate_incoming: 100_000_000.0,
+        ..Default::default()
+    };
+    let later = h.now + cfg.probe_rtt_interval * 2;
+    assert!(h.core.on_measurement(later, stale).is_empty());
+    assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+    assert_eq!(h.core.min_rtt_us(), 10_000);
+    assert_eq!(h.core.bottle_rate(), 1_250_000.0);
+}
+
+#[test]
+fn late_reports_are_ignored() {
+    let cfg = BbrConfig {
+        max_report_age: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    let late_report = |h: &mut Harness, waited| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: 5_000_000.0,
+            rate_incoming: 5_000_000.0,
+            received: Some(h.now - waited),
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m)
+    };
+
+    // seconds old, so its rate says nothing about the path any more
+    assert!(late_report(&mut h, Duration::from_secs(2)).is_empty());
+    assert_eq!(h.core.bottle_rate(), 1_250_000.0);
+    let flow = &cfg.snapshots.flows()[0];
+    assert_eq!((flow.late_reports, flow.stale_reports), (1, 0));
+
+    assert!(!late_report(&mut h, Duration::from_millis(50)).is_empty());
+    assert!(h.core.bottle_rate() > 1_250_000.0);
+}
+
+#[test]
+fn recurring_warnings_are_counted_across_flows() {
+    let cfg = BbrConfig {
+        max_report_age: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    let base = Instant::now();
+    let sibling = |sock_id| DatapathInfo { sock_id, ..info(1) };
+    let mut flows = [
+        Harness::started_flow(&cfg, &info(1), base),
+        Harness::started_flow(&cfg, &sibling(2), base),
+    ];
+    let late_report = |h: &mut Harness| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            received: Some(h.now - Duration::from_secs(1)),
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m);
+    };
+
+    // the first late report of either flow is logged, and the rest only counted
+    for _ in 0..5 {
+        for h in &mut flows {
+            late_report(h);
+        }
+    }
+    assert_eq!(cfg.warnings.suppressed(), 9);
+
+    // until the interval has passed
+    flows[1].now += cfg.warn_interval;
+    late_report(&mut flows[1]);
+    late_report(&mut flows[0]);
+    assert_eq!(cfg.warnings.suppressed(), 10);
+}
+
+#[test]
+fn snapshots_track_installed_registers_and_stale_reports() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    let stale = Measurement {
+        program_uid: h.uid - 1,
+        minrtt_us: 10_000,
+        ..Default::default()
+    };
+    h.core.on_measurement(h.now, stale);
+
+    let flows = cfg.snapshots.flows();
+    assert_eq!(flows.len(), 1);
+    let flow = &flows[0];
+    assert_eq!(flow.id.sock_id, 1);
+    assert_eq!(flow.mode, BbrMode::ProbeBw);
+    assert_eq!(flow.program, "probe_bw");
+    assert_eq!(flow.registers["bottleRate"], 1_250_000);
+    assert_eq!(flow.registers["cwndCap"], 25_000);
+    assert_eq!(flow.registers["Rate"], 1_562_500);
+    assert!(!flow.registers.contains_key("bdpTarget"));
+    assert_eq!(flow.stale_reports, 1);
+    assert_eq!(flow.reports, u64::from(STARTUP_FULL_BW_ROUNDS) + 2);
+
+    drop(h);
+    assert!(cfg.snapshots.flows().is_empty());
+}
+
+#[test]
+fn flows_are_identified_by_their_4_tuple() {
+    let cfg = BbrConfig::default();
+    let core = BbrCore::new(&cfg, &info(1), Instant::now());
+    let id = core.flow_id();
+    assert_eq!(id.to_string(), "1 10.0.0.1:40000->10.0.0.2:5201");
+
+    // a dump lists the 4-tuple next to the rest of the state
+    let dump = serde_json::to_value(&cfg.snapshots.flows()[0]).unwrap();
+    assert_eq!(dump["sock_id"], 1);
+    assert_eq!(dump["src"], "10.0.0.1");
+    assert_eq!(dump["dst"], "10.0.0.2");
+    assert_eq!(dump["sport"], 40000);
+    assert_eq!(dump["dport"], 5201);
+}
+
+#[test]
+fn snapshots_count_probe_rtts_cycles_and_reinstalls() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    let before = h.core.snapshot();
+    // init_program, drain and probe_bw
+    assert_eq!(before.program_installs, 3);
+    assert_eq!(before.probe_bw_cycles, 0);
+
+    h.now += Duration::from_millis(10);
+    let end_of_cycle = Measurement {
+        program_uid: h.uid,
+        minrtt_us: 10_000,
+        rate_outgoing: 1_250_000.0,
+        rate_incoming: 1_250_000.0,
+        pulse_state: 2,
+        ..Default::default()
+    };
+    h.core.on_measurement(h.now, end_of_cycle);
+    assert_eq!(h.core.snapshot().probe_bw_cycles, 1);
+
+    h.core.install_failed();
+    let reinstall = h.core.take_reinstall().unwrap();
+    h.apply(reinstall);
+    h.core.program_rejected("probe_bw");
+    let reinstall = h.core.take_reinstall().unwrap();
+    h.apply(reinstall);
+    h.report(cfg.probe_rtt_interval * 2, 12_000, 1_250_000.0);
+    assert_eq!(h.core.mode(), BbrMode::ProbeRtt);
+
+    let after = h.core.snapshot();
+    assert_eq!(after.probe_rtt_entries, 1);
+    assert_eq!(after.reinstalls, 2);
+    assert_eq!(after.failed_updates, 1);
+    assert_eq!(after.rejected_installs, 1);
+    // the two reinstalls and probe_rtt
+    assert_eq!(after.program_installs, before.program_installs + 3);
+    assert_eq!(cfg.snapshots.flows()[0], after);
+}
+
+#[test]
+fn loss_rate_counts_each_report_interval() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    let report = |h: &mut Harness, loss, acked| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: 1_250_000.0,
+            rate_incoming: 1_250_000.0,
+            loss,
+            acked,
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m);
+        cfg.snapshots.flows()[0].clone()
+    };
+
+    let flow = report(&mut h, 2, 98);
+    assert_eq!(flow.loss_rate, 0.02);
+    let flow = report(&mut h, 0, 100);
+    assert_eq!(flow.loss_rate, 0.0);
+    assert_eq!(flow.lost_packets, 2);
+    assert_eq!(flow.acked_packets, 198);
+
+    // a report that accounts for no packets, like probe_rtt's, keeps the last rate
+    let flow = report(&mut h, 1, 3);
+    assert_eq!(flow.loss_rate, 0.25);
+    let flow = report(&mut h, 0, 0);
+    assert_eq!(flow.loss_rate, 0.25);
+}
+
+#[test]
+fn shutdown_releases_flow_on_next_report() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    assert_eq!(cfg.shutdown.active_flows(), 1);
+
+    cfg.shutdown.request();
+    let actions = h.report(Duration::from_millis(10), 10_000, 1_000_000.0);
+    assert_eq!(
+        actions,
+        vec![Action::SetProgram {
+            program: "init_program",
+            fields: vec![("Cwnd", 25_000), ("Rate", UNPACED_RATE)],
+        }]
+    );
+    assert!(h.core.is_released());
+    assert_eq!(cfg.shutdown.active_flows(), 0);
+    assert_eq!(cfg.shutdown.wait_for_release(Duration::ZERO), 0);
+
+    // a released flow no longer reacts, even to an expired min_rtt
+    let actions = h.report(cfg.probe_rtt_interval * 2, 12_000, 1_000_000.0);
+    assert!(actions.is_empty());
+}
+
+#[test]
+fn flows_are_released_at_once_when_the_agent_dies() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    let actions = h.core.release_now(h.now);
+    assert_eq!(
+        actions,
+        vec![Action::SetProgram {
+            program: "init_program",
+            fields: vec![("Cwnd", 25_000), ("Rate", UNPACED_RATE)],
+        }]
+    );
+    assert!(h.core.is_released());
+    assert_eq!(cfg.shutdown.active_flows(), 0);
+    assert!(h.core.release_now(h.now).is_empty());
+}
+
+#[test]
+fn disabled_probe_rtt_refreshes_min_rtt_from_samples() {
+    let cfg = BbrConfig {
+        probe_rtt_interval: Duration::ZERO,
+        ..Default::default()
+    };
+    let window = Duration::from_secs(ccp_bbr::MIN_RTT_WINDOW_SECONDS);
+    let mut h = Harness::started(&cfg);
+
+    // the path got longer: the window's lowest sample replaces min_rtt instead of PROBE_RTT
+    h.report(window / 2, 13_000, 1_000_000.0);
+    let actions = h.report(window, 12_000, 1_000_000.0);
+    assert_eq!(actions, vec![Action::Update(vec![("cwndCap", 30_000)])]);
+    assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+    assert_eq!(h.core.min_rtt_us(), 12_000);
+
+    // a natural low sample still lowers it right away
+    let actions = h.report(Duration::from_millis(10), 11_000, 1_000_000.0);
+    assert_eq!(actions, vec![Action::Update(vec![("cwndCap", 27_500)])]);
+    assert_eq!(h.core.min_rtt_us(), 11_000);
+}
+
+#[test]
+fn scaled_probe_rtt_lasts_longer_in_larger_groups() {
+    let cfg = BbrConfig {
+        scale_probe_rtt: true,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    let probe_rtt_us = |actions: &[Action]| match &actions[0] {
+        Action::SetProgram {
+            program: "probe_rtt",
+            fields,
+        } => fields
+            .iter()
+            .find(|(reg, _)| *reg == "probeRttUs")
+            .map(|&(_, val)| val),
+        other => panic!("{:?}", other),
+    };
+
+    // two other flows to the same /24, and one elsewhere
+    let now = h.now;
+    let sibling = |sock_id, dst_ip| {
+        let info = DatapathInfo {
+            sock_id,
+            dst_ip,
+            ..info(1)
+        };
+        BbrCore::new(&cfg, &info, now)
+    };
+    let siblings = [sibling(2, 0x0a00_0002), sibling(3, 0x0a00_00fe)];
+    let _elsewhere = sibling(4, 0x0a00_0102);
+    let actions = h.report(cfg.probe_rtt_interval * 2, 12_000, 1_000_000.0);
+    assert_eq!(
+        probe_rtt_us(&actions),
+        Some(u64::from(3 * PROBE_RTT_DURATION_US))
+    );
+    h.report(Duration::from_millis(700), 12_000, 0.0);
+    assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+
+    // alone in its group, the flow probes for the usual 200ms
+    drop(siblings);
+    let actions = h.report(cfg.probe_rtt_interval * 2, 12_000, 1_000_000.0);
+    assert_eq!(
+        probe_rtt_us(&actions),
+        Some(u64::from(PROBE_RTT_DURATION_US))
+    );
+    h.report(Duration::from_millis(250), 12_000, 0.0);
+
+    // however many flows share the bottleneck
+    let _crowd: Vec<_> = (10..20)
+        .map(|sock_id| sibling(sock_id, 0x0a00_0002))
+        .collect();
+    let actions = h.report(cfg.probe_rtt_interval * 2, 12_000, 1_000_000.0);
+    assert_eq!(
+        probe_rtt_us(&actions),
+        Some(u64::from(PROBE_RTT_MAX_GROUP_SCALE * PROBE_RTT_DURATION_US))
+    );
+}
+
+#[test]
+fn shared_min_rtt_spares_siblings_a_probe_rtt() {
+    let cfg = BbrConfig {
+        share_min_rtt: true,
+        // so that only the sharing seeds new flows
+        path_cache: PathCache::new(Duration::ZERO, 24),
+        ..Default::default()
+    };
+    let base = Instant::now();
+    let sibling = |sock_id| DatapathInfo { sock_id, ..info(1) };
+    let mut a = Harness::started_flow(&cfg, &info(1), base);
+    let mut b = Harness::started_flow(&cfg, &sibling(2), base);
+
+    // a lower sample one flow takes is taken by the other
+    a.report(Duration::from_millis(10), 9_000, 1_250_000.0);
+    let actions = b.report(Duration::from_millis(20), 10_000, 1_250_000.0);
+    assert_eq!(b.core.min_rtt_us(), 9_000);
+    assert_eq!(actions, vec![Action::Update(vec![("cwndCap", 22_500)])]);
+
+    // a's PROBE_RTT measures for both, even though it finds the path slower
+    a.report(cfg.probe_rtt_interval * 2, 12_000, 1_250_000.0);
+    assert_eq!(a.core.mode(), BbrMode::ProbeRtt);
+    a.report(Duration::from_millis(250), 11_000, 0.0);
+    assert_eq!(a.core.mode(), BbrMode::ProbeBw);
+    b.report(
+        a.now - b.now + Duration::from_millis(10),
+        12_000,
+        1_250_000.0,
+    );
+    assert_eq!(b.core.mode(), BbrMode::ProbeBw);
+    assert_eq!(b.core.min_rtt_us(), 11_000);
+
+    // and a new flow starts from it
+    let c = BbrCore::new(&cfg, &sibling(3), a.now);
+    assert_eq!(c.min_rtt_us(), 11_000);
+    let elsewhere = DatapathInfo {
+        sock_id: 4,
+        dst_ip: 0x0a00_0102,
+        ..info(1)
+    };
+    let d = BbrCore::new(&cfg, &elsewhere, a.now);
+    assert_eq!(d.min_rtt_us(), 1_000_000);
+}
+
+#[test]
+fn aligned_probe_rtt_starts_together_across_flows() {
+    let base = Instant::now();
+    let cfg = BbrConfig {
+        probe_rtt_alignment: Some(WallClock {
+            instant: base,
+            since_epoch: Duration::from_secs(1_000),
+        }),
+        ..Default::default()
+    };
+
+    let report = |core: &mut BbrCore, uid: u32, at: Instant| {
+        let m = Measurement {
+            program_uid: uid,
+            minrtt_us: 10_000,
+            rate_outgoing: 1_250_000.0,
+            rate_incoming: 1_250_000.0,
+            ..Default::default()
+        };
+        core.on_measurement(at, m)
+    };
+    let mut flows: Vec<(BbrCore, u32)> = [base, base + Duration::from_secs(3)]
+        .iter()
+        .map(|&start| {
+            let mut core = BbrCore::new(&cfg, &info(1), start);
+            let mut uid = 1;
+            core.program_installed(uid);
+            let mut now = start;
+            while core.mode() != BbrMode::ProbeBw {
+                now += Duration::from_millis(10);
+                let actions = report(&mut core, uid, now);
+                if actions
+                    .iter()
+                    .any(|a| matches!(a, Action::SetProgram { .. }))
+                {
+                    uid += 1;
+                    core.program_installed(uid);
+                }
+            }
+            (core, uid)
+        })
+        .collect();
+
+    // both flows are due at the first 10 s boundary, though their samples are 3 s apart
+    for (core, uid) in &mut flows {
+        report(core, *uid, base + Duration::from_millis(9_990));
+        assert_eq!(core.mode(), BbrMode::ProbeBw);
+    }
+    for (core, uid) in &mut flows {
+        report(core, *uid, base + Duration::from_millis(10_010));
+        assert_eq!(core.mode(), BbrMode::ProbeRtt);
+    }
+}
+
+#[test]
+fn smoothed_rates_follow_the_estimate_in_steps() {
+    let cfg = BbrConfig {
+        rate_smoothing: Some(0.1),
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+
+    // 10% past the rates probe_bw was installed with, though the estimate moves at once
+    let actions = h.report(Duration::from_millis(10), 10_000, 2_500_000.0);
+    assert_eq!(h.core.bottle_rate(), 1_875_000.0);
+    assert_eq!(
+        actions,
+        vec![Action::Update(vec![
+            ("bottleRate", 1_375_000),
+            ("threeFourthsRate", 1_031_250),
+            ("fiveFourthsRate", 1_718_750),
+            ("cwndCap", 37_500),
+        ])]
+    );
+
+    // later reports take further steps
+    let actions = h.report(Duration::from_millis(10), 10_000, 1_000_000.0);
+    assert_eq!(
+        actions,
+        vec![Action::Update(vec![
+            ("bottleRate", 1_512_500),
+            ("fiveFourthsRate", 1_890_625),
+            ("threeFourthsRate", 1_134_375),
+        ])]
+    );
+    let mut steps = 1;
+    while !h
+        .report(Duration::from_millis(10), 10_000, 1_000_000.0)
+        .is_empty()
+    {
+        steps += 1;
+    }
+    assert_eq!(steps, 4);
+    let registers = h.core.snapshot().registers;
+    assert_eq!(registers["bottleRate"], 1_875_000);
+    assert_eq!(registers["threeFourthsRate"], 1_406_250);
+    assert_eq!(registers["fiveFourthsRate"], 2_343_750);
+}
+
+#[test]
+fn receiver_limited_reports_do_not_raise_bottle_rate() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+
+    h.now += Duration::from_millis(10);
+    let m = Measurement {
+        program_uid: h.uid,
+        minrtt_us: 10_000,
+        rate_outgoing: 2_500_000.0,
+        rate_incoming: 2_500_000.0,
+        receiver_limited: true,
+        ..Default::default()
+    };
+    assert!(h.core.on_measurement(h.now, m).is_empty());
+    assert_eq!(h.core.bottle_rate(), 1_250_000.0);
+}
+
+#[test]
+fn pulses_with_few_acks_do_not_raise_bottle_rate() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    let report = |h: &mut Harness, acks| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: 2_500_000.0,
+            rate_incoming: 2_500_000.0,
+            acks,
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m)
+    };
+
+    // two delayed acks say little about the path
+    assert!(report(&mut h, MIN_RATE_SAMPLE_ACKS - 2).is_empty());
+    assert_eq!(h.core.bottle_rate(), 1_250_000.0);
+
+    // nor did the sparse report enter the smoothed rates
+    assert!(!report(&mut h, MIN_RATE_SAMPLE_ACKS).is_empty());
+    assert_eq!(h.core.bottle_rate(), 1_875_000.0);
+}
+
+#[test]
+fn lossy_mode_ignores_losses_without_rtt_inflation() {
+    let lossy_report = |h: &mut Harness, minrtt_us| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us,
+            rate_outgoing: 1_000_000.0,
+            rate_incoming: 1_000_000.0,
+            loss: 3,
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m)
+    };
+
+    let cfg = BbrConfig {
+        loss_mode: LossMode::Lossy,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    assert!(lossy_report(&mut h, 11_000).is_empty());
+    assert_eq!(h.core.bottle_rate(), 1_250_000.0);
+
+    // 15 ms against a 10 ms path: the queue is full
+    let actions = lossy_report(&mut h, 15_000);
+    assert_eq!(h.core.bottle_rate(), 1_062_500.0);
+    assert_eq!(
+        actions,
+        vec![Action::Update(vec![
+            ("bottleRate", 1_062_500),
+            ("threeFourthsRate", 796_875),
+            ("fiveFourthsRate", 1_328_125),
+            ("cwndCap", 21_250),
+        ])]
+    );
+
+    let cfg = BbrConfig {
+        loss_mode: LossMode::Congestion,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    assert!(!lossy_report(&mut h, 10_000).is_empty());
+    assert_eq!(h.core.bottle_rate(), 1_062_500.0);
+}
+
+#[test]
+fn min_rate_floors_loss_backoff_and_probe_rtt() {
+    let cfg = BbrConfig {
+        loss_mode: LossMode::Congestion,
+        min_rate: Some(Rate::from_mbps(9.0)),
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    h.now += Duration::from_millis(10);
+    let m = Measurement {
+        program_uid: h.uid,
+        minrtt_us: 10_000,
+        rate_outgoing: 1_000_000.0,
+        rate_incoming: 1_000_000.0,
+        loss: 3,
+        ..Default::default()
+    };
+    let actions = h.core.on_measurement(h.now, m);
+
+    // the estimate backs off, but the flow keeps pacing at 1.125 MB/s
+    assert_eq!(h.core.bottle_rate(), 1_062_500.0);
+    // the down pulse was already at the floor
+    assert_eq!(
+        actions,
+        vec![Action::Update(vec![
+            ("bottleRate", 1_125_000),
+            ("fiveFourthsRate", 1_328_125),
+            ("cwndCap", 22_500),
+        ])]
+    );
+
+    // and PROBE_RTT keeps one BDP of the floor in flight
+    let actions = h.report(cfg.probe_rtt_interval * 2, 12_000, 1_000_000.0);
+    assert_eq!(h.core.mode(), BbrMode::ProbeRtt);
+    let floor_bdp = (1_125_000.0 * f64::from(h.core.min_rtt_us()) / 1e6) as u32;
+    assert!(floor_bdp > 4 * MSS);
+    assert_eq!(
+        actions,
+        vec![
+            Action::SetProgram {
+                program: "probe_rtt",
+                fields: vec![
+                    ("targetInflight", u64::from(floor_bdp)),
+                    ("probeRttUs", u64::from(PROBE_RTT_DURATION_US)),
+                ],
+            },
+            Action::Update(vec![("Cwnd", u64::from(floor_bdp))]),
+        ]
+    );
+}
+
+#[test]
+fn lossy_mode_gate_follows_the_configured_rtt_inflation() {
+    let cfg = BbrConfig {
+        loss_mode: LossMode::Lossy,
+        loss_rtt_inflation: 1.05,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    let lossy_report = |h: &mut Harness, minrtt_us| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us,
+            rate_outgoing: 1_000_000.0,
+            rate_incoming: 1_000_000.0,
+            loss: 3,
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m)
+    };
+
+    // 10.4 ms is within 5% of the 10 ms path
+    assert!(lossy_report(&mut h, 10_400).is_empty());
+    assert_eq!(h.core.bottle_rate(), 1_250_000.0);
+    // but 11 ms, which the default margin tolerates, is not
+    assert!(!lossy_report(&mut h, 11_000).is_empty());
+    assert_eq!(h.core.bottle_rate(), 1_062_500.0);
+}
+
+#[test]
+fn receiver_limited_rounds_do_not_end_startup() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::new(&cfg);
+    h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    for _ in 0..2 * STARTUP_FULL_BW_ROUNDS {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: 1_250_000.0,
+            rate_incoming: 1_250_000.0,
+            receiver_limited: true,
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m);
+        assert_eq!(h.core.mode(), BbrMode::Startup);
+    }
+}
+
+#[test]
+fn stalled_reports_are_counted_but_not_sampled() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::new(&cfg);
+    h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    // rounds that delivered next to nothing are no plateau
+    for _ in 0..2 * STARTUP_FULL_BW_ROUNDS {
+        h.report(Duration::from_millis(10), 10_000, 1_000.0);
+        assert_eq!(h.core.mode(), BbrMode::Startup);
+    }
+    assert_eq!(h.core.bottle_rate(), 1_250_000.0);
+    assert_eq!(
+        h.core.snapshot().stalled_reports,
+        2 * STARTUP_FULL_BW_ROUNDS as u64
+    );
+
+    let mut h = Harness::started(&cfg);
+    for _ in 0..3 {
+        assert!(h.report(Duration::from_millis(10), 10_000, 0.0).is_empty());
+    }
+    assert_eq!(h.core.bottle_rate(), 1_250_000.0);
+    assert_eq!(h.core.snapshot().stalled_reports, 3);
+
+    // nor did they drag the smoothed rates toward zero
+    h.report(Duration::from_millis(10), 10_000, 2_500_000.0);
+    assert_eq!(h.core.bottle_rate(), 1_875_000.0);
+}
+
+#[test]
+fn expired_bandwidth_filter_lowers_bottle_rate() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    let filtered_report = |h: &mut Harness, max_rate, inflight_bytes| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: max_rate,
+            rate_incoming: max_rate,
+            inflight_bytes,
+            max_rate,
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m)
+    };
+
+    // enough in flight to fill the BDP of 12.5 kB
+    let actions = filtered_report(&mut h, 1_000_000.0, 12_500);
+    assert_eq!(h.core.bottle_rate(), 1_000_000.0);
+    assert!(
+        matches!(&actions[..], [Action::Update(fields)] if fields.contains(&("bottleRate", 1_000_000))),
+        "{:?}",
+        actions
+    );
+
+    // an application-limited flow's filter does not measure the path
+    assert!(filtered_report(&mut h, 500_000.0, 0).is_empty());
+    assert_eq!(h.core.bottle_rate(), 1_000_000.0);
+}
+
+#[test]
+fn stable_estimates_shrink_the_probe_gain() {
+    let cfg = BbrConfig {
+        stable_probe_gain: Some(1.1),
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    let report = |h: &mut Harness, pulse_state, rate| {
+        h.now += Duration::from_millis(20);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: rate,
+            rate_incoming: rate,
+            pulse_state,
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m)
+    };
+    let up_pulse = |actions: &[Action]| match actions {
+        [Action::Update(pulse), Action::Update(rates)] => {
+            let five_fourths = rates.iter().find(|(reg, _)| *reg == "fiveFourthsRate");
+            assert_eq!(pulse[0].1, five_fourths.unwrap().1);
+            pulse[0].1
+        }
+        _ => panic!("{:?}", actions),
+    };
+
+    // the first cycle has no previous estimate to compare with
+    for _ in 0..STABLE_PROBE_CYCLES {
+        assert!(report(&mut h, 2, 1_000_000.0).is_empty());
+    }
+    assert_eq!(up_pulse(&report(&mut h, 2, 1_000_000.0)), 1_375_000);
+
+    // a probe that finds more bandwidth grows the pulse back at the end of its cycle
+    report(&mut h, 0, 2_000_000.0);
+    let bottle_rate = h.core.bottle_rate();
+    assert!(bottle_rate > 1_250_000.0);
+    assert_eq!(
+        up_pulse(&report(&mut h, 2, 1_000_000.0)),
+        (bottle_rate * PROBE_GAIN) as u64
+    );
+}
+
+#[test]
+fn rejected_probe_bw_falls_back_to_aimd() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::new(&cfg);
+    for _ in 0..=STARTUP_FULL_BW_ROUNDS {
+        h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    }
+    let is_program = |actions: &[Action], name: &str| {
+        actions
+            .iter()
+            .any(|a| matches!(a, Action::SetProgram { program, .. } if *program == name))
+    };
+
+    // drain ends by installing probe_bw, which the datapath keeps refusing
+    h.now += Duration::from_millis(10);
+    let m = Measurement {
+        program_uid: h.uid,
+        minrtt_us: 10_000,
+        ..Default::default()
+    };
+    assert!(is_program(&h.core.on_measurement(h.now, m), "probe_bw"));
+    h.core.program_rejected("probe_bw");
+    for _ in 1..PROBE_BW_INSTALL_ATTEMPTS {
+        assert!(is_program(&h.core.take_reinstall().unwrap(), "probe_bw"));
+        h.core.program_rejected("probe_bw");
+    }
+    let fallback = h.core.take_reinstall().unwrap();
+    assert_eq!(
+        fallback,
+        vec![Action::SetProgram {
+            program: "aimd",
+            fields: vec![
+                ("Cwnd", u64::from(10 * MSS)),
+                ("aiBytes", u64::from(MSS)),
+                ("minCwnd", u64::from(4 * MSS)),
+                ("Rate", UNPACED_RATE),
+            ],
+        }]
+    );
+    h.apply(fallback);
+    assert!(h.core.is_degraded());
+    assert!(h.core.snapshot().degraded);
+    assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+
+    // the datapath runs AIMD on its own; reports only update the estimates
+    assert!(h
+        .report(Duration::from_millis(10), 8_000, 1_000_000.0)
+        .is_empty());
+    assert_eq!(h.core.min_rtt_us(), 8_000);
+
+    // the other flows of the configuration do not try probe_bw again
+    let mut other = Harness::new(&cfg);
+    for _ in 0..=STARTUP_FULL_BW_ROUNDS {
+        other.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    }
+    let actions = other.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    assert!(is_program(&actions, "aimd"));
+    assert!(other.core.is_degraded());
+}
+
+#[test]
+fn failed_updates_are_retried_then_frozen() {
+    let cfg = BbrConfig {
+        update_retries: 2,
+        update_failure: UpdateFailurePolicy::Freeze,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    let rewrite = Action::Update(h.core.snapshot().registers.into_iter().collect());
+
+    h.core.install_failed();
+    assert_eq!(h.core.take_reinstall(), Some(vec![rewrite.clone()]));
+    // a report handled in between starts the count over
+    h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    for _ in 0..2 {
+        h.core.install_failed();
+        assert!(matches!(
+            h.core.take_reinstall().unwrap()[..],
+            [Action::Update(_)]
+        ));
+    }
+    assert_eq!(h.core.snapshot().reinstalls, 0);
+
+    h.core.install_failed();
+    let frozen = h.core.take_reinstall().unwrap();
+    match &frozen[..] {
+        [Action::SetProgram { program, fields }] => {
+            assert_eq!(*program, "init_program");
+            assert!(fields.iter().any(|&(reg, _)| reg == "Cwnd"));
+            assert!(fields.iter().any(|&(reg, _)| reg == "Rate"));
+        }
+        actions => panic!("{:?}", actions),
+    }
+    h.apply(frozen);
+    assert!(h.core.is_frozen());
+    assert!(h.core.snapshot().frozen);
+
+    // nothing updates the flow any more
+    let reports = h.core.snapshot().reports;
+    assert!(h
+        .report(cfg.probe_rtt_interval * 2, 5_000, 2_500_000.0)
+        .is_empty());
+    assert!(h.core.pause().is_empty());
+    assert_eq!(h.core.snapshot().reports, reports);
+    assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+}
+
+#[test]
+fn failed_updates_can_degrade_flows() {
+    let cfg = BbrConfig {
+        update_failure: UpdateFailurePolicy::Degrade,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+
+    h.core.install_failed();
+    let fallback = h.core.take_reinstall().unwrap();
+    assert!(matches!(
+        fallback[..],
+        [Action::SetProgram {
+            program: "aimd",
+            ..
+        }]
+    ));
+    h.apply(fallback);
+    assert!(h.core.is_degraded());
+    assert_eq!(h.core.snapshot().reinstalls, 0);
+
+    // the fallback's own failures reinstall it
+    h.core.install_failed();
+    let reinstall = h.core.take_reinstall().unwrap();
+    assert!(matches!(
+        reinstall[..],
+        [Action::SetProgram {
+            program: "aimd",
+            ..
+        }]
+    ));
+    assert_eq!(h.core.snapshot().reinstalls, 1);
+}
+
+#[test]
+fn paused_flows_stop_probing() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    let up_rate = |actions: &[Action]| match actions {
+        [Action::Update(pulse), Action::Update(rates)] => {
+            let five_fourths = rates.iter().find(|(reg, _)| *reg == "fiveFourthsRate");
+            assert_eq!(pulse[0], ("Rate", five_fourths.unwrap().1));
+            pulse[0].1
+        }
+        _ => panic!("{:?}", actions),
+    };
+
+    // every pulse paces at the estimate
+    assert_eq!(up_rate(&h.core.pause()), 1_250_000);
+    assert!(h.core.is_paused());
+    assert!(h.core.snapshot().paused);
+    assert!(h.core.pause().is_empty());
+
+    // resuming through the configuration takes effect on the next report
+    cfg.paused.resume(1);
+    let actions = h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    assert_eq!(up_rate(&actions), 1_562_500);
+    assert!(!h.core.is_paused());
+}
+
+#[test]
+fn congestion_losses_end_startup() {
+    let lossy_report = |h: &mut Harness, loss| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: 2_500_000.0,
+            rate_incoming: 2_500_000.0,
+            loss,
+            ..Default::default()
+        };
+        let actions = h.core.on_measurement(h.now, m);
+        h.apply(actions)
+    };
+
+    // losses alone do not end STARTUP unless --loss_mode says they are congestion
+    let mut h = Harness::new(&BbrConfig::default());
+    lossy_report(&mut h, 3);
+    assert_eq!(h.core.mode(), BbrMode::Startup);
+
+    let cfg = BbrConfig {
+        loss_mode: LossMode::Congestion,
+        ..Default::default()
+    };
+    let mut h = Harness::new(&cfg);
+    lossy_report(&mut h, 0);
+    assert_eq!(h.core.mode(), BbrMode::Startup);
+    lossy_report(&mut h, 3);
+    assert_eq!(h.core.mode(), BbrMode::Drain);
+    assert_eq!(
+        h.core.transition_reason(),
+        Some(TransitionReason::LossLimit)
+    );
+}
+
+#[test]
+fn paused_flows_leave_startup() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::new(&cfg);
+    h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    cfg.paused.pause(1);
+    h.report(Duration::from_millis(10), 10_000, 2_500_000.0);
+    assert_eq!(h.core.mode(), BbrMode::Drain);
+    assert_eq!(h.core.transition_reason(), Some(TransitionReason::Manual));
+    assert_eq!(
+        cfg.snapshots.flows()[0].transition_reason,
+        Some(TransitionReason::Manual)
+    );
+    // without having found the pipe full
+    assert!(!h.core.pipe_full());
+
+    // and enter PROBE_BW without up pulses
+    let actions = h.report(Duration::from_millis(10), 10_000, 2_500_000.0);
+    assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+    let fields = actions
+        .iter()
+        .find_map(|action| match action {
+            Action::SetProgram { fields, .. } => Some(fields.clone()),
+            _ => None,
+        })
+        .unwrap();
+    let field = |name| fields.iter().find(|(reg, _)| *reg == name).unwrap().1;
+    assert_eq!(field("fiveFourthsRate"), field("bottleRate"));
+    assert_eq!(field("threeFourthsRate"), field("bottleRate"));
+}
+
+#[test]
+fn probe_rtt_interval_in_round_trips_counts_pulse_cycles() {
+    let cfg = BbrConfig {
+        probe_rtt_rounds: Some(2 * PULSE_CYCLE_ROUNDS),
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    let cycle_end = |h: &mut Harness, after| {
+        h.now += after;
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: 1_000_000.0,
+            rate_incoming: 1_000_000.0,
+            pulse_state: 2,
+            ..Default::default()
+        };
+        let actions = h.core.on_measurement(h.now, m);
+        h.apply(actions);
+    };
+
+    // wall-clock time alone does not expire min_rtt
+    cycle_end(&mut h, cfg.probe_rtt_interval * 2);
+    cycle_end(&mut h, Duration::from_millis(80));
+    assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+    cycle_end(&mut h, Duration::from_millis(80));
+    assert_eq!(h.core.mode(), BbrMode::ProbeRtt);
+}
+
+#[test]
+fn fast_step_down_follows_a_shrunken_link() {
+    let cruise_report = |h: &mut Harness, minrtt_us| {
+        h.now += Duration::from_millis(80);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us,
+            rate_outgoing: 500_000.0,
+            rate_incoming: 500_000.0,
+            inflight_bytes: 20_000,
+            pulse_state: 2,
+            ..Default::default()
+        };
+        let actions = h.core.on_measurement(h.now, m);
+        h.apply(actions);
+    };
+
+    let cfg = BbrConfig {
+        fast_step_down: true,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    for _ in 1..STEP_DOWN_PHASES {
+        cruise_report(&mut h, 15_000);
+    }
+    // a phase without a queue starts the count over
+    cruise_report(&mut h, 10_000);
+    for _ in 1..STEP_DOWN_PHASES {
+        cruise_report(&mut h, 15_000);
+    }
+    assert_eq!(h.core.bottle_rate(), 1_250_000.0);
+    cruise_report(&mut h, 15_000);
+    assert!(h.core.bottle_rate() < 1_250_000.0 * STEP_DOWN_RATIO);
+
+    let mut h = Harness::started(&BbrConfig::default());
+    for _ in 0..STEP_DOWN_PHASES {
+        cruise_report(&mut h, 15_000);
+    }
+    assert_eq!(h.core.bottle_rate(), 1_250_000.0);
+}
+
+#[test]
+fn only_up_pulses_raise_the_estimate() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    let phases = [PulsePhase::Up, PulsePhase::Down, PulsePhase::Cruise];
+    let report = |h: &mut Harness, pulse_state| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: 2_000_000.0,
+            rate_incoming: 2_000_000.0,
+            inflight_bytes: 20_000,
+            pulse_state,
+            ..Default::default()
+        };
+        assert_eq!(m.pulse_phase(), phases[pulse_state as usize]);
+        h.core.on_measurement(h.now, m)
+    };
+
+    assert!(report(&mut h, 1).is_empty());
+    assert!(report(&mut h, 2).is_empty());
+    assert_eq!(h.core.bottle_rate(), 1_250_000.0);
+    assert!(!report(&mut h, 0).is_empty());
+    assert!(h.core.bottle_rate() > 1_250_000.0);
+}
+
+#[test]
+fn loss_bursts_halve_cwnd_and_hold_off_probing() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    let report = |h: &mut Harness, pulse_state, loss, acked| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: 1_000_000.0,
+            rate_incoming: 1_000_000.0,
+            pulse_state,
+            loss,
+            acked,
+            inflight_bytes: 20_000,
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m)
+    };
+
+    // a fifth of the window is not a burst
+    report(&mut h, 0, 10, 40);
+    assert_eq!(h.core.snapshot().loss_bursts, 0);
+
+    let actions = report(&mut h, 0, 40, 60);
+    assert_eq!(h.core.snapshot().loss_bursts, 1);
+    assert_eq!(
+        actions,
+        vec![
+            Action::Update(vec![("Cwnd", 10_000)]),
+            Action::Update(vec![
+                ("threeFourthsRate", 1_250_000),
+                ("fiveFourthsRate", 1_250_000),
+                ("cwndCap", 10_000),
+            ]),
+        ]
+    );
+
+    // the rest of this cycle and the next two stay at the estimate
+    for _ in 1..LOSS_BURST_HOLD_CYCLES {
+        report(&mut h, 2, 0, 100);
+    }
+    let actions = report(&mut h, 2, 0, 100);
+    assert_eq!(
+        actions,
+        vec![
+            Action::Update(vec![("Rate", 1_562_500)]),
+            Action::Update(vec![
+                ("threeFourthsRate", 937_500),
+                ("fiveFourthsRate", 1_562_500),
+                ("cwndCap", 25_000),
+            ]),
+        ]
+    );
+
+    // and with bursts disabled, the flow only sees losses
+    let cfg = BbrConfig {
+        loss_burst_fraction: None,
+        ..BbrConfig::default()
+    };
+    let mut h = Harness::started(&cfg);
+    assert!(report(&mut h, 0, 40, 60).is_empty());
+    assert_eq!(h.core.snapshot().loss_bursts, 0);
+
+    // a burst smaller than half of what was in flight keeps the rest, and a packet more
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    h.now += Duration::from_millis(10);
+    let m = Measurement {
+        program_uid: h.uid,
+        minrtt_us: 10_000,
+        rate_outgoing: 1_000_000.0,
+        rate_incoming: 1_000_000.0,
+        loss: 8,
+        acked: 10,
+        inflight_bytes: 40_000,
+        ..Default::default()
+    };
+    let actions = h.core.on_measurement(h.now, m);
+    assert_eq!(
+        actions[0],
+        Action::Update(vec![("Cwnd", 40_000 - 8 * 1_460 + 1_460)])
+    );
+}
+
+#[test]
+fn cycles_delivering_below_the_pacing_rate_count_as_gaps() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::started(&cfg);
+    assert_eq!(h.core.delivery_ratio(), None);
+    let cycle = |h: &mut Harness, rate, receiver_limited| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: rate,
+            rate_incoming: rate,
+            pulse_state: 2,
+            inflight_bytes: 20_000,
+            receiver_limited,
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m);
+    };
+
+    // the estimate stays at 1.25 MB/s while a policer lets half of it through
+    for cycles in 1..=u64::from(DELIVERY_GAP_WARN_CYCLES) {
+        cycle(&mut h, 625_000.0, false);
+        let ratio = h.core.delivery_ratio().unwrap();
+        assert!(ratio < DELIVERY_GAP_RATIO, "{}", ratio);
+        assert_eq!(h.core.snapshot().delivery_gap_cycles, cycles);
+    }
+
+    // a cycle the receiver held back says nothing about the path
+    let before = h.core.delivery_ratio();
+    cycle(&mut h, 100_000.0, true);
+    assert_eq!(h.core.delivery_ratio(), before);
+    assert_eq!(
+        h.core.snapshot().delivery_gap_cycles,
+        u64::from(DELIVERY_GAP_WARN_CYCLES)
+    );
+
+    cycle(&mut h, 1_250_000.0, false);
+    let ratio = h.core.delivery_ratio().unwrap();
+    assert!(ratio >= DELIVERY_GAP_RATIO, "{}", ratio);
+    let snapshot = h.core.snapshot();
+    assert_eq!(snapshot.delivery_ratio, Some(ratio));
+    assert_eq!(
+        snapshot.delivery_gap_cycles,
+        u64::from(DELIVERY_GAP_WARN_CYCLES)
+    );
+}
+
+#[test]
+fn pulse_shift_follows_the_install_latency() {
+    let cfg = BbrConfig {
+        pulse_shift: true,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    let report = |h: &mut Harness, pulse_state, rate, nonce, nonce_age_us| {
+        h.now += Duration::from_millis(10);
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: rate,
+            rate_incoming: rate,
+            max_rate: rate,
+            pulse_state,
+            nonce,
+            nonce_age_us,
+            ..Default::default()
+        };
+        h.core.on_measurement(h.now, m)
+    };
+
+    // the update the up pulse prompts carries a nonce
+    let actions = report(&mut h, 0, 1_500_000.0, 0, 0);
+    assert_eq!(
+        actions,
+        vec![Action::Update(vec![
+            ("bottleRate", 1_500_000),
+            ("threeFourthsRate", 1_125_000),
+            ("fiveFourthsRate", 1_875_000),
+            ("cwndCap", 30_000),
+            ("updateNonce", 1),
+        ])]
+    );
+
+    // the next report saw it 7ms ago, so it took 3ms to take effect
+    let actions = report(&mut h, 1, 1_500_000.0, 1, 7_000);
+    assert_eq!(
+        actions,
+        vec![Action::Update(vec![
+            ("pulseShiftUs", 3_000),
+            ("updateNonce", 2),
+        ])]
+    );
+    assert_eq!(h.core.snapshot().install_latency_us, 3_000);
+
+    // a faster install moves the average, but not the shift by more than a pulse over 16
+    assert!(report(&mut h, 2, 1_500_000.0, 2, 9_000).is_empty());
+    assert_eq!(h.core.snapshot().install_latency_us, 2_750);
+}
+
+#[test]
+fn flows_expose_their_bdp_once_the_pipe_is_full() {
+    let cfg = BbrConfig::default();
+    let mut h = Harness::new(&cfg);
+    // 1 Mbit/s over the initial 1s min RTT
+    assert_eq!(h.core.estimated_bdp_bytes(), 125_000);
+    h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    assert!(!h.core.pipe_full());
+    assert!(!h.core.snapshot().pipe_full);
+
+    let h = Harness::started(&cfg);
+    assert!(h.core.pipe_full());
+    assert_eq!(h.core.estimated_bdp_bytes(), 12_500);
+    let snapshot = h.core.snapshot();
+    assert!(snapshot.pipe_full);
+    assert_eq!(snapshot.estimated_bdp_bytes, 12_500);
+}
+
+#[test]
+fn quiet_flows_skip_probe_rtt() {
+    let cfg = BbrConfig {
+        skip_quiet_probe_rtt: true,
+        ..Default::default()
+    };
+    let report = |h: &mut Harness, after, inflight_bytes| {
+        h.now += after;
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 12_000,
+            rate_outgoing: 1_250_000.0,
+            rate_incoming: 1_250_000.0,
+            inflight_bytes,
+            ..Default::default()
+        };
+        let actions = h.core.on_measurement(h.now, m);
+        h.apply(actions);
+    };
+    // reports every 100ms until the min RTT expires, one of them app-limited if `quiet`
+    let run = |quiet| {
+        let mut h = Harness::started(&cfg);
+        for i in 0..110 {
+            let inflight = if quiet && i == 50 { 1_000 } else { 25_000 };
+            report(&mut h, Duration::from_millis(100), inflight);
+        }
+        h
+    };
+
+    // a flow that keeps the pipe full still drains it
+    let h = run(false);
+    assert_eq!(h.core.snapshot().probe_rtt_entries, 1);
+    assert_eq!(h.core.snapshot().skipped_probe_rtts, 0);
+
+    // one that left it for a while has RTT samples as good as PROBE_RTT's
+    let h = run(true);
+    assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+    assert_eq!(h.core.snapshot().probe_rtt_entries, 0);
+    assert_eq!(h.core.snapshot().skipped_probe_rtts, 1);
+    assert_eq!(h.core.min_rtt_us(), 12_000);
+
+    // and so does one that went idle
+    let mut h = Harness::started(&cfg);
+    report(&mut h, Duration::from_millis(100), 25_000);
+    report(&mut h, cfg.probe_rtt_interval * 2, 25_000);
+    assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+    assert_eq!(h.core.snapshot().skipped_probe_rtts, 1);
+}
+
+#[test]
+fn high_rtt_flows_cycle_faster_and_keep_half_the_bdp_in_probe_rtt() {
+    // reports through STARTUP and DRAIN on the harness's 10ms path, and the fields of the
+    // probe_bw install that follows
+    let enter_probe_bw = |threshold_ms| {
+        let cfg = BbrConfig {
+            high_rtt_threshold: Some(Duration::from_millis(threshold_ms)),
+            ..Default::default()
+        };
+        let mut h = Harness::new(&cfg);
+        let mut actions = vec![];
+        for _ in 0..=STARTUP_FULL_BW_ROUNDS + 1 {
+            actions = h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+        }
+        assert_eq!(h.core.mode(), BbrMode::ProbeBw);
+        let fields = actions
+            .into_iter()
+            .find_map(|action| match action {
+                Action::SetProgram {
+                    program: "probe_bw",
+                    fields,
+                } => Some(fields),
+                _ => None,
+            })
+            .unwrap();
+        (h, fields)
+    };
+    let probe_rtt_target = |h: &mut Harness| {
+        let actions = h.report(Duration::from_secs(20), 10_000, 1_250_000.0);
+        assert_eq!(h.core.mode(), BbrMode::ProbeRtt);
+        actions.into_iter().find_map(|action| match action {
+            Action::SetProgram {
+                program: "probe_rtt",
+                fields,
+            } => Some(fields[0]),
+            _ => None,
+        })
+    };
+
+    // 10ms is above a 5ms threshold
+    let (mut h, fields) = enter_probe_bw(5);
+    assert!(h.core.high_rtt());
+    assert!(h.core.snapshot().high_rtt);
+    assert_eq!(h.core.cycle_rounds(), HIGH_RTT_CYCLE_ROUNDS);
+    assert!(fields.contains(&("cycleRounds", 4)));
+    assert!(fields.contains(&("bwLongFilter", 1)));
+    assert_eq!(probe_rtt_target(&mut h), Some(("targetInflight", 6_250)));
+
+    // and below a 20ms one
+    let (mut h, fields) = enter_probe_bw(20);
+    assert!(!h.core.high_rtt());
+    assert_eq!(h.core.cycle_rounds(), PULSE_CYCLE_ROUNDS);
+    assert!(fields.contains(&("cycleRounds", 8)));
+    assert!(fields.contains(&("bwLongFilter", 0)));
+    assert_eq!(
+        probe_rtt_target(&mut h),
+        Some(("targetInflight", u64::from(4 * MSS)))
+    );
+}
+
+#[test]
+fn scheduled_probes_end_the_cruise_phase_at_their_time() {
+    let cfg = BbrConfig {
+        schedule_probes: true,
+        ..Default::default()
+    };
+    let mut h = Harness::started(&cfg);
+    let report = |h: &mut Harness, after, pulse_state| {
+        h.now += after;
+        let m = Measurement {
+            program_uid: h.uid,
+            minrtt_us: 10_000,
+            rate_outgoing: 1_250_000.0,
+            rate_incoming: 1_250_000.0,
+            max_rate: 1_250_000.0,
+            pulse_state,
+            ..Default::default()
+        };
+        let actions = h.core.on_measurement(h.now, m);
+        h.apply(actions)
+    };
+    let scheduled = |at_us| Action::Update(vec![("probeAtUs", at_us)]);
+
+    // until a cycle ends, the flow does not know when its cycle started, and leaves the probe
+    // scheduled
+    let at = h.now + Duration::from_millis(200);
+    assert!(h.core.schedule_probe(h.now, at).is_empty());
+    assert_eq!(cfg.probe_schedule.get(1), Some(at));
+    report(&mut h, Duration::from_millis(10), 0);
+    report(&mut h, Duration::from_millis(10), 1);
+    // the cycle that starts 30ms in cruises until 170ms into it
+    let actions = report(&mut h, Duration::from_millis(10), 2);
+    assert!(actions.contains(&scheduled(170_000)), "{:?}", actions);
+    assert!(cfg.probe_schedule.is_empty());
+
+    // the next cycle starts at the scheduled time, and the flow times the next probe from it
+    report(&mut h, Duration::from_millis(10), 0);
+    report(&mut h, Duration::from_millis(10), 1);
+    report(&mut h, Duration::from_millis(150), 2);
+    let at = h.now + Duration::from_millis(50);
+    assert_eq!(h.core.schedule_probe(h.now, at), vec![scheduled(50_000)]);
+
+    // a time that has passed is dropped
+    let at = h.now - Duration::from_millis(1);
+    assert!(h.core.schedule_probe(h.now, at).is_empty());
+    assert!(cfg.probe_schedule.is_empty());
+}
+
+#[test]
+fn rate_hints_follow_the_pacing_rate() {
+    let cfg = BbrConfig::default();
+    let coarse = cfg.rate_hints.subscribe(0.25);
+    let every = cfg.rate_hints.subscribe(0.0);
+    let mut h = Harness::new(&cfg);
+    assert!(coarse.try_recv().is_err());
+
+    // a flow's first report hints its rate to every subscriber
+    h.report(Duration::from_millis(10), 10_000, 1_250_000.0);
+    let hint = coarse.try_recv().unwrap();
+    assert_eq!(hint.id, h.core.snapshot().id);
+    assert_eq!(
+        hint.pacing_rate,
+        Rate::from_bytes_per_sec(h.core.pacing_rate())
+    );
+    assert_eq!(hint.estimated_bdp_bytes, h.core.estimated_bdp_bytes());
+    assert_eq!(every.try_recv().unwrap(), hint);
+
+    // less than a quarter more
+    h.report(Duration::from_millis(10), 10_000, 1_500_000.0);
+    assert!(coarse.try_recv().is_err());
+    assert!(every.try_recv().unwrap().pacing_rate > hint.pacing_rate);
+    // more than a quarter more than the last hint
+    h.report(Duration::from_millis(10), 10_000, 2_000_000.0);
+    let hint = coarse.try_recv().unwrap();
+    assert_eq!(
+        hint.pacing_rate,
+        Rate::from_bytes_per_sec(h.core.pacing_rate())
+    );
+
+    // dropped subscribers are left out
+    drop(every);
+    h.report(Duration::from_millis(10), 10_000, 2_000_000.0);
+    assert!(h.core.pacing_rate() > hint.pacing_rate.bytes_per_sec());
+    assert!(coarse.try_recv().is_err());
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use ccp_bbr::datapath::DatapathKind;
+use ccp_bbr::latency::LatencyHistogram;
+use ccp_bbr::log_granularity::CycleSummary;
+use ccp_bbr::stats::{graphite_lines, graphite_marker, influx_lines, influx_marker, StatsSink};
+use ccp_bbr::transport::Transport;
+use ccp_bbr::{BbrConfig, BbrCore, BbrMode, Measurement, TransitionReason, NO_RTT_SAMPLE};
+use common::info;
+use std::io::Read;
+use std::net::{TcpListener, UdpSocket};
+use std::time::{Duration, Instant};
+
+const AT: Duration = Duration::from_secs(1_700_000_000);
+
+#[test]
+fn sinks_parse() {
+    assert_eq!(
+        "influx://localhost:8089".parse(),
+        Ok(StatsSink::Influx(String::from("localhost:8089")))
+    );
+    assert_eq!(
+        "graphite://10.0.0.5:2003".parse(),
+        Ok(StatsSink::Graphite(String::from("10.0.0.5:2003")))
+    );
+    assert!("influx://localhost".parse::<StatsSink>().is_err());
+    assert!("influx://:8089".parse::<StatsSink>().is_err());
+    assert!("graphite://localhost:http".parse::<StatsSink>().is_err());
+    assert!("statsd://localhost:8125".parse::<StatsSink>().is_err());
+    assert!("localhost:8089".parse::<StatsSink>().is_err());
+}
+
+#[test]
+fn influx_lines_tag_each_flow() {
+    let cfg = BbrConfig::default();
+    let _flow = BbrCore::new(&cfg, &info(7), Instant::now());
+    let lines = influx_lines("unix", &cfg.snapshots.flows(), AT);
+    assert_eq!(
+        lines,
+        [
+            "bbr,ipc=unix,sock_id=7,src=10.0.0.1,dst=10.0.0.2,sport=40000,dport=5201 \
+             mode=\"Startup\",bottle_rate_bps=1000000,pacing_rate_bps=1000000,\
+             estimated_bdp_bytes=125000i,min_rtt_us=1000000i,srtt_us=0i,\
+             inflight_bytes=0i,loss_rate=0,lost_packets=0i,probe_rtt_entries=0i,startup_time_us=0i,\
+             drain_time_us=0i,probe_bw_time_us=0i,probe_rtt_time_us=0i,\
+             probe_bw_cycles=0i,delivery_gap_cycles=0i,reinstalls=0i,failed_updates=0i,stale_reports=0i,\
+             stalled_reports=0i,handling_p50_us=0i,handling_p99_us=0i,reg_Cwnd=14600i,reg_pacingGain=2885390i 1700000000000000000"
+        ]
+    );
+}
+
+#[test]
+fn graphite_lines_name_each_metric() {
+    let cfg = BbrConfig::default();
+    let _flow = BbrCore::new(&cfg, &info(7), Instant::now());
+    let lines = graphite_lines("unix", &cfg.snapshots.flows(), AT);
+    assert!(lines.starts_with("bbr.unix.7.mode 0 1700000000\n"));
+    assert!(lines.contains("bbr.unix.7.bottle_rate_bps 1000000 1700000000\n"));
+    assert!(lines.contains("bbr.unix.7.min_rtt_us 1000000 1700000000\n"));
+    assert!(lines.contains("bbr.unix.7.registers.Cwnd 14600 1700000000\n"));
+    assert!(lines.contains("bbr.unix.7.pacing_rate_bps 1000000 1700000000\n"));
+    assert_eq!(lines.lines().count(), 24);
+}
+
+#[test]
+fn transition_reasons_follow_the_mode() {
+    let cfg = BbrConfig::default();
+    let _flow = BbrCore::new(&cfg, &info(7), Instant::now());
+    let mut flows = cfg.snapshots.flows();
+    flows[0].mode = BbrMode::ProbeRtt;
+    flows[0].transition_reason = Some(TransitionReason::MinRttExpired);
+
+    let lines = influx_lines("unix", &flows, AT);
+    assert!(
+        lines[0].contains(" mode=\"ProbeRtt\",transition_reason=\"MinRttExpired\",bottle"),
+        "{}",
+        lines[0]
+    );
+    let lines = graphite_lines("unix", &flows, AT);
+    assert!(lines.contains("bbr.unix.7.transition_reason 0 1700000000\n"));
+    assert_eq!(lines.lines().count(), 25);
+}
+
+#[test]
+fn push_reaches_the_sink() {
+    let cfg = BbrConfig::default();
+    let transport = Transport::new(&cfg, "unix", DatapathKind::Quic);
+    let _flows = [1, 2].map(|sock_id| BbrCore::new(&transport.cfg, &info(sock_id), Instant::now()));
+    let transports = [transport];
+
+    let influx = UdpSocket::bind("127.0.0.1:0").unwrap();
+    influx
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let sink = StatsSink::Influx(influx.local_addr().unwrap().to_string());
+    sink.push(&transports, AT).unwrap();
+    let mut buf = [0; 1500];
+    for sock_id in [1, 2] {
+        let len = influx.recv(&mut buf).unwrap();
+        let line = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(
+            line.starts_with(&format!("bbr,ipc=unix,sock_id={},", sock_id)),
+            "{}",
+            line
+        );
+    }
+
+    let graphite = TcpListener::bind("127.0.0.1:0").unwrap();
+    let sink = StatsSink::Graphite(graphite.local_addr().unwrap().to_string());
+    sink.push(&transports, AT).unwrap();
+    let mut received = String::new();
+    graphite
+        .accept()
+        .unwrap()
+        .0
+        .read_to_string(&mut received)
+        .unwrap();
+    assert_eq!(received.lines().count(), 48);
+    assert!(received.contains("bbr.unix.2.mode 0 1700000000\n"));
+}
+
+#[test]
+fn handling_latency_merges_into_transport_percentiles() {
+    let mut handling = LatencyHistogram::default();
+    assert_eq!(handling.percentile_us(0.99), 0);
+    for us in 1..=100 {
+        handling.record(Duration::from_micros(us * 10));
+    }
+    assert_eq!(handling.count(), 100);
+    // 500us is in the bucket from 256us, and 990us in the one from 512us
+    assert_eq!(handling.percentile_us(0.5), 512);
+    assert_eq!(handling.percentile_us(0.99), 1024);
+    handling.record(Duration::from_secs(60));
+    assert_eq!(handling.percentile_us(1.0), 1 << 24);
+
+    let cfg = BbrConfig::default();
+    let transport = Transport::new(&cfg, "unix", DatapathKind::Quic);
+    let mut flows =
+        [1, 2].map(|sock_id| BbrCore::new(&transport.cfg, &info(sock_id), Instant::now()));
+    for flow in &mut flows {
+        flow.record_handling(Duration::from_micros(40));
+        // snapshots pick it up with the next report
+        flow.on_measurement(Instant::now(), Measurement::default());
+    }
+    assert_eq!(flows[0].handling_latency().count(), 1);
+    let stats = serde_json::to_value(transport.stats()).unwrap();
+    assert_eq!(
+        stats["handling"],
+        serde_json::json!({"count": 2, "p50_us": 64, "p90_us": 64, "p99_us": 64})
+    );
+}
+
+#[test]
+fn cycle_summaries_average_what_their_reports_saw() {
+    let mut cycle = CycleSummary::default();
+    assert_eq!(cycle.avg_rate(), 0.0);
+    assert_eq!(cycle.loss_rate(), 0.0);
+    cycle.record(1_000_000.0, 20_000, 1, 9);
+    // a report without a rate sample or an RTT sample
+    cycle.record(0.0, NO_RTT_SAMPLE, 0, 10);
+    cycle.record(2_000_000.0, 18_000, 1, 19);
+    assert_eq!(cycle.reports, 3);
+    assert_eq!(cycle.avg_rate(), 1_500_000.0);
+    assert_eq!(cycle.min_rtt_us, 18_000);
+    assert_eq!(cycle.loss, 2);
+    assert_eq!(cycle.loss_rate(), 0.05);
+    assert_eq!(cycle.delivery_ratio(), None);
+    cycle.record_delivery(800_000.0, 1_000_000.0);
+    cycle.record_delivery(1_000_000.0, 1_000_000.0);
+    assert_eq!(cycle.delivery_ratio(), Some(0.9));
+}
+
+#[test]
+fn markers_are_named_for_each_sink() {
+    assert_eq!(
+        influx_marker("phase 2", AT),
+        "bbr_marker name=\"phase 2\" 1700000000000000000"
+    );
+    assert_eq!(
+        graphite_marker("start load phase 2.b", AT),
+        "bbr.markers.start_load_phase_2_b 1 1700000000\n"
+    );
+}
@@ -0,0 +1,25 @@
+use ccp_bbr::systemd;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+#[test]
+fn notifications_reach_the_notify_socket() {
+    assert!(systemd::notify("READY=1").is_ok());
+
+    let path = std::env::temp_dir().join(format!("bbr-notify-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let sock = UnixDatagram::bind(&path).unwrap();
+    std::env::set_var("NOTIFY_SOCKET", &path);
+    std::env::set_var("WATCHDOG_USEC", "2000000");
+    std::env::set_var("WATCHDOG_PID", std::process::id().to_string());
+
+    systemd::notify("READY=1").unwrap();
+    let mut buf = [0; 64];
+    let n = sock.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"READY=1");
+    assert_eq!(systemd::watchdog_interval(), Some(Duration::from_secs(2)));
+
+    std::env::set_var("WATCHDOG_PID", "1");
+    assert_eq!(systemd::watchdog_interval(), None);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use ccp_bbr::bandwidth::Rate;
+use ccp_bbr::tenant::{
+    cgroup_path, settings_for, socket_inode, tenant_for, TenantConfig, TenantLookup, TenantRule,
+    Tenants,
+};
+use portus::DatapathInfo;
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+fn info(src: Ipv4Addr, src_port: u16, dst: Ipv4Addr, dst_port: u16) -> DatapathInfo {
+    DatapathInfo {
+        src_ip: u32::from(src),
+        src_port: u32::from(src_port),
+        dst_ip: u32::from(dst),
+        dst_port: u32::from(dst_port),
+        ..common::info(1)
+    }
+}
+
+#[test]
+fn tenant_rules_and_configs_round_trip() {
+    let rule: TenantRule = "src=172.17.0.0/24:blue".parse().unwrap();
+    assert_eq!(rule.tenant, "blue");
+    assert_eq!(rule.to_string(), "src=172.17.0.0/24:blue");
+    assert!("src=172.17.0.0/24".parse::<TenantRule>().is_err());
+    assert!("proto=tcp:blue".parse::<TenantRule>().is_err());
+
+    // tenants a lookup finds may hold colons
+    let config: TenantConfig = "netns:4026532281:weight=2,max_rate=100,aggregate_rate=1Gbps"
+        .parse()
+        .unwrap();
+    assert_eq!(config.tenant, "netns:4026532281");
+    assert_eq!(config.settings.weight, Some(2.0));
+    assert_eq!(config.settings.max_rate, Some(Rate::from_mbps(100.0)));
+    assert_eq!(config.settings.min_rate, None);
+    assert_eq!(
+        config.settings.aggregate_rate,
+        Some(Rate::from_mbps(1000.0))
+    );
+    assert_eq!(config.to_string().parse::<TenantConfig>().unwrap(), config);
+    assert!("blue:weight=0".parse::<TenantConfig>().is_err());
+    assert!("blue:max_rate=0".parse::<TenantConfig>().is_err());
+    assert!("blue:weight".parse::<TenantConfig>().is_err());
+    assert!(":weight=2".parse::<TenantConfig>().is_err());
+}
+
+#[test]
+fn flows_take_their_tenants_settings() {
+    let rules = vec![
+        "dport=443:blue".parse().unwrap(),
+        "src=10.0.0.0/8:red".parse().unwrap(),
+    ];
+    let local = Ipv4Addr::new(10, 0, 0, 1);
+    let remote = Ipv4Addr::new(192, 0, 2, 1);
+    let https = info(local, 40000, remote, 443);
+    assert_eq!(
+        tenant_for(&rules, TenantLookup::None, &https).as_deref(),
+        Some("blue")
+    );
+    let other = info(local, 40000, remote, 80);
+    assert_eq!(
+        tenant_for(&rules, TenantLookup::None, &other).as_deref(),
+        Some("red")
+    );
+    let elsewhere = info(Ipv4Addr::new(192, 0, 2, 2), 40000, remote, 80);
+    assert_eq!(tenant_for(&rules, TenantLookup::None, &elsewhere), None);
+
+    let configs: Vec<TenantConfig> = vec![
+        "blue:weight=2".parse().unwrap(),
+        "red:weight=3".parse().unwrap(),
+        "blue:weight=4".parse().unwrap(),
+    ];
+    assert_eq!(settings_for(&configs, Some("blue")).weight, Some(4.0));
+    assert_eq!(settings_for(&configs, Some("green")).weight, None);
+    assert_eq!(settings_for(&configs, None).weight, None);
+}
+
+#[test]
+fn tenants_count_their_flows() {
+    let tenants = Tenants::default();
+    let cap = Rate::from_mbps(90.0);
+    assert_eq!(tenants.share("blue", cap), cap);
+    for sock_id in 1..=3 {
+        tenants.join("blue", sock_id);
+    }
+    tenants.join("red", 4);
+    assert_eq!(tenants.share("blue", cap), Rate::from_mbps(30.0));
+    tenants.leave("blue", 2);
+    tenants.leave("red", 4);
+    assert_eq!(tenants.len("blue"), 2);
+    assert_eq!(
+        tenants.all().into_iter().collect::<Vec<_>>(),
+        vec![(String::from("blue"), 2)]
+    );
+}
+
+#[test]
+fn tcp_tables_and_cgroups_are_parsed() {
+    let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 31337 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:9C40 0100007F:1F90 06 00000000:00000000 03:00000A2B 00000000     0        0 0 3 0000000000000000
+   2: 0100007F:9C40 0100007F:1F90 01 00000000:00000000 00:00000000 00000000  1000        0 42424 1 0000000000000000 20 4 30 10 -1
+";
+    let localhost = Ipv4Addr::LOCALHOST;
+    // the TIME_WAIT entry before it has no inode
+    assert_eq!(
+        socket_inode(table, &info(localhost, 40000, localhost, 8080)),
+        Some(42424)
+    );
+    assert_eq!(
+        socket_inode(table, &info(localhost, 40001, localhost, 8080)),
+        None
+    );
+
+    assert_eq!(
+        cgroup_path("0::/system.slice/docker-abc.scope\n").as_deref(),
+        Some("/system.slice/docker-abc.scope")
+    );
+    assert_eq!(
+        cgroup_path("12:cpu,cpuacct:/kubepods/pod1\n11:memory:/kubepods/pod1\n").as_deref(),
+        Some("/kubepods/pod1")
+    );
+    assert_eq!(cgroup_path(""), None);
+}
+
+#[test]
+fn sockets_are_looked_up_in_proc() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let local = stream.local_addr().unwrap();
+    let flow = info(
+        Ipv4Addr::LOCALHOST,
+        local.port(),
+        Ipv4Addr::LOCALHOST,
+        listener.local_addr().unwrap().port(),
+    );
+
+    let netns = std::fs::read_link("/proc/self/ns/net").unwrap();
+    let netns = netns.to_str().unwrap();
+    let inode = &netns["net:[".len()..netns.len() - 1];
+    assert_eq!(
+        TenantLookup::Netns.lookup(&flow),
+        Some(format!("netns:{}", inode))
+    );
+    let cgroups = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+    assert_eq!(TenantLookup::Cgroup.lookup(&flow), cgroup_path(&cgroups));
+    assert_eq!(TenantLookup::None.lookup(&flow), None);
+
+    let closed = info(Ipv4Addr::LOCALHOST, 1, Ipv4Addr::LOCALHOST, 2);
+    assert_eq!(TenantLookup::Netns.lookup(&closed), None);
+}
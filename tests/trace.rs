@@ -0,0 +1,124 @@
+mod common;
+
+use ccp_bbr::trace::{self, Decision, Recorder};
+use ccp_bbr::{Action, BbrConfig, BbrCore, BbrMode, Measurement};
+use std::time::{Duration, Instant};
+
+const TRACE: &str = r#"
+{"event":"new_flow","elapsed_us":0,"sock_id":7,"init_cwnd":14600,"mss":1460}
+{"event":"report","elapsed_us":10000,"sock_id":7,"minrtt_us":10000,"rate":1250000.0}
+{"event":"report","elapsed_us":20000,"sock_id":7,"minrtt_us":10000,"rate":1000000.0}
+{"event":"report","elapsed_us":30000,"sock_id":7,"minrtt_us":10000,"rate":1000000.0}
+{"event":"report","elapsed_us":40000,"sock_id":7,"minrtt_us":10000,"rate":1000000.0}
+{"event":"report","elapsed_us":50000,"sock_id":7,"minrtt_us":10000,"rate":1000000.0}
+{"event":"report","elapsed_us":10060000,"sock_id":7,"minrtt_us":11000,"rate":1000000.0}
+"#;
+
+#[test]
+fn replay_yields_decisions_on_virtual_clock() {
+    let mut decisions: Vec<Decision> = vec![];
+    trace::replay(&BbrConfig::default(), TRACE.as_bytes(), |d| {
+        decisions.push(d.clone())
+    })
+    .unwrap();
+
+    let summary: Vec<_> = decisions
+        .iter()
+        .map(|d| {
+            let program = match &d.action {
+                Action::SetProgram { program, .. } => Some(*program),
+                Action::Update(_) => None,
+            };
+            (d.elapsed_us, d.sock_id, d.mode, program)
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (0, 7, BbrMode::Startup, Some("init_program")),
+            (10_000, 7, BbrMode::Startup, None),
+            (20_000, 7, BbrMode::Startup, None),
+            (30_000, 7, BbrMode::Startup, None),
+            (40_000, 7, BbrMode::Drain, Some("drain")),
+            (40_000, 7, BbrMode::Drain, None),
+            (50_000, 7, BbrMode::ProbeBw, None),
+            (50_000, 7, BbrMode::ProbeBw, Some("probe_bw")),
+            (10_060_000, 7, BbrMode::ProbeRtt, Some("probe_rtt")),
+            (10_060_000, 7, BbrMode::ProbeRtt, None),
+        ]
+    );
+}
+
+#[test]
+fn replay_reports_malformed_lines() {
+    let err = trace::replay(
+        &BbrConfig::default(),
+        "{\"event\":\"bogus\"}".as_bytes(),
+        |_| {},
+    )
+    .unwrap_err();
+    assert!(err.starts_with("trace line 1:"), "{}", err);
+}
+
+#[test]
+fn recorded_reports_replay_to_the_same_decisions() {
+    let path = std::env::temp_dir().join(format!("ccp_bbr_record_{}.jsonl", std::process::id()));
+    let cfg = BbrConfig {
+        recorder: Some(Recorder::create(&path).unwrap()),
+        ..Default::default()
+    };
+    let info = common::info(3);
+
+    let mut now = Instant::now();
+    let mut core = BbrCore::new(&cfg, &info, now);
+    let mut uid = 0;
+    let mut live = vec![];
+    let mut apply = |core: &mut BbrCore, actions: Vec<Action>| {
+        for action in actions {
+            if let Action::SetProgram { .. } = action {
+                uid += 1;
+                core.program_installed(uid);
+            }
+            live.push(action);
+        }
+        uid
+    };
+    let start = core.start();
+    let mut program_uid = apply(&mut core, start);
+    for (after_ms, minrtt_us, rate) in [
+        (10, 10_000, 1_250_000.0),
+        (10, 10_000, 1_000_000.0),
+        (10, 10_000, 1_000_000.0),
+        (10, 10_000, 1_000_000.0),
+        (10, 10_000, 1_000_000.0),
+        (10_000, 11_000, 1_000_000.0),
+        (250, 11_000, 0.0),
+    ] {
+        now += Duration::from_millis(after_ms);
+        let m = Measurement {
+            program_uid,
+            minrtt_us,
+            rate_outgoing: rate,
+            rate_incoming: rate,
+            srtt_us: minrtt_us + 500,
+            acked: 10,
+            ..Default::default()
+        };
+        let actions = core.on_measurement(now, m);
+        program_uid = apply(&mut core, actions);
+    }
+    drop(cfg);
+
+    let mut replayed = vec![];
+    let trace = std::fs::File::open(&path).unwrap();
+    trace::replay(&BbrConfig::default(), std::io::BufReader::new(trace), |d| {
+        assert_eq!(d.sock_id, 3);
+        replayed.push(d.action.clone())
+    })
+    .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(live
+        .iter()
+        .any(|a| matches!(a, Action::SetProgram { program, .. } if *program == "probe_rtt")));
+    assert_eq!(replayed, live);
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use ccp_bbr::datapath::DatapathKind;
+use ccp_bbr::transport::{parse_transports, Transport};
+use ccp_bbr::{BbrConfig, BbrCore, Measurement};
+use common::info;
+use std::time::{Duration, Instant};
+
+#[test]
+fn ipc_lists_name_each_transport_once() {
+    assert_eq!(parse_transports("unix").unwrap(), vec!["unix"]);
+    assert_eq!(
+        parse_transports("netlink, unix").unwrap(),
+        vec!["netlink", "unix"]
+    );
+    assert!(parse_transports("netlink,tcp").is_err());
+    assert!(parse_transports("unix,unix").is_err());
+    assert!(parse_transports("").is_err());
+}
+
+#[test]
+fn transports_keep_their_flows_apart() {
+    let cfg = BbrConfig::default();
+    let kernel = Transport::new(&cfg, "netlink", DatapathKind::Kernel);
+    let quic = Transport::new(&cfg, "unix", DatapathKind::Quic);
+    assert!(kernel.cfg.programs()["probe_bw"].contains("Ack.lost_pkts_sample"));
+    assert!(!quic.cfg.programs()["probe_bw"].contains("Ack.lost_pkts_sample"));
+
+    // both datapaths number their sockets from 1
+    let now = Instant::now();
+    let mut kernel_flow = BbrCore::new(&kernel.cfg, &info(1), now);
+    let quic_flow = BbrCore::new(&quic.cfg, &info(1), now);
+    assert_eq!(cfg.shutdown.active_flows(), 2);
+
+    kernel_flow.program_installed(1);
+    let report = Measurement {
+        program_uid: 1,
+        minrtt_us: 10_000,
+        rate_outgoing: 1_250_000.0,
+        rate_incoming: 1_250_000.0,
+        ..Default::default()
+    };
+    kernel_flow.on_measurement(now + Duration::from_millis(10), report);
+    let kernel_stats = kernel.stats();
+    assert_eq!((kernel_stats.flows, kernel_stats.reports), (1, 1));
+    assert_eq!(kernel_stats.datapath, DatapathKind::Kernel);
+    let quic_stats = quic.stats();
+    assert_eq!((quic_stats.flows, quic_stats.reports), (1, 0));
+
+    // a datapath that turns out to lack a primitive only changes its own flows
+    let no_rates = Measurement {
+        rate_outgoing: 0.0,
+        rate_incoming: 0.0,
+        delivery_rate: 1_250_000.0,
+        ..report
+    };
+    kernel_flow.on_measurement(now + Duration::from_millis(20), no_rates);
+    assert!(kernel.stats().lacks_flow_rates);
+    assert!(!quic.stats().lacks_flow_rates);
+    assert!(!kernel.stats().lacks_probe_bw);
+    assert_eq!(kernel.stats().degraded_flows, 0);
+
+    drop(kernel_flow);
+    assert_eq!(cfg.shutdown.active_flows(), 1);
+    drop(quic_flow);
+    assert_eq!(cfg.shutdown.active_flows(), 0);
+}
+
+#[test]
+fn shutdown_releases_every_transport() {
+    let cfg = BbrConfig::default();
+    let quic = Transport::new(&cfg, "unix", DatapathKind::Quic);
+    let now = Instant::now();
+    let mut flow = BbrCore::new(&quic.cfg, &info(1), now);
+    flow.program_installed(1);
+
+    cfg.shutdown.request();
+    let report = Measurement {
+        program_uid: 1,
+        minrtt_us: 10_000,
+        ..Default::default()
+    };
+    let actions = flow.on_measurement(now + Duration::from_millis(10), report);
+    assert!(matches!(
+        &actions[..],
+        [ccp_bbr::Action::SetProgram {
+            program: "init_program",
+            ..
+        }]
+    ));
+    assert_eq!(cfg.shutdown.active_flows(), 0);
+}
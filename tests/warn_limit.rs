@@ -0,0 +1,85 @@
+use ccp_bbr::warn_limit::WarnLimiter;
+use std::time::{Duration, Instant};
+
+#[test]
+fn recurring_warnings_are_logged_once_per_interval() {
+    let warnings = WarnLimiter::default();
+    let interval = Duration::from_secs(10);
+    let start = Instant::now();
+    let at = |s| start + Duration::from_secs(s);
+
+    assert_eq!(
+        warnings.check("update failed", "EPIPE", interval, at(0)),
+        Some(0)
+    );
+    assert_eq!(
+        warnings.check("update failed", "EPIPE", interval, at(1)),
+        None
+    );
+    assert_eq!(
+        warnings.check("update failed", "EPIPE", interval, at(9)),
+        None
+    );
+    // another error, or another message, is another warning
+    assert_eq!(
+        warnings.check("update failed", "EBADF", interval, at(9)),
+        Some(0)
+    );
+    assert_eq!(
+        warnings.check("install failed", "EPIPE", interval, at(9)),
+        Some(0)
+    );
+
+    // the next one logged tells how many were held back since the last
+    assert_eq!(
+        warnings.check("update failed", "EPIPE", interval, at(10)),
+        Some(2)
+    );
+    assert_eq!(
+        warnings.check("update failed", "EPIPE", interval, at(11)),
+        None
+    );
+    assert_eq!(
+        warnings.check("update failed", "EPIPE", interval, at(30)),
+        Some(1)
+    );
+    assert_eq!(warnings.suppressed(), 3);
+
+    // clones count together, as flows of one configuration do
+    let shared = warnings.clone();
+    assert_eq!(
+        shared.check("update failed", "EPIPE", interval, at(31)),
+        None
+    );
+    assert_eq!(warnings.suppressed(), 4);
+}
+
+#[test]
+fn a_zero_interval_logs_every_warning() {
+    let warnings = WarnLimiter::default();
+    let now = Instant::now();
+    for _ in 0..3 {
+        assert_eq!(
+            warnings.check("update failed", "EPIPE", Duration::ZERO, now),
+            Some(0)
+        );
+    }
+    assert_eq!(warnings.suppressed(), 0);
+}
+
+#[test]
+fn warnings_logged_long_enough_ago_are_forgotten() {
+    let warnings = WarnLimiter::default();
+    let start = Instant::now();
+    let interval = Duration::from_secs(10);
+    // a new error detail every second for a day, each logged once
+    for second in 0..86_400 {
+        let at = start + Duration::from_secs(second);
+        assert_eq!(
+            warnings.check("update failed", &format!("errno {}", second), interval, at),
+            Some(0)
+        );
+        assert!(warnings.len() <= 16 * 10, "{}", warnings.len());
+    }
+    assert_eq!(warnings.suppressed(), 0);
+}